@@ -1,13 +1,13 @@
 use anyhow::Result;
 
 use minidb::accessor::method::AccessMethod;
-use minidb::buffer::manager::BufferPoolManager;
+use minidb::buffer::{entity::PAGE_SIZE, manager::BufferPoolManager};
 
 use minidb::rdbms::{btree::BTree, clocksweep::ClockSweepManager, disk::DiskManager};
 
 fn main() -> Result<()> {
-    let disk = DiskManager::open("test.btr")?;
-    let mut bufmgr = ClockSweepManager::new(disk, 10);
+    let disk = DiskManager::open("test.btr", PAGE_SIZE)?;
+    let mut bufmgr = ClockSweepManager::new(disk, 10)?;
 
     let btree = BTree::create(&mut bufmgr)?;
 
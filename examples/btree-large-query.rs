@@ -6,13 +6,14 @@ use minidb::accessor::{
 };
 use minidb::storage::entity::PageId;
 
+use minidb::buffer::entity::PAGE_SIZE;
 use minidb::rdbms::{btree::BTree, clocksweep::ClockSweepManager, disk::DiskManager};
 
 fn main() -> Result<()> {
-    let disk = DiskManager::open("large.btr")?;
-    let mut bufmgr = ClockSweepManager::new(disk, 10);
+    let disk = DiskManager::open("large.btr", PAGE_SIZE)?;
+    let mut bufmgr = ClockSweepManager::new(disk, 10)?;
 
-    let btree = BTree::new(PageId(0));
+    let btree = BTree::new(PageId(1));
     let mut iter = btree.search(
         &mut bufmgr,
         SearchMode::Key(vec![
@@ -2,15 +2,21 @@ use anyhow::Result;
 use md5::{Digest, Md5};
 
 use minidb::accessor::method::AccessMethod;
-use minidb::buffer::manager::BufferPoolManager;
+use minidb::buffer::{entity::PAGE_SIZE, manager::BufferPoolManager};
 
-use minidb::rdbms::{btree::BTree, clocksweep::ClockSweepManager, disk::DiskManager};
+use minidb::rdbms::{
+    btree::BTree,
+    clocksweep::ClockSweepManager,
+    disk::{DiskManager, SyncPolicy},
+};
 
 const NUM_PAIRS: u32 = 1_000_000;
 
 fn main() -> Result<()> {
-    let disk = DiskManager::open("large.btr")?;
-    let mut bufmgr = ClockSweepManager::new(disk, 100);
+    let mut disk = DiskManager::open("large.btr", PAGE_SIZE)?;
+    // 一括ロードなので fsync を待たずに OS に任せる
+    disk.set_sync_policy(SyncPolicy::OsBuffered);
+    let mut bufmgr = ClockSweepManager::new(disk, 100)?;
 
     let btree = BTree::create(&mut bufmgr)?;
     for i in 1u32..=NUM_PAIRS {
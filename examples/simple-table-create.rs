@@ -1,17 +1,17 @@
 use anyhow::Result;
 
-use minidb::buffer::manager::BufferPoolManager;
+use minidb::buffer::{entity::PAGE_SIZE, manager::BufferPoolManager};
 use minidb::sql::ddl::table::Table;
 use minidb::storage::entity::PageId;
 
 use minidb::rdbms::{clocksweep::ClockSweepManager, disk::DiskManager, table::SimpleTable};
 
 fn main() -> Result<()> {
-    let disk = DiskManager::open("simple.rly")?;
-    let mut bufmgr = ClockSweepManager::new(disk, 10);
+    let disk = DiskManager::open("simple.rly", PAGE_SIZE)?;
+    let mut bufmgr = ClockSweepManager::new(disk, 10)?;
 
     let mut table = SimpleTable {
-        meta_page_id: PageId(0),
+        meta_page_id: PageId(1),
         num_key_elems: 1,
     };
     table.create(&mut bufmgr)?;
@@ -6,13 +6,16 @@ use minidb::accessor::{
 };
 use minidb::storage::entity::PageId;
 
-use minidb::rdbms::{btree::BTree, clocksweep::ClockSweepManager, disk::DiskManager, util::tuple};
+use minidb::buffer::entity::PAGE_SIZE;
+use minidb::rdbms::{
+    btree::BTree, clocksweep::ClockSweepManager, disk::DiskManager, mvcc::TupleHeader, util::tuple,
+};
 
 fn main() -> Result<()> {
-    let disk = DiskManager::open("simple.rly")?;
-    let mut bufmgr = ClockSweepManager::new(disk, 10);
+    let disk = DiskManager::open("simple.rly", PAGE_SIZE)?;
+    let mut bufmgr = ClockSweepManager::new(disk, 10)?;
 
-    let btree = BTree::new(PageId(0));
+    let btree = BTree::new(PageId(1));
     let mut search_key = vec![];
     tuple::encode([b"y"].iter(), &mut search_key);
     let mut iter = btree.search(&mut bufmgr, SearchMode::Key(search_key))?;
@@ -23,7 +26,8 @@ fn main() -> Result<()> {
         if record[0] != b"y" {
             break;
         }
-        tuple::decode(&value, &mut record);
+        let (_, value) = TupleHeader::decode(&value)?;
+        tuple::decode(value, &mut record);
         println!("{:?}", tuple::Pretty(&record));
     }
     Ok(())
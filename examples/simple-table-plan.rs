@@ -1,28 +1,42 @@
 use anyhow::Result;
+use std::ops::Bound;
+use std::sync::Arc;
 
+use minidb::buffer::entity::PAGE_SIZE;
 use minidb::rdbms::btree::BTree;
+use minidb::sql::dml::entity::Value;
 use minidb::sql::dml::query::PlanNode;
 use minidb::storage::entity::PageId;
 
-use minidb::rdbms::{clocksweep::ClockSweepManager, disk::DiskManager, query::*, util::tuple};
+use minidb::rdbms::{
+    clocksweep::ClockSweepManager, disk::DiskManager, expr::*, mvcc::AllVisible, query::*,
+    util::tuple,
+};
 
 fn main() -> Result<()> {
-    let disk = DiskManager::open("simple.rly")?;
-    let mut bufmgr = ClockSweepManager::new(disk, 10);
-    let table_accessor = &BTree::new(PageId(0));
+    let disk = DiskManager::open("simple.rly", PAGE_SIZE)?;
+    let mut bufmgr = ClockSweepManager::new(disk, 10)?;
 
     let plan = Filter {
-        cond: &|record| record[1].as_slice() < b"Dave",
-        inner_plan: &SeqScan {
-            table_accessor,
-            search_mode: TupleSearchMode::Key(&[b"w"]),
-            while_cond: &|pkey| pkey[0].as_slice() < b"z",
-        },
+        cond: Expr::compare(
+            CmpOp::Lt,
+            Expr::Column(1),
+            Expr::Literal(Value::Bytes(b"Dave".to_vec())),
+        ),
+        inner_plan: Arc::new(SeqScan {
+            table_accessor: Arc::new(BTree::new(PageId(1))),
+            search_mode: TupleSearchMode::Key(vec![b"w".to_vec()]),
+            num_key_elems: 1,
+            stop_key: Bound::Excluded(vec![b"z".to_vec()]),
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        }),
     };
     let mut exec = plan.start(&mut bufmgr)?;
 
     while let Some(record) = exec.next(&mut bufmgr)? {
-        println!("{:?}", tuple::Pretty(&record));
+        println!("{:?}", tuple::Pretty(record.tuple()));
     }
     Ok(())
 }
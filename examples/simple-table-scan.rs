@@ -6,19 +6,23 @@ use minidb::accessor::{
 };
 use minidb::storage::entity::PageId;
 
-use minidb::rdbms::{btree::BTree, clocksweep::ClockSweepManager, disk::DiskManager, util::tuple};
+use minidb::buffer::entity::PAGE_SIZE;
+use minidb::rdbms::{
+    btree::BTree, clocksweep::ClockSweepManager, disk::DiskManager, mvcc::TupleHeader, util::tuple,
+};
 
 fn main() -> Result<()> {
-    let disk = DiskManager::open("simple.rly")?;
-    let mut bufmgr = ClockSweepManager::new(disk, 10);
+    let disk = DiskManager::open("simple.rly", PAGE_SIZE)?;
+    let mut bufmgr = ClockSweepManager::new(disk, 10)?;
 
-    let btree = BTree::new(PageId(0));
+    let btree = BTree::new(PageId(1));
     let mut iter = btree.search(&mut bufmgr, SearchMode::Start)?;
 
     while let Some((key, value)) = iter.next(&mut bufmgr)? {
         let mut record = vec![];
         tuple::decode(&key, &mut record);
-        tuple::decode(&value, &mut record);
+        let (_, value) = TupleHeader::decode(&value)?;
+        tuple::decode(value, &mut record);
         if record[2] == b"Smith" {
             println!("{:?}", tuple::Pretty(&record));
         }
@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use minidb::buffer::manager::BufferPoolManager;
+use minidb::buffer::{entity::PAGE_SIZE, manager::BufferPoolManager};
 use minidb::sql::ddl::table::Table as ITable;
 use minidb::storage::entity::PageId;
 
@@ -11,16 +11,18 @@ use minidb::rdbms::{
 };
 
 fn main() -> Result<()> {
-    let disk = DiskManager::open("table.rly")?;
-    let mut bufmgr = ClockSweepManager::new(disk, 10);
+    let disk = DiskManager::open("table.rly", PAGE_SIZE)?;
+    let mut bufmgr = ClockSweepManager::new(disk, 10)?;
 
     let mut table = Table {
-        meta_page_id: PageId(0),
+        meta_page_id: PageId(1),
         num_key_elems: 1,
         unique_indices: vec![UniqueIndex {
             meta_page_id: PageId::INVALID_PAGE_ID,
             skey: vec![2], // last_name
         }],
+        non_unique_indices: vec![],
+        schema: None,
     };
     table.create(&mut bufmgr)?;
     dbg!(&table);
@@ -1,27 +1,35 @@
 use anyhow::Result;
+use std::ops::Bound;
+use std::sync::Arc;
 
+use minidb::buffer::entity::PAGE_SIZE;
 use minidb::rdbms::btree::BTree;
 use minidb::sql::dml::query::PlanNode;
 use minidb::storage::entity::PageId;
 
-use minidb::rdbms::{clocksweep::ClockSweepManager, disk::DiskManager, query::*, util::tuple};
+use minidb::rdbms::{
+    clocksweep::ClockSweepManager, disk::DiskManager, expr::*, mvcc::AllVisible, query::*,
+    util::tuple,
+};
 
 fn main() -> Result<()> {
-    let disk = DiskManager::open("table.rly")?;
-    let mut bufmgr = ClockSweepManager::new(disk, 10);
-    let table_accessor = &BTree::new(PageId(0));
-    let index_accessor = &BTree::new(PageId(2));
+    let disk = DiskManager::open("table.rly", PAGE_SIZE)?;
+    let mut bufmgr = ClockSweepManager::new(disk, 10)?;
 
     let plan = IndexScan {
-        table_accessor,
-        index_accessor,
-        search_mode: TupleSearchMode::Key(&[b"Smith"]),
-        while_cond: &|skey| skey[0].as_slice() == b"Smith",
+        table_accessor: Arc::new(BTree::new(PageId(1))),
+        index_accessor: Arc::new(BTree::new(PageId(3))),
+        search_mode: TupleSearchMode::Key(vec![b"Smith".to_vec()]),
+        skey: vec![2],
+        stop_key: Bound::Included(vec![b"Smith".to_vec()]),
+        while_cond: Expr::TRUE,
+        visibility: Arc::new(AllVisible),
+        schema: vec![],
     };
     let mut exec = plan.start(&mut bufmgr)?;
 
     while let Some(record) = exec.next(&mut bufmgr)? {
-        println!("{:?}", tuple::Pretty(&record));
+        println!("{:?}", tuple::Pretty(record.tuple()));
     }
     Ok(())
 }
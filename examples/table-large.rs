@@ -2,7 +2,7 @@ use anyhow::Result;
 use md5::Md5;
 use sha1::{Digest, Sha1};
 
-use minidb::buffer::manager::BufferPoolManager;
+use minidb::buffer::{entity::PAGE_SIZE, manager::BufferPoolManager};
 use minidb::sql::ddl::table::Table as ITable;
 use minidb::storage::entity::PageId;
 
@@ -10,21 +10,25 @@ const NUM_ROWS: u32 = 10_000_000;
 
 use minidb::rdbms::{
     clocksweep::ClockSweepManager,
-    disk::DiskManager,
+    disk::{DiskManager, SyncPolicy},
     table::{Table, UniqueIndex},
 };
 
 fn main() -> Result<()> {
-    let disk = DiskManager::open("table_large.rly")?;
-    let mut bufmgr = ClockSweepManager::new(disk, 1_000_000);
+    let mut disk = DiskManager::open("table_large.rly", PAGE_SIZE)?;
+    // 一括ロードなので fsync を待たずに OS に任せる
+    disk.set_sync_policy(SyncPolicy::OsBuffered);
+    let mut bufmgr = ClockSweepManager::new(disk, 1_000_000)?;
 
     let mut table = Table {
-        meta_page_id: PageId(0),
+        meta_page_id: PageId(1),
         num_key_elems: 1,
         unique_indices: vec![UniqueIndex {
             meta_page_id: PageId::INVALID_PAGE_ID,
             skey: vec![2], // last_name
         }],
+        non_unique_indices: vec![],
+        schema: None,
     };
     table.create(&mut bufmgr)?;
     dbg!(&table);
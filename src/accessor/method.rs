@@ -1,3 +1,6 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
 use thiserror::Error;
 
 use super::entity::SearchMode;
@@ -7,11 +10,18 @@ use crate::buffer::manager::{self, BufferPoolManager};
 pub enum Error {
     #[error("duplicate key")]
     DuplicateKey,
+    #[error("key not found")]
+    KeyNotFound,
+    #[error("bulk load needs an empty tree")]
+    NotEmpty,
+    #[error("bulk load needs keys in ascending order")]
+    Unsorted,
     #[error(transparent)]
     Buffer(#[from] manager::Error),
 }
 
 pub trait Iterable<T: BufferPoolManager> {
+    #[allow(clippy::type_complexity)]
     fn next(&mut self, bufmgr: &mut T) -> Result<Option<(Vec<u8>, Vec<u8>)>, Error>;
 }
 
@@ -20,10 +30,27 @@ pub trait AccessMethod<T: BufferPoolManager> {
 
     // レコードを検索する
     fn search(&self, bufmgr: &mut T, search_option: SearchMode) -> Result<Self::Iterable, Error>;
+    // stop_key を超えたキーの手前で終わるように検索する
+    // キーは先頭の stop_key の長さのバイト列で比べる (Included なら先頭が同じ間は続ける)
+    // 既定では search と同じで、終わりは呼ぶ側で確かめる
+    fn search_range(
+        &self,
+        bufmgr: &mut T,
+        search_option: SearchMode,
+        stop_key: Bound<Vec<u8>>,
+    ) -> Result<Self::Iterable, Error> {
+        let _ = stop_key;
+        self.search(bufmgr, search_option)
+    }
     // レコードを挿入する
     fn insert(&self, bufmgr: &mut T, key: &[u8], value: &[u8]) -> Result<(), Error>;
+    // レコードを削除する
+    fn delete(&self, bufmgr: &mut T, key: &[u8]) -> Result<(), Error>;
 }
 
+// プランが持つアクセサ (実行器とも共有する)
+pub type SharedAccessMethod<T, U> = Arc<dyn AccessMethod<T, Iterable = U> + Send + Sync>;
+
 pub trait HaveAccessMethod<T: BufferPoolManager> {
     type Iter: Iterable<T>;
 
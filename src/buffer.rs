@@ -1,2 +1,3 @@
 pub mod entity;
 pub mod manager;
+pub mod metrics;
@@ -1,7 +1,11 @@
-use crate::storage::entity::PageId;
-use std::cell::{Cell, RefCell};
+use crate::storage::entity::{PageId, CHECKSUM_SIZE};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::ops::Deref;
+use std::rc::Rc;
 
 pub const PAGE_SIZE: usize = 4096;
+// ページのうちストレージのチェックサムを除いた上位層が使える部分
+pub const PAGE_BODY_SIZE: usize = PAGE_SIZE - CHECKSUM_SIZE;
 
 pub type Page = [u8; PAGE_SIZE];
 
@@ -12,6 +16,26 @@ pub struct Buffer {
     pub is_dirty: Cell<bool>,
 }
 
+impl Buffer {
+    pub fn body(&self) -> Ref<'_, [u8]> {
+        Ref::map(self.page.borrow(), |page| &page[..PAGE_BODY_SIZE])
+    }
+
+    pub fn body_mut(&self) -> RefMut<'_, [u8]> {
+        RefMut::map(self.page.borrow_mut(), |page| &mut page[..PAGE_BODY_SIZE])
+    }
+
+    // ページの内容を不変なスナップショットとして複製する
+    // スナップショットはフレームを pin しないので追い出しを妨げず、
+    // 元のページがその場で書き換えられても影響を受けない
+    pub fn freeze(&self) -> Snapshot {
+        Snapshot {
+            page_id: self.page_id,
+            page: Rc::new(*self.page.borrow()),
+        }
+    }
+}
+
 impl Default for Buffer {
     fn default() -> Self {
         Self {
@@ -21,3 +45,24 @@ impl Default for Buffer {
         }
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub page_id: PageId,
+    page: Rc<Page>,
+}
+
+impl Deref for Snapshot {
+    type Target = Page;
+    fn deref(&self) -> &Self::Target {
+        &self.page
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    // 書き出したダーティページの数
+    pub num_flushed_pages: usize,
+    // 書き出したページのうち最大のページ ID
+    pub max_flushed_page_id: Option<PageId>,
+}
@@ -1,5 +1,5 @@
-use super::entity::Buffer;
-use crate::storage::entity::PageId;
+use super::entity::{Buffer, Checkpoint};
+use crate::storage::{entity::PageId, manager::Error as StorageError};
 
 use std::io;
 use std::rc::Rc;
@@ -8,8 +8,28 @@ use std::rc::Rc;
 pub enum Error {
     #[error(transparent)]
     Io(#[from] io::Error),
+    #[error(transparent)]
+    Storage(StorageError),
     #[error("no free buffer available in buffer pool")]
     NoFreeBuffer,
+    #[error("page {0:?} is still in use")]
+    PagePinned(PageId),
+    #[error("buffer quota exceeded ({0} frames)")]
+    QuotaExceeded(usize),
+    #[error("page {page_id:?} is corrupted")]
+    CorruptPage { page_id: PageId },
+    // ストレージのページの大きさがバッファプールのフレームと違う
+    #[error("the buffer pool holds {expected}-byte pages but the storage has {actual}-byte pages")]
+    PageSize { expected: usize, actual: usize },
+}
+
+impl From<StorageError> for Error {
+    fn from(err: StorageError) -> Self {
+        match err {
+            StorageError::Corrupt(page_id) => Error::CorruptPage { page_id },
+            err => Error::Storage(err),
+        }
+    }
 }
 
 pub trait BufferPoolManager {
@@ -19,4 +39,32 @@ pub trait BufferPoolManager {
     fn create_page(&mut self) -> Result<Rc<Buffer>, Error>;
     // ストレージに書き出す
     fn flush(&mut self) -> Result<(), Error>;
+    // ダーティページをページ ID 順に書き出して同期する
+    fn checkpoint(&mut self) -> Result<Checkpoint, Error>;
+    // ページを書き戻さずにバッファプールから破棄する
+    fn discard_page(&mut self, page_id: PageId) -> Result<(), Error>;
+    // ページを破棄してストレージに返し、再利用できるようにする
+    // (ストレージに返せない buffermanager はバッファプールから破棄するだけ)
+    fn free_page(&mut self, page_id: PageId) -> Result<(), Error> {
+        self.discard_page(page_id)
+    }
+    // これまでに成功した fetch_page の回数 (数えない buffermanager は 0 のまま)
+    fn fetch_count(&self) -> u64 {
+        0
+    }
 }
+
+// バッファマネージャの動作を観測するためのフック
+pub trait Observer {
+    // ページを取得した (hit はバッファプールに載っていたかどうか)
+    fn on_fetch(&mut self, _page_id: PageId, _hit: bool) {}
+    // ページをバッファプールから追い出した
+    fn on_evict(&mut self, _page_id: PageId) {}
+    // ダーティページをストレージに書き戻した
+    fn on_writeback(&mut self, _page_id: PageId) {}
+}
+
+// 何もしない Observer
+pub struct NullObserver;
+
+impl Observer for NullObserver {}
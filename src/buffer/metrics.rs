@@ -0,0 +1,48 @@
+use super::manager::Observer;
+use crate::storage::entity::PageId;
+
+pub const BUFFER_POOL_HITS: &str = "buffer_pool.hits";
+pub const BUFFER_POOL_MISSES: &str = "buffer_pool.misses";
+pub const BUFFER_POOL_EVICTIONS: &str = "buffer_pool.evictions";
+pub const BUFFER_POOL_WRITEBACKS: &str = "buffer_pool.writebacks";
+pub const BUFFER_POOL_SIZE: &str = "buffer_pool.size";
+pub const BUFFER_POOL_RESIDENT_PAGES: &str = "buffer_pool.resident_pages";
+pub const BUFFER_POOL_DIRTY_PAGES: &str = "buffer_pool.dirty_pages";
+
+// Prometheus などの計測基盤へつなぐための口
+pub trait Metrics {
+    // カウンタを value だけ増やす
+    fn increment_counter(&mut self, name: &'static str, value: u64);
+    // ゲージに値を設定する
+    fn set_gauge(&mut self, name: &'static str, value: f64);
+}
+
+// バッファマネージャのイベントを Metrics のカウンタに変換する Observer
+pub struct MetricsObserver<M: Metrics> {
+    metrics: M,
+}
+
+impl<M: Metrics> MetricsObserver<M> {
+    pub fn new(metrics: M) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<M: Metrics> Observer for MetricsObserver<M> {
+    fn on_fetch(&mut self, _page_id: PageId, hit: bool) {
+        let name = if hit {
+            BUFFER_POOL_HITS
+        } else {
+            BUFFER_POOL_MISSES
+        };
+        self.metrics.increment_counter(name, 1);
+    }
+
+    fn on_evict(&mut self, _page_id: PageId) {
+        self.metrics.increment_counter(BUFFER_POOL_EVICTIONS, 1);
+    }
+
+    fn on_writeback(&mut self, _page_id: PageId) {
+        self.metrics.increment_counter(BUFFER_POOL_WRITEBACKS, 1);
+    }
+}
@@ -2,5 +2,6 @@ pub mod accessor;
 pub mod buffer;
 pub mod sql;
 pub mod storage;
+pub mod wal;
 
 pub mod rdbms;
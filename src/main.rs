@@ -1,60 +1,119 @@
 use anyhow::Result;
 use std::path::Path;
+use std::sync::Arc;
 
-use minidb::buffer::manager::BufferPoolManager;
-use minidb::sql::{ddl::table::Table as ITable, dml::query::*};
+use minidb::buffer::{entity::PAGE_SIZE, manager::BufferPoolManager};
+use minidb::sql::{ddl::table::Table as ITable, dml::entity::Type, parser::parse_statement};
 use minidb::storage::entity::PageId;
 
 use minidb::rdbms::{
-    btree::*, clocksweep::ClockSweepManager, disk::DiskManager, query::*, table::*, util::tuple,
+    binder::{bind, BoundStatement},
+    catalog::{Catalog, ColumnSchema, IndexSchema},
+    clocksweep::ClockSweepManager,
+    disk::DiskManager,
+    mvcc::AllVisible,
+    planner::Planner,
+    syscat::SystemCatalog,
+    table::*,
+    util::tuple,
 };
 
 fn create(db_path: &str) -> Result<()> {
     // config
-    let disk = DiskManager::open(db_path)?;
-    let mut bufmgr = ClockSweepManager::new(disk, 10);
+    let disk = DiskManager::open(db_path, PAGE_SIZE)?;
+    let mut bufmgr = ClockSweepManager::new(disk, 10)?;
+    let syscat = SystemCatalog::create(&mut bufmgr)?;
 
     let mut table = Table {
-        meta_page_id: PageId(0),
+        meta_page_id: PageId::INVALID_PAGE_ID,
         num_key_elems: 1,
         unique_indices: vec![UniqueIndex {
             meta_page_id: PageId::INVALID_PAGE_ID,
             skey: vec![2], // last_name
         }],
+        non_unique_indices: vec![],
+        schema: None,
     };
 
     // init db
     table.create(&mut bufmgr)?;
     dbg!(&table);
-    table.insert(&mut bufmgr, &[b"z", b"Alice", b"Smith"])?;
-    table.insert(&mut bufmgr, &[b"x", b"Bob", b"Johnson"])?;
-    table.insert(&mut bufmgr, &[b"y", b"Charlie", b"Williams"])?;
-    table.insert(&mut bufmgr, &[b"w", b"Dave", b"Miller"])?;
-    table.insert(&mut bufmgr, &[b"v", b"Eve", b"Brown"])?;
+    let column = |name: &str, column_type| ColumnSchema {
+        name: name.to_string(),
+        column_type,
+        nullable: false,
+    };
+    let mut catalog = Catalog::new();
+    let columns = vec![
+        column("id", Type::Int),
+        column("first_name", Type::Bytes),
+        column("last_name", Type::Bytes),
+    ];
+    let people = syscat.add_table(
+        &mut bufmgr,
+        &mut catalog,
+        "people",
+        columns,
+        1,
+        table.meta_page_id,
+    )?;
+    table.schema = Some(catalog.table_by_id(people).unwrap().schema());
+    syscat.add_index(
+        &mut bufmgr,
+        &mut catalog,
+        people,
+        IndexSchema {
+            name: "people_last_name".to_string(),
+            columns: vec![2],
+            unique: true,
+            meta_page_id: table.unique_indices[0].meta_page_id,
+        },
+    )?;
+    // id はシーケンスで振る
+    let mut sequence = syscat.add_sequence(&mut bufmgr, &mut catalog, people, 1)?;
+    for record in [
+        [b"Alice" as &[u8], b"Smith"],
+        [b"Bob", b"Johnson"],
+        [b"Charlie", b"Williams"],
+        [b"Dave", b"Miller"],
+        [b"Eve", b"Brown"],
+    ] {
+        table.insert_auto(&mut bufmgr, &mut sequence, &record)?;
+    }
 
     bufmgr.flush()?;
+    bufmgr
+        .storage_mut()
+        .set_catalog_root_page_id(syscat.class_page_id)?;
 
     Ok(())
 }
 
 fn query(db_path: &str) -> Result<()> {
     // config
-    let disk = DiskManager::open(db_path)?;
-    let mut bufmgr = ClockSweepManager::new(disk, 10);
+    let disk = DiskManager::open(db_path, PAGE_SIZE)?;
+    let mut bufmgr = ClockSweepManager::new(disk, 10)?;
+
+    // catalog
+    let root = match bufmgr.storage().catalog_root_page_id() {
+        Some(root) => root,
+        None => anyhow::bail!("{} has no system catalog", db_path),
+    };
+    let catalog = SystemCatalog::open(&mut bufmgr, root)?.load(&mut bufmgr)?;
 
     // query
-    let table_accessor = &BTree::new(PageId(0));
-    let index_accessor = &BTree::new(PageId(2));
-    let plan = IndexScan {
-        table_accessor,
-        index_accessor,
-        search_mode: TupleSearchMode::Key(&[b"Smith"]),
-        while_cond: &|skey| skey[0].as_slice() == b"Smith",
+    let statement = parse_statement("SELECT * FROM people WHERE last_name = 'Smith'")?;
+    let plan = match bind(&catalog, &statement)? {
+        BoundStatement::Query { plan, .. } => plan,
+        _ => unreachable!(),
     };
+    let planner = Planner::new(&catalog, Arc::new(AllVisible), 1 << 20);
+    let plan = planner.plan(&plan)?;
+    println!("{}", planner.explain(&plan));
     let mut exec = plan.start(&mut bufmgr)?;
 
     while let Some(record) = exec.next(&mut bufmgr)? {
-        println!("{:?}", tuple::Pretty(&record));
+        println!("{:?}", tuple::Pretty(record.tuple()));
     }
 
     Ok(())
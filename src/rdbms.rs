@@ -1,17 +1,106 @@
 // Disk を使った storagemanager の具体的な実装
 pub mod disk;
 
+// mmap を使った storagemanager の具体的な実装
+pub mod mmap;
+
+// メモリ上にページを持つ storagemanager の具体的な実装
+pub mod memory;
+
+// tokio を使った非同期 storagemanager の具体的な実装
+#[cfg(unix)]
+pub mod asyncdisk;
+
+// 複数のヒープファイルを束ねる storagemanager
+pub mod tablespace;
+
+// リレーションごとに別ファイルを使う storagemanager
+pub mod segment;
+
+// オブジェクトストアにページを置く storagemanager の具体的な実装
+pub mod remote;
+
+// ページの古い版を残す storagemanager のラッパー
+pub mod versioned;
+
+// 複数の sync の要求をまとめる storagemanager のラッパー
+pub mod groupsync;
+
+// ページを書き換えずに追記していく storagemanager の具体的な実装
+pub mod appendonly;
+
+// 二つの storagemanager に同じページを書き出すラッパー
+pub mod mirrored;
+
+// sync していない書き込みをクラッシュで失う、リカバリのテスト用の storagemanager
+#[cfg(any(test, feature = "crash-sim"))]
+pub mod crashsim;
+
+// セグメントファイルに書いていく logmanager の具体的な実装
+pub mod wal;
+
+// ログと連動したファジーチェックポイント
+pub mod checkpoint;
+
+// ログを使ったトランザクションマネージャ
+pub mod txn;
+// トランザクションが使う共有・排他ロック
+pub mod lock;
+// MVCC のタプルのヘッダと可視性の判定
+pub mod mvcc;
+// 直列化可能なスナップショット分離 (SSI) のための rw 依存の追跡
+pub mod ssi;
+// コミットしたトランザクションの論理的な変更をログから取り出す
+pub mod cdc;
+
 // Clock-sweek を使った buffer pool による buffermanager の具体的な実装
 pub mod clocksweep;
 
+// 一つの操作が使えるフレーム数を制限する buffermanager のラッパー
+pub mod quota;
+
+// 一時ファイルへスピルする一時データ用の buffermanager
+pub mod temp;
+
+// buffermanager への呼び出しを記録するラッパー
+pub mod recording;
+
 // B+Tree を使った accessmethod の具体的な実装
 pub mod btree;
 
 // Table と UniqueIndex の実装
 pub mod table;
+// 主キーに振る値を一つのページに書いておくシーケンス
+pub mod sequence;
 
 // B+Tree を使った Planner + Executor の具体的実装
 pub mod query;
+// 述語と射影に使う式とその評価
+pub mod expr;
+// 一つのクエリの実行器が使うメモリの量を数えて制限する
+pub mod memctx;
+// テーブルとインデックスの定義を引くカタログ
+pub mod catalog;
+// 構文木の名前をカタログで引いて論理プランにする
+pub mod binder;
+// 定数を畳み述語を下ろして論理プランを書き換える
+pub mod rewrite;
+// 述語と使えるインデックスを見て論理プランから物理プランを組み立てる
+pub mod planner;
+// 統計から行の数と費用を見積もる
+pub mod cost;
+// テーブルを読んで統計を集める (ANALYZE)
+pub mod analyze;
+// テーブルとインデックスの定義をデータベースの中に書いておくシステムカタログ
+pub mod syscat;
+// カタログの中身を読めるようにした仮想テーブル
+pub mod introspect;
+// カタログを持って SQL を準備し実行する入口
+pub mod database;
+// テキストや CSV の行をテーブルにまとめて書き込む (COPY)
+pub mod copy;
+// 古いファイルのスキーマを登録した手順で新しくする
+pub mod migration;
 
 // ユーティリティ
 pub mod util;
@@ -0,0 +1,299 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bincode::Options;
+
+use super::{
+    btree::BTree,
+    catalog::{Catalog, ColumnStats, TableId, TableSchema, TableStats},
+    expr::Expr,
+    mvcc::{Visibility, TUPLE_HEADER_SIZE},
+    query::{SeqScan, TupleSearchMode},
+    table::Table,
+    util::tuple,
+};
+use crate::accessor::{
+    entity::SearchMode,
+    method::{AccessMethod, Iterable},
+};
+use crate::buffer::{entity::PAGE_BODY_SIZE, manager::BufferPoolManager};
+use crate::sql::ddl::table::Table as ITable;
+use crate::sql::dml::{
+    entity::{Type, Value},
+    query::PlanNode,
+};
+use crate::storage::entity::PageId;
+
+// 等深ヒストグラムのバケットの数
+const HISTOGRAM_BUCKETS: usize = 10;
+// 統計に持つバイト列の長さ (長い値は前の方だけを持つ)
+const MAX_VALUE_LEN: usize = 32;
+
+fn decode_value(column_type: Type, bytes: &[u8]) -> Result<Value> {
+    Ok(match Value::decode(column_type, bytes)? {
+        Value::Bytes(mut bytes) => {
+            bytes.truncate(MAX_VALUE_LEN);
+            Value::Bytes(bytes)
+        }
+        value => value,
+    })
+}
+
+// 並べた NULL でない値から列の統計を求める
+fn column_stats(mut values: Vec<Value>, nulls: u64) -> ColumnStats {
+    values.sort();
+    let mut distinct = 0;
+    for (i, value) in values.iter().enumerate() {
+        if i == 0 || values[i - 1] != *value {
+            distinct += 1;
+        }
+    }
+    // i 番目のバケットの上端は、値を順に並べたときの (i + 1) / バケットの数 のところ
+    let buckets = HISTOGRAM_BUCKETS.min(values.len());
+    let histogram = (1..=buckets)
+        .map(|i| values[i * values.len() / buckets - 1].clone())
+        .collect();
+    ColumnStats {
+        distinct,
+        nulls,
+        min: values.first().cloned(),
+        max: values.last().cloned(),
+        histogram,
+    }
+}
+
+// テーブルの見える行を全て読んで統計を求める (ANALYZE)
+pub fn analyze<T: BufferPoolManager + 'static>(
+    bufmgr: &mut T,
+    table: &TableSchema,
+    visibility: Arc<dyn Visibility + Send + Sync>,
+) -> Result<TableStats> {
+    let types = table.types();
+    let plan = SeqScan {
+        table_accessor: Arc::new(BTree::new(table.meta_page_id)),
+        search_mode: TupleSearchMode::Start,
+        num_key_elems: table.num_key_elems,
+        stop_key: Bound::Unbounded,
+        while_cond: Expr::TRUE,
+        visibility,
+        schema: types.clone(),
+    };
+    let mut exec = PlanNode::<T>::start(&plan, bufmgr)?;
+    let mut values = vec![vec![]; types.len()];
+    let mut nulls = vec![0; types.len()];
+    let (mut rows, mut bytes) = (0, 0);
+    while let Some(row) = exec.next(bufmgr)? {
+        rows += 1;
+        let mut encoded = vec![];
+        tuple::encode(row.tuple().iter(), &mut encoded);
+        bytes += encoded.len() + TUPLE_HEADER_SIZE;
+        for (column, &column_type) in types.iter().enumerate() {
+            match decode_value(column_type, &row.tuple()[column])? {
+                Value::Null => nulls[column] += 1,
+                value => values[column].push(value),
+            }
+        }
+    }
+    let columns = values
+        .into_iter()
+        .zip(nulls)
+        .map(|(values, nulls)| column_stats(values, nulls))
+        .collect();
+    Ok(TableStats {
+        rows,
+        pages: bytes.div_ceil(PAGE_BODY_SIZE) as u64,
+        columns,
+    })
+}
+
+// テーブルごとの統計を書いておくカタログのテーブル
+// キーは (テーブルの番号, 0) に行とページの数、(テーブルの番号, 列の番号 + 1) に列の統計
+// 統計は bincode にしたバイト列の一列だけを持つ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsTable {
+    pub meta_page_id: PageId,
+}
+
+fn stats_elems(table: TableId, entry: u64) -> [Vec<u8>; 2] {
+    [
+        Value::Int(table as i64).encode(),
+        Value::Int(entry as i64).encode(),
+    ]
+}
+
+fn stats_key(table: TableId, entry: u64) -> Vec<u8> {
+    let mut key = vec![];
+    tuple::encode(stats_elems(table, entry).iter(), &mut key);
+    key
+}
+
+impl StatsTable {
+    pub fn create<T: BufferPoolManager>(bufmgr: &mut T) -> Result<Self> {
+        let btree = BTree::create(bufmgr)?;
+        Ok(Self {
+            meta_page_id: btree.meta_page_id,
+        })
+    }
+
+    // テーブルの統計を書き換える
+    pub fn save<T: BufferPoolManager>(
+        &self,
+        bufmgr: &mut T,
+        table: TableId,
+        stats: &TableStats,
+    ) -> Result<()> {
+        self.remove(bufmgr, table)?;
+        let options = bincode::options();
+        let rows = Table {
+            meta_page_id: self.meta_page_id,
+            num_key_elems: 2,
+            unique_indices: vec![],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        let mut insert = |entry: u64, value: Vec<u8>| {
+            let [table, entry] = stats_elems(table, entry);
+            rows.insert(bufmgr, &[&table, &entry, &value])
+        };
+        insert(0, options.serialize(&(stats.rows, stats.pages))?)?;
+        for (column, column_stats) in stats.columns.iter().enumerate() {
+            insert(column as u64 + 1, options.serialize(column_stats)?)?;
+        }
+        Ok(())
+    }
+
+    // テーブルの統計を消す
+    pub fn remove<T: BufferPoolManager>(&self, bufmgr: &mut T, table: TableId) -> Result<()> {
+        let btree = BTree::new(self.meta_page_id);
+        let mut old = vec![];
+        let mut iter = btree.search(bufmgr, SearchMode::Key(stats_key(table, 0)))?;
+        while let Some((key, _)) = iter.next(bufmgr)? {
+            if entry_table(&key)? != table {
+                break;
+            }
+            old.push(key);
+        }
+        for key in old {
+            btree.delete(bufmgr, &key)?;
+        }
+        Ok(())
+    }
+
+    // 書いておいた統計を catalog のテーブルに付ける (catalog にないテーブルのものは読み飛ばす)
+    pub fn load<T: BufferPoolManager>(&self, bufmgr: &mut T, catalog: &mut Catalog) -> Result<()> {
+        let btree = BTree::new(self.meta_page_id);
+        let options = bincode::options();
+        let mut loaded: Vec<(TableId, TableStats)> = vec![];
+        let mut iter = btree.search(bufmgr, SearchMode::Start)?;
+        while let Some((key, value)) = iter.next(bufmgr)? {
+            let (table, entry) = (entry_table(&key)?, entry_column(&key)?);
+            let mut elems = vec![];
+            tuple::decode(&value[TUPLE_HEADER_SIZE..], &mut elems);
+            let value = &elems[0];
+            if entry == 0 {
+                let (rows, pages) = options.deserialize(value)?;
+                let stats = TableStats {
+                    rows,
+                    pages,
+                    columns: vec![],
+                };
+                loaded.push((table, stats));
+            } else if let Some((_, stats)) = loaded.last_mut().filter(|(id, _)| *id == table) {
+                stats.columns.push(options.deserialize(value)?);
+            }
+        }
+        for (table, stats) in loaded {
+            if catalog.table_by_id(table).is_some() {
+                catalog.set_stats(table, stats)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn entry_elems(key: &[u8]) -> Result<(i64, i64)> {
+    let mut elems = vec![];
+    tuple::decode(key, &mut elems);
+    match (
+        Value::decode(Type::Int, &elems[0])?,
+        Value::decode(Type::Int, &elems[1])?,
+    ) {
+        (Value::Int(table), Value::Int(entry)) => Ok((table, entry)),
+        _ => unreachable!(),
+    }
+}
+
+fn entry_table(key: &[u8]) -> Result<TableId> {
+    Ok(entry_elems(key)?.0 as TableId)
+}
+
+fn entry_column(key: &[u8]) -> Result<u64> {
+    Ok(entry_elems(key)?.1 as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdbms::{
+        catalog::ColumnSchema, clocksweep::ClockSweepManager, memory::MemoryManager,
+        mvcc::AllVisible,
+    };
+
+    #[test]
+    fn analyze_test() {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        table.create(&mut bufmgr).unwrap();
+        // id は 0..100、group は 0..10 を繰り返し、note は 4 行に一つが NULL
+        for id in 0..100 {
+            let note = if id % 4 == 0 { vec![] } else { b"x".to_vec() };
+            let record = [Value::Int(id).encode(), Value::Int(id % 10).encode(), note];
+            let record: Vec<_> = record.iter().map(Vec::as_slice).collect();
+            table.insert(&mut bufmgr, &record).unwrap();
+        }
+        let column = |name: &str, column_type| ColumnSchema {
+            name: name.to_string(),
+            column_type,
+            nullable: true,
+        };
+        let mut catalog = Catalog::new();
+        let columns = vec![
+            column("id", Type::Int),
+            column("group", Type::Int),
+            column("note", Type::Bytes),
+        ];
+        let id = catalog
+            .add_table("t", columns, 1, table.meta_page_id)
+            .unwrap();
+
+        let schema = catalog.table_by_id(id).unwrap().clone();
+        let stats = analyze(&mut bufmgr, &schema, Arc::new(AllVisible)).unwrap();
+        assert_eq!((100, 2), (stats.rows, stats.pages));
+        let ids = &stats.columns[0];
+        assert_eq!((100, 0), (ids.distinct, ids.nulls));
+        assert_eq!(
+            (Some(Value::Int(0)), Some(Value::Int(99))),
+            (ids.min.clone(), ids.max.clone())
+        );
+        let bounds: Vec<_> = (1..=10).map(|i| Value::Int(i * 10 - 1)).collect();
+        assert_eq!(bounds, ids.histogram);
+        assert_eq!(10, stats.columns[1].distinct);
+        assert_eq!((1, 25), (stats.columns[2].distinct, stats.columns[2].nulls));
+
+        // 書いておいた統計を読み戻す
+        let stats_table = StatsTable::create(&mut bufmgr).unwrap();
+        stats_table.save(&mut bufmgr, id, &stats).unwrap();
+        let mut rewritten = stats.clone();
+        rewritten.rows = 7;
+        stats_table.save(&mut bufmgr, id, &rewritten).unwrap();
+        stats_table.load(&mut bufmgr, &mut catalog).unwrap();
+        assert_eq!(Some(&rewritten), catalog.stats(id));
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::buffer::entity::PAGE_SIZE;
+use crate::storage::{
+    checksum,
+    entity::{IoStats, PageId},
+    manager::{Error, Result, StorageManager},
+};
+
+// ログの 1 レコードの大きさ
+// [page_id: u64][ページ] の順で、ページ末尾にはチェックサムを書く
+const RECORD_SIZE: u64 = 8 + PAGE_SIZE as u64;
+
+// ページを書き換えずにログの末尾へ追記していく storagemanager
+// ページごとに最新のレコードの位置を持っておき、読み出しはそこから行う
+// 書きかけのレコードは開くときに捨てるので、クラッシュしても最後に書き終えた状態に戻る
+pub struct AppendOnlyStorage {
+    log_file: File,
+    // ページ ID から最新のレコードの位置への対応表
+    mapping: HashMap<PageId, u64>,
+    // 採番するページを決めるカウンタ
+    next_page_id: u64,
+    // 書き終えたレコードの数
+    num_records: u64,
+    // 読み書きの統計
+    stats: IoStats,
+}
+
+impl AppendOnlyStorage {
+    // ログを先頭から読んで対応表を作り直す
+    // 途中で切れたりチェックサムが合わなかったりするレコード以降は切り詰める
+    pub fn new(mut log_file: File) -> io::Result<Self> {
+        let log_size = log_file.metadata()?.len();
+        log_file.seek(SeekFrom::Start(0))?;
+        let mut mapping = HashMap::new();
+        let mut next_page_id = 1;
+        let mut num_records = 0;
+        let mut record = vec![0u8; RECORD_SIZE as usize];
+        while (num_records + 1) * RECORD_SIZE <= log_size {
+            log_file.read_exact(&mut record)?;
+            let (header, page) = record.split_at(8);
+            if !checksum::verify(page) {
+                break;
+            }
+            let page_id = PageId(u64::from_le_bytes(header.try_into().unwrap()));
+            mapping.insert(page_id, num_records * RECORD_SIZE);
+            next_page_id = std::cmp::max(next_page_id, page_id.to_u64() + 1);
+            num_records += 1;
+        }
+        log_file.set_len(num_records * RECORD_SIZE)?;
+        Ok(Self {
+            log_file,
+            mapping,
+            next_page_id,
+            num_records,
+            stats: IoStats::default(),
+        })
+    }
+
+    pub fn open(log_file_path: impl AsRef<Path>) -> io::Result<Self> {
+        let log_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(log_file_path)?;
+        Self::new(log_file)
+    }
+
+    // 書き換えられた古い版も含めたレコードの数
+    pub fn num_records(&self) -> u64 {
+        self.num_records
+    }
+
+    // これまでの読み書きの統計
+    pub fn stats(&self) -> IoStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = IoStats::default();
+    }
+}
+
+impl StorageManager for AppendOnlyStorage {
+    // 一度使ったページ ID は再利用しない
+    fn allocate_page(&mut self) -> Result<PageId> {
+        let page_id = self.next_page_id;
+        self.next_page_id += 1;
+        Ok(PageId(page_id))
+    }
+
+    fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> Result<()> {
+        let offset = *self.mapping.get(&page_id).ok_or(Error::PastEof(page_id))?;
+        self.log_file.seek(SeekFrom::Start(offset + 8))?;
+        self.log_file.read_exact(data)?;
+        self.stats.num_reads += 1;
+        self.stats.bytes_read += data.len() as u64;
+        if !checksum::verify(data) {
+            return Err(Error::Corrupt(page_id));
+        }
+        Ok(())
+    }
+
+    // 元のレコードはそのまま残し、新しいレコードを末尾に追記する
+    fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+        let offset = self.num_records * RECORD_SIZE;
+        let mut record = Vec::with_capacity(RECORD_SIZE as usize);
+        record.extend_from_slice(&page_id.to_u64().to_le_bytes());
+        record.extend_from_slice(data);
+        checksum::stamp(&mut record[8..]);
+        self.log_file.seek(SeekFrom::Start(offset))?;
+        self.log_file.write_all(&record)?;
+        self.mapping.insert(page_id, offset);
+        self.num_records += 1;
+        self.stats.num_writes += 1;
+        self.stats.bytes_written += record.len() as u64;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        let start = Instant::now();
+        self.log_file.sync_data()?;
+        self.stats.num_syncs += 1;
+        self.stats.sync_duration += start.elapsed();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::entity::PAGE_BODY_SIZE;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn append_only_test() {
+        let (log_file, log_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let mut storage = AppendOnlyStorage::new(log_file).unwrap();
+        let hello_page_id = storage.allocate_page().unwrap();
+        let world_page_id = storage.allocate_page().unwrap();
+        let mut hello = vec![0u8; PAGE_SIZE];
+        hello[..5].copy_from_slice(b"hello");
+        let mut world = vec![0u8; PAGE_SIZE];
+        world[..5].copy_from_slice(b"world");
+        storage.write_page_data(hello_page_id, &hello).unwrap();
+        storage.write_page_data(world_page_id, &hello).unwrap();
+        // 書き直しても元のレコードは残る
+        storage.write_page_data(world_page_id, &world).unwrap();
+        storage.sync().unwrap();
+        assert_eq!(3, storage.num_records());
+
+        let mut buf = vec![0u8; PAGE_SIZE];
+        storage.read_page_data(world_page_id, &mut buf).unwrap();
+        assert_eq!(world[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        assert!(matches!(
+            storage.read_page_data(PageId(3), &mut buf),
+            Err(Error::PastEof(_))
+        ));
+        drop(storage);
+
+        // 書きかけのレコードを残してクラッシュした
+        let mut log_file = OpenOptions::new()
+            .append(true)
+            .open(&log_file_path)
+            .unwrap();
+        log_file
+            .write_all(&hello_page_id.to_u64().to_le_bytes())
+            .unwrap();
+        log_file.write_all(&world[..100]).unwrap();
+        drop(log_file);
+
+        let mut storage = AppendOnlyStorage::open(&log_file_path).unwrap();
+        assert_eq!(3, storage.num_records());
+        storage.read_page_data(hello_page_id, &mut buf).unwrap();
+        assert_eq!(hello[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        storage.read_page_data(world_page_id, &mut buf).unwrap();
+        assert_eq!(world[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        // 採番も続きから行う
+        assert_eq!(PageId(3), storage.allocate_page().unwrap());
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::task;
+
+use super::disk::{read_header, write_header, FileHeader};
+use crate::buffer::entity::PAGE_SIZE;
+use crate::storage::{
+    checksum,
+    entity::PageId,
+    manager::{self, AsyncStorageManager},
+};
+
+// ブロッキングスレッドで位置指定の読み書きを行う非同期 storagemanager
+// ファイルのフォーマットは DiskManager と同じ
+pub struct AsyncDiskManager {
+    // ヒープファイルのファイルディスクリプタ
+    heap_file: Arc<File>,
+    // 採番するページを決めるカウンタ
+    next_page_id: AtomicU64,
+}
+
+impl AsyncDiskManager {
+    pub fn new(mut heap_file: File) -> Result<Self> {
+        let heap_file_size = heap_file.metadata()?.len();
+        if heap_file_size == 0 {
+            // 新しいファイルにはヘッダを書き込む
+            write_header(&mut heap_file, &FileHeader::new(PAGE_SIZE), PAGE_SIZE)?;
+        } else {
+            read_header(&mut heap_file, PAGE_SIZE)?;
+        }
+        let next_page_id = std::cmp::max(heap_file_size / PAGE_SIZE as u64, 1);
+        Ok(Self {
+            heap_file: Arc::new(heap_file),
+            next_page_id: AtomicU64::new(next_page_id),
+        })
+    }
+
+    pub fn open(heap_file_path: impl AsRef<Path>) -> Result<Self> {
+        let heap_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(heap_file_path)?;
+        Self::new(heap_file)
+    }
+}
+
+async fn spawn_io<R: Send + 'static>(f: impl FnOnce() -> Result<R> + Send + 'static) -> Result<R> {
+    task::spawn_blocking(f).await.map_err(Error::other)?
+}
+
+impl AsyncStorageManager for AsyncDiskManager {
+    fn allocate_page(&self) -> PageId {
+        PageId(self.next_page_id.fetch_add(1, Ordering::SeqCst))
+    }
+    async fn read_page_data(&self, page_id: PageId, data: &mut [u8]) -> manager::Result<()> {
+        let heap_file = Arc::clone(&self.heap_file);
+        let len = data.len();
+        let page = spawn_io(move || {
+            let mut page = vec![0u8; len];
+            heap_file.read_exact_at(&mut page, PAGE_SIZE as u64 * page_id.to_u64())?;
+            Ok(page)
+        })
+        .await
+        .map_err(|err| match err.kind() {
+            ErrorKind::UnexpectedEof => manager::Error::PastEof(page_id),
+            _ => err.into(),
+        })?;
+        if !checksum::verify(&page) {
+            return Err(manager::Error::Corrupt(page_id));
+        }
+        data.copy_from_slice(&page);
+        Ok(())
+    }
+    async fn write_page_data(&self, page_id: PageId, data: &[u8]) -> manager::Result<()> {
+        let heap_file = Arc::clone(&self.heap_file);
+        let mut page = data.to_vec();
+        checksum::stamp(&mut page);
+        spawn_io(move || heap_file.write_all_at(&page, PAGE_SIZE as u64 * page_id.to_u64()))
+            .await?;
+        Ok(())
+    }
+    async fn sync(&self) -> manager::Result<()> {
+        let heap_file = Arc::clone(&self.heap_file);
+        Ok(spawn_io(move || heap_file.sync_all()).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::entity::PAGE_BODY_SIZE;
+    use crate::rdbms::disk::DiskManager;
+    use crate::storage::manager::StorageManager;
+    use tempfile::NamedTempFile;
+
+    #[tokio::test]
+    async fn async_disk_test() {
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let disk = AsyncDiskManager::new(data_file).unwrap();
+        let pages: Vec<_> = (0..4u8)
+            .map(|n| {
+                let mut page = vec![0u8; PAGE_SIZE];
+                page[0] = n;
+                (disk.allocate_page(), page)
+            })
+            .collect();
+        for (page_id, page) in &pages {
+            disk.write_page_data(*page_id, page).await.unwrap();
+        }
+        disk.sync().await.unwrap();
+
+        let mut buf = vec![0u8; PAGE_SIZE];
+        for (page_id, page) in &pages {
+            disk.read_page_data(*page_id, &mut buf).await.unwrap();
+            assert_eq!(page[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        }
+        drop(disk);
+
+        // DiskManager と同じフォーマットで読める
+        let mut disk = DiskManager::open(&data_file_path, PAGE_SIZE).unwrap();
+        let (page_id, page) = &pages[3];
+        disk.read_page_data(*page_id, &mut buf).unwrap();
+        assert_eq!(page[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+    }
+}
@@ -0,0 +1,1169 @@
+use std::cell::RefCell;
+
+use anyhow::Result;
+
+use super::catalog::{Catalog, ColumnSchema, TableId, TableSchema};
+use super::expr::{ArithOp, CmpOp, Expr};
+use super::introspect::is_virtual;
+use super::query::OnConflict;
+use crate::sql::dml::entity::{Type, Value};
+use crate::sql::parser::{self, BinaryOp, ExprKind, SelectItem, Statement};
+
+// 名前が引けない、型が合わないなど (pos は SQL の文字列の中のバイト位置)
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{message} at position {pos}")]
+    Semantic { pos: usize, message: String },
+}
+
+fn semantic_error<T>(pos: usize, message: impl Into<String>) -> Result<T> {
+    Err(Error::Semantic {
+        pos,
+        message: message.into(),
+    }
+    .into())
+}
+
+// 名前をカタログの番号と列の位置に置き換えた文
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BoundStatement {
+    CreateTable {
+        name: String,
+        columns: Vec<ColumnSchema>,
+        num_key_elems: usize,
+    },
+    CreateIndex {
+        table: TableId,
+        name: String,
+        columns: Vec<usize>,
+        unique: bool,
+    },
+    DropTable {
+        table: TableId,
+    },
+    DropIndex {
+        table: TableId,
+        name: String,
+    },
+    // input の行はテーブルの全ての列を順に並べたもの (書かなかった列は NULL)
+    // シーケンスで振る主キーを書かなかったときも NULL にしておき、書くときに振る
+    Insert {
+        table: TableId,
+        input: LogicalPlan,
+        on_conflict: OnConflict,
+    },
+    Query {
+        plan: LogicalPlan,
+        // 結果の列の名前と型 (NULL やパラメータだけの列は Type::Null)
+        columns: Vec<ColumnSchema>,
+    },
+    // input はテーブルの行のうち書き換えるもの
+    Update {
+        table: TableId,
+        input: LogicalPlan,
+        assignments: Vec<(usize, Expr)>,
+    },
+    Delete {
+        table: TableId,
+        input: LogicalPlan,
+    },
+}
+
+// どう実行するかを決める前の問合せ (式の Column は入力の行の列の位置)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogicalPlan {
+    // テーブルの全ての列
+    Scan {
+        table: TableId,
+    },
+    // 与えた式を評価した行 (FROM のない SELECT では列のない一行)
+    Values {
+        rows: Vec<Vec<Expr>>,
+    },
+    Filter {
+        input: Box<LogicalPlan>,
+        cond: Expr,
+    },
+    // 左の列の後に右の列を並べる
+    Join {
+        left: Box<LogicalPlan>,
+        right: Box<LogicalPlan>,
+        cond: Expr,
+    },
+    Project {
+        input: Box<LogicalPlan>,
+        exprs: Vec<Expr>,
+    },
+    Sort {
+        input: Box<LogicalPlan>,
+        keys: Vec<OrderKey>,
+    },
+    Limit {
+        input: Box<LogicalPlan>,
+        limit: Option<u64>,
+        offset: u64,
+    },
+}
+
+impl LogicalPlan {
+    // 出力の列の数
+    pub fn width(&self, catalog: &Catalog) -> Result<usize> {
+        Ok(match self {
+            LogicalPlan::Scan { table } => match catalog.table_by_id(*table) {
+                Some(table) => table.columns.len(),
+                None => anyhow::bail!("table {} does not exist", table),
+            },
+            LogicalPlan::Values { rows } => rows.first().map_or(0, Vec::len),
+            LogicalPlan::Filter { input, .. }
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Limit { input, .. } => input.width(catalog)?,
+            LogicalPlan::Join { left, right, .. } => left.width(catalog)? + right.width(catalog)?,
+            LogicalPlan::Project { exprs, .. } => exprs.len(),
+        })
+    }
+
+    // table を読むか
+    pub fn reads(&self, table: TableId) -> bool {
+        match self {
+            LogicalPlan::Scan { table: scanned } => *scanned == table,
+            LogicalPlan::Values { .. } => false,
+            LogicalPlan::Filter { input, .. }
+            | LogicalPlan::Project { input, .. }
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Limit { input, .. } => input.reads(table),
+            LogicalPlan::Join { left, right, .. } => left.reads(table) || right.reads(table),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderKey {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+// FROM に書いたテーブルの列を並べたもの
+#[derive(Debug, Default)]
+struct Scope {
+    columns: Vec<ScopeColumn>,
+}
+
+#[derive(Debug)]
+struct ScopeColumn {
+    // 別名があれば別名
+    table: String,
+    name: String,
+    column_type: Type,
+}
+
+impl Scope {
+    fn add_table(&mut self, name: &str, schema: &TableSchema) {
+        for column in schema.columns.iter() {
+            self.columns.push(ScopeColumn {
+                table: name.to_string(),
+                name: column.name.clone(),
+                column_type: column.column_type,
+            });
+        }
+    }
+
+    fn has_table(&self, name: &str) -> bool {
+        self.columns.iter().any(|column| column.table == name)
+    }
+
+    fn resolve(&self, table: Option<&str>, name: &str, pos: usize) -> Result<(usize, Type)> {
+        if let Some(table) = table {
+            if !self.has_table(table) {
+                return semantic_error(pos, format!("unknown table {}", table));
+            }
+        }
+        let mut found = self.columns.iter().enumerate().filter(|(_, column)| {
+            column.name == name && table.is_none_or(|table| column.table == table)
+        });
+        let full_name = match table {
+            Some(table) => format!("{}.{}", table, name),
+            None => name.to_string(),
+        };
+        match (found.next(), found.next()) {
+            (Some((index, column)), None) => Ok((index, column.column_type)),
+            (Some(_), Some(_)) => semantic_error(pos, format!("ambiguous column {}", full_name)),
+            (None, _) => semantic_error(pos, format!("unknown column {}", full_name)),
+        }
+    }
+}
+
+thread_local! {
+    // bind_with_param_types で束縛している間に、パラメータの型を周りの式から決めたもの
+    static PARAM_TYPES: RefCell<Vec<Option<Type>>> = const { RefCell::new(Vec::new()) };
+}
+
+// bind に加えて、パラメータ ($1 から順) ごとにその型を返す
+// 列と比べたり列に入れたりしない NULL やパラメータだけで決まらないものは None
+pub fn bind_with_param_types(
+    catalog: &Catalog,
+    statement: &Statement,
+) -> Result<(BoundStatement, Vec<Option<Type>>)> {
+    let saved = PARAM_TYPES.with(|types| types.replace(vec![]));
+    let bound = bind_statement(catalog, statement);
+    let types = PARAM_TYPES.with(|types| types.replace(saved));
+    Ok((bound?, types))
+}
+
+// index 番目のパラメータの型を f に渡す (まだ出てこなかったパラメータも None で埋める)
+fn with_param_type<R>(index: usize, f: impl FnOnce(&mut Option<Type>) -> R) -> R {
+    PARAM_TYPES.with(|types| {
+        let mut types = types.borrow_mut();
+        if types.len() <= index {
+            types.resize(index + 1, None);
+        }
+        f(&mut types[index])
+    })
+}
+
+// 型の決まった式の相手がパラメータなら、その型を覚えておく
+fn note_param(expr: &Expr, param_type: Type, pos: usize) -> Result<()> {
+    let index = match expr {
+        Expr::Param(index) => *index,
+        _ => return Ok(()),
+    };
+    with_param_type(index, |known| match *known {
+        Some(known) if known != param_type => {
+            let message = format!(
+                "parameter ${} is used as both {:?} and {:?}",
+                index + 1,
+                known,
+                param_type
+            );
+            semantic_error(pos, message)
+        }
+        _ => {
+            *known = Some(param_type);
+            Ok(())
+        }
+    })
+}
+
+// 文の名前をカタログで引き、列の数と型を確かめる
+pub fn bind(catalog: &Catalog, statement: &Statement) -> Result<BoundStatement> {
+    Ok(bind_with_param_types(catalog, statement)?.0)
+}
+
+fn bind_statement(catalog: &Catalog, statement: &Statement) -> Result<BoundStatement> {
+    match statement {
+        Statement::CreateTable(create) => bind_create_table(catalog, create),
+        Statement::CreateIndex(create) => bind_create_index(catalog, create),
+        Statement::DropTable(table) => Ok(BoundStatement::DropTable {
+            table: lookup_user_table(catalog, table)?.id,
+        }),
+        Statement::DropIndex(drop) => {
+            let schema = lookup_user_table(catalog, &drop.table)?;
+            if !schema
+                .indexes
+                .iter()
+                .any(|index| index.name == drop.name.name)
+            {
+                let message = format!("unknown index {} on {}", drop.name.name, schema.name);
+                return semantic_error(drop.name.pos, message);
+            }
+            Ok(BoundStatement::DropIndex {
+                table: schema.id,
+                name: drop.name.name.clone(),
+            })
+        }
+        Statement::Insert(insert) => bind_insert(catalog, insert),
+        Statement::Select(select) => bind_select(catalog, select),
+        Statement::Update(update) => bind_update(catalog, update),
+        Statement::Delete(delete) => {
+            let (schema, scope) = table_scope(catalog, &delete.table)?;
+            let input = filtered_scan(&scope, schema.id, delete.where_cond.as_ref())?;
+            Ok(BoundStatement::Delete {
+                table: schema.id,
+                input,
+            })
+        }
+    }
+}
+
+fn lookup_table<'c>(catalog: &'c Catalog, table: &parser::Ident) -> Result<&'c TableSchema> {
+    match catalog.table(&table.name) {
+        Some(schema) => Ok(schema),
+        None => semantic_error(table.pos, format!("unknown table {}", table.name)),
+    }
+}
+
+// 書き換えたり定義を変えたりするテーブル (仮想テーブルは読むだけ)
+fn lookup_user_table<'c>(catalog: &'c Catalog, table: &parser::Ident) -> Result<&'c TableSchema> {
+    let schema = lookup_table(catalog, table)?;
+    if is_virtual(schema.id) {
+        return semantic_error(table.pos, format!("{} is read-only", table.name));
+    }
+    Ok(schema)
+}
+
+fn lookup_column(schema: &TableSchema, column: &parser::Ident) -> Result<usize> {
+    match schema.column(&column.name) {
+        Some(index) => Ok(index),
+        None => semantic_error(
+            column.pos,
+            format!("unknown column {} in {}", column.name, schema.name),
+        ),
+    }
+}
+
+fn table_scope<'c>(
+    catalog: &'c Catalog,
+    table: &parser::Ident,
+) -> Result<(&'c TableSchema, Scope)> {
+    let schema = lookup_user_table(catalog, table)?;
+    let mut scope = Scope::default();
+    scope.add_table(&schema.name, schema);
+    Ok((schema, scope))
+}
+
+fn filtered_scan(
+    scope: &Scope,
+    table: TableId,
+    where_cond: Option<&parser::Expr>,
+) -> Result<LogicalPlan> {
+    let scan = LogicalPlan::Scan { table };
+    match where_cond {
+        Some(cond) => Ok(LogicalPlan::Filter {
+            input: Box::new(scan),
+            cond: bind_cond(scope, cond, "WHERE")?,
+        }),
+        None => Ok(scan),
+    }
+}
+
+fn bind_create_table(catalog: &Catalog, create: &parser::CreateTable) -> Result<BoundStatement> {
+    if catalog.table(&create.name.name).is_some() {
+        let message = format!("table {} already exists", create.name.name);
+        return semantic_error(create.name.pos, message);
+    }
+    for (i, column) in create.columns.iter().enumerate() {
+        if create.columns[..i]
+            .iter()
+            .any(|other| other.name.name == column.name.name)
+        {
+            let message = format!("duplicate column {}", column.name.name);
+            return semantic_error(column.name.pos, message);
+        }
+    }
+    if create.primary_key.is_empty() {
+        let message = format!("table {} has no primary key", create.name.name);
+        return semantic_error(create.name.pos, message);
+    }
+    // 主キーは B-tree のキーになる先頭の列でなければならない
+    for (i, key) in create.primary_key.iter().enumerate() {
+        match create.columns.iter().position(|c| c.name.name == key.name) {
+            Some(index) if index == i => {}
+            Some(_) => {
+                let message = format!(
+                    "primary key column {} must be column {} of the table",
+                    key.name,
+                    i + 1
+                );
+                return semantic_error(key.pos, message);
+            }
+            None => return semantic_error(key.pos, format!("unknown column {}", key.name)),
+        }
+    }
+    Ok(BoundStatement::CreateTable {
+        name: create.name.name.clone(),
+        columns: create
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| ColumnSchema {
+                name: column.name.name.clone(),
+                column_type: column.column_type,
+                // 主キーの列は NULL にできない
+                nullable: column.nullable && i >= create.primary_key.len(),
+            })
+            .collect(),
+        num_key_elems: create.primary_key.len(),
+    })
+}
+
+fn bind_create_index(catalog: &Catalog, create: &parser::CreateIndex) -> Result<BoundStatement> {
+    let schema = lookup_user_table(catalog, &create.table)?;
+    if schema
+        .indexes
+        .iter()
+        .any(|index| index.name == create.name.name)
+    {
+        let message = format!(
+            "index {} already exists on {}",
+            create.name.name, schema.name
+        );
+        return semantic_error(create.name.pos, message);
+    }
+    let columns = create
+        .columns
+        .iter()
+        .map(|column| lookup_column(schema, column))
+        .collect::<Result<_>>()?;
+    Ok(BoundStatement::CreateIndex {
+        table: schema.id,
+        name: create.name.name.clone(),
+        columns,
+        unique: create.unique,
+    })
+}
+
+fn bind_insert(catalog: &Catalog, insert: &parser::Insert) -> Result<BoundStatement> {
+    let schema = lookup_user_table(catalog, &insert.table)?;
+    let targets = match &insert.columns {
+        Some(columns) => {
+            let mut targets = vec![];
+            for column in columns {
+                let index = lookup_column(schema, column)?;
+                if targets.contains(&index) {
+                    let message = format!("column {} is given twice", column.name);
+                    return semantic_error(column.pos, message);
+                }
+                targets.push(index);
+            }
+            targets
+        }
+        None => (0..schema.columns.len()).collect(),
+    };
+    let generated = |index: &usize| *index == 0 && schema.sequence_page_id.is_some();
+    if let Some(missing) =
+        (0..schema.num_key_elems).find(|index| !targets.contains(index) && !generated(index))
+    {
+        let message = format!(
+            "no value for primary key column {}",
+            schema.columns[missing].name
+        );
+        return semantic_error(insert.table.pos, message);
+    }
+    let input = match &insert.source {
+        parser::InsertSource::Values(rows) => bind_values(schema, &targets, insert, rows)?,
+        parser::InsertSource::Select(select) => {
+            bind_insert_select(catalog, schema, &targets, insert, select)?
+        }
+    };
+    let on_conflict = match &insert.on_conflict {
+        Some(on_conflict) => bind_on_conflict(schema, on_conflict)?,
+        None => OnConflict::Error,
+    };
+    Ok(BoundStatement::Insert {
+        table: schema.id,
+        input,
+        on_conflict,
+    })
+}
+
+fn bind_values(
+    schema: &TableSchema,
+    targets: &[usize],
+    insert: &parser::Insert,
+    rows: &[Vec<parser::Expr>],
+) -> Result<LogicalPlan> {
+    let empty = Scope::default();
+    let mut values_rows = vec![];
+    for values in rows.iter() {
+        if values.len() != targets.len() {
+            let pos = values.first().map_or(insert.table.pos, |value| value.pos);
+            let message = format!("expected {} values but got {}", targets.len(), values.len());
+            return semantic_error(pos, message);
+        }
+        let mut row = vec![Expr::Literal(Value::Null); schema.columns.len()];
+        for (&index, value) in targets.iter().zip(values) {
+            let column = &schema.columns[index];
+            row[index] = bind_typed(&empty, value, column.column_type, &column.name)?;
+        }
+        values_rows.push(row);
+    }
+    Ok(LogicalPlan::Values { rows: values_rows })
+}
+
+// 衝突を調べる列は主キーに限る
+// DO UPDATE の式はテーブルの列に excluded の列を続けた行で評価する (主キーは書き換えられない)
+fn bind_on_conflict(schema: &TableSchema, on_conflict: &parser::OnConflict) -> Result<OnConflict> {
+    if let Some(columns) = &on_conflict.columns {
+        let mut indexes = vec![];
+        for column in columns {
+            indexes.push(lookup_column(schema, column)?);
+        }
+        indexes.sort_unstable();
+        if indexes != (0..schema.num_key_elems).collect::<Vec<_>>() {
+            let message = "ON CONFLICT columns must be the primary key".to_string();
+            return semantic_error(columns[0].pos, message);
+        }
+    }
+    let assignments = match &on_conflict.action {
+        parser::ConflictAction::Nothing => return Ok(OnConflict::Nothing),
+        parser::ConflictAction::Update(assignments) => assignments,
+    };
+    let mut scope = Scope::default();
+    scope.add_table(&schema.name, schema);
+    scope.add_table("excluded", schema);
+    let mut bound: Vec<(usize, Expr)> = vec![];
+    for (column, value) in assignments.iter() {
+        let index = lookup_column(schema, column)?;
+        if index < schema.num_key_elems {
+            let message = format!("cannot update primary key column {}", column.name);
+            return semantic_error(column.pos, message);
+        }
+        if bound.iter().any(|(other, _)| *other == index) {
+            let message = format!("column {} is given twice", column.name);
+            return semantic_error(column.pos, message);
+        }
+        let column_type = schema.columns[index].column_type;
+        bound.push((index, bind_typed(&scope, value, column_type, &column.name)?));
+    }
+    Ok(OnConflict::Update(bound))
+}
+
+// SELECT の結果の列を順に targets の列に入れ、テーブルの列の並びにする
+fn bind_insert_select(
+    catalog: &Catalog,
+    schema: &TableSchema,
+    targets: &[usize],
+    insert: &parser::Insert,
+    select: &parser::Select,
+) -> Result<LogicalPlan> {
+    let (plan, columns) = match bind_select(catalog, select)? {
+        BoundStatement::Query { plan, columns } => (plan, columns),
+        bound => unreachable!("SELECT is bound to {:?}", bound),
+    };
+    if columns.len() != targets.len() {
+        let message = format!(
+            "expected {} columns but SELECT returns {}",
+            targets.len(),
+            columns.len()
+        );
+        return semantic_error(insert.table.pos, message);
+    }
+    let mut exprs = vec![Expr::Literal(Value::Null); schema.columns.len()];
+    for (i, (&index, column)) in targets.iter().zip(&columns).enumerate() {
+        let target = &schema.columns[index];
+        if column.column_type != Type::Null && column.column_type != target.column_type {
+            let message = format!(
+                "column {} is {:?} but the selected {} is {:?}",
+                target.name, target.column_type, column.name, column.column_type
+            );
+            return semantic_error(insert.table.pos, message);
+        }
+        exprs[index] = Expr::Column(i);
+    }
+    Ok(LogicalPlan::Project {
+        input: Box::new(plan),
+        exprs,
+    })
+}
+
+fn bind_select(catalog: &Catalog, select: &parser::Select) -> Result<BoundStatement> {
+    let mut scope = Scope::default();
+    let mut plan: Option<LogicalPlan> = None;
+    for item in select.from.iter() {
+        let schema = lookup_table(catalog, &item.table)?;
+        let name = item.alias.as_ref().unwrap_or(&item.table);
+        if scope.has_table(&name.name) {
+            return semantic_error(name.pos, format!("table {} is given twice", name.name));
+        }
+        scope.add_table(&name.name, schema);
+        let scan = LogicalPlan::Scan { table: schema.id };
+        plan = Some(match plan {
+            None => scan,
+            Some(left) => LogicalPlan::Join {
+                left: Box::new(left),
+                right: Box::new(scan),
+                cond: match &item.on {
+                    Some(on) => bind_cond(&scope, on, "ON")?,
+                    None => Expr::TRUE,
+                },
+            },
+        });
+    }
+    let mut plan = plan.unwrap_or(LogicalPlan::Values { rows: vec![vec![]] });
+    if let Some(cond) = &select.where_cond {
+        plan = LogicalPlan::Filter {
+            input: Box::new(plan),
+            cond: bind_cond(&scope, cond, "WHERE")?,
+        };
+    }
+
+    // 結果の列と、ORDER BY で別名を引くための名前
+    let mut exprs = vec![];
+    let mut columns = vec![];
+    let mut aliases = vec![];
+    for item in select.items.iter() {
+        match item {
+            SelectItem::Wildcard(pos) => {
+                if scope.columns.is_empty() {
+                    return semantic_error(*pos, "* needs a FROM clause");
+                }
+                for (index, column) in scope.columns.iter().enumerate() {
+                    exprs.push(Expr::Column(index));
+                    // 外部結合で NULL が入るかもしれないので、結果の列はどれも NULL になりうるとする
+                    columns.push(ColumnSchema {
+                        name: column.name.clone(),
+                        column_type: column.column_type,
+                        nullable: true,
+                    });
+                    aliases.push(None);
+                }
+            }
+            SelectItem::Expr { expr, alias } => {
+                let (bound, column_type) = bind_expr(&scope, expr)?;
+                let name = match (alias, &expr.kind) {
+                    (Some(alias), _) => alias.name.clone(),
+                    (None, ExprKind::Column { name, .. }) => name.clone(),
+                    (None, _) => "?column?".to_string(),
+                };
+                exprs.push(bound);
+                columns.push(ColumnSchema {
+                    name,
+                    column_type: column_type.unwrap_or(Type::Null),
+                    nullable: true,
+                });
+                aliases.push(alias.as_ref().map(|alias| alias.name.clone()));
+            }
+        }
+    }
+
+    if !select.order_by.is_empty() {
+        let mut keys = vec![];
+        for order_by in select.order_by.iter() {
+            let expr = match &order_by.expr.kind {
+                // 結果の列の番号 (1 から数える)
+                ExprKind::Literal(Value::Int(n)) => {
+                    match exprs.get((*n as usize).wrapping_sub(1)) {
+                        Some(expr) if *n > 0 => expr.clone(),
+                        _ => {
+                            let message = format!("ORDER BY position {} is out of range", n);
+                            return semantic_error(order_by.expr.pos, message);
+                        }
+                    }
+                }
+                // 結果の列の別名
+                ExprKind::Column { table: None, name }
+                    if aliases.iter().any(|alias| alias.as_deref() == Some(name)) =>
+                {
+                    let index = aliases
+                        .iter()
+                        .position(|alias| alias.as_deref() == Some(name))
+                        .unwrap();
+                    exprs[index].clone()
+                }
+                _ => bind_expr(&scope, &order_by.expr)?.0,
+            };
+            keys.push(OrderKey {
+                expr,
+                descending: order_by.descending,
+            });
+        }
+        plan = LogicalPlan::Sort {
+            input: Box::new(plan),
+            keys,
+        };
+    }
+    plan = LogicalPlan::Project {
+        input: Box::new(plan),
+        exprs,
+    };
+    if select.limit.is_some() || select.offset.is_some() {
+        plan = LogicalPlan::Limit {
+            input: Box::new(plan),
+            limit: select.limit,
+            offset: select.offset.unwrap_or(0),
+        };
+    }
+    Ok(BoundStatement::Query { plan, columns })
+}
+
+fn bind_update(catalog: &Catalog, update: &parser::Update) -> Result<BoundStatement> {
+    let (schema, scope) = table_scope(catalog, &update.table)?;
+    let mut assignments: Vec<(usize, Expr)> = vec![];
+    for (column, value) in update.assignments.iter() {
+        let index = lookup_column(schema, column)?;
+        if assignments.iter().any(|(other, _)| *other == index) {
+            let message = format!("column {} is given twice", column.name);
+            return semantic_error(column.pos, message);
+        }
+        let column_type = schema.columns[index].column_type;
+        assignments.push((index, bind_typed(&scope, value, column_type, &column.name)?));
+    }
+    let input = filtered_scan(&scope, schema.id, update.where_cond.as_ref())?;
+    Ok(BoundStatement::Update {
+        table: schema.id,
+        input,
+        assignments,
+    })
+}
+
+// WHERE や ON の条件 (真偽値か NULL でなければならない)
+fn bind_cond(scope: &Scope, cond: &parser::Expr, clause: &str) -> Result<Expr> {
+    let (bound, cond_type) = bind_expr(scope, cond)?;
+    match cond_type {
+        None => {
+            note_param(&bound, Type::Bool, cond.pos)?;
+            Ok(bound)
+        }
+        Some(Type::Bool) => Ok(bound),
+        Some(other) => {
+            let message = format!("{} must be a boolean but got {:?}", clause, other);
+            semantic_error(cond.pos, message)
+        }
+    }
+}
+
+// column の列に入れる値
+fn bind_typed(
+    scope: &Scope,
+    value: &parser::Expr,
+    column_type: Type,
+    column: &str,
+) -> Result<Expr> {
+    let (bound, value_type) = bind_expr(scope, value)?;
+    match value_type {
+        Some(value_type) if value_type != column_type => {
+            let message = format!(
+                "column {} is {:?} but the value is {:?}",
+                column, column_type, value_type
+            );
+            semantic_error(value.pos, message)
+        }
+        Some(_) => Ok(bound),
+        None => {
+            note_param(&bound, column_type, value.pos)?;
+            Ok(bound)
+        }
+    }
+}
+
+// 式の名前を列の位置にして型を決める (NULL やパラメータのように決まらなければ None)
+fn bind_expr(scope: &Scope, expr: &parser::Expr) -> Result<(Expr, Option<Type>)> {
+    // 決まっている型が want と違えば失敗する
+    let expect = |operand: &parser::Expr, want: Type, what: &str| -> Result<Expr> {
+        let (bound, operand_type) = bind_expr(scope, operand)?;
+        match operand_type {
+            Some(operand_type) if operand_type != want => {
+                let message = format!("{} needs {:?} but got {:?}", what, want, operand_type);
+                semantic_error(operand.pos, message)
+            }
+            Some(_) => Ok(bound),
+            None => {
+                note_param(&bound, want, operand.pos)?;
+                Ok(bound)
+            }
+        }
+    };
+    Ok(match &expr.kind {
+        ExprKind::Column { table, name } => {
+            let (index, column_type) = scope.resolve(table.as_deref(), name, expr.pos)?;
+            (Expr::Column(index), Some(column_type))
+        }
+        ExprKind::Literal(Value::Null) => (Expr::Literal(Value::Null), None),
+        ExprKind::Literal(value) => (Expr::Literal(value.clone()), Some(value.column_type())),
+        ExprKind::Param(index) => {
+            with_param_type(*index, |_| ());
+            (Expr::Param(*index), None)
+        }
+        ExprKind::Binary(op, lhs, rhs) => {
+            let arith = match op {
+                BinaryOp::Add => Some(ArithOp::Add),
+                BinaryOp::Sub => Some(ArithOp::Sub),
+                BinaryOp::Mul => Some(ArithOp::Mul),
+                BinaryOp::Div => Some(ArithOp::Div),
+                _ => None,
+            };
+            let cmp = match op {
+                BinaryOp::Eq => Some(CmpOp::Eq),
+                BinaryOp::Ne => Some(CmpOp::Ne),
+                BinaryOp::Lt => Some(CmpOp::Lt),
+                BinaryOp::Le => Some(CmpOp::Le),
+                BinaryOp::Gt => Some(CmpOp::Gt),
+                BinaryOp::Ge => Some(CmpOp::Ge),
+                _ => None,
+            };
+            if let Some(arith) = arith {
+                let what = format!("{:?}", arith);
+                let lhs = expect(lhs, Type::Int, &what)?;
+                let rhs = expect(rhs, Type::Int, &what)?;
+                (Expr::arith(arith, lhs, rhs), Some(Type::Int))
+            } else if let Some(cmp) = cmp {
+                let (lhs_bound, lhs_type) = bind_expr(scope, lhs)?;
+                let (rhs_bound, rhs_type) = bind_expr(scope, rhs)?;
+                match (lhs_type, rhs_type) {
+                    (Some(lhs_type), Some(rhs_type)) if lhs_type != rhs_type => {
+                        let message = format!("cannot compare {:?} with {:?}", lhs_type, rhs_type);
+                        return semantic_error(rhs.pos, message);
+                    }
+                    (None, Some(rhs_type)) => note_param(&lhs_bound, rhs_type, lhs.pos)?,
+                    (Some(lhs_type), None) => note_param(&rhs_bound, lhs_type, rhs.pos)?,
+                    _ => {}
+                }
+                (Expr::compare(cmp, lhs_bound, rhs_bound), Some(Type::Bool))
+            } else {
+                let what = if *op == BinaryOp::And { "AND" } else { "OR" };
+                let operands = vec![
+                    expect(lhs, Type::Bool, what)?,
+                    expect(rhs, Type::Bool, what)?,
+                ];
+                // 同じ演算が続けば一つにまとめる
+                let flatten = |operands: Vec<Expr>| {
+                    let mut flat = vec![];
+                    for operand in operands {
+                        match (operand, op) {
+                            (Expr::And(inner), BinaryOp::And) | (Expr::Or(inner), BinaryOp::Or) => {
+                                flat.extend(inner)
+                            }
+                            (operand, _) => flat.push(operand),
+                        }
+                    }
+                    flat
+                };
+                let bound = match op {
+                    BinaryOp::And => Expr::And(flatten(operands)),
+                    _ => Expr::Or(flatten(operands)),
+                };
+                (bound, Some(Type::Bool))
+            }
+        }
+        ExprKind::Not(operand) => (
+            Expr::Not(Box::new(expect(operand, Type::Bool, "NOT")?)),
+            Some(Type::Bool),
+        ),
+        ExprKind::Neg(operand) => {
+            let operand = expect(operand, Type::Int, "-")?;
+            let zero = Expr::Literal(Value::Int(0));
+            (Expr::arith(ArithOp::Sub, zero, operand), Some(Type::Int))
+        }
+        ExprKind::IsNull { expr, negated } => {
+            let is_null = Expr::IsNull(Box::new(bind_expr(scope, expr)?.0));
+            let bound = if *negated {
+                Expr::Not(Box::new(is_null))
+            } else {
+                is_null
+            };
+            (bound, Some(Type::Bool))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::parse_statement;
+    use crate::storage::entity::PageId;
+
+    fn catalog() -> Catalog {
+        let column = |name: &str, column_type| ColumnSchema {
+            name: name.to_string(),
+            column_type,
+            nullable: true,
+        };
+        let mut catalog = Catalog::new();
+        let people = vec![
+            column("id", Type::Int),
+            column("name", Type::Bytes),
+            column("dept", Type::Int),
+        ];
+        catalog.add_table("people", people, 1, PageId(1)).unwrap();
+        let dept = vec![column("id", Type::Int), column("title", Type::Bytes)];
+        catalog.add_table("dept", dept, 1, PageId(2)).unwrap();
+        catalog
+    }
+
+    fn bind_sql(catalog: &Catalog, sql: &str) -> Result<BoundStatement> {
+        bind(catalog, &parse_statement(sql)?)
+    }
+
+    fn error(catalog: &Catalog, sql: &str) -> (usize, String) {
+        let err = bind_sql(catalog, sql).unwrap_err();
+        match err.downcast::<Error>() {
+            Ok(Error::Semantic { pos, message }) => (pos, message),
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn select_test() {
+        let catalog = catalog();
+        let bound = bind_sql(
+            &catalog,
+            "SELECT p.name, d.title AS t FROM people p JOIN dept d ON p.dept = d.id \
+             WHERE p.id > 1 AND name IS NOT NULL ORDER BY t DESC LIMIT 3",
+        )
+        .unwrap();
+        let cmp = |op, lhs, rhs| Expr::compare(op, lhs, rhs);
+        let plan = LogicalPlan::Limit {
+            input: Box::new(LogicalPlan::Project {
+                input: Box::new(LogicalPlan::Sort {
+                    input: Box::new(LogicalPlan::Filter {
+                        input: Box::new(LogicalPlan::Join {
+                            left: Box::new(LogicalPlan::Scan { table: 0 }),
+                            right: Box::new(LogicalPlan::Scan { table: 1 }),
+                            // 右の列は左の三列の後
+                            cond: cmp(CmpOp::Eq, Expr::Column(2), Expr::Column(3)),
+                        }),
+                        cond: Expr::And(vec![
+                            cmp(CmpOp::Gt, Expr::Column(0), Expr::Literal(Value::Int(1))),
+                            Expr::Not(Box::new(Expr::IsNull(Box::new(Expr::Column(1))))),
+                        ]),
+                    }),
+                    keys: vec![OrderKey {
+                        expr: Expr::Column(4),
+                        descending: true,
+                    }],
+                }),
+                exprs: vec![Expr::Column(1), Expr::Column(4)],
+            }),
+            limit: Some(3),
+            offset: 0,
+        };
+        let columns = vec![
+            ColumnSchema {
+                name: "name".to_string(),
+                column_type: Type::Bytes,
+                nullable: true,
+            },
+            ColumnSchema {
+                name: "t".to_string(),
+                column_type: Type::Bytes,
+                nullable: true,
+            },
+        ];
+        assert_eq!(BoundStatement::Query { plan, columns }, bound);
+
+        // FROM がなければ列のない一行に対して評価する
+        match bind_sql(&catalog, "SELECT 1 + $1").unwrap() {
+            BoundStatement::Query { plan, columns } => {
+                assert_eq!(Type::Int, columns[0].column_type);
+                assert!(matches!(
+                    plan,
+                    LogicalPlan::Project { input, .. } if *input == LogicalPlan::Values { rows: vec![vec![]] }
+                ));
+            }
+            bound => panic!("unexpected statement: {:?}", bound),
+        }
+    }
+
+    #[test]
+    fn dml_test() {
+        let catalog = catalog();
+        assert_eq!(
+            BoundStatement::Insert {
+                table: 0,
+                input: LogicalPlan::Values {
+                    rows: vec![vec![
+                        Expr::Literal(Value::Int(7)),
+                        Expr::Literal(Value::Null),
+                        Expr::Param(0),
+                    ]],
+                },
+                on_conflict: OnConflict::Error,
+            },
+            bind_sql(&catalog, "INSERT INTO people (dept, id) VALUES ($1, 7)").unwrap()
+        );
+        // シーケンスで振る主キーは書かなくてよい
+        let sql = "INSERT INTO people (name) VALUES ('x')";
+        assert!(bind_sql(&catalog, sql).is_err());
+        let mut catalog = catalog;
+        catalog.set_sequence(0, PageId(3)).unwrap();
+        match bind_sql(&catalog, sql).unwrap() {
+            BoundStatement::Insert {
+                input: LogicalPlan::Values { rows },
+                ..
+            } => assert_eq!(Expr::Literal(Value::Null), rows[0][0]),
+            bound => panic!("unexpected statement: {:?}", bound),
+        }
+        // SELECT の列を書いた列の位置に並べ、書かなかった列は NULL
+        match bind_sql(
+            &catalog,
+            "INSERT INTO people (dept, id) SELECT id, id + 1 FROM dept",
+        )
+        .unwrap()
+        {
+            BoundStatement::Insert {
+                table: 0,
+                input: LogicalPlan::Project { exprs, .. },
+                ..
+            } => assert_eq!(
+                vec![Expr::Column(1), Expr::Literal(Value::Null), Expr::Column(0)],
+                exprs
+            ),
+            bound => panic!("unexpected statement: {:?}", bound),
+        }
+        assert_eq!(
+            (12, "expected 2 columns but SELECT returns 1".to_string()),
+            error(&catalog, "INSERT INTO dept SELECT id FROM people")
+        );
+        assert_eq!(
+            (
+                12,
+                "column id is Int but the selected name is Bytes".to_string()
+            ),
+            error(&catalog, "INSERT INTO dept SELECT name, id FROM people")
+        );
+        // excluded の列は今の行の列の後ろに続く
+        match bind_sql(
+            &catalog,
+            "INSERT INTO people VALUES (1, 'x', 2) ON CONFLICT (id) \
+             DO UPDATE SET name = excluded.name, dept = people.dept + 1",
+        )
+        .unwrap()
+        {
+            BoundStatement::Insert {
+                on_conflict: OnConflict::Update(assignments),
+                ..
+            } => {
+                assert_eq!((1, Expr::Column(4)), assignments[0]);
+                assert_eq!(2, assignments[1].0);
+            }
+            bound => panic!("unexpected statement: {:?}", bound),
+        }
+        assert_eq!(
+            (
+                46,
+                "ON CONFLICT columns must be the primary key".to_string()
+            ),
+            error(
+                &catalog,
+                "INSERT INTO dept VALUES (1, 'x') ON CONFLICT (title) DO NOTHING"
+            )
+        );
+        assert_eq!(
+            (59, "cannot update primary key column id".to_string()),
+            error(
+                &catalog,
+                "INSERT INTO dept VALUES (1, 'x') ON CONFLICT DO UPDATE SET id = 2"
+            )
+        );
+        assert_eq!(
+            (67, "ambiguous column title".to_string()),
+            error(
+                &catalog,
+                "INSERT INTO dept VALUES (1, 'x') ON CONFLICT DO UPDATE SET title = title"
+            )
+        );
+        match bind_sql(&catalog, "UPDATE people SET dept = dept + 1 WHERE id = 3").unwrap() {
+            BoundStatement::Update {
+                table,
+                input,
+                assignments,
+            } => {
+                assert_eq!(0, table);
+                assert!(matches!(input, LogicalPlan::Filter { .. }));
+                assert_eq!(2, assignments[0].0);
+            }
+            bound => panic!("unexpected statement: {:?}", bound),
+        }
+        assert_eq!(
+            BoundStatement::Delete {
+                table: 1,
+                input: LogicalPlan::Scan { table: 1 },
+            },
+            bind_sql(&catalog, "DELETE FROM dept").unwrap()
+        );
+    }
+
+    #[test]
+    fn param_types_test() {
+        let catalog = catalog();
+        let param_types = |sql: &str| {
+            let statement = parse_statement(sql).unwrap();
+            bind_with_param_types(&catalog, &statement).map(|(_, types)| types)
+        };
+        assert_eq!(
+            vec![Some(Type::Int), Some(Type::Bytes)],
+            param_types("SELECT name FROM people WHERE $1 < id AND name = $2").unwrap()
+        );
+        assert_eq!(vec![Some(Type::Int)], param_types("SELECT -$1").unwrap());
+        // 使わなかったパラメータや、相手の型が決まらないパラメータは None
+        assert_eq!(
+            vec![None, Some(Type::Int)],
+            param_types("INSERT INTO people (id, dept) VALUES ($2, 1)").unwrap()
+        );
+        assert_eq!(vec![None], param_types("SELECT $1 IS NULL").unwrap());
+        let err = param_types("SELECT * FROM people WHERE id = $1 AND name = $1").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("parameter $1 is used as both Int and Bytes"));
+    }
+
+    #[test]
+    fn ddl_test() {
+        let catalog = catalog();
+        assert_eq!(
+            BoundStatement::CreateIndex {
+                table: 0,
+                name: "by_dept".to_string(),
+                columns: vec![2, 0],
+                unique: false,
+            },
+            bind_sql(&catalog, "CREATE INDEX by_dept ON people (dept, id)").unwrap()
+        );
+        match bind_sql(
+            &catalog,
+            "CREATE TABLE t (a INT, b TEXT, c BOOL, PRIMARY KEY (a, b))",
+        ) {
+            Ok(BoundStatement::CreateTable { num_key_elems, .. }) => assert_eq!(2, num_key_elems),
+            bound => panic!("unexpected statement: {:?}", bound),
+        }
+        assert_eq!(
+            (
+                23,
+                "primary key column b must be column 1 of the table".to_string()
+            ),
+            error(&catalog, "CREATE TABLE t (a INT, b TEXT PRIMARY KEY)")
+        );
+        assert_eq!(
+            (13, "table dept already exists".to_string()),
+            error(&catalog, "CREATE TABLE dept (id INT PRIMARY KEY)")
+        );
+        assert_eq!(
+            BoundStatement::DropTable { table: 1 },
+            bind_sql(&catalog, "DROP TABLE dept").unwrap()
+        );
+        assert_eq!(
+            (11, "unknown index by_id on people".to_string()),
+            error(&catalog, "DROP INDEX by_id ON people")
+        );
+    }
+
+    #[test]
+    fn error_test() {
+        let catalog = catalog();
+        assert_eq!(
+            (14, "unknown table staff".to_string()),
+            error(&catalog, "SELECT * FROM staff")
+        );
+        assert_eq!(
+            (7, "ambiguous column id".to_string()),
+            error(&catalog, "SELECT id FROM people, dept")
+        );
+        assert_eq!(
+            (7, "unknown column p.title".to_string()),
+            error(&catalog, "SELECT p.title FROM people p, dept")
+        );
+        assert_eq!(
+            (32, "cannot compare Int with Bytes".to_string()),
+            error(&catalog, "SELECT * FROM people WHERE id = 'x'")
+        );
+        assert_eq!(
+            (27, "WHERE must be a boolean but got Int".to_string()),
+            error(&catalog, "SELECT * FROM people WHERE id + 1")
+        );
+        assert_eq!(
+            (37, "expected 2 values but got 1".to_string()),
+            error(&catalog, "INSERT INTO dept (id, title) VALUES (1)")
+        );
+        assert_eq!(
+            (12, "no value for primary key column id".to_string()),
+            error(&catalog, "INSERT INTO people (name) VALUES ('x')")
+        );
+        assert_eq!(
+            (40, "column title is Bytes but the value is Int".to_string()),
+            error(&catalog, "INSERT INTO dept (id, title) VALUES (1, 2)")
+        );
+        assert_eq!(
+            (37, "ORDER BY position 3 is out of range".to_string()),
+            error(&catalog, "SELECT id, name FROM people ORDER BY 3")
+        );
+    }
+}
@@ -1,5 +1,6 @@
-use std::cell::{Ref, RefMut};
+use std::cmp::Ordering;
 use std::convert::identity;
+use std::ops::Bound;
 use std::rc::Rc;
 
 use bincode::Options;
@@ -54,6 +55,7 @@ fn tuple_slot_id(
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct BTree {
     pub meta_page_id: PageId,
 }
@@ -61,9 +63,9 @@ pub struct BTree {
 impl BTree {
     pub fn create(bufmgr: &mut dyn BufferPoolManager) -> Result<Self, Error> {
         let meta_buffer = bufmgr.create_page()?;
-        let mut meta = meta::Meta::new(meta_buffer.page.borrow_mut() as RefMut<[_]>);
+        let mut meta = meta::Meta::new(meta_buffer.body_mut());
         let root_buffer = bufmgr.create_page()?;
-        let mut root = node::Node::new(root_buffer.page.borrow_mut() as RefMut<[_]>);
+        let mut root = node::Node::new(root_buffer.body_mut());
         root.initialize_as_leaf();
         let mut leaf = leaf::Leaf::new(root.body);
         leaf.initialize();
@@ -78,7 +80,7 @@ impl BTree {
     fn fetch_root_page(&self, bufmgr: &mut dyn BufferPoolManager) -> Result<Rc<Buffer>, Error> {
         let root_page_id = {
             let meta_buffer = bufmgr.fetch_page(self.meta_page_id)?;
-            let meta = meta::Meta::new(meta_buffer.page.borrow() as Ref<[_]>);
+            let meta = meta::Meta::new(meta_buffer.body());
             meta.header.root_page_id
         };
         Ok(bufmgr.fetch_page(root_page_id)?)
@@ -90,7 +92,7 @@ impl BTree {
         node_buffer: Rc<Buffer>,
         search_mode: SearchMode,
     ) -> Result<Iter, Error> {
-        let node = node::Node::new(node_buffer.page.borrow() as Ref<[_]>);
+        let node = node::Node::new(node_buffer.body());
         match node::Body::new(node.header.node_type, node.body.as_bytes()) {
             node::Body::Leaf(leaf) => {
                 let slot_id = tuple_slot_id(&search_mode, &leaf).unwrap_or_else(identity);
@@ -98,6 +100,8 @@ impl BTree {
                 Ok(Iter {
                     buffer: node_buffer,
                     slot_id,
+                    stop_key: Bound::Unbounded,
+                    done: false,
                 })
             }
             node::Body::Branch(branch) => {
@@ -110,19 +114,26 @@ impl BTree {
         }
     }
 
+    // replaced が Some なら同じキーの値を置き換えて、前の値を入れる (None なら DuplicateKey)
     fn insert_internal(
         &self,
         bufmgr: &mut dyn BufferPoolManager,
         buffer: Rc<Buffer>,
         key: &[u8],
         value: &[u8],
+        replaced: &mut Option<Option<Vec<u8>>>,
     ) -> Result<Option<(Vec<u8>, PageId)>, Error> {
-        let node = node::Node::new(buffer.page.borrow_mut() as RefMut<[_]>);
+        let node = node::Node::new(buffer.body_mut());
         match node::Body::new(node.header.node_type, node.body) {
             node::Body::Leaf(mut leaf) => {
-                let slot_id = match leaf.search_slot_id(key) {
-                    Ok(_) => return Err(Error::DuplicateKey),
-                    Err(slot_id) => slot_id,
+                let slot_id = match (leaf.search_slot_id(key), replaced.as_mut()) {
+                    (Ok(slot_id), Some(replaced)) => {
+                        *replaced = Some(leaf.pair_at(slot_id).value.to_vec());
+                        leaf.remove(slot_id);
+                        slot_id
+                    }
+                    (Ok(_), None) => return Err(Error::DuplicateKey),
+                    (Err(slot_id), _) => slot_id,
                 };
                 if leaf.insert(slot_id, key, value).is_some() {
                     buffer.is_dirty.set(true);
@@ -136,16 +147,14 @@ impl BTree {
                     let new_leaf_buffer = bufmgr.create_page()?;
 
                     if let Some(prev_leaf_buffer) = prev_leaf_buffer {
-                        let node =
-                            node::Node::new(prev_leaf_buffer.page.borrow_mut() as RefMut<[_]>);
+                        let node = node::Node::new(prev_leaf_buffer.body_mut());
                         let mut prev_leaf = leaf::Leaf::new(node.body);
                         prev_leaf.set_next_page_id(Some(new_leaf_buffer.page_id));
                         prev_leaf_buffer.is_dirty.set(true);
                     }
                     leaf.set_prev_page_id(Some(new_leaf_buffer.page_id));
 
-                    let mut new_leaf_node =
-                        node::Node::new(new_leaf_buffer.page.borrow_mut() as RefMut<[_]>);
+                    let mut new_leaf_node = node::Node::new(new_leaf_buffer.body_mut());
                     new_leaf_node.initialize_as_leaf();
                     let mut new_leaf = leaf::Leaf::new(new_leaf_node.body);
                     new_leaf.initialize();
@@ -161,7 +170,7 @@ impl BTree {
                 let child_page_id = branch.child_at(child_idx);
                 let child_node_buffer = bufmgr.fetch_page(child_page_id)?;
                 if let Some((overflow_key_from_child, overflow_child_page_id)) =
-                    self.insert_internal(bufmgr, child_node_buffer, key, value)?
+                    self.insert_internal(bufmgr, child_node_buffer, key, value, replaced)?
                 {
                     if branch
                         .insert(child_idx, &overflow_key_from_child, overflow_child_page_id)
@@ -171,8 +180,7 @@ impl BTree {
                         Ok(None)
                     } else {
                         let new_branch_buffer = bufmgr.create_page()?;
-                        let mut new_branch_node =
-                            node::Node::new(new_branch_buffer.page.borrow_mut() as RefMut<[_]>);
+                        let mut new_branch_node = node::Node::new(new_branch_buffer.body_mut());
                         new_branch_node.initialize_as_branch();
                         let mut new_branch = branch::Branch::new(new_branch_node.body);
                         let overflow_key = branch.split_insert(
@@ -192,22 +200,208 @@ impl BTree {
     }
 }
 
-impl<T: BufferPoolManager> AccessMethod<T> for BTree {
-    type Iterable = Iter;
+impl BTree {
+    // 葉からキーを消すだけで、空になった葉の併合はしない
+    fn delete_internal(
+        &self,
+        bufmgr: &mut dyn BufferPoolManager,
+        buffer: Rc<Buffer>,
+        key: &[u8],
+    ) -> Result<(), Error> {
+        let node = node::Node::new(buffer.body_mut());
+        match node::Body::new(node.header.node_type, node.body) {
+            node::Body::Leaf(mut leaf) => {
+                let slot_id = leaf.search_slot_id(key).map_err(|_| Error::KeyNotFound)?;
+                leaf.remove(slot_id);
+                buffer.is_dirty.set(true);
+                Ok(())
+            }
+            node::Body::Branch(branch) => {
+                let child_page_id = branch.search_child(key);
+                let child_node_buffer = bufmgr.fetch_page(child_page_id)?;
+                self.delete_internal(bufmgr, child_node_buffer, key)
+            }
+        }
+    }
+}
 
-    fn search(&self, bufmgr: &mut T, search_option: SearchMode) -> Result<Self::Iterable, Error> {
-        let root_page = self.fetch_root_page(bufmgr)?;
-        self.search_internal(bufmgr, root_page, search_option)
+impl BTree {
+    // 枝の下の葉と、その親の組を全て集める (根の葉は含めない)
+    pub fn leaves(
+        &self,
+        bufmgr: &mut dyn BufferPoolManager,
+    ) -> Result<Vec<(PageId, PageId)>, Error> {
+        let root_buffer = self.fetch_root_page(bufmgr)?;
+        let mut leaves = vec![];
+        let mut stack = vec![root_buffer.page_id];
+        drop(root_buffer);
+        while let Some(page_id) = stack.pop() {
+            let buffer = bufmgr.fetch_page(page_id)?;
+            let children: Vec<_> = {
+                let node = node::Node::new(buffer.body());
+                match node::Body::new(node.header.node_type, node.body.as_bytes()) {
+                    node::Body::Leaf(_) => continue,
+                    node::Body::Branch(branch) => (0..=branch.num_pairs())
+                        .map(|child_idx| branch.child_at(child_idx))
+                        .collect(),
+                }
+            };
+            for child_page_id in children {
+                let child_buffer = bufmgr.fetch_page(child_page_id)?;
+                let node = node::Node::new(child_buffer.body());
+                match node::Body::new(node.header.node_type, node.body.as_bytes()) {
+                    node::Body::Leaf(_) => leaves.push((page_id, child_page_id)),
+                    node::Body::Branch(_) => stack.push(child_page_id),
+                }
+            }
+        }
+        Ok(leaves)
     }
 
-    fn insert(&self, bufmgr: &mut T, key: &[u8], value: &[u8]) -> Result<(), Error> {
+    // 葉の組を全て同じ親の隣の葉に移し、空になった葉を親と葉のリストから外す
+    // 外した子のキーが入るようになる隣 (右の子なら左隣、そうでなければ右隣) に移す
+    // 隣に収まらなかったり、親の唯一の子だったりすれば何もせずに false を返す
+    // 外した葉のページは呼び出し側で解放する
+    pub fn merge_leaf(
+        &self,
+        bufmgr: &mut dyn BufferPoolManager,
+        parent_page_id: PageId,
+        leaf_page_id: PageId,
+    ) -> Result<bool, Error> {
+        let sibling_page_id = {
+            let parent_buffer = bufmgr.fetch_page(parent_page_id)?;
+            let parent_node = node::Node::new(parent_buffer.body());
+            let parent = branch::Branch::new(parent_node.body.as_bytes());
+            let num_pairs = parent.num_pairs();
+            match (0..=num_pairs).find(|&child_idx| parent.child_at(child_idx) == leaf_page_id) {
+                Some(child_idx) if num_pairs > 0 && child_idx == num_pairs => {
+                    parent.child_at(child_idx - 1)
+                }
+                Some(child_idx) if num_pairs > 0 => parent.child_at(child_idx + 1),
+                _ => return Ok(false),
+            }
+        };
+        let leaf_buffer = bufmgr.fetch_page(leaf_page_id)?;
+        let sibling_buffer = bufmgr.fetch_page(sibling_page_id)?;
+        {
+            let leaf_node = node::Node::new(leaf_buffer.body_mut());
+            let sibling_node = node::Node::new(sibling_buffer.body_mut());
+            let (mut leaf, mut sibling) = match (
+                node::Body::new(leaf_node.header.node_type, leaf_node.body),
+                node::Body::new(sibling_node.header.node_type, sibling_node.body),
+            ) {
+                (node::Body::Leaf(leaf), node::Body::Leaf(sibling)) => (leaf, sibling),
+                _ => return Ok(false),
+            };
+            if leaf.pairs_size() > sibling.free_space() {
+                return Ok(false);
+            }
+            while leaf.num_pairs() > 0 {
+                let pair = leaf.pair_at(0);
+                let (key, value) = (pair.key.to_vec(), pair.value.to_vec());
+                let slot_id = sibling
+                    .search_slot_id(&key)
+                    .expect_err("key must be unique");
+                sibling
+                    .insert(slot_id, &key, &value)
+                    .expect("sibling must have space");
+                leaf.remove(0);
+            }
+        }
+        leaf_buffer.is_dirty.set(true);
+        sibling_buffer.is_dirty.set(true);
+        self.remove_empty_leaf(bufmgr, parent_page_id, leaf_page_id)
+    }
+
+    // meta ページも含めた木の全てのページ
+    pub fn page_ids(&self, bufmgr: &mut dyn BufferPoolManager) -> Result<Vec<PageId>, Error> {
+        let mut page_ids = vec![self.meta_page_id];
+        let root_buffer = self.fetch_root_page(bufmgr)?;
+        let mut stack = vec![root_buffer.page_id];
+        drop(root_buffer);
+        while let Some(page_id) = stack.pop() {
+            page_ids.push(page_id);
+            let buffer = bufmgr.fetch_page(page_id)?;
+            let node = node::Node::new(buffer.body());
+            if let node::Body::Branch(branch) =
+                node::Body::new(node.header.node_type, node.body.as_bytes())
+            {
+                stack.extend((0..=branch.num_pairs()).map(|child_idx| branch.child_at(child_idx)));
+            }
+        }
+        Ok(page_ids)
+    }
+
+    // 木の全てのページを解放して再利用できるようにする (以後この木は使えない)
+    pub fn destroy(self, bufmgr: &mut dyn BufferPoolManager) -> Result<(), Error> {
+        for page_id in self.page_ids(bufmgr)? {
+            bufmgr.free_page(page_id)?;
+        }
+        Ok(())
+    }
+
+    // 空の葉を親と葉のリストから外す
+    // 葉が空でなくなっていたり、親の最後の子だったりすれば何もせずに false を返す
+    // 外した葉のページは呼び出し側で解放する
+    pub fn remove_empty_leaf(
+        &self,
+        bufmgr: &mut dyn BufferPoolManager,
+        parent_page_id: PageId,
+        leaf_page_id: PageId,
+    ) -> Result<bool, Error> {
+        let parent_buffer = bufmgr.fetch_page(parent_page_id)?;
+        let leaf_buffer = bufmgr.fetch_page(leaf_page_id)?;
+        let mut parent_node = node::Node::new(parent_buffer.body_mut());
+        let mut parent = branch::Branch::new(&mut parent_node.body[..]);
+        let child_idx = match (0..=parent.num_pairs())
+            .find(|&child_idx| parent.child_at(child_idx) == leaf_page_id)
+        {
+            Some(child_idx) if parent.num_pairs() > 0 => child_idx,
+            _ => return Ok(false),
+        };
+        let (prev_page_id, next_page_id) = {
+            let leaf_node = node::Node::new(leaf_buffer.body());
+            let leaf = leaf::Leaf::new(leaf_node.body);
+            if leaf.num_pairs() > 0 {
+                return Ok(false);
+            }
+            (leaf.prev_page_id(), leaf.next_page_id())
+        };
+        if let Some(prev_page_id) = prev_page_id {
+            let prev_buffer = bufmgr.fetch_page(prev_page_id)?;
+            let node = node::Node::new(prev_buffer.body_mut());
+            leaf::Leaf::new(node.body).set_next_page_id(next_page_id);
+            prev_buffer.is_dirty.set(true);
+        }
+        if let Some(next_page_id) = next_page_id {
+            let next_buffer = bufmgr.fetch_page(next_page_id)?;
+            let node = node::Node::new(next_buffer.body_mut());
+            leaf::Leaf::new(node.body).set_prev_page_id(prev_page_id);
+            next_buffer.is_dirty.set(true);
+        }
+        parent.remove_child(child_idx);
+        parent_buffer.is_dirty.set(true);
+        Ok(true)
+    }
+}
+
+impl BTree {
+    fn insert_root(
+        &self,
+        bufmgr: &mut dyn BufferPoolManager,
+        key: &[u8],
+        value: &[u8],
+        replaced: &mut Option<Option<Vec<u8>>>,
+    ) -> Result<(), Error> {
         let meta_buffer = bufmgr.fetch_page(self.meta_page_id)?;
-        let mut meta = meta::Meta::new(meta_buffer.page.borrow_mut() as RefMut<[_]>);
+        let mut meta = meta::Meta::new(meta_buffer.body_mut());
         let root_page_id = meta.header.root_page_id;
         let root_buffer = bufmgr.fetch_page(root_page_id)?;
-        if let Some((key, child_page_id)) = self.insert_internal(bufmgr, root_buffer, key, value)? {
+        if let Some((key, child_page_id)) =
+            self.insert_internal(bufmgr, root_buffer, key, value, replaced)?
+        {
             let new_root_buffer = bufmgr.create_page()?;
-            let mut node = node::Node::new(new_root_buffer.page.borrow_mut() as RefMut<[_]>);
+            let mut node = node::Node::new(new_root_buffer.body_mut());
             node.initialize_as_branch();
             let mut branch = branch::Branch::new(node.body);
             branch.initialize(&key, child_page_id, root_page_id);
@@ -216,16 +410,176 @@ impl<T: BufferPoolManager> AccessMethod<T> for BTree {
         }
         Ok(())
     }
+
+    // key がなければ挿入し、あれば値を置き換えて前の値を返す
+    // 置き換えた値が葉に入らなければ、挿入と同じように葉を分割する
+    pub fn upsert(
+        &self,
+        bufmgr: &mut dyn BufferPoolManager,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let mut replaced = Some(None);
+        self.insert_root(bufmgr, key, value, &mut replaced)?;
+        Ok(replaced.flatten())
+    }
+
+    // key の値 (なければ None)
+    pub fn get(
+        &self,
+        bufmgr: &mut dyn BufferPoolManager,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let root_buffer = self.fetch_root_page(bufmgr)?;
+        let iter = self.search_internal(bufmgr, root_buffer, SearchMode::Key(key.to_vec()))?;
+        Ok(match iter.get() {
+            Some((found, value)) if found == key => Some(value),
+            _ => None,
+        })
+    }
+
+    // 根の葉に何もないか
+    pub fn is_empty(&self, bufmgr: &mut dyn BufferPoolManager) -> Result<bool, Error> {
+        let root_buffer = self.fetch_root_page(bufmgr)?;
+        let node = node::Node::new(root_buffer.body());
+        Ok(
+            match node::Body::new(node.header.node_type, node.body.as_bytes()) {
+                node::Body::Leaf(leaf) => leaf.num_pairs() == 0,
+                node::Body::Branch(_) => false,
+            },
+        )
+    }
+
+    // 空の木に、キーの昇順に並べた pairs を左の葉から詰めて入れ、下の段から枝を組み立てる
+    // 一つずつ挿入するよりページを読む回数が少なく、葉も分割せずに詰まる
+    // 木が空でなければ NotEmpty、同じキーが続けば DuplicateKey、昇順でなければ Unsorted を返す
+    // どれもページを書く前に確かめるので、失敗しても木は空のまま残る
+    pub fn bulk_load(
+        &self,
+        bufmgr: &mut dyn BufferPoolManager,
+        pairs: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+    ) -> Result<(), Error> {
+        if !self.is_empty(bufmgr)? {
+            return Err(Error::NotEmpty);
+        }
+        let pairs: Vec<_> = pairs.into_iter().collect();
+        for window in pairs.windows(2) {
+            match window[0].0.cmp(&window[1].0) {
+                Ordering::Less => {}
+                Ordering::Equal => return Err(Error::DuplicateKey),
+                Ordering::Greater => return Err(Error::Unsorted),
+            }
+        }
+        // 葉の段のページと、その最初のキー
+        let mut children: Vec<(Vec<u8>, PageId)> = vec![];
+        let mut leaf_buffer = self.fetch_root_page(bufmgr)?;
+        let mut first_key: Option<Vec<u8>> = None;
+        for (key, value) in pairs {
+            let inserted = {
+                let node = node::Node::new(leaf_buffer.body_mut());
+                let mut leaf = leaf::Leaf::new(node.body);
+                leaf.insert(leaf.num_pairs(), &key, &value).is_some()
+            };
+            if !inserted {
+                let new_leaf_buffer = bufmgr.create_page()?;
+                {
+                    let node = node::Node::new(leaf_buffer.body_mut());
+                    leaf::Leaf::new(node.body).set_next_page_id(Some(new_leaf_buffer.page_id));
+                    let mut node = node::Node::new(new_leaf_buffer.body_mut());
+                    node.initialize_as_leaf();
+                    let mut leaf = leaf::Leaf::new(node.body);
+                    leaf.initialize();
+                    leaf.set_prev_page_id(Some(leaf_buffer.page_id));
+                    leaf.insert(0, &key, &value)
+                        .expect("new leaf must have space");
+                }
+                leaf_buffer.is_dirty.set(true);
+                children.push((first_key.take().unwrap(), leaf_buffer.page_id));
+                leaf_buffer = new_leaf_buffer;
+            }
+            leaf_buffer.is_dirty.set(true);
+            if first_key.is_none() {
+                first_key = Some(key);
+            }
+        }
+        children.push((first_key.unwrap_or_default(), leaf_buffer.page_id));
+        drop(leaf_buffer);
+
+        // 一つの根になるまで、子を左から詰めた枝の段を重ねる
+        while children.len() > 1 {
+            let mut parents: Vec<(Vec<u8>, PageId)> = vec![];
+            let mut branch_buffer: Option<Rc<Buffer>> = None;
+            for (key, child) in children {
+                if let Some(buffer) = &branch_buffer {
+                    let node = node::Node::new(buffer.body_mut());
+                    if branch::Branch::new(node.body)
+                        .push_child(&key, child)
+                        .is_some()
+                    {
+                        continue;
+                    }
+                }
+                let buffer = bufmgr.create_page()?;
+                {
+                    let mut node = node::Node::new(buffer.body_mut());
+                    node.initialize_as_branch();
+                    branch::Branch::new(node.body).initialize_with_child(child);
+                }
+                buffer.is_dirty.set(true);
+                parents.push((key, buffer.page_id));
+                branch_buffer = Some(buffer);
+            }
+            children = parents;
+        }
+        let meta_buffer = bufmgr.fetch_page(self.meta_page_id)?;
+        let mut meta = meta::Meta::new(meta_buffer.body_mut());
+        meta.header.root_page_id = children[0].1;
+        meta_buffer.is_dirty.set(true);
+        Ok(())
+    }
+}
+
+impl<T: BufferPoolManager> AccessMethod<T> for BTree {
+    type Iterable = Iter;
+
+    fn search(&self, bufmgr: &mut T, search_option: SearchMode) -> Result<Self::Iterable, Error> {
+        let root_page = self.fetch_root_page(bufmgr)?;
+        self.search_internal(bufmgr, root_page, search_option)
+    }
+
+    fn search_range(
+        &self,
+        bufmgr: &mut T,
+        search_option: SearchMode,
+        stop_key: Bound<Vec<u8>>,
+    ) -> Result<Self::Iterable, Error> {
+        let root_page = self.fetch_root_page(bufmgr)?;
+        let mut iter = self.search_internal(bufmgr, root_page, search_option)?;
+        iter.stop_key = stop_key;
+        Ok(iter)
+    }
+
+    fn insert(&self, bufmgr: &mut T, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.insert_root(bufmgr, key, value, &mut None)
+    }
+
+    fn delete(&self, bufmgr: &mut T, key: &[u8]) -> Result<(), Error> {
+        let root_page = self.fetch_root_page(bufmgr)?;
+        self.delete_internal(bufmgr, root_page, key)
+    }
 }
 
 pub struct Iter {
     buffer: Rc<Buffer>,
     slot_id: usize,
+    // キーがこれを超えたら、次の葉を読まずに終わる
+    stop_key: Bound<Vec<u8>>,
+    done: bool,
 }
 
 impl Iter {
     fn get(&self) -> Option<(Vec<u8>, Vec<u8>)> {
-        let leaf_node = node::Node::new(self.buffer.page.borrow() as Ref<[_]>);
+        let leaf_node = node::Node::new(self.buffer.body());
         let leaf = leaf::Leaf::new(leaf_node.body);
         if self.slot_id < leaf.num_pairs() {
             let pair = leaf.pair_at(self.slot_id);
@@ -234,36 +588,71 @@ impl Iter {
             None
         }
     }
+
+    // key が stop_key より前か、stop_key そのものか、超えているか
+    fn compare_stop(&self, key: &[u8]) -> Ordering {
+        match &self.stop_key {
+            Bound::Included(stop_key) => {
+                let prefix = &key[..key.len().min(stop_key.len())];
+                match prefix.cmp(stop_key) {
+                    Ordering::Equal if key.len() > stop_key.len() => Ordering::Less,
+                    ord => ord,
+                }
+            }
+            Bound::Excluded(stop_key) => match key.cmp(stop_key) {
+                Ordering::Less => Ordering::Less,
+                _ => Ordering::Greater,
+            },
+            Bound::Unbounded => Ordering::Less,
+        }
+    }
 }
 
 impl<T: BufferPoolManager> Iterable<T> for Iter {
+    // 葉の終わりまで来たら次の葉へ進む (削除で空になった葉は飛ばす)
+    // stop_key を超えるか、stop_key そのもの (その後ろは全て超える) を返したら、それ以上の葉を読まない
     #[allow(clippy::type_complexity)]
     fn next(&mut self, bufmgr: &mut T) -> Result<Option<(Vec<u8>, Vec<u8>)>, Error> {
-        let value = self.get();
-        self.slot_id += 1;
-        let next_page_id = {
-            let leaf_node = node::Node::new(self.buffer.page.borrow() as Ref<[_]>);
-            let leaf = leaf::Leaf::new(leaf_node.body);
-            if self.slot_id < leaf.num_pairs() {
-                return Ok(value);
+        loop {
+            if self.done {
+                return Ok(None);
+            }
+            if let Some((key, value)) = self.get() {
+                self.slot_id += 1;
+                match self.compare_stop(&key) {
+                    Ordering::Less => {}
+                    Ordering::Equal => self.done = true,
+                    Ordering::Greater => {
+                        self.done = true;
+                        return Ok(None);
+                    }
+                }
+                return Ok(Some((key, value)));
+            }
+            let next_page_id = {
+                let leaf_node = node::Node::new(self.buffer.body());
+                let leaf = leaf::Leaf::new(leaf_node.body);
+                leaf.next_page_id()
+            };
+            match next_page_id {
+                Some(next_page_id) => {
+                    self.buffer = bufmgr.fetch_page(next_page_id)?;
+                    self.slot_id = 0;
+                }
+                None => return Ok(None),
             }
-            leaf.next_page_id()
-        };
-        if let Some(next_page_id) = next_page_id {
-            self.buffer = bufmgr.fetch_page(next_page_id)?;
-            self.slot_id = 0;
         }
-        Ok(value)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::convert::TryInto;
     use std::rc::Rc;
 
     use super::*;
     use crate::buffer::{
-        entity::Buffer,
+        entity::{Buffer, Checkpoint},
         manager::{self, BufferPoolManager},
     };
     use crate::storage::entity::PageId;
@@ -272,6 +661,7 @@ mod tests {
     struct InfinityBuffer {
         next_page_id: u64,
         data: Vec<Rc<Buffer>>,
+        fetches: u64,
     }
 
     impl InfinityBuffer {
@@ -279,6 +669,7 @@ mod tests {
             Self {
                 next_page_id: 0,
                 data: vec![],
+                fetches: 0,
             }
         }
     }
@@ -288,8 +679,10 @@ mod tests {
             let page_id = self.next_page_id;
             self.next_page_id += 1;
 
-            let mut buffer = Buffer::default();
-            buffer.page_id = PageId(page_id);
+            let buffer = Buffer {
+                page_id: PageId(page_id),
+                ..Default::default()
+            };
             buffer.is_dirty.set(true);
             let rc = Rc::new(buffer);
 
@@ -299,11 +692,21 @@ mod tests {
 
         fn fetch_page(&mut self, page_id: PageId) -> Result<Rc<Buffer>, manager::Error> {
             let rc = &self.data[page_id.0 as usize];
+            self.fetches += 1;
             Ok(Rc::clone(rc))
         }
+        fn fetch_count(&self) -> u64 {
+            self.fetches
+        }
         fn flush(&mut self) -> Result<(), manager::Error> {
             Ok(())
         }
+        fn checkpoint(&mut self) -> Result<Checkpoint, manager::Error> {
+            Ok(Checkpoint::default())
+        }
+        fn discard_page(&mut self, _: PageId) -> Result<(), manager::Error> {
+            Ok(())
+        }
     }
 
     #[test]
@@ -345,6 +748,50 @@ mod tests {
             let res = iter.next(&mut bufmgr).unwrap();
             assert!(res.is_none());
         }
+        {
+            // delete
+            let res1 = btree.delete(&mut bufmgr, &4u64.to_be_bytes());
+            assert!(res1.is_ok());
+            let res2 = btree.delete(&mut bufmgr, &4u64.to_be_bytes());
+            assert!(matches!(res2, Err(Error::KeyNotFound)));
+            let mut iter = btree.search(&mut bufmgr, SearchMode::Start).unwrap();
+            let (_, value1) = iter.next(&mut bufmgr).unwrap().unwrap();
+            assert_eq!(b"hello", &value1[..]);
+            let (_, value2) = iter.next(&mut bufmgr).unwrap().unwrap();
+            assert_eq!(b"world", &value2[..]);
+        }
+    }
+
+    #[test]
+    fn test_upsert() {
+        let mut bufmgr = InfinityBuffer::new();
+        let btree = BTree::create(&mut bufmgr).unwrap();
+        let long_padding = vec![0xDEu8; 1500];
+        for key in [3u64, 6, 8] {
+            btree.insert(&mut bufmgr, &key.to_be_bytes(), b"x").unwrap();
+        }
+        assert_eq!(
+            None,
+            btree
+                .upsert(&mut bufmgr, &4u64.to_be_bytes(), &long_padding)
+                .unwrap()
+        );
+        // 置き換えた値が葉に入らなければ葉を分割する
+        for key in [3u64, 6, 8] {
+            assert_eq!(
+                Some(b"x".to_vec()),
+                btree
+                    .upsert(&mut bufmgr, &key.to_be_bytes(), &long_padding)
+                    .unwrap()
+            );
+        }
+        for key in [3u64, 4, 6, 8] {
+            assert_eq!(
+                Some(long_padding.clone()),
+                btree.get(&mut bufmgr, &key.to_be_bytes()).unwrap()
+            );
+        }
+        assert_eq!(None, btree.get(&mut bufmgr, &5u64.to_be_bytes()).unwrap());
     }
 
     #[test]
@@ -376,4 +823,181 @@ mod tests {
             assert_eq!(b"hello", &value[..]);
         }
     }
+
+    #[test]
+    fn test_range() {
+        let mut bufmgr = InfinityBuffer::new();
+        let btree = BTree::create(&mut bufmgr).unwrap();
+        // 一つの葉に数組しか入らないようにする
+        let padding = vec![0u8; 1000];
+        for i in 0..20u64 {
+            btree
+                .insert(&mut bufmgr, &i.to_be_bytes(), &padding)
+                .unwrap();
+        }
+        let collect = |bufmgr: &mut InfinityBuffer, stop_key| {
+            let fetches = bufmgr.fetch_count();
+            let mut iter = btree
+                .search_range(bufmgr, SearchMode::Start, stop_key)
+                .unwrap();
+            let mut keys = vec![];
+            while let Some((key, _)) = iter.next(bufmgr).unwrap() {
+                keys.push(u64::from_be_bytes(key.try_into().unwrap()));
+            }
+            (keys, bufmgr.fetch_count() - fetches)
+        };
+
+        // 先頭から各キーを読むまでに取得したページの数
+        let fetches = bufmgr.fetch_count();
+        let mut iter = btree.search(&mut bufmgr, SearchMode::Start).unwrap();
+        let mut reach = vec![];
+        while iter.next(&mut bufmgr).unwrap().is_some() {
+            reach.push(bufmgr.fetch_count() - fetches);
+        }
+        // 葉が分かれていること
+        assert!(reach[19] > reach[0]);
+
+        for i in 0..20u64 {
+            // そのキーを返したら次の葉を読まない
+            let (keys, fetches) = collect(&mut bufmgr, Bound::Included(i.to_be_bytes().to_vec()));
+            assert_eq!((0..=i).collect::<Vec<_>>(), keys);
+            assert_eq!(reach[i as usize], fetches);
+            // 超えたキーを見たところで終わる
+            let (keys, fetches) = collect(&mut bufmgr, Bound::Excluded(i.to_be_bytes().to_vec()));
+            assert_eq!((0..i).collect::<Vec<_>>(), keys);
+            assert_eq!(reach[i as usize], fetches);
+        }
+
+        // 先頭のバイト列が同じ間は続ける
+        let (keys, _) = collect(&mut bufmgr, Bound::Included(vec![0; 7]));
+        assert_eq!((0..20).collect::<Vec<_>>(), keys);
+        let (keys, _) = collect(&mut bufmgr, Bound::Excluded(vec![0; 7]));
+        assert!(keys.is_empty());
+    }
+
+    #[test]
+    fn test_merge_leaf() {
+        let mut bufmgr = InfinityBuffer::new();
+        let btree = BTree::create(&mut bufmgr).unwrap();
+        let long_padding = vec![0xDEu8; 1000];
+        for key in 0u64..16 {
+            btree
+                .insert(&mut bufmgr, &key.to_be_bytes(), &long_padding)
+                .unwrap();
+        }
+        // 四つに一つだけ残す
+        for key in (0u64..16).filter(|key| key % 4 != 0) {
+            btree.delete(&mut bufmgr, &key.to_be_bytes()).unwrap();
+        }
+        let leaves = btree.leaves(&mut bufmgr).unwrap();
+        assert!(leaves.len() > 2);
+        let mut num_merged = 0;
+        for (parent_page_id, leaf_page_id) in leaves.clone() {
+            if btree
+                .merge_leaf(&mut bufmgr, parent_page_id, leaf_page_id)
+                .unwrap()
+            {
+                num_merged += 1;
+            }
+        }
+        assert!(num_merged > 0);
+        assert_eq!(
+            leaves.len() - num_merged,
+            btree.leaves(&mut bufmgr).unwrap().len()
+        );
+        // 寄せた先の葉からも、端から順にも読める
+        for key in (0u64..16).step_by(4) {
+            let value = btree.get(&mut bufmgr, &key.to_be_bytes()).unwrap();
+            assert_eq!(Some(long_padding.clone()), value);
+        }
+        let mut iter = btree.search(&mut bufmgr, SearchMode::Start).unwrap();
+        let mut keys = vec![];
+        while let Some((key, _)) = iter.next(&mut bufmgr).unwrap() {
+            keys.push(key);
+        }
+        let expected: Vec<_> = (0u64..16)
+            .step_by(4)
+            .map(|key| key.to_be_bytes().to_vec())
+            .collect();
+        assert_eq!(expected, keys);
+    }
+
+    #[test]
+    fn test_destroy() {
+        use crate::rdbms::{clocksweep::ClockSweepManager, memory::MemoryManager};
+
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let btree = BTree::create(&mut bufmgr).unwrap();
+        let long_padding = vec![0xDEu8; 1500];
+        for key in 0u64..8 {
+            btree
+                .insert(&mut bufmgr, &key.to_be_bytes(), &long_padding)
+                .unwrap();
+        }
+        let page_ids = btree.page_ids(&mut bufmgr).unwrap();
+        // meta と根の枝と、分かれた葉
+        assert!(page_ids.len() > 4);
+        btree.destroy(&mut bufmgr).unwrap();
+
+        // 解放したページを使い回す
+        let other = BTree::create(&mut bufmgr).unwrap();
+        let mut reused = other.page_ids(&mut bufmgr).unwrap();
+        reused.retain(|page_id| page_ids.contains(page_id));
+        assert_eq!(2, reused.len());
+    }
+
+    #[test]
+    fn test_bulk_load() {
+        use crate::rdbms::{clocksweep::ClockSweepManager, memory::MemoryManager};
+
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let btree = BTree::create(&mut bufmgr).unwrap();
+        let value = vec![0xDEu8; 100];
+        let pairs = (0u64..2000).map(|key| (key.to_be_bytes().to_vec(), value.clone()));
+        btree.bulk_load(&mut bufmgr, pairs).unwrap();
+        assert!(!btree.is_empty(&mut bufmgr).unwrap());
+        // 葉が何段かの枝の下に並ぶ
+        assert!(btree.page_ids(&mut bufmgr).unwrap().len() > 50);
+
+        let mut iter = btree.search(&mut bufmgr, SearchMode::Start).unwrap();
+        for key in 0u64..2000 {
+            let (found, _) = iter.next(&mut bufmgr).unwrap().unwrap();
+            assert_eq!(key.to_be_bytes().to_vec(), found);
+        }
+        assert!(iter.next(&mut bufmgr).unwrap().is_none());
+        for key in [0u64, 777, 1999] {
+            let (found, _) = btree
+                .search(&mut bufmgr, SearchMode::Key(key.to_be_bytes().to_vec()))
+                .unwrap()
+                .get()
+                .unwrap();
+            assert_eq!(key.to_be_bytes().to_vec(), found);
+        }
+        // 詰めた葉にもふつうに挿入できる
+        btree
+            .insert(&mut bufmgr, &1000u64.to_be_bytes(), b"x")
+            .unwrap_err();
+        btree
+            .insert(&mut bufmgr, &5000u64.to_be_bytes(), b"x")
+            .unwrap();
+
+        let other = BTree::create(&mut bufmgr).unwrap();
+        let pairs = [1u64, 2, 2].map(|key| (key.to_be_bytes().to_vec(), vec![]));
+        assert!(matches!(
+            other.bulk_load(&mut bufmgr, pairs),
+            Err(Error::DuplicateKey)
+        ));
+        let pairs = [2u64, 1].map(|key| (key.to_be_bytes().to_vec(), vec![]));
+        assert!(matches!(
+            other.bulk_load(&mut bufmgr, pairs),
+            Err(Error::Unsorted)
+        ));
+        // 失敗しても何も書かない
+        assert!(other.is_empty(&mut bufmgr).unwrap());
+        let pairs = [(b"x".to_vec(), vec![])];
+        assert!(matches!(
+            btree.bulk_load(&mut bufmgr, pairs),
+            Err(Error::NotEmpty)
+        ));
+    }
 }
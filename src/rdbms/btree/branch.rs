@@ -33,7 +33,7 @@ impl<B: ByteSlice> Branch<B> {
 
     pub fn search_slot_id(&self, key: &[u8]) -> Result<usize, usize> {
         binary_search_by(self.num_pairs(), |slot_id| {
-            self.pair_at(slot_id).key.cmp(&key)
+            self.pair_at(slot_id).key.cmp(key)
         })
     }
 
@@ -57,7 +57,7 @@ impl<B: ByteSlice> Branch<B> {
         }
     }
 
-    pub fn pair_at(&self, slot_id: usize) -> Pair {
+    pub fn pair_at(&self, slot_id: usize) -> Pair<'_> {
         Pair::from_bytes(&self.body[slot_id])
     }
 
@@ -74,6 +74,21 @@ impl<B: ByteSliceMut> Branch<B> {
         self.header.right_child = right_child;
     }
 
+    // 子を一つだけ持つ枝にする (bulk load で右へ子を足していく)
+    pub fn initialize_with_child(&mut self, child: PageId) {
+        self.body.initialize();
+        self.header.right_child = child;
+    }
+
+    // key 以上のキーを持つ child を一番右の子として足す
+    #[must_use = "insertion may fail"]
+    pub fn push_child(&mut self, key: &[u8], child: PageId) -> Option<()> {
+        let right_child = self.header.right_child;
+        self.insert(self.num_pairs(), key, right_child)?;
+        self.header.right_child = child;
+        Some(())
+    }
+
     pub fn fill_right_child(&mut self) {
         let last_id = self.num_pairs() - 1;
         let right_child: PageId = self.pair_at(last_id).value.into();
@@ -81,6 +96,16 @@ impl<B: ByteSliceMut> Branch<B> {
         self.header.right_child = right_child;
     }
 
+    // 子を外す (その子に入るはずのキーは隣の子に入るようになる)
+    pub fn remove_child(&mut self, child_idx: usize) {
+        assert!(self.num_pairs() > 0, "branch must keep at least one child");
+        if child_idx == self.num_pairs() {
+            self.fill_right_child();
+        } else {
+            self.body.remove(child_idx);
+        }
+    }
+
     #[must_use = "insertion may fail"]
     pub fn insert(&mut self, slot_id: usize, key: &[u8], page_id: PageId) -> Option<()> {
         let pair = Pair {
@@ -42,23 +42,34 @@ impl<B: ByteSlice> Leaf<B> {
 
     pub fn search_slot_id(&self, key: &[u8]) -> Result<usize, usize> {
         binary_search_by(self.num_pairs(), |slot_id| {
-            self.pair_at(slot_id).key.cmp(&key)
+            self.pair_at(slot_id).key.cmp(key)
         })
     }
 
     #[allow(dead_code)]
-    pub fn search_pair(&self, key: &[u8]) -> Option<Pair> {
+    pub fn search_pair(&self, key: &[u8]) -> Option<Pair<'_>> {
         let slot_id = self.search_slot_id(key).ok()?;
         Some(self.pair_at(slot_id))
     }
 
-    pub fn pair_at(&self, slot_id: usize) -> Pair {
+    pub fn pair_at(&self, slot_id: usize) -> Pair<'_> {
         Pair::from_bytes(&self.body[slot_id])
     }
 
     pub fn max_pair_size(&self) -> usize {
         self.body.capacity() / 2 - size_of::<slotted::Pointer>()
     }
+
+    pub fn free_space(&self) -> usize {
+        self.body.free_space()
+    }
+
+    // 全ての組を他の葉に移すのに要る空き
+    pub fn pairs_size(&self) -> usize {
+        (0..self.num_pairs())
+            .map(|slot_id| self.body[slot_id].len() + size_of::<slotted::Pointer>())
+            .sum()
+    }
 }
 
 impl<B: ByteSliceMut> Leaf<B> {
@@ -121,6 +132,10 @@ impl<B: ByteSliceMut> Leaf<B> {
         self.pair_at(0).key.to_vec()
     }
 
+    pub fn remove(&mut self, slot_id: usize) {
+        self.body.remove(slot_id);
+    }
+
     pub fn transfer(&mut self, dest: &mut Leaf<impl ByteSliceMut>) {
         let next_index = dest.num_pairs();
         assert!(dest.body.insert(next_index, self.body[0].len()).is_some());
@@ -180,7 +180,7 @@ mod tests {
             slotted[index].copy_from_slice(buf);
         };
         let push = |slotted: &mut Slotted<&mut [u8]>, buf: &[u8]| {
-            let index = slotted.num_slots() as usize;
+            let index = slotted.num_slots();
             insert(slotted, index, buf);
         };
         slotted.initialize();
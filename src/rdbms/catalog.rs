@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::sync::atomic::{self, AtomicU64};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    introspect::virtual_tables,
+    table::{NonUniqueIndex, Table, UniqueIndex},
+};
+use crate::sql::ddl::entity::{Column, Schema};
+use crate::sql::dml::entity::{Type, Value};
+use crate::storage::entity::PageId;
+
+// テーブルを見分ける番号 (名前を変えても変わらない)
+pub type TableId = u32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub column_type: Type,
+    pub nullable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexSchema {
+    pub name: String,
+    // 副キーにするテーブルの列
+    pub columns: Vec<usize>,
+    pub unique: bool,
+    pub meta_page_id: PageId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableSchema {
+    pub id: TableId,
+    pub name: String,
+    pub columns: Vec<ColumnSchema>,
+    // 主キーは先頭の num_key_elems 列
+    pub num_key_elems: usize,
+    pub meta_page_id: PageId,
+    pub indexes: Vec<IndexSchema>,
+    // 主キーを自動で振るなら、その値を振るシーケンスのページ
+    pub sequence_page_id: Option<PageId>,
+}
+
+impl TableSchema {
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column.name == name)
+    }
+
+    pub fn types(&self) -> Vec<Type> {
+        self.columns
+            .iter()
+            .map(|column| column.column_type)
+            .collect()
+    }
+
+    // Table が INSERT するレコードを確かめるのに使う列の定義
+    pub fn schema(&self) -> Schema {
+        Schema {
+            columns: self
+                .columns
+                .iter()
+                .map(|column| Column {
+                    name: column.name.clone(),
+                    column_type: column.column_type,
+                    nullable: column.nullable,
+                })
+                .collect(),
+        }
+    }
+
+    // 行を INSERT すると主キーとインデックスの全ての木に書き、列の定義で確かめる Table
+    pub fn table(&self) -> Table {
+        Table {
+            meta_page_id: self.meta_page_id,
+            num_key_elems: self.num_key_elems,
+            unique_indices: self
+                .indexes
+                .iter()
+                .filter(|index| index.unique)
+                .map(|index| UniqueIndex {
+                    meta_page_id: index.meta_page_id,
+                    skey: index.columns.clone(),
+                })
+                .collect(),
+            non_unique_indices: self
+                .indexes
+                .iter()
+                .filter(|index| !index.unique)
+                .map(|index| NonUniqueIndex {
+                    meta_page_id: index.meta_page_id,
+                    skey: index.columns.clone(),
+                })
+                .collect(),
+            schema: Some(self.schema()),
+        }
+    }
+}
+
+// オプティマイザが行の数を見積もるのに使う、テーブルの統計
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableStats {
+    pub rows: u64,
+    pub pages: u64,
+    // テーブルの列の順
+    pub columns: Vec<ColumnStats>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnStats {
+    // 値の種類の数 (0 なら分からない)
+    pub distinct: u64,
+    pub nulls: u64,
+    // NULL でない値の最小と最大
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    // 等深ヒストグラムの各バケットの上端 (min から最初の上端までが最初のバケット)
+    // NULL でない値がバケットごとにほぼ同じ数ずつ入る
+    pub histogram: Vec<Value>,
+}
+
+// 定義を書き換えるたびに Catalog::version に振る番号 (どのカタログとも重ならないように全体で一つ)
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+
+// テーブルとインデックスの定義を名前で引けるようにしたもの
+#[derive(Debug, Default)]
+pub struct Catalog {
+    tables: Vec<TableSchema>,
+    next_id: TableId,
+    stats: HashMap<TableId, TableStats>,
+    version: u64,
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 仮想テーブルも引ける
+    pub fn table(&self, name: &str) -> Option<&TableSchema> {
+        self.tables
+            .iter()
+            .chain(virtual_tables())
+            .find(|table| table.name == name)
+    }
+
+    pub fn table_by_id(&self, id: TableId) -> Option<&TableSchema> {
+        self.tables
+            .iter()
+            .chain(virtual_tables())
+            .find(|table| table.id == id)
+    }
+
+    // テーブルやインデックスを加えたり外したりするたびに変わる (統計を変えても変わらない)
+    // 準備したプランがまだこのカタログで使えるかを確かめるのに使う
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn bump_version(&mut self) {
+        self.version = NEXT_VERSION.fetch_add(1, atomic::Ordering::Relaxed);
+    }
+
+    // ユーザのテーブル (仮想テーブルは含まない)
+    pub fn tables(&self) -> impl Iterator<Item = &TableSchema> {
+        self.tables.iter()
+    }
+
+    // 新しい番号を振って加える (同じ名前のテーブルがあれば失敗する)
+    pub fn add_table(
+        &mut self,
+        name: &str,
+        columns: Vec<ColumnSchema>,
+        num_key_elems: usize,
+        meta_page_id: PageId,
+    ) -> Result<TableId> {
+        if self.table(name).is_some() {
+            bail!("table {} already exists", name);
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.bump_version();
+        self.tables.push(TableSchema {
+            id,
+            name: name.to_string(),
+            columns,
+            num_key_elems,
+            meta_page_id,
+            indexes: vec![],
+            sequence_page_id: None,
+        });
+        Ok(id)
+    }
+
+    // 番号の決まったテーブルを加える (システムカタログから読み戻すときに使う)
+    pub fn restore_table(&mut self, table: TableSchema) -> Result<()> {
+        if self.table(&table.name).is_some() || self.table_by_id(table.id).is_some() {
+            bail!("table {} already exists", table.name);
+        }
+        self.next_id = self.next_id.max(table.id + 1);
+        self.bump_version();
+        self.tables.push(table);
+        Ok(())
+    }
+
+    pub fn add_index(&mut self, table: TableId, index: IndexSchema) -> Result<()> {
+        let table = match self.tables.iter_mut().find(|schema| schema.id == table) {
+            Some(table) => table,
+            None => bail!("table {} does not exist", table),
+        };
+        if table.indexes.iter().any(|other| other.name == index.name) {
+            bail!("index {} already exists on {}", index.name, table.name);
+        }
+        table.indexes.push(index);
+        self.bump_version();
+        Ok(())
+    }
+
+    // テーブルをその統計とともに外し、外した定義を返す
+    pub fn drop_table(&mut self, table: TableId) -> Result<TableSchema> {
+        let pos = match self.tables.iter().position(|schema| schema.id == table) {
+            Some(pos) => pos,
+            None => bail!("table {} does not exist", table),
+        };
+        self.stats.remove(&table);
+        self.bump_version();
+        Ok(self.tables.remove(pos))
+    }
+
+    pub fn drop_index(&mut self, table: TableId, name: &str) -> Result<IndexSchema> {
+        let table = match self.tables.iter_mut().find(|schema| schema.id == table) {
+            Some(table) => table,
+            None => bail!("table {} does not exist", table),
+        };
+        let index = match table.indexes.iter().position(|index| index.name == name) {
+            Some(pos) => table.indexes.remove(pos),
+            None => bail!("index {} does not exist on {}", name, table.name),
+        };
+        self.bump_version();
+        Ok(index)
+    }
+
+    // 一列の整数の主キーをシーケンスで振るようにする
+    pub fn set_sequence(&mut self, table: TableId, page_id: PageId) -> Result<()> {
+        let table = match self.tables.iter_mut().find(|schema| schema.id == table) {
+            Some(table) => table,
+            None => bail!("table {} does not exist", table),
+        };
+        if table.num_key_elems != 1 || table.columns[0].column_type != Type::Int {
+            bail!("table {} has no single integer primary key", table.name);
+        }
+        table.sequence_page_id = Some(page_id);
+        self.bump_version();
+        Ok(())
+    }
+
+    // 統計のないテーブルは決まった大きさとみなして見積もる
+    pub fn stats(&self, table: TableId) -> Option<&TableStats> {
+        self.stats.get(&table)
+    }
+
+    pub fn set_stats(&mut self, table: TableId, stats: TableStats) -> Result<()> {
+        if self.table_by_id(table).is_none() {
+            bail!("table {} does not exist", table);
+        }
+        self.stats.insert(table, stats);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalog_test() {
+        let mut catalog = Catalog::new();
+        let columns = vec![
+            ColumnSchema {
+                name: "id".to_string(),
+                column_type: Type::Int,
+                nullable: false,
+            },
+            ColumnSchema {
+                name: "name".to_string(),
+                column_type: Type::Bytes,
+                nullable: true,
+            },
+        ];
+        let people = catalog
+            .add_table("people", columns.clone(), 1, PageId(1))
+            .unwrap();
+        let dept = catalog.add_table("dept", columns, 1, PageId(3)).unwrap();
+        assert_ne!(people, dept);
+        assert!(catalog.add_table("people", vec![], 1, PageId(5)).is_err());
+
+        let index = IndexSchema {
+            name: "by_name".to_string(),
+            columns: vec![1],
+            unique: true,
+            meta_page_id: PageId(2),
+        };
+        catalog.add_index(people, index.clone()).unwrap();
+        assert!(catalog.add_index(people, index).is_err());
+        let table = catalog.table("people").unwrap();
+        assert_eq!(Some(1), table.column("name"));
+        assert_eq!(vec![Type::Int, Type::Bytes], table.types());
+        assert_eq!(1, table.indexes.len());
+        assert_eq!("dept", catalog.table_by_id(dept).unwrap().name);
+
+        let stats = TableStats {
+            rows: 2,
+            pages: 1,
+            columns: vec![ColumnStats::default(), ColumnStats::default()],
+        };
+        assert_eq!(None, catalog.stats(people));
+        catalog.set_stats(people, stats.clone()).unwrap();
+        assert_eq!(Some(&stats), catalog.stats(people));
+        assert!(catalog.set_stats(99, stats).is_err());
+
+        assert_eq!(
+            PageId(2),
+            catalog.drop_index(people, "by_name").unwrap().meta_page_id
+        );
+        assert!(catalog.drop_index(people, "by_name").is_err());
+        assert_eq!("people", catalog.drop_table(people).unwrap().name);
+        assert_eq!(None, catalog.table("people"));
+        assert_eq!(None, catalog.stats(people));
+        assert!(catalog.drop_table(people).is_err());
+    }
+}
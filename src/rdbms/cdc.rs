@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::storage::entity::PageId;
+use crate::wal::{
+    entity::{LogRecord, Lsn, TxnId},
+    manager::LogManager,
+};
+
+use super::{mvcc::TupleHeader, util::tuple};
+
+// テーブル (B-tree のメタページ) の行の変更
+// key と values は主キーとそれ以外の列
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Insert {
+        table: PageId,
+        key: Vec<Vec<u8>>,
+        values: Vec<Vec<u8>>,
+    },
+    Delete {
+        table: PageId,
+        key: Vec<Vec<u8>>,
+    },
+    // テーブルの行として読めない変更 (TransactionManager::insert で直に書いた B-tree など)
+    // キーと値は書いたままのバイト列
+    RawInsert {
+        tree: PageId,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    RawDelete {
+        tree: PageId,
+        key: Vec<u8>,
+    },
+}
+
+// コミットしたトランザクションの変更
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    pub txn_id: TxnId,
+    // コミットのレコードの LSN (これより前の変更は取り出し済み)
+    pub commit_lsn: Lsn,
+    // コミットした UNIX 時刻 (マイクロ秒)
+    pub commit_time: u64,
+    pub change: Change,
+}
+
+// 書き出したログからコミットしたトランザクションの変更を取り出す
+// 変更はコミットした順に、トランザクションの中では書いた順に返す
+// アボートしたトランザクションの変更は返さない
+// ログが切り詰められる前に poll しないと、読んでいないレコードを失って Error::Truncated になる
+// 取り出せるのは TransactionManager の insert, insert_row, mark_deleted で書いた変更だけで、
+// Txn::bind で包んだ bufmgr に書いた変更 (Table::insert_as やインデックス、SQL の INSERT や upsert、
+// マイグレーションなど) はページの内容しかログに残らないので取り出せない
+pub struct ChangeStream {
+    // 次に読むレコードの LSN
+    next_lsn: Lsn,
+    // これより前のコミットは取り出さない
+    skip_before: Lsn,
+    // コミットを待っている変更
+    in_flight: HashMap<TxnId, Vec<Change>>,
+}
+
+impl ChangeStream {
+    // start_lsn から読み、skip_before より前にコミットしたトランザクションは飛ばす
+    // TransactionManager::subscribe で作る
+    pub fn new(start_lsn: Lsn, skip_before: Lsn) -> Self {
+        Self {
+            next_lsn: start_lsn,
+            skip_before,
+            in_flight: HashMap::new(),
+        }
+    }
+
+    // 次に読むレコードの LSN (再開するときに ChangeStream::new に渡す)
+    pub fn next_lsn(&self) -> Lsn {
+        self.next_lsn
+    }
+
+    // 前回の poll の後に書き出されたログから、コミットしたトランザクションの変更を取り出す
+    pub fn poll(&mut self, log: &mut impl LogManager) -> Result<Vec<ChangeEvent>> {
+        let end_lsn = log.flushed_lsn();
+        if end_lsn <= self.next_lsn {
+            return Ok(vec![]);
+        }
+        let records = log.read_from(self.next_lsn)?;
+        self.next_lsn = end_lsn;
+        let mut events = vec![];
+        for (lsn, record) in records {
+            match record {
+                LogRecord::Insert {
+                    txn_id,
+                    tree,
+                    key,
+                    value,
+                } => {
+                    let values = TupleHeader::decode(&value)
+                        .ok()
+                        .and_then(|(_, body)| tuple::try_decode(body));
+                    let change = match (tuple::try_decode(&key), values) {
+                        (Some(key), Some(values)) => Change::Insert {
+                            table: tree,
+                            key,
+                            values,
+                        },
+                        _ => Change::RawInsert { tree, key, value },
+                    };
+                    self.in_flight.entry(txn_id).or_default().push(change);
+                }
+                LogRecord::Delete { txn_id, tree, key } => {
+                    let change = match tuple::try_decode(&key) {
+                        Some(key) => Change::Delete { table: tree, key },
+                        None => Change::RawDelete { tree, key },
+                    };
+                    self.in_flight.entry(txn_id).or_default().push(change);
+                }
+                LogRecord::Commit {
+                    txn_id,
+                    commit_time,
+                } => {
+                    let changes = self.in_flight.remove(&txn_id).unwrap_or_default();
+                    if lsn < self.skip_before {
+                        continue;
+                    }
+                    events.extend(changes.into_iter().map(|change| ChangeEvent {
+                        txn_id,
+                        commit_lsn: lsn,
+                        commit_time,
+                        change,
+                    }));
+                }
+                LogRecord::Abort { txn_id } => {
+                    self.in_flight.remove(&txn_id);
+                }
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdbms::{
+        btree::BTree, clocksweep::ClockSweepManager, memory::MemoryManager, table::SimpleTable,
+        txn::TransactionManager, wal::FileLogManager,
+    };
+    use crate::sql::ddl::table::Table as ITable;
+    use tempfile::tempdir;
+
+    #[test]
+    fn change_stream_test() {
+        let dir = tempdir().unwrap();
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut table = SimpleTable {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+        };
+        table.create(&mut bufmgr).unwrap();
+        let btree = BTree::new(table.meta_page_id);
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        let insert = |key: &[u8], name: &[u8]| Change::Insert {
+            table: table.meta_page_id,
+            key: vec![key.to_vec()],
+            values: vec![name.to_vec()],
+        };
+
+        // 取り出しを始める前にコミットしたものは返さない
+        let mut txn = txn_mgr.begin().unwrap();
+        txn_mgr
+            .insert_row(&mut txn, &mut bufmgr, &table, &[b"a", b"Alice"])
+            .unwrap();
+        txn_mgr.commit(txn).unwrap();
+        // 始める前から活動中のトランザクションの変更は返す
+        let mut txn = txn_mgr.begin().unwrap();
+        txn_mgr
+            .insert_row(&mut txn, &mut bufmgr, &table, &[b"b", b"Bob"])
+            .unwrap();
+        let mut stream = txn_mgr.subscribe();
+        assert!(stream.poll(txn_mgr.log_mut()).unwrap().is_empty());
+        txn_mgr.commit(txn).unwrap();
+        let events = stream.poll(txn_mgr.log_mut()).unwrap();
+        assert_eq!(1, events.len());
+        assert_eq!(insert(b"b", b"Bob"), events[0].change);
+
+        // アボートしたものは返さない
+        let mut txn = txn_mgr.begin().unwrap();
+        txn_mgr
+            .insert_row(&mut txn, &mut bufmgr, &table, &[b"c", b"Charlie"])
+            .unwrap();
+        txn_mgr.rollback(txn, &mut bufmgr).unwrap();
+        let mut txn = txn_mgr.begin().unwrap();
+        let txn_id = txn.id();
+        let key = encode_key(b"a");
+        txn_mgr
+            .mark_deleted(&mut txn, &mut bufmgr, &btree, &key)
+            .unwrap();
+        txn_mgr.commit(txn).unwrap();
+        let events = stream.poll(txn_mgr.log_mut()).unwrap();
+        assert_eq!(1, events.len());
+        assert_eq!(txn_id, events[0].txn_id);
+        assert_eq!(
+            Change::Delete {
+                table: table.meta_page_id,
+                key: vec![b"a".to_vec()],
+            },
+            events[0].change
+        );
+        assert!(stream.poll(txn_mgr.log_mut()).unwrap().is_empty());
+
+        // テーブルでない木への挿入は書いたまま返す
+        let raw = BTree::create(&mut bufmgr).unwrap();
+        let mut txn = txn_mgr.begin().unwrap();
+        txn_mgr
+            .insert(&mut txn, &mut bufmgr, &raw, b"a", b"Alice")
+            .unwrap();
+        txn_mgr.commit(txn).unwrap();
+        let events = stream.poll(txn_mgr.log_mut()).unwrap();
+        assert_eq!(1, events.len());
+        assert_eq!(
+            Change::RawInsert {
+                tree: raw.meta_page_id,
+                key: b"a".to_vec(),
+                value: b"Alice".to_vec(),
+            },
+            events[0].change
+        );
+
+        // bind で書いた行はページの内容しかログに残らないので返さない
+        let mut txn = txn_mgr.begin().unwrap();
+        let txn_id = txn.id();
+        table
+            .insert_as(
+                &mut txn.bind(&mut bufmgr).unwrap(),
+                txn_id,
+                &[b"d", b"Dave"],
+            )
+            .unwrap();
+        txn_mgr.commit(txn).unwrap();
+        assert!(stream.poll(txn_mgr.log_mut()).unwrap().is_empty());
+        assert!(btree.get(&mut bufmgr, &encode_key(b"d")).unwrap().is_some());
+    }
+
+    // 一列の主キーを B-tree のキーにする
+    fn encode_key(elem: &[u8]) -> Vec<u8> {
+        let mut key = vec![];
+        tuple::encode([elem].iter(), &mut key);
+        key
+    }
+}
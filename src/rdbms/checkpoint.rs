@@ -0,0 +1,444 @@
+use std::collections::{hash_map::Entry, HashMap};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+use crate::buffer::entity::PAGE_SIZE;
+use crate::storage::{entity::PageId, manager::StorageManager};
+use crate::wal::{
+    entity::{LogRecord, Lsn, TxnId},
+    manager::LogManager,
+};
+
+use super::{clocksweep::ClockSweepManager, wal::ArchivedLog};
+
+// 進行中のチェックポイント
+struct InProgress {
+    // チェックポイントのレコードの LSN
+    lsn: Lsn,
+    // これより前のログは書き出しが終われば要らなくなる
+    redo_lsn: Lsn,
+    // まだ書き出していないダーティページ
+    pending: Vec<PageId>,
+}
+
+// ファジーチェックポイント
+// ダーティページと活動中のトランザクションをログに記録してから、
+// 他の処理の合間に step で少しずつダーティページを書き出す
+// 記録したページを全て書き出したら sync して、要らなくなったログを切り詰める
+pub struct Checkpointer {
+    // 前回のチェックポイントからこれだけログが伸びたらチェックポイントを始める
+    interval: u64,
+    last_lsn: Lsn,
+    in_progress: Option<InProgress>,
+}
+
+impl Checkpointer {
+    pub fn new(interval: u64) -> Self {
+        Self {
+            interval,
+            last_lsn: Lsn(0),
+            in_progress: None,
+        }
+    }
+
+    pub fn is_in_progress(&self) -> bool {
+        self.in_progress.is_some()
+    }
+
+    // 次のチェックポイントを始める頃合いか
+    pub fn is_due(&self, log: &impl LogManager) -> bool {
+        !self.is_in_progress() && log.end_lsn().to_u64() - self.last_lsn.to_u64() >= self.interval
+    }
+
+    // チェックポイントを始めてそのレコードの LSN を返す
+    // active_txns は活動中のトランザクションと、その最初のレコードの LSN
+    // next_txn_id は次に採番するトランザクション ID
+    pub fn begin<T: StorageManager>(
+        &mut self,
+        bufmgr: &ClockSweepManager<T>,
+        log: &mut impl LogManager,
+        active_txns: &[(TxnId, Lsn)],
+        next_txn_id: TxnId,
+    ) -> Result<Lsn> {
+        let dirty_pages = bufmgr.dirty_page_ids();
+        let lsn = log.append(&LogRecord::Checkpoint {
+            dirty_pages: dirty_pages.clone(),
+            active_txns: active_txns.to_vec(),
+            next_txn_id,
+        })?;
+        log.flush()?;
+        let redo_lsn = active_txns
+            .iter()
+            .map(|&(_, first_lsn)| first_lsn)
+            .fold(lsn, std::cmp::min);
+        self.last_lsn = lsn;
+        self.in_progress = Some(InProgress {
+            lsn,
+            redo_lsn,
+            pending: dirty_pages,
+        });
+        Ok(lsn)
+    }
+
+    // 記録したダーティページを最大 max_pages だけ書き出す
+    // 使用中のページは飛ばして次の step で書き直す
+    // チェックポイントが完了したら true を返す
+    pub fn step<T: StorageManager>(
+        &mut self,
+        bufmgr: &mut ClockSweepManager<T>,
+        log: &mut impl LogManager,
+        max_pages: usize,
+    ) -> Result<bool> {
+        let in_progress = match self.in_progress.as_mut() {
+            Some(in_progress) => in_progress,
+            None => return Ok(true),
+        };
+        let mut skipped = vec![];
+        let mut num_flushed = 0;
+        while num_flushed < max_pages {
+            let page_id = match in_progress.pending.pop() {
+                Some(page_id) => page_id,
+                None => break,
+            };
+            if bufmgr.flush_page(page_id)? {
+                num_flushed += 1;
+            } else {
+                skipped.push(page_id);
+            }
+        }
+        in_progress.pending.extend(skipped);
+        if !in_progress.pending.is_empty() {
+            return Ok(false);
+        }
+        bufmgr.storage_mut().sync()?;
+        let redo_lsn = in_progress.redo_lsn;
+        self.in_progress = None;
+        log.truncate_before(redo_lsn)?;
+        Ok(true)
+    }
+
+    // 進行中のチェックポイントのレコードの LSN
+    pub fn in_progress_lsn(&self) -> Option<Lsn> {
+        self.in_progress.as_ref().map(|in_progress| in_progress.lsn)
+    }
+}
+
+// どこまでにコミットしたトランザクションを戻すか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryTarget {
+    // ログの最後まで
+    Latest,
+    // この LSN より前にコミットしたものまで
+    Lsn(Lsn),
+    // この時刻までにコミットしたものまで
+    Time(SystemTime),
+}
+
+impl RecoveryTarget {
+    fn includes(&self, lsn: Lsn, commit_time: u64) -> bool {
+        match *self {
+            RecoveryTarget::Latest => true,
+            RecoveryTarget::Lsn(target) => lsn < target,
+            RecoveryTarget::Time(target) => {
+                let target = target.duration_since(UNIX_EPOCH).unwrap_or_default();
+                commit_time <= target.as_micros() as u64
+            }
+        }
+    }
+}
+
+// コミットしたトランザクションが書き換えたページの内容を順に storage に書き戻す
+// 切り詰められていないログは最後に完了したチェックポイント以降のものなので、先頭から全て適用する
+// コミットしていないトランザクションのページは書き出されていないので、補償レコードまでの取り消しの他は何もしなくてよい
+// (取り消しの続きは TransactionManager::undo_incomplete で行う)
+// 適用したレコードの数を返す
+pub fn recover(storage: &mut impl StorageManager, log: &mut impl LogManager) -> Result<usize> {
+    let start_lsn = log.start_lsn();
+    restore(storage, log, start_lsn, RecoveryTarget::Latest)
+}
+
+// FileLogManager::backup_since で作った増分バックアップを、
+// その前までのバックアップを戻した storage に適用する
+// from には増分バックアップを作るときに渡した LSN を渡す
+// バックアップのディレクトリは読むだけで書き換えない
+pub fn apply_incremental(
+    storage: &mut impl StorageManager,
+    dir: impl AsRef<Path>,
+    from: Lsn,
+    target: RecoveryTarget,
+) -> Result<usize> {
+    let mut log = ArchivedLog::open(dir)?;
+    restore(storage, &mut log, from, target)
+}
+
+// ベースバックアップの storage に from 以降のログを target まで適用する (PITR)
+// from はバックアップを取る前に完了したチェックポイントの redo の開始位置で、
+// log にはアーカイブしたセグメントのディレクトリを ArchivedLog で開いたものを渡せばよい
+// target より後にコミットしたトランザクションの書き換えを含まないよう、バックアップは target より前に取ったものを使う
+pub fn restore(
+    storage: &mut impl StorageManager,
+    log: &mut impl LogManager,
+    from: Lsn,
+    target: RecoveryTarget,
+) -> Result<usize> {
+    let mut records = log.read_from(from)?;
+    // target に含まれない最初のコミットから後は捨てる
+    let cutoff = records
+        .iter()
+        .position(|(lsn, record)| match *record {
+            LogRecord::Commit { commit_time, .. } => !target.includes(*lsn, commit_time),
+            _ => matches!(target, RecoveryTarget::Lsn(target) if *lsn >= target),
+        })
+        .unwrap_or(records.len());
+    records.truncate(cutoff);
+    // トランザクションのページのレコードはこれより前のものを redo する
+    // コミットしたものは全て、していないものは最後の補償レコードまで
+    let mut redo_until: HashMap<TxnId, Lsn> = HashMap::new();
+    for (lsn, record) in &records {
+        if let LogRecord::Commit { txn_id, .. } | LogRecord::Compensation { txn_id, .. } = *record {
+            redo_until.insert(txn_id, *lsn);
+        }
+    }
+    let redone =
+        |txn_id: &TxnId, lsn: &Lsn| redo_until.get(txn_id).is_some_and(|until| lsn < until);
+    // 書き戻すページの内容
+    // 差分は storage から読んだページではなく、ここにある PageImage からの内容に当てる
+    // (in-place の書き込みが途中で切れたページは storage から正しく読めない)
+    let mut pages: HashMap<PageId, Vec<u8>> = HashMap::new();
+    let mut num_records = 0;
+    for (lsn, record) in records {
+        match record {
+            LogRecord::PageImage {
+                txn_id,
+                page_id,
+                image,
+            } if redone(&txn_id, &lsn) => {
+                pages.insert(page_id, image);
+            }
+            LogRecord::PageDelta {
+                txn_id,
+                page_id,
+                offset,
+                data,
+            } if redone(&txn_id, &lsn) => {
+                if let Entry::Vacant(entry) = pages.entry(page_id) {
+                    // 切り詰めたログの PageImage の後の差分で、ページはチェックポイントで書き出し済み
+                    let mut page = vec![0u8; PAGE_SIZE];
+                    storage.read_page_data(page_id, &mut page)?;
+                    entry.insert(page);
+                }
+                let page = pages.get_mut(&page_id).unwrap();
+                let offset = offset as usize;
+                page[offset..offset + data.len()].copy_from_slice(&data);
+            }
+            _ => continue,
+        }
+        num_records += 1;
+    }
+    let mut page_ids: Vec<_> = pages.keys().copied().collect();
+    page_ids.sort();
+    for page_id in page_ids {
+        storage.write_page_data(page_id, &pages[&page_id])?;
+    }
+    storage.sync()?;
+    Ok(num_records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::{
+        entity::{PAGE_BODY_SIZE, PAGE_SIZE},
+        manager::BufferPoolManager,
+    };
+    use crate::rdbms::{
+        memory::MemoryManager,
+        wal::{archive_to, FileLogManager},
+    };
+    use tempfile::tempdir;
+
+    // ページを書き換えてその内容をログに残す
+    fn write_page<T: StorageManager>(
+        bufmgr: &mut ClockSweepManager<T>,
+        log: &mut FileLogManager,
+        page_id: PageId,
+        n: u8,
+    ) {
+        let buffer = bufmgr.fetch_page(page_id).unwrap();
+        buffer.body_mut().fill(n);
+        buffer.is_dirty.set(true);
+        let txn_id = TxnId(n as u64);
+        log.append(&LogRecord::PageImage {
+            txn_id,
+            page_id,
+            image: buffer.page.borrow().to_vec(),
+        })
+        .unwrap();
+        // n 秒にコミットしたことにする
+        log.append(&LogRecord::Commit {
+            txn_id,
+            commit_time: n as u64 * 1_000_000,
+        })
+        .unwrap();
+        log.flush().unwrap();
+    }
+
+    #[test]
+    fn checkpoint_test() {
+        let dir = tempdir().unwrap();
+        let mut log = FileLogManager::with_segment_size(dir.path(), PAGE_SIZE as u64).unwrap();
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 4).unwrap();
+        let page_ids: Vec<_> = (0..3)
+            .map(|_| bufmgr.create_page().unwrap().page_id)
+            .collect();
+        for (n, &page_id) in page_ids.iter().enumerate() {
+            write_page(&mut bufmgr, &mut log, page_id, n as u8 + 1);
+        }
+        let mut checkpointer = Checkpointer::new(2 * PAGE_SIZE as u64);
+        assert!(checkpointer.is_due(&log));
+
+        let first_segment = log.segment_paths()[0].clone();
+        let lsn = checkpointer
+            .begin(&bufmgr, &mut log, &[], TxnId(1))
+            .unwrap();
+        // チェックポイントの途中でも書き換えを続けられる
+        let pinned = bufmgr.fetch_page(page_ids[0]).unwrap();
+        assert!(!checkpointer.step(&mut bufmgr, &mut log, 1).unwrap());
+        assert!(!checkpointer.step(&mut bufmgr, &mut log, 3).unwrap());
+        // 使用中のページは書き出さない
+        assert_eq!(vec![page_ids[0]], bufmgr.dirty_page_ids());
+        assert_eq!(Some(lsn), checkpointer.in_progress_lsn());
+        drop(pinned);
+        assert!(checkpointer.step(&mut bufmgr, &mut log, 3).unwrap());
+        assert!(bufmgr.dirty_page_ids().is_empty());
+        assert!(!checkpointer.is_due(&log));
+        // チェックポイントより前のセグメントは消える
+        assert!(!first_segment.exists());
+        assert!(log.start_lsn() <= lsn);
+
+        // チェックポイントの後の書き換えはログから戻せる
+        write_page(&mut bufmgr, &mut log, page_ids[1], 42);
+        // コミットしていないものは戻さない
+        log.append(&LogRecord::PageImage {
+            txn_id: TxnId(43),
+            page_id: page_ids[2],
+            image: vec![43; PAGE_SIZE],
+        })
+        .unwrap();
+        log.flush().unwrap();
+        let mut storage = MemoryManager::new();
+        assert_eq!(1, recover(&mut storage, &mut log).unwrap());
+        let mut buf = vec![0u8; PAGE_SIZE];
+        storage.read_page_data(page_ids[1], &mut buf).unwrap();
+        assert_eq!([42u8; PAGE_BODY_SIZE][..], buf[..PAGE_BODY_SIZE]);
+    }
+
+    #[test]
+    fn restore_test() {
+        let dir = tempdir().unwrap();
+        let archive_dir = dir.path().join("archive");
+        let mut log =
+            FileLogManager::with_segment_size(dir.path().join("wal"), PAGE_SIZE as u64).unwrap();
+        log.set_archiver(archive_to(&archive_dir));
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 4).unwrap();
+        let page_ids: Vec<_> = (0..3)
+            .map(|_| bufmgr.create_page().unwrap().page_id)
+            .collect();
+        let mut lsns = vec![];
+        for (n, &page_id) in page_ids.iter().enumerate() {
+            lsns.push(log.end_lsn());
+            write_page(&mut bufmgr, &mut log, page_id, n as u8 + 1);
+        }
+        // 最後のセグメントまでアーカイブすれば、生きているログは切り詰めてよい
+        log.switch_segment().unwrap();
+        log.truncate_before(log.end_lsn()).unwrap();
+        assert_eq!(1, log.segment_paths().len());
+
+        // 空のベースバックアップに 2 秒までにコミットしたものを戻す
+        let mut archive = ArchivedLog::open(&archive_dir).unwrap();
+        let target = RecoveryTarget::Time(UNIX_EPOCH + std::time::Duration::from_secs(2));
+        let mut storage = MemoryManager::new();
+        assert_eq!(
+            2,
+            restore(&mut storage, &mut archive, Lsn(0), target).unwrap()
+        );
+        let mut buf = vec![0u8; PAGE_SIZE];
+        storage.read_page_data(page_ids[1], &mut buf).unwrap();
+        assert_eq!([2u8; PAGE_BODY_SIZE][..], buf[..PAGE_BODY_SIZE]);
+
+        // LSN を指定するとそれより前にコミットしたものだけを戻す
+        let target = RecoveryTarget::Lsn(lsns[1]);
+        let mut storage = MemoryManager::new();
+        assert_eq!(
+            1,
+            restore(&mut storage, &mut archive, Lsn(0), target).unwrap()
+        );
+        let target = RecoveryTarget::Latest;
+        assert_eq!(
+            3,
+            restore(&mut storage, &mut archive, Lsn(0), target).unwrap()
+        );
+    }
+
+    #[test]
+    fn incremental_backup_test() {
+        let dir = tempdir().unwrap();
+        let mut log =
+            FileLogManager::with_segment_size(dir.path().join("wal"), PAGE_SIZE as u64).unwrap();
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 4).unwrap();
+        let page_ids: Vec<_> = (0..3)
+            .map(|_| bufmgr.create_page().unwrap().page_id)
+            .collect();
+        let read_page = |storage: &mut MemoryManager, page_id| {
+            let mut buf = vec![0u8; PAGE_SIZE];
+            storage.read_page_data(page_id, &mut buf).unwrap();
+            buf[0]
+        };
+
+        // ベースバックアップ
+        write_page(&mut bufmgr, &mut log, page_ids[0], 1);
+        let mut backup = MemoryManager::new();
+        recover(&mut backup, &mut log).unwrap();
+        let base_end = log.flushed_lsn();
+
+        // 前のバックアップの後のセグメントだけをコピーする
+        write_page(&mut bufmgr, &mut log, page_ids[1], 2);
+        write_page(&mut bufmgr, &mut log, page_ids[2], 3);
+        let first_dir = dir.path().join("incr1");
+        let first_end = log.backup_since(base_end, &first_dir).unwrap();
+        let num_copied = std::fs::read_dir(&first_dir).unwrap().count();
+        assert!(num_copied < log.segment_paths().len());
+        write_page(&mut bufmgr, &mut log, page_ids[0], 4);
+        let second_dir = dir.path().join("incr2");
+        log.backup_since(first_end, &second_dir).unwrap();
+
+        // 順に適用する
+        let target = RecoveryTarget::Latest;
+        assert_eq!(
+            2,
+            apply_incremental(&mut backup, &first_dir, base_end, target).unwrap()
+        );
+        assert_eq!(
+            1,
+            apply_incremental(&mut backup, &second_dir, first_end, target).unwrap()
+        );
+        let contents: Vec<_> = page_ids
+            .iter()
+            .map(|&page_id| read_page(&mut backup, page_id))
+            .collect();
+        assert_eq!(vec![4, 2, 3], contents);
+
+        // 切り詰めたところからは作れない
+        log.truncate_before(log.end_lsn()).unwrap();
+        assert!(log
+            .backup_since(base_end, dir.path().join("incr3"))
+            .is_err());
+        // 空のディレクトリはバックアップとして読まず、セグメントも作らない
+        let empty = dir.path().join("empty");
+        std::fs::create_dir(&empty).unwrap();
+        assert!(apply_incremental(&mut backup, &empty, first_end, RecoveryTarget::Latest).is_err());
+        assert_eq!(0, std::fs::read_dir(&empty).unwrap().count());
+    }
+}
@@ -1,9 +1,23 @@
 use std::collections::HashMap;
+use std::io::Write;
 use std::ops::{Index, IndexMut};
+use std::path::Path;
 use std::rc::Rc;
 
-use crate::buffer::{entity::Buffer, manager::*};
-use crate::storage::{entity::PageId, manager::*};
+use crate::buffer::{
+    entity::{Buffer, Checkpoint, PAGE_SIZE},
+    manager::*,
+    metrics::*,
+};
+use crate::storage::{
+    entity::PageId,
+    manager::{Error as StorageError, StorageManager},
+};
+
+use super::disk::DiskManager;
+
+// usage_count の上限の既定値 (PostgreSQL と同じ)
+pub const DEFAULT_MAX_USAGE_COUNT: u64 = 5;
 
 #[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct BufferId(usize);
@@ -11,6 +25,8 @@ pub struct BufferId(usize);
 #[derive(Debug, Default)]
 struct Frame {
     usage_count: u64,
+    // ページを読み込んだ時刻 (ClockSweepManager::clock)
+    loaded_at: u64,
     buffer: Rc<Buffer>,
 }
 
@@ -54,10 +70,12 @@ impl BufferPool {
         let victim_id = loop {
             let next_victim_id = self.next_victim_id;
             let frame = &mut self[next_victim_id];
-            if frame.usage_count == 0 {
-                break self.next_victim_id;
-            }
             if Rc::get_mut(&mut frame.buffer).is_some() {
+                if frame.usage_count == 0 {
+                    // 次の探索は犠牲にしたフレームの次から始める
+                    self.next_victim_id = self.increment_id(next_victim_id);
+                    break next_victim_id;
+                }
                 frame.usage_count -= 1;
                 consecutive_pinned = 0;
             } else {
@@ -80,27 +98,272 @@ pub struct ClockSweepManager<T: StorageManager> {
     disk: T,
     pool: BufferPool,
     page_table: HashMap<PageId, BufferId>,
+    observer: Box<dyn Observer>,
+    max_usage_count: u64,
+    // ページへのアクセスごとに進む論理時刻
+    clock: u64,
+    // midpoint insertion で cold から hot に昇格するまでの最小滞在時間
+    min_residency: Option<u64>,
 }
 
 impl<T: StorageManager> ClockSweepManager<T> {
-    pub fn new(disk: T, pool_size: usize) -> Self {
+    pub fn new(disk: T, pool_size: usize) -> Result<Self, Error> {
+        Self::with_max_usage_count(disk, pool_size, DEFAULT_MAX_USAGE_COUNT)
+    }
+
+    // usage_count の上限を指定して生成する
+    // フレームは PAGE_SIZE の大きさなので、ページの大きさが違う disk は Error::PageSize で断る
+    pub fn with_max_usage_count(
+        disk: T,
+        pool_size: usize,
+        max_usage_count: u64,
+    ) -> Result<Self, Error> {
+        if let Some(page_size) = disk.page_size() {
+            if page_size != PAGE_SIZE {
+                return Err(Error::PageSize {
+                    expected: PAGE_SIZE,
+                    actual: page_size,
+                });
+            }
+        }
         let pool = BufferPool::new(pool_size);
         let page_table = HashMap::new();
-        Self {
+        Ok(Self {
             disk,
             pool,
             page_table,
+            observer: Box::new(NullObserver),
+            max_usage_count,
+            clock: 0,
+            min_residency: None,
+        })
+    }
+
+    // midpoint insertion を有効にする
+    // 新しく読み込んだページは cold (usage_count = 0) から始まり、読み込みから
+    // min_residency 回以上のアクセスを経た後の再アクセスで初めて hot に昇格する
+    // 一度きりのスキャンでワーキングセットが追い出されるのを防ぐ
+    pub fn set_midpoint_insertion(&mut self, min_residency: Option<u64>) {
+        self.min_residency = min_residency;
+    }
+
+    fn initial_usage_count(&self) -> u64 {
+        match self.min_residency {
+            Some(_) => 0,
+            None => 1,
+        }
+    }
+
+    // ページ ID 順に並べ、連続するページはまとめて一度に書き出す
+    fn write_back(&mut self, mut pages: Vec<(PageId, BufferId)>) -> Result<(), Error> {
+        pages.sort_by_key(|&(page_id, _)| page_id);
+        for run in pages.chunk_by(|(lhs, _), (rhs, _)| lhs.to_u64() + 1 == rhs.to_u64()) {
+            let pool = &self.pool;
+            let buffers: Vec<_> = run
+                .iter()
+                .map(|&(_, buffer_id)| pool[buffer_id].buffer.page.borrow())
+                .collect();
+            let data: Vec<&[u8]> = buffers.iter().map(|page| page.as_ref()).collect();
+            self.disk.write_pages_data(run[0].0, &data)?;
+            for &(page_id, buffer_id) in run {
+                self.pool[buffer_id].buffer.is_dirty.set(false);
+                self.observer.on_writeback(page_id);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn storage(&self) -> &T {
+        &self.disk
+    }
+
+    // 下位の storagemanager を直接操作する
+    // バッファプールに載っているページの読み書きはこれを通さないこと
+    pub fn storage_mut(&mut self) -> &mut T {
+        &mut self.disk
+    }
+
+    // バッファプールの動作を観測する Observer を設定する
+    pub fn set_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observer = observer;
+    }
+
+    // バッファプールに載っているページ ID を usage_count の大きい順に返す
+    pub fn dump_resident_pages(&self) -> Vec<PageId> {
+        let mut resident: Vec<_> = self
+            .page_table
+            .iter()
+            .map(|(&page_id, &buffer_id)| (self.pool[buffer_id].usage_count, page_id))
+            .collect();
+        resident.sort_by(|(lhs_count, lhs_id), (rhs_count, rhs_id)| {
+            rhs_count.cmp(lhs_count).then(lhs_id.0.cmp(&rhs_id.0))
+        });
+        resident.into_iter().map(|(_, page_id)| page_id).collect()
+    }
+
+    // ダーティなページの ID を返す
+    pub fn dirty_page_ids(&self) -> Vec<PageId> {
+        let mut dirty_pages: Vec<_> = self
+            .page_table
+            .iter()
+            .filter(|(_, &buffer_id)| self.pool[buffer_id].buffer.is_dirty.get())
+            .map(|(&page_id, _)| page_id)
+            .collect();
+        dirty_pages.sort();
+        dirty_pages
+    }
+
+    // ページがダーティなら書き戻す
+    // 使用中 (pin されている) のページは書き換えの途中かもしれないので書かずに false を返す
+    pub fn flush_page(&mut self, page_id: PageId) -> Result<bool, Error> {
+        let buffer_id = match self.page_table.get(&page_id) {
+            Some(&buffer_id) => buffer_id,
+            None => return Ok(true),
+        };
+        let buffer = &self.pool[buffer_id].buffer;
+        if Rc::strong_count(buffer) > 1 {
+            return Ok(false);
+        }
+        if buffer.is_dirty.get() {
+            self.write_back(vec![(page_id, buffer_id)])?;
         }
+        Ok(true)
+    }
+
+    // バッファプールの状態をゲージとして報告する
+    pub fn report_metrics(&self, metrics: &mut dyn Metrics) {
+        let num_dirty_pages = self
+            .page_table
+            .values()
+            .filter(|&&buffer_id| self.pool[buffer_id].buffer.is_dirty.get())
+            .count();
+        metrics.set_gauge(BUFFER_POOL_SIZE, self.pool.size() as f64);
+        metrics.set_gauge(BUFFER_POOL_RESIDENT_PAGES, self.page_table.len() as f64);
+        metrics.set_gauge(BUFFER_POOL_DIRTY_PAGES, num_dirty_pages as f64);
+    }
+
+    // 指定したページを先読みしてバッファプールを温める
+    // プールサイズを超える分は先に読んだページを追い出してしまうので読まない
+    // 載っていないページは ID 順に並べ、連続するものはまとめて読み出す
+    pub fn warmup(&mut self, page_ids: &[PageId]) -> Result<(), Error> {
+        let mut missing = vec![];
+        for &page_id in page_ids.iter().take(self.pool.size()) {
+            if self.page_table.contains_key(&page_id) {
+                self.fetch_page(page_id)?;
+            } else {
+                missing.push(page_id);
+            }
+        }
+        missing.sort();
+        missing.dedup();
+        for run in missing.chunk_by(|lhs, rhs| lhs.to_u64() + 1 == rhs.to_u64()) {
+            self.load_pages(run)?;
+        }
+        Ok(())
+    }
+
+    // 連続するページをフレームに割り当て、一度に読み込む
+    // まとめて読めなかったときは fetch_page で一つずつ読み直す
+    fn load_pages(&mut self, run: &[PageId]) -> Result<(), Error> {
+        let initial_usage_count = self.initial_usage_count();
+        // 割り当てたフレームは読み終わるまで pin しておく
+        let mut frames = vec![];
+        for &page_id in run {
+            self.clock += 1;
+            let buffer_id = match self.pool.evict() {
+                Some(buffer_id) => buffer_id,
+                None => {
+                    self.reset_frames(frames);
+                    return Err(Error::NoFreeBuffer);
+                }
+            };
+            let frame = &mut self.pool[buffer_id];
+            let evict_page_id = frame.buffer.page_id;
+            let buffer = Rc::get_mut(&mut frame.buffer).unwrap();
+            if buffer.is_dirty.get() {
+                if let Err(err) = self
+                    .disk
+                    .write_page_data(evict_page_id, buffer.page.get_mut())
+                {
+                    self.reset_frames(frames);
+                    return Err(err.into());
+                }
+                self.observer.on_writeback(evict_page_id);
+            }
+            if let Some(evict_page_id) = evict_page_id.valid() {
+                self.observer.on_evict(evict_page_id);
+            }
+            self.page_table.remove(&evict_page_id);
+            *buffer = Buffer::default();
+            buffer.page_id = page_id;
+            frame.usage_count = initial_usage_count;
+            frame.loaded_at = self.clock;
+            frames.push((buffer_id, Rc::clone(&frame.buffer)));
+        }
+        let result = {
+            let mut pages: Vec<_> = frames
+                .iter()
+                .map(|(_, buffer)| buffer.page.borrow_mut())
+                .collect();
+            let mut bufs: Vec<&mut [u8]> = pages.iter_mut().map(|page| &mut page[..]).collect();
+            self.disk.read_pages_data(run[0], &mut bufs)
+        };
+        if result.is_err() {
+            self.reset_frames(frames);
+            for &page_id in run {
+                self.fetch_page(page_id)?;
+            }
+            return Ok(());
+        }
+        for (buffer_id, buffer) in frames {
+            self.observer.on_fetch(buffer.page_id, false);
+            self.page_table.insert(buffer.page_id, buffer_id);
+        }
+        Ok(())
+    }
+
+    // 割り当てたが読み込めなかったフレームを空きに戻す
+    fn reset_frames(&mut self, frames: Vec<(BufferId, Rc<Buffer>)>) {
+        for (buffer_id, buffer) in frames {
+            drop(buffer);
+            let frame = &mut self.pool[buffer_id];
+            frame.buffer = Default::default();
+            frame.usage_count = 0;
+        }
+    }
+}
+
+impl ClockSweepManager<DiskManager> {
+    // ダーティページを書き出してからヒープファイルの全ページを dest にコピーする
+    pub fn backup(&mut self, dest: impl Write) -> Result<u64, Error> {
+        self.checkpoint()?;
+        Ok(self.disk.backup(dest)?)
+    }
+
+    // ダーティページを書き出してからヒープファイルのスナップショットを path に作る
+    pub fn snapshot_to(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        self.checkpoint()?;
+        Ok(self.disk.snapshot_to(path)?)
     }
 }
 
 impl<T: StorageManager> BufferPoolManager for ClockSweepManager<T> {
     fn fetch_page(&mut self, page_id: PageId) -> Result<Rc<Buffer>, Error> {
+        self.clock += 1;
         if let Some(&buffer_id) = self.page_table.get(&page_id) {
             let frame = &mut self.pool[buffer_id];
-            frame.usage_count += 1;
+            let is_cold = frame.usage_count == 0;
+            let promotable = match self.min_residency {
+                Some(min_residency) => self.clock - frame.loaded_at >= min_residency,
+                None => true,
+            };
+            if frame.usage_count < self.max_usage_count && (!is_cold || promotable) {
+                frame.usage_count += 1;
+            }
+            self.observer.on_fetch(page_id, true);
             return Ok(frame.buffer.clone());
         }
+        let initial_usage_count = self.initial_usage_count();
         let buffer_id = self.pool.evict().ok_or(Error::NoFreeBuffer)?;
         let frame = &mut self.pool[buffer_id];
         let evict_page_id = frame.buffer.page_id;
@@ -109,12 +372,29 @@ impl<T: StorageManager> BufferPoolManager for ClockSweepManager<T> {
             if buffer.is_dirty.get() {
                 self.disk
                     .write_page_data(evict_page_id, buffer.page.get_mut())?;
+                self.observer.on_writeback(evict_page_id);
+            }
+            if let Some(evict_page_id) = evict_page_id.valid() {
+                self.observer.on_evict(evict_page_id);
             }
             buffer.page_id = page_id;
             buffer.is_dirty.set(false);
-            self.disk.read_page_data(page_id, buffer.page.get_mut())?;
-            frame.usage_count = 1;
+            match self.disk.read_page_data(page_id, buffer.page.get_mut()) {
+                Ok(()) => {}
+                // まだ書き出されていないページはゼロで埋まったページとして扱う
+                Err(StorageError::PastEof(_)) => buffer.page.get_mut().fill(0),
+                Err(err) => {
+                    // 読み込みに失敗したフレームは空きに戻す
+                    *buffer = Buffer::default();
+                    frame.usage_count = 0;
+                    self.page_table.remove(&evict_page_id);
+                    return Err(err.into());
+                }
+            }
+            frame.usage_count = initial_usage_count;
+            frame.loaded_at = self.clock;
         }
+        self.observer.on_fetch(page_id, false);
         let page = Rc::clone(&frame.buffer);
         self.page_table.remove(&evict_page_id);
         self.page_table.insert(page_id, buffer_id);
@@ -122,6 +402,8 @@ impl<T: StorageManager> BufferPoolManager for ClockSweepManager<T> {
     }
 
     fn create_page(&mut self) -> Result<Rc<Buffer>, Error> {
+        self.clock += 1;
+        let initial_usage_count = self.initial_usage_count();
         let buffer_id = self.pool.evict().ok_or(Error::NoFreeBuffer)?;
         let frame = &mut self.pool[buffer_id];
         let evict_page_id = frame.buffer.page_id;
@@ -130,13 +412,25 @@ impl<T: StorageManager> BufferPoolManager for ClockSweepManager<T> {
             if buffer.is_dirty.get() {
                 self.disk
                     .write_page_data(evict_page_id, buffer.page.get_mut())?;
+                self.observer.on_writeback(evict_page_id);
+            }
+            if let Some(evict_page_id) = evict_page_id.valid() {
+                self.observer.on_evict(evict_page_id);
             }
             self.page_table.remove(&evict_page_id);
-            let page_id = self.disk.allocate_page();
             *buffer = Buffer::default();
+            let page_id = match self.disk.allocate_page() {
+                Ok(page_id) => page_id,
+                Err(err) => {
+                    // 追い出したフレームは空きのまま残す
+                    frame.usage_count = 0;
+                    return Err(err.into());
+                }
+            };
             buffer.page_id = page_id;
             buffer.is_dirty.set(true);
-            frame.usage_count = 1;
+            frame.usage_count = initial_usage_count;
+            frame.loaded_at = self.clock;
             page_id
         };
         let page = Rc::clone(&frame.buffer);
@@ -146,24 +440,59 @@ impl<T: StorageManager> BufferPoolManager for ClockSweepManager<T> {
     }
 
     fn flush(&mut self) -> Result<(), Error> {
-        for (&page_id, &buffer_id) in self.page_table.iter() {
-            let frame = &self.pool[buffer_id];
-            let mut page = frame.buffer.page.borrow_mut();
-            self.disk.write_page_data(page_id, page.as_mut())?;
-            frame.buffer.is_dirty.set(false);
-        }
+        let pages = self
+            .page_table
+            .iter()
+            .map(|(&page_id, &buffer_id)| (page_id, buffer_id))
+            .collect();
+        self.write_back(pages)?;
         self.disk.sync()?;
         Ok(())
     }
+
+    fn checkpoint(&mut self) -> Result<Checkpoint, Error> {
+        let dirty_pages: Vec<_> = self
+            .page_table
+            .iter()
+            .filter(|(_, &buffer_id)| self.pool[buffer_id].buffer.is_dirty.get())
+            .map(|(&page_id, &buffer_id)| (page_id, buffer_id))
+            .collect();
+        let num_flushed_pages = dirty_pages.len();
+        let max_flushed_page_id = dirty_pages.iter().map(|&(page_id, _)| page_id).max();
+        self.write_back(dirty_pages)?;
+        self.disk.sync()?;
+        Ok(Checkpoint {
+            num_flushed_pages,
+            max_flushed_page_id,
+        })
+    }
+
+    fn discard_page(&mut self, page_id: PageId) -> Result<(), Error> {
+        let buffer_id = match self.page_table.get(&page_id) {
+            Some(&buffer_id) => buffer_id,
+            None => return Ok(()),
+        };
+        let frame = &mut self.pool[buffer_id];
+        let buffer = Rc::get_mut(&mut frame.buffer).ok_or(Error::PagePinned(page_id))?;
+        *buffer = Buffer::default();
+        frame.usage_count = 0;
+        self.page_table.remove(&page_id);
+        Ok(())
+    }
+
+    fn free_page(&mut self, page_id: PageId) -> Result<(), Error> {
+        self.discard_page(page_id)?;
+        self.disk.deallocate_page(page_id)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{
-        buffer::entity::PAGE_SIZE,
-        storage::{entity::PageId, manager::StorageManager},
+    use crate::storage::{
+        entity::PageId,
+        manager::{Result, StorageManager},
     };
-    use std::io::Result;
 
     #[derive(Debug, PartialEq)]
     enum Op {
@@ -188,11 +517,11 @@ mod tests {
     }
 
     impl StorageManager for TraceStorage {
-        fn allocate_page(&mut self) -> PageId {
+        fn allocate_page(&mut self) -> Result<PageId> {
             let pid = PageId(self.next_page_id);
             self.next_page_id += 1;
             self.history.push(Op::Alloc(pid));
-            pid
+            Ok(pid)
         }
         fn read_page_data(&mut self, page_id: PageId, _data: &mut [u8]) -> Result<()> {
             self.history.push(Op::Read(page_id));
@@ -213,7 +542,7 @@ mod tests {
         use super::*;
 
         let mock = TraceStorage::new();
-        let mut bufmgr = ClockSweepManager::new(mock, 1);
+        let mut bufmgr = ClockSweepManager::new(mock, 1).unwrap();
         {
             let res = bufmgr.create_page();
             assert!(res.is_ok());
@@ -249,7 +578,7 @@ mod tests {
         use super::*;
 
         let mock = TraceStorage::new();
-        let mut bufmgr = ClockSweepManager::new(mock, 1);
+        let mut bufmgr = ClockSweepManager::new(mock, 1).unwrap();
         {
             let res = bufmgr.fetch_page(PageId(1));
             assert!(res.is_ok());
@@ -311,7 +640,7 @@ mod tests {
         use super::*;
 
         let mock = TraceStorage::new();
-        let mut bufmgr = ClockSweepManager::new(mock, 3);
+        let mut bufmgr = ClockSweepManager::new(mock, 3).unwrap();
         {
             let res = bufmgr.flush();
             assert!(res.is_ok());
@@ -348,9 +677,359 @@ mod tests {
             );
             let res = bufmgr.flush();
             assert!(res.is_ok());
-            // flush 操作が HashMap::iter() で順序が変わるのでログの数のみで確認
-            // ここまでの 6 レコードに buffer Write 3 つと Sync の 4 レコードが追加
-            assert_eq!(10, bufmgr.disk.history.len())
+            // ページ ID 順に書き出す
+            assert_eq!(
+                vec![
+                    Op::Write(PageId(1)),
+                    Op::Write(PageId(2)),
+                    Op::Write(PageId(3)),
+                    Op::Sync,
+                ],
+                bufmgr.disk.history[6..]
+            );
         }
     }
+
+    #[test]
+    fn checkpoint_test() {
+        use super::*;
+
+        let mock = TraceStorage::new();
+        let mut bufmgr = ClockSweepManager::new(mock, 4).unwrap();
+        {
+            let _ = bufmgr.create_page();
+            let _ = bufmgr.create_page();
+            let _ = bufmgr.fetch_page(PageId(10));
+            let _ = bufmgr.create_page();
+            let res = bufmgr.checkpoint();
+            assert_eq!(
+                Checkpoint {
+                    num_flushed_pages: 3,
+                    max_flushed_page_id: Some(PageId(3)),
+                },
+                res.unwrap()
+            );
+            // clean なページは書かず、ページ ID 順に書き出す
+            assert_eq!(
+                vec![
+                    Op::Write(PageId(1)),
+                    Op::Write(PageId(2)),
+                    Op::Write(PageId(3)),
+                    Op::Sync,
+                ],
+                bufmgr.disk.history[4..]
+            );
+        }
+        {
+            let res = bufmgr.checkpoint();
+            assert_eq!(Checkpoint::default(), res.unwrap());
+            assert_eq!(Some(&Op::Sync), bufmgr.disk.history.last());
+            assert_eq!(9, bufmgr.disk.history.len());
+        }
+    }
+
+    #[test]
+    fn discard_page_test() {
+        use super::*;
+
+        let mock = TraceStorage::new();
+        let mut bufmgr = ClockSweepManager::new(mock, 1).unwrap();
+        {
+            let buffer = bufmgr.create_page().unwrap();
+            let res_err = bufmgr.discard_page(buffer.page_id);
+            assert!(matches!(res_err, Err(Error::PagePinned(PageId(1)))));
+        }
+        {
+            let res = bufmgr.discard_page(PageId(1));
+            assert!(res.is_ok());
+            // 破棄したページは書き戻さない
+            let res = bufmgr.flush();
+            assert!(res.is_ok());
+            assert_eq!(vec![Op::Alloc(PageId(1)), Op::Sync], bufmgr.disk.history);
+            // 空いたフレームを再利用できる
+            let res = bufmgr.fetch_page(PageId(2));
+            assert!(res.is_ok());
+            assert_eq!(
+                vec![Op::Alloc(PageId(1)), Op::Sync, Op::Read(PageId(2))],
+                bufmgr.disk.history
+            );
+        }
+        {
+            // バッファプールに無いページの破棄は何もしない
+            let res = bufmgr.discard_page(PageId(42));
+            assert!(res.is_ok());
+        }
+    }
+
+    #[test]
+    fn corrupt_page_test() {
+        use super::*;
+
+        struct CorruptStorage;
+
+        impl StorageManager for CorruptStorage {
+            fn allocate_page(&mut self) -> Result<PageId> {
+                unreachable!()
+            }
+            fn read_page_data(&mut self, page_id: PageId, _data: &mut [u8]) -> Result<()> {
+                match page_id {
+                    PageId(2) => Err(StorageError::Corrupt(page_id)),
+                    PageId(3) => Err(StorageError::PastEof(page_id)),
+                    _ => Ok(()),
+                }
+            }
+            fn write_page_data(&mut self, _page_id: PageId, _data: &[u8]) -> Result<()> {
+                Ok(())
+            }
+            fn sync(&mut self) -> Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut bufmgr = ClockSweepManager::new(CorruptStorage, 1).unwrap();
+        assert!(bufmgr.fetch_page(PageId(1)).is_ok());
+        let res_err = bufmgr.fetch_page(PageId(2));
+        assert!(matches!(
+            res_err,
+            Err(Error::CorruptPage { page_id: PageId(2) })
+        ));
+        // 壊れたページはバッファプールに残らない
+        assert!(bufmgr.page_table.is_empty());
+        assert!(bufmgr.fetch_page(PageId(1)).is_ok());
+        // まだ書き出されていないページはゼロで埋まったページになる
+        let buffer = bufmgr.fetch_page(PageId(3)).unwrap();
+        assert!(buffer.page.borrow().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn max_usage_count_test() {
+        use super::*;
+
+        let mock = TraceStorage::new();
+        let mut bufmgr = ClockSweepManager::with_max_usage_count(mock, 2, 3).unwrap();
+        {
+            for _ in 0..10 {
+                let _ = bufmgr.fetch_page(PageId(1));
+            }
+            let buffer_id = bufmgr.page_table[&PageId(1)];
+            assert_eq!(3, bufmgr.pool[buffer_id].usage_count);
+        }
+        {
+            // 上限で頭打ちなので数周で追い出せる
+            for page_id in 2..=5 {
+                let _ = bufmgr.fetch_page(PageId(page_id));
+            }
+            assert!(!bufmgr.page_table.contains_key(&PageId(1)));
+        }
+    }
+
+    #[test]
+    fn midpoint_insertion_test() {
+        use super::*;
+
+        let mock = TraceStorage::new();
+        let mut bufmgr = ClockSweepManager::new(mock, 2).unwrap();
+        bufmgr.set_midpoint_insertion(Some(3));
+        {
+            // 読み込み直後の再アクセスでは hot にならない
+            let _ = bufmgr.fetch_page(PageId(1));
+            let _ = bufmgr.fetch_page(PageId(1));
+            let _ = bufmgr.fetch_page(PageId(2));
+            let _ = bufmgr.fetch_page(PageId(3));
+            assert!(!bufmgr.page_table.contains_key(&PageId(1)));
+            assert!(bufmgr.page_table.contains_key(&PageId(2)));
+        }
+        {
+            // 十分に滞在した後の再アクセスで hot に昇格する
+            let _ = bufmgr.fetch_page(PageId(3));
+            let _ = bufmgr.fetch_page(PageId(3));
+            let _ = bufmgr.fetch_page(PageId(2));
+            let _ = bufmgr.fetch_page(PageId(4));
+            assert!(bufmgr.page_table.contains_key(&PageId(2)));
+            assert!(!bufmgr.page_table.contains_key(&PageId(3)));
+        }
+    }
+
+    #[test]
+    fn observer_test() {
+        use super::*;
+        use std::cell::RefCell;
+
+        #[derive(Debug, PartialEq)]
+        enum Event {
+            Fetch(PageId, bool),
+            Evict(PageId),
+            Writeback(PageId),
+        }
+
+        struct Recorder(Rc<RefCell<Vec<Event>>>);
+
+        impl Observer for Recorder {
+            fn on_fetch(&mut self, page_id: PageId, hit: bool) {
+                self.0.borrow_mut().push(Event::Fetch(page_id, hit));
+            }
+            fn on_evict(&mut self, page_id: PageId) {
+                self.0.borrow_mut().push(Event::Evict(page_id));
+            }
+            fn on_writeback(&mut self, page_id: PageId) {
+                self.0.borrow_mut().push(Event::Writeback(page_id));
+            }
+        }
+
+        let events = Rc::new(RefCell::new(vec![]));
+        let mock = TraceStorage::new();
+        let mut bufmgr = ClockSweepManager::new(mock, 1).unwrap();
+        bufmgr.set_observer(Box::new(Recorder(Rc::clone(&events))));
+        {
+            let _ = bufmgr.create_page();
+            let _ = bufmgr.fetch_page(PageId(1));
+            let _ = bufmgr.fetch_page(PageId(2));
+            assert_eq!(
+                vec![
+                    Event::Fetch(PageId(1), true),
+                    Event::Writeback(PageId(1)),
+                    Event::Evict(PageId(1)),
+                    Event::Fetch(PageId(2), false),
+                ],
+                *events.borrow()
+            );
+        }
+        {
+            events.borrow_mut().clear();
+            let _ = bufmgr.flush();
+            assert_eq!(vec![Event::Writeback(PageId(2))], *events.borrow());
+        }
+    }
+
+    #[test]
+    fn metrics_test() {
+        use super::*;
+        use std::cell::RefCell;
+
+        #[derive(Default)]
+        struct Registry {
+            counters: HashMap<&'static str, u64>,
+            gauges: HashMap<&'static str, f64>,
+        }
+
+        impl Metrics for Rc<RefCell<Registry>> {
+            fn increment_counter(&mut self, name: &'static str, value: u64) {
+                *self.borrow_mut().counters.entry(name).or_default() += value;
+            }
+            fn set_gauge(&mut self, name: &'static str, value: f64) {
+                self.borrow_mut().gauges.insert(name, value);
+            }
+        }
+
+        let registry = Rc::new(RefCell::new(Registry::default()));
+        let mock = TraceStorage::new();
+        let mut bufmgr = ClockSweepManager::new(mock, 2).unwrap();
+        bufmgr.set_observer(Box::new(MetricsObserver::new(Rc::clone(&registry))));
+        {
+            let _ = bufmgr.create_page();
+            let _ = bufmgr.fetch_page(PageId(2));
+            let _ = bufmgr.fetch_page(PageId(3));
+            let _ = bufmgr.fetch_page(PageId(3));
+            let registry = registry.borrow();
+            assert_eq!(1, registry.counters[BUFFER_POOL_HITS]);
+            assert_eq!(2, registry.counters[BUFFER_POOL_MISSES]);
+            assert_eq!(1, registry.counters[BUFFER_POOL_EVICTIONS]);
+            assert_eq!(1, registry.counters[BUFFER_POOL_WRITEBACKS]);
+        }
+        {
+            let _ = bufmgr.create_page();
+            bufmgr.report_metrics(&mut Rc::clone(&registry));
+            let registry = registry.borrow();
+            assert_eq!(2.0, registry.gauges[BUFFER_POOL_SIZE]);
+            assert_eq!(2.0, registry.gauges[BUFFER_POOL_RESIDENT_PAGES]);
+            assert_eq!(1.0, registry.gauges[BUFFER_POOL_DIRTY_PAGES]);
+        }
+    }
+
+    #[test]
+    fn freeze_test() {
+        use super::*;
+
+        let mock = TraceStorage::new();
+        let mut bufmgr = ClockSweepManager::new(mock, 1).unwrap();
+        let snapshot = {
+            let buffer = bufmgr.create_page().unwrap();
+            buffer.page.borrow_mut()[0] = 42;
+            let snapshot = buffer.freeze();
+            // 元のページを書き換えてもスナップショットは変わらない
+            buffer.page.borrow_mut()[0] = 43;
+            snapshot
+        };
+        // スナップショットはフレームを pin しない
+        assert!(bufmgr.fetch_page(PageId(2)).is_ok());
+        assert_eq!(PageId(1), snapshot.page_id);
+        assert_eq!(42, snapshot[0]);
+    }
+
+    #[test]
+    fn warmup_test() {
+        use super::*;
+
+        let mock = TraceStorage::new();
+        let mut bufmgr = ClockSweepManager::new(mock, 2).unwrap();
+        assert!(bufmgr.dump_resident_pages().is_empty());
+        {
+            let _ = bufmgr.fetch_page(PageId(1));
+            let _ = bufmgr.fetch_page(PageId(2));
+            let _ = bufmgr.fetch_page(PageId(2));
+            // よく使われたページが先頭に来る
+            assert_eq!(vec![PageId(2), PageId(1)], bufmgr.dump_resident_pages());
+        }
+        {
+            let mock = TraceStorage::new();
+            let mut bufmgr = ClockSweepManager::new(mock, 2).unwrap();
+            let res = bufmgr.warmup(&[PageId(2), PageId(1), PageId(3)]);
+            assert!(res.is_ok());
+            // プールサイズを超える分は読まない
+            // 連続するページは ID 順にまとめて読む
+            assert_eq!(
+                vec![Op::Read(PageId(1)), Op::Read(PageId(2))],
+                bufmgr.disk.history
+            );
+            let _ = bufmgr.fetch_page(PageId(1));
+            let _ = bufmgr.fetch_page(PageId(2));
+            // no storage access(hit the cache)
+            assert_eq!(2, bufmgr.disk.history.len());
+        }
+    }
+
+    #[test]
+    fn backup_test() {
+        use super::*;
+        use tempfile::NamedTempFile;
+
+        let disk = DiskManager::new(tempfile::tempfile().unwrap(), PAGE_SIZE).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 2).unwrap();
+        let page_id = {
+            let buffer = bufmgr.create_page().unwrap();
+            buffer.body_mut()[..5].copy_from_slice(b"hello");
+            buffer.is_dirty.set(true);
+            buffer.page_id
+        };
+        // ダーティページもバックアップに含まれる
+        let (mut backup, backup_path) = NamedTempFile::new().unwrap().into_parts();
+        assert_eq!(2, bufmgr.backup(&mut backup).unwrap());
+        drop(backup);
+        let mut restored = DiskManager::open(&backup_path, PAGE_SIZE).unwrap();
+        let mut buf = vec![0u8; PAGE_SIZE];
+        restored.read_page_data(page_id, &mut buf).unwrap();
+        assert_eq!(b"hello", &buf[..5]);
+    }
+
+    #[test]
+    fn page_size_test() {
+        use super::*;
+
+        // フレームより大きなページのファイルはバッファプールに載せられない
+        let disk = DiskManager::new(tempfile::tempfile().unwrap(), PAGE_SIZE * 2).unwrap();
+        assert!(matches!(
+            ClockSweepManager::new(disk, 1),
+            Err(Error::PageSize { expected: PAGE_SIZE, actual }) if actual == PAGE_SIZE * 2
+        ));
+    }
 }
@@ -0,0 +1,311 @@
+use std::io::BufRead;
+use std::sync::Mutex;
+
+use anyhow::Result;
+
+use super::{btree::BTree, catalog::TableSchema, sequence::Sequence, table::Table};
+use crate::buffer::manager::BufferPoolManager;
+use crate::sql::ddl::table::Table as ITable;
+use crate::sql::dml::entity::{Tuple, Type, Value};
+
+// 空でないテーブルに一度に INSERT する行の数
+pub const BATCH_SIZE: usize = 1000;
+
+// COPY で読む一行の書き方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // 列をタブで区切る。NULL は \N と書き、\t \n \r \\ でタブ、改行、復帰、\ を書く
+    Text,
+    // 列をカンマで区切る。" で囲めばカンマも書け、" は "" と書く。囲まない空の列は NULL
+    Csv,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("line {line}: expected {expected} columns but got {actual}")]
+    Columns {
+        line: usize,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("line {line}: column {column} is {expected:?} but got {value:?}")]
+    Value {
+        line: usize,
+        column: String,
+        expected: Type,
+        value: String,
+    },
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    // 主キーや一意のインデックスで重なるなど、読めた行をテーブルに書けなかった
+    #[error("line {line}: {source}")]
+    Insert {
+        line: usize,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+// reader の行を table に書き、書いた行の数を返す
+// テーブルが空なら全ての行を読んでから Table::bulk_load で木を詰めて作り、
+// そうでなければ BATCH_SIZE 行ずつ主キーの順に並べて INSERT する (近いキーが続けて同じ葉に入る)
+// どちらも失敗したら何も書かずに返す (INSERT なら、それまでに書いた行を消してから何行目かを返す)
+// 書き終えるまで sync せず、最後に一度だけ flush する
+// sequence は主キーを振るテーブルのシーケンス (主キーが NULL の行に振る)
+pub fn copy_from<T: BufferPoolManager>(
+    bufmgr: &mut T,
+    schema: &TableSchema,
+    sequence: Option<&Mutex<Sequence>>,
+    reader: impl BufRead,
+    format: Format,
+) -> Result<usize> {
+    let table = schema.table();
+    // 書いた行 (失敗したら消す)
+    let mut inserted: Vec<Tuple> = vec![];
+    match copy_rows(
+        bufmgr,
+        schema,
+        &table,
+        sequence,
+        reader,
+        format,
+        &mut inserted,
+    ) {
+        Ok(count) => {
+            bufmgr.flush()?;
+            Ok(count)
+        }
+        Err(err) => {
+            delete_all(bufmgr, &table, inserted)?;
+            Err(err)
+        }
+    }
+}
+
+fn copy_rows<T: BufferPoolManager>(
+    bufmgr: &mut T,
+    schema: &TableSchema,
+    table: &Table,
+    sequence: Option<&Mutex<Sequence>>,
+    reader: impl BufRead,
+    format: Format,
+    inserted: &mut Vec<Tuple>,
+) -> Result<usize> {
+    let bulk = BTree::new(schema.meta_page_id).is_empty(bufmgr)?;
+    // 読んだ行と、その行番号
+    let mut batch: Vec<(usize, Tuple)> = vec![];
+    let mut count = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        // 空行は読み飛ばす
+        if line.is_empty() {
+            continue;
+        }
+        let values = parse_line(schema, i + 1, &line, format)?;
+        let mut record: Tuple = values.iter().map(Value::encode).collect();
+        if let Some(sequence) = sequence {
+            if record[0].is_empty() {
+                let id = sequence.lock().unwrap().next_value(bufmgr)?;
+                record[0] = Value::Int(id).encode();
+            }
+        }
+        batch.push((i + 1, record));
+        count += 1;
+        if !bulk && batch.len() == BATCH_SIZE {
+            insert_batch(bufmgr, table, schema, &mut batch, inserted)?;
+        }
+    }
+    if bulk {
+        let records: Vec<_> = batch.into_iter().map(|(_, record)| record).collect();
+        table.bulk_load(bufmgr, &records)?;
+    } else {
+        insert_batch(bufmgr, table, schema, &mut batch, inserted)?;
+    }
+    Ok(count)
+}
+
+fn insert_batch<T: BufferPoolManager>(
+    bufmgr: &mut T,
+    table: &impl ITable<T>,
+    schema: &TableSchema,
+    batch: &mut Vec<(usize, Tuple)>,
+    inserted: &mut Vec<Tuple>,
+) -> Result<()> {
+    batch.sort_by(|(_, a), (_, b)| a[..schema.num_key_elems].cmp(&b[..schema.num_key_elems]));
+    for (line, record) in batch.drain(..) {
+        let elems: Vec<_> = record.iter().map(Vec::as_slice).collect();
+        if let Err(err) = table.insert(bufmgr, &elems) {
+            let source = err.into();
+            return Err(Error::Insert { line, source }.into());
+        }
+        inserted.push(record);
+    }
+    Ok(())
+}
+
+// 書いた行を逆の順に消す
+fn delete_all<T: BufferPoolManager>(
+    bufmgr: &mut T,
+    table: &impl ITable<T>,
+    inserted: Vec<Tuple>,
+) -> Result<()> {
+    for record in inserted.into_iter().rev() {
+        let record: Vec<_> = record.iter().map(Vec::as_slice).collect();
+        table.delete(bufmgr, &record)?;
+    }
+    Ok(())
+}
+
+// 一行を列の型に従って値にする
+fn parse_line(schema: &TableSchema, line: usize, text: &str, format: Format) -> Result<Vec<Value>> {
+    let fields = match format {
+        Format::Text => split_text(line, text)?,
+        Format::Csv => split_csv(line, text)?,
+    };
+    if fields.len() != schema.columns.len() {
+        return Err(Error::Columns {
+            line,
+            expected: schema.columns.len(),
+            actual: fields.len(),
+        }
+        .into());
+    }
+    let mut values = vec![];
+    for (field, column) in fields.into_iter().zip(&schema.columns) {
+        let field = match field {
+            Some(field) => field,
+            None => {
+                values.push(Value::Null);
+                continue;
+            }
+        };
+        let value = match column.column_type {
+            Type::Int => field.parse().ok().map(Value::Int),
+            Type::Bool => match field.to_ascii_lowercase().as_str() {
+                "true" | "t" => Some(Value::Bool(true)),
+                "false" | "f" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            Type::Bytes => Some(Value::Bytes(field.clone().into_bytes())),
+            Type::Null => None,
+        };
+        match value {
+            Some(value) => values.push(value),
+            None => {
+                return Err(Error::Value {
+                    line,
+                    column: column.name.clone(),
+                    expected: column.column_type,
+                    value: field,
+                }
+                .into())
+            }
+        }
+    }
+    Ok(values)
+}
+
+// タブで区切った列を戻す (NULL は None)
+fn split_text(line: usize, text: &str) -> Result<Vec<Option<String>>, Error> {
+    let mut fields = vec![];
+    for field in text.split('\t') {
+        if field == "\\N" {
+            fields.push(None);
+            continue;
+        }
+        let mut unescaped = String::new();
+        let mut chars = field.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unescaped.push(c);
+                continue;
+            }
+            unescaped.push(match chars.next() {
+                Some('t') => '\t',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('\\') => '\\',
+                Some(c) => {
+                    let message = format!("unknown escape \\{}", c);
+                    return Err(Error::Syntax { line, message });
+                }
+                None => {
+                    let message = "backslash at the end of a column".to_string();
+                    return Err(Error::Syntax { line, message });
+                }
+            });
+        }
+        fields.push(Some(unescaped));
+    }
+    Ok(fields)
+}
+
+// カンマで区切った列を戻す (囲まない空の列は None)
+fn split_csv(line: usize, text: &str) -> Result<Vec<Option<String>>, Error> {
+    let mut fields = vec![];
+    let mut chars = text.chars().peekable();
+    loop {
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            let mut field = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => {
+                        let message = "unterminated quoted field".to_string();
+                        return Err(Error::Syntax { line, message });
+                    }
+                }
+            }
+            fields.push(Some(field));
+            match chars.next() {
+                Some(',') => continue,
+                None => break,
+                Some(c) => {
+                    let message = format!("unexpected {:?} after a quoted field", c);
+                    return Err(Error::Syntax { line, message });
+                }
+            }
+        }
+        let mut field = String::new();
+        let mut more = false;
+        for c in chars.by_ref() {
+            if c == ',' {
+                more = true;
+                break;
+            }
+            field.push(c);
+        }
+        fields.push(if field.is_empty() { None } else { Some(field) });
+        if !more {
+            break;
+        }
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_test() {
+        let some = |field: &str| Some(field.to_string());
+        assert_eq!(
+            vec![some("a\tb"), None, some(""), some("c\\")],
+            split_text(1, "a\\tb\t\\N\t\tc\\\\").unwrap()
+        );
+        assert!(split_text(1, "a\\x").is_err());
+        assert_eq!(
+            vec![some("1"), None, some("x, \"y\""), some(""), None],
+            split_csv(1, "1,,\"x, \"\"y\"\"\",\"\",").unwrap()
+        );
+        assert_eq!(vec![some("a")], split_csv(1, "a").unwrap());
+        assert!(split_csv(1, "\"a").is_err());
+        assert!(split_csv(1, "\"a\"b").is_err());
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use super::{
+    catalog::ColumnStats,
+    expr::{CmpOp, Expr},
+};
+use crate::sql::dml::entity::Value;
+
+// 統計がないテーブルの行の数
+pub const DEFAULT_ROWS: f64 = 1000.0;
+// 統計がないテーブルの一ページあたりの行の数
+pub const ROWS_PER_PAGE: f64 = 50.0;
+
+// 値の種類が分からない列の等号を満たす行の割合
+const DEFAULT_EQ_SEL: f64 = 0.005;
+// 大小の比べを満たす行の割合
+const DEFAULT_RANGE_SEL: f64 = 1.0 / 3.0;
+const DEFAULT_NULL_SEL: f64 = 0.005;
+// 見積もれない式を満たす行の割合
+const DEFAULT_SEL: f64 = 0.5;
+
+// プランの費用の見積もりに使う単価 (順に一ページ読むのを 1 とする)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostModel {
+    pub seq_page_cost: f64,
+    // B+Tree を根から引いたり、インデックスからテーブルの行を引いたりして飛び飛びに読むページ
+    pub random_page_cost: f64,
+    // 一行を扱う CPU
+    pub cpu_tuple_cost: f64,
+    // 式を一つ評価したり、キーを一度比べたりする CPU
+    pub cpu_operator_cost: f64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            seq_page_cost: 1.0,
+            random_page_cost: 4.0,
+            cpu_tuple_cost: 0.01,
+            cpu_operator_cost: 0.0025,
+        }
+    }
+}
+
+impl CostModel {
+    // 根から引いて、葉を pages ページ順に読む
+    pub fn index_search(&self, pages: f64) -> f64 {
+        self.random_page_cost + pages * self.seq_page_cost
+    }
+
+    // rows 行を並べる
+    pub fn sort(&self, rows: f64) -> f64 {
+        2.0 * self.cpu_operator_cost * rows * rows.max(2.0).log2()
+    }
+}
+
+// ノードが返す行の数と、全ての行を返すまでの費用の見積もり
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Cost {
+    pub rows: f64,
+    pub total: f64,
+}
+
+impl fmt::Display for Cost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(cost={:.2} rows={:.0})", self.total, self.rows)
+    }
+}
+
+// 列の値の見積もり
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ColumnEstimate {
+    // 値の種類の数
+    pub distinct: f64,
+    pub null_frac: f64,
+    // ANALYZE で集めたもの (なければ範囲の述語は決まった割合で見積もる)
+    pub stats: Option<Arc<ColumnStats>>,
+}
+
+impl ColumnEstimate {
+    pub fn new(distinct: f64) -> Self {
+        Self {
+            distinct,
+            ..Self::default()
+        }
+    }
+
+    pub fn from_stats(rows: u64, stats: ColumnStats) -> Self {
+        Self {
+            distinct: stats.distinct.max(1) as f64,
+            null_frac: stats.nulls as f64 / rows.max(1) as f64,
+            stats: Some(Arc::new(stats)),
+        }
+    }
+
+    // NULL でない値のうち value より小さいものの割合 (ヒストグラムがなければ None)
+    // バケットの中は整数なら一様に分布しているとみなし、それ以外は半分とする
+    fn fraction_below(&self, value: &Value) -> Option<f64> {
+        let stats = self.stats.as_ref()?;
+        let min = stats.min.as_ref()?;
+        if stats.histogram.is_empty() || value.column_type() != min.column_type() {
+            return None;
+        }
+        if value <= min {
+            return Some(0.0);
+        }
+        let buckets = stats.histogram.len();
+        let i = match stats.histogram.iter().position(|bound| value <= bound) {
+            Some(i) => i,
+            None => return Some(1.0),
+        };
+        let lower = if i == 0 { min } else { &stats.histogram[i - 1] };
+        let within = match (lower, &stats.histogram[i], value) {
+            // 差が i64 に収まらないこともあるので、先に f64 にしてから引く
+            (Value::Int(lower), Value::Int(upper), Value::Int(value)) if upper > lower => {
+                (*value as f64 - *lower as f64) / (*upper as f64 - *lower as f64)
+            }
+            _ => 0.5,
+        };
+        Some((i as f64 + within) / buckets as f64)
+    }
+}
+
+// 列と定数の比べ (列を左にしたもの)
+fn column_literal(cond: &Expr) -> Option<(usize, CmpOp, &Value)> {
+    match cond {
+        Expr::Compare(op, lhs, rhs) => match (lhs.as_ref(), rhs.as_ref()) {
+            (Expr::Column(column), Expr::Literal(value)) => Some((*column, *op, value)),
+            (Expr::Literal(value), Expr::Column(column)) => {
+                let op = match op {
+                    CmpOp::Lt => CmpOp::Gt,
+                    CmpOp::Le => CmpOp::Ge,
+                    CmpOp::Gt => CmpOp::Lt,
+                    CmpOp::Ge => CmpOp::Le,
+                    op => *op,
+                };
+                Some((*column, op, value))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// ヒストグラムで見積もれる範囲の述語なら、列と、下限か、その値より小さいものの割合
+fn range_bound(cond: &Expr, columns: &[ColumnEstimate]) -> Option<(usize, bool, f64)> {
+    let (column, op, value) = column_literal(cond)?;
+    let below = columns.get(column)?.fraction_below(value)?;
+    match op {
+        CmpOp::Gt | CmpOp::Ge => Some((column, true, below)),
+        CmpOp::Lt | CmpOp::Le => Some((column, false, below)),
+        CmpOp::Eq | CmpOp::Ne => None,
+    }
+}
+
+// 述語を全て満たす行の割合
+// 同じ列の下限と上限は、それぞれの割合を掛けずにヒストグラムで間の割合を見積もる
+pub fn conjunction<'e>(
+    conds: impl IntoIterator<Item = &'e Expr>,
+    columns: &[ColumnEstimate],
+) -> f64 {
+    let mut sel = 1.0;
+    // 列ごとの (下限より小さい割合, 上限より小さい割合)
+    let mut ranges = BTreeMap::new();
+    for cond in conds {
+        match range_bound(cond, columns) {
+            Some((column, is_lower, below)) => {
+                let range = ranges.entry(column).or_insert((0.0_f64, 1.0_f64));
+                if is_lower {
+                    range.0 = range.0.max(below);
+                } else {
+                    range.1 = range.1.min(below);
+                }
+            }
+            None => sel *= selectivity(cond, columns),
+        }
+    }
+    for (column, (lower, upper)) in ranges {
+        sel *= (upper - lower).max(0.0) * (1.0 - columns[column].null_frac);
+    }
+    sel
+}
+
+// 述語を満たす行の割合
+// columns は入力の行の列ごとの見積もり
+pub fn selectivity(cond: &Expr, columns: &[ColumnEstimate]) -> f64 {
+    let column = |expr: &Expr| match expr {
+        Expr::Column(column) => columns.get(*column),
+        _ => None,
+    };
+    match cond {
+        Expr::Literal(Value::Bool(true)) => 1.0,
+        // 偽か NULL
+        Expr::Literal(_) => 0.0,
+        Expr::Compare(CmpOp::Eq, lhs, rhs) => eq_selectivity(cond, column(lhs), column(rhs)),
+        Expr::Compare(CmpOp::Ne, lhs, rhs) => 1.0 - eq_selectivity(cond, column(lhs), column(rhs)),
+        Expr::Compare(..) if range_bound(cond, columns).is_some() => conjunction([cond], columns),
+        Expr::Compare(..) => DEFAULT_RANGE_SEL,
+        Expr::And(exprs) => conjunction(exprs, columns),
+        Expr::Or(exprs) => {
+            let none: f64 = exprs
+                .iter()
+                .map(|expr| 1.0 - selectivity(expr, columns))
+                .product();
+            1.0 - none
+        }
+        Expr::Not(expr) => 1.0 - selectivity(expr, columns),
+        Expr::IsNull(expr) => match column(expr) {
+            Some(ColumnEstimate {
+                stats: Some(_),
+                null_frac,
+                ..
+            }) => *null_frac,
+            _ => DEFAULT_NULL_SEL,
+        },
+        Expr::Column(_) | Expr::Arith(..) | Expr::Param(_) => DEFAULT_SEL,
+    }
+}
+
+// 列どうしなら種類の多い方の値ごとに一つ、値と比べるならその列の値の一つに当たるとみなす
+// 最小と最大の外の定数には当たらない
+fn eq_selectivity(cond: &Expr, lhs: Option<&ColumnEstimate>, rhs: Option<&ColumnEstimate>) -> f64 {
+    match (lhs, rhs) {
+        (Some(lhs), Some(rhs)) => 1.0 / lhs.distinct.max(rhs.distinct).max(1.0),
+        (Some(column), None) | (None, Some(column)) => {
+            let out_of_range = match (column_literal(cond), &column.stats) {
+                (Some((_, _, value)), Some(stats)) => {
+                    stats.min.as_ref().is_some_and(|min| value < min)
+                        || stats.max.as_ref().is_some_and(|max| value > max)
+                }
+                _ => false,
+            };
+            if out_of_range {
+                return 0.0;
+            }
+            (1.0 - column.null_frac) / column.distinct.max(1.0)
+        }
+        (None, None) => DEFAULT_EQ_SEL,
+    }
+}
+
+// 値の種類の数が分からない列の見積もり
+pub fn default_distinct(rows: f64) -> f64 {
+    (1.0 / DEFAULT_EQ_SEL).min(rows).max(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selectivity_test() {
+        let distinct = [ColumnEstimate::new(100.0), ColumnEstimate::new(4.0)];
+        let col = Expr::Column;
+        let eq = |lhs, rhs| Expr::compare(CmpOp::Eq, lhs, rhs);
+        let int = |n| Expr::Literal(Value::Int(n));
+        assert_eq!(0.25, selectivity(&eq(col(1), int(1)), &distinct));
+        assert_eq!(0.01, selectivity(&eq(col(0), col(1)), &distinct));
+        assert_eq!(DEFAULT_EQ_SEL, selectivity(&eq(int(1), int(2)), &distinct));
+        let and = Expr::And(vec![
+            eq(col(1), int(1)),
+            Expr::compare(CmpOp::Lt, col(0), int(5)),
+        ]);
+        assert_eq!(0.25 / 3.0, selectivity(&and, &distinct));
+        let or = Expr::Or(vec![eq(col(1), int(1)), eq(col(1), int(2))]);
+        assert_eq!(1.0 - 0.75 * 0.75, selectivity(&or, &distinct));
+        assert_eq!(0.0, selectivity(&Expr::Literal(Value::Null), &distinct));
+    }
+
+    #[test]
+    fn histogram_test() {
+        // 0..100 を 4 つのバケットに分け、10 行に一つは NULL
+        let stats = ColumnStats {
+            distinct: 100,
+            nulls: 10,
+            min: Some(Value::Int(0)),
+            max: Some(Value::Int(99)),
+            histogram: [24, 49, 74, 99].iter().map(|&n| Value::Int(n)).collect(),
+        };
+        let columns = [ColumnEstimate::from_stats(100, stats)];
+        let col = || Expr::Column(0);
+        let int = |n| Expr::Literal(Value::Int(n));
+        let lt = |n| Expr::compare(CmpOp::Lt, col(), int(n));
+        let ge = |n| Expr::compare(CmpOp::Ge, col(), int(n));
+        let close = |expected: f64, actual: f64| (expected - actual).abs() < 1e-9;
+
+        assert!(close(0.9 * 0.5, selectivity(&lt(49), &columns)));
+        assert!(close(
+            0.9 * 0.5,
+            selectivity(&Expr::compare(CmpOp::Gt, int(49), col()), &columns)
+        ));
+        assert!(close(0.0, selectivity(&lt(-5), &columns)));
+        assert!(close(0.9, selectivity(&ge(-5), &columns)));
+        // 同じ列の下限と上限は間の割合にする
+        let between = Expr::And(vec![ge(24), lt(74)]);
+        assert!(close(0.9 * 0.5, selectivity(&between, &columns)));
+        // 最小と最大の外の値には当たらない
+        let eq = |n| Expr::compare(CmpOp::Eq, col(), int(n));
+        assert!(close(0.009, selectivity(&eq(10), &columns)));
+        assert_eq!(0.0, selectivity(&eq(100), &columns));
+        assert!(close(
+            0.1,
+            selectivity(&Expr::IsNull(Box::new(col())), &columns)
+        ));
+
+        // 値の幅が i64 に収まらなくても見積もれる
+        let stats = ColumnStats {
+            distinct: 2,
+            nulls: 0,
+            min: Some(Value::Int(i64::MIN)),
+            max: Some(Value::Int(i64::MAX)),
+            histogram: vec![Value::Int(i64::MAX)],
+        };
+        let columns = [ColumnEstimate::from_stats(2, stats)];
+        assert!(close(0.5, selectivity(&lt(0), &columns)));
+    }
+}
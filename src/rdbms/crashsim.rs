@@ -0,0 +1,239 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io;
+use std::rc::Rc;
+
+use crate::storage::{
+    entity::PageId,
+    manager::{Result, StorageManager},
+};
+
+// クラッシュしても残る内容
+// sync されたページだけが中の storagemanager に書かれる
+// 開いた CrashSimStorage を捨てれば、sync していない書き込みは消える (プロセスのクラッシュ)
+pub struct SimDisk<T: StorageManager> {
+    inner: Rc<RefCell<T>>,
+}
+
+impl<T: StorageManager> Clone for SimDisk<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Rc::clone(&self.inner),
+        }
+    }
+}
+
+impl<T: StorageManager> SimDisk<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(inner)),
+        }
+    }
+
+    // データベースを開く (クラッシュの後に開き直してリカバリを確かめる)
+    pub fn open(&self) -> CrashSimStorage<T> {
+        CrashSimStorage {
+            disk: self.clone(),
+            unsynced: BTreeMap::new(),
+            writes_until_crash: None,
+            crashed: false,
+        }
+    }
+}
+
+// 書き込みを sync までメモリに溜めておく、リカバリのテスト用の storagemanager
+// ページの割り当てと解放はすぐに永続化する (DiskManager は割り当てたときにファイルを伸ばす)
+// crash_after_writes でクラッシュする時点を決められ、クラッシュした後は全ての操作が失敗する
+pub struct CrashSimStorage<T: StorageManager> {
+    disk: SimDisk<T>,
+    // sync していない書き込み
+    unsynced: BTreeMap<PageId, Vec<u8>>,
+    // この回数だけ書き込んだら、次の書き込みでクラッシュする
+    writes_until_crash: Option<usize>,
+    crashed: bool,
+}
+
+impl<T: StorageManager> CrashSimStorage<T> {
+    pub fn crash_after_writes(&mut self, num_writes: usize) {
+        self.writes_until_crash = Some(num_writes);
+    }
+
+    // sync していない書き込みを捨ててクラッシュする
+    pub fn crash(&mut self) {
+        self.unsynced.clear();
+        self.crashed = true;
+    }
+
+    pub fn is_crashed(&self) -> bool {
+        self.crashed
+    }
+
+    // sync していないページ
+    pub fn unsynced_page_ids(&self) -> Vec<PageId> {
+        self.unsynced.keys().copied().collect()
+    }
+
+    fn check_crashed(&self) -> Result<()> {
+        if self.crashed {
+            return Err(io::Error::other("simulated crash").into());
+        }
+        Ok(())
+    }
+}
+
+impl<T: StorageManager> StorageManager for CrashSimStorage<T> {
+    fn page_size(&self) -> Option<usize> {
+        self.disk.inner.borrow().page_size()
+    }
+
+    fn allocate_page(&mut self) -> Result<PageId> {
+        self.check_crashed()?;
+        self.disk.inner.borrow_mut().allocate_page()
+    }
+
+    fn deallocate_page(&mut self, page_id: PageId) -> Result<()> {
+        self.check_crashed()?;
+        self.unsynced.remove(&page_id);
+        self.disk.inner.borrow_mut().deallocate_page(page_id)
+    }
+
+    fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> Result<()> {
+        self.check_crashed()?;
+        match self.unsynced.get(&page_id) {
+            Some(page) => {
+                data.copy_from_slice(page);
+                Ok(())
+            }
+            None => self.disk.inner.borrow_mut().read_page_data(page_id, data),
+        }
+    }
+
+    fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+        self.check_crashed()?;
+        match self.writes_until_crash.as_mut() {
+            Some(0) => self.crash(),
+            Some(n) => *n -= 1,
+            None => {}
+        }
+        self.check_crashed()?;
+        self.unsynced.insert(page_id, data.to_vec());
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.check_crashed()?;
+        let mut inner = self.disk.inner.borrow_mut();
+        for (&page_id, data) in &self.unsynced {
+            inner.write_page_data(page_id, data)?;
+        }
+        inner.sync()?;
+        self.unsynced.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accessor::{
+        entity::SearchMode,
+        method::{AccessMethod, Iterable},
+    };
+    use crate::buffer::{entity::PAGE_SIZE, manager::BufferPoolManager};
+    use crate::rdbms::{
+        btree::BTree,
+        checkpoint::{recover, Checkpointer},
+        clocksweep::ClockSweepManager,
+        memory::MemoryManager,
+        table::SimpleTable,
+        txn::TransactionManager,
+        wal::FileLogManager,
+    };
+    use crate::sql::ddl::table::Table as ITable;
+    use tempfile::tempdir;
+
+    #[test]
+    fn crash_test() {
+        let disk = SimDisk::new(MemoryManager::new());
+        let mut storage = disk.open();
+        let page_ids: Vec<_> = (0..3).map(|_| storage.allocate_page().unwrap()).collect();
+        storage
+            .write_page_data(page_ids[0], &[1; PAGE_SIZE])
+            .unwrap();
+        storage.sync().unwrap();
+        storage
+            .write_page_data(page_ids[0], &[2; PAGE_SIZE])
+            .unwrap();
+        storage
+            .write_page_data(page_ids[1], &[2; PAGE_SIZE])
+            .unwrap();
+        // クラッシュするまでは書いた内容が読める
+        let mut buf = vec![0u8; PAGE_SIZE];
+        storage.read_page_data(page_ids[0], &mut buf).unwrap();
+        assert_eq!(vec![2u8; PAGE_SIZE], buf);
+        storage.crash_after_writes(1);
+        storage
+            .write_page_data(page_ids[2], &[3; PAGE_SIZE])
+            .unwrap();
+        assert!(storage
+            .write_page_data(page_ids[2], &[4; PAGE_SIZE])
+            .is_err());
+        assert!(storage.is_crashed());
+        assert!(storage.sync().is_err());
+
+        // 開き直すと sync したものだけが残っている
+        let mut storage = disk.open();
+        storage.read_page_data(page_ids[0], &mut buf).unwrap();
+        assert_eq!(vec![1u8; PAGE_SIZE], buf);
+        assert!(storage.read_page_data(page_ids[1], &mut buf).is_err());
+    }
+
+    #[test]
+    fn recovery_test() {
+        let dir = tempdir().unwrap();
+        let disk = SimDisk::new(MemoryManager::new());
+        let mut bufmgr = ClockSweepManager::new(disk.open(), 10).unwrap();
+        let mut table = SimpleTable {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+        };
+        table.create(&mut bufmgr).unwrap();
+        bufmgr.flush().unwrap();
+        let btree = BTree::new(table.meta_page_id);
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        let insert = |txn_mgr: &mut TransactionManager<_>, bufmgr: &mut _, key: &[u8]| {
+            let mut txn = txn_mgr.begin().unwrap();
+            let txn_id = txn.id();
+            table
+                .insert_as(&mut txn.bind(bufmgr).unwrap(), txn_id, &[key, &[b'x'; 100]])
+                .unwrap();
+            txn_mgr.commit(txn).unwrap();
+        };
+        for i in 0..100 {
+            insert(&mut txn_mgr, &mut bufmgr, format!("{:04}", i).as_bytes());
+        }
+
+        // チェックポイントでページを書き出している途中でクラッシュした
+        let mut checkpointer = Checkpointer::new(0);
+        txn_mgr
+            .begin_checkpoint(&mut checkpointer, &bufmgr)
+            .unwrap();
+        bufmgr.storage_mut().crash_after_writes(2);
+        assert!(checkpointer
+            .step(&mut bufmgr, txn_mgr.log_mut(), 100)
+            .is_err());
+        drop(bufmgr);
+
+        // コミットした行は全てログから戻せる
+        let mut storage = disk.open();
+        recover(&mut storage, txn_mgr.log_mut()).unwrap();
+        let mut bufmgr = ClockSweepManager::new(storage, 10).unwrap();
+        let mut iter = btree.search(&mut bufmgr, SearchMode::Start).unwrap();
+        let mut count = 0;
+        while iter.next(&mut bufmgr).unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(100, count);
+    }
+}
@@ -0,0 +1,737 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+
+use super::{
+    binder::{bind_with_param_types, BoundStatement},
+    catalog::{Catalog, ColumnSchema, TableId},
+    copy::{self, Format},
+    expr::with_params,
+    introspect::is_virtual,
+    memctx::{with_memory_context, MemoryContext},
+    mvcc::{AllVisible, Visibility},
+    planner::{Plan, Planner},
+    sequence::Sequence,
+    syscat::SystemCatalog,
+};
+use crate::buffer::manager::BufferPoolManager;
+use crate::sql::dml::entity::{Type, Value};
+use crate::sql::parser::parse_statement;
+use crate::storage::entity::PageId;
+
+// 実行するときに渡したパラメータが、準備した文の使い方に合わない
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("expected {expected} parameters but got {actual}")]
+    ParamCount { expected: usize, actual: usize },
+    #[error("parameter ${number} expects {expected:?} but got {actual:?}")]
+    ParamType {
+        number: usize,
+        expected: Type,
+        actual: Value,
+    },
+    #[error("the catalog has changed since the statement was prepared")]
+    Stale,
+}
+
+// buffer pool とシステムカタログから読んだカタログを持ち、SQL を受け付ける
+pub struct Database<T: BufferPoolManager> {
+    pub bufmgr: T,
+    pub syscat: SystemCatalog,
+    pub catalog: Catalog,
+    pub visibility: Arc<dyn Visibility + Send + Sync>,
+    pub work_mem: usize,
+    // テーブルごとに開いたシーケンス (準備した文の間で手元の値を分け合う)
+    sequences: Mutex<HashMap<TableId, Arc<Mutex<Sequence>>>>,
+}
+
+impl<T: BufferPoolManager + 'static> Database<T> {
+    // 空のシステムカタログを作る
+    pub fn create(mut bufmgr: T) -> Result<Self> {
+        let syscat = SystemCatalog::create(&mut bufmgr)?;
+        Ok(Self::new(bufmgr, syscat, Catalog::new()))
+    }
+
+    // minidb_class の meta ページからシステムカタログを開き、カタログを読む
+    pub fn open(mut bufmgr: T, class_page_id: PageId) -> Result<Self> {
+        let syscat = SystemCatalog::open(&mut bufmgr, class_page_id)?;
+        let catalog = syscat.load(&mut bufmgr)?;
+        Ok(Self::new(bufmgr, syscat, catalog))
+    }
+
+    fn new(bufmgr: T, syscat: SystemCatalog, catalog: Catalog) -> Self {
+        Self {
+            bufmgr,
+            syscat,
+            catalog,
+            visibility: Arc::new(AllVisible),
+            work_mem: 1 << 20,
+            sequences: Mutex::default(),
+        }
+    }
+
+    fn sequence(&self, table: TableId) -> Option<Arc<Mutex<Sequence>>> {
+        let page_id = self.catalog.table_by_id(table)?.sequence_page_id?;
+        let mut sequences = self.sequences.lock().unwrap();
+        let sequence = sequences
+            .entry(table)
+            .or_insert_with(|| Arc::new(Mutex::new(Sequence::new(page_id))));
+        Some(sequence.clone())
+    }
+
+    // SQL を解析してプランまで組み立てておく
+    // 同じ文を値だけ変えて何度も実行するときに、解析と計画をやり直さずに済む
+    // INSERT は INSERT した行の数を count の一列の一行で返す
+    pub fn prepare(&self, sql: &str) -> Result<PreparedStatement<T>> {
+        let statement = parse_statement(sql)?;
+        let (bound, param_types) = bind_with_param_types(&self.catalog, &statement)?;
+        let planner = Planner::new(&self.catalog, self.visibility.clone(), self.work_mem);
+        let (plan, columns) = match bound {
+            BoundStatement::Query { plan, columns } => (planner.plan(&plan)?, columns),
+            BoundStatement::Insert {
+                table,
+                input,
+                on_conflict,
+            } => {
+                let count = ColumnSchema {
+                    name: "count".to_string(),
+                    column_type: Type::Int,
+                    nullable: false,
+                };
+                let sequence = self.sequence(table);
+                let plan = planner.plan_insert(table, &input, &on_conflict, sequence)?;
+                (plan, vec![count])
+            }
+            _ => bail!("only queries and INSERT can be prepared: {}", sql),
+        };
+        Ok(PreparedStatement {
+            plan,
+            columns,
+            param_types,
+            catalog_version: self.catalog.version(),
+        })
+    }
+
+    // reader の行を table にまとめて書き込み、書いた行の数を返す
+    pub fn copy_from(
+        &mut self,
+        table: &str,
+        reader: impl BufRead,
+        format: Format,
+    ) -> Result<usize> {
+        let schema = match self.catalog.table(table) {
+            Some(schema) if is_virtual(schema.id) => bail!("{} is read-only", table),
+            Some(schema) => schema,
+            None => bail!("unknown table {}", table),
+        };
+        let sequence = self.sequence(schema.id);
+        copy::copy_from(
+            &mut self.bufmgr,
+            schema,
+            sequence.as_deref(),
+            reader,
+            format,
+        )
+    }
+
+    // 適用したマイグレーションの一番大きい version (一つもなければ 0)
+    pub fn schema_version(&mut self) -> Result<u32> {
+        let migrations = self.syscat.migrations(&mut self.bufmgr)?;
+        Ok(migrations.last().map_or(0, |&(version, _)| version))
+    }
+
+    // 一度だけ実行する問合せ
+    pub fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Vec<Value>>> {
+        self.prepare(sql)?.execute(self, params)
+    }
+}
+
+// 準備した文のプランと、結果の列と、パラメータ ($1 から順) の型
+pub struct PreparedStatement<T: BufferPoolManager> {
+    plan: Plan<T>,
+    pub columns: Vec<ColumnSchema>,
+    // 型の決まらないパラメータ (None) にはどの型の値も渡せる
+    pub param_types: Vec<Option<Type>>,
+    // 準備したときのカタログの版 (テーブルやインデックスが変わったらプランは使えない)
+    catalog_version: u64,
+}
+
+impl<T: BufferPoolManager + 'static> PreparedStatement<T> {
+    // パラメータの数と型を確かめて実行し、結果の行を全て返す (NULL はどのパラメータにも渡せる)
+    // 準備した後にカタログが変わっていれば Stale を返すので、準備し直すこと
+    // 実行器は合わせて db.work_mem までのメモリを使う
+    pub fn execute(&self, db: &mut Database<T>, params: &[Value]) -> Result<Vec<Vec<Value>>> {
+        let context = Arc::new(MemoryContext::new(db.work_mem));
+        self.execute_in(db, params, context)
+    }
+
+    // context からメモリを確保して実行する (同じ context を渡した実行は合わせて上限を守る)
+    pub fn execute_in(
+        &self,
+        db: &mut Database<T>,
+        params: &[Value],
+        context: Arc<MemoryContext>,
+    ) -> Result<Vec<Vec<Value>>> {
+        if db.catalog.version() != self.catalog_version {
+            return Err(Error::Stale.into());
+        }
+        if params.len() != self.param_types.len() {
+            return Err(Error::ParamCount {
+                expected: self.param_types.len(),
+                actual: params.len(),
+            }
+            .into());
+        }
+        for (i, (param, expected)) in params.iter().zip(&self.param_types).enumerate() {
+            match expected {
+                Some(expected) if !param.is_null() && param.column_type() != *expected => {
+                    return Err(Error::ParamType {
+                        number: i + 1,
+                        expected: *expected,
+                        actual: param.clone(),
+                    }
+                    .into());
+                }
+                _ => {}
+            }
+        }
+        let bufmgr = &mut db.bufmgr;
+        with_memory_context(context, || {
+            with_params(params.to_vec(), || {
+                let mut exec = self.plan.start(bufmgr)?;
+                let mut rows = vec![];
+                while let Some(row) = exec.next(bufmgr)? {
+                    rows.push((0..row.len()).map(|i| row.get(i)).collect::<Result<_>>()?);
+                }
+                Ok(rows)
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdbms::{
+        btree::BTree, catalog::IndexSchema, clocksweep::ClockSweepManager, memory::MemoryManager,
+        table::Table,
+    };
+    use crate::sql::ddl::table::Table as ITable;
+
+    type Bufmgr = ClockSweepManager<MemoryManager>;
+
+    #[test]
+    fn prepare_test() {
+        let mut db: Database<Bufmgr> =
+            Database::create(ClockSweepManager::new(MemoryManager::new(), 10).unwrap()).unwrap();
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        table.create(&mut db.bufmgr).unwrap();
+        for (id, name) in [(1, "alice"), (2, "bob"), (3, "carol")] {
+            let record = [Value::Int(id).encode(), Value::Bytes(name.into()).encode()];
+            table
+                .insert(&mut db.bufmgr, &[&record[0], &record[1]])
+                .unwrap();
+        }
+        let column = |name: &str, column_type| ColumnSchema {
+            name: name.to_string(),
+            column_type,
+            nullable: false,
+        };
+        let columns = vec![column("id", Type::Int), column("name", Type::Bytes)];
+        db.syscat
+            .add_table(
+                &mut db.bufmgr,
+                &mut db.catalog,
+                "people",
+                columns,
+                1,
+                table.meta_page_id,
+            )
+            .unwrap();
+
+        // 一度準備した文を値を変えて実行する
+        let stmt = db
+            .prepare("SELECT name FROM people WHERE id >= $1")
+            .unwrap();
+        assert_eq!(vec![Some(Type::Int)], stmt.param_types);
+        assert_eq!("name", stmt.columns[0].name);
+        let names = |rows: Vec<Vec<Value>>| -> Vec<_> {
+            rows.into_iter().map(|row| row[0].clone()).collect()
+        };
+        assert_eq!(
+            vec![
+                Value::Bytes(b"bob".to_vec()),
+                Value::Bytes(b"carol".to_vec())
+            ],
+            names(stmt.execute(&mut db, &[Value::Int(2)]).unwrap())
+        );
+        assert_eq!(
+            vec![Value::Bytes(b"carol".to_vec())],
+            names(stmt.execute(&mut db, &[Value::Int(3)]).unwrap())
+        );
+        assert!(stmt.execute(&mut db, &[Value::Null]).unwrap().is_empty());
+
+        let err = stmt.execute(&mut db, &[Value::Bool(true)]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::ParamType {
+                number: 1,
+                expected: Type::Int,
+                ..
+            })
+        ));
+        let err = stmt.execute(&mut db, &[]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::ParamCount {
+                expected: 1,
+                actual: 0
+            })
+        ));
+        assert!(db.prepare("DELETE FROM people").is_err());
+
+        // 並べ替えた行は渡した context から確保する
+        let context = Arc::new(MemoryContext::new(1 << 20));
+        let sorted = db.prepare("SELECT id FROM people ORDER BY name").unwrap();
+        assert_eq!(
+            3,
+            sorted
+                .execute_in(&mut db, &[], context.clone())
+                .unwrap()
+                .len()
+        );
+        assert!(context.peak() > 0);
+        assert_eq!(0, context.used());
+
+        // 開き直したデータベースでも同じように引ける
+        let root = db.syscat.class_page_id;
+        let mut db = Database::open(db.bufmgr, root).unwrap();
+        let rows = db
+            .query(
+                "SELECT id FROM people WHERE name = $1",
+                &[Value::Bytes(b"bob".to_vec())],
+            )
+            .unwrap();
+        assert_eq!(vec![vec![Value::Int(2)]], rows);
+
+        // 開き直す前に準備した文や、テーブルを外す前に準備した文は実行しない
+        assert!(matches!(
+            stmt.execute(&mut db, &[Value::Int(2)])
+                .unwrap_err()
+                .downcast_ref::<Error>(),
+            Some(Error::Stale)
+        ));
+        let stmt = db.prepare("SELECT id FROM people").unwrap();
+        let people = db.catalog.table("people").unwrap().id;
+        db.syscat
+            .drop_table(&mut db.bufmgr, &mut db.catalog, people)
+            .unwrap();
+        assert!(matches!(
+            stmt.execute(&mut db, &[])
+                .unwrap_err()
+                .downcast_ref::<Error>(),
+            Some(Error::Stale)
+        ));
+    }
+
+    // 空の B+Tree を作ってシステムカタログに登録する
+    fn create_table(db: &mut Database<Bufmgr>, name: &str, columns: &[(&str, Type)]) -> TableId {
+        let meta_page_id = BTree::create(&mut db.bufmgr).unwrap().meta_page_id;
+        let columns = columns
+            .iter()
+            .map(|&(name, column_type)| ColumnSchema {
+                name: name.to_string(),
+                column_type,
+                nullable: name != "id",
+            })
+            .collect();
+        db.syscat
+            .add_table(
+                &mut db.bufmgr,
+                &mut db.catalog,
+                name,
+                columns,
+                1,
+                meta_page_id,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn insert_select_test() {
+        let mut db: Database<Bufmgr> =
+            Database::create(ClockSweepManager::new(MemoryManager::new(), 10).unwrap()).unwrap();
+        let columns = [("id", Type::Int), ("name", Type::Bytes)];
+        create_table(&mut db, "people", &columns);
+        let archive = create_table(&mut db, "archive", &columns);
+        let index = IndexSchema {
+            name: "by_name".to_string(),
+            columns: vec![1],
+            unique: true,
+            meta_page_id: BTree::create(&mut db.bufmgr).unwrap().meta_page_id,
+        };
+        db.syscat
+            .add_index(&mut db.bufmgr, &mut db.catalog, archive, index)
+            .unwrap();
+        let bytes = |value: &str| Value::Bytes(value.into());
+        let count = |n| vec![vec![Value::Int(n)]];
+
+        assert_eq!(
+            count(3),
+            db.query(
+                "INSERT INTO people VALUES (1, 'alice'), (2, 'bob'), (3, $1)",
+                &[bytes("carol")]
+            )
+            .unwrap()
+        );
+        // 他のテーブルから条件に合う行を写す (インデックスにも書く)
+        let stmt = db
+            .prepare("INSERT INTO archive SELECT id, name FROM people WHERE id >= $1")
+            .unwrap();
+        assert_eq!("count", stmt.columns[0].name);
+        assert_eq!(count(2), stmt.execute(&mut db, &[Value::Int(2)]).unwrap());
+        assert_eq!(
+            vec![vec![Value::Int(3)]],
+            db.query("SELECT id FROM archive WHERE name = 'carol'", &[])
+                .unwrap()
+        );
+        // 一意のインデックスに同じ値は書けず、失敗した文はそれより前の行も書かない
+        assert!(db
+            .query(
+                "INSERT INTO archive (id, name) SELECT 9, name FROM people WHERE id = 2",
+                &[]
+            )
+            .is_err());
+        assert!(db
+            .query("INSERT INTO archive VALUES (5, 'x'), (6, 'carol')", &[])
+            .is_err());
+        assert_eq!(
+            vec![
+                vec![Value::Int(2), bytes("bob")],
+                vec![Value::Int(3), bytes("carol")]
+            ],
+            db.query("SELECT id, name FROM archive", &[]).unwrap()
+        );
+        assert!(db
+            .query("SELECT id FROM archive WHERE name = 'x'", &[])
+            .unwrap()
+            .is_empty());
+
+        // 書き込むテーブル自身から読んでも、書き足した行は読まない
+        let log = create_table(&mut db, "log", &[("id", Type::Int), ("name", Type::Bytes)]);
+        db.syscat
+            .add_sequence(&mut db.bufmgr, &mut db.catalog, log, 1)
+            .unwrap();
+        db.query("INSERT INTO log (name) VALUES ('a'), ('b')", &[])
+            .unwrap();
+        assert_eq!(
+            count(2),
+            db.query("INSERT INTO log (name) SELECT name FROM log", &[])
+                .unwrap()
+        );
+        assert_eq!(
+            vec![
+                vec![Value::Int(1), bytes("a")],
+                vec![Value::Int(2), bytes("b")],
+                vec![Value::Int(3), bytes("a")],
+                vec![Value::Int(4), bytes("b")],
+            ],
+            db.query("SELECT id, name FROM log", &[]).unwrap()
+        );
+        // 列の型が合わなければ準備できない
+        assert!(db
+            .prepare("INSERT INTO log (name) SELECT id FROM people")
+            .is_err());
+    }
+
+    #[test]
+    fn null_test() {
+        let mut db: Database<Bufmgr> =
+            Database::create(ClockSweepManager::new(MemoryManager::new(), 10).unwrap()).unwrap();
+        create_table(
+            &mut db,
+            "people",
+            &[("id", Type::Int), ("age", Type::Int), ("name", Type::Bytes)],
+        );
+        db.query(
+            "INSERT INTO people VALUES (1, NULL, NULL), (2, 20, ''), (3, NULL, 'carol')",
+            &[],
+        )
+        .unwrap();
+        // NULL の整数の列も読め、NULL と空のバイト列は区別する
+        let bytes = |value: &str| Value::Bytes(value.into());
+        assert_eq!(
+            vec![
+                vec![Value::Int(1), Value::Null, Value::Null],
+                vec![Value::Int(2), Value::Int(20), bytes("")],
+                vec![Value::Int(3), Value::Null, bytes("carol")],
+            ],
+            db.query("SELECT id, age, name FROM people", &[]).unwrap()
+        );
+        assert_eq!(
+            vec![vec![Value::Int(1)]],
+            db.query("SELECT id FROM people WHERE name IS NULL", &[])
+                .unwrap()
+        );
+        assert_eq!(
+            vec![vec![Value::Int(2)]],
+            db.query("SELECT id FROM people WHERE name = ''", &[])
+                .unwrap()
+        );
+
+        // NULL にできない BYTES の列にも空のバイト列は書ける
+        let meta_page_id = BTree::create(&mut db.bufmgr).unwrap().meta_page_id;
+        let column = |name: &str, column_type| ColumnSchema {
+            name: name.to_string(),
+            column_type,
+            nullable: false,
+        };
+        let columns = vec![column("id", Type::Int), column("name", Type::Bytes)];
+        db.syscat
+            .add_table(
+                &mut db.bufmgr,
+                &mut db.catalog,
+                "tags",
+                columns,
+                1,
+                meta_page_id,
+            )
+            .unwrap();
+        db.query("INSERT INTO tags VALUES (1, '')", &[]).unwrap();
+        assert!(db.query("INSERT INTO tags VALUES (2, NULL)", &[]).is_err());
+        assert_eq!(
+            vec![vec![Value::Int(1), bytes("")]],
+            db.query("SELECT id, name FROM tags", &[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn copy_test() {
+        let mut db: Database<Bufmgr> =
+            Database::create(ClockSweepManager::new(MemoryManager::new(), 10).unwrap()).unwrap();
+        let people = create_table(
+            &mut db,
+            "people",
+            &[("id", Type::Int), ("name", Type::Bytes)],
+        );
+        let index = IndexSchema {
+            name: "by_name".to_string(),
+            columns: vec![1],
+            unique: true,
+            meta_page_id: BTree::create(&mut db.bufmgr).unwrap().meta_page_id,
+        };
+        db.syscat
+            .add_index(&mut db.bufmgr, &mut db.catalog, people, index)
+            .unwrap();
+
+        // 主キーや一意のインデックスで重なる行があれば、どの木にも書かずに失敗する
+        for text in ["1\ta\n2\ta\n", "1\ta\n1\tb\n"] {
+            assert!(db
+                .copy_from("people", text.as_bytes(), Format::Text)
+                .is_err());
+            assert!(db.query("SELECT id FROM people", &[]).unwrap().is_empty());
+        }
+
+        // 空のテーブルには木を詰めて作る (行の順は問わない)
+        let text: String = (0..3000)
+            .rev()
+            .map(|id| format!("{}\tname{}\n", id, id))
+            .collect();
+        assert_eq!(
+            3000,
+            db.copy_from("people", text.as_bytes(), Format::Text)
+                .unwrap()
+        );
+        let rows = db.query("SELECT id FROM people", &[]).unwrap();
+        assert_eq!(
+            (0..3000).map(|id| vec![Value::Int(id)]).collect::<Vec<_>>(),
+            rows
+        );
+        assert_eq!(
+            vec![vec![Value::Int(1234)]],
+            db.query("SELECT id FROM people WHERE name = 'name1234'", &[])
+                .unwrap()
+        );
+
+        // 空でなければ一行ずつ INSERT する
+        let csv = "3000,\"x, \"\"y\"\"\"\n\n3001,z\n";
+        assert_eq!(
+            2,
+            db.copy_from("people", csv.as_bytes(), Format::Csv).unwrap()
+        );
+        assert_eq!(
+            vec![
+                vec![Value::Bytes(b"x, \"y\"".to_vec())],
+                vec![Value::Bytes(b"z".to_vec())]
+            ],
+            db.query("SELECT name FROM people WHERE id >= 3000", &[])
+                .unwrap()
+        );
+        // 書けない行があれば何行目かを返す
+        let err = db
+            .copy_from("people", "3002,a\nb,c\n".as_bytes(), Format::Csv)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<copy::Error>(),
+            Some(copy::Error::Value { line: 2, .. })
+        ));
+        // 書けない行があれば、それより前の行も書かずに何行目かを返す
+        let before = db.query("SELECT id, name FROM people", &[]).unwrap();
+        for (text, line) in [
+            ("7\tdup\n", 1),
+            ("3002\ta\n3003\tname5\n", 2),
+            ("3004\tb\n3005\tc\n3006\t\\N\n3007\tz\n", 4),
+        ] {
+            let err = db
+                .copy_from("people", text.as_bytes(), Format::Text)
+                .unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<copy::Error>(),
+                    Some(copy::Error::Insert { line: l, .. }) if *l == line
+                ),
+                "{}",
+                err
+            );
+            assert_eq!(
+                before,
+                db.query("SELECT id, name FROM people", &[]).unwrap()
+            );
+            assert!(db
+                .query("SELECT id FROM people WHERE name = 'a'", &[])
+                .unwrap()
+                .is_empty());
+        }
+        assert!(db
+            .copy_from("minidb_tables", "".as_bytes(), Format::Text)
+            .is_err());
+
+        // 主キーを振るテーブルには NULL の主キーに振る
+        let log = create_table(&mut db, "log", &[("id", Type::Int), ("name", Type::Bytes)]);
+        db.syscat
+            .add_sequence(&mut db.bufmgr, &mut db.catalog, log, 1)
+            .unwrap();
+        db.copy_from("log", "\\N\ta\n\\N\tb\n".as_bytes(), Format::Text)
+            .unwrap();
+        db.query("INSERT INTO log (name) VALUES ('c')", &[])
+            .unwrap();
+        assert_eq!(
+            vec![
+                vec![Value::Int(1)],
+                vec![Value::Int(2)],
+                vec![Value::Int(3)]
+            ],
+            db.query("SELECT id FROM log", &[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn upsert_test() {
+        let mut db: Database<Bufmgr> =
+            Database::create(ClockSweepManager::new(MemoryManager::new(), 10).unwrap()).unwrap();
+        let columns = [
+            ("id", Type::Int),
+            ("name", Type::Bytes),
+            ("visits", Type::Int),
+        ];
+        let people = create_table(&mut db, "people", &columns);
+        for (name, columns, unique) in [("by_name", vec![1], true), ("by_visits", vec![2], false)] {
+            let index = IndexSchema {
+                name: name.to_string(),
+                columns,
+                unique,
+                meta_page_id: BTree::create(&mut db.bufmgr).unwrap().meta_page_id,
+            };
+            db.syscat
+                .add_index(&mut db.bufmgr, &mut db.catalog, people, index)
+                .unwrap();
+        }
+        let count = |n| vec![vec![Value::Int(n)]];
+        db.query(
+            "INSERT INTO people VALUES (1, 'alice', 1), (2, 'bob', 1)",
+            &[],
+        )
+        .unwrap();
+        assert!(db
+            .query("INSERT INTO people VALUES (1, 'carol', 1)", &[])
+            .is_err());
+
+        // 主キーが重なる行は飛ばして数えない
+        assert_eq!(
+            count(1),
+            db.query(
+                "INSERT INTO people VALUES (1, 'carol', 1), (3, 'carol', 1) \
+                 ON CONFLICT (id) DO NOTHING",
+                &[]
+            )
+            .unwrap()
+        );
+
+        // 主キーが重なる行は今の行と excluded の値で書き換え、インデックスも書き換える
+        let stmt = db
+            .prepare(
+                "INSERT INTO people VALUES ($1, $2, 1) ON CONFLICT (id) \
+                 DO UPDATE SET name = excluded.name, visits = people.visits + excluded.visits",
+            )
+            .unwrap();
+        let bytes = |value: &str| Value::Bytes(value.into());
+        for (id, name) in [(2, "robert"), (2, "bobby"), (4, "dave")] {
+            assert_eq!(
+                count(1),
+                stmt.execute(&mut db, &[Value::Int(id), bytes(name)])
+                    .unwrap()
+            );
+        }
+        let ids = |db: &mut Database<Bufmgr>, sql: &str| -> Vec<_> {
+            let rows = db.query(sql, &[]).unwrap();
+            rows.into_iter().map(|row| row[0].clone()).collect()
+        };
+        assert_eq!(
+            vec![Value::Int(2)],
+            ids(&mut db, "SELECT id FROM people WHERE name = 'bobby'")
+        );
+        assert!(ids(&mut db, "SELECT id FROM people WHERE name = 'bob'").is_empty());
+        assert!(ids(&mut db, "SELECT id FROM people WHERE name = 'robert'").is_empty());
+        assert_eq!(
+            vec![Value::Int(2)],
+            ids(&mut db, "SELECT id FROM people WHERE visits = 3")
+        );
+        assert_eq!(
+            vec![Value::Int(1), Value::Int(3), Value::Int(4)],
+            ids(&mut db, "SELECT id FROM people WHERE visits = 1")
+        );
+        // 書き換えた値が一意のインデックスで他の行と重なれば失敗する
+        assert!(stmt
+            .execute(&mut db, &[Value::Int(4), bytes("alice")])
+            .is_err());
+        assert_eq!(
+            vec![Value::Int(4)],
+            ids(&mut db, "SELECT id FROM people WHERE name = 'dave'")
+        );
+        // 後の行で失敗すれば、前の行で書き換えた行も元に戻す
+        assert!(db
+            .query(
+                "INSERT INTO people VALUES (1, 'zed', 1), (5, 'bobby', 1) ON CONFLICT (id) \
+                 DO UPDATE SET name = excluded.name",
+                &[]
+            )
+            .is_err());
+        assert_eq!(
+            vec![Value::Int(1)],
+            ids(&mut db, "SELECT id FROM people WHERE name = 'alice'")
+        );
+        assert!(ids(&mut db, "SELECT id FROM people WHERE name = 'zed'").is_empty());
+        assert_eq!(
+            vec![Value::Int(1), Value::Int(2), Value::Int(3), Value::Int(4)],
+            ids(&mut db, "SELECT id FROM people")
+        );
+    }
+}
@@ -1,91 +1,962 @@
+use std::convert::TryInto;
 use std::fs::{File, OpenOptions};
-use std::io::{prelude::*, Result, SeekFrom};
+use std::io::{self, prelude::*, Error, ErrorKind, Result, SeekFrom};
 use std::path::Path;
+use std::time::Instant;
 
-use crate::buffer::entity::PAGE_SIZE; // TODO: コンストラクタから貰いたい
-use crate::storage::{entity::PageId, manager::*};
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::storage::{
+    checksum,
+    entity::{IoStats, PageId},
+    manager::{self, StorageManager},
+};
+
+// 解放済みページの先頭に書き込む印 (続けて次の解放済みページの ID を書く)
+const FREE_PAGE_MARK: [u8; 8] = *b"FREEPAGE";
+
+// ファイルヘッダの識別子とフォーマットのバージョン
+// (3 からテーブルの値の先頭に TupleHeader を置く)
+// (4 から空か 0 で始まる BYTES の値の前に 0 を足して NULL と区別する)
+pub const MAGIC: [u8; 8] = *b"MINIDB\0\0";
+pub const FORMAT_VERSION: u64 = 4;
+// ページ 0 はファイルヘッダ用に予約している
+pub const HEADER_PAGE_ID: PageId = PageId(0);
+// ページサイズの下限 (O_DIRECT で読み書きできるセクタの大きさ)
+pub const MIN_PAGE_SIZE: usize = 512;
+// ページサイズに合わせて揃える境界の上限
+const MAX_ALIGN: usize = 4096;
+// バックアップ中にチェックサムの合わないページを読み直す回数
+const BACKUP_RETRIES: usize = 8;
+
+#[derive(Debug, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct FileHeader {
+    pub magic: [u8; 8],
+    pub version: u64,
+    pub page_size: u64,
+    // カタログのルートとなるメタページ
+    pub catalog_root_page_id: PageId,
+    // 解放済みページのリストの先頭 (各ページが次のページの ID を持つ)
+    pub free_list_head: PageId,
+}
+
+impl FileHeader {
+    pub(crate) fn new(page_size: usize) -> Self {
+        Self {
+            magic: MAGIC,
+            version: FORMAT_VERSION,
+            page_size: page_size as u64,
+            catalog_root_page_id: PageId::INVALID_PAGE_ID,
+            free_list_head: PageId::INVALID_PAGE_ID,
+        }
+    }
+
+    // page_size はこのファイルを使うバッファプールのページサイズ
+    pub(crate) fn validate(&self, page_size: usize) -> Result<()> {
+        if self.magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a minidb file"));
+        }
+        if self.version != FORMAT_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported format version {}", self.version),
+            ));
+        }
+        if self.page_size != page_size as u64 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "page size mismatch: file has {}, expected {}",
+                    self.page_size, page_size
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// ページサイズが MIN_PAGE_SIZE 以上の 2 の冪であることを確かめる
+fn check_page_size(page_size: usize) -> Result<()> {
+    if page_size < MIN_PAGE_SIZE || !page_size.is_power_of_two() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid page size {}", page_size),
+        ));
+    }
+    Ok(())
+}
+
+// O_DIRECT でも読み書きできるよう MAX_ALIGN の境界に揃えたバッファ
+// align には MAX_ALIGN と同じ値を書くこと
+#[repr(C, align(4096))]
+#[derive(Clone, Copy)]
+struct AlignedBlock([u8; MAX_ALIGN]);
+
+struct AlignedBuf {
+    blocks: Vec<AlignedBlock>,
+    len: usize,
+}
+
+impl AlignedBuf {
+    fn new(len: usize) -> Self {
+        let num_blocks = len.div_ceil(MAX_ALIGN);
+        Self {
+            blocks: vec![AlignedBlock([0u8; MAX_ALIGN]); num_blocks],
+            len,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        // SAFETY: AlignedBlock は u8 の配列だけからなりパディングを持たない
+        unsafe { std::slice::from_raw_parts(self.blocks.as_ptr() as *const u8, self.len) }
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        // SAFETY: AlignedBlock は u8 の配列だけからなりパディングを持たない
+        unsafe { std::slice::from_raw_parts_mut(self.blocks.as_mut_ptr() as *mut u8, self.len) }
+    }
+}
+
+// sync でどこまで永続化を待つか
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    // データとメタデータを fsync する
+    #[default]
+    Always,
+    // データだけを fdatasync する
+    Fdatasync,
+    // OS のページキャッシュに渡すだけで待たない
+    OsBuffered,
+    // 何もしない (一時的なデータ向け)
+    Never,
+}
 
 pub struct DiskManager {
     // ヒープファイルのファイルディスクリプタ
     heap_file: File,
+    // ファイルヘッダ
+    header: FileHeader,
+    // ページサイズ (ヘッダに記録したものと同じ)
+    page_size: usize,
     // 採番するページを決めるカウンタ
     next_page_id: u64,
+    // 読み書きの統計
+    stats: IoStats,
+    // sync の振る舞い
+    sync_policy: SyncPolicy,
+    // ヒープファイルの大きさの上限 (バイト数、ヘッダを含む)
+    max_size: Option<u64>,
 }
 
 impl DiskManager {
-    pub fn new(heap_file: File) -> Result<Self> {
+    // page_size はこのファイルを使うバッファプールのページサイズ
+    // 既存のファイルに記録されたページサイズと違っていれば開けない
+    pub fn new(mut heap_file: File, page_size: usize) -> Result<Self> {
+        check_page_size(page_size)?;
         let heap_file_size = heap_file.metadata()?.len();
-        let next_page_id = heap_file_size / PAGE_SIZE as u64;
+        let header = if heap_file_size == 0 {
+            // 新しいファイルにはヘッダを書き込む
+            let header = FileHeader::new(page_size);
+            write_header(&mut heap_file, &header, page_size)?;
+            header
+        } else {
+            read_header(&mut heap_file, page_size)?
+        };
+        let next_page_id = std::cmp::max(heap_file_size / page_size as u64, 1);
         Ok(Self {
             heap_file,
+            header,
+            page_size,
             next_page_id,
+            stats: IoStats::default(),
+            sync_policy: SyncPolicy::default(),
+            max_size: None,
         })
     }
 
-    pub fn open(heap_file_path: impl AsRef<Path>) -> Result<Self> {
+    pub fn open(heap_file_path: impl AsRef<Path>, page_size: usize) -> Result<Self> {
         let heap_file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(heap_file_path)?;
-        Self::new(heap_file)
+        Self::new(heap_file, page_size)
+    }
+
+    // OS のページキャッシュを通さない (O_DIRECT) でヒープファイルを開く
+    // バッファプールのサイズを変えた実験で OS 側のキャッシュに邪魔されなくなる
+    #[cfg(target_os = "linux")]
+    pub fn open_direct(heap_file_path: impl AsRef<Path>, page_size: usize) -> Result<Self> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let heap_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .custom_flags(libc::O_DIRECT)
+            .open(heap_file_path)?;
+        Self::new(heap_file, page_size)
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    // page_size と違う大きさのバッファでは読み書きしない
+    fn check_len(&self, len: usize) -> manager::Result<()> {
+        if len != self.page_size {
+            let message = format!("expected a {}-byte page but got {}", self.page_size, len);
+            return Err(Error::new(ErrorKind::InvalidInput, message).into());
+        }
+        Ok(())
+    }
+
+    pub fn sync_policy(&self) -> SyncPolicy {
+        self.sync_policy
+    }
+
+    // 一括ロードなどで永続性より速さを取りたいときに sync の振る舞いを変える
+    pub fn set_sync_policy(&mut self, sync_policy: SyncPolicy) {
+        self.sync_policy = sync_policy;
+    }
+
+    pub fn max_size(&self) -> Option<u64> {
+        self.max_size
+    }
+
+    // ヒープファイルの大きさに上限を設ける
+    // 上限を超えるページは割り当てずに SizeLimitExceeded を返すので、
+    // 暴走した一括ロードでディスクを使い切ることがない
+    pub fn set_max_size(&mut self, max_size: Option<u64>) {
+        self.max_size = max_size;
+    }
+
+    // これまでの読み書きの統計
+    pub fn stats(&self) -> IoStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = IoStats::default();
+    }
+
+    pub fn catalog_root_page_id(&self) -> Option<PageId> {
+        self.header.catalog_root_page_id.valid()
+    }
+
+    // カタログのルートを記録してヘッダを書き直す
+    pub fn set_catalog_root_page_id(&mut self, page_id: PageId) -> Result<()> {
+        self.header.catalog_root_page_id = page_id;
+        write_header(&mut self.heap_file, &self.header, self.page_size)
+    }
+
+    // ヒープファイルの全ページを dest にコピーし、コピーしたページ数を返す
+    // バッファプールのダーティページは含まれないので、先に checkpoint しておくこと
+    pub fn backup(&mut self, dest: impl Write) -> Result<u64> {
+        self.heap_file.flush()?;
+        copy_pages(&mut self.heap_file, self.page_size, dest)
+    }
+
+    // ヒープファイルの一貫したコピーを path に作る
+    // reflink できるファイルシステムではブロックを共有するのですぐに終わり、
+    // できなければ copy_file_range (std::io::copy) で、それも駄目ならページごとにコピーする
+    // バッファプールのダーティページは含まれないので、先に checkpoint しておくこと
+    pub fn snapshot_to(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.heap_file.sync_all()?;
+        let mut dest = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        if reflink(&self.heap_file, &dest).is_err() {
+            self.heap_file.seek(SeekFrom::Start(0))?;
+            if io::copy(&mut self.heap_file, &mut dest).is_err() {
+                // O_DIRECT で開いている場合などは揃えたバッファで読み直す
+                dest.set_len(0)?;
+                dest.seek(SeekFrom::Start(0))?;
+                copy_pages(&mut self.heap_file, self.page_size, &mut dest)?;
+            }
+        }
+        dest.sync_all()
+    }
+}
+
+// src の中身を dest とブロックを共有する形で複製する
+#[cfg(target_os = "linux")]
+fn reflink(src: &File, dest: &File) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: どちらも開いているファイルのディスクリプタ
+    let ret = unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+    if ret == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_src: &File, _dest: &File) -> Result<()> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "reflink is not supported",
+    ))
+}
+
+// 別のプロセスやスレッドが使っているヒープファイルを開いてバックアップする
+pub fn backup_file(heap_file_path: impl AsRef<Path>, dest: impl Write) -> Result<u64> {
+    let mut heap_file = File::open(heap_file_path)?;
+    // ページサイズはヘッダに記録されたものを使う
+    let mut header = FileHeader::new(0);
+    heap_file.read_exact(header.as_bytes_mut())?;
+    copy_pages(&mut heap_file, header.page_size as usize, dest)
+}
+
+// 書き込みと重なって書きかけのページを読んだときはチェックサムが合わないので読み直す
+fn copy_pages(heap_file: &mut File, page_size: usize, mut dest: impl Write) -> Result<u64> {
+    check_page_size(page_size)?;
+    read_header(heap_file, page_size)?;
+    let num_pages = heap_file.metadata()?.len() / page_size as u64;
+    let mut page = AlignedBuf::new(page_size);
+    for page_id in 0..num_pages {
+        let mut retries = 0;
+        loop {
+            heap_file.seek(SeekFrom::Start(page_size as u64 * page_id))?;
+            heap_file.read_exact(page.as_bytes_mut())?;
+            // ヘッダのページにはチェックサムを付けていない
+            if PageId(page_id) == HEADER_PAGE_ID || checksum::verify(page.as_bytes()) {
+                break;
+            }
+            if retries == BACKUP_RETRIES {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("checksum mismatch in page {:?}", PageId(page_id)),
+                ));
+            }
+            retries += 1;
+        }
+        dest.write_all(page.as_bytes())?;
+    }
+    dest.flush()?;
+    Ok(num_pages)
+}
+
+pub(crate) fn read_header(heap_file: &mut File, page_size: usize) -> Result<FileHeader> {
+    let mut page = AlignedBuf::new(page_size);
+    heap_file.seek(SeekFrom::Start(0))?;
+    heap_file
+        .read_exact(page.as_bytes_mut())
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "file header is truncated"))?;
+    let mut header = FileHeader::new(page_size);
+    let header_len = header.as_bytes().len();
+    header
+        .as_bytes_mut()
+        .copy_from_slice(&page.as_bytes()[..header_len]);
+    header.validate(page_size)?;
+    Ok(header)
+}
+
+pub(crate) fn write_header(
+    heap_file: &mut File,
+    header: &FileHeader,
+    page_size: usize,
+) -> Result<()> {
+    let mut page = AlignedBuf::new(page_size);
+    page.as_bytes_mut()[..header.as_bytes().len()].copy_from_slice(header.as_bytes());
+    heap_file.seek(SeekFrom::Start(0))?;
+    heap_file.write_all(page.as_bytes())
+}
+
+impl DiskManager {
+    // 解放済みの印が付いていれば、次の解放済みページの ID を返す
+    fn read_free_page(&mut self, page_id: PageId) -> manager::Result<Option<PageId>> {
+        let mut data = vec![0u8; self.page_size];
+        self.read_page_data(page_id, &mut data)?;
+        let (mark, rest) = data.split_at(FREE_PAGE_MARK.len());
+        if mark != FREE_PAGE_MARK {
+            return Ok(None);
+        }
+        let next = u64::from_le_bytes(rest[..8].try_into().unwrap());
+        Ok(Some(PageId(next)))
     }
 }
 
 impl StorageManager for DiskManager {
-    fn allocate_page(&mut self) -> PageId {
+    fn page_size(&self) -> Option<usize> {
+        Some(self.page_size)
+    }
+    fn allocate_page(&mut self) -> manager::Result<PageId> {
+        // 解放済みのページがあればファイルを伸ばさずに再利用する
+        if let Some(page_id) = self.header.free_list_head.valid() {
+            let next = match self.read_free_page(page_id)? {
+                Some(next) => next,
+                None => return Err(manager::Error::Corrupt(page_id)),
+            };
+            self.header.free_list_head = next;
+            write_header(&mut self.heap_file, &self.header, self.page_size)?;
+            // 印を消して一度も書かれていないページに戻す (二重解放の検出に使うので)
+            self.heap_file
+                .seek(SeekFrom::Start(self.page_size as u64 * page_id.to_u64()))?;
+            self.heap_file
+                .write_all(AlignedBuf::new(self.page_size).as_bytes())?;
+            return Ok(page_id);
+        }
         let page_id = self.next_page_id;
+        if let Some(max_size) = self.max_size {
+            if (page_id + 1) * self.page_size as u64 > max_size {
+                return Err(manager::Error::SizeLimitExceeded(max_size));
+            }
+        }
+        // 割り当てた時点でファイルを 0 で伸ばしておく
+        // 書き出す前にクラッシュしても開き直したときに同じページ ID を採番せず、
+        // 読めば一度も書かれていない (全て 0 の) ページが返る
+        self.heap_file
+            .set_len((page_id + 1) * self.page_size as u64)?;
         self.next_page_id += 1;
-        PageId(page_id)
+        Ok(PageId(page_id))
     }
-    fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> Result<()> {
+    fn deallocate_page(&mut self, page_id: PageId) -> manager::Result<()> {
+        if page_id == HEADER_PAGE_ID || page_id.to_u64() >= self.next_page_id {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("page {:?} is not allocated", page_id),
+            )
+            .into());
+        }
+        if self.read_free_page(page_id)?.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("page {:?} is already free", page_id),
+            )
+            .into());
+        }
+        // ページに次を書いてからヘッダを書き換える
+        // 間でクラッシュしてもページを一つ失うだけで、リストは壊れない
+        let mut data = vec![0u8; self.page_size];
+        data[..FREE_PAGE_MARK.len()].copy_from_slice(&FREE_PAGE_MARK);
+        let next = self.header.free_list_head.to_u64().to_le_bytes();
+        data[FREE_PAGE_MARK.len()..FREE_PAGE_MARK.len() + 8].copy_from_slice(&next);
+        self.write_page_data(page_id, &data)?;
+        self.header.free_list_head = page_id;
+        write_header(&mut self.heap_file, &self.header, self.page_size)?;
+        Ok(())
+    }
+    fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> manager::Result<()> {
         // オフセットを計算
-        let offset = PAGE_SIZE as u64 * page_id.to_u64();
+        let offset = self.page_size as u64 * page_id.to_u64();
         // ページ先頭へシーク
         self.heap_file.seek(SeekFrom::Start(offset))?;
         // データを読み出す
-        self.heap_file.read_exact(data)
+        self.check_len(data.len())?;
+        let mut page = AlignedBuf::new(self.page_size);
+        self.heap_file
+            .read_exact(page.as_bytes_mut())
+            .map_err(|err| match err.kind() {
+                ErrorKind::UnexpectedEof => manager::Error::PastEof(page_id),
+                _ => err.into(),
+            })?;
+        data.copy_from_slice(page.as_bytes());
+        self.stats.num_reads += 1;
+        self.stats.bytes_read += self.page_size as u64;
+        // チェックサムを検証する
+        if !checksum::verify(data) {
+            return Err(manager::Error::Corrupt(page_id));
+        }
+        Ok(())
     }
-    fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+    fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> manager::Result<()> {
         // オフセットを計算
-        let offset = PAGE_SIZE as u64 * page_id.to_u64();
+        let offset = self.page_size as u64 * page_id.to_u64();
         // ページ先頭へシーク
         self.heap_file.seek(SeekFrom::Start(offset))?;
-        // データを書きこむ
-        self.heap_file.write_all(data)
+        // チェックサムを付けてデータを書きこむ
+        self.check_len(data.len())?;
+        let mut page = AlignedBuf::new(self.page_size);
+        page.as_bytes_mut().copy_from_slice(data);
+        checksum::stamp(page.as_bytes_mut());
+        self.heap_file.write_all(page.as_bytes())?;
+        self.stats.num_writes += 1;
+        self.stats.bytes_written += self.page_size as u64;
+        Ok(())
     }
-    fn sync(&mut self) -> Result<()> {
-        self.heap_file.flush()?;
-        self.heap_file.sync_all()
+    fn read_pages_data(
+        &mut self,
+        first_page_id: PageId,
+        bufs: &mut [&mut [u8]],
+    ) -> manager::Result<()> {
+        // 連続するページは一度のシークと読み出しで済ませる
+        for buf in bufs.iter() {
+            self.check_len(buf.len())?;
+        }
+        let offset = self.page_size as u64 * first_page_id.to_u64();
+        self.heap_file.seek(SeekFrom::Start(offset))?;
+        let mut data = AlignedBuf::new(self.page_size * bufs.len());
+        match self.heap_file.read_exact(data.as_bytes_mut()) {
+            Ok(()) => {}
+            // 末尾を越えるページが含まれていればページごとに読んでどこで越えたかを返す
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                for (i, buf) in bufs.iter_mut().enumerate() {
+                    self.read_page_data(PageId(first_page_id.to_u64() + i as u64), buf)?;
+                }
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        }
+        self.stats.num_reads += 1;
+        self.stats.bytes_read += data.as_bytes().len() as u64;
+        for (i, (chunk, buf)) in data
+            .as_bytes()
+            .chunks(self.page_size)
+            .zip(bufs.iter_mut())
+            .enumerate()
+        {
+            if !checksum::verify(chunk) {
+                return Err(manager::Error::Corrupt(PageId(
+                    first_page_id.to_u64() + i as u64,
+                )));
+            }
+            buf.copy_from_slice(chunk);
+        }
+        Ok(())
+    }
+    fn write_pages_data(&mut self, first_page_id: PageId, pages: &[&[u8]]) -> manager::Result<()> {
+        // 連続するページは一度のシークと書き込みで済ませる
+        for page in pages {
+            self.check_len(page.len())?;
+        }
+        let offset = self.page_size as u64 * first_page_id.to_u64();
+        self.heap_file.seek(SeekFrom::Start(offset))?;
+        let mut data = AlignedBuf::new(self.page_size * pages.len());
+        for (chunk, page) in data.as_bytes_mut().chunks_mut(self.page_size).zip(pages) {
+            chunk.copy_from_slice(page);
+            checksum::stamp(chunk);
+        }
+        self.heap_file.write_all(data.as_bytes())?;
+        self.stats.num_writes += 1;
+        self.stats.bytes_written += data.as_bytes().len() as u64;
+        Ok(())
+    }
+    fn sync(&mut self) -> manager::Result<()> {
+        let start = Instant::now();
+        match self.sync_policy {
+            SyncPolicy::Always => {
+                self.heap_file.flush()?;
+                self.heap_file.sync_all()?;
+            }
+            SyncPolicy::Fdatasync => self.heap_file.sync_data()?,
+            SyncPolicy::OsBuffered => {
+                self.heap_file.flush()?;
+                return Ok(());
+            }
+            SyncPolicy::Never => return Ok(()),
+        }
+        self.stats.num_syncs += 1;
+        self.stats.sync_duration += start.elapsed();
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::buffer::entity::{PAGE_BODY_SIZE, PAGE_SIZE};
+    use crate::storage::entity::CHECKSUM_SIZE;
+
     #[test]
     fn unit_test() {
         use super::{DiskManager, *};
         use tempfile::NamedTempFile;
 
         let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
-        let mut disk = DiskManager::new(data_file).unwrap();
+        let mut disk = DiskManager::new(data_file, PAGE_SIZE).unwrap();
         let mut hello = Vec::with_capacity(PAGE_SIZE);
         hello.extend_from_slice(b"hello");
         hello.resize(PAGE_SIZE, 0);
-        let hello_page_id = disk.allocate_page();
+        let hello_page_id = disk.allocate_page().unwrap();
         disk.write_page_data(hello_page_id, &hello).unwrap();
         let mut world = Vec::with_capacity(PAGE_SIZE);
         world.extend_from_slice(b"world");
         world.resize(PAGE_SIZE, 0);
-        let world_page_id = disk.allocate_page();
+        let world_page_id = disk.allocate_page().unwrap();
         disk.write_page_data(world_page_id, &world).unwrap();
         drop(disk);
-        let mut disk2 = DiskManager::open(&data_file_path).unwrap();
+        let mut disk2 = DiskManager::open(&data_file_path, PAGE_SIZE).unwrap();
         let mut buf = vec![0; PAGE_SIZE];
         disk2.read_page_data(hello_page_id, &mut buf).unwrap();
-        assert_eq!(hello, buf);
+        assert_eq!(hello[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
         disk2.read_page_data(world_page_id, &mut buf).unwrap();
-        assert_eq!(world, buf);
+        assert_eq!(world[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+    }
+
+    #[test]
+    fn deallocate_page_test() {
+        use super::{DiskManager, *};
+        use tempfile::NamedTempFile;
+
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let mut disk = DiskManager::new(data_file, PAGE_SIZE).unwrap();
+        let page_ids: Vec<_> = (0..4).map(|_| disk.allocate_page().unwrap()).collect();
+        for &page_id in &page_ids {
+            disk.write_page_data(page_id, &[0u8; PAGE_SIZE]).unwrap();
+        }
+        disk.deallocate_page(page_ids[1]).unwrap();
+        assert_eq!(page_ids[1], disk.allocate_page().unwrap());
+        disk.write_page_data(page_ids[1], &[0u8; PAGE_SIZE])
+            .unwrap();
+        disk.deallocate_page(page_ids[2]).unwrap();
+        disk.deallocate_page(page_ids[0]).unwrap();
+        drop(disk);
+
+        // 開き直してもフリーリストは失われない
+        let mut disk2 = DiskManager::open(&data_file_path, PAGE_SIZE).unwrap();
+        let mut reused = vec![
+            disk2.allocate_page().unwrap(),
+            disk2.allocate_page().unwrap(),
+        ];
+        reused.sort();
+        assert_eq!(vec![page_ids[0], page_ids[2]], reused);
+        assert_eq!(PageId(5), disk2.allocate_page().unwrap());
+
+        // 解放済みのページや割り当てていないページは解放できない
+        disk2.deallocate_page(page_ids[1]).unwrap();
+        assert!(disk2.deallocate_page(page_ids[1]).is_err());
+        assert!(disk2.deallocate_page(PageId(100)).is_err());
+        assert!(disk2.deallocate_page(HEADER_PAGE_ID).is_err());
+        assert_eq!(page_ids[1], disk2.allocate_page().unwrap());
+        // 再利用したページは一度も書かれていないページとして読める
+        let mut buf = vec![0xAB; PAGE_SIZE];
+        disk2.read_page_data(page_ids[1], &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+        assert_eq!(PageId(6), disk2.allocate_page().unwrap());
+    }
+
+    #[test]
+    fn zero_fill_test() {
+        use super::{DiskManager, *};
+        use tempfile::NamedTempFile;
+
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let mut disk = DiskManager::new(data_file, PAGE_SIZE).unwrap();
+        let page_id = disk.allocate_page().unwrap();
+        // 書き出す前にクラッシュした
+        drop(disk);
+
+        let mut disk2 = DiskManager::open(&data_file_path, PAGE_SIZE).unwrap();
+        let mut buf = vec![0xAB; PAGE_SIZE];
+        disk2.read_page_data(page_id, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+        // 割り当て済みのページ ID は採番し直さない
+        assert_eq!(PageId(page_id.to_u64() + 1), disk2.allocate_page().unwrap());
+    }
+
+    #[test]
+    fn max_size_test() {
+        use super::{DiskManager, *};
+        use tempfile::tempfile;
+
+        let mut disk = DiskManager::new(tempfile().unwrap(), PAGE_SIZE).unwrap();
+        // ヘッダと 2 ページ分まで
+        disk.set_max_size(Some(3 * PAGE_SIZE as u64));
+        let page_ids: Vec<_> = (0..2).map(|_| disk.allocate_page().unwrap()).collect();
+        let res_err = disk.allocate_page();
+        assert!(matches!(
+            res_err,
+            Err(manager::Error::SizeLimitExceeded(size)) if size == 3 * PAGE_SIZE as u64
+        ));
+        // 解放したページは上限に関わらず再利用できる
+        disk.deallocate_page(page_ids[0]).unwrap();
+        assert_eq!(page_ids[0], disk.allocate_page().unwrap());
+        // 上限を外せばまた伸ばせる
+        disk.set_max_size(None);
+        assert_eq!(PageId(3), disk.allocate_page().unwrap());
+    }
+
+    #[test]
+    fn header_test() {
+        use super::{DiskManager, *};
+        use tempfile::NamedTempFile;
+
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let mut disk = DiskManager::new(data_file, PAGE_SIZE).unwrap();
+        // ページ 0 はヘッダ用
+        assert_eq!(PageId(1), disk.allocate_page().unwrap());
+        assert_eq!(None, disk.catalog_root_page_id());
+        disk.set_catalog_root_page_id(PageId(1)).unwrap();
+        drop(disk);
+
+        let disk2 = DiskManager::open(&data_file_path, PAGE_SIZE).unwrap();
+        assert_eq!(Some(PageId(1)), disk2.catalog_root_page_id());
+        drop(disk2);
+
+        // minidb のファイルではないものは開けない
+        let (mut garbage, garbage_path) = NamedTempFile::new().unwrap().into_parts();
+        garbage.write_all(&[0xAB; PAGE_SIZE]).unwrap();
+        drop(garbage);
+        let res_err = DiskManager::open(&garbage_path, PAGE_SIZE);
+        assert_eq!(
+            ErrorKind::InvalidData,
+            res_err.err().map(|err| err.kind()).unwrap()
+        );
+    }
+
+    #[test]
+    fn page_size_test() {
+        use super::{DiskManager, *};
+        use tempfile::NamedTempFile;
+
+        let page_size = PAGE_SIZE * 2;
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let mut disk = DiskManager::new(data_file, page_size).unwrap();
+        assert_eq!(page_size, disk.page_size());
+        let page = vec![42u8; page_size];
+        let page_id = disk.allocate_page().unwrap();
+        disk.write_page_data(page_id, &page).unwrap();
+        drop(disk);
+
+        let mut disk2 = DiskManager::open(&data_file_path, page_size).unwrap();
+        let mut buf = vec![0u8; page_size];
+        disk2.read_page_data(page_id, &mut buf).unwrap();
+        assert_eq!(
+            page[..page_size - CHECKSUM_SIZE],
+            buf[..page_size - CHECKSUM_SIZE]
+        );
+        assert_eq!(PageId(2), disk2.allocate_page().unwrap());
+        drop(disk2);
+
+        // 記録されたページサイズと違うバッファプールからは開けない
+        let res_err = DiskManager::open(&data_file_path, PAGE_SIZE);
+        assert_eq!(
+            ErrorKind::InvalidData,
+            res_err.err().map(|err| err.kind()).unwrap()
+        );
+        let res_err = DiskManager::open(&data_file_path, 1000);
+        assert_eq!(
+            ErrorKind::InvalidInput,
+            res_err.err().map(|err| err.kind()).unwrap()
+        );
+
+        // ページサイズと違う大きさのバッファでは読み書きしない
+        let mut disk = DiskManager::open(&data_file_path, page_size).unwrap();
+        assert!(disk.write_page_data(page_id, &[0u8; PAGE_SIZE]).is_err());
+        assert!(disk.read_page_data(page_id, &mut [0u8; PAGE_SIZE]).is_err());
+    }
+
+    #[test]
+    fn stats_test() {
+        use super::{DiskManager, *};
+        use tempfile::tempfile;
+
+        let mut disk = DiskManager::new(tempfile().unwrap(), PAGE_SIZE).unwrap();
+        let page = vec![1u8; PAGE_SIZE];
+        let page_ids: Vec<_> = (0..3).map(|_| disk.allocate_page().unwrap()).collect();
+        disk.write_page_data(page_ids[0], &page).unwrap();
+        disk.write_pages_data(page_ids[1], &[&page, &page]).unwrap();
+        let mut buf = vec![0u8; PAGE_SIZE];
+        disk.read_page_data(page_ids[2], &mut buf).unwrap();
+        disk.sync().unwrap();
+
+        let stats = disk.stats();
+        assert_eq!(1, stats.num_reads);
+        assert_eq!(2, stats.num_writes);
+        assert_eq!(PAGE_SIZE as u64, stats.bytes_read);
+        assert_eq!(3 * PAGE_SIZE as u64, stats.bytes_written);
+        assert_eq!(1, stats.num_syncs);
+        disk.reset_stats();
+        assert_eq!(IoStats::default(), disk.stats());
+    }
+
+    #[test]
+    fn sync_policy_test() {
+        use super::{DiskManager, *};
+        use tempfile::tempfile;
+
+        let mut disk = DiskManager::new(tempfile().unwrap(), PAGE_SIZE).unwrap();
+        assert_eq!(SyncPolicy::Always, disk.sync_policy());
+        disk.sync().unwrap();
+        disk.set_sync_policy(SyncPolicy::Fdatasync);
+        disk.sync().unwrap();
+        // fsync しないポリシーでは sync の回数に数えない
+        disk.set_sync_policy(SyncPolicy::OsBuffered);
+        disk.sync().unwrap();
+        disk.set_sync_policy(SyncPolicy::Never);
+        disk.sync().unwrap();
+        assert_eq!(2, disk.stats().num_syncs);
+    }
+
+    #[test]
+    fn checksum_test() {
+        use super::{DiskManager, *};
+        use tempfile::NamedTempFile;
+
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let mut disk = DiskManager::new(data_file, PAGE_SIZE).unwrap();
+        let page_id = disk.allocate_page().unwrap();
+        disk.write_page_data(page_id, &[42u8; PAGE_SIZE]).unwrap();
+        drop(disk);
+
+        // ページの途中を書き換えて壊す
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&data_file_path)
+            .unwrap();
+        file.seek(SeekFrom::Start(PAGE_SIZE as u64 * page_id.to_u64() + 100))
+            .unwrap();
+        file.write_all(&[0u8]).unwrap();
+        drop(file);
+
+        let mut disk2 = DiskManager::open(&data_file_path, PAGE_SIZE).unwrap();
+        let mut buf = vec![0; PAGE_SIZE];
+        let res_err = disk2.read_page_data(page_id, &mut buf);
+        assert!(matches!(res_err, Err(manager::Error::Corrupt(id)) if id == page_id));
+        // 書き出されていないページはファイルの末尾を越える
+        let res_err = disk2.read_page_data(PageId(42), &mut buf);
+        assert!(matches!(res_err, Err(manager::Error::PastEof(PageId(42)))));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn direct_io_test() {
+        use super::{DiskManager, *};
+        use tempfile::NamedTempFile;
+
+        let (_, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let mut disk = match DiskManager::open_direct(&data_file_path, PAGE_SIZE) {
+            Ok(disk) => disk,
+            // O_DIRECT に対応していないファイルシステム
+            Err(err) if err.kind() == ErrorKind::InvalidInput => return,
+            Err(err) => panic!("{}", err),
+        };
+        let page_ids: Vec<_> = (0..3).map(|_| disk.allocate_page().unwrap()).collect();
+        let pages: Vec<_> = (0..3u8).map(|n| vec![n; PAGE_SIZE]).collect();
+        let data: Vec<&[u8]> = pages.iter().map(|page| page.as_slice()).collect();
+        disk.write_pages_data(page_ids[0], &data).unwrap();
+        disk.write_page_data(page_ids[1], &pages[0]).unwrap();
+        disk.sync().unwrap();
+        drop(disk);
+
+        let mut disk2 = DiskManager::open_direct(&data_file_path, PAGE_SIZE).unwrap();
+        let mut buf = vec![0; PAGE_SIZE];
+        disk2.read_page_data(page_ids[1], &mut buf).unwrap();
+        assert_eq!(pages[0][..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        disk2.read_page_data(page_ids[2], &mut buf).unwrap();
+        assert_eq!(pages[2][..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+    }
+
+    #[test]
+    fn backup_test() {
+        use super::{backup_file, DiskManager, *};
+        use tempfile::NamedTempFile;
+
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let mut disk = DiskManager::new(data_file, PAGE_SIZE).unwrap();
+        let pages: Vec<_> = (0..3u8).map(|n| vec![n + 1; PAGE_SIZE]).collect();
+        let page_ids: Vec<_> = pages
+            .iter()
+            .map(|page| {
+                let page_id = disk.allocate_page().unwrap();
+                disk.write_page_data(page_id, page).unwrap();
+                page_id
+            })
+            .collect();
+        disk.set_catalog_root_page_id(page_ids[0]).unwrap();
+
+        let (mut backup, backup_path) = NamedTempFile::new().unwrap().into_parts();
+        assert_eq!(4, disk.backup(&mut backup).unwrap());
+        drop(backup);
+        let mut restored = DiskManager::open(&backup_path, PAGE_SIZE).unwrap();
+        assert_eq!(Some(page_ids[0]), restored.catalog_root_page_id());
+        let mut buf = vec![0; PAGE_SIZE];
+        for (page_id, page) in page_ids.iter().zip(pages.iter()) {
+            restored.read_page_data(*page_id, &mut buf).unwrap();
+            assert_eq!(page[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        }
+
+        // 読み直しても壊れたままのページがあればバックアップは失敗する
+        let mut heap_file = OpenOptions::new()
+            .write(true)
+            .open(&data_file_path)
+            .unwrap();
+        heap_file
+            .seek(SeekFrom::Start(PAGE_SIZE as u64 * page_ids[1].to_u64()))
+            .unwrap();
+        heap_file.write_all(b"garbage").unwrap();
+        let err = backup_file(&data_file_path, Vec::new()).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn snapshot_to_test() {
+        use super::{DiskManager, *};
+        use tempfile::{tempdir, tempfile};
+
+        let mut disk = DiskManager::new(tempfile().unwrap(), PAGE_SIZE).unwrap();
+        let hello = vec![7u8; PAGE_SIZE];
+        let page_id = disk.allocate_page().unwrap();
+        disk.write_page_data(page_id, &hello).unwrap();
+
+        let dir = tempdir().unwrap();
+        let snapshot_path = dir.path().join("snapshot.db");
+        disk.snapshot_to(&snapshot_path).unwrap();
+        // スナップショットを取った後の書き込みはスナップショットに影響しない
+        disk.write_page_data(page_id, &[8u8; PAGE_SIZE]).unwrap();
+        drop(disk);
+
+        let mut snapshot = DiskManager::open(&snapshot_path, PAGE_SIZE).unwrap();
+        let mut buf = vec![0; PAGE_SIZE];
+        snapshot.read_page_data(page_id, &mut buf).unwrap();
+        assert_eq!(hello[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+    }
+
+    #[test]
+    fn read_pages_data_test() {
+        use super::{DiskManager, *};
+        use tempfile::tempfile;
+
+        let mut disk = DiskManager::new(tempfile().unwrap(), PAGE_SIZE).unwrap();
+        let pages: Vec<_> = (0..3u8).map(|n| vec![n + 1; PAGE_SIZE]).collect();
+        let page_ids: Vec<_> = (0..4).map(|_| disk.allocate_page().unwrap()).collect();
+        let data: Vec<&[u8]> = pages.iter().map(|page| page.as_slice()).collect();
+        disk.write_pages_data(page_ids[0], &data).unwrap();
+        disk.reset_stats();
+
+        let mut bufs = vec![vec![0u8; PAGE_SIZE]; 3];
+        let mut slices: Vec<&mut [u8]> = bufs.iter_mut().map(|buf| buf.as_mut_slice()).collect();
+        disk.read_pages_data(page_ids[0], &mut slices).unwrap();
+        for (page, buf) in pages.iter().zip(bufs.iter()) {
+            assert_eq!(page[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        }
+        // 3 ページを一度に読んでいる
+        assert_eq!(1, disk.stats().num_reads);
+
+        // 割り当てたが書き出していないページは 0 で埋まっている
+        let mut bufs = vec![vec![0xABu8; PAGE_SIZE]; 2];
+        let mut slices: Vec<&mut [u8]> = bufs.iter_mut().map(|buf| buf.as_mut_slice()).collect();
+        disk.read_pages_data(page_ids[2], &mut slices).unwrap();
+        assert!(bufs[1].iter().all(|&b| b == 0));
+
+        // 割り当てていないページを含めば、そのページで末尾を越える
+        let mut slices: Vec<&mut [u8]> = bufs.iter_mut().map(|buf| buf.as_mut_slice()).collect();
+        let res_err = disk.read_pages_data(page_ids[3], &mut slices);
+        let past_eof_page_id = PageId(page_ids[3].to_u64() + 1);
+        assert!(matches!(res_err, Err(manager::Error::PastEof(id)) if id == past_eof_page_id));
+    }
+
+    #[test]
+    fn write_pages_data_test() {
+        use super::{DiskManager, *};
+        use tempfile::tempfile;
+
+        let mut disk = DiskManager::new(tempfile().unwrap(), PAGE_SIZE).unwrap();
+        let page_ids: Vec<_> = (0..3).map(|_| disk.allocate_page().unwrap()).collect();
+        let pages: Vec<_> = (0..3u8).map(|n| vec![n; PAGE_SIZE]).collect();
+        let data: Vec<&[u8]> = pages.iter().map(|page| page.as_slice()).collect();
+        disk.write_pages_data(page_ids[0], &data).unwrap();
+        let mut buf = vec![0; PAGE_SIZE];
+        for (page_id, page) in page_ids.into_iter().zip(pages) {
+            disk.read_page_data(page_id, &mut buf).unwrap();
+            assert_eq!(page[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        }
     }
 
     #[test]
@@ -96,44 +967,44 @@ mod tests {
         use crate::buffer::manager::*;
         use tempfile::tempfile;
 
-        let mut hello = Vec::with_capacity(PAGE_SIZE);
+        let mut hello = Vec::with_capacity(PAGE_BODY_SIZE);
         hello.extend_from_slice(b"hello");
-        hello.resize(PAGE_SIZE, 0);
-        let mut world = Vec::with_capacity(PAGE_SIZE);
+        hello.resize(PAGE_BODY_SIZE, 0);
+        let mut world = Vec::with_capacity(PAGE_BODY_SIZE);
         world.extend_from_slice(b"world");
-        world.resize(PAGE_SIZE, 0);
+        world.resize(PAGE_BODY_SIZE, 0);
 
-        let disk = DiskManager::new(tempfile().unwrap()).unwrap();
-        let mut bufmgr = ClockSweepManager::new(disk, 1);
+        let disk = DiskManager::new(tempfile().unwrap(), PAGE_SIZE).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 1).unwrap();
         let page1_id = {
             let buffer = bufmgr.create_page().unwrap();
             assert!(bufmgr.create_page().is_err());
-            let mut page = buffer.page.borrow_mut();
+            let mut page = buffer.body_mut();
             page.copy_from_slice(&hello);
             buffer.is_dirty.set(true);
             buffer.page_id
         };
         {
             let buffer = bufmgr.fetch_page(page1_id).unwrap();
-            let page = buffer.page.borrow();
-            assert_eq!(&hello, page.as_ref());
+            let page = buffer.body();
+            assert_eq!(hello.as_slice(), page.as_ref());
         }
         let page2_id = {
             let buffer = bufmgr.create_page().unwrap();
-            let mut page = buffer.page.borrow_mut();
+            let mut page = buffer.body_mut();
             page.copy_from_slice(&world);
             buffer.is_dirty.set(true);
             buffer.page_id
         };
         {
             let buffer = bufmgr.fetch_page(page1_id).unwrap();
-            let page = buffer.page.borrow();
-            assert_eq!(&hello, page.as_ref());
+            let page = buffer.body();
+            assert_eq!(hello.as_slice(), page.as_ref());
         }
         {
             let buffer = bufmgr.fetch_page(page2_id).unwrap();
-            let page = buffer.page.borrow();
-            assert_eq!(&world, page.as_ref());
+            let page = buffer.body();
+            assert_eq!(world.as_slice(), page.as_ref());
         }
     }
 }
@@ -0,0 +1,439 @@
+use std::cell::RefCell;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::sql::dml::entity::{Row, Value};
+
+// 比較 (同じ型の値どうしを比べる)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// 整数の四則演算
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+// 行に対して評価する式
+// NULL を含む比較や演算は NULL になり、論理演算は三値論理に従う
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Expr {
+    Column(usize),
+    Literal(Value),
+    Compare(CmpOp, Box<Expr>, Box<Expr>),
+    Arith(ArithOp, Box<Expr>, Box<Expr>),
+    // 空なら真
+    And(Vec<Expr>),
+    // 空なら偽
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    // NULL なら真 (NULL にはならない)
+    IsNull(Box<Expr>),
+    // with_params で束縛した値 (相関副問合せで外側の行から渡す)
+    Param(usize),
+}
+
+thread_local! {
+    static PARAMS: RefCell<Vec<Value>> = const { RefCell::new(Vec::new()) };
+}
+
+// f を実行する間だけ Expr::Param が params を参照するようにする
+pub fn with_params<R>(params: Vec<Value>, f: impl FnOnce() -> R) -> R {
+    let saved = PARAMS.with(|bound| bound.replace(params));
+    let result = f();
+    PARAMS.with(|bound| bound.replace(saved));
+    result
+}
+
+// 今束縛している値 (子の実行器を後で作り直すときに同じ値を束縛し直す)
+pub fn current_params() -> Vec<Value> {
+    PARAMS.with(|bound| bound.borrow().clone())
+}
+
+// AND を平らにして一つずつの述語にする (真のリテラルは除く)
+pub fn conjuncts(expr: &Expr, out: &mut Vec<Expr>) {
+    match expr {
+        Expr::And(exprs) => exprs.iter().for_each(|expr| conjuncts(expr, out)),
+        expr if *expr == Expr::TRUE => {}
+        expr => out.push(expr.clone()),
+    }
+}
+
+pub fn conjoin(mut exprs: Vec<Expr>) -> Expr {
+    match exprs.len() {
+        0 => Expr::TRUE,
+        1 => exprs.pop().unwrap(),
+        _ => Expr::And(exprs),
+    }
+}
+
+impl Expr {
+    pub const TRUE: Expr = Expr::Literal(Value::Bool(true));
+
+    pub fn compare(op: CmpOp, lhs: Expr, rhs: Expr) -> Self {
+        Expr::Compare(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    pub fn arith(op: ArithOp, lhs: Expr, rhs: Expr) -> Self {
+        Expr::Arith(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    pub fn eval(&self, row: &Row) -> Result<Value> {
+        match self {
+            Expr::Column(column) => row.get(*column),
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Compare(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(row)?, rhs.eval(row)?);
+                if lhs.is_null() || rhs.is_null() {
+                    return Ok(Value::Null);
+                }
+                let ord = lhs.try_cmp(&rhs)?;
+                let result = match op {
+                    CmpOp::Eq => ord.is_eq(),
+                    CmpOp::Ne => ord.is_ne(),
+                    CmpOp::Lt => ord.is_lt(),
+                    CmpOp::Le => ord.is_le(),
+                    CmpOp::Gt => ord.is_gt(),
+                    CmpOp::Ge => ord.is_ge(),
+                };
+                Ok(Value::Bool(result))
+            }
+            Expr::Arith(op, lhs, rhs) => {
+                let (lhs, rhs) = match (lhs.eval(row)?, rhs.eval(row)?) {
+                    (Value::Int(lhs), Value::Int(rhs)) => (lhs, rhs),
+                    (Value::Null, _) | (_, Value::Null) => return Ok(Value::Null),
+                    (lhs, rhs) => bail!("cannot apply {:?} to {:?} and {:?}", op, lhs, rhs),
+                };
+                let result = match op {
+                    ArithOp::Add => lhs.checked_add(rhs),
+                    ArithOp::Sub => lhs.checked_sub(rhs),
+                    ArithOp::Mul => lhs.checked_mul(rhs),
+                    ArithOp::Div => lhs.checked_div(rhs),
+                };
+                match result {
+                    Some(result) => Ok(Value::Int(result)),
+                    None => bail!("{:?} of {} and {} is out of range", op, lhs, rhs),
+                }
+            }
+            Expr::And(exprs) => {
+                let mut unknown = false;
+                for expr in exprs {
+                    match expr.truth(row)? {
+                        Some(false) => return Ok(Value::Bool(false)),
+                        Some(true) => {}
+                        None => unknown = true,
+                    }
+                }
+                Ok(if unknown {
+                    Value::Null
+                } else {
+                    Value::Bool(true)
+                })
+            }
+            Expr::Or(exprs) => {
+                let mut unknown = false;
+                for expr in exprs {
+                    match expr.truth(row)? {
+                        Some(true) => return Ok(Value::Bool(true)),
+                        Some(false) => {}
+                        None => unknown = true,
+                    }
+                }
+                Ok(if unknown {
+                    Value::Null
+                } else {
+                    Value::Bool(false)
+                })
+            }
+            Expr::Not(expr) => Ok(match expr.truth(row)? {
+                Some(result) => Value::Bool(!result),
+                None => Value::Null,
+            }),
+            Expr::IsNull(expr) => Ok(Value::Bool(expr.eval(row)?.is_null())),
+            Expr::Param(index) => match PARAMS.with(|bound| bound.borrow().get(*index).cloned()) {
+                Some(value) => Ok(value),
+                None => bail!("parameter {} is not bound", index),
+            },
+        }
+    }
+
+    // Expr::Param を今束縛している値に置き換える
+    // 実行器を作るときに置き換えておけば、with_params を抜けた後も同じ値で評価できる
+    pub fn bind_params(&self) -> Result<Expr> {
+        let bind_all = |exprs: &[Expr]| exprs.iter().map(Expr::bind_params).collect::<Result<_>>();
+        Ok(match self {
+            Expr::Column(_) | Expr::Literal(_) => self.clone(),
+            Expr::Compare(op, lhs, rhs) => {
+                Expr::compare(*op, lhs.bind_params()?, rhs.bind_params()?)
+            }
+            Expr::Arith(op, lhs, rhs) => Expr::arith(*op, lhs.bind_params()?, rhs.bind_params()?),
+            Expr::And(exprs) => Expr::And(bind_all(exprs)?),
+            Expr::Or(exprs) => Expr::Or(bind_all(exprs)?),
+            Expr::Not(expr) => Expr::Not(Box::new(expr.bind_params()?)),
+            Expr::IsNull(expr) => Expr::IsNull(Box::new(expr.bind_params()?)),
+            Expr::Param(_) => Expr::Literal(self.eval(&Row::from_values(vec![]))?),
+        })
+    }
+
+    // 参照している列を out に加える
+    pub fn columns(&self, out: &mut Vec<usize>) {
+        match self {
+            Expr::Column(column) => out.push(*column),
+            Expr::Literal(_) | Expr::Param(_) => {}
+            Expr::Compare(_, lhs, rhs) | Expr::Arith(_, lhs, rhs) => {
+                lhs.columns(out);
+                rhs.columns(out);
+            }
+            Expr::And(exprs) | Expr::Or(exprs) => exprs.iter().for_each(|expr| expr.columns(out)),
+            Expr::Not(expr) | Expr::IsNull(expr) => expr.columns(out),
+        }
+    }
+
+    // 列の位置を f で付け替える (入力の行の列の並びが変わるとき)
+    pub fn map_columns(&self, f: &impl Fn(usize) -> usize) -> Expr {
+        match self {
+            Expr::Column(column) => Expr::Column(f(*column)),
+            Expr::Literal(_) | Expr::Param(_) => self.clone(),
+            Expr::Compare(op, lhs, rhs) => {
+                Expr::compare(*op, lhs.map_columns(f), rhs.map_columns(f))
+            }
+            Expr::Arith(op, lhs, rhs) => Expr::arith(*op, lhs.map_columns(f), rhs.map_columns(f)),
+            Expr::And(exprs) => Expr::And(exprs.iter().map(|expr| expr.map_columns(f)).collect()),
+            Expr::Or(exprs) => Expr::Or(exprs.iter().map(|expr| expr.map_columns(f)).collect()),
+            Expr::Not(expr) => Expr::Not(Box::new(expr.map_columns(f))),
+            Expr::IsNull(expr) => Expr::IsNull(Box::new(expr.map_columns(f))),
+        }
+    }
+
+    // Column(i) を exprs[i] に置き換える (射影の上の式を射影の下の列で書き直す)
+    pub fn substitute(&self, exprs: &[Expr]) -> Expr {
+        let all = |operands: &[Expr]| operands.iter().map(|expr| expr.substitute(exprs)).collect();
+        match self {
+            Expr::Column(column) => exprs[*column].clone(),
+            Expr::Literal(_) | Expr::Param(_) => self.clone(),
+            Expr::Compare(op, lhs, rhs) => {
+                Expr::compare(*op, lhs.substitute(exprs), rhs.substitute(exprs))
+            }
+            Expr::Arith(op, lhs, rhs) => {
+                Expr::arith(*op, lhs.substitute(exprs), rhs.substitute(exprs))
+            }
+            Expr::And(operands) => Expr::And(all(operands)),
+            Expr::Or(operands) => Expr::Or(all(operands)),
+            Expr::Not(expr) => Expr::Not(Box::new(expr.substitute(exprs))),
+            Expr::IsNull(expr) => Expr::IsNull(Box::new(expr.substitute(exprs))),
+        }
+    }
+
+    // 列もパラメータも見ない部分を評価してリテラルにし、AND と OR の決まった項を除く
+    // 評価すると失敗するもの (桁あふれなど) は実行するときに失敗するよう残す
+    pub fn fold(&self) -> Expr {
+        let folded = match self {
+            Expr::Column(_) | Expr::Literal(_) | Expr::Param(_) => return self.clone(),
+            Expr::Compare(op, lhs, rhs) => Expr::compare(*op, lhs.fold(), rhs.fold()),
+            Expr::Arith(op, lhs, rhs) => Expr::arith(*op, lhs.fold(), rhs.fold()),
+            // 偽 (OR なら真) の項があれば全体が決まり、真 (OR なら偽) の項は結果を変えない
+            Expr::And(exprs) | Expr::Or(exprs) => {
+                let is_and = matches!(self, Expr::And(_));
+                let mut operands = vec![];
+                for expr in exprs {
+                    match expr.fold() {
+                        Expr::Literal(Value::Bool(value)) if value != is_and => {
+                            return Expr::Literal(Value::Bool(value));
+                        }
+                        Expr::Literal(Value::Bool(_)) => {}
+                        expr => operands.push(expr),
+                    }
+                }
+                match (operands.len(), is_and) {
+                    (0, _) => return Expr::Literal(Value::Bool(is_and)),
+                    (1, _) => return operands.pop().unwrap(),
+                    (_, true) => Expr::And(operands),
+                    (_, false) => Expr::Or(operands),
+                }
+            }
+            Expr::Not(expr) => Expr::Not(Box::new(expr.fold())),
+            Expr::IsNull(expr) => Expr::IsNull(Box::new(expr.fold())),
+        };
+        if !folded.is_constant() {
+            return folded;
+        }
+        match folded.eval(&Row::from_values(vec![])) {
+            Ok(value) => Expr::Literal(value),
+            Err(_) => folded,
+        }
+    }
+
+    // 行にもパラメータにもよらない式か
+    fn is_constant(&self) -> bool {
+        match self {
+            Expr::Column(_) | Expr::Param(_) => false,
+            Expr::Literal(_) => true,
+            Expr::Compare(_, lhs, rhs) | Expr::Arith(_, lhs, rhs) => {
+                lhs.is_constant() && rhs.is_constant()
+            }
+            Expr::And(exprs) | Expr::Or(exprs) => exprs.iter().all(Expr::is_constant),
+            Expr::Not(expr) | Expr::IsNull(expr) => expr.is_constant(),
+        }
+    }
+
+    // 述語として評価する (NULL は偽とする)
+    pub fn is_true(&self, row: &Row) -> Result<bool> {
+        Ok(self.truth(row)?.unwrap_or(false))
+    }
+
+    // 真偽値として評価する (NULL なら None)
+    fn truth(&self, row: &Row) -> Result<Option<bool>> {
+        match self.eval(row)? {
+            Value::Bool(result) => Ok(Some(result)),
+            Value::Null => Ok(None),
+            value => bail!("expected a boolean but got {:?}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval_test() {
+        let row = Row::from_values(vec![
+            Value::Bytes(b"Bob".to_vec()),
+            Value::Int(40),
+            Value::Int(2),
+        ]);
+        // (col1 + col2) / 2 = 21
+        let avg = Expr::arith(
+            ArithOp::Div,
+            Expr::arith(ArithOp::Add, Expr::Column(1), Expr::Column(2)),
+            Expr::Literal(Value::Int(2)),
+        );
+        assert_eq!(Value::Int(21), avg.eval(&row).unwrap());
+        let cond = Expr::And(vec![
+            Expr::compare(
+                CmpOp::Eq,
+                Expr::Column(0),
+                Expr::Literal(Value::Bytes(b"Bob".to_vec())),
+            ),
+            Expr::Or(vec![
+                Expr::compare(CmpOp::Lt, avg.clone(), Expr::Literal(Value::Int(20))),
+                Expr::Not(Box::new(Expr::compare(
+                    CmpOp::Ge,
+                    Expr::Column(2),
+                    Expr::Literal(Value::Int(3)),
+                ))),
+            ]),
+        ]);
+        assert!(cond.is_true(&row).unwrap());
+        assert!(Expr::TRUE.is_true(&row).unwrap());
+        assert!(!Expr::Or(vec![]).is_true(&row).unwrap());
+        // 数は数として比べる (-1 はバイト列では 2 より大きい)
+        let negative = Expr::compare(CmpOp::Lt, Expr::Literal(Value::Int(-1)), Expr::Column(2));
+        assert!(negative.is_true(&row).unwrap());
+
+        // 直列化して戻せる
+        let bytes = bincode::serialize(&cond).unwrap();
+        assert_eq!(cond, bincode::deserialize::<Expr>(&bytes).unwrap());
+
+        // 型の合わないものや範囲外は失敗する
+        assert!(Expr::Column(0).is_true(&row).is_err());
+        assert!(Expr::Column(3).eval(&row).is_err());
+        let div_by_zero = Expr::arith(ArithOp::Div, Expr::Column(1), Expr::Literal(Value::Int(0)));
+        assert!(div_by_zero.eval(&row).is_err());
+        let not_a_number = Expr::arith(ArithOp::Add, Expr::Column(0), Expr::Column(1));
+        assert!(not_a_number.eval(&row).is_err());
+        let mismatch = Expr::compare(CmpOp::Eq, Expr::Column(0), Expr::Column(1));
+        assert!(mismatch.eval(&row).is_err());
+    }
+
+    #[test]
+    fn null_test() {
+        let row = Row::from_values(vec![Value::Int(1), Value::Null]);
+        let unknown = Expr::compare(CmpOp::Eq, Expr::Column(0), Expr::Column(1));
+        assert_eq!(Value::Null, unknown.eval(&row).unwrap());
+        assert!(!unknown.is_true(&row).unwrap());
+        assert!(!Expr::Not(Box::new(unknown.clone())).is_true(&row).unwrap());
+        let sum = Expr::arith(ArithOp::Add, Expr::Column(0), Expr::Column(1));
+        assert_eq!(Value::Null, sum.eval(&row).unwrap());
+        // 三値論理
+        let t = Expr::TRUE;
+        let f = Expr::Literal(Value::Bool(false));
+        let and = |exprs: &[&Expr]| Expr::And(exprs.iter().map(|&e| e.clone()).collect());
+        let or = |exprs: &[&Expr]| Expr::Or(exprs.iter().map(|&e| e.clone()).collect());
+        assert_eq!(Value::Null, and(&[&t, &unknown]).eval(&row).unwrap());
+        assert_eq!(Value::Bool(false), and(&[&unknown, &f]).eval(&row).unwrap());
+        assert_eq!(Value::Null, or(&[&f, &unknown]).eval(&row).unwrap());
+        assert_eq!(Value::Bool(true), or(&[&unknown, &t]).eval(&row).unwrap());
+        assert!(Expr::IsNull(Box::new(unknown)).is_true(&row).unwrap());
+        assert!(!Expr::IsNull(Box::new(Expr::Column(0)))
+            .is_true(&row)
+            .unwrap());
+    }
+
+    #[test]
+    fn param_test() {
+        let row = Row::from_values(vec![Value::Int(1)]);
+        let cond = Expr::compare(CmpOp::Eq, Expr::Column(0), Expr::Param(0));
+        assert!(cond.is_true(&row).is_err());
+        assert!(with_params(vec![Value::Int(1)], || cond.is_true(&row)).unwrap());
+        // 入れ子にしても抜けたら外側の値に戻る
+        with_params(vec![Value::Int(2)], || {
+            assert!(!cond.is_true(&row).unwrap());
+            assert!(with_params(vec![Value::Int(1)], || cond.is_true(&row)).unwrap());
+            assert!(!cond.is_true(&row).unwrap());
+        });
+        assert!(cond.is_true(&row).is_err());
+    }
+
+    #[test]
+    fn bind_params_test() {
+        let row = Row::from_values(vec![Value::Int(1)]);
+        let cond = Expr::Not(Box::new(Expr::compare(
+            CmpOp::Eq,
+            Expr::Column(0),
+            Expr::Param(0),
+        )));
+        assert!(cond.bind_params().is_err());
+        let bound = with_params(vec![Value::Int(2)], || cond.bind_params()).unwrap();
+        // 束縛を抜けても置き換えた値で評価できる
+        assert!(bound.is_true(&row).unwrap());
+        assert_eq!(
+            Expr::Not(Box::new(Expr::compare(
+                CmpOp::Eq,
+                Expr::Column(0),
+                Expr::Literal(Value::Int(2)),
+            ))),
+            bound
+        );
+    }
+
+    #[test]
+    fn columns_test() {
+        let expr = Expr::And(vec![
+            Expr::compare(CmpOp::Eq, Expr::Column(3), Expr::Param(0)),
+            Expr::IsNull(Box::new(Expr::arith(
+                ArithOp::Add,
+                Expr::Column(1),
+                Expr::Column(3),
+            ))),
+        ]);
+        let mut columns = vec![];
+        expr.columns(&mut columns);
+        assert_eq!(vec![3, 1, 3], columns);
+        let shifted = expr.map_columns(&|column| column - 1);
+        let mut columns = vec![];
+        shifted.columns(&mut columns);
+        assert_eq!(vec![2, 0, 2], columns);
+    }
+}
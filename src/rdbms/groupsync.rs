@@ -0,0 +1,172 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::storage::{
+    entity::PageId,
+    manager::{Result, StorageManager},
+};
+
+// sync を待つ時間の既定値
+pub const DEFAULT_SYNC_WINDOW: Duration = Duration::from_millis(1);
+
+#[derive(Default)]
+struct SyncState {
+    // これまでに受け付けた sync の要求の数
+    requested: u64,
+    // 永続化が済んだ要求の数
+    completed: u64,
+    // 誰かが下位層の sync を実行中か
+    syncing: bool,
+    // 実際に下位層の sync を呼んだ回数
+    num_syncs: u64,
+}
+
+struct Shared<T> {
+    inner: Mutex<T>,
+    state: Mutex<SyncState>,
+    synced: Condvar,
+    window: Duration,
+}
+
+// 複数のスレッドからの sync の要求をまとめて一度の sync で済ませる storagemanager のラッパー
+// 最初に来た要求 (リーダー) が window だけ待ってから sync し、その間に来た要求もまとめて完了させる
+// clone したハンドルを各スレッドに渡して使う
+pub struct GroupSyncStorage<T: StorageManager> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: StorageManager> Clone for GroupSyncStorage<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T: StorageManager> GroupSyncStorage<T> {
+    pub fn new(inner: T) -> Self {
+        Self::with_window(inner, DEFAULT_SYNC_WINDOW)
+    }
+
+    pub fn with_window(inner: T, window: Duration) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                inner: Mutex::new(inner),
+                state: Mutex::new(SyncState::default()),
+                synced: Condvar::new(),
+                window,
+            }),
+        }
+    }
+
+    // 受け付けた sync の要求の数
+    pub fn num_sync_requests(&self) -> u64 {
+        self.shared.state.lock().unwrap().requested
+    }
+
+    // 実際に下位層の sync を呼んだ回数
+    pub fn num_syncs(&self) -> u64 {
+        self.shared.state.lock().unwrap().num_syncs
+    }
+
+    fn with_inner<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut self.shared.inner.lock().unwrap())
+    }
+}
+
+impl<T: StorageManager> StorageManager for GroupSyncStorage<T> {
+    fn page_size(&self) -> Option<usize> {
+        self.with_inner(|inner| inner.page_size())
+    }
+
+    fn allocate_page(&mut self) -> Result<PageId> {
+        self.with_inner(|inner| inner.allocate_page())
+    }
+
+    fn deallocate_page(&mut self, page_id: PageId) -> Result<()> {
+        self.with_inner(|inner| inner.deallocate_page(page_id))
+    }
+
+    fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> Result<()> {
+        self.with_inner(|inner| inner.read_page_data(page_id, data))
+    }
+
+    fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+        self.with_inner(|inner| inner.write_page_data(page_id, data))
+    }
+
+    fn read_pages_data(&mut self, first_page_id: PageId, bufs: &mut [&mut [u8]]) -> Result<()> {
+        self.with_inner(|inner| inner.read_pages_data(first_page_id, bufs))
+    }
+
+    fn write_pages_data(&mut self, first_page_id: PageId, pages: &[&[u8]]) -> Result<()> {
+        self.with_inner(|inner| inner.write_pages_data(first_page_id, pages))
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        let shared = &self.shared;
+        let mut state = shared.state.lock().unwrap();
+        state.requested += 1;
+        let ticket = state.requested;
+        loop {
+            if state.completed >= ticket {
+                return Ok(());
+            }
+            if !state.syncing {
+                break;
+            }
+            state = shared.synced.wait(state).unwrap();
+        }
+        // リーダーとして後続の要求が集まるのを待ってからまとめて sync する
+        state.syncing = true;
+        drop(state);
+        thread::sleep(shared.window);
+        let target = shared.state.lock().unwrap().requested;
+        let result = shared.inner.lock().unwrap().sync();
+        let mut state = shared.state.lock().unwrap();
+        state.syncing = false;
+        state.num_syncs += 1;
+        // 失敗したときは完了させず、待っている要求のどれかが改めてリーダーになる
+        if result.is_ok() {
+            state.completed = target;
+        }
+        shared.synced.notify_all();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdbms::memory::MemoryManager;
+    use std::sync::Barrier;
+
+    #[test]
+    fn group_sync_test() {
+        const NUM_THREADS: usize = 8;
+        let storage =
+            GroupSyncStorage::with_window(MemoryManager::new(), Duration::from_millis(50));
+        let barrier = Arc::new(Barrier::new(NUM_THREADS));
+        let handles: Vec<_> = (0..NUM_THREADS)
+            .map(|_| {
+                let mut storage = storage.clone();
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    storage.sync().unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(NUM_THREADS as u64, storage.num_sync_requests());
+        // 同時に来た要求はまとめられる
+        assert!(storage.num_syncs() < NUM_THREADS as u64);
+        assert_eq!(
+            storage.num_syncs(),
+            storage.with_inner(|inner| inner.stats().num_syncs)
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::sync::OnceLock;
+
+use super::catalog::{Catalog, ColumnSchema, TableId, TableSchema};
+use crate::sql::dml::entity::{Type, Value};
+use crate::storage::entity::PageId;
+
+// 中身をカタログから作る仮想テーブルの番号 (システムカタログの番号の下に振る)
+pub const TABLES_TABLE_ID: TableId = TableId::MAX - 4;
+pub const COLUMNS_TABLE_ID: TableId = TableId::MAX - 5;
+pub const INDEXES_TABLE_ID: TableId = TableId::MAX - 6;
+pub const STATS_TABLE_ID: TableId = TableId::MAX - 7;
+pub const FIRST_VIRTUAL_TABLE_ID: TableId = STATS_TABLE_ID;
+
+pub fn is_virtual(table: TableId) -> bool {
+    (FIRST_VIRTUAL_TABLE_ID..=TABLES_TABLE_ID).contains(&table)
+}
+
+// 仮想テーブルの定義 (B+Tree を持たないので meta ページはない)
+pub fn virtual_tables() -> &'static [TableSchema] {
+    static TABLES: OnceLock<Vec<TableSchema>> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let table = |id, name: &str, columns: &[(&str, Type, bool)], num_key_elems| TableSchema {
+            id,
+            name: name.to_string(),
+            columns: columns
+                .iter()
+                .map(|&(name, column_type, nullable)| ColumnSchema {
+                    name: name.to_string(),
+                    column_type,
+                    nullable,
+                })
+                .collect(),
+            num_key_elems,
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            indexes: vec![],
+            sequence_page_id: None,
+        };
+        vec![
+            table(
+                TABLES_TABLE_ID,
+                "minidb_tables",
+                &[
+                    ("id", Type::Int, false),
+                    ("name", Type::Bytes, false),
+                    ("num_key_elems", Type::Int, false),
+                    // 仮想テーブルなら NULL
+                    ("meta_page_id", Type::Int, true),
+                ],
+                1,
+            ),
+            table(
+                COLUMNS_TABLE_ID,
+                "minidb_columns",
+                &[
+                    ("table_id", Type::Int, false),
+                    ("number", Type::Int, false),
+                    ("name", Type::Bytes, false),
+                    ("type", Type::Bytes, false),
+                    ("nullable", Type::Bool, false),
+                    ("primary_key", Type::Bool, false),
+                ],
+                2,
+            ),
+            table(
+                INDEXES_TABLE_ID,
+                "minidb_indexes",
+                &[
+                    ("table_id", Type::Int, false),
+                    ("name", Type::Bytes, false),
+                    // 副キーの列の名前をカンマで区切って並べたもの
+                    ("columns", Type::Bytes, false),
+                    ("unique", Type::Bool, false),
+                    ("meta_page_id", Type::Int, false),
+                ],
+                2,
+            ),
+            table(
+                STATS_TABLE_ID,
+                "minidb_stats",
+                &[
+                    ("table_id", Type::Int, false),
+                    ("number", Type::Int, false),
+                    ("rows", Type::Int, false),
+                    ("pages", Type::Int, false),
+                    // 分からなければ NULL
+                    ("distinct", Type::Int, true),
+                    ("nulls", Type::Int, false),
+                ],
+                2,
+            ),
+        ]
+    })
+}
+
+// 仮想テーブルの行を今のカタログから作る (主キーの順に並べる)
+pub fn rows(catalog: &Catalog, table: TableId) -> Vec<Vec<Value>> {
+    let int = |value: usize| Value::Int(value as i64);
+    let bytes = |value: &str| Value::Bytes(value.as_bytes().to_vec());
+    let mut tables: Vec<_> = catalog.tables().chain(virtual_tables()).collect();
+    tables.sort_by_key(|table| table.id);
+    let mut rows = vec![];
+    match table {
+        TABLES_TABLE_ID => {
+            for table in tables {
+                let meta_page_id = match is_virtual(table.id) {
+                    true => Value::Null,
+                    false => Value::Int(table.meta_page_id.0 as i64),
+                };
+                rows.push(vec![
+                    Value::Int(table.id as i64),
+                    bytes(&table.name),
+                    int(table.num_key_elems),
+                    meta_page_id,
+                ]);
+            }
+        }
+        COLUMNS_TABLE_ID => {
+            for table in tables {
+                for (number, column) in table.columns.iter().enumerate() {
+                    rows.push(vec![
+                        Value::Int(table.id as i64),
+                        int(number),
+                        bytes(&column.name),
+                        bytes(&format!("{:?}", column.column_type)),
+                        Value::Bool(column.nullable),
+                        Value::Bool(number < table.num_key_elems),
+                    ]);
+                }
+            }
+        }
+        INDEXES_TABLE_ID => {
+            for table in tables {
+                let mut indexes: Vec<_> = table.indexes.iter().collect();
+                indexes.sort_by(|a, b| a.name.cmp(&b.name));
+                for index in indexes {
+                    let columns: Vec<_> = index
+                        .columns
+                        .iter()
+                        .map(|&column| table.columns[column].name.as_str())
+                        .collect();
+                    rows.push(vec![
+                        Value::Int(table.id as i64),
+                        bytes(&index.name),
+                        bytes(&columns.join(", ")),
+                        Value::Bool(index.unique),
+                        Value::Int(index.meta_page_id.0 as i64),
+                    ]);
+                }
+            }
+        }
+        STATS_TABLE_ID => {
+            for table in tables {
+                let stats = match catalog.stats(table.id) {
+                    Some(stats) => stats,
+                    None => continue,
+                };
+                for (number, column) in stats.columns.iter().enumerate() {
+                    let distinct = match column.distinct {
+                        0 => Value::Null,
+                        distinct => Value::Int(distinct as i64),
+                    };
+                    rows.push(vec![
+                        Value::Int(table.id as i64),
+                        int(number),
+                        Value::Int(stats.rows as i64),
+                        Value::Int(stats.pages as i64),
+                        distinct,
+                        Value::Int(column.nulls as i64),
+                    ]);
+                }
+            }
+        }
+        _ => {}
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdbms::{
+        catalog::{ColumnStats, IndexSchema, TableStats},
+        clocksweep::ClockSweepManager,
+        database::Database,
+        memory::MemoryManager,
+    };
+
+    #[test]
+    fn introspect_test() {
+        let mut db =
+            Database::create(ClockSweepManager::new(MemoryManager::new(), 10).unwrap()).unwrap();
+        let column = |name: &str, column_type| ColumnSchema {
+            name: name.to_string(),
+            column_type,
+            nullable: true,
+        };
+        let columns = vec![
+            column("id", Type::Int),
+            column("name", Type::Bytes),
+            column("dept", Type::Int),
+        ];
+        let people = db
+            .catalog
+            .add_table("people", columns, 1, PageId(10))
+            .unwrap();
+        let index = IndexSchema {
+            name: "by_dept".to_string(),
+            columns: vec![2, 1],
+            unique: false,
+            meta_page_id: PageId(11),
+        };
+        db.catalog.add_index(people, index).unwrap();
+        let stats = TableStats {
+            rows: 4,
+            pages: 1,
+            columns: vec![
+                ColumnStats {
+                    distinct: 4,
+                    ..Default::default()
+                },
+                ColumnStats::default(),
+                ColumnStats {
+                    distinct: 2,
+                    nulls: 1,
+                    ..Default::default()
+                },
+            ],
+        };
+        db.catalog.set_stats(people, stats).unwrap();
+
+        let bytes = |value: &str| Value::Bytes(value.as_bytes().to_vec());
+        let rows = db
+            .query("SELECT name, meta_page_id FROM minidb_tables", &[])
+            .unwrap();
+        assert_eq!(vec![bytes("people"), Value::Int(10)], rows[0]);
+        // 仮想テーブル自身も並ぶ
+        assert!(rows.contains(&vec![bytes("minidb_columns"), Value::Null]));
+
+        // 普通のテーブルと同じように結合して絞り込める
+        let rows = db
+            .query(
+                "SELECT c.name, c.type FROM minidb_columns c JOIN minidb_tables t \
+                 ON c.table_id = t.id WHERE t.name = $1 AND c.primary_key = FALSE",
+                &[bytes("people")],
+            )
+            .unwrap();
+        assert_eq!(
+            vec![
+                vec![bytes("name"), bytes("Bytes")],
+                vec![bytes("dept"), bytes("Int")]
+            ],
+            rows
+        );
+        let rows = db
+            .query("SELECT name, columns, unique FROM minidb_indexes", &[])
+            .unwrap();
+        assert_eq!(
+            vec![vec![
+                bytes("by_dept"),
+                bytes("dept, name"),
+                Value::Bool(false)
+            ]],
+            rows
+        );
+        let rows = db
+            .query(
+                "SELECT number, distinct, nulls FROM minidb_stats WHERE table_id = $1",
+                &[Value::Int(people as i64)],
+            )
+            .unwrap();
+        assert_eq!(
+            vec![
+                vec![Value::Int(0), Value::Int(4), Value::Int(0)],
+                vec![Value::Int(1), Value::Null, Value::Int(0)],
+                vec![Value::Int(2), Value::Int(2), Value::Int(1)],
+            ],
+            rows
+        );
+
+        // 読むだけで、書き換えたり同じ名前のテーブルを作ったりはできない
+        assert!(db.prepare("DELETE FROM minidb_tables").is_err());
+        assert!(db
+            .catalog
+            .add_table("minidb_stats", vec![], 1, PageId(20))
+            .is_err());
+    }
+}
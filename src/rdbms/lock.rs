@@ -0,0 +1,276 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::storage::entity::PageId;
+use crate::wal::entity::TxnId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+impl LockMode {
+    fn is_compatible(self, other: LockMode) -> bool {
+        self == LockMode::Shared && other == LockMode::Shared
+    }
+
+    fn covers(self, other: LockMode) -> bool {
+        self == LockMode::Exclusive || other == LockMode::Shared
+    }
+}
+
+// ロックをかける対象
+// B-tree への挿入は分割で複数のページを書き換えるので、木全体をメタページのロックで守る
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LockTarget {
+    Page(PageId),
+    // テーブル (B-tree のメタページ) の中の主キー
+    Row(PageId, Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockStatus {
+    Granted,
+    // 待ち行列に入った (ロックを持っているトランザクションが終われば与えられる)
+    Waiting,
+    // 待つとデッドロックになるので要求を取り下げた (トランザクションをロールバックすること)
+    Deadlock,
+}
+
+#[derive(Debug)]
+struct Request {
+    txn_id: TxnId,
+    mode: LockMode,
+    granted: bool,
+    // 共有ロックを持ったまま排他ロックへの格上げを待っている
+    upgrading: bool,
+}
+
+impl Request {
+    // 待っている要求のモード
+    fn wanted_mode(&self) -> LockMode {
+        if self.upgrading {
+            LockMode::Exclusive
+        } else {
+            self.mode
+        }
+    }
+
+    fn is_waiting(&self) -> bool {
+        !self.granted || self.upgrading
+    }
+}
+
+// 対象ごとにロックの待ち行列を持つロックマネージャ
+// 与えたロックの後ろに待っている要求を並べ、先頭から順に与えていく
+// 待ちはブロックせずに Waiting を返すので、呼び出し側は release_all の後に is_granted で確かめる
+#[derive(Debug, Default)]
+pub struct LockManager {
+    queues: HashMap<LockTarget, VecDeque<Request>>,
+    // トランザクションが要求した対象
+    targets: HashMap<TxnId, HashSet<LockTarget>>,
+}
+
+impl LockManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lock(&mut self, txn_id: TxnId, target: LockTarget, mode: LockMode) -> LockStatus {
+        let queue = self.queues.entry(target.clone()).or_default();
+        match queue.iter_mut().find(|req| req.txn_id == txn_id) {
+            Some(req) if req.is_waiting() => return LockStatus::Waiting,
+            Some(req) if req.mode.covers(mode) => return LockStatus::Granted,
+            // 共有ロックからの格上げは他に持っているトランザクションがいなければすぐに与える
+            Some(req) => req.upgrading = true,
+            None => {
+                queue.push_back(Request {
+                    txn_id,
+                    mode,
+                    granted: false,
+                    upgrading: false,
+                });
+                self.targets
+                    .entry(txn_id)
+                    .or_default()
+                    .insert(target.clone());
+            }
+        }
+        Self::grant_waiting(queue, &mut vec![]);
+        if self.is_granted(txn_id, &target) {
+            return LockStatus::Granted;
+        }
+        if self.waits_for_itself(txn_id) {
+            self.cancel(txn_id, &target);
+            return LockStatus::Deadlock;
+        }
+        LockStatus::Waiting
+    }
+
+    // 要求したモードのロックを持っているか (格上げを待っている間は false)
+    pub fn is_granted(&self, txn_id: TxnId, target: &LockTarget) -> bool {
+        self.queues.get(target).is_some_and(|queue| {
+            queue
+                .iter()
+                .any(|req| req.txn_id == txn_id && !req.is_waiting())
+        })
+    }
+
+    // トランザクションのロックを全て外し、新たにロックを与えたトランザクションを返す
+    pub fn release_all(&mut self, txn_id: TxnId) -> Vec<TxnId> {
+        let mut woken = vec![];
+        for target in self.targets.remove(&txn_id).unwrap_or_default() {
+            let queue = self.queues.get_mut(&target).unwrap();
+            queue.retain(|req| req.txn_id != txn_id);
+            Self::grant_waiting(queue, &mut woken);
+            if queue.is_empty() {
+                self.queues.remove(&target);
+            }
+        }
+        woken.sort();
+        woken.dedup();
+        woken
+    }
+
+    // 待っている要求を先頭から与えられるだけ与える (格上げを優先する)
+    fn grant_waiting(queue: &mut VecDeque<Request>, woken: &mut Vec<TxnId>) {
+        if let Some(pos) = queue.iter().position(|req| req.upgrading) {
+            if queue.iter().filter(|req| req.granted).count() > 1 {
+                return;
+            }
+            queue[pos].mode = LockMode::Exclusive;
+            queue[pos].upgrading = false;
+            woken.push(queue[pos].txn_id);
+        }
+        for i in 0..queue.len() {
+            if queue[i].granted {
+                continue;
+            }
+            let mode = queue[i].mode;
+            let compatible = queue
+                .iter()
+                .filter(|req| req.granted)
+                .all(|req| req.mode.is_compatible(mode));
+            if !compatible {
+                break;
+            }
+            queue[i].granted = true;
+            woken.push(queue[i].txn_id);
+        }
+    }
+
+    // 待っている要求を取り下げる (格上げなら共有ロックは持ったままにする)
+    fn cancel(&mut self, txn_id: TxnId, target: &LockTarget) {
+        let queue = self.queues.get_mut(target).unwrap();
+        let pos = queue.iter().position(|req| req.txn_id == txn_id).unwrap();
+        if queue[pos].upgrading {
+            queue[pos].upgrading = false;
+        } else {
+            queue.remove(pos);
+            self.targets.get_mut(&txn_id).unwrap().remove(target);
+        }
+    }
+
+    // txn_id から待ちの関係をたどって txn_id に戻ってくるか
+    fn waits_for_itself(&self, txn_id: TxnId) -> bool {
+        let mut stack = vec![txn_id];
+        let mut visited = HashSet::new();
+        while let Some(waiter) = stack.pop() {
+            for holder in self.blockers(waiter) {
+                if holder == txn_id {
+                    return true;
+                }
+                if visited.insert(holder) {
+                    stack.push(holder);
+                }
+            }
+        }
+        false
+    }
+
+    // waiter の要求を待たせているトランザクション
+    // 格上げなら共有ロックを持っている他の全て、そうでなければ前に並んでいる両立しないもの
+    fn blockers(&self, waiter: TxnId) -> Vec<TxnId> {
+        let mut blockers = vec![];
+        for target in self.targets.get(&waiter).into_iter().flatten() {
+            let queue = &self.queues[target];
+            let pos = match queue
+                .iter()
+                .position(|req| req.txn_id == waiter && req.is_waiting())
+            {
+                Some(pos) => pos,
+                None => continue,
+            };
+            if queue[pos].upgrading {
+                blockers.extend(
+                    queue
+                        .iter()
+                        .filter(|req| req.granted && req.txn_id != waiter)
+                        .map(|req| req.txn_id),
+                );
+            } else {
+                let mode = queue[pos].mode;
+                blockers.extend(
+                    queue
+                        .iter()
+                        .take(pos)
+                        .filter(|req| !req.wanted_mode().is_compatible(mode))
+                        .map(|req| req.txn_id),
+                );
+            }
+        }
+        blockers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_test() {
+        let mut lockmgr = LockManager::new();
+        let page = LockTarget::Page(PageId(1));
+        let row = LockTarget::Row(PageId(1), b"a".to_vec());
+        let (t1, t2, t3) = (TxnId(1), TxnId(2), TxnId(3));
+
+        // 共有ロックは同時に持てる
+        assert_eq!(
+            LockStatus::Granted,
+            lockmgr.lock(t1, page.clone(), LockMode::Shared)
+        );
+        assert_eq!(
+            LockStatus::Granted,
+            lockmgr.lock(t2, page.clone(), LockMode::Shared)
+        );
+        // 排他ロックは待つ
+        assert_eq!(
+            LockStatus::Waiting,
+            lockmgr.lock(t3, page.clone(), LockMode::Exclusive)
+        );
+        // 格上げは他の共有ロックが外れるまで待つ
+        assert_eq!(
+            LockStatus::Waiting,
+            lockmgr.lock(t1, page.clone(), LockMode::Exclusive)
+        );
+        // t2 が t1 の持つ行を待つとデッドロックになる
+        assert_eq!(
+            LockStatus::Granted,
+            lockmgr.lock(t1, row.clone(), LockMode::Exclusive)
+        );
+        assert_eq!(
+            LockStatus::Deadlock,
+            lockmgr.lock(t2, row.clone(), LockMode::Shared)
+        );
+        // t2 が終われば t1 の格上げが先に与えられる
+        assert_eq!(vec![t1], lockmgr.release_all(t2));
+        assert!(lockmgr.is_granted(t1, &page));
+        assert!(!lockmgr.is_granted(t3, &page));
+        assert_eq!(vec![t3], lockmgr.release_all(t1));
+        assert!(lockmgr.is_granted(t3, &page));
+        assert_eq!(
+            LockStatus::Granted,
+            lockmgr.lock(t2, row, LockMode::Exclusive)
+        );
+    }
+}
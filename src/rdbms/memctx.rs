@@ -0,0 +1,163 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// 一つのクエリの実行器が使うメモリの量を数え、合わせて limit バイトを超えないようにする
+// ソートの並べ替え領域、集約のハッシュ表、Materialize が持つ行がここから確保する
+// 確保できなければ実行器は一時ファイルへ書き出す
+#[derive(Debug)]
+pub struct MemoryContext {
+    limit: usize,
+    used: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+thread_local! {
+    // with_memory_context の実行中だけ Some になる
+    static CURRENT: RefCell<Option<Arc<MemoryContext>>> = const { RefCell::new(None) };
+}
+
+// f を実行する間に開いた実行器は context からメモリを確保する
+// PreparedStatement::execute は実行ごとに work_mem を上限にした context をここで渡す
+pub fn with_memory_context<R>(context: Arc<MemoryContext>, f: impl FnOnce() -> R) -> R {
+    let saved = CURRENT.with(|current| current.replace(Some(context)));
+    let result = f();
+    CURRENT.with(|current| current.replace(saved));
+    result
+}
+
+impl MemoryContext {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    // 実行器を開くときに使うもの (with_memory_context の外なら上限のないもの)
+    pub fn current() -> Arc<Self> {
+        CURRENT
+            .with(|current| current.borrow().clone())
+            .unwrap_or_else(|| Arc::new(Self::new(usize::MAX)))
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    // これまでに一度に使った最大の量
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    // limit を超えなければ bytes を確保する
+    fn try_alloc(&self, bytes: usize) -> bool {
+        let result = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|&used| used <= self.limit)
+            });
+        match result {
+            Ok(used) => {
+                self.peak.fetch_max(used + bytes, Ordering::Relaxed);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    // limit を超えても bytes を確保する
+    fn alloc(&self, bytes: usize) {
+        let used = self.used.fetch_add(bytes, Ordering::Relaxed);
+        self.peak.fetch_max(used + bytes, Ordering::Relaxed);
+    }
+
+    fn free(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+// 一つの実行器が MemoryContext から確保している分
+// 手放すと確保していた分を全て返す
+#[derive(Debug)]
+pub struct MemoryReservation {
+    context: Arc<MemoryContext>,
+    bytes: usize,
+}
+
+impl MemoryReservation {
+    pub fn new(context: Arc<MemoryContext>) -> Self {
+        Self { context, bytes: 0 }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // 上限を超えなければ bytes 増やす (超えるなら何もせず false)
+    pub fn try_grow(&mut self, bytes: usize) -> bool {
+        let ok = self.context.try_alloc(bytes);
+        if ok {
+            self.bytes += bytes;
+        }
+        ok
+    }
+
+    // 上限を超えても bytes 増やす (少なくとも一つは持たないと進めないとき)
+    pub fn grow(&mut self, bytes: usize) {
+        self.context.alloc(bytes);
+        self.bytes += bytes;
+    }
+
+    // 書き出したなどで持たなくなった分を全て返す
+    pub fn clear(&mut self) {
+        self.context.free(self.bytes);
+        self.bytes = 0;
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reservation_test() {
+        let context = Arc::new(MemoryContext::new(100));
+        let mut sort = MemoryReservation::new(context.clone());
+        let mut hash = MemoryReservation::new(context.clone());
+        assert!(sort.try_grow(60));
+        // 合わせて上限を超える分は確保しない
+        assert!(!hash.try_grow(50));
+        assert!(hash.try_grow(40));
+        assert_eq!((100, 60, 40), (context.used(), sort.bytes(), hash.bytes()));
+        assert!(!hash.try_grow(1));
+
+        // 上限を超えても確保できる
+        hash.grow(10);
+        assert_eq!(110, context.used());
+        sort.clear();
+        assert_eq!(50, context.used());
+        drop(hash);
+        assert_eq!((0, 110), (context.used(), context.peak()));
+    }
+
+    #[test]
+    fn current_test() {
+        assert_eq!(usize::MAX, MemoryContext::current().limit());
+        let context = Arc::new(MemoryContext::new(10));
+        let limit = with_memory_context(context, || MemoryContext::current().limit());
+        assert_eq!(10, limit);
+        assert_eq!(usize::MAX, MemoryContext::current().limit());
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::buffer::entity::PAGE_SIZE;
+use crate::storage::{
+    entity::{IoStats, PageId},
+    manager::{Error, Result, StorageManager},
+};
+
+// save で書き出すデータの識別子
+const MAGIC: [u8; 8] = *b"MINIDBMM";
+
+// ページをメモリ上に持つ storagemanager
+// テスト用だが、save/load でファイルなどに書き出して復元できる
+// ページ 0 は DiskManager と同じくヘッダ用に空けておく
+pub struct MemoryManager {
+    pages: BTreeMap<PageId, Box<[u8]>>,
+    // 採番するページを決めるカウンタ
+    next_page_id: u64,
+    // 解放済みで再利用できるページ
+    free_pages: Vec<PageId>,
+    // 読み書きの統計
+    stats: IoStats,
+}
+
+impl Default for MemoryManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryManager {
+    pub fn new() -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            next_page_id: 1,
+            free_pages: vec![],
+            stats: IoStats::default(),
+        }
+    }
+
+    // これまでの読み書きの統計
+    pub fn stats(&self) -> IoStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = IoStats::default();
+    }
+
+    // 全てのページを writer に書き出す
+    // [magic][page_size][next_page_id][解放済みページ数][解放済みページ ID...]
+    // [ページ数][(ページ ID, ページ)...] の順でリトルエンディアンで書く
+    pub fn save(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&(PAGE_SIZE as u64).to_le_bytes())?;
+        writer.write_all(&self.next_page_id.to_le_bytes())?;
+        writer.write_all(&(self.free_pages.len() as u64).to_le_bytes())?;
+        for page_id in &self.free_pages {
+            writer.write_all(&page_id.to_u64().to_le_bytes())?;
+        }
+        writer.write_all(&(self.pages.len() as u64).to_le_bytes())?;
+        for (page_id, page) in &self.pages {
+            writer.write_all(&page_id.to_u64().to_le_bytes())?;
+            writer.write_all(page)?;
+        }
+        writer.flush()
+    }
+
+    // save で書き出したものを読み込んで復元する
+    pub fn load(mut reader: impl Read) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "not a minidb memory image",
+            ));
+        }
+        let page_size = read_u64(&mut reader)?;
+        if page_size != PAGE_SIZE as u64 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "page size mismatch: image has {}, expected {}",
+                    page_size, PAGE_SIZE
+                ),
+            ));
+        }
+        let next_page_id = read_u64(&mut reader)?;
+        let num_free_pages = read_u64(&mut reader)?;
+        let free_pages = (0..num_free_pages)
+            .map(|_| read_u64(&mut reader).map(PageId))
+            .collect::<io::Result<_>>()?;
+        let num_pages = read_u64(&mut reader)?;
+        let mut pages = BTreeMap::new();
+        for _ in 0..num_pages {
+            let page_id = PageId(read_u64(&mut reader)?);
+            let mut page = vec![0u8; PAGE_SIZE].into_boxed_slice();
+            reader.read_exact(&mut page)?;
+            pages.insert(page_id, page);
+        }
+        Ok(Self {
+            pages,
+            next_page_id,
+            free_pages,
+            stats: IoStats::default(),
+        })
+    }
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf.as_ref().try_into().unwrap()))
+}
+
+impl StorageManager for MemoryManager {
+    fn allocate_page(&mut self) -> Result<PageId> {
+        if let Some(page_id) = self.free_pages.pop() {
+            return Ok(page_id);
+        }
+        let page_id = self.next_page_id;
+        self.next_page_id += 1;
+        Ok(PageId(page_id))
+    }
+
+    fn deallocate_page(&mut self, page_id: PageId) -> Result<()> {
+        self.pages.remove(&page_id);
+        self.free_pages.push(page_id);
+        Ok(())
+    }
+
+    fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> Result<()> {
+        let page = self.pages.get(&page_id).ok_or(Error::PastEof(page_id))?;
+        data.copy_from_slice(page);
+        self.stats.num_reads += 1;
+        self.stats.bytes_read += data.len() as u64;
+        Ok(())
+    }
+
+    fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+        self.pages.insert(page_id, data.into());
+        self.stats.num_writes += 1;
+        self.stats.bytes_written += data.len() as u64;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        // メモリ上なので書き出すものは無い
+        self.stats.num_syncs += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::manager::BufferPoolManager;
+    use crate::rdbms::clocksweep::ClockSweepManager;
+
+    #[test]
+    fn save_load_test() {
+        let mut memory = MemoryManager::new();
+        let pages: Vec<_> = (0..3u8).map(|n| vec![n + 1; PAGE_SIZE]).collect();
+        let page_ids: Vec<_> = pages
+            .iter()
+            .map(|page| {
+                let page_id = memory.allocate_page().unwrap();
+                memory.write_page_data(page_id, page).unwrap();
+                page_id
+            })
+            .collect();
+        assert_eq!(PageId(1), page_ids[0]);
+        memory.deallocate_page(page_ids[1]).unwrap();
+        assert_eq!(3, memory.stats().num_writes);
+
+        let mut image = vec![];
+        memory.save(&mut image).unwrap();
+        let mut restored = MemoryManager::load(image.as_slice()).unwrap();
+        let mut buf = vec![0u8; PAGE_SIZE];
+        restored.read_page_data(page_ids[2], &mut buf).unwrap();
+        assert_eq!(pages[2], buf);
+        assert!(matches!(
+            restored.read_page_data(page_ids[1], &mut buf),
+            Err(Error::PastEof(_))
+        ));
+        // 解放済みのページと採番の状態も復元される
+        assert_eq!(page_ids[1], restored.allocate_page().unwrap());
+        assert_eq!(PageId(4), restored.allocate_page().unwrap());
+
+        assert!(MemoryManager::load(&b"garbage!"[..]).is_err());
+    }
+
+    #[test]
+    fn buffer_pool_test() {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 1).unwrap();
+        let page_id = {
+            let buffer = bufmgr.create_page().unwrap();
+            buffer.body_mut()[..5].copy_from_slice(b"hello");
+            buffer.is_dirty.set(true);
+            buffer.page_id
+        };
+        // 追い出されたページはメモリに書き戻される
+        bufmgr.create_page().unwrap();
+        let buffer = bufmgr.fetch_page(page_id).unwrap();
+        assert_eq!(b"hello", &buffer.body()[..5]);
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::Result;
+
+use super::{
+    catalog::Catalog,
+    database::Database,
+    syscat::SystemCatalog,
+    txn::{TransactionManager, Txn, TxnBufferManager},
+};
+use crate::buffer::manager::BufferPoolManager;
+use crate::wal::manager::LogManager;
+
+// ファイルに記録したマイグレーションが、登録したものと合わない
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("database is at version {version} but only {latest} migrations are registered")]
+    Newer { version: u32, latest: u32 },
+    #[error("migration {version} was applied as {applied:?} but is registered as {registered:?}")]
+    Renamed {
+        version: u32,
+        applied: String,
+        registered: String,
+    },
+}
+
+// 一つのマイグレーションが DDL に使うもの
+// bufmgr での書き換えはトランザクションの中なので、マイグレーションが失敗すれば元に戻る
+pub struct Migration<'a, T: BufferPoolManager> {
+    pub bufmgr: TxnBufferManager<'a, T>,
+    pub syscat: &'a SystemCatalog,
+    pub catalog: &'a mut Catalog,
+}
+
+type Step<T> = Box<dyn Fn(&mut Migration<'_, T>) -> Result<()>>;
+
+// アプリケーションが登録する、スキーマを変えていく手順の列
+// 登録した順に 1 から version を振るので、一度リリースした手順は並べ替えたり消したりしないこと
+pub struct Migrations<T: BufferPoolManager> {
+    steps: Vec<(String, Step<T>)>,
+}
+
+impl<T: BufferPoolManager + 'static> Default for Migrations<T> {
+    fn default() -> Self {
+        Self { steps: vec![] }
+    }
+}
+
+impl<T: BufferPoolManager + 'static> Migrations<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 次の version の手順を加える (名前は minidb_migration に残り、開き直したときに照らし合わせる)
+    pub fn add(
+        mut self,
+        name: &str,
+        step: impl Fn(&mut Migration<'_, T>) -> Result<()> + 'static,
+    ) -> Self {
+        self.steps.push((name.to_string(), Box::new(step)));
+        self
+    }
+
+    // 全ての手順を適用した後の version
+    pub fn latest(&self) -> u32 {
+        self.steps.len() as u32
+    }
+
+    // db にまだ適用していない手順を順に適用し、適用した数を返す
+    // 手順ごとに別のトランザクションで走らせ、minidb_migration に書いてからコミットする
+    // 失敗した手順はロールバックしてエラーを返す (それより前の手順は適用したまま残る)
+    pub fn run<L: LogManager>(
+        &self,
+        db: &mut Database<T>,
+        txn_mgr: &mut TransactionManager<L>,
+    ) -> Result<usize> {
+        let applied = db.syscat.migrations(&mut db.bufmgr)?;
+        let version = applied.last().map_or(0, |&(version, _)| version);
+        if version > self.latest() {
+            return Err(Error::Newer {
+                version,
+                latest: self.latest(),
+            }
+            .into());
+        }
+        for (version, applied) in applied {
+            let registered = &self.steps[version as usize - 1].0;
+            if &applied != registered {
+                return Err(Error::Renamed {
+                    version,
+                    applied,
+                    registered: registered.clone(),
+                }
+                .into());
+            }
+        }
+        let pending = &self.steps[version as usize..];
+        for (version, (name, step)) in (version + 1..).zip(pending) {
+            let syscat = db.syscat.clone();
+            let mut txn = txn_mgr.begin()?;
+            match apply(db, &mut txn, version, name, step) {
+                Ok(()) => txn_mgr.commit(txn)?,
+                Err(err) => {
+                    txn_mgr.rollback(txn, &mut db.bufmgr)?;
+                    // 書き換えたカタログはページから読み直す
+                    db.syscat = syscat;
+                    db.catalog = db.syscat.load(&mut db.bufmgr)?;
+                    return Err(err.context(format!("migration {} ({}) failed", version, name)));
+                }
+            }
+        }
+        Ok(pending.len())
+    }
+}
+
+fn apply<T: BufferPoolManager + 'static>(
+    db: &mut Database<T>,
+    txn: &mut Txn,
+    version: u32,
+    name: &str,
+    step: &Step<T>,
+) -> Result<()> {
+    let mut migration = Migration {
+        bufmgr: txn.bind(&mut db.bufmgr)?,
+        syscat: &db.syscat,
+        catalog: &mut db.catalog,
+    };
+    step(&mut migration)?;
+    db.syscat
+        .add_migration(&mut txn.bind(&mut db.bufmgr)?, version, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdbms::{
+        btree::BTree,
+        catalog::{ColumnSchema, IndexSchema},
+        clocksweep::ClockSweepManager,
+        memory::MemoryManager,
+        wal::FileLogManager,
+    };
+    use crate::sql::dml::entity::{Type, Value};
+    use anyhow::bail;
+    use tempfile::tempdir;
+
+    type Bufmgr = ClockSweepManager<MemoryManager>;
+
+    fn create_people(m: &mut Migration<'_, Bufmgr>) -> Result<()> {
+        let column = |name: &str, column_type| ColumnSchema {
+            name: name.to_string(),
+            column_type,
+            nullable: false,
+        };
+        let columns = vec![column("id", Type::Int), column("name", Type::Bytes)];
+        let meta_page_id = BTree::create(&mut m.bufmgr)?.meta_page_id;
+        m.syscat
+            .add_table(&mut m.bufmgr, m.catalog, "people", columns, 1, meta_page_id)?;
+        Ok(())
+    }
+
+    fn index_people(m: &mut Migration<'_, Bufmgr>) -> Result<()> {
+        let people = m.catalog.table("people").unwrap().id;
+        let index = IndexSchema {
+            name: "by_name".to_string(),
+            columns: vec![1],
+            unique: false,
+            meta_page_id: BTree::create(&mut m.bufmgr)?.meta_page_id,
+        };
+        m.syscat.add_index(&mut m.bufmgr, m.catalog, people, index)
+    }
+
+    #[test]
+    fn migration_test() {
+        let dir = tempdir().unwrap();
+        let mut txn_mgr =
+            TransactionManager::new(FileLogManager::open(dir.path()).unwrap()).unwrap();
+        let mut db =
+            Database::create(ClockSweepManager::new(MemoryManager::new(), 64).unwrap()).unwrap();
+        let migrations = Migrations::new()
+            .add("create people", create_people)
+            .add("index people", index_people);
+        assert_eq!(0, db.schema_version().unwrap());
+        assert_eq!(2, migrations.run(&mut db, &mut txn_mgr).unwrap());
+        assert_eq!(2, db.schema_version().unwrap());
+        assert_eq!(1, db.catalog.table("people").unwrap().indexes.len());
+        db.query("INSERT INTO people VALUES (1, 'alice')", &[])
+            .unwrap();
+        // 適用済みなら何もしない
+        assert_eq!(0, migrations.run(&mut db, &mut txn_mgr).unwrap());
+
+        // 失敗した手順の書き換えは残らない
+        let migrations = migrations.add("create pets and fail", |m| {
+            let meta_page_id = BTree::create(&mut m.bufmgr)?.meta_page_id;
+            m.syscat
+                .add_table(&mut m.bufmgr, m.catalog, "pets", vec![], 1, meta_page_id)?;
+            bail!("oops")
+        });
+        assert!(migrations.run(&mut db, &mut txn_mgr).is_err());
+        assert_eq!(2, db.schema_version().unwrap());
+        assert_eq!(None, db.catalog.table("pets"));
+        assert_eq!(None, db.syscat.load(&mut db.bufmgr).unwrap().table("pets"));
+        let rows = db.query("SELECT name FROM people", &[]).unwrap();
+        assert_eq!(vec![vec![Value::Bytes(b"alice".to_vec())]], rows);
+
+        // 開き直しても version は残り、登録した手順と合わなければ開けない
+        let root = db.syscat.class_page_id;
+        let mut db = Database::open(db.bufmgr, root).unwrap();
+        assert_eq!(2, db.schema_version().unwrap());
+        let older = Migrations::new().add("create people", create_people);
+        assert!(older.run(&mut db, &mut txn_mgr).is_err());
+        let renamed = Migrations::new()
+            .add("create people", create_people)
+            .add("index names", index_people);
+        assert!(renamed.run(&mut db, &mut txn_mgr).is_err());
+        assert_eq!(2, db.catalog.table("people").unwrap().columns.len());
+    }
+}
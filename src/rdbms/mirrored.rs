@@ -0,0 +1,125 @@
+use std::io;
+
+use crate::storage::{
+    entity::PageId,
+    manager::{Error, Result, StorageManager},
+};
+
+// 全てのページを二つの storagemanager に書き出すラッパー
+// 読み出しは primary から行い、壊れていれば mirror から読んで primary を直す
+// 二つは同じ順に割り当てを行うので、同じページ ID が同じページを指す
+pub struct MirroredStorage<A: StorageManager, B: StorageManager> {
+    primary: A,
+    mirror: B,
+    // mirror から読んで primary を直した回数
+    num_repairs: u64,
+}
+
+impl<A: StorageManager, B: StorageManager> MirroredStorage<A, B> {
+    pub fn new(primary: A, mirror: B) -> Self {
+        Self {
+            primary,
+            mirror,
+            num_repairs: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.primary, self.mirror)
+    }
+
+    pub fn num_repairs(&self) -> u64 {
+        self.num_repairs
+    }
+}
+
+impl<A: StorageManager, B: StorageManager> StorageManager for MirroredStorage<A, B> {
+    fn page_size(&self) -> Option<usize> {
+        self.primary.page_size()
+    }
+
+    fn allocate_page(&mut self) -> Result<PageId> {
+        let page_id = self.primary.allocate_page()?;
+        let mirror_page_id = self.mirror.allocate_page()?;
+        if page_id != mirror_page_id {
+            return Err(Error::Io(io::Error::other(format!(
+                "mirror is out of sync: allocated {:?} on primary but {:?} on mirror",
+                page_id, mirror_page_id
+            ))));
+        }
+        Ok(page_id)
+    }
+
+    fn deallocate_page(&mut self, page_id: PageId) -> Result<()> {
+        self.primary.deallocate_page(page_id)?;
+        self.mirror.deallocate_page(page_id)
+    }
+
+    fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> Result<()> {
+        match self.primary.read_page_data(page_id, data) {
+            Err(Error::Corrupt(_)) => {
+                self.mirror.read_page_data(page_id, data)?;
+                self.primary.write_page_data(page_id, data)?;
+                self.num_repairs += 1;
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+        self.primary.write_page_data(page_id, data)?;
+        self.mirror.write_page_data(page_id, data)
+    }
+
+    fn write_pages_data(&mut self, first_page_id: PageId, pages: &[&[u8]]) -> Result<()> {
+        self.primary.write_pages_data(first_page_id, pages)?;
+        self.mirror.write_pages_data(first_page_id, pages)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.primary.sync()?;
+        self.mirror.sync()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::entity::{PAGE_BODY_SIZE, PAGE_SIZE};
+    use crate::rdbms::{disk::DiskManager, memory::MemoryManager};
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn mirrored_test() {
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file, PAGE_SIZE).unwrap();
+        let mut storage = MirroredStorage::new(disk, MemoryManager::new());
+        let page_id = storage.allocate_page().unwrap();
+        let mut page = vec![0u8; PAGE_SIZE];
+        page[..5].copy_from_slice(b"hello");
+        storage.write_page_data(page_id, &page).unwrap();
+        storage.sync().unwrap();
+
+        // primary のページの途中を書き換えて壊す
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&data_file_path)
+            .unwrap();
+        file.seek(SeekFrom::Start(PAGE_SIZE as u64 * page_id.to_u64() + 100))
+            .unwrap();
+        file.write_all(b"garbage").unwrap();
+        drop(file);
+
+        // mirror から読めて、primary も直る
+        let mut buf = vec![0u8; PAGE_SIZE];
+        storage.read_page_data(page_id, &mut buf).unwrap();
+        assert_eq!(page[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        assert_eq!(1, storage.num_repairs());
+        let (mut disk, _) = storage.into_inner();
+        disk.read_page_data(page_id, &mut buf).unwrap();
+        assert_eq!(page[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+    }
+}
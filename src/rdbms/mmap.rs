@@ -0,0 +1,144 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use memmap2::MmapMut;
+use zerocopy::{AsBytes, LayoutVerified};
+
+use super::disk::FileHeader;
+use crate::buffer::entity::PAGE_SIZE;
+use crate::storage::{
+    checksum,
+    entity::PageId,
+    manager::{self, StorageManager},
+};
+
+// ヒープファイルをメモリにマップして読み書きする
+// ファイルのフォーマットは DiskManager と同じ
+pub struct MmapManager {
+    // ヒープファイルのファイルディスクリプタ
+    heap_file: File,
+    // ヒープファイル全体のマップ
+    mmap: MmapMut,
+    // 採番するページを決めるカウンタ
+    next_page_id: u64,
+}
+
+impl MmapManager {
+    pub fn new(heap_file: File) -> Result<Self> {
+        let heap_file_size = heap_file.metadata()?.len();
+        if heap_file_size == 0 {
+            // 新しいファイルにはヘッダを書き込む
+            heap_file.set_len(PAGE_SIZE as u64)?;
+        }
+        // SAFETY: ヒープファイルは MmapManager が排他的に使う
+        let mut mmap = unsafe { MmapMut::map_mut(&heap_file)? };
+        if heap_file_size == 0 {
+            let header = FileHeader::new(PAGE_SIZE);
+            mmap[..header.as_bytes().len()].copy_from_slice(header.as_bytes());
+        }
+        let (header, _) = LayoutVerified::<_, FileHeader>::new_from_prefix(&mmap[..])
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "file header is truncated"))?;
+        header.validate(PAGE_SIZE)?;
+        let next_page_id = std::cmp::max(mmap.len() as u64 / PAGE_SIZE as u64, 1);
+        Ok(Self {
+            heap_file,
+            mmap,
+            next_page_id,
+        })
+    }
+
+    pub fn open(heap_file_path: impl AsRef<Path>) -> Result<Self> {
+        let heap_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(heap_file_path)?;
+        Self::new(heap_file)
+    }
+
+    fn page_range(page_id: PageId) -> std::ops::Range<usize> {
+        let offset = PAGE_SIZE * page_id.to_u64() as usize;
+        offset..offset + PAGE_SIZE
+    }
+
+    // ページが収まるようにファイルを伸ばしてマップし直す
+    fn ensure_mapped(&mut self, page_id: PageId) -> Result<()> {
+        let end = Self::page_range(page_id).end;
+        if end <= self.mmap.len() {
+            return Ok(());
+        }
+        self.mmap.flush()?;
+        self.heap_file.set_len(end as u64)?;
+        // SAFETY: ヒープファイルは MmapManager が排他的に使う
+        self.mmap = unsafe { MmapMut::map_mut(&self.heap_file)? };
+        Ok(())
+    }
+}
+
+impl StorageManager for MmapManager {
+    fn allocate_page(&mut self) -> manager::Result<PageId> {
+        let page_id = self.next_page_id;
+        self.next_page_id += 1;
+        Ok(PageId(page_id))
+    }
+    fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> manager::Result<()> {
+        let range = Self::page_range(page_id);
+        let page = self
+            .mmap
+            .get(range)
+            .ok_or(manager::Error::PastEof(page_id))?;
+        if !checksum::verify(page) {
+            return Err(manager::Error::Corrupt(page_id));
+        }
+        data.copy_from_slice(page);
+        Ok(())
+    }
+    fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> manager::Result<()> {
+        self.ensure_mapped(page_id)?;
+        let page = &mut self.mmap[Self::page_range(page_id)];
+        page.copy_from_slice(data);
+        checksum::stamp(page);
+        Ok(())
+    }
+    fn sync(&mut self) -> manager::Result<()> {
+        // msync
+        Ok(self.mmap.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::entity::PAGE_BODY_SIZE;
+    use crate::rdbms::disk::DiskManager;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn mmap_test() {
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let mut mmap = MmapManager::new(data_file).unwrap();
+        let mut hello = vec![0u8; PAGE_SIZE];
+        hello[..5].copy_from_slice(b"hello");
+        let hello_page_id = mmap.allocate_page().unwrap();
+        mmap.write_page_data(hello_page_id, &hello).unwrap();
+        let mut world = vec![0u8; PAGE_SIZE];
+        world[..5].copy_from_slice(b"world");
+        let world_page_id = mmap.allocate_page().unwrap();
+        mmap.write_page_data(world_page_id, &world).unwrap();
+        mmap.sync().unwrap();
+
+        let mut buf = vec![0; PAGE_SIZE];
+        mmap.read_page_data(hello_page_id, &mut buf).unwrap();
+        assert_eq!(hello[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        assert!(mmap.read_page_data(PageId(42), &mut buf).is_err());
+        drop(mmap);
+
+        // DiskManager と同じフォーマットで読める
+        let mut disk = DiskManager::open(&data_file_path, PAGE_SIZE).unwrap();
+        disk.read_page_data(world_page_id, &mut buf).unwrap();
+        assert_eq!(world[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        assert_eq!(PageId(3), disk.allocate_page().unwrap());
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::wal::entity::TxnId;
+
+// テーブルの値の先頭に置くヘッダの大きさ
+// [xmin: u64][xmax: u64] の順でリトルエンディアンで書く
+pub const TUPLE_HEADER_SIZE: usize = 16;
+
+// タプルを挿入したトランザクション (xmin) と削除したトランザクション (xmax)
+// トランザクションの外で挿入したタプルの xmin と、削除されていないタプルの xmax は INVALID_TXN_ID にする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TupleHeader {
+    pub xmin: TxnId,
+    pub xmax: TxnId,
+}
+
+impl TupleHeader {
+    pub fn new(xmin: TxnId) -> Self {
+        Self {
+            xmin,
+            xmax: TxnId::INVALID_TXN_ID,
+        }
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.xmin.0.to_le_bytes());
+        buf.extend_from_slice(&self.xmax.0.to_le_bytes());
+    }
+
+    // 値をヘッダとタプル本体に分ける
+    // ヘッダの大きさに足りなければ Error::TruncatedHeader を返す
+    pub fn decode(value: &[u8]) -> Result<(Self, &[u8]), Error> {
+        if value.len() < TUPLE_HEADER_SIZE {
+            return Err(Error::TruncatedHeader(value.len()));
+        }
+        let (header, body) = value.split_at(TUPLE_HEADER_SIZE);
+        let xmin = TxnId(u64::from_le_bytes(header[0..8].try_into().unwrap()));
+        let xmax = TxnId(u64::from_le_bytes(header[8..16].try_into().unwrap()));
+        Ok((Self { xmin, xmax }, body))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // 削除しようとしたタプルは他のトランザクションが先に削除していた
+    #[error("tuple was already deleted by {0:?}")]
+    AlreadyDeleted(TxnId),
+    // 直列化できない実行になるのでトランザクションをロールバックすること
+    #[error(
+        "could not serialize access due to read/write dependencies among transactions in {0:?}"
+    )]
+    SerializationFailure(TxnId),
+    // TupleHeader のない値をテーブルの行として読もうとした
+    #[error("value of {0} bytes is too short for a tuple header")]
+    TruncatedHeader(usize),
+}
+
+// スキャンでタプルを返すかどうかを決める
+pub trait Visibility {
+    fn is_visible(&self, header: &TupleHeader) -> bool;
+
+    // トランザクションを使わず、削除した行がインデックスごと消えているか
+    // (インデックスの項目だけで行を返してよいか)
+    fn is_all_visible(&self) -> bool {
+        false
+    }
+}
+
+// 削除されていないタプルを全て見る (トランザクションを使わないとき)
+pub struct AllVisible;
+
+impl Visibility for AllVisible {
+    fn is_visible(&self, header: &TupleHeader) -> bool {
+        header.xmax == TxnId::INVALID_TXN_ID
+    }
+
+    fn is_all_visible(&self) -> bool {
+        true
+    }
+}
+
+// ある時点でコミット済みだったトランザクションの書き込みと、自分の書き込みだけを見る
+// ロールバックした書き込みは取り消されて残らないので、タプルに残るのは
+// コミットしたか実行中のトランザクションの ID だけになる
+#[derive(Debug, Clone)]
+pub struct TxnSnapshot {
+    // 読み出すトランザクション (トランザクションの外なら INVALID_TXN_ID)
+    pub txn_id: TxnId,
+    // これ以降の ID のトランザクションはまだ始まっていなかった
+    pub next_txn_id: TxnId,
+    // 実行中だったトランザクション
+    pub active: BTreeSet<TxnId>,
+    // このスナップショット (と複製) で見えた行の数
+    rows_read: Arc<AtomicU64>,
+}
+
+impl TxnSnapshot {
+    pub fn new(txn_id: TxnId, next_txn_id: TxnId, active: BTreeSet<TxnId>) -> Self {
+        Self {
+            txn_id,
+            next_txn_id,
+            active,
+            rows_read: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn rows_read(&self) -> u64 {
+        self.rows_read.load(Ordering::Relaxed)
+    }
+
+    // スナップショットを取った時点でコミット済みだったか
+    pub fn is_committed(&self, txn_id: TxnId) -> bool {
+        txn_id == TxnId::INVALID_TXN_ID
+            || (txn_id < self.next_txn_id && !self.active.contains(&txn_id))
+    }
+
+    fn sees(&self, txn_id: TxnId) -> bool {
+        (txn_id == self.txn_id && txn_id != TxnId::INVALID_TXN_ID) || self.is_committed(txn_id)
+    }
+}
+
+impl Visibility for TxnSnapshot {
+    fn is_visible(&self, header: &TupleHeader) -> bool {
+        let visible = self.sees(header.xmin)
+            && (header.xmax == TxnId::INVALID_TXN_ID || !self.sees(header.xmax));
+        if visible {
+            self.rows_read.fetch_add(1, Ordering::Relaxed);
+        }
+        visible
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visibility_test() {
+        let snapshot = TxnSnapshot::new(
+            TxnId(3),
+            TxnId(5),
+            [TxnId(2), TxnId(3)].iter().copied().collect(),
+        );
+        let header = |xmin, xmax| TupleHeader {
+            xmin: TxnId(xmin),
+            xmax: TxnId(xmax),
+        };
+        let mut value = vec![];
+        header(1, 4).encode(&mut value);
+        value.extend_from_slice(b"body");
+        assert_eq!(
+            (header(1, 4), &b"body"[..]),
+            TupleHeader::decode(&value).unwrap()
+        );
+        assert!(matches!(
+            TupleHeader::decode(b"Alice"),
+            Err(Error::TruncatedHeader(5))
+        ));
+
+        // コミット済みか自分の挿入だけが見える
+        assert!(snapshot.is_visible(&header(0, 0)));
+        assert!(snapshot.is_visible(&header(1, 0)));
+        assert!(snapshot.is_visible(&header(3, 0)));
+        assert!(!snapshot.is_visible(&header(2, 0)));
+        assert!(!snapshot.is_visible(&header(5, 0)));
+        // コミット済みか自分の削除なら見えない
+        assert!(!snapshot.is_visible(&header(1, 4)));
+        assert!(!snapshot.is_visible(&header(1, 3)));
+        assert!(snapshot.is_visible(&header(1, 2)));
+        assert!(snapshot.is_visible(&header(1, 6)));
+
+        assert!(AllVisible.is_visible(&header(2, 0)));
+        assert!(!AllVisible.is_visible(&header(2, 4)));
+    }
+}
@@ -0,0 +1,1625 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+
+use super::{
+    binder::{LogicalPlan, OrderKey},
+    btree::{self, BTree},
+    catalog::{Catalog, TableId, TableSchema},
+    cost::{
+        conjunction, default_distinct, ColumnEstimate, Cost, CostModel, DEFAULT_ROWS, ROWS_PER_PAGE,
+    },
+    expr::{conjoin, conjuncts, CmpOp, Expr},
+    introspect::{self, is_virtual},
+    mvcc::Visibility,
+    query::*,
+    rewrite::rewrite,
+    sequence::Sequence,
+};
+use crate::accessor::method::SharedAccessMethod;
+use crate::buffer::manager::BufferPoolManager;
+use crate::sql::dml::{
+    entity::{Row, Tuple},
+    query::{SharedPlan, SortKey},
+};
+
+pub type Plan<T> = SharedPlan<T, btree::Iter>;
+
+// これより多くのテーブルの結合は順を探さずに書いた順に結合する
+const MAX_JOIN_SEARCH: usize = 8;
+
+// 論理プランを、統計から見積もった費用の一番小さい物理プランにする
+// テーブルは主キーか各インデックスの先頭の列の等号と、その次の列の範囲で探すものから選び、
+// 結合はテーブルを結合する順と、結合の仕方 (NestedLoopJoin, MergeJoin, IndexNestedLoopJoin) を選ぶ
+pub struct Planner<'c> {
+    pub catalog: &'c Catalog,
+    pub visibility: Arc<dyn Visibility + Send + Sync>,
+    // Sort や Materialize がメモリに持つ量の上限
+    pub work_mem: usize,
+    // インデックスの項目には可視性がないので、全ての行が見えるときだけ IndexOnlyScan を使う
+    pub index_only: bool,
+    pub cost_model: CostModel,
+    // 最後に組み立てたプランのノードごとの見積もり (ノードのアドレスで見分ける)
+    costs: RefCell<HashMap<usize, Cost>>,
+}
+
+// 列と値を比べる述語 (値はリテラルかパラメータ)
+struct KeyPred {
+    column: usize,
+    op: CmpOp,
+    value: Expr,
+}
+
+fn key_pred(conjunct: &Expr) -> Option<KeyPred> {
+    let is_value = |expr: &Expr| match expr {
+        Expr::Param(_) => true,
+        Expr::Literal(value) => !value.is_null(),
+        _ => false,
+    };
+    let (op, lhs, rhs) = match conjunct {
+        Expr::Compare(op, lhs, rhs) => (*op, lhs.as_ref(), rhs.as_ref()),
+        _ => return None,
+    };
+    match (lhs, rhs) {
+        (Expr::Column(column), value) if is_value(value) => Some(KeyPred {
+            column: *column,
+            op,
+            value: value.clone(),
+        }),
+        (value, Expr::Column(column)) if is_value(value) => {
+            let op = match op {
+                CmpOp::Lt => CmpOp::Gt,
+                CmpOp::Le => CmpOp::Ge,
+                CmpOp::Gt => CmpOp::Lt,
+                CmpOp::Ge => CmpOp::Le,
+                op => op,
+            };
+            Some(KeyPred {
+                column: *column,
+                op,
+                value: value.clone(),
+            })
+        }
+        _ => None,
+    }
+}
+
+// キーの先頭から等号で決まる列の値と、その次の列の範囲
+#[derive(Default)]
+struct KeyRange {
+    eq: Vec<Expr>,
+    // (値, 値を含むか)
+    lower: Option<(Expr, bool)>,
+    upper: Option<(Expr, bool)>,
+    // 探す範囲を決めるのに使った述語の位置
+    used: Vec<usize>,
+    // そのうち探す範囲だけで確かめられるもの (Filter に残さない)
+    exact: Vec<usize>,
+}
+
+impl KeyRange {
+    fn new(key_columns: &[usize], conjuncts: &[Expr]) -> Self {
+        let preds: Vec<_> = conjuncts.iter().map(key_pred).collect();
+        let mut range = Self::default();
+        for &column in key_columns {
+            let eq = preds.iter().position(
+                |pred| matches!(pred, Some(pred) if pred.column == column && pred.op == CmpOp::Eq),
+            );
+            if let Some(i) = eq {
+                range.eq.push(preds[i].as_ref().unwrap().value.clone());
+                range.used.push(i);
+                range.exact.push(i);
+                continue;
+            }
+            for (i, pred) in preds.iter().enumerate() {
+                let pred = match pred {
+                    Some(pred) if pred.column == column => pred,
+                    _ => continue,
+                };
+                match pred.op {
+                    CmpOp::Gt | CmpOp::Ge if range.lower.is_none() => {
+                        // 値を含まない下限は探し始めたところで読み飛ばすので残す
+                        let inclusive = pred.op == CmpOp::Ge;
+                        range.lower = Some((pred.value.clone(), inclusive));
+                        range.used.push(i);
+                        if inclusive {
+                            range.exact.push(i);
+                        }
+                    }
+                    CmpOp::Lt | CmpOp::Le if range.upper.is_none() => {
+                        range.upper = Some((pred.value.clone(), pred.op == CmpOp::Le));
+                        range.used.push(i);
+                        range.exact.push(i);
+                    }
+                    _ => {}
+                }
+            }
+            break;
+        }
+        range
+    }
+
+    // 探し始めるキーと、終わるキーか終わる条件
+    // 全てリテラルなら終わるキーにし、パラメータがあれば実行するまで値が分からないので
+    // キーの列を比べる条件にする
+    fn bounds(&self) -> (TupleSearchMode, Bound<Tuple>, Expr) {
+        let literals = |exprs: &mut dyn Iterator<Item = &Expr>| -> Option<Tuple> {
+            exprs
+                .map(|expr| match expr {
+                    Expr::Literal(value) => Some(value.encode()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let lower = self.lower.iter().map(|(value, _)| value);
+        let start: Vec<_> = self.eq.iter().chain(lower).cloned().collect();
+        let search_mode = if start.is_empty() {
+            TupleSearchMode::Start
+        } else {
+            match literals(&mut start.iter()) {
+                Some(key) => TupleSearchMode::Key(key),
+                None => TupleSearchMode::Exprs(start),
+            }
+        };
+
+        // 下限だけのパラメータに NULL を渡せば、どの行とも比べられないので一行も読まない
+        let lower_not_null = match &self.lower {
+            Some((param @ Expr::Param(_), _)) => {
+                Some(Expr::Not(Box::new(Expr::IsNull(Box::new(param.clone())))))
+            }
+            _ => None,
+        };
+        let upper = self.upper.iter().map(|(value, _)| value);
+        if let Some(stop_key) = literals(&mut self.eq.iter().chain(upper)) {
+            let stop_key = match self.upper {
+                Some((_, false)) => Bound::Excluded(stop_key),
+                Some((_, true)) => Bound::Included(stop_key),
+                None if stop_key.is_empty() => Bound::Unbounded,
+                None => Bound::Included(stop_key),
+            };
+            return (search_mode, stop_key, lower_not_null.unwrap_or(Expr::TRUE));
+        }
+        let mut conds: Vec<_> = self
+            .eq
+            .iter()
+            .enumerate()
+            .map(|(i, value)| Expr::compare(CmpOp::Eq, Expr::Column(i), value.clone()))
+            .collect();
+        if let Some((value, inclusive)) = &self.upper {
+            let op = if *inclusive { CmpOp::Le } else { CmpOp::Lt };
+            conds.push(Expr::compare(
+                op,
+                Expr::Column(self.eq.len()),
+                value.clone(),
+            ));
+        }
+        conds.extend(lower_not_null);
+        (search_mode, Bound::Unbounded, conjoin(conds))
+    }
+}
+
+fn columns_of<'e>(exprs: impl IntoIterator<Item = &'e Expr>) -> Vec<usize> {
+    let mut columns = vec![];
+    for expr in exprs {
+        expr.columns(&mut columns);
+    }
+    columns
+}
+
+// column が layout の何番目の列か
+fn position(layout: &[usize], column: usize) -> usize {
+    layout
+        .iter()
+        .position(|&c| c == column)
+        .expect("column not covered by plan")
+}
+
+// 組み立てたプランと見積もり
+struct Planned<T> {
+    plan: Plan<T>,
+    cost: Cost,
+    // 出力の列ごとの値の見積もり
+    columns: Vec<ColumnEstimate>,
+    // 等号の述語で値が一つに決まる出力の列 (並び順を比べるときに無視できる)
+    fixed: Vec<usize>,
+}
+
+impl<T> Clone for Planned<T> {
+    fn clone(&self) -> Self {
+        Self {
+            plan: self.plan.clone(),
+            cost: self.cost,
+            columns: self.columns.clone(),
+            fixed: self.fixed.clone(),
+        }
+    }
+}
+
+impl<T: BufferPoolManager> Planned<T> {
+    // 出力の行が keys の順に並んでいるか
+    // 値が一つに決まる列は、どこに挟まっていても並び順を変えないので両方から除いて比べる
+    // (a, b) の複合インデックスを a = 1 で探せば b の順に並んでいる
+    fn ordered_by(&self, keys: &[SortKey]) -> bool {
+        let unfixed = |keys: &mut dyn Iterator<Item = SortKey>| -> Vec<_> {
+            keys.filter(|key| !self.fixed.contains(&key.column))
+                .collect()
+        };
+        satisfies_ordering(
+            &unfixed(&mut self.plan.output_ordering().into_iter()),
+            &unfixed(&mut keys.iter().copied()),
+        )
+    }
+}
+
+// 列と値を等号で比べる述語の列 (値はリテラルかパラメータなので、満たす行ではその列の値は一つ)
+fn fixed_columns(conds: &[Expr]) -> Vec<usize> {
+    conds
+        .iter()
+        .filter_map(key_pred)
+        .filter(|pred| pred.op == CmpOp::Eq)
+        .map(|pred| pred.column)
+        .collect()
+}
+
+// 結合するものの一つと、元の行のどこからどこまでの列か
+struct Leaf<'p> {
+    plan: &'p LogicalPlan,
+    offset: usize,
+    width: usize,
+    // この列だけを見る述語 (leaf の列で書いたもの)
+    conds: Vec<Expr>,
+}
+
+// 結合するものの一部を結合したプランと、その出力の列が元の行のどの列か
+struct Joined<T> {
+    planned: Planned<T>,
+    layout: Vec<usize>,
+}
+
+// 二つ以上の結合するものの列を見る述語と、見ている結合するもの (ビットの集合)
+struct JoinCond {
+    cond: Expr,
+    leaves: usize,
+}
+
+impl<'c> Planner<'c> {
+    pub fn new(
+        catalog: &'c Catalog,
+        visibility: Arc<dyn Visibility + Send + Sync>,
+        work_mem: usize,
+    ) -> Self {
+        Self {
+            catalog,
+            index_only: visibility.is_all_visible(),
+            visibility,
+            work_mem,
+            cost_model: CostModel::default(),
+            costs: RefCell::default(),
+        }
+    }
+
+    pub fn plan<T: BufferPoolManager + 'static>(&self, plan: &LogicalPlan) -> Result<Plan<T>> {
+        self.costs.borrow_mut().clear();
+        let plan = rewrite(self.catalog, plan)?;
+        Ok(self.build(&plan)?.plan)
+    }
+
+    // input の行を table に INSERT するプラン (INSERT した行の数を返す)
+    // input が table を読むなら、書き足した行を読まないように先に読み切っておく
+    // sequence は主キーを振るテーブルのシーケンス (プランごとに開くと手元の値を捨てるので呼ぶ側が持つ)
+    pub fn plan_insert<T: BufferPoolManager + 'static>(
+        &self,
+        table: TableId,
+        input: &LogicalPlan,
+        on_conflict: &OnConflict,
+        sequence: Option<Arc<Mutex<Sequence>>>,
+    ) -> Result<Plan<T>> {
+        self.costs.borrow_mut().clear();
+        let schema = self.table(table)?;
+        let input = rewrite(self.catalog, input)?;
+        let mut planned = self.build(&input)?;
+        if input.reads(table) {
+            let cost = Cost {
+                rows: planned.cost.rows,
+                total: planned.cost.total + planned.cost.rows * self.cost_model.cpu_tuple_cost,
+            };
+            let materialize = Arc::new(Materialize {
+                inner_plan: planned.plan,
+                work_mem: self.work_mem,
+            });
+            planned = self.node(materialize, cost, planned.columns);
+        }
+        let insert = Arc::new(Insert {
+            table: Arc::new(schema.table()),
+            sequence,
+            inner_plan: planned.plan,
+            on_conflict: on_conflict.clone(),
+            types: schema.types(),
+            num_key_elems: schema.num_key_elems,
+        });
+        Ok(self
+            .node(insert, planned.cost, vec![ColumnEstimate::new(1.0)])
+            .plan)
+    }
+
+    // 最後に plan で組み立てたプランを、各ノードの見積もりを書き足して説明する (EXPLAIN)
+    pub fn explain<T: BufferPoolManager + 'static>(&self, plan: &Plan<T>) -> String {
+        let costs = self.costs.borrow();
+        plan.explain_with(&|node| match costs.get(&node) {
+            Some(cost) => format!(" {}", cost),
+            None => String::new(),
+        })
+    }
+
+    // 見積もりを覚えておく (同じアドレスに前に作って捨てたノードのものがあれば上書きする)
+    fn node<T: BufferPoolManager + 'static>(
+        &self,
+        plan: Plan<T>,
+        cost: Cost,
+        mut columns: Vec<ColumnEstimate>,
+    ) -> Planned<T> {
+        let node = Arc::as_ptr(&plan) as *const () as usize;
+        self.costs.borrow_mut().insert(node, cost);
+        for column in &mut columns {
+            column.distinct = column.distinct.min(cost.rows).max(1.0);
+        }
+        Planned {
+            plan,
+            cost,
+            columns,
+            fixed: vec![],
+        }
+    }
+
+    fn build<T: BufferPoolManager + 'static>(&self, plan: &LogicalPlan) -> Result<Planned<T>> {
+        Ok(match plan {
+            LogicalPlan::Scan { table } => self.scan(self.table(*table)?, vec![], None).0,
+            LogicalPlan::Filter { input, cond } => {
+                let mut conds = vec![];
+                conjuncts(cond, &mut conds);
+                match input.as_ref() {
+                    LogicalPlan::Scan { table } => self.scan(self.table(*table)?, conds, None).0,
+                    input @ LogicalPlan::Join { .. } => self.join(input, conds)?,
+                    input => {
+                        let input = self.build(input)?;
+                        self.filter(input, conds)
+                    }
+                }
+            }
+            LogicalPlan::Join { .. } => self.join(plan, vec![])?,
+            LogicalPlan::Project { input, exprs } => self.project(input, exprs)?,
+            LogicalPlan::Sort { input, keys } => {
+                let input = self.build(input)?;
+                self.sort(input, keys)
+            }
+            LogicalPlan::Limit {
+                input,
+                limit,
+                offset,
+            } => self.limit(input, limit.map(|limit| limit as usize), *offset as usize)?,
+            LogicalPlan::Values { rows } => {
+                let width = rows.first().map_or(0, Vec::len);
+                let mut plans: Vec<_> = rows
+                    .iter()
+                    .map(|exprs| {
+                        let empty = Arc::new(Values::new(vec![Row::from_values(vec![])]));
+                        let one = Cost {
+                            rows: 1.0,
+                            total: self.cost_model.cpu_tuple_cost,
+                        };
+                        let empty = self.node(empty, one, vec![]);
+                        self.projected(empty, exprs.clone())
+                    })
+                    .collect();
+                if plans.len() == 1 {
+                    plans.pop().unwrap()
+                } else {
+                    let cost = Cost {
+                        rows: plans.len() as f64,
+                        total: plans.iter().map(|planned| planned.cost.total).sum(),
+                    };
+                    let plan = Arc::new(Union {
+                        inner_plans: plans.into_iter().map(|planned| planned.plan).collect(),
+                        distinct: false,
+                    });
+                    self.node(plan, cost, vec![ColumnEstimate::new(cost.rows); width])
+                }
+            }
+        })
+    }
+
+    fn table(&self, id: TableId) -> Result<&'c TableSchema> {
+        match self.catalog.table_by_id(id) {
+            Some(table) => Ok(table),
+            None => bail!("table {} does not exist", id),
+        }
+    }
+
+    // テーブルの行とページの数と、列ごとの値の見積もり
+    fn table_stats(&self, table: &TableSchema) -> (f64, f64, Vec<ColumnEstimate>) {
+        // 一列の主キーや一意のインデックスの列は行ごとに違う
+        let unique = |column: usize| {
+            (column == 0 && table.num_key_elems == 1)
+                || table
+                    .indexes
+                    .iter()
+                    .any(|index| index.unique && index.columns == [column])
+        };
+        let stats = self.catalog.stats(table.id);
+        let (rows, pages) = match stats {
+            Some(stats) => (stats.rows as f64, stats.pages as f64),
+            None => (DEFAULT_ROWS, DEFAULT_ROWS / ROWS_PER_PAGE),
+        };
+        let known = |column: usize| {
+            stats?
+                .columns
+                .get(column)
+                .filter(|stats| stats.distinct > 0)
+        };
+        let columns = (0..table.columns.len())
+            .map(|column| match known(column) {
+                Some(column) => ColumnEstimate::from_stats(stats.unwrap().rows, column.clone()),
+                None if unique(column) => ColumnEstimate::new(rows),
+                None => ColumnEstimate::new(default_distinct(rows)),
+            })
+            .collect();
+        (rows.max(1.0), pages.max(1.0), columns)
+    }
+
+    fn filter<T: BufferPoolManager + 'static>(
+        &self,
+        input: Planned<T>,
+        conds: Vec<Expr>,
+    ) -> Planned<T> {
+        if conds.is_empty() {
+            return input;
+        }
+        let sel = conjunction(&conds, &input.columns);
+        let cost = Cost {
+            rows: input.cost.rows * sel,
+            total: input.cost.total
+                + input.cost.rows * self.cost_model.cpu_operator_cost * conds.len() as f64,
+        };
+        let mut fixed = input.fixed;
+        fixed.extend(fixed_columns(&conds));
+        let plan = Arc::new(Filter {
+            inner_plan: input.plan,
+            cond: conjoin(conds),
+        });
+        Planned {
+            fixed,
+            ..self.node(plan, cost, input.columns)
+        }
+    }
+
+    fn projected<T: BufferPoolManager + 'static>(
+        &self,
+        input: Planned<T>,
+        exprs: Vec<Expr>,
+    ) -> Planned<T> {
+        let rows = input.cost.rows;
+        let cost = Cost {
+            rows,
+            total: input.cost.total + rows * self.cost_model.cpu_operator_cost * exprs.len() as f64,
+        };
+        let columns = exprs
+            .iter()
+            .map(|expr| match expr {
+                Expr::Column(column) => input.columns[*column].clone(),
+                _ => ColumnEstimate::new(rows),
+            })
+            .collect();
+        let fixed = (0..exprs.len())
+            .filter(|&i| matches!(exprs[i], Expr::Column(column) if input.fixed.contains(&column)))
+            .collect();
+        let plan = Arc::new(Project {
+            inner_plan: input.plan,
+            exprs,
+        });
+        Planned {
+            fixed,
+            ..self.node(plan, cost, columns)
+        }
+    }
+
+    // テーブルの読み方を見積もって選び、探す範囲で確かめられない述語の Filter を重ねる
+    // 出力の行の各列がテーブルのどの列かも返す
+    // used を与えれば、それだけの列で足りるときはインデックスだけを読むものも比べる
+    fn scan<T: BufferPoolManager + 'static>(
+        &self,
+        table: &TableSchema,
+        conds: Vec<Expr>,
+        used: Option<&[usize]>,
+    ) -> (Planned<T>, Vec<usize>) {
+        if is_virtual(table.id) {
+            return self.virtual_scan(table, conds);
+        }
+        let (rows, pages, columns) = self.table_stats(table);
+        let model = &self.cost_model;
+        let types = table.types();
+        let pkey: Vec<_> = (0..table.num_key_elems).collect();
+        let all: Vec<_> = (0..table.columns.len()).collect();
+        let mut candidates = vec![];
+
+        let scanned = |range: &KeyRange| -> (f64, Vec<Expr>) {
+            let sel = conjunction(range.used.iter().map(|&i| &conds[i]), &columns);
+            let residual = conds
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !range.exact.contains(i))
+                .map(|(_, cond)| cond.clone())
+                .collect();
+            (rows * sel, residual)
+        };
+
+        let range = KeyRange::new(&pkey, &conds);
+        let (matched, residual) = scanned(&range);
+        let (search_mode, stop_key, while_cond) = range.bounds();
+        let mut total =
+            pages * matched / rows * model.seq_page_cost + matched * model.cpu_tuple_cost;
+        if !matches!(search_mode, TupleSearchMode::Start) {
+            total += model.random_page_cost;
+        }
+        let plan = Arc::new(SeqScan {
+            table_accessor: Arc::new(BTree::new(table.meta_page_id)),
+            search_mode,
+            num_key_elems: table.num_key_elems,
+            stop_key,
+            while_cond,
+            visibility: self.visibility.clone(),
+            schema: types.clone(),
+        });
+        let cost = Cost {
+            rows: matched,
+            total,
+        };
+        candidates.push((
+            self.node(plan, cost, columns.clone()),
+            all.clone(),
+            residual,
+        ));
+
+        for index in &table.indexes {
+            let range = KeyRange::new(&index.columns, &conds);
+            if range.used.is_empty() && used.is_none() {
+                continue;
+            }
+            let (matched, residual) = scanned(&range);
+            let (search_mode, stop_key, while_cond) = range.bounds();
+            // インデックスの項目は副キーと主キーだけなので、その分テーブルより小さい
+            let width = (index.columns.len() + table.num_key_elems) as f64;
+            let index_pages = (pages * width / table.columns.len() as f64).max(1.0);
+            let searched = model.index_search(index_pages * matched / rows);
+
+            let covered =
+                |column: &usize| *column < table.num_key_elems || index.columns.contains(column);
+            let covering =
+                used.is_some_and(|used| used.iter().chain(&columns_of(&residual)).all(covered));
+            if self.index_only && covering {
+                let layout: Vec<_> = index.columns.iter().copied().chain(pkey.clone()).collect();
+                let plan = Arc::new(IndexOnlyScan {
+                    index_accessor: Arc::new(BTree::new(index.meta_page_id)),
+                    search_mode,
+                    num_key_elems: index.columns.len(),
+                    stop_key,
+                    while_cond,
+                    schema: layout.iter().map(|&column| types[column]).collect(),
+                });
+                let cost = Cost {
+                    rows: matched,
+                    total: searched + matched * model.cpu_tuple_cost,
+                };
+                let columns = layout
+                    .iter()
+                    .map(|&column| columns[column].clone())
+                    .collect();
+                let residual = residual
+                    .iter()
+                    .map(|cond| cond.map_columns(&|column| position(&layout, column)))
+                    .collect();
+                candidates.push((self.node(plan, cost, columns), layout, residual));
+                continue;
+            }
+            if range.used.is_empty() {
+                continue;
+            }
+            // 見つけた項目ごとにテーブルを主キーで引く
+            let plan = Arc::new(IndexScan {
+                table_accessor: Arc::new(BTree::new(table.meta_page_id)),
+                index_accessor: Arc::new(BTree::new(index.meta_page_id)),
+                search_mode,
+                skey: index.columns.clone(),
+                stop_key,
+                while_cond,
+                visibility: self.visibility.clone(),
+                schema: types.clone(),
+            });
+            let cost = Cost {
+                rows: matched,
+                total: searched + matched * (model.random_page_cost + model.cpu_tuple_cost),
+            };
+            candidates.push((
+                self.node(plan, cost, columns.clone()),
+                all.clone(),
+                residual,
+            ));
+        }
+
+        // 同じ費用なら先に加えた主キーのものを選ぶ
+        let (mut planned, layout) = candidates
+            .into_iter()
+            .map(|(planned, layout, residual)| (self.filter(planned, residual), layout))
+            .reduce(|best, candidate| {
+                if candidate.0.cost.total < best.0.cost.total {
+                    candidate
+                } else {
+                    best
+                }
+            })
+            .unwrap();
+        // 探す範囲で確かめて Filter に残さなかった等号の列も値が一つに決まる
+        planned.fixed = fixed_columns(&conds)
+            .into_iter()
+            .filter_map(|column| layout.iter().position(|&c| c == column))
+            .collect();
+        (planned, layout)
+    }
+
+    // 仮想テーブルの行をプランを組み立てるときのカタログから作って並べ、述語は全て Filter で確かめる
+    fn virtual_scan<T: BufferPoolManager + 'static>(
+        &self,
+        table: &TableSchema,
+        conds: Vec<Expr>,
+    ) -> (Planned<T>, Vec<usize>) {
+        let rows: Vec<_> = introspect::rows(self.catalog, table.id)
+            .into_iter()
+            .map(Row::from_values)
+            .collect();
+        let cost = Cost {
+            rows: rows.len() as f64,
+            total: rows.len() as f64 * self.cost_model.cpu_tuple_cost,
+        };
+        let columns = vec![ColumnEstimate::new(cost.rows); table.columns.len()];
+        let plan = Arc::new(Values::new(rows));
+        let planned = self.filter(self.node(plan, cost, columns), conds);
+        (planned, (0..table.columns.len()).collect())
+    }
+
+    // 結合の木を結合するものに分け、その条件を集める (列は元の行の位置で書く)
+    fn flatten<'p>(
+        &self,
+        plan: &'p LogicalPlan,
+        offset: usize,
+        leaves: &mut Vec<Leaf<'p>>,
+        conds: &mut Vec<Expr>,
+    ) -> Result<()> {
+        match plan {
+            LogicalPlan::Join { left, right, cond } => {
+                let mut join_conds = vec![];
+                conjuncts(cond, &mut join_conds);
+                conds.extend(
+                    join_conds
+                        .iter()
+                        .map(|cond| cond.map_columns(&|column| column + offset)),
+                );
+                self.flatten(left, offset, leaves, conds)?;
+                self.flatten(right, offset + left.width(self.catalog)?, leaves, conds)
+            }
+            plan => {
+                leaves.push(Leaf {
+                    plan,
+                    offset,
+                    width: plan.width(self.catalog)?,
+                    conds: vec![],
+                });
+                Ok(())
+            }
+        }
+    }
+
+    // 内部結合の木を、結合するものの順と結合の仕方を選び直して組み立てる
+    // 一つの結合するものだけを見る述語はそれを読むところへ下ろし、
+    // 残りはそれが見る全てを結合したところで確かめる
+    // 最後に元の列の順に並べ直す
+    fn join<T: BufferPoolManager + 'static>(
+        &self,
+        plan: &LogicalPlan,
+        mut conds: Vec<Expr>,
+    ) -> Result<Planned<T>> {
+        let mut leaves = vec![];
+        self.flatten(plan, 0, &mut leaves, &mut conds)?;
+        if leaves.len() >= usize::BITS as usize {
+            bail!("too many tables to join: {}", leaves.len());
+        }
+        let (mut join_conds, mut constant_conds) = (vec![], vec![]);
+        for cond in conds {
+            let columns = columns_of([&cond]);
+            let mask: usize = leaves
+                .iter()
+                .enumerate()
+                .filter(|(_, leaf)| {
+                    columns
+                        .iter()
+                        .any(|&column| (leaf.offset..leaf.offset + leaf.width).contains(&column))
+                })
+                .fold(0, |mask, (i, _)| mask | 1 << i);
+            if mask == 0 {
+                constant_conds.push(cond);
+            } else if mask.count_ones() == 1 {
+                let leaf = &mut leaves[mask.trailing_zeros() as usize];
+                let offset = leaf.offset;
+                leaf.conds.push(cond.map_columns(&|column| column - offset));
+            } else {
+                join_conds.push(JoinCond { cond, leaves: mask });
+            }
+        }
+
+        let mut inputs = vec![];
+        for leaf in &leaves {
+            let plan = match leaf.conds.is_empty() {
+                true => leaf.plan.clone(),
+                false => LogicalPlan::Filter {
+                    input: Box::new(leaf.plan.clone()),
+                    cond: conjoin(leaf.conds.clone()),
+                },
+            };
+            inputs.push(self.build::<T>(&plan)?);
+        }
+        let single = |i: usize| Joined {
+            planned: inputs[i].clone(),
+            layout: (leaves[i].offset..leaves[i].offset + leaves[i].width).collect(),
+        };
+
+        let n = leaves.len();
+        let joined = if n <= MAX_JOIN_SEARCH {
+            // 結合したものの集合ごとに一番安いものを、一つ少ない集合に一つを結合して求める
+            let mut best: Vec<Option<Joined<T>>> = (0..1 << n).map(|_| None).collect();
+            for i in 0..n {
+                best[1 << i] = Some(single(i));
+            }
+            for set in 1..1usize << n {
+                if set.count_ones() < 2 {
+                    continue;
+                }
+                for i in (0..n).filter(|i| set & 1 << i != 0) {
+                    let rest = set & !(1 << i);
+                    let left = match &best[rest] {
+                        Some(left) => left,
+                        None => continue,
+                    };
+                    let joined = self.join_step(left, rest, &leaves, &inputs, i, &join_conds)?;
+                    let better = match &best[set] {
+                        Some(current) => joined.planned.cost.total < current.planned.cost.total,
+                        None => true,
+                    };
+                    if better {
+                        best[set] = Some(joined);
+                    }
+                }
+            }
+            best.pop().unwrap().unwrap()
+        } else {
+            let mut joined = single(0);
+            for i in 1..n {
+                joined = self.join_step(&joined, (1 << i) - 1, &leaves, &inputs, i, &join_conds)?;
+            }
+            joined
+        };
+
+        let Joined {
+            mut planned,
+            layout,
+        } = joined;
+        if layout.iter().enumerate().any(|(i, &column)| i != column) {
+            let exprs = (0..layout.len())
+                .map(|column| Expr::Column(position(&layout, column)))
+                .collect();
+            planned = self.projected(planned, exprs);
+        }
+        Ok(self.filter(planned, constant_conds))
+    }
+
+    // left (結合したものの集合は left_set) に i 番目の結合するものを結合する
+    // NestedLoopJoin, MergeJoin, IndexNestedLoopJoin のうち一番安いもの
+    fn join_step<T: BufferPoolManager + 'static>(
+        &self,
+        left: &Joined<T>,
+        left_set: usize,
+        leaves: &[Leaf],
+        inputs: &[Planned<T>],
+        i: usize,
+        join_conds: &[JoinCond],
+    ) -> Result<Joined<T>> {
+        let model = &self.cost_model;
+        let leaf = &leaves[i];
+        let right = &inputs[i];
+        let set = left_set | 1 << i;
+        let layout: Vec<_> = left
+            .layout
+            .iter()
+            .copied()
+            .chain(leaf.offset..leaf.offset + leaf.width)
+            .collect();
+        let conds: Vec<_> = join_conds
+            .iter()
+            .filter(|cond| cond.leaves & !set == 0 && cond.leaves & 1 << i != 0)
+            .map(|cond| cond.cond.map_columns(&|column| position(&layout, column)))
+            .collect();
+        let left_width = left.layout.len();
+        let columns: Vec<_> = left
+            .planned
+            .columns
+            .iter()
+            .chain(&right.columns)
+            .cloned()
+            .collect();
+        let sel = conjunction(&conds, &columns);
+        let rows = left.planned.cost.rows * right.cost.rows * sel;
+        let output = rows * model.cpu_tuple_cost;
+        let joined = |planned| Joined {
+            planned,
+            layout: layout.clone(),
+        };
+
+        // 左の列と右の列の等号
+        let equi: Vec<_> = conds
+            .iter()
+            .filter_map(|cond| match cond {
+                Expr::Compare(CmpOp::Eq, lhs, rhs) => match (lhs.as_ref(), rhs.as_ref()) {
+                    (Expr::Column(a), Expr::Column(b)) if *a < left_width && *b >= left_width => {
+                        Some((*a, *b - left_width))
+                    }
+                    (Expr::Column(a), Expr::Column(b)) if *b < left_width && *a >= left_width => {
+                        Some((*b, *a - left_width))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+
+        // 右を一度だけ読んでメモリに持ち、左の行ごとに全て比べる
+        let materialized = Cost {
+            rows: right.cost.rows,
+            total: right.cost.total + right.cost.rows * model.cpu_tuple_cost,
+        };
+        let materialize = Arc::new(Materialize {
+            inner_plan: right.plan.clone(),
+            work_mem: self.work_mem,
+        });
+        let materialize = self.node(materialize, materialized, right.columns.clone());
+        let compared = left.planned.cost.rows
+            * right.cost.rows
+            * model.cpu_operator_cost
+            * conds.len().max(1) as f64;
+        let cost = Cost {
+            rows,
+            total: left.planned.cost.total + materialized.total + compared + output,
+        };
+        let plan = Arc::new(NestedLoopJoin {
+            outer_plan: left.planned.plan.clone(),
+            inner_plan: materialize.plan,
+            cond: conjoin(conds.clone()),
+            kind: JoinKind::Inner,
+        });
+        let mut best = joined(self.node(plan, cost, columns.clone()));
+
+        // 両方を等号の列で並べて突き合わせる
+        if !equi.is_empty() {
+            let outer = self.sort(
+                left.planned.clone(),
+                &equi
+                    .iter()
+                    .map(|&(a, _)| OrderKey {
+                        expr: Expr::Column(a),
+                        descending: false,
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            let inner = self.sort(
+                right.clone(),
+                &equi
+                    .iter()
+                    .map(|&(_, b)| OrderKey {
+                        expr: Expr::Column(b),
+                        descending: false,
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            let merged = (outer.cost.rows + inner.cost.rows) * model.cpu_operator_cost;
+            let cost = Cost {
+                rows: outer.cost.rows * inner.cost.rows
+                    / equi_distinct(&equi, &columns, left_width),
+                total: outer.cost.total + inner.cost.total + merged + output,
+            };
+            let plan = Arc::new(MergeJoin {
+                outer_plan: outer.plan,
+                inner_plan: inner.plan,
+                outer_keys: equi.iter().map(|&(a, _)| a).collect(),
+                inner_keys: equi.iter().map(|&(_, b)| b).collect(),
+                kind: JoinKind::Inner,
+            });
+            let merge = self.node(plan, cost, columns.clone());
+            let residual = conds
+                .iter()
+                .filter(|cond| !is_equi(cond, left_width))
+                .cloned()
+                .collect();
+            let merge = self.filter(merge, residual);
+            if merge.cost.total < best.planned.cost.total {
+                best = joined(merge);
+            }
+        }
+
+        // 左の行ごとに右のテーブルを主キーかインデックスで引く
+        if let LogicalPlan::Scan { table } = leaf.plan {
+            let table = self.table(*table)?;
+            let (table_rows, _, table_columns) = self.table_stats(table);
+            let pkey: Vec<_> = (0..table.num_key_elems).collect();
+            let keys = std::iter::once((&pkey, None)).chain(
+                table
+                    .indexes
+                    .iter()
+                    .map(|index| (&index.columns, Some(index))),
+            );
+            for (key_columns, index) in keys {
+                let outer_keys: Vec<_> = key_columns
+                    .iter()
+                    .map_while(|&key| equi.iter().find(|&&(_, b)| b == key).map(|&(a, _)| a))
+                    .collect();
+                if outer_keys.is_empty() {
+                    continue;
+                }
+                let per_key: f64 = key_columns[..outer_keys.len()]
+                    .iter()
+                    .map(|&column| table_columns[column].distinct)
+                    .product();
+                let matched = (table_rows / per_key).max(1.0);
+                let fetch = match index {
+                    Some(_) => model.random_page_cost + model.cpu_tuple_cost,
+                    None => model.cpu_tuple_cost,
+                };
+                let probes = left.planned.cost.rows * (model.random_page_cost + matched * fetch);
+                let cost = Cost {
+                    rows: left.planned.cost.rows * matched,
+                    total: left.planned.cost.total + probes,
+                };
+                let plan = Arc::new(IndexNestedLoopJoin {
+                    outer_plan: left.planned.plan.clone(),
+                    table_accessor: Arc::new(BTree::new(table.meta_page_id)),
+                    index_accessor: index.map(|index| {
+                        Arc::new(BTree::new(index.meta_page_id)) as SharedAccessMethod<_, _>
+                    }),
+                    outer_keys,
+                    visibility: self.visibility.clone(),
+                    schema: table.types(),
+                });
+                let columns = left
+                    .planned
+                    .columns
+                    .iter()
+                    .chain(&table_columns)
+                    .cloned()
+                    .collect();
+                let probed = self.node(plan, cost, columns);
+                // NULL のキーでも同じ空のキーの行を引いてしまうので、結合の条件も引いた後に確かめる
+                let right_conds = leaf
+                    .conds
+                    .iter()
+                    .map(|cond| cond.map_columns(&|column| column + left_width));
+                let probed = self.filter(probed, right_conds.chain(conds.clone()).collect());
+                if probed.cost.total < best.planned.cost.total {
+                    best = joined(probed);
+                }
+            }
+        }
+        Ok(best)
+    }
+
+    fn project<T: BufferPoolManager + 'static>(
+        &self,
+        input: &LogicalPlan,
+        exprs: &[Expr],
+    ) -> Result<Planned<T>> {
+        // テーブルを読んで述語で選んだものだけは、読み方と合わせて列を付け替える
+        let mut conds = vec![];
+        let table = match input {
+            LogicalPlan::Scan { table } => Some(*table),
+            LogicalPlan::Filter { input, cond } => match input.as_ref() {
+                LogicalPlan::Scan { table } => {
+                    conjuncts(cond, &mut conds);
+                    Some(*table)
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        let table = match table {
+            Some(table) => table,
+            None => {
+                let input = self.build(input)?;
+                return Ok(self.projected(input, exprs.to_vec()));
+            }
+        };
+        let used = columns_of(exprs.iter().chain(&conds));
+        let (input, layout) = self.scan(self.table(table)?, conds, Some(&used));
+        let exprs = exprs
+            .iter()
+            .map(|expr| expr.map_columns(&|column| position(&layout, column)))
+            .collect();
+        Ok(self.projected(input, exprs))
+    }
+
+    // 並べ替えが要るときだけ Sort を重ねる
+    // 列でない並べ方の式は行の後ろに足して並べ、並べた後に取り除く
+    fn sort<T: BufferPoolManager + 'static>(
+        &self,
+        input: Planned<T>,
+        keys: &[OrderKey],
+    ) -> Planned<T> {
+        let width = input.columns.len();
+        let mut extra = vec![];
+        let sort_keys: Vec<_> = keys
+            .iter()
+            .map(|key| {
+                let column = match &key.expr {
+                    Expr::Column(column) => *column,
+                    expr => {
+                        extra.push(expr.clone());
+                        width + extra.len() - 1
+                    }
+                };
+                SortKey {
+                    column,
+                    descending: key.descending,
+                }
+            })
+            .collect();
+        if input.ordered_by(&sort_keys) {
+            return input;
+        }
+        let identity = || (0..width).map(Expr::Column);
+        let input = match extra.is_empty() {
+            true => input,
+            false => self.projected(input, identity().chain(extra.clone()).collect()),
+        };
+        let cost = Cost {
+            rows: input.cost.rows,
+            total: input.cost.total + self.cost_model.sort(input.cost.rows),
+        };
+        let plan = Arc::new(Sort {
+            inner_plan: input.plan,
+            sort_keys,
+            work_mem: self.work_mem,
+        });
+        let sorted = self.node(plan, cost, input.columns);
+        match extra.is_empty() {
+            true => sorted,
+            false => self.projected(sorted, identity().collect()),
+        }
+    }
+
+    // 並べた後の LIMIT は、並べ替えが要るなら上位の行だけを持つ TopN にする
+    fn limit<T: BufferPoolManager + 'static>(
+        &self,
+        input: &LogicalPlan,
+        limit: Option<usize>,
+        offset: usize,
+    ) -> Result<Planned<T>> {
+        let (sort, exprs) = match input {
+            LogicalPlan::Project { input, exprs } => (input.as_ref(), Some(exprs)),
+            input => (input, None),
+        };
+        let mut top = None;
+        if let (LogicalPlan::Sort { input, keys }, Some(limit)) = (sort, limit) {
+            let sort_keys: Option<Vec<_>> = keys
+                .iter()
+                .map(|key| match key.expr {
+                    Expr::Column(column) => Some(SortKey {
+                        column,
+                        descending: key.descending,
+                    }),
+                    _ => None,
+                })
+                .collect();
+            let input: Planned<T> = self.build(input)?;
+            if let Some(sort_keys) = sort_keys.filter(|keys| !input.ordered_by(keys)) {
+                let kept = (limit + offset) as f64;
+                let cost = Cost {
+                    rows: input.cost.rows.min(kept),
+                    total: input.cost.total
+                        + input.cost.rows
+                            * self.cost_model.cpu_operator_cost
+                            * kept.max(2.0).log2(),
+                };
+                let plan = Arc::new(TopN {
+                    inner_plan: input.plan,
+                    sort_keys,
+                    limit: limit + offset,
+                });
+                let planned = self.node(plan, cost, input.columns);
+                top = Some(match exprs {
+                    Some(exprs) => self.projected(planned, exprs.clone()),
+                    None => planned,
+                });
+            }
+        }
+        let (input, limit) = match top {
+            Some(top) if offset == 0 => return Ok(top),
+            Some(top) => (top, None),
+            None => (self.build(input)?, limit),
+        };
+        let mut rows = (input.cost.rows - offset as f64).max(0.0);
+        if let Some(limit) = limit {
+            rows = rows.min(limit as f64);
+        }
+        let cost = Cost {
+            rows,
+            total: input.cost.total,
+        };
+        let plan = Arc::new(Limit {
+            inner_plan: input.plan,
+            limit,
+            offset,
+        });
+        Ok(self.node(plan, cost, input.columns))
+    }
+}
+
+// 等号の列の組を全て満たす割合の逆数 (種類の多い方の値の種類の数を掛けたもの)
+fn equi_distinct(equi: &[(usize, usize)], columns: &[ColumnEstimate], left_width: usize) -> f64 {
+    equi.iter()
+        .map(|&(a, b)| {
+            columns[a]
+                .distinct
+                .max(columns[left_width + b].distinct)
+                .max(1.0)
+        })
+        .product()
+}
+
+// 左の列と右の列の等号か
+fn is_equi(cond: &Expr, left_width: usize) -> bool {
+    match cond {
+        Expr::Compare(CmpOp::Eq, lhs, rhs) => match (lhs.as_ref(), rhs.as_ref()) {
+            (Expr::Column(a), Expr::Column(b)) => (*a < left_width) != (*b < left_width),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdbms::{
+        binder::{bind, BoundStatement},
+        catalog::{ColumnSchema, ColumnStats, IndexSchema, TableStats},
+        clocksweep::ClockSweepManager,
+        expr::with_params,
+        memory::MemoryManager,
+        mvcc::{AllVisible, TxnSnapshot},
+        table::{NonUniqueIndex, Table, UniqueIndex},
+    };
+    use crate::sql::{
+        ddl::table::Table as ITable,
+        dml::entity::{Type, Value},
+        parser::parse_statement,
+    };
+    use crate::storage::entity::PageId;
+    use crate::wal::entity::TxnId;
+    use std::collections::BTreeSet;
+
+    type Bufmgr = ClockSweepManager<MemoryManager>;
+
+    fn insert(table: &Table, bufmgr: &mut Bufmgr, values: &[Value]) {
+        let record: Vec<_> = values.iter().map(Value::encode).collect();
+        let record: Vec<_> = record.iter().map(Vec::as_slice).collect();
+        table.insert(bufmgr, &record).unwrap();
+    }
+
+    // people (id, name, dept) は name に一意のインデックスがある
+    fn setup() -> (Bufmgr, Catalog) {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut people = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![UniqueIndex {
+                meta_page_id: PageId::INVALID_PAGE_ID,
+                skey: vec![1],
+            }],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        people.create(&mut bufmgr).unwrap();
+        for (id, name, dept) in [
+            (1, "alice", 10),
+            (2, "bob", 20),
+            (3, "carol", 10),
+            (4, "dave", 30),
+        ] {
+            let record = [Value::Int(id), Value::Bytes(name.into()), Value::Int(dept)];
+            insert(&people, &mut bufmgr, &record);
+        }
+        let mut dept = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        dept.create(&mut bufmgr).unwrap();
+        for (id, title) in [(10, "dev"), (20, "ops"), (30, "dev")] {
+            insert(
+                &dept,
+                &mut bufmgr,
+                &[Value::Int(id), Value::Bytes(title.into())],
+            );
+        }
+
+        let column = |name: &str, column_type| ColumnSchema {
+            name: name.to_string(),
+            column_type,
+            nullable: true,
+        };
+        let mut catalog = Catalog::new();
+        let columns = vec![
+            column("id", Type::Int),
+            column("name", Type::Bytes),
+            column("dept", Type::Int),
+        ];
+        let id = catalog
+            .add_table("people", columns, 1, people.meta_page_id)
+            .unwrap();
+        let index = IndexSchema {
+            name: "people_name".to_string(),
+            columns: vec![1],
+            unique: true,
+            meta_page_id: people.unique_indices[0].meta_page_id,
+        };
+        catalog.add_index(id, index).unwrap();
+        let columns = vec![column("id", Type::Int), column("title", Type::Bytes)];
+        catalog
+            .add_table("dept", columns, 1, dept.meta_page_id)
+            .unwrap();
+        (bufmgr, catalog)
+    }
+
+    fn plan_of(catalog: &Catalog, sql: &str) -> Plan<Bufmgr> {
+        let planner = Planner::new(catalog, Arc::new(AllVisible), 1 << 20);
+        match bind(catalog, &parse_statement(sql).unwrap()).unwrap() {
+            BoundStatement::Query { plan, .. } => planner.plan(&plan).unwrap(),
+            statement => panic!("not a query: {:?}", statement),
+        }
+    }
+
+    fn run(bufmgr: &mut Bufmgr, plan: &Plan<Bufmgr>, params: Vec<Value>) -> Vec<Vec<Value>> {
+        with_params(params, || {
+            let mut exec = plan.start(bufmgr).unwrap();
+            let mut rows = vec![];
+            while let Some(row) = exec.next(bufmgr).unwrap() {
+                rows.push((0..row.len()).map(|i| row.get(i).unwrap()).collect());
+            }
+            rows
+        })
+    }
+
+    // プランの木のノードの名前を上から並べる
+    fn nodes(plan: &Plan<Bufmgr>) -> Vec<String> {
+        plan.explain()
+            .lines()
+            .map(|line| {
+                let line = line.trim_start().trim_start_matches("-> ");
+                line.split(' ').next().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    fn ids(rows: Vec<Vec<Value>>) -> Vec<Value> {
+        rows.into_iter().map(|row| row[0].clone()).collect()
+    }
+
+    #[test]
+    fn access_path_test() {
+        let (mut bufmgr, catalog) = setup();
+        let bob = || vec![Value::Bytes(b"bob".to_vec())];
+        for (sql, params, expected_nodes, expected_ids) in [
+            (
+                "SELECT * FROM people",
+                vec![],
+                "Project SeqScan",
+                vec![1, 2, 3, 4],
+            ),
+            // 主キーの範囲は探す範囲だけで確かめる
+            (
+                "SELECT * FROM people WHERE id >= 2 AND id < 4",
+                vec![],
+                "Project SeqScan",
+                vec![2, 3],
+            ),
+            // 値を含まない下限と、キーでない列の述語は Filter に残す
+            (
+                "SELECT * FROM people WHERE id > 2 AND dept = 10",
+                vec![],
+                "Project Filter SeqScan",
+                vec![3],
+            ),
+            (
+                "SELECT * FROM people WHERE name = 'bob'",
+                vec![],
+                "Project IndexScan",
+                vec![2],
+            ),
+            // 副キーと主キーだけで足りればテーブルを読まない
+            (
+                "SELECT id FROM people WHERE name = 'bob'",
+                vec![],
+                "Project IndexOnlyScan",
+                vec![2],
+            ),
+            (
+                "SELECT id FROM people WHERE id = $1",
+                vec![Value::Int(3)],
+                "Project SeqScan",
+                vec![3],
+            ),
+            // 定数を畳んでから探す範囲を決める
+            (
+                "SELECT * FROM people WHERE name = 'bob' AND 1 + 1 = 2",
+                vec![],
+                "Project IndexScan",
+                vec![2],
+            ),
+            // 広い範囲の行を一行ずつテーブルから引くより、全体を読む方が安い
+            (
+                "SELECT * FROM people WHERE 'b' <= name AND name < $1",
+                bob(),
+                "Project Filter SeqScan",
+                vec![],
+            ),
+            (
+                "SELECT * FROM people WHERE name <= $1 AND dept = 10",
+                bob(),
+                "Project Filter SeqScan",
+                vec![1],
+            ),
+        ] {
+            let plan = plan_of(&catalog, sql);
+            assert_eq!(expected_nodes, nodes(&plan).join(" "), "{}", sql);
+            let expected: Vec<_> = expected_ids.into_iter().map(Value::Int).collect();
+            assert_eq!(expected, ids(run(&mut bufmgr, &plan, params)), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn join_test() {
+        let (mut bufmgr, catalog) = setup();
+        // 片方だけの述語はそれぞれの入力へ下ろし、LIMIT のある ORDER BY は TopN にする
+        let sql = "SELECT p.name FROM people p JOIN dept d ON p.dept = d.id \
+                   WHERE d.title = 'dev' AND p.id > 1 ORDER BY p.id DESC LIMIT 1";
+        let plan = plan_of(&catalog, sql);
+        assert_eq!(
+            vec![
+                "Project",
+                "TopN",
+                "NestedLoopJoin",
+                "Filter",
+                "SeqScan",
+                "Materialize",
+                "Filter",
+                "SeqScan"
+            ],
+            nodes(&plan)
+        );
+        let rows = run(&mut bufmgr, &plan, vec![]);
+        assert_eq!(vec![vec![Value::Bytes(b"dave".to_vec())]], rows);
+
+        // 主キーの順に読めば並べ替えは要らない
+        let plan = plan_of(
+            &catalog,
+            "SELECT id FROM people ORDER BY id LIMIT 2 OFFSET 1",
+        );
+        assert_eq!(vec!["Limit", "Project", "SeqScan"], nodes(&plan));
+        assert_eq!(
+            vec![Value::Int(2), Value::Int(3)],
+            ids(run(&mut bufmgr, &plan, vec![]))
+        );
+    }
+
+    #[test]
+    fn composite_index_test() {
+        let (mut bufmgr, mut catalog) = setup();
+        // staff (id, dept, name) は (dept, name) に一意でないインデックスがある
+        let mut staff = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![],
+            non_unique_indices: vec![NonUniqueIndex {
+                meta_page_id: PageId::INVALID_PAGE_ID,
+                skey: vec![1, 2],
+            }],
+            schema: None,
+        };
+        staff.create(&mut bufmgr).unwrap();
+        for (id, dept, name) in [
+            (1, 20, "erin"),
+            (2, 10, "dave"),
+            (3, 20, "bob"),
+            (4, 10, "alice"),
+            (5, 10, "carol"),
+        ] {
+            let record = [Value::Int(id), Value::Int(dept), Value::Bytes(name.into())];
+            insert(&staff, &mut bufmgr, &record);
+        }
+        let column = |name: &str, column_type| ColumnSchema {
+            name: name.to_string(),
+            column_type,
+            nullable: true,
+        };
+        let columns = vec![
+            column("id", Type::Int),
+            column("dept", Type::Int),
+            column("name", Type::Bytes),
+        ];
+        let id = catalog
+            .add_table("staff", columns, 1, staff.meta_page_id)
+            .unwrap();
+        let index = IndexSchema {
+            name: "staff_dept_name".to_string(),
+            columns: vec![1, 2],
+            unique: false,
+            meta_page_id: staff.non_unique_indices[0].meta_page_id,
+        };
+        catalog.add_index(id, index).unwrap();
+
+        for (sql, params, expected_nodes, expected_ids) in [
+            // 先頭の列だけでも探せて、残りの列の順に並んでいる
+            (
+                "SELECT * FROM staff WHERE dept = 10",
+                vec![],
+                "Project IndexOnlyScan",
+                vec![4, 5, 2],
+            ),
+            (
+                "SELECT * FROM staff WHERE dept = 10 ORDER BY name",
+                vec![],
+                "Project IndexScan",
+                vec![4, 5, 2],
+            ),
+            (
+                "SELECT id FROM staff WHERE dept = $1 ORDER BY dept, name DESC",
+                vec![Value::Int(20)],
+                "Project Sort IndexScan",
+                vec![1, 3],
+            ),
+            (
+                "SELECT id, name FROM staff WHERE dept = $1 ORDER BY name LIMIT 1",
+                vec![Value::Int(20)],
+                "Limit Project IndexScan",
+                vec![3],
+            ),
+            // 先頭の列が決まらなければ並べ替える
+            (
+                "SELECT id FROM staff WHERE name = 'bob' ORDER BY dept",
+                vec![],
+                "Project Sort Filter SeqScan",
+                vec![3],
+            ),
+        ] {
+            let plan = plan_of(&catalog, sql);
+            assert_eq!(expected_nodes, nodes(&plan).join(" "), "{}", sql);
+            let expected: Vec<_> = expected_ids.into_iter().map(Value::Int).collect();
+            assert_eq!(expected, ids(run(&mut bufmgr, &plan, params)), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn cost_test() {
+        let (mut bufmgr, mut catalog) = setup();
+        let sql = "SELECT * FROM people WHERE name = 'bob'";
+        assert_eq!(
+            "Project IndexScan",
+            nodes(&plan_of(&catalog, sql)).join(" ")
+        );
+        // スナップショットで読むなら、インデックスの項目だけで行を返さない
+        let covered_sql = "SELECT id FROM people WHERE name = 'bob'";
+        assert_eq!(
+            "Project IndexOnlyScan",
+            nodes(&plan_of(&catalog, covered_sql)).join(" ")
+        );
+        let BoundStatement::Query { plan, .. } =
+            bind(&catalog, &parse_statement(covered_sql).unwrap()).unwrap()
+        else {
+            unreachable!()
+        };
+        let snapshot = TxnSnapshot::new(TxnId(1), TxnId(2), BTreeSet::new());
+        let planner = Planner::new(&catalog, Arc::new(snapshot), 1 << 20);
+        let plan: Plan<Bufmgr> = planner.plan(&plan).unwrap();
+        assert_eq!("Project IndexScan", nodes(&plan).join(" "));
+
+        // 一ページしかなければ、インデックスを引くより全体を読む方が安い
+        let people = catalog.table("people").unwrap().id;
+        let columns = [4, 4, 3]
+            .iter()
+            .map(|&distinct| ColumnStats {
+                distinct,
+                ..Default::default()
+            })
+            .collect();
+        let stats = TableStats {
+            rows: 4,
+            pages: 1,
+            columns,
+        };
+        catalog.set_stats(people, stats).unwrap();
+        let planner = Planner::new(&catalog, Arc::new(AllVisible), 1 << 20);
+        let BoundStatement::Query { plan, .. } =
+            bind(&catalog, &parse_statement(sql).unwrap()).unwrap()
+        else {
+            unreachable!()
+        };
+        let plan: Plan<Bufmgr> = planner.plan(&plan).unwrap();
+        assert_eq!("Project Filter SeqScan", nodes(&plan).join(" "));
+        let explain = planner.explain(&plan);
+        assert!(
+            explain.lines().all(|line| line.contains("(cost=")),
+            "{}",
+            explain
+        );
+        assert!(
+            explain.contains("-> SeqScan (cost=1.04 rows=4)"),
+            "{}",
+            explain
+        );
+        assert_eq!(vec![Value::Int(2)], ids(run(&mut bufmgr, &plan, vec![])));
+    }
+
+    #[test]
+    fn join_order_test() {
+        let (mut bufmgr, catalog) = setup();
+        // 一行に絞れる people を先に読み、dept は主キーで引く (列は書いた順に並べ直す)
+        let sql =
+            "SELECT d.title, p.id FROM dept d JOIN people p ON p.dept = d.id WHERE p.name = 'bob'";
+        let plan = plan_of(&catalog, sql);
+        assert_eq!(
+            vec![
+                "Project",
+                "Project",
+                "Filter",
+                "IndexNestedLoopJoin",
+                "IndexScan"
+            ],
+            nodes(&plan)
+        );
+        let rows = run(&mut bufmgr, &plan, vec![]);
+        assert_eq!(
+            vec![vec![Value::Bytes(b"ops".to_vec()), Value::Int(2)]],
+            rows
+        );
+
+        // 絞れなければ、両方を結合の列で並べて突き合わせる
+        let sql = "SELECT p.id, d.title FROM people p JOIN dept d ON p.dept = d.id";
+        let plan = plan_of(&catalog, sql);
+        assert_eq!(
+            vec![
+                "Project",
+                "Project",
+                "MergeJoin",
+                "SeqScan",
+                "Sort",
+                "SeqScan"
+            ],
+            nodes(&plan)
+        );
+        let mut rows = run(&mut bufmgr, &plan, vec![]);
+        rows.sort();
+        let expected: Vec<_> = [(1, "dev"), (2, "ops"), (3, "dev"), (4, "dev")]
+            .iter()
+            .map(|&(id, title)| vec![Value::Int(id), Value::Bytes(title.into())])
+            .collect();
+        assert_eq!(expected, rows);
+    }
+}
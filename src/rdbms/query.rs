@@ -1,503 +1,5049 @@
-use anyhow::Result;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{
+    btree_set,
+    hash_map::{self, DefaultHasher, HashMap},
+    BTreeSet, BinaryHeap, HashSet,
+};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
 
-use super::util::tuple;
+use super::{
+    expr::{current_params, with_params, Expr},
+    memctx::{MemoryContext, MemoryReservation},
+    mvcc::{TupleHeader, Visibility},
+    sequence::Sequence,
+    temp::{TempBufferManager, TupleRun, TupleRunReader},
+    util::tuple,
+};
 use crate::accessor::{
     entity::SearchMode,
-    method::{AccessMethod, HaveAccessMethod, Iterable},
+    method::{AccessMethod, HaveAccessMethod, Iterable, SharedAccessMethod},
 };
 use crate::buffer::manager::BufferPoolManager;
-use crate::sql::dml::{entity::Tuple, query::*};
+use crate::sql::{
+    ddl::table::Table as ITable,
+    dml::{
+        entity::{Row, Tuple, Type, Value},
+        query::*,
+    },
+};
 
 pub type TupleSlice<'a> = &'a [Vec<u8>];
 
-pub enum TupleSearchMode<'a> {
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // セカンダリインデックスの項目が指す主キーの行がテーブルにない
+    #[error("index entry {skey:02x?} points to missing row {pkey:02x?}")]
+    IndexCorruption { skey: Tuple, pkey: Tuple },
+}
+
+pub enum TupleSearchMode {
     Start,
-    Key(&'a [&'a [u8]]),
+    Key(Tuple),
+    // 実行器を作るときに評価してキーにする式 (Expr::Param で実行ごとに違うキーを探す)
+    Exprs(Vec<Expr>),
 }
 
-impl<'a> TupleSearchMode<'a> {
-    fn encode(&self) -> SearchMode {
+impl TupleSearchMode {
+    fn encode(&self) -> Result<SearchMode> {
+        let mut key = vec![];
         match self {
-            TupleSearchMode::Start => SearchMode::Start,
-            TupleSearchMode::Key(tuple) => {
-                let mut key = vec![];
-                tuple::encode(tuple.iter(), &mut key);
-                SearchMode::Key(key)
+            TupleSearchMode::Start => return Ok(SearchMode::Start),
+            TupleSearchMode::Key(tuple) => tuple::encode(tuple.iter(), &mut key),
+            TupleSearchMode::Exprs(exprs) => {
+                let empty = Row::from_values(vec![]);
+                let values = exprs
+                    .iter()
+                    .map(|expr| Ok(expr.eval(&empty)?.encode()))
+                    .collect::<Result<Vec<_>>>()?;
+                tuple::encode(values.iter(), &mut key);
             }
         }
+        Ok(SearchMode::Key(key))
+    }
+}
+
+// key の先頭の prefix.len() 列を prefix と列ごとに比べる
+// エンコードしたバイト列どうしで比べると、列の境目が揃わないので列ごとに比べる
+pub fn compare_prefix(key: &[impl AsRef<[u8]>], prefix: &[impl AsRef<[u8]>]) -> Ordering {
+    key.iter()
+        .map(|elem| elem.as_ref())
+        .take(prefix.len())
+        .cmp(prefix.iter().map(|elem| elem.as_ref()))
+}
+
+// row の columns の列を順に並べた複合キー
+pub fn key_values(row: &Row, columns: &[usize]) -> Result<Vec<Value>> {
+    columns.iter().map(|&column| row.get(column)).collect()
+}
+
+// 複合キーを先頭の列から順に比べる (同じ位置の列は同じ型であること)
+pub fn compare_keys(a: &[Value], b: &[Value]) -> Result<Ordering> {
+    for (a, b) in a.iter().zip(b) {
+        let ord = a.try_cmp(b)?;
+        if ord != Ordering::Equal {
+            return Ok(ord);
+        }
+    }
+    Ok(a.len().cmp(&b.len()))
+}
+
+// key の先頭の列が stop_key を超えていないか
+fn is_before_stop(stop_key: &Bound<Tuple>, key: TupleSlice) -> bool {
+    match stop_key {
+        Bound::Included(stop_key) => compare_prefix(key, stop_key) != Ordering::Greater,
+        Bound::Excluded(stop_key) => compare_prefix(key, stop_key) == Ordering::Less,
+        Bound::Unbounded => true,
+    }
+}
+
+// アクセサにも stop_key を渡して、超えたところで先の葉を読まずに終わらせる
+// (memcmpable なので、列ごとに前方を比べるのとエンコードしたバイト列の前方を比べるのが同じになる)
+fn encode_stop_key(stop_key: &Bound<Tuple>) -> Bound<Vec<u8>> {
+    stop_key.as_ref().map(|stop_key| {
+        let mut key = vec![];
+        tuple::encode(stop_key.iter(), &mut key);
+        key
+    })
+}
+
+// 式ごとに Expr::Param を今束縛している値に置き換える
+fn bind_params(exprs: &[Expr]) -> Result<Vec<Expr>> {
+    exprs.iter().map(Expr::bind_params).collect()
+}
+
+// スキャンの説明 (開始位置と終わりの条件は既定でなければ書き足す)
+fn describe_scan(
+    name: &str,
+    search_mode: &TupleSearchMode,
+    stop_key: &Bound<Tuple>,
+    while_cond: &Expr,
+) -> String {
+    let mut options = vec![];
+    match search_mode {
+        TupleSearchMode::Start => {}
+        TupleSearchMode::Key(key) => options.push(format!("from {:?}", tuple::Pretty(key))),
+        TupleSearchMode::Exprs(exprs) => options.push(format!("from {:?}", exprs)),
+    }
+    match stop_key {
+        Bound::Included(stop_key) => options.push(format!("to <= {:?}", tuple::Pretty(stop_key))),
+        Bound::Excluded(stop_key) => options.push(format!("to < {:?}", tuple::Pretty(stop_key))),
+        Bound::Unbounded => {}
+    }
+    if *while_cond != Expr::TRUE {
+        options.push(format!("while {:?}", while_cond));
+    }
+    if options.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} ({})", name, options.join(", "))
     }
 }
 
-pub struct SeqScan<'a, T: BufferPoolManager, U: Iterable<T>> {
-    pub table_accessor: &'a dyn AccessMethod<T, Iterable = U>,
-    pub search_mode: TupleSearchMode<'a>,
-    pub while_cond: &'a dyn Fn(TupleSlice) -> bool,
+pub struct SeqScan<T: BufferPoolManager, U: Iterable<T>> {
+    pub table_accessor: SharedAccessMethod<T, U>,
+    pub search_mode: TupleSearchMode,
+    // 主キーの列の数 (行はこの列の順に並ぶ)
+    pub num_key_elems: usize,
+    // キー (インデックスなら副キー) がこれを超えたら終わる (キーの先頭の列と比べる)
+    pub stop_key: Bound<Tuple>,
+    pub while_cond: Expr,
+    // 見えないタプルは飛ばす
+    pub visibility: Arc<dyn Visibility + Send + Sync>,
+    // 行の列の型 (足りない列はバイト列とみなす)
+    pub schema: Vec<Type>,
 }
 
-impl<'a, T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for SeqScan<'a, T, U> {
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for SeqScan<T, U> {
     type Iter = U;
 
-    fn table_accessor(&self) -> Option<Box<&'a dyn AccessMethod<T, Iterable = Self::Iter>>> {
-        Some(Box::new(self.table_accessor))
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        Some(Box::new(self.table_accessor.as_ref()))
     }
-    fn index_accessor(&self) -> Option<Box<&'a dyn AccessMethod<T, Iterable = Self::Iter>>> {
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
         None
     }
 }
 
-impl<'a, T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for SeqScan<'a, T, U> {
-    fn start(&self, bufmgr: &mut T) -> Result<BoxExecutor<T>> {
-        let table_iter = self
-            .table_accessor()
-            .unwrap()
-            .search(bufmgr, self.search_mode.encode())?;
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for SeqScan<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        let table_iter = self.table_accessor.search_range(
+            bufmgr,
+            self.search_mode.encode()?,
+            encode_stop_key(&self.stop_key),
+        )?;
         Ok(Box::new(ExecSeqScan {
             table_iter: Box::new(table_iter),
-            while_cond: self.while_cond,
+            stop_key: self.stop_key.clone(),
+            while_cond: self.while_cond.bind_params()?,
+            visibility: self.visibility.clone(),
+            types: Arc::from(self.schema.as_slice()),
+            done: false,
         }))
     }
+
+    fn describe(&self) -> String {
+        let (search_mode, stop_key) = (&self.search_mode, &self.stop_key);
+        describe_scan("SeqScan", search_mode, stop_key, &self.while_cond)
+    }
+
+    fn output_ordering(&self) -> Vec<SortKey> {
+        (0..self.num_key_elems).map(SortKey::asc).collect()
+    }
 }
 
-pub struct ExecSeqScan<'a, T: BufferPoolManager> {
+pub struct ExecSeqScan<T: BufferPoolManager> {
     table_iter: Box<dyn Iterable<T>>,
-    while_cond: &'a dyn Fn(TupleSlice) -> bool,
+    stop_key: Bound<Tuple>,
+    while_cond: Expr,
+    visibility: Arc<dyn Visibility + Send + Sync>,
+    types: Arc<[Type]>,
+    // 終わりに達したら、それ以上イテレータを進めない
+    done: bool,
 }
 
-impl<'a, T: BufferPoolManager> Executor<T> for ExecSeqScan<'a, T> {
-    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Tuple>> {
-        let (pkey_bytes, tuple_bytes) = match self.table_iter.next(bufmgr)? {
-            Some(pair) => pair,
-            None => return Ok(None),
-        };
-        let mut pkey = vec![];
-        tuple::decode(&pkey_bytes, &mut pkey);
-        if !(self.while_cond)(&pkey) {
-            return Ok(None);
+impl<T: BufferPoolManager> ExecSeqScan<T> {
+    fn scan_next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        while !self.done {
+            let (pkey_bytes, value_bytes) = match self.table_iter.next(bufmgr)? {
+                Some(pair) => pair,
+                None => {
+                    self.done = true;
+                    break;
+                }
+            };
+            let mut pkey = vec![];
+            tuple::decode(&pkey_bytes, &mut pkey);
+            if !is_before_stop(&self.stop_key, &pkey) {
+                self.done = true;
+                break;
+            }
+            // 主キーは行の先頭の列なので、行と同じ型で読む
+            let pkey = Row::new(pkey, self.types.clone());
+            if !self.while_cond.is_true(&pkey)? {
+                self.done = true;
+                break;
+            }
+            let (header, tuple_bytes) = TupleHeader::decode(&value_bytes)?;
+            if !self.visibility.is_visible(&header) {
+                continue;
+            }
+            let mut tuple = pkey.into_tuple();
+            tuple::decode(tuple_bytes, &mut tuple);
+            return Ok(Some(Row::new(tuple, self.types.clone())));
+        }
+        Ok(None)
+    }
+}
+
+impl<T: BufferPoolManager> Executor<T> for ExecSeqScan<T> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        self.scan_next(bufmgr)
+    }
+
+    fn next_batch(&mut self, bufmgr: &mut T, out: &mut TupleBatch) -> Result<()> {
+        out.clear();
+        while !out.is_full() {
+            match self.scan_next(bufmgr)? {
+                Some(row) => out.push(row),
+                None => break,
+            }
         }
-        let mut tuple = pkey;
-        tuple::decode(&tuple_bytes, &mut tuple);
-        Ok(Some(tuple))
+        Ok(())
     }
 }
 
-pub struct Filter<'a, T: BufferPoolManager, U: Iterable<T>> {
-    pub inner_plan: &'a dyn PlanNode<T, Iter = U>,
-    pub cond: &'a dyn Fn(TupleSlice) -> bool,
+pub struct Filter<T: BufferPoolManager, U: Iterable<T>> {
+    pub inner_plan: SharedPlan<T, U>,
+    pub cond: Expr,
 }
 
-impl<'a, T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for Filter<'a, T, U> {
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for Filter<T, U> {
     type Iter = U;
 
-    fn table_accessor(&self) -> Option<Box<&'a dyn AccessMethod<T, Iterable = Self::Iter>>> {
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
         None
     }
-    fn index_accessor(&self) -> Option<Box<&'a dyn AccessMethod<T, Iterable = Self::Iter>>> {
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
         None
     }
 }
 
-impl<'a, T: BufferPoolManager, U: Iterable<T>> PlanNode<T> for Filter<'a, T, U> {
-    fn start(&self, bufmgr: &mut T) -> Result<BoxExecutor<T>> {
-        let inner_iter = self.inner_plan.start(bufmgr)?;
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for Filter<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        let inner_iter = open(self.inner_plan.as_ref(), bufmgr)?;
         Ok(Box::new(ExecFilter {
             inner_iter,
-            cond: self.cond,
+            cond: self.cond.bind_params()?,
         }))
     }
+
+    fn describe(&self) -> String {
+        format!("Filter ({:?})", self.cond)
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.inner_plan.as_ref()]
+    }
+
+    fn output_ordering(&self) -> Vec<SortKey> {
+        self.inner_plan.output_ordering()
+    }
 }
 
 pub struct ExecFilter<'a, T: BufferPoolManager> {
     inner_iter: BoxExecutor<'a, T>,
-    cond: &'a dyn Fn(TupleSlice) -> bool,
+    cond: Expr,
 }
 
 impl<'a, T: BufferPoolManager> Executor<T> for ExecFilter<'a, T> {
-    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Tuple>> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
         loop {
             match self.inner_iter.next(bufmgr)? {
-                Some(tuple) => {
-                    if (self.cond)(&tuple) {
-                        return Ok(Some(tuple));
+                Some(row) => {
+                    if self.cond.is_true(&row)? {
+                        return Ok(Some(row));
                     }
                 }
                 None => return Ok(None),
             }
         }
     }
+
+    // 子のバッチから条件に合わない行を取り除く (全て取り除いたら次のバッチを読む)
+    fn next_batch(&mut self, bufmgr: &mut T, out: &mut TupleBatch) -> Result<()> {
+        loop {
+            self.inner_iter.next_batch(bufmgr, out)?;
+            if out.is_empty() {
+                return Ok(());
+            }
+            let mut error = None;
+            out.rows_mut().retain(|row| match self.cond.is_true(row) {
+                Ok(matched) => matched,
+                Err(err) => {
+                    error.get_or_insert(err);
+                    false
+                }
+            });
+            if let Some(err) = error {
+                return Err(err);
+            }
+            if !out.is_empty() {
+                return Ok(());
+            }
+        }
+    }
 }
 
-pub struct IndexScan<'a, T: BufferPoolManager, U: Iterable<T>> {
-    pub table_accessor: &'a dyn AccessMethod<T, Iterable = U>,
-    pub index_accessor: &'a dyn AccessMethod<T, Iterable = U>,
-    pub search_mode: TupleSearchMode<'a>,
-    pub while_cond: &'a dyn Fn(TupleSlice) -> bool,
+// 行ごとに exprs を評価した値を並べた行を返す
+pub struct Project<T: BufferPoolManager, U: Iterable<T>> {
+    pub inner_plan: SharedPlan<T, U>,
+    pub exprs: Vec<Expr>,
 }
 
-impl<'a, T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for IndexScan<'a, T, U> {
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for Project<T, U> {
     type Iter = U;
 
-    fn table_accessor(&self) -> Option<Box<&'a dyn AccessMethod<T, Iterable = Self::Iter>>> {
-        Some(Box::new(self.table_accessor))
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
     }
-    fn index_accessor(&self) -> Option<Box<&'a dyn AccessMethod<T, Iterable = Self::Iter>>> {
-        Some(Box::new(self.index_accessor))
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
     }
 }
 
-impl<'a, T: BufferPoolManager, U: Iterable<T>> PlanNode<T> for IndexScan<'a, T, U> {
-    fn start(&self, bufmgr: &mut T) -> Result<BoxExecutor<T>> {
-        let table_accessor = *self.table_accessor().unwrap();
-        let index_iter = self
-            .index_accessor()
-            .unwrap()
-            .search(bufmgr, self.search_mode.encode())?;
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for Project<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        let inner_iter = open(self.inner_plan.as_ref(), bufmgr)?;
+        Ok(Box::new(ExecProject {
+            inner_iter,
+            exprs: bind_params(&self.exprs)?,
+        }))
+    }
+
+    fn describe(&self) -> String {
+        format!("Project {:?}", self.exprs)
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.inner_plan.as_ref()]
+    }
+
+    // 並んでいる列をそのまま出す間だけ、出す位置に読み替えて引き継ぐ
+    fn output_ordering(&self) -> Vec<SortKey> {
+        let mut ordering = vec![];
+        for key in self.inner_plan.output_ordering() {
+            match self
+                .exprs
+                .iter()
+                .position(|expr| *expr == Expr::Column(key.column))
+            {
+                Some(column) => ordering.push(SortKey { column, ..key }),
+                None => break,
+            }
+        }
+        ordering
+    }
+}
+
+pub struct ExecProject<'a, T: BufferPoolManager> {
+    inner_iter: BoxExecutor<'a, T>,
+    exprs: Vec<Expr>,
+}
+
+impl<'a, T: BufferPoolManager> Executor<T> for ExecProject<'a, T> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        match self.inner_iter.next(bufmgr)? {
+            Some(row) => Ok(Some(Row::from_values(
+                self.exprs
+                    .iter()
+                    .map(|expr| expr.eval(&row))
+                    .collect::<Result<_>>()?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    // 子のバッチの行をその場で置き換える
+    fn next_batch(&mut self, bufmgr: &mut T, out: &mut TupleBatch) -> Result<()> {
+        self.inner_iter.next_batch(bufmgr, out)?;
+        for row in out.rows_mut() {
+            *row = Row::from_values(
+                self.exprs
+                    .iter()
+                    .map(|expr| expr.eval(row))
+                    .collect::<Result<_>>()?,
+            );
+        }
+        Ok(())
+    }
+}
+
+pub struct IndexScan<T: BufferPoolManager, U: Iterable<T>> {
+    pub table_accessor: SharedAccessMethod<T, U>,
+    pub index_accessor: SharedAccessMethod<T, U>,
+    pub search_mode: TupleSearchMode,
+    // 副キーにしたテーブルの列 (行はこの列の順に並ぶ)
+    pub skey: Vec<usize>,
+    // キー (インデックスなら副キー) がこれを超えたら終わる (キーの先頭の列と比べる)
+    pub stop_key: Bound<Tuple>,
+    // 副キーの列を並べた行に対して、それぞれの列の schema の型で評価する
+    pub while_cond: Expr,
+    // 見えないタプルは飛ばす
+    pub visibility: Arc<dyn Visibility + Send + Sync>,
+    // テーブルの行の列の型 (足りない列はバイト列とみなす)
+    pub schema: Vec<Type>,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for IndexScan<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        Some(Box::new(self.table_accessor.as_ref()))
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        Some(Box::new(self.index_accessor.as_ref()))
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for IndexScan<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        let index_iter = self.index_accessor.search_range(
+            bufmgr,
+            self.search_mode.encode()?,
+            encode_stop_key(&self.stop_key),
+        )?;
+        let skey_types = self
+            .skey
+            .iter()
+            .map(|&column| self.schema.get(column).copied().unwrap_or(Type::Bytes))
+            .collect();
         Ok(Box::new(ExecIndexScan {
-            table_accessor,
+            table_accessor: self.table_accessor.clone(),
             index_iter,
-            while_cond: self.while_cond,
+            stop_key: self.stop_key.clone(),
+            while_cond: self.while_cond.bind_params()?,
+            visibility: self.visibility.clone(),
+            skey_types,
+            types: Arc::from(self.schema.as_slice()),
         }))
     }
+
+    fn describe(&self) -> String {
+        let (search_mode, stop_key) = (&self.search_mode, &self.stop_key);
+        describe_scan("IndexScan", search_mode, stop_key, &self.while_cond)
+    }
+
+    fn output_ordering(&self) -> Vec<SortKey> {
+        self.skey.iter().copied().map(SortKey::asc).collect()
+    }
 }
 
-pub struct ExecIndexScan<'a, T: BufferPoolManager, U: Iterable<T>> {
-    table_accessor: &'a dyn AccessMethod<T, Iterable = U>,
+pub struct ExecIndexScan<T: BufferPoolManager, U: Iterable<T>> {
+    table_accessor: SharedAccessMethod<T, U>,
     index_iter: U,
-    while_cond: &'a dyn Fn(TupleSlice) -> bool,
+    stop_key: Bound<Tuple>,
+    while_cond: Expr,
+    visibility: Arc<dyn Visibility + Send + Sync>,
+    skey_types: Arc<[Type]>,
+    types: Arc<[Type]>,
 }
 
-impl<'a, T: BufferPoolManager, U: Iterable<T>> Executor<T> for ExecIndexScan<'a, T, U> {
-    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Tuple>> {
-        let (skey_bytes, pkey_bytes) = match self.index_iter.next(bufmgr)? {
-            Some(pair) => pair,
-            None => return Ok(None),
-        };
-        let mut skey = vec![];
-        tuple::decode(&skey_bytes, &mut skey);
-        if !(self.while_cond)(&skey) {
-            return Ok(None);
+impl<T: BufferPoolManager, U: Iterable<T>> Executor<T> for ExecIndexScan<T, U> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        loop {
+            let (skey_bytes, pkey_bytes) = match self.index_iter.next(bufmgr)? {
+                Some(pair) => pair,
+                None => return Ok(None),
+            };
+            let mut skey = vec![];
+            tuple::decode(&skey_bytes, &mut skey);
+            if !is_before_stop(&self.stop_key, &skey)
+                || !self
+                    .while_cond
+                    .is_true(&Row::new(skey, self.skey_types.clone()))?
+            {
+                return Ok(None);
+            }
+            let types = &self.types;
+            match fetch_row(
+                self.table_accessor.as_ref(),
+                bufmgr,
+                &skey_bytes,
+                pkey_bytes,
+                self.visibility.as_ref(),
+                types,
+            )? {
+                Some(row) => return Ok(Some(row)),
+                None => continue,
+            }
+        }
+    }
+}
+
+// セカンダリインデックスの項目 (skey_bytes, pkey_bytes) の主キーでテーブルの行を引き、見えれば返す
+// 行がなければ Error::IndexCorruption
+fn fetch_row<T: BufferPoolManager, U: Iterable<T>>(
+    table_accessor: &dyn AccessMethod<T, Iterable = U>,
+    bufmgr: &mut T,
+    skey_bytes: &[u8],
+    pkey_bytes: Vec<u8>,
+    visibility: &dyn Visibility,
+    types: &Arc<[Type]>,
+) -> Result<Option<Row>> {
+    let mut table_iter = table_accessor.search(bufmgr, SearchMode::Key(pkey_bytes.clone()))?;
+    match table_iter.next(bufmgr)? {
+        // 主キーがなければ次のキーの項目が返る
+        Some((key_bytes, value_bytes)) if key_bytes == pkey_bytes => {
+            decode_row(&key_bytes, &value_bytes, visibility, types)
+        }
+        _ => {
+            let (mut skey, mut pkey) = (vec![], vec![]);
+            tuple::decode(skey_bytes, &mut skey);
+            tuple::decode(&pkey_bytes, &mut pkey);
+            Err(Error::IndexCorruption { skey, pkey }.into())
         }
-        let mut table_iter = self
-            .table_accessor
-            .search(bufmgr, SearchMode::Key(pkey_bytes))?;
-        let (pkey_bytes, tuple_bytes) = table_iter.next(bufmgr)?.unwrap();
-        let mut tuple = vec![];
-        tuple::decode(&pkey_bytes, &mut tuple);
-        tuple::decode(&tuple_bytes, &mut tuple);
-        Ok(Some(tuple))
     }
 }
 
-pub struct IndexOnlyScan<'a, T: BufferPoolManager, U: Iterable<T>> {
-    pub index_accessor: &'a dyn AccessMethod<T, Iterable = U>,
-    pub search_mode: TupleSearchMode<'a>,
-    pub while_cond: &'a dyn Fn(TupleSlice) -> bool,
+// インデックスの項目の副キーの列に主キーの列を続ける
+// NonUniqueIndex のキーは主キーまで含むので、キーの列が副キーより多ければ値は読まない
+fn append_pkey(tuple: &mut Tuple, num_key_elems: usize, pkey_bytes: &[u8]) {
+    if tuple.len() <= num_key_elems {
+        tuple::decode(pkey_bytes, tuple);
+    }
+}
+
+// テーブルの項目を行にする (見えなければ None)
+fn decode_row(
+    pkey_bytes: &[u8],
+    value_bytes: &[u8],
+    visibility: &dyn Visibility,
+    types: &Arc<[Type]>,
+) -> Result<Option<Row>> {
+    let (header, tuple_bytes) = TupleHeader::decode(value_bytes)?;
+    if !visibility.is_visible(&header) {
+        return Ok(None);
+    }
+    let mut tuple = vec![];
+    tuple::decode(pkey_bytes, &mut tuple);
+    tuple::decode(tuple_bytes, &mut tuple);
+    Ok(Some(Row::new(tuple, types.clone())))
 }
 
-impl<'a, T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for IndexOnlyScan<'a, T, U> {
+pub struct IndexOnlyScan<T: BufferPoolManager, U: Iterable<T>> {
+    pub index_accessor: SharedAccessMethod<T, U>,
+    pub search_mode: TupleSearchMode,
+    // 副キーの列の数 (行はこの列の順に並ぶ)
+    pub num_key_elems: usize,
+    // キー (インデックスなら副キー) がこれを超えたら終わる (キーの先頭の列と比べる)
+    pub stop_key: Bound<Tuple>,
+    pub while_cond: Expr,
+    // 副キーと主キーを並べた行の列の型 (足りない列はバイト列とみなす)
+    pub schema: Vec<Type>,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for IndexOnlyScan<T, U> {
     type Iter = U;
 
-    fn table_accessor(&self) -> Option<Box<&'a dyn AccessMethod<T, Iterable = Self::Iter>>> {
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
         None
     }
-    fn index_accessor(&self) -> Option<Box<&'a dyn AccessMethod<T, Iterable = Self::Iter>>> {
-        Some(Box::new(self.index_accessor))
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        Some(Box::new(self.index_accessor.as_ref()))
     }
 }
 
-impl<'a, T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for IndexOnlyScan<'a, T, U> {
-    fn start(&self, bufmgr: &mut T) -> Result<BoxExecutor<T>> {
-        let index_iter = self
-            .index_accessor()
-            .unwrap()
-            .search(bufmgr, self.search_mode.encode())?;
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for IndexOnlyScan<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        let index_iter = self.index_accessor.search_range(
+            bufmgr,
+            self.search_mode.encode()?,
+            encode_stop_key(&self.stop_key),
+        )?;
         Ok(Box::new(ExecIndexOnlyScan {
             index_iter: Box::new(index_iter),
-            while_cond: self.while_cond,
+            num_key_elems: self.num_key_elems,
+            stop_key: self.stop_key.clone(),
+            while_cond: self.while_cond.bind_params()?,
+            types: Arc::from(self.schema.as_slice()),
         }))
     }
+
+    fn describe(&self) -> String {
+        let (search_mode, stop_key) = (&self.search_mode, &self.stop_key);
+        describe_scan("IndexOnlyScan", search_mode, stop_key, &self.while_cond)
+    }
+
+    fn output_ordering(&self) -> Vec<SortKey> {
+        (0..self.num_key_elems).map(SortKey::asc).collect()
+    }
 }
 
-pub struct ExecIndexOnlyScan<'a, T: BufferPoolManager> {
+pub struct ExecIndexOnlyScan<T: BufferPoolManager> {
     index_iter: Box<dyn Iterable<T>>,
-    while_cond: &'a dyn Fn(TupleSlice) -> bool,
+    num_key_elems: usize,
+    stop_key: Bound<Tuple>,
+    while_cond: Expr,
+    types: Arc<[Type]>,
 }
 
-impl<'a, T: BufferPoolManager> Executor<T> for ExecIndexOnlyScan<'a, T> {
-    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Tuple>> {
+impl<T: BufferPoolManager> Executor<T> for ExecIndexOnlyScan<T> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
         let (skey_bytes, pkey_bytes) = match self.index_iter.next(bufmgr)? {
             Some(pair) => pair,
             None => return Ok(None),
         };
         let mut skey = vec![];
         tuple::decode(&skey_bytes, &mut skey);
-        if !(self.while_cond)(&skey) {
+        if !is_before_stop(&self.stop_key, &skey) {
+            return Ok(None);
+        }
+        let skey = Row::new(skey, self.types.clone());
+        if !self.while_cond.is_true(&skey)? {
             return Ok(None);
         }
-        let mut tuple = skey;
-        tuple::decode(&pkey_bytes, &mut tuple);
-        Ok(Some(tuple))
+        let mut tuple = skey.into_tuple();
+        append_pkey(&mut tuple, self.num_key_elems, &pkey_bytes);
+        Ok(Some(Row::new(tuple, self.types.clone())))
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// 複合インデックスの先頭の prefix_len 列に条件がなく、続く列にだけ範囲があるときに使う
+// 先頭の列の値ごとに、その値と start_key を並べたところへ探し直し、stop_key を超えたら次の値へ飛ぶ
+// 先頭の列の値の種類が少なければ、インデックス全体を読むよりずっと少なく済む
+pub struct SkipScan<T: BufferPoolManager, U: Iterable<T>> {
+    pub index_accessor: SharedAccessMethod<T, U>,
+    // 副キーの列の数 (行はこの列の順に並ぶ)
+    pub num_key_elems: usize,
+    // 飛ばしながら読む副キーの先頭の列の数
+    pub prefix_len: usize,
+    // 先頭の列に続く列の範囲 (それぞれ続く列の前方と比べる)
+    pub start_key: Bound<Tuple>,
+    pub stop_key: Bound<Tuple>,
+    // 副キーと主キーを並べた行の列の型 (足りない列はバイト列とみなす)
+    pub schema: Vec<Type>,
+}
 
-    use crate::accessor::{entity::SearchMode, method};
-    use crate::buffer::{
-        entity::Buffer,
-        manager::{BufferPoolManager, Error},
-    };
-    use crate::storage::entity::PageId;
-    use std::rc::Rc;
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for SkipScan<T, U> {
+    type Iter = U;
 
-    struct Empty {}
-    impl BufferPoolManager for Empty {
-        fn fetch_page(&mut self, _: PageId) -> Result<Rc<Buffer>, Error> {
-            panic!("Not implement!")
-        }
-        fn create_page(&mut self) -> Result<Rc<Buffer>, Error> {
-            panic!("Not implement!")
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        Some(Box::new(self.index_accessor.as_ref()))
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for SkipScan<T, U> {
+    fn start<'a>(&self, _bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        Ok(Box::new(ExecSkipScan {
+            index_accessor: self.index_accessor.clone(),
+            num_key_elems: self.num_key_elems,
+            prefix_len: self.prefix_len,
+            start_key: self.start_key.clone(),
+            stop_key: self.stop_key.clone(),
+            types: Arc::from(self.schema.as_slice()),
+            seek: Some(TupleSearchMode::Start),
+            prefix: None,
+            index_iter: None,
+        }))
+    }
+
+    fn describe(&self) -> String {
+        let mut options = vec![format!("prefix_len: {}", self.prefix_len)];
+        match &self.start_key {
+            Bound::Included(key) => options.push(format!("from >= {:?}", tuple::Pretty(key))),
+            Bound::Excluded(key) => options.push(format!("from > {:?}", tuple::Pretty(key))),
+            Bound::Unbounded => {}
         }
-        fn flush(&mut self) -> Result<(), Error> {
-            panic!("Not implement!")
+        match &self.stop_key {
+            Bound::Included(key) => options.push(format!("to <= {:?}", tuple::Pretty(key))),
+            Bound::Excluded(key) => options.push(format!("to < {:?}", tuple::Pretty(key))),
+            Bound::Unbounded => {}
         }
+        format!("SkipScan ({})", options.join(", "))
     }
 
-    struct Counter {
-        next: u8,
+    fn output_ordering(&self) -> Vec<SortKey> {
+        (0..self.num_key_elems).map(SortKey::asc).collect()
     }
-    impl Counter {
-        fn new(init: u8) -> Self {
-            Self { next: init }
+}
+
+pub struct ExecSkipScan<T: BufferPoolManager, U: Iterable<T>> {
+    index_accessor: SharedAccessMethod<T, U>,
+    num_key_elems: usize,
+    prefix_len: usize,
+    start_key: Bound<Tuple>,
+    stop_key: Bound<Tuple>,
+    types: Arc<[Type]>,
+    // 次に探すところ (None なら読み終えた)
+    seek: Option<TupleSearchMode>,
+    // 今読んでいる先頭の列の値
+    prefix: Option<Tuple>,
+    index_iter: Option<U>,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> ExecSkipScan<T, U> {
+    // 先頭の列に続く列が start_key 以降か
+    fn is_after_start(&self, rest: TupleSlice) -> bool {
+        match &self.start_key {
+            Bound::Included(start_key) => compare_prefix(rest, start_key) != Ordering::Less,
+            Bound::Excluded(start_key) => compare_prefix(rest, start_key) == Ordering::Greater,
+            Bound::Unbounded => true,
         }
     }
-    impl Iterable<Empty> for Counter {
-        fn next(&mut self, _: &mut Empty) -> Result<Option<(Vec<u8>, Vec<u8>)>, method::Error> {
-            let c = self.next;
-            if c == u8::MAX {
-                return Ok(None);
-            } else {
-                self.next += 1;
-                let mut key = vec![];
-                tuple::encode(vec![&[c]].iter(), &mut key);
-                let mut val = vec![];
-                tuple::encode(vec![&[c]].iter(), &mut val);
-                Ok(Some((key, val)))
-            }
+
+    // 先頭の列が prefix より大きいキーのうち最小のものへ飛ぶ
+    // 最後の列に 0 を一つ足したバイト列は、元のバイト列のすぐ次になる
+    fn skip_prefix(&mut self, mut prefix: Tuple) {
+        if let Some(last) = prefix.last_mut() {
+            last.push(0);
         }
+        self.seek = Some(TupleSearchMode::Key(prefix));
+        self.prefix = None;
+        self.index_iter = None;
     }
+}
 
-    struct Generate {}
-    impl AccessMethod<Empty> for Generate {
-        type Iterable = Counter;
-        fn search(
-            &self,
-            _: &mut Empty,
-            search_option: SearchMode,
-        ) -> Result<Self::Iterable, method::Error> {
-            match search_option {
-                SearchMode::Start => Ok(Counter::new(0)),
-                SearchMode::Key(n) => Ok(Counter::new(n[0])),
+impl<T: BufferPoolManager, U: Iterable<T>> Executor<T> for ExecSkipScan<T, U> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        loop {
+            let index_iter = match &mut self.index_iter {
+                Some(index_iter) => index_iter,
+                None => {
+                    let seek = match self.seek.take() {
+                        Some(seek) => seek,
+                        None => return Ok(None),
+                    };
+                    self.index_iter = Some(self.index_accessor.search(bufmgr, seek.encode()?)?);
+                    continue;
+                }
+            };
+            let (skey_bytes, pkey_bytes) = match index_iter.next(bufmgr)? {
+                Some(pair) => pair,
+                None => {
+                    self.index_iter = None;
+                    return Ok(None);
+                }
+            };
+            let mut skey = vec![];
+            tuple::decode(&skey_bytes, &mut skey);
+            let (prefix, rest) = skey.split_at(self.prefix_len.min(skey.len()));
+            if self.prefix.as_deref() != Some(prefix) {
+                // 先頭の列の値が変わったら、範囲の始めまで飛ぶ
+                self.prefix = Some(prefix.to_vec());
+                if let Bound::Included(start_key) | Bound::Excluded(start_key) = &self.start_key {
+                    if !self.is_after_start(rest) {
+                        let mut key = prefix.to_vec();
+                        key.extend(start_key.iter().cloned());
+                        self.seek = Some(TupleSearchMode::Key(key));
+                        self.index_iter = None;
+                        continue;
+                    }
+                }
             }
-        }
-        fn insert(&self, _: &mut Empty, _: &[u8], _: &[u8]) -> Result<(), method::Error> {
-            panic!("Not implement!")
+            if !self.is_after_start(rest) {
+                continue;
+            }
+            if !is_before_stop(&self.stop_key, rest) {
+                let prefix = prefix.to_vec();
+                self.skip_prefix(prefix);
+                continue;
+            }
+            let mut tuple = skey;
+            append_pkey(&mut tuple, self.num_key_elems, &pkey_bytes);
+            return Ok(Some(Row::new(tuple, self.types.clone())));
         }
     }
+}
 
-    #[test]
-    fn seq_scan_test() {
-        let mut bufmgr = Empty {};
-        {
-            let plan = SeqScan {
-                table_accessor: &Generate {},
-                search_mode: TupleSearchMode::Start,
-                while_cond: &|_| true,
-            };
-            let mut exec = plan.start(&mut bufmgr).unwrap();
+// 複数のインデックスから主キーを集め、その積 (AND) か和 (OR) の主キーでテーブルの行を一度ずつ引く
+// 別々の列の条件をそれぞれのインデックスで絞れるので、テーブル全体を読まずに済む
+// 集めた主キーは全てメモリに持つ
+pub struct IndexMergeScan<T: BufferPoolManager, U: Iterable<T>> {
+    pub table_accessor: SharedAccessMethod<T, U>,
+    pub sources: Vec<KeySource<T, U>>,
+    pub kind: MergeKind,
+    // 見えない行は飛ばす
+    pub visibility: Arc<dyn Visibility + Send + Sync>,
+    // テーブルの行の列の型 (足りない列はバイト列とみなす)
+    pub schema: Vec<Type>,
+}
 
-            let res1 = exec.next(&mut bufmgr);
-            let first = res1.unwrap().unwrap();
-            assert_eq!(first, vec![&[0], &[0]]);
+// 主キーを返すもの (IndexOnlyScan など) と、その行の主キーの列
+pub struct KeySource<T: BufferPoolManager, U: Iterable<T>> {
+    pub plan: SharedPlan<T, U>,
+    pub pkey_cols: Vec<usize>,
+}
 
-            let res2 = exec.next(&mut bufmgr);
-            let second = res2.unwrap().unwrap();
-            assert_eq!(second, vec![&[1], &[1]]);
-        }
-        {
-            let plan = SeqScan {
-                table_accessor: &Generate {},
-                search_mode: TupleSearchMode::Key(&[&[42u8]]),
-                while_cond: &|_| true,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeKind {
+    Intersect,
+    Union,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for IndexMergeScan<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        Some(Box::new(self.table_accessor.as_ref()))
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for IndexMergeScan<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        let mut merged: Option<BTreeSet<Vec<u8>>> = None;
+        for source in self.sources.iter() {
+            // 符号化した主キーのバイト列の順は主キーの順と同じ
+            let mut pkeys = BTreeSet::new();
+            let mut iter = open(source.plan.as_ref(), bufmgr)?;
+            while let Some(row) = iter.next(bufmgr)? {
+                let mut pkey_bytes = vec![];
+                let pkey = source.pkey_cols.iter().map(|&column| &row.tuple()[column]);
+                tuple::encode(pkey, &mut pkey_bytes);
+                pkeys.insert(pkey_bytes);
+            }
+            let pkeys = match (merged.take(), self.kind) {
+                (None, _) => pkeys,
+                (Some(merged), MergeKind::Intersect) => {
+                    merged.intersection(&pkeys).cloned().collect()
+                }
+                (Some(mut merged), MergeKind::Union) => {
+                    merged.extend(pkeys);
+                    merged
+                }
             };
-            let mut exec = plan.start(&mut bufmgr).unwrap();
+            // 積が空になったら残りのインデックスは読まない
+            let exhausted = self.kind == MergeKind::Intersect && pkeys.is_empty();
+            merged = Some(pkeys);
+            if exhausted {
+                break;
+            }
+        }
+        Ok(Box::new(ExecIndexMergeScan {
+            pkeys: merged.unwrap_or_default().into_iter(),
+            table_accessor: self.table_accessor.clone(),
+            visibility: self.visibility.clone(),
+            types: Arc::from(self.schema.as_slice()),
+        }))
+    }
 
-            let res1 = exec.next(&mut bufmgr);
-            let first = res1.unwrap().unwrap();
-            assert_eq!(first, vec![&[42], &[42]]);
+    fn describe(&self) -> String {
+        format!("IndexMergeScan ({:?})", self.kind)
+    }
 
-            let res2 = exec.next(&mut bufmgr);
-            let second = res2.unwrap().unwrap();
-            assert_eq!(second, vec![&[43], &[43]]);
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        self.sources
+            .iter()
+            .map(|source| source.plan.as_ref() as _)
+            .collect()
+    }
+
+    fn output_ordering(&self) -> Vec<SortKey> {
+        match self.sources.first() {
+            Some(source) => (0..source.pkey_cols.len()).map(SortKey::asc).collect(),
+            None => vec![],
         }
-        {
-            let plan = SeqScan {
-                table_accessor: &Generate {},
-                search_mode: TupleSearchMode::Key(&[&[42u8]]),
-                while_cond: &|_| false,
-            };
-            let mut exec = plan.start(&mut bufmgr).unwrap();
+    }
+}
 
-            let res1 = exec.next(&mut bufmgr);
-            let nodata = res1.unwrap();
-            assert!(nodata.is_none());
+pub struct ExecIndexMergeScan<T: BufferPoolManager, U: Iterable<T>> {
+    pkeys: btree_set::IntoIter<Vec<u8>>,
+    table_accessor: SharedAccessMethod<T, U>,
+    visibility: Arc<dyn Visibility + Send + Sync>,
+    types: Arc<[Type]>,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> Executor<T> for ExecIndexMergeScan<T, U> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        for pkey_bytes in self.pkeys.by_ref() {
+            // 主キーだけでは副キーが分からないので、壊れていれば副キーは空で報告する
+            let row = fetch_row(
+                self.table_accessor.as_ref(),
+                bufmgr,
+                &[],
+                pkey_bytes,
+                self.visibility.as_ref(),
+                &self.types,
+            )?;
+            if row.is_some() {
+                return Ok(row);
+            }
         }
+        Ok(None)
     }
-    #[test]
+}
+
+// 左の行ごとに、outer_keys の列の値でインデックスを引いて右のテーブルの行と結合する
+// outer_keys の列をキーの先頭の列と順に突き合わせるので、より多くの列からなるキーの前方でも結合できる
+// index_accessor が None なら、右のテーブルの主キーで引く
+pub struct IndexNestedLoopJoin<T: BufferPoolManager, U: Iterable<T>> {
+    pub outer_plan: SharedPlan<T, U>,
+    pub table_accessor: SharedAccessMethod<T, U>,
+    pub index_accessor: Option<SharedAccessMethod<T, U>>,
+    pub outer_keys: Vec<usize>,
+    // 見えない右の行は飛ばす
+    pub visibility: Arc<dyn Visibility + Send + Sync>,
+    // 右のテーブルの行の列の型 (足りない列はバイト列とみなす)
+    pub schema: Vec<Type>,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for IndexNestedLoopJoin<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        Some(Box::new(self.table_accessor.as_ref()))
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        match &self.index_accessor {
+            Some(index_accessor) => Some(Box::new(index_accessor.as_ref())),
+            None => None,
+        }
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for IndexNestedLoopJoin<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        let outer_iter = open(self.outer_plan.as_ref(), bufmgr)?;
+        Ok(Box::new(ExecIndexNestedLoopJoin {
+            outer_iter,
+            table_accessor: self.table_accessor.clone(),
+            index_accessor: self.index_accessor.clone(),
+            outer_keys: self.outer_keys.clone(),
+            visibility: self.visibility.clone(),
+            types: Arc::from(self.schema.as_slice()),
+            outer: None,
+            inner_iter: None,
+        }))
+    }
+
+    fn describe(&self) -> String {
+        let by = match self.index_accessor {
+            Some(_) => "index",
+            None => "primary key",
+        };
+        format!(
+            "IndexNestedLoopJoin (outer_keys: {:?}, by {})",
+            self.outer_keys, by
+        )
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.outer_plan.as_ref()]
+    }
+
+    fn output_ordering(&self) -> Vec<SortKey> {
+        self.outer_plan.output_ordering()
+    }
+}
+
+pub struct ExecIndexNestedLoopJoin<'a, T: BufferPoolManager, U: Iterable<T>> {
+    outer_iter: BoxExecutor<'a, T>,
+    table_accessor: SharedAccessMethod<T, U>,
+    index_accessor: Option<SharedAccessMethod<T, U>>,
+    outer_keys: Vec<usize>,
+    visibility: Arc<dyn Visibility + Send + Sync>,
+    types: Arc<[Type]>,
+    // 今の左の行と、その値で引いている右のイテレータ
+    outer: Option<Row>,
+    inner_iter: Option<U>,
+}
+
+impl<'a, T: BufferPoolManager, U: Iterable<T>> Executor<T> for ExecIndexNestedLoopJoin<'a, T, U> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        loop {
+            let (outer, inner_iter) = match (&self.outer, &mut self.inner_iter) {
+                (Some(outer), Some(inner_iter)) => (outer, inner_iter),
+                _ => {
+                    let outer = match self.outer_iter.next(bufmgr)? {
+                        Some(outer) => self.outer.insert(outer),
+                        None => return Ok(None),
+                    };
+                    let key = self
+                        .outer_keys
+                        .iter()
+                        .map(|&column| outer.tuple()[column].clone())
+                        .collect();
+                    let key = TupleSearchMode::Key(key).encode()?;
+                    let accessor = self.index_accessor.as_ref().unwrap_or(&self.table_accessor);
+                    self.inner_iter = Some(accessor.search(bufmgr, key)?);
+                    continue;
+                }
+            };
+            let (key_bytes, value_bytes) = match inner_iter.next(bufmgr)? {
+                Some(pair) => pair,
+                None => {
+                    self.inner_iter = None;
+                    continue;
+                }
+            };
+            let mut key = vec![];
+            tuple::decode(&key_bytes, &mut key);
+            let outer_key = self.outer_keys.iter().map(|&column| &outer.tuple()[column]);
+            if key.len() < self.outer_keys.len()
+                || !key.iter().zip(outer_key).all(|(elem, outer)| elem == outer)
+            {
+                self.inner_iter = None;
+                continue;
+            }
+            let (visibility, types) = (self.visibility.as_ref(), &self.types);
+            let inner = match self.index_accessor {
+                Some(_) => fetch_row(
+                    self.table_accessor.as_ref(),
+                    bufmgr,
+                    &key_bytes,
+                    value_bytes,
+                    visibility,
+                    types,
+                )?,
+                None => decode_row(&key_bytes, &value_bytes, visibility, types)?,
+            };
+            if let Some(inner) = inner {
+                return Ok(Some(outer.clone().concat(&inner)));
+            }
+        }
+    }
+}
+
+// 行の sort_keys の列の値 (比べるたびに読まないように先に読んでおく)
+fn sort_values(sort_keys: &[SortKey], row: &Row) -> Result<Vec<Value>> {
+    sort_keys.iter().map(|key| row.get(key.column)).collect()
+}
+
+fn compare_values(sort_keys: &[SortKey], a: &[Value], b: &[Value]) -> Ordering {
+    for (key, (a, b)) in sort_keys.iter().zip(a.iter().zip(b)) {
+        let ord = a.cmp(b);
+        let ord = if key.descending { ord.reverse() } else { ord };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+// ordering の順に並んでいれば required の順にも並んでいるか (required が ordering の先頭と一致する)
+pub fn satisfies_ordering(ordering: &[SortKey], required: &[SortKey]) -> bool {
+    required.len() <= ordering.len() && ordering.iter().zip(required).all(|(a, b)| a == b)
+}
+
+// plan を sort_keys の順に並べる
+// 既にその順に並んでいれば Sort を挟まずにそのまま返す
+pub fn sorted<T: BufferPoolManager + 'static, U: 'static + Iterable<T>>(
+    plan: SharedPlan<T, U>,
+    sort_keys: Vec<SortKey>,
+    work_mem: usize,
+) -> SharedPlan<T, U> {
+    if satisfies_ordering(&plan.output_ordering(), &sort_keys) {
+        return plan;
+    }
+    Arc::new(Sort {
+        inner_plan: plan,
+        sort_keys,
+        work_mem,
+    })
+}
+
+// ソートのランや集約のパーティションを書き出す一時データのバッファプールの大きさ
+const TEMP_POOL_SIZE: usize = 16;
+
+// 行を型ごと書き出したランを作る (Row::from_tagged で読み戻す)
+fn spill_rows<'r>(
+    temp: &mut TempBufferManager,
+    rows: impl IntoIterator<Item = &'r Row>,
+) -> Result<TupleRun> {
+    let mut run = TupleRun::new();
+    for row in rows {
+        run.push(temp, &row.to_tagged())?;
+    }
+    Ok(run)
+}
+
+// 入力を sort_keys の順に並べる (同じ順位のものは入力の順を保つ)
+// work_mem バイトまではメモリで並べ、超えるか MemoryContext から確保できなければ
+// 並べたランを一時ファイルへ書き出して、
+// 最後に全てのランをマージしながら返す
+pub struct Sort<T: BufferPoolManager, U: Iterable<T>> {
+    pub inner_plan: SharedPlan<T, U>,
+    pub sort_keys: Vec<SortKey>,
+    pub work_mem: usize,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for Sort<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for Sort<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        let mut inner_iter = open(self.inner_plan.as_ref(), bufmgr)?;
+        let mut rows = vec![];
+        let mut mem = 0;
+        let mut reservation = MemoryReservation::new(MemoryContext::current());
+        let mut spilled: Option<(TempBufferManager, Vec<TupleRun>)> = None;
+        while let Some(row) = inner_iter.next(bufmgr)? {
+            let size = row.tuple().iter().map(|elem| elem.len()).sum::<usize>();
+            mem += size;
+            rows.push((sort_values(&self.sort_keys, &row)?, row));
+            if mem > self.work_mem || !reservation.try_grow(size) {
+                let (temp, runs) = match &mut spilled {
+                    Some(spilled) => spilled,
+                    None => spilled.insert((TempBufferManager::new(TEMP_POOL_SIZE)?, vec![])),
+                };
+                rows.sort_by(|a, b| compare_values(&self.sort_keys, &a.0, &b.0));
+                runs.push(spill_rows(temp, rows.iter().map(|(_, row)| row))?);
+                rows.clear();
+                reservation.clear();
+                mem = 0;
+            }
+        }
+        rows.sort_by(|a, b| compare_values(&self.sort_keys, &a.0, &b.0));
+        let state = match spilled {
+            Some((mut temp, mut runs)) => {
+                runs.push(spill_rows(&mut temp, rows.iter().map(|(_, row)| row))?);
+                reservation.clear();
+                let mut heads = vec![];
+                for run in runs {
+                    let mut reader = run.into_reader();
+                    if let Some(head) = reader.next(&mut temp)? {
+                        let head = Row::from_tagged(head)?;
+                        heads.push((sort_values(&self.sort_keys, &head)?, head, reader));
+                    }
+                }
+                SortState::Merge {
+                    temp: Box::new(temp),
+                    heads,
+                }
+            }
+            None => SortState::Memory(rows.into_iter()),
+        };
+        Ok(Box::new(ExecSort {
+            sort_keys: self.sort_keys.clone(),
+            state,
+            _reservation: reservation,
+        }))
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "Sort (keys: {:?}, work_mem: {})",
+            self.sort_keys, self.work_mem
+        )
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.inner_plan.as_ref()]
+    }
+
+    fn output_ordering(&self) -> Vec<SortKey> {
+        self.sort_keys.clone()
+    }
+}
+
+enum SortState {
+    Memory(std::vec::IntoIter<(Vec<Value>, Row)>),
+    // 書き出したランの先頭と、その続きを読むもの (ランの順に並べる)
+    Merge {
+        temp: Box<TempBufferManager>,
+        heads: Vec<(Vec<Value>, Row, TupleRunReader)>,
+    },
+}
+
+pub struct ExecSort {
+    sort_keys: Vec<SortKey>,
+    state: SortState,
+    // メモリで並べた行を返し終えるまで持っておく
+    _reservation: MemoryReservation,
+}
+
+impl<T: BufferPoolManager> Executor<T> for ExecSort {
+    fn next(&mut self, _: &mut T) -> Result<Option<Row>> {
+        match &mut self.state {
+            SortState::Memory(iter) => Ok(iter.next().map(|(_, row)| row)),
+            SortState::Merge { temp, heads } => {
+                // 同じ順位なら前のランから返して入力の順を保つ
+                if heads.is_empty() {
+                    return Ok(None);
+                }
+                let mut min = 0;
+                for i in 1..heads.len() {
+                    if compare_values(&self.sort_keys, &heads[i].0, &heads[min].0) == Ordering::Less
+                    {
+                        min = i;
+                    }
+                }
+                let row = match heads[min].2.next(temp.as_mut())? {
+                    Some(next) => {
+                        let next = Row::from_tagged(next)?;
+                        heads[min].0 = sort_values(&self.sort_keys, &next)?;
+                        std::mem::replace(&mut heads[min].1, next)
+                    }
+                    None => heads.remove(min).1,
+                };
+                Ok(Some(row))
+            }
+        }
+    }
+}
+
+// sort_keys の順で先頭から limit 行だけを返す (ORDER BY ... LIMIT)
+// 全体を並べずに、それまでの上位 limit 行だけをヒープに持つ
+// 同じ順位のものは入力の順を保つ
+pub struct TopN<T: BufferPoolManager, U: Iterable<T>> {
+    pub inner_plan: SharedPlan<T, U>,
+    pub sort_keys: Vec<SortKey>,
+    pub limit: usize,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for TopN<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+// 順位の一番低いものが先頭に来るヒープの要素 (keys は sort_keys の列の値、seq は入力の順)
+struct Ranked<'a> {
+    keys: Vec<Value>,
+    row: Row,
+    seq: usize,
+    sort_keys: &'a [SortKey],
+}
+
+impl<'a> Ord for Ranked<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_values(self.sort_keys, &self.keys, &other.keys).then(self.seq.cmp(&other.seq))
+    }
+}
+
+impl<'a> PartialOrd for Ranked<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> PartialEq for Ranked<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a> Eq for Ranked<'a> {}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for TopN<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        let mut inner_iter = open(self.inner_plan.as_ref(), bufmgr)?;
+        let mut heap = BinaryHeap::with_capacity(self.limit + 1);
+        let mut seq = 0;
+        while let Some(row) = inner_iter.next(bufmgr)? {
+            heap.push(Ranked {
+                keys: sort_values(&self.sort_keys, &row)?,
+                row,
+                seq,
+                sort_keys: &self.sort_keys,
+            });
+            seq += 1;
+            if heap.len() > self.limit {
+                heap.pop();
+            }
+        }
+        let rows: Vec<_> = heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|ranked| ranked.row)
+            .collect();
+        Ok(Box::new(ExecTopN {
+            rows: rows.into_iter(),
+        }))
+    }
+
+    fn describe(&self) -> String {
+        format!("TopN (keys: {:?}, limit: {})", self.sort_keys, self.limit)
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.inner_plan.as_ref()]
+    }
+
+    fn output_ordering(&self) -> Vec<SortKey> {
+        self.sort_keys.clone()
+    }
+}
+
+pub struct ExecTopN {
+    rows: std::vec::IntoIter<Row>,
+}
+
+impl<T: BufferPoolManager> Executor<T> for ExecTopN {
+    fn next(&mut self, _: &mut T) -> Result<Option<Row>> {
+        Ok(self.rows.next())
+    }
+}
+
+// 先頭の offset 行を読み捨て、続く limit 行だけを返す (limit が None なら残り全て)
+pub struct Limit<T: BufferPoolManager, U: Iterable<T>> {
+    pub inner_plan: SharedPlan<T, U>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for Limit<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for Limit<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        Ok(Box::new(ExecLimit {
+            inner_iter: open(self.inner_plan.as_ref(), bufmgr)?,
+            skip: self.offset,
+            remaining: self.limit,
+        }))
+    }
+
+    fn describe(&self) -> String {
+        match self.limit {
+            Some(limit) => format!("Limit (limit: {}, offset: {})", limit, self.offset),
+            None => format!("Limit (offset: {})", self.offset),
+        }
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.inner_plan.as_ref()]
+    }
+
+    fn output_ordering(&self) -> Vec<SortKey> {
+        self.inner_plan.output_ordering()
+    }
+}
+
+pub struct ExecLimit<'a, T: BufferPoolManager> {
+    inner_iter: BoxExecutor<'a, T>,
+    skip: usize,
+    remaining: Option<usize>,
+}
+
+impl<'a, T: BufferPoolManager> Executor<T> for ExecLimit<'a, T> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        // 返し終えたら子を読まない
+        if self.remaining == Some(0) {
+            return Ok(None);
+        }
+        while self.skip > 0 {
+            if self.inner_iter.next(bufmgr)?.is_none() {
+                self.skip = 0;
+                self.remaining = Some(0);
+                return Ok(None);
+            }
+            self.skip -= 1;
+        }
+        let row = self.inner_iter.next(bufmgr)?;
+        if let (Some(remaining), Some(_)) = (&mut self.remaining, &row) {
+            *remaining -= 1;
+        }
+        Ok(row)
+    }
+}
+
+// 結合の種類
+// 外部結合では相手のない行も返し、相手の列は NULL で埋める (埋める列の数を持つ)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left {
+        inner_width: usize,
+    },
+    Right {
+        outer_width: usize,
+    },
+    Full {
+        outer_width: usize,
+        inner_width: usize,
+    },
+}
+
+impl JoinKind {
+    // 相手のない左の行を返すなら、右の列の数
+    fn inner_width(&self) -> Option<usize> {
+        match *self {
+            JoinKind::Left { inner_width } | JoinKind::Full { inner_width, .. } => {
+                Some(inner_width)
+            }
+            _ => None,
+        }
+    }
+
+    // 相手のない右の行を返すなら、左の列の数
+    fn outer_width(&self) -> Option<usize> {
+        match *self {
+            JoinKind::Right { outer_width } | JoinKind::Full { outer_width, .. } => {
+                Some(outer_width)
+            }
+            _ => None,
+        }
+    }
+}
+
+// outer_keys と inner_keys の列 (複合キー) で並んだ二つの入力を、同じ値の行どうし結合する
+// (左の列の後に右の列を並べる)
+// 右の同じ値の行はまとめて持っておき、左の同じ値の行ごとに繰り返し返す
+// NULL を含むキーはどの行とも結合しない
+pub struct MergeJoin<T: BufferPoolManager, U: Iterable<T>> {
+    pub outer_plan: SharedPlan<T, U>,
+    pub inner_plan: SharedPlan<T, U>,
+    pub outer_keys: Vec<usize>,
+    pub inner_keys: Vec<usize>,
+    pub kind: JoinKind,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for MergeJoin<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for MergeJoin<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        let mut outer_iter = open(self.outer_plan.as_ref(), bufmgr)?;
+        let mut inner_iter = open(self.inner_plan.as_ref(), bufmgr)?;
+        let outer = outer_iter.next(bufmgr)?;
+        let inner = inner_iter.next(bufmgr)?;
+        Ok(Box::new(ExecMergeJoin {
+            outer_iter,
+            inner_iter,
+            outer_keys: self.outer_keys.clone(),
+            inner_keys: self.inner_keys.clone(),
+            kind: self.kind,
+            outer,
+            inner,
+            group_key: None,
+            group: vec![],
+            pos: 0,
+        }))
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "MergeJoin ({:?}, outer_keys: {:?}, inner_keys: {:?})",
+            self.kind, self.outer_keys, self.inner_keys
+        )
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.outer_plan.as_ref(), self.inner_plan.as_ref()]
+    }
+
+    // 相手のない右の行を返さなければ、左の行の順に返す
+    fn output_ordering(&self) -> Vec<SortKey> {
+        match self.kind.outer_width() {
+            Some(_) => vec![],
+            None => self.outer_plan.output_ordering(),
+        }
+    }
+}
+
+pub struct ExecMergeJoin<'a, T: BufferPoolManager> {
+    outer_iter: BoxExecutor<'a, T>,
+    inner_iter: BoxExecutor<'a, T>,
+    outer_keys: Vec<usize>,
+    inner_keys: Vec<usize>,
+    kind: JoinKind,
+    // 今の左の行と、まだ group に入れていない右の先頭の行
+    outer: Option<Row>,
+    inner: Option<Row>,
+    // 右の group_key の行と、今の左の行と次に結合するものの位置
+    group_key: Option<Vec<Value>>,
+    group: Vec<Row>,
+    pos: usize,
+}
+
+impl<'a, T: BufferPoolManager> ExecMergeJoin<'a, T> {
+    // 相手のない今の左の行を読み飛ばす (左外部結合なら NULL で埋めて返す)
+    fn skip_outer(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        let outer = std::mem::replace(&mut self.outer, self.outer_iter.next(bufmgr)?);
+        self.pos = 0;
+        match (outer, self.kind.inner_width()) {
+            (Some(outer), Some(inner_width)) => Ok(Some(outer.concat(&Row::nulls(inner_width)))),
+            _ => Ok(None),
+        }
+    }
+
+    // 相手のない右の先頭の行を読み飛ばす (右外部結合なら NULL で埋めて返す)
+    fn skip_inner(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        let inner = std::mem::replace(&mut self.inner, self.inner_iter.next(bufmgr)?);
+        match (inner, self.kind.outer_width()) {
+            (Some(inner), Some(outer_width)) => Ok(Some(Row::nulls(outer_width).concat(&inner))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<'a, T: BufferPoolManager> Executor<T> for ExecMergeJoin<'a, T> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        loop {
+            let outer = match &self.outer {
+                Some(outer) => outer,
+                None if self.inner.is_some() && self.kind.outer_width().is_some() => {
+                    match self.skip_inner(bufmgr)? {
+                        Some(row) => return Ok(Some(row)),
+                        None => continue,
+                    }
+                }
+                None => return Ok(None),
+            };
+            let key = key_values(outer, &self.outer_keys)?;
+            if self.group_key.as_ref() == Some(&key) {
+                if self.pos < self.group.len() {
+                    let row = outer.clone().concat(&self.group[self.pos]);
+                    self.pos += 1;
+                    return Ok(Some(row));
+                }
+                self.outer = self.outer_iter.next(bufmgr)?;
+                self.pos = 0;
+                continue;
+            }
+
+            // 左の値より小さい右の行を飛ばし、同じ値の行を集める
+            self.group.clear();
+            self.group_key = None;
+            self.pos = 0;
+            if key.iter().any(Value::is_null) {
+                match self.skip_outer(bufmgr)? {
+                    Some(row) => return Ok(Some(row)),
+                    None => continue,
+                }
+            }
+            while let Some(inner) = &self.inner {
+                match compare_keys(&key_values(inner, &self.inner_keys)?, &key)? {
+                    Ordering::Less => {
+                        if let Some(row) = self.skip_inner(bufmgr)? {
+                            return Ok(Some(row));
+                        }
+                    }
+                    Ordering::Equal => {
+                        let inner =
+                            std::mem::replace(&mut self.inner, self.inner_iter.next(bufmgr)?);
+                        self.group.extend(inner);
+                    }
+                    Ordering::Greater => break,
+                }
+            }
+            if self.group.is_empty() {
+                if self.inner.is_none() && self.kind.inner_width().is_none() {
+                    return Ok(None);
+                }
+                if let Some(row) = self.skip_outer(bufmgr)? {
+                    return Ok(Some(row));
+                }
+            } else {
+                self.group_key = Some(key);
+            }
+        }
+    }
+}
+
+// 集約関数 (COUNT と SUM は整数を返す)
+// SUM と MIN と MAX は NULL の列を読み飛ばし、NULL でない値がなければ NULL を返す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    // 行の数 (NULL も数える)
+    Count,
+    // 整数の列を足す
+    Sum(usize),
+    // 列の値を型に従って比べる
+    Min(usize),
+    Max(usize),
+}
+
+impl Aggregate {
+    fn init(&self, row: &Row) -> Result<Value> {
+        match *self {
+            Aggregate::Count => Ok(Value::Int(1)),
+            Aggregate::Sum(column) => sum_operand(row.get(column)?),
+            Aggregate::Min(column) | Aggregate::Max(column) => row.get(column),
+        }
+    }
+
+    fn update(&self, state: &mut Value, row: &Row) -> Result<()> {
+        let value = match *self {
+            Aggregate::Count => {
+                match state {
+                    Value::Int(count) => *count += 1,
+                    state => bail!("invalid state {:?} for {:?}", state, self),
+                }
+                return Ok(());
+            }
+            Aggregate::Sum(column) => sum_operand(row.get(column)?)?,
+            Aggregate::Min(column) | Aggregate::Max(column) => row.get(column)?,
+        };
+        if value.is_null() {
+            return Ok(());
+        }
+        if state.is_null() {
+            *state = value;
+            return Ok(());
+        }
+        match (*self, &mut *state, &value) {
+            (Aggregate::Sum(_), Value::Int(sum), Value::Int(n)) => {
+                *sum = match sum.checked_add(*n) {
+                    Some(sum) => sum,
+                    None => bail!("SUM overflowed"),
+                };
+            }
+            (Aggregate::Min(_), _, _) => {
+                if value.try_cmp(state)? == Ordering::Less {
+                    *state = value;
+                }
+            }
+            (Aggregate::Max(_), _, _) => {
+                if value.try_cmp(state)? == Ordering::Greater {
+                    *state = value;
+                }
+            }
+            (aggregate, state, _) => bail!("invalid state {:?} for {:?}", state, aggregate),
+        }
+        Ok(())
+    }
+}
+
+fn sum_operand(value: Value) -> Result<Value> {
+    match value {
+        Value::Int(_) | Value::Null => Ok(value),
+        value => bail!("cannot SUM {:?}", value),
+    }
+}
+
+// ハッシュ表に持つ値の大きさ (エンコードしたときのバイト数)
+fn value_size(value: &Value) -> usize {
+    match value {
+        Value::Int(_) => 8,
+        Value::Bool(_) => 1,
+        Value::Bytes(bytes) => bytes.len(),
+        Value::Null => 0,
+    }
+}
+
+// 集約でメモリに入りきらなかった行を分けるパーティションの数
+const AGGREGATE_SPILL_PARTITIONS: usize = 4;
+
+// group_cols の列の値が同じ行をハッシュ表でまとめ、グループごとに
+// [group_cols の値..., aggregates の値...] の一行を返す (順序は決まらない)
+// ハッシュ表が work_mem バイトを超えるか MemoryContext が足りなくなったら、
+// 新しいグループの行はハッシュ値で分けて一時ファイルへ書き出し、
+// 表のグループを返した後でパーティションごとに同じことを繰り返す
+// 入力が空なら何も返さない
+pub struct HashAggregate<T: BufferPoolManager, U: Iterable<T>> {
+    pub inner_plan: SharedPlan<T, U>,
+    pub group_cols: Vec<usize>,
+    pub aggregates: Vec<Aggregate>,
+    pub work_mem: usize,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for HashAggregate<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for HashAggregate<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        let mut inner_iter = open(self.inner_plan.as_ref(), bufmgr)?;
+        let mut exec = ExecHashAggregate {
+            group_cols: self.group_cols.clone(),
+            aggregates: self.aggregates.clone(),
+            work_mem: self.work_mem,
+            reservation: MemoryReservation::new(MemoryContext::current()),
+            temp: None,
+            groups: HashMap::new().into_iter(),
+            partitions: vec![],
+        };
+        exec.aggregate(|_| inner_iter.next(bufmgr))?;
+        Ok(Box::new(exec))
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "HashAggregate (group_cols: {:?}, aggregates: {:?}, work_mem: {})",
+            self.group_cols, self.aggregates, self.work_mem
+        )
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.inner_plan.as_ref()]
+    }
+}
+
+pub struct ExecHashAggregate {
+    group_cols: Vec<usize>,
+    aggregates: Vec<Aggregate>,
+    work_mem: usize,
+    // ハッシュ表の分 (集約し直すたびに空にする)
+    reservation: MemoryReservation,
+    // スピルするまでは作らない
+    temp: Option<Box<TempBufferManager>>,
+    // 返していないグループと、まだ集約していないパーティション
+    groups: hash_map::IntoIter<Vec<Value>, Vec<Value>>,
+    partitions: Vec<TupleRun>,
+}
+
+impl ExecHashAggregate {
+    // next_row で読んだ行を集約して groups にする
+    fn aggregate(
+        &mut self,
+        mut next_row: impl FnMut(&mut Option<Box<TempBufferManager>>) -> Result<Option<Row>>,
+    ) -> Result<()> {
+        let mut groups: HashMap<Vec<Value>, Vec<Value>> = HashMap::new();
+        let mut mem = 0;
+        self.reservation.clear();
+        let mut spilled: Vec<_> = (0..AGGREGATE_SPILL_PARTITIONS)
+            .map(|_| TupleRun::new())
+            .collect();
+        while let Some(row) = next_row(&mut self.temp)? {
+            let key = self
+                .group_cols
+                .iter()
+                .map(|&column| row.get(column))
+                .collect::<Result<Vec<_>>>()?;
+            if let Some(states) = groups.get_mut(&key) {
+                for (aggregate, state) in self.aggregates.iter().zip(states) {
+                    aggregate.update(state, &row)?;
+                }
+                continue;
+            }
+            let states = self
+                .aggregates
+                .iter()
+                .map(|aggregate| aggregate.init(&row))
+                .collect::<Result<Vec<_>>>()?;
+            let size = key.iter().chain(&states).map(value_size).sum::<usize>();
+            // 表が空なら必ず入れて、パーティションを読み直すたびに少なくとも一つは集約する
+            if groups.is_empty() {
+                self.reservation.grow(size);
+            } else if mem > self.work_mem || !self.reservation.try_grow(size) {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                let partition = hasher.finish() as usize % AGGREGATE_SPILL_PARTITIONS;
+                let temp = match &mut self.temp {
+                    Some(temp) => temp,
+                    None => self
+                        .temp
+                        .insert(Box::new(TempBufferManager::new(TEMP_POOL_SIZE)?)),
+                };
+                spilled[partition].push(temp.as_mut(), &row.to_tagged())?;
+                continue;
+            }
+            mem += size;
+            groups.insert(key, states);
+        }
+        self.groups = groups.into_iter();
+        self.partitions
+            .extend(spilled.into_iter().filter(|run| !run.is_empty()));
+        Ok(())
+    }
+}
+
+impl<T: BufferPoolManager> Executor<T> for ExecHashAggregate {
+    fn next(&mut self, _: &mut T) -> Result<Option<Row>> {
+        loop {
+            if let Some((mut values, states)) = self.groups.next() {
+                values.extend(states);
+                return Ok(Some(Row::from_values(values)));
+            }
+            let mut reader = match self.partitions.pop() {
+                Some(run) => run.into_reader(),
+                None => return Ok(None),
+            };
+            self.aggregate(|temp| match reader.next(temp.as_mut().unwrap().as_mut())? {
+                Some(tuple) => Ok(Some(Row::from_tagged(tuple)?)),
+                None => Ok(None),
+            })?;
+        }
+    }
+}
+
+// group_cols の列の値で並んだ入力の、値が同じ続きの行をまとめて HashAggregate と同じ行を返す
+// 値が変わったところでそれまでのグループを返すので、一グループ分しか持たない
+// 入力が並んでいなければ同じグループを何度も返す (並びは確かめない)
+pub struct SortedAggregate<T: BufferPoolManager, U: Iterable<T>> {
+    pub inner_plan: SharedPlan<T, U>,
+    pub group_cols: Vec<usize>,
+    pub aggregates: Vec<Aggregate>,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for SortedAggregate<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for SortedAggregate<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        Ok(Box::new(ExecSortedAggregate {
+            inner_iter: open(self.inner_plan.as_ref(), bufmgr)?,
+            group_cols: self.group_cols.clone(),
+            aggregates: self.aggregates.clone(),
+            group: None,
+        }))
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "SortedAggregate (group_cols: {:?}, aggregates: {:?})",
+            self.group_cols, self.aggregates
+        )
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.inner_plan.as_ref()]
+    }
+
+    // グループの列は先頭に並べて出すので、その位置に読み替えて引き継ぐ
+    fn output_ordering(&self) -> Vec<SortKey> {
+        let mut ordering = vec![];
+        for key in self.inner_plan.output_ordering() {
+            match self
+                .group_cols
+                .iter()
+                .position(|&column| column == key.column)
+            {
+                Some(column) => ordering.push(SortKey { column, ..key }),
+                None => break,
+            }
+        }
+        ordering
+    }
+}
+
+pub struct ExecSortedAggregate<'a, T: BufferPoolManager> {
+    inner_iter: BoxExecutor<'a, T>,
+    group_cols: Vec<usize>,
+    aggregates: Vec<Aggregate>,
+    // 集約している途中のグループの [group_cols の値] と [aggregates の値]
+    group: Option<(Vec<Value>, Vec<Value>)>,
+}
+
+impl<'a, T: BufferPoolManager> Executor<T> for ExecSortedAggregate<'a, T> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        while let Some(row) = self.inner_iter.next(bufmgr)? {
+            let key = key_values(&row, &self.group_cols)?;
+            if let Some((group_key, states)) = &mut self.group {
+                if *group_key == key {
+                    for (aggregate, state) in self.aggregates.iter().zip(states) {
+                        aggregate.update(state, &row)?;
+                    }
+                    continue;
+                }
+            }
+            let states = self
+                .aggregates
+                .iter()
+                .map(|aggregate| aggregate.init(&row))
+                .collect::<Result<Vec<_>>>()?;
+            if let Some((mut values, states)) = self.group.replace((key, states)) {
+                values.extend(states);
+                return Ok(Some(Row::from_values(values)));
+            }
+        }
+        Ok(self.group.take().map(|(mut values, states)| {
+            values.extend(states);
+            Row::from_values(values)
+        }))
+    }
+}
+
+// inner_plans の結果を順に続けて返す (UNION ALL)
+// distinct なら、前に返したものと同じ行は飛ばす (UNION)
+pub struct Union<T: BufferPoolManager, U: Iterable<T>> {
+    pub inner_plans: Vec<SharedPlan<T, U>>,
+    pub distinct: bool,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for Union<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for Union<T, U> {
+    fn start<'a>(&self, _: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        Ok(Box::new(ExecUnion {
+            inner_plans: self.inner_plans.clone(),
+            params: current_params(),
+            inner_iter: None,
+            next_plan: 0,
+            seen: if self.distinct {
+                Some(HashSet::new())
+            } else {
+                None
+            },
+        }))
+    }
+
+    fn describe(&self) -> String {
+        if self.distinct {
+            "Union".to_string()
+        } else {
+            "Union All".to_string()
+        }
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        self.inner_plans
+            .iter()
+            .map(|plan| plan.as_ref() as _)
+            .collect()
+    }
+}
+
+pub struct ExecUnion<'a, T: BufferPoolManager, U: Iterable<T>> {
+    inner_plans: Vec<SharedPlan<T, U>>,
+    // 子の実行器を作るときに束縛し直す値
+    params: Vec<Value>,
+    // 読んでいる子と、次に始める子 (子は前のものを読み終えてから始める)
+    inner_iter: Option<BoxExecutor<'a, T>>,
+    next_plan: usize,
+    // distinct のとき、返した行
+    seen: Option<HashSet<Tuple>>,
+}
+
+impl<'a, T: BufferPoolManager + 'a, U: Iterable<T>> Executor<T> for ExecUnion<'a, T, U> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        loop {
+            let inner_iter = match &mut self.inner_iter {
+                Some(inner_iter) => inner_iter,
+                None => match self.inner_plans.get(self.next_plan) {
+                    Some(plan) => {
+                        self.next_plan += 1;
+                        let params = self.params.clone();
+                        let inner_iter = with_params(params, || open(plan.as_ref(), bufmgr))?;
+                        self.inner_iter.insert(inner_iter)
+                    }
+                    None => return Ok(None),
+                },
+            };
+            let row = match inner_iter.next(bufmgr)? {
+                Some(row) => row,
+                None => {
+                    self.inner_iter = None;
+                    continue;
+                }
+            };
+            if let Some(seen) = &mut self.seen {
+                if !seen.insert(row.tuple().clone()) {
+                    continue;
+                }
+            }
+            return Ok(Some(row));
+        }
+    }
+}
+
+// inner_plan の返す行をテーブルから消し (インデックスの項目も消す)、消した行の数を
+// 整数の一列の行として一度だけ返す
+// 読んでいる木を書き換えないように、先に inner_plan を読み切ってから消す
+// 行は ITable::delete で物理的に消すので MVCC の外 (トランザクションの印は付けない)
+pub struct Delete<T: BufferPoolManager, U: Iterable<T>> {
+    pub table: Arc<dyn ITable<T> + Send + Sync>,
+    pub inner_plan: SharedPlan<T, U>,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for Delete<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for Delete<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        Ok(Box::new(ExecDelete {
+            table: self.table.clone(),
+            inner_iter: open(self.inner_plan.as_ref(), bufmgr)?,
+            done: false,
+        }))
+    }
+
+    fn describe(&self) -> String {
+        "Delete".to_string()
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.inner_plan.as_ref()]
+    }
+}
+
+pub struct ExecDelete<'a, T: BufferPoolManager> {
+    table: Arc<dyn ITable<T> + Send + Sync>,
+    inner_iter: BoxExecutor<'a, T>,
+    done: bool,
+}
+
+impl<'a, T: BufferPoolManager> Executor<T> for ExecDelete<'a, T> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+        let mut records = vec![];
+        while let Some(record) = self.inner_iter.next(bufmgr)? {
+            records.push(record);
+        }
+        for record in &records {
+            let record: Vec<_> = record.tuple().iter().map(|elem| elem.as_slice()).collect();
+            self.table.delete(bufmgr, &record)?;
+        }
+        Ok(Some(Row::from_values(vec![Value::Int(
+            records.len() as i64
+        )])))
+    }
+}
+
+// INSERT する行と主キーが同じ行がすでにあるときにすること
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnConflict {
+    // 主キーの重複で失敗する
+    Error,
+    // その行は INSERT せず、数えもしない
+    Nothing,
+    // 今の行の列に INSERT しようとした行の列を続けた行で式を評価し、今の行の列を書き換える
+    Update(Vec<(usize, Expr)>),
+}
+
+// inner_plan の返す行 (テーブルの全ての列を並べたもの) をテーブルに INSERT し、INSERT した行の数を
+// 整数の一列の行として一度だけ返す (ON CONFLICT で書き換えた行も数える)
+// 主キーが NULL の行は sequence で主キーを振る
+// 書き込むテーブルを inner_plan が読むなら、inner_plan を Materialize にして先に読み切っておく
+// 途中の行で失敗したら、それまでに書いた行を逆の順に戻してからエラーを返す (文は全て書くか何も書かない)
+pub struct Insert<T: BufferPoolManager, U: Iterable<T>> {
+    pub table: Arc<dyn ITable<T> + Send + Sync>,
+    pub sequence: Option<Arc<Mutex<Sequence>>>,
+    pub inner_plan: SharedPlan<T, U>,
+    pub on_conflict: OnConflict,
+    // テーブルの列の型と主キーの列の数 (ON CONFLICT DO UPDATE の式を評価するのに使う)
+    pub types: Vec<Type>,
+    pub num_key_elems: usize,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for Insert<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for Insert<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        Ok(Box::new(ExecInsert {
+            table: self.table.clone(),
+            sequence: self.sequence.clone(),
+            on_conflict: self.on_conflict.clone(),
+            types: self.types.clone(),
+            num_key_elems: self.num_key_elems,
+            inner_iter: open(self.inner_plan.as_ref(), bufmgr)?,
+            done: false,
+        }))
+    }
+
+    fn describe(&self) -> String {
+        match &self.on_conflict {
+            OnConflict::Error => "Insert".to_string(),
+            OnConflict::Nothing => "Insert (on conflict: nothing)".to_string(),
+            OnConflict::Update(_) => "Insert (on conflict: update)".to_string(),
+        }
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.inner_plan.as_ref()]
+    }
+}
+
+// 文が書いたものを戻すための記録
+enum InsertUndo {
+    // INSERT した行 (消せば戻る)
+    Inserted(Tuple),
+    // ON CONFLICT DO UPDATE で書き換える前の行 (書き戻せば戻る)
+    Updated(Tuple),
+}
+
+pub struct ExecInsert<'a, T: BufferPoolManager> {
+    table: Arc<dyn ITable<T> + Send + Sync>,
+    sequence: Option<Arc<Mutex<Sequence>>>,
+    on_conflict: OnConflict,
+    types: Vec<Type>,
+    num_key_elems: usize,
+    inner_iter: BoxExecutor<'a, T>,
+    done: bool,
+}
+
+impl<'a, T: BufferPoolManager> ExecInsert<'a, T> {
+    // 主キーが同じ行があれば on_conflict に従い、書いたら戻し方を返す
+    fn insert(&self, bufmgr: &mut T, record: &[&[u8]]) -> Result<Option<InsertUndo>> {
+        let inserted = || InsertUndo::Inserted(record.iter().map(|elem| elem.to_vec()).collect());
+        let assignments = match &self.on_conflict {
+            OnConflict::Error => {
+                self.table.insert(bufmgr, record)?;
+                return Ok(Some(inserted()));
+            }
+            OnConflict::Nothing => None,
+            OnConflict::Update(assignments) => Some(assignments),
+        };
+        let old = match self.table.get(bufmgr, &record[..self.num_key_elems])? {
+            Some(old) => old,
+            None => {
+                self.table.insert(bufmgr, record)?;
+                return Ok(Some(inserted()));
+            }
+        };
+        let assignments = match assignments {
+            Some(assignments) => assignments,
+            None => return Ok(None),
+        };
+        let types = self.types.iter().chain(&self.types);
+        let values = old
+            .iter()
+            .map(Vec::as_slice)
+            .chain(record.iter().copied())
+            .zip(types)
+            .map(|(bytes, &column_type)| Value::decode(column_type, bytes))
+            .collect::<Result<_>>()?;
+        let row = Row::from_values(values);
+        let mut new = old.clone();
+        for (column, expr) in assignments {
+            new[*column] = expr.eval(&row)?.encode();
+        }
+        let new: Vec<_> = new.iter().map(Vec::as_slice).collect();
+        self.table.upsert(bufmgr, &new)?;
+        Ok(Some(InsertUndo::Updated(old)))
+    }
+
+    // 行を一つずつ書き、書いた行の数を返す (書いたものは undo に積む)
+    fn insert_all(&mut self, bufmgr: &mut T, undo: &mut Vec<InsertUndo>) -> Result<i64> {
+        let mut count = 0;
+        while let Some(row) = self.inner_iter.next(bufmgr)? {
+            let mut record = row.tuple().clone();
+            if let Some(sequence) = &self.sequence {
+                if record[0].is_empty() {
+                    let id = sequence.lock().unwrap().next_value(bufmgr)?;
+                    record[0] = Value::Int(id).encode();
+                }
+            }
+            let record: Vec<_> = record.iter().map(Vec::as_slice).collect();
+            if let Some(entry) = self.insert(bufmgr, &record)? {
+                undo.push(entry);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    // 書いた順と逆に戻す (振ったシーケンスの値は戻さない)
+    fn undo(&self, bufmgr: &mut T, undo: Vec<InsertUndo>) -> Result<()> {
+        for entry in undo.into_iter().rev() {
+            match entry {
+                InsertUndo::Inserted(record) => {
+                    let record: Vec<_> = record.iter().map(Vec::as_slice).collect();
+                    self.table.delete(bufmgr, &record)?;
+                }
+                InsertUndo::Updated(old) => {
+                    let old: Vec<_> = old.iter().map(Vec::as_slice).collect();
+                    self.table.upsert(bufmgr, &old)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T: BufferPoolManager> Executor<T> for ExecInsert<'a, T> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+        let mut undo = vec![];
+        match self.insert_all(bufmgr, &mut undo) {
+            Ok(count) => Ok(Some(Row::from_values(vec![Value::Int(count)]))),
+            Err(err) => {
+                self.undo(bufmgr, undo)?;
+                Err(err)
+            }
+        }
+    }
+}
+
+// inner_plan の結果を最初に読み切って持っておき、rewind で何度でも先頭から返す
+// work_mem バイトを超えた分や MemoryContext から確保できなかった分は一時ファイルへ書き出す
+pub struct Materialize<T: BufferPoolManager, U: Iterable<T>> {
+    pub inner_plan: SharedPlan<T, U>,
+    pub work_mem: usize,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for Materialize<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for Materialize<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        let mut inner_iter = open(self.inner_plan.as_ref(), bufmgr)?;
+        let mut rows = vec![];
+        let mut mem = 0;
+        let mut reservation = MemoryReservation::new(MemoryContext::current());
+        let mut spilled: Option<(TempBufferManager, TupleRun)> = None;
+        while let Some(row) = inner_iter.next(bufmgr)? {
+            if let Some((temp, run)) = &mut spilled {
+                run.push(temp, &row.to_tagged())?;
+                continue;
+            }
+            let size = row.tuple().iter().map(|elem| elem.len()).sum::<usize>();
+            mem += size;
+            if mem > self.work_mem || !reservation.try_grow(size) {
+                let mut temp = TempBufferManager::new(TEMP_POOL_SIZE)?;
+                let mut run = TupleRun::new();
+                run.push(&mut temp, &row.to_tagged())?;
+                spilled = Some((temp, run));
+                continue;
+            }
+            rows.push(row);
+        }
+        Ok(Box::new(ExecMaterialize {
+            rows,
+            _reservation: reservation,
+            pos: 0,
+            spilled: spilled.map(|(temp, run)| (Box::new(temp), run.into_reader())),
+        }))
+    }
+
+    fn describe(&self) -> String {
+        format!("Materialize (work_mem: {})", self.work_mem)
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.inner_plan.as_ref()]
+    }
+
+    fn output_ordering(&self) -> Vec<SortKey> {
+        self.inner_plan.output_ordering()
+    }
+}
+
+pub struct ExecMaterialize {
+    // メモリに持っている先頭の行と、次に返すものの位置
+    rows: Vec<Row>,
+    _reservation: MemoryReservation,
+    pos: usize,
+    // 書き出した残りの行
+    spilled: Option<(Box<TempBufferManager>, TupleRunReader)>,
+}
+
+impl<T: BufferPoolManager> Executor<T> for ExecMaterialize {
+    fn next(&mut self, _: &mut T) -> Result<Option<Row>> {
+        if let Some(row) = self.rows.get(self.pos) {
+            self.pos += 1;
+            return Ok(Some(row.clone()));
+        }
+        match &mut self.spilled {
+            Some((temp, reader)) => match reader.next(temp.as_mut())? {
+                Some(tuple) => Ok(Some(Row::from_tagged(tuple)?)),
+                None => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn rewind(&mut self, _: &mut T) -> Result<bool> {
+        self.pos = 0;
+        if let Some((_, reader)) = &mut self.spilled {
+            reader.rewind();
+        }
+        Ok(true)
+    }
+}
+
+// 与えた行をそのまま返す (テーブルを作らずに小さな行の集まりを結合や挿入に渡す)
+pub struct Values<T: BufferPoolManager, U: Iterable<T>> {
+    rows: Arc<[Row]>,
+    _marker: PhantomData<fn() -> (T, U)>,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> Values<T, U> {
+    pub fn new(rows: Vec<Row>) -> Self {
+        Self {
+            rows: Arc::from(rows),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for Values<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for Values<T, U> {
+    fn start<'a>(&self, _: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        Ok(Box::new(ExecValues {
+            rows: self.rows.clone(),
+            pos: 0,
+        }))
+    }
+
+    fn describe(&self) -> String {
+        format!("Values (rows: {})", self.rows.len())
+    }
+}
+
+pub struct ExecValues {
+    rows: Arc<[Row]>,
+    pos: usize,
+}
+
+impl<T: BufferPoolManager> Executor<T> for ExecValues {
+    fn next(&mut self, _: &mut T) -> Result<Option<Row>> {
+        let row = self.rows.get(self.pos).cloned();
+        self.pos += row.is_some() as usize;
+        Ok(row)
+    }
+
+    fn rewind(&mut self, _: &mut T) -> Result<bool> {
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+// 左の行ごとに右の行を全て読み、cond を満たす組を結合する (左の列の後に右の列を並べる)
+// 右の実行器を rewind できれば作り直さずに読み直すので、右を Materialize にすれば
+// 右のプランを左の行ごとに実行しなくて済む
+// 右外部結合では、右の行が毎回同じ順に返ることを前提に相手のあった位置を覚えておき、
+// 左を読み終えてからもう一度右を読んで相手のなかった行を返す
+pub struct NestedLoopJoin<T: BufferPoolManager, U: Iterable<T>> {
+    pub outer_plan: SharedPlan<T, U>,
+    pub inner_plan: SharedPlan<T, U>,
+    pub cond: Expr,
+    pub kind: JoinKind,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for NestedLoopJoin<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for NestedLoopJoin<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        Ok(Box::new(ExecNestedLoopJoin {
+            outer_iter: open(self.outer_plan.as_ref(), bufmgr)?,
+            inner_plan: self.inner_plan.clone(),
+            cond: self.cond.bind_params()?,
+            kind: self.kind,
+            params: current_params(),
+            outer: None,
+            outer_matched: false,
+            inner_iter: None,
+            inner_pos: 0,
+            inner_matched: vec![],
+            outer_done: false,
+        }))
+    }
+
+    fn describe(&self) -> String {
+        format!("NestedLoopJoin ({:?}, {:?})", self.kind, self.cond)
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.outer_plan.as_ref(), self.inner_plan.as_ref()]
+    }
+
+    // 相手のない右の行を返さなければ、左の行の順に返す
+    fn output_ordering(&self) -> Vec<SortKey> {
+        match self.kind.outer_width() {
+            Some(_) => vec![],
+            None => self.outer_plan.output_ordering(),
+        }
+    }
+}
+
+pub struct ExecNestedLoopJoin<'a, T: BufferPoolManager, U: Iterable<T>> {
+    outer_iter: BoxExecutor<'a, T>,
+    inner_plan: SharedPlan<T, U>,
+    cond: Expr,
+    kind: JoinKind,
+    // 右の実行器を作り直すときに束縛し直す値
+    params: Vec<Value>,
+    // 今の左の行と、それに相手があったかどうか
+    outer: Option<Row>,
+    outer_matched: bool,
+    // 今の左の行と組み合わせている右の実行器 (次の左の行で読み直す) と、次に読む右の行の位置
+    inner_iter: Option<BoxExecutor<'a, T>>,
+    inner_pos: usize,
+    // 右外部結合で、相手のあった右の行の位置
+    inner_matched: Vec<bool>,
+    // 左を読み終えて、相手のなかった右の行を返しているところ
+    outer_done: bool,
+}
+
+impl<'a, T: BufferPoolManager + 'a, U: Iterable<T>> ExecNestedLoopJoin<'a, T, U> {
+    fn restart_inner(&mut self, bufmgr: &mut T) -> Result<()> {
+        let rewound = match &mut self.inner_iter {
+            Some(inner_iter) => inner_iter.rewind(bufmgr)?,
+            None => false,
+        };
+        if !rewound {
+            let inner_plan = self.inner_plan.as_ref();
+            let inner_iter = with_params(self.params.clone(), || open(inner_plan, bufmgr))?;
+            self.inner_iter = Some(inner_iter);
+        }
+        self.inner_pos = 0;
+        Ok(())
+    }
+}
+
+impl<'a, T: BufferPoolManager + 'a, U: Iterable<T>> Executor<T> for ExecNestedLoopJoin<'a, T, U> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        loop {
+            if self.outer.is_none() && !self.outer_done {
+                match self.outer_iter.next(bufmgr)? {
+                    Some(outer) => {
+                        self.outer = Some(outer);
+                        self.outer_matched = false;
+                    }
+                    None if self.kind.outer_width().is_some() => self.outer_done = true,
+                    None => return Ok(None),
+                }
+                self.restart_inner(bufmgr)?;
+                continue;
+            }
+            let inner = match &mut self.inner_iter {
+                Some(inner_iter) => inner_iter.next(bufmgr)?,
+                None => None,
+            };
+            let pos = self.inner_pos;
+            self.inner_pos += 1;
+            match (self.outer.take(), inner) {
+                (Some(outer), Some(inner)) => {
+                    let row = outer.clone().concat(&inner);
+                    self.outer = Some(outer);
+                    if self.cond.is_true(&row)? {
+                        self.outer_matched = true;
+                        if self.kind.outer_width().is_some() {
+                            if self.inner_matched.len() <= pos {
+                                self.inner_matched.resize(pos + 1, false);
+                            }
+                            self.inner_matched[pos] = true;
+                        }
+                        return Ok(Some(row));
+                    }
+                }
+                (Some(outer), None) => {
+                    if let (false, Some(inner_width)) =
+                        (self.outer_matched, self.kind.inner_width())
+                    {
+                        return Ok(Some(outer.concat(&Row::nulls(inner_width))));
+                    }
+                }
+                (None, Some(inner)) => {
+                    let matched = self.inner_matched.get(pos).copied().unwrap_or(false);
+                    if let (false, Some(outer_width)) = (matched, self.kind.outer_width()) {
+                        return Ok(Some(Row::nulls(outer_width).concat(&inner)));
+                    }
+                }
+                (None, None) => return Ok(None),
+            }
+        }
+    }
+}
+
+// 相関副問合せの結果を外側の行にどう付け足すか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplyKind {
+    // 一行一列の結果の値 (行がないか二行以上あれば失敗する)
+    Scalar,
+    // 結果が一行でもあるかどうか
+    Exists,
+}
+
+// 左の行ごとに params を評価して Expr::Param に束縛し、inner_plan を実行し直して
+// その結果を左の列の後に一列足す
+// 同じ params の値が続けて現れることが多いので、結果は値ごとに覚えておく
+pub struct Apply<T: BufferPoolManager, U: Iterable<T>> {
+    pub outer_plan: SharedPlan<T, U>,
+    pub inner_plan: SharedPlan<T, U>,
+    pub params: Vec<Expr>,
+    pub kind: ApplyKind,
+}
+
+impl<T: BufferPoolManager, U: Iterable<T>> HaveAccessMethod<T> for Apply<T, U> {
+    type Iter = U;
+
+    fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+    fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<T, Iterable = Self::Iter>>> {
+        None
+    }
+}
+
+impl<T: BufferPoolManager, U: 'static + Iterable<T>> PlanNode<T> for Apply<T, U> {
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a,
+    {
+        Ok(Box::new(ExecApply {
+            outer_iter: open(self.outer_plan.as_ref(), bufmgr)?,
+            inner_plan: self.inner_plan.clone(),
+            params: bind_params(&self.params)?,
+            kind: self.kind,
+            cache: HashMap::new(),
+        }))
+    }
+
+    fn describe(&self) -> String {
+        format!("Apply ({:?}, params: {:?})", self.kind, self.params)
+    }
+
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = U>> {
+        vec![self.outer_plan.as_ref(), self.inner_plan.as_ref()]
+    }
+
+    fn output_ordering(&self) -> Vec<SortKey> {
+        self.outer_plan.output_ordering()
+    }
+}
+
+pub struct ExecApply<'a, T: BufferPoolManager, U: Iterable<T>> {
+    outer_iter: BoxExecutor<'a, T>,
+    inner_plan: SharedPlan<T, U>,
+    params: Vec<Expr>,
+    kind: ApplyKind,
+    cache: HashMap<Vec<Value>, Value>,
+}
+
+impl<'a, T: BufferPoolManager + 'a, U: Iterable<T>> ExecApply<'a, T, U> {
+    fn run_inner(&self, bufmgr: &mut T, params: Vec<Value>) -> Result<Value> {
+        with_params(params, || {
+            let mut inner_iter = open(self.inner_plan.as_ref(), bufmgr)?;
+            let first = inner_iter.next(bufmgr)?;
+            match self.kind {
+                ApplyKind::Exists => Ok(Value::Bool(first.is_some())),
+                ApplyKind::Scalar => {
+                    let row = match first {
+                        Some(row) => row,
+                        None => bail!("scalar subquery returned no rows"),
+                    };
+                    if row.len() != 1 {
+                        bail!("scalar subquery returned {} columns", row.len());
+                    }
+                    if inner_iter.next(bufmgr)?.is_some() {
+                        bail!("scalar subquery returned more than one row");
+                    }
+                    row.get(0)
+                }
+            }
+        })
+    }
+}
+
+impl<'a, T: BufferPoolManager + 'a, U: Iterable<T>> Executor<T> for ExecApply<'a, T, U> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        let outer = match self.outer_iter.next(bufmgr)? {
+            Some(outer) => outer,
+            None => return Ok(None),
+        };
+        let params = self
+            .params
+            .iter()
+            .map(|param| param.eval(&outer))
+            .collect::<Result<Vec<_>>>()?;
+        let value = match self.cache.get(&params) {
+            Some(value) => value.clone(),
+            None => {
+                let value = self.run_inner(bufmgr, params.clone())?;
+                self.cache.insert(params, value.clone());
+                value
+            }
+        };
+        Ok(Some(outer.concat(&Row::from_values(vec![value]))))
+    }
+}
+
+// EXPLAIN ANALYZE で計測したノードごとの値
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NodeStats {
+    // 実行器を作った回数 (rewind で読み直した回数も含む)
+    pub loops: u64,
+    // 返した行の数
+    pub rows: u64,
+    // 実行器を作るときと next で取得したページの数 (子の分も含む)
+    pub fetches: u64,
+    // next と next_batch を呼ばれた回数
+    pub calls: u64,
+    // 実行器を作るときと next にかかった時間 (子の分も含む)
+    pub elapsed: Duration,
+}
+
+// プランのノードを見分ける値 (ノードのアドレス)
+fn node_id<T: BufferPoolManager, U: Iterable<T>>(plan: &dyn PlanNode<T, Iter = U>) -> usize {
+    plan as *const dyn PlanNode<T, Iter = U> as *const () as usize
+}
+
+thread_local! {
+    // explain_analyze の実行中だけ Some になる (ノードのアドレスで引く)
+    static ANALYZE_STATS: RefCell<Option<HashMap<usize, NodeStats>>> = const { RefCell::new(None) };
+}
+
+fn update_stats(node: usize, f: impl FnOnce(&mut NodeStats)) {
+    ANALYZE_STATS.with(|stats| {
+        if let Some(stats) = stats.borrow_mut().as_mut() {
+            f(stats.entry(node).or_default());
+        }
+    });
+}
+
+// 子のプランの実行器を作る
+// explain_analyze の実行中なら計測する実行器で包む
+fn open<'a, T: BufferPoolManager + 'a, U: Iterable<T>>(
+    plan: &dyn PlanNode<T, Iter = U>,
+    bufmgr: &mut T,
+) -> Result<BoxExecutor<'a, T>> {
+    if ANALYZE_STATS.with(|stats| stats.borrow().is_none()) {
+        return plan.start(bufmgr);
+    }
+    let node = node_id(plan);
+    let (fetches, started) = (bufmgr.fetch_count(), Instant::now());
+    let inner_iter = plan.start(bufmgr)?;
+    update_stats(node, |stats| {
+        stats.loops += 1;
+        stats.fetches += bufmgr.fetch_count() - fetches;
+        stats.elapsed += started.elapsed();
+    });
+    Ok(Box::new(ExecAnalyze { inner_iter, node }))
+}
+
+// params を Expr::Param に束縛してプランの実行器を作る
+// 値は実行器を作るときに写し取るので、同じプランを値を変えて何度でも実行できる
+pub fn start_with_params<'a, T: BufferPoolManager + 'a, U: Iterable<T>>(
+    plan: &dyn PlanNode<T, Iter = U>,
+    bufmgr: &mut T,
+    params: Vec<Value>,
+) -> Result<BoxExecutor<'a, T>> {
+    with_params(params, || open(plan, bufmgr))
+}
+
+pub struct ExecAnalyze<'a, T: BufferPoolManager> {
+    inner_iter: BoxExecutor<'a, T>,
+    node: usize,
+}
+
+impl<'a, T: BufferPoolManager> Executor<T> for ExecAnalyze<'a, T> {
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>> {
+        let (fetches, started) = (bufmgr.fetch_count(), Instant::now());
+        let row = self.inner_iter.next(bufmgr)?;
+        update_stats(self.node, |stats| {
+            stats.rows += row.is_some() as u64;
+            stats.fetches += bufmgr.fetch_count() - fetches;
+            stats.calls += 1;
+            stats.elapsed += started.elapsed();
+        });
+        Ok(row)
+    }
+
+    fn next_batch(&mut self, bufmgr: &mut T, out: &mut TupleBatch) -> Result<()> {
+        let (fetches, started) = (bufmgr.fetch_count(), Instant::now());
+        self.inner_iter.next_batch(bufmgr, out)?;
+        update_stats(self.node, |stats| {
+            stats.rows += out.len() as u64;
+            stats.fetches += bufmgr.fetch_count() - fetches;
+            stats.calls += 1;
+            stats.elapsed += started.elapsed();
+        });
+        Ok(())
+    }
+
+    // 読み直すのも一回の実行として数える
+    fn rewind(&mut self, bufmgr: &mut T) -> Result<bool> {
+        let (fetches, started) = (bufmgr.fetch_count(), Instant::now());
+        let rewound = self.inner_iter.rewind(bufmgr)?;
+        update_stats(self.node, |stats| {
+            stats.loops += rewound as u64;
+            stats.fetches += bufmgr.fetch_count() - fetches;
+            stats.elapsed += started.elapsed();
+        });
+        Ok(rewound)
+    }
+}
+
+// analyze で計測した、プランのノードごとの NodeStats
+#[derive(Debug, Default, Clone)]
+pub struct PlanStats {
+    nodes: HashMap<usize, NodeStats>,
+}
+
+impl PlanStats {
+    // plan のノード (analyze に渡したプランの木の中のもの) の値
+    pub fn get<T: BufferPoolManager, U: Iterable<T>>(
+        &self,
+        plan: &dyn PlanNode<T, Iter = U>,
+    ) -> NodeStats {
+        self.nodes.get(&node_id(plan)).copied().unwrap_or_default()
+    }
+
+    // 各ノードの説明に、呼ばれた回数とかかった時間も含めて全ての値を書き足す
+    pub fn explain<T: BufferPoolManager, U: Iterable<T>>(
+        &self,
+        plan: &dyn PlanNode<T, Iter = U>,
+    ) -> String {
+        plan.explain_with(&|node| {
+            let stats = self.nodes.get(&node).copied().unwrap_or_default();
+            format!(
+                " (loops={} rows={} fetches={} calls={} time={:.3}ms)",
+                stats.loops,
+                stats.rows,
+                stats.fetches,
+                stats.calls,
+                stats.elapsed.as_secs_f64() * 1000.0
+            )
+        })
+    }
+}
+
+// plan を最後まで実行し (行は捨てる)、ノードごとの NodeStats を返す
+// ページの数は fetch_count を数える buffermanager (RecordingManager など) でなければ 0 になる
+pub fn analyze<T: BufferPoolManager, U: Iterable<T>>(
+    plan: &dyn PlanNode<T, Iter = U>,
+    bufmgr: &mut T,
+) -> Result<PlanStats> {
+    ANALYZE_STATS.with(|stats| *stats.borrow_mut() = Some(HashMap::new()));
+    let result = open(plan, bufmgr).and_then(|mut exec| {
+        while exec.next(bufmgr)?.is_some() {}
+        Ok(())
+    });
+    let nodes = ANALYZE_STATS.with(|stats| stats.borrow_mut().take().unwrap_or_default());
+    result?;
+    Ok(PlanStats { nodes })
+}
+
+// analyze して、各ノードの説明に実行ごとに変わらない値を書き足して返す (EXPLAIN ANALYZE)
+pub fn explain_analyze<T: BufferPoolManager, U: Iterable<T>>(
+    plan: &dyn PlanNode<T, Iter = U>,
+    bufmgr: &mut T,
+) -> Result<String> {
+    let stats = analyze(plan, bufmgr)?;
+    Ok(plan.explain_with(&|node| {
+        let stats = stats.nodes.get(&node).copied().unwrap_or_default();
+        format!(
+            " (loops={} rows={} fetches={})",
+            stats.loops, stats.rows, stats.fetches
+        )
+    }))
+}
+
+// 実行器を持ち続けて、fetch のたびに続きの行を返す
+// 呼ぶ側は fetch の合間に同じバッファプールで他のことをしてよい
+pub struct Cursor<'a, T: BufferPoolManager> {
+    exec: BoxExecutor<'a, T>,
+    done: bool,
+    fetched: u64,
+}
+
+impl<'a, T: BufferPoolManager + 'a> Cursor<'a, T> {
+    pub fn open<U: Iterable<T>>(plan: &dyn PlanNode<T, Iter = U>, bufmgr: &mut T) -> Result<Self> {
+        Ok(Self {
+            exec: open(plan, bufmgr)?,
+            done: false,
+            fetched: 0,
+        })
+    }
+
+    // 最大 n 行を返す (n 行より少なければ終わり)
+    // 失敗したらそれ以降は何も返さない
+    pub fn fetch(&mut self, bufmgr: &mut T, n: usize) -> Result<Vec<Row>> {
+        let mut rows = vec![];
+        while !self.done && rows.len() < n {
+            let mut batch = TupleBatch::new(n - rows.len());
+            if let Err(err) = self.exec.next_batch(bufmgr, &mut batch) {
+                self.done = true;
+                return Err(err);
+            }
+            if batch.is_empty() {
+                self.done = true;
+            }
+            rows.append(batch.rows_mut());
+        }
+        self.fetched += rows.len() as u64;
+        Ok(rows)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    // これまでに返した行の数
+    pub fn fetched(&self) -> u64 {
+        self.fetched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdbms::expr::{ArithOp, CmpOp};
+    use crate::rdbms::{
+        btree::BTree,
+        clocksweep::ClockSweepManager,
+        memctx::with_memory_context,
+        memory::MemoryManager,
+        mvcc::AllVisible,
+        recording::RecordingManager,
+        table::{NonUniqueIndex, Table, UniqueIndex},
+    };
+    use crate::wal::entity::TxnId;
+
+    use crate::accessor::{entity::SearchMode, method};
+    use crate::buffer::{
+        entity::{Buffer, Checkpoint},
+        manager::{BufferPoolManager, Error},
+    };
+    use crate::storage::entity::PageId;
+    use std::rc::Rc;
+
+    struct Empty {}
+    impl BufferPoolManager for Empty {
+        fn fetch_page(&mut self, _: PageId) -> Result<Rc<Buffer>, Error> {
+            panic!("Not implement!")
+        }
+        fn create_page(&mut self) -> Result<Rc<Buffer>, Error> {
+            panic!("Not implement!")
+        }
+        fn flush(&mut self) -> Result<(), Error> {
+            panic!("Not implement!")
+        }
+        fn checkpoint(&mut self) -> Result<Checkpoint, Error> {
+            panic!("Not implement!")
+        }
+        fn discard_page(&mut self, _: PageId) -> Result<(), Error> {
+            panic!("Not implement!")
+        }
+    }
+
+    struct Counter {
+        next: u8,
+        is_table: bool,
+    }
+    impl Counter {
+        fn new(init: u8, is_table: bool) -> Self {
+            Self {
+                next: init,
+                is_table,
+            }
+        }
+    }
+    impl Iterable<Empty> for Counter {
+        fn next(&mut self, _: &mut Empty) -> Result<Option<(Vec<u8>, Vec<u8>)>, method::Error> {
+            let c = self.next;
+            if c == u8::MAX {
+                Ok(None)
+            } else {
+                self.next += 1;
+                let mut key = vec![];
+                tuple::encode([&[c]].iter(), &mut key);
+                let mut val = vec![];
+                // テーブルの値にはヘッダが付く
+                if self.is_table {
+                    TupleHeader::new(TxnId::INVALID_TXN_ID).encode(&mut val);
+                }
+                tuple::encode([&[c]].iter(), &mut val);
+                Ok(Some((key, val)))
+            }
+        }
+    }
+
+    struct Generate {
+        is_table: bool,
+    }
+    impl AccessMethod<Empty> for Generate {
+        type Iterable = Counter;
+        fn search(
+            &self,
+            _: &mut Empty,
+            search_option: SearchMode,
+        ) -> Result<Self::Iterable, method::Error> {
+            match search_option {
+                SearchMode::Start => Ok(Counter::new(0, self.is_table)),
+                SearchMode::Key(n) => Ok(Counter::new(n[0], self.is_table)),
+            }
+        }
+        fn insert(&self, _: &mut Empty, _: &[u8], _: &[u8]) -> Result<(), method::Error> {
+            panic!("Not implement!")
+        }
+        fn delete(&self, _: &mut Empty, _: &[u8]) -> Result<(), method::Error> {
+            panic!("Not implement!")
+        }
+    }
+
+    // 与えた行を返すだけのプラン
+    struct Rows(Vec<Row>);
+    impl HaveAccessMethod<Empty> for Rows {
+        type Iter = Counter;
+
+        fn table_accessor(&self) -> Option<Box<&dyn AccessMethod<Empty, Iterable = Counter>>> {
+            None
+        }
+        fn index_accessor(&self) -> Option<Box<&dyn AccessMethod<Empty, Iterable = Counter>>> {
+            None
+        }
+    }
+    impl PlanNode<Empty> for Rows {
+        fn start<'a>(&self, _: &mut Empty) -> Result<BoxExecutor<'a, Empty>> {
+            Ok(Box::new(ExecRows(self.0.clone().into_iter())))
+        }
+
+        fn describe(&self) -> String {
+            format!("Rows ({} rows)", self.0.len())
+        }
+    }
+    struct ExecRows(std::vec::IntoIter<Row>);
+    impl Executor<Empty> for ExecRows {
+        fn next(&mut self, _: &mut Empty) -> Result<Option<Row>> {
+            Ok(self.0.next())
+        }
+    }
+
+    // 列を全てバイト列とみなす行
+    fn bytes_row(tuple: Tuple) -> Row {
+        Row::new(tuple, Arc::from(vec![]))
+    }
+
+    #[test]
+    fn seq_scan_test() {
+        let mut bufmgr = Empty {};
+        {
+            let plan = SeqScan {
+                table_accessor: Arc::new(Generate { is_table: true }),
+                search_mode: TupleSearchMode::Start,
+                num_key_elems: 1,
+                stop_key: Bound::Unbounded,
+                while_cond: Expr::TRUE,
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+
+            let res1 = exec.next(&mut bufmgr);
+            let first = res1.unwrap().unwrap().into_tuple();
+            assert_eq!(first, vec![&[0], &[0]]);
+
+            let res2 = exec.next(&mut bufmgr);
+            let second = res2.unwrap().unwrap().into_tuple();
+            assert_eq!(second, vec![&[1], &[1]]);
+        }
+        {
+            let plan = SeqScan {
+                table_accessor: Arc::new(Generate { is_table: true }),
+                search_mode: TupleSearchMode::Key(vec![vec![42u8]]),
+                num_key_elems: 1,
+                stop_key: Bound::Unbounded,
+                while_cond: Expr::TRUE,
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+
+            let res1 = exec.next(&mut bufmgr);
+            let first = res1.unwrap().unwrap().into_tuple();
+            assert_eq!(first, vec![&[42], &[42]]);
+
+            let res2 = exec.next(&mut bufmgr);
+            let second = res2.unwrap().unwrap().into_tuple();
+            assert_eq!(second, vec![&[43], &[43]]);
+        }
+        {
+            let plan = SeqScan {
+                table_accessor: Arc::new(Generate { is_table: true }),
+                search_mode: TupleSearchMode::Key(vec![vec![42u8]]),
+                num_key_elems: 1,
+                stop_key: Bound::Unbounded,
+                while_cond: Expr::Literal(Value::Bool(false)),
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+
+            let res1 = exec.next(&mut bufmgr);
+            let nodata = res1.unwrap();
+            assert!(nodata.is_none());
+        }
+    }
+    #[test]
+    fn stop_key_test() {
+        let mut bufmgr = Empty {};
+        for (stop_key, last) in [
+            (Bound::Included(vec![vec![44u8]]), 44),
+            (Bound::Excluded(vec![vec![44u8]]), 43),
+        ] {
+            let seq_scan = SeqScan {
+                table_accessor: Arc::new(Generate { is_table: true }),
+                search_mode: TupleSearchMode::Key(vec![vec![42u8]]),
+                num_key_elems: 1,
+                stop_key: stop_key.clone(),
+                while_cond: Expr::TRUE,
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
+            };
+            let index_scan = IndexScan {
+                table_accessor: Arc::new(Generate { is_table: true }),
+                index_accessor: Arc::new(Generate { is_table: false }),
+                search_mode: TupleSearchMode::Key(vec![vec![42u8]]),
+                skey: vec![1],
+                stop_key: stop_key.clone(),
+                while_cond: Expr::TRUE,
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
+            };
+            let index_only_scan = IndexOnlyScan {
+                index_accessor: Arc::new(Generate { is_table: false }),
+                search_mode: TupleSearchMode::Key(vec![vec![42u8]]),
+                num_key_elems: 1,
+                stop_key,
+                while_cond: Expr::TRUE,
+                schema: vec![],
+            };
+            let plans: [&dyn PlanNode<_, Iter = _>; 3] = [&seq_scan, &index_scan, &index_only_scan];
+            for plan in plans.iter() {
+                let mut exec = plan.start(&mut bufmgr).unwrap();
+                for c in 42..=last {
+                    assert_eq!(
+                        exec.next(&mut bufmgr).unwrap().unwrap().into_tuple(),
+                        vec![&[c], &[c]]
+                    );
+                }
+                assert!(exec.next(&mut bufmgr).unwrap().is_none());
+            }
+        }
+    }
+    #[test]
     fn filter_test() {
         let mut bufmgr = Empty {};
-        {
-            let is_odd = |n: u8| n % 2 == 1;
-            let plan = Filter {
-                cond: &|record| is_odd(record[1].as_slice()[0]),
-                inner_plan: &SeqScan {
-                    table_accessor: &Generate {},
-                    search_mode: TupleSearchMode::Start,
-                    while_cond: &|_| true,
-                },
+        {
+            let plan = Filter {
+                cond: Expr::Or(vec![
+                    Expr::compare(
+                        CmpOp::Eq,
+                        Expr::Column(1),
+                        Expr::Literal(Value::Bytes(vec![1])),
+                    ),
+                    Expr::compare(
+                        CmpOp::Eq,
+                        Expr::Column(1),
+                        Expr::Literal(Value::Bytes(vec![3])),
+                    ),
+                ]),
+                inner_plan: Arc::new(SeqScan {
+                    table_accessor: Arc::new(Generate { is_table: true }),
+                    search_mode: TupleSearchMode::Start,
+                    num_key_elems: 1,
+                    stop_key: Bound::Unbounded,
+                    while_cond: Expr::TRUE,
+                    visibility: Arc::new(AllVisible),
+                    schema: vec![],
+                }),
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+
+            let res1 = exec.next(&mut bufmgr);
+            let first = res1.unwrap().unwrap().into_tuple();
+            assert_eq!(first, vec![&[1], &[1]]);
+
+            let res2 = exec.next(&mut bufmgr);
+            let second = res2.unwrap().unwrap().into_tuple();
+            assert_eq!(second, vec![&[3], &[3]]);
+        }
+        {
+            let plan = Filter {
+                cond: Expr::compare(
+                    CmpOp::Lt,
+                    Expr::Column(1),
+                    Expr::Literal(Value::Bytes(vec![44])),
+                ),
+                inner_plan: Arc::new(SeqScan {
+                    table_accessor: Arc::new(Generate { is_table: true }),
+                    search_mode: TupleSearchMode::Key(vec![vec![42u8]]),
+                    num_key_elems: 1,
+                    stop_key: Bound::Unbounded,
+                    while_cond: Expr::TRUE,
+                    visibility: Arc::new(AllVisible),
+                    schema: vec![],
+                }),
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+
+            let res1 = exec.next(&mut bufmgr);
+            let first = res1.unwrap().unwrap().into_tuple();
+            assert_eq!(first, vec![&[42], &[42]]);
+
+            let res2 = exec.next(&mut bufmgr);
+            let second = res2.unwrap().unwrap().into_tuple();
+            assert_eq!(second, vec![&[43], &[43]]);
+
+            let res3 = exec.next(&mut bufmgr);
+            let nodata = res3.unwrap();
+            assert!(nodata.is_none());
+        }
+    }
+    #[test]
+    fn project_test() {
+        let mut bufmgr = Empty {};
+        let rows: SharedPlan<_, _> = Arc::new(Rows(vec![Row::from_values(vec![
+            Value::Bytes(b"a".to_vec()),
+            Value::Int(40),
+        ])]));
+        let plan = Project {
+            inner_plan: rows.clone(),
+            exprs: vec![
+                Expr::arith(ArithOp::Add, Expr::Column(1), Expr::Literal(Value::Int(2))),
+                Expr::Column(0),
+                Expr::compare(
+                    CmpOp::Eq,
+                    Expr::Column(0),
+                    Expr::Literal(Value::Bytes(b"a".to_vec())),
+                ),
+            ],
+        };
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        assert_eq!(
+            Row::from_values(vec![
+                Value::Int(42),
+                Value::Bytes(b"a".to_vec()),
+                Value::Bool(true)
+            ]),
+            exec.next(&mut bufmgr).unwrap().unwrap()
+        );
+        assert!(exec.next(&mut bufmgr).unwrap().is_none());
+    }
+    #[test]
+    fn index_scan_test() {
+        let mut bufmgr = Empty {};
+        {
+            let plan = IndexScan {
+                table_accessor: Arc::new(Generate { is_table: true }),
+                index_accessor: Arc::new(Generate { is_table: false }),
+                search_mode: TupleSearchMode::Start,
+                skey: vec![1],
+                stop_key: Bound::Unbounded,
+                while_cond: Expr::TRUE,
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+
+            let res1 = exec.next(&mut bufmgr);
+            let first = res1.unwrap().unwrap().into_tuple();
+            assert_eq!(first, vec![&[0], &[0]]);
+
+            let res2 = exec.next(&mut bufmgr);
+            let second = res2.unwrap().unwrap().into_tuple();
+            assert_eq!(second, vec![&[1], &[1]]);
+        }
+        {
+            let plan = IndexScan {
+                table_accessor: Arc::new(Generate { is_table: true }),
+                index_accessor: Arc::new(Generate { is_table: false }),
+                search_mode: TupleSearchMode::Key(vec![vec![42u8]]),
+                skey: vec![1],
+                stop_key: Bound::Unbounded,
+                while_cond: Expr::TRUE,
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+
+            let res1 = exec.next(&mut bufmgr);
+            let first = res1.unwrap().unwrap().into_tuple();
+            assert_eq!(first, vec![&[42], &[42]]);
+
+            let res2 = exec.next(&mut bufmgr);
+            let second = res2.unwrap().unwrap().into_tuple();
+            assert_eq!(second, vec![&[43], &[43]]);
+        }
+        {
+            let plan = IndexScan {
+                table_accessor: Arc::new(Generate { is_table: true }),
+                index_accessor: Arc::new(Generate { is_table: false }),
+                search_mode: TupleSearchMode::Key(vec![vec![42u8]]),
+                skey: vec![1],
+                stop_key: Bound::Unbounded,
+                while_cond: Expr::Literal(Value::Bool(false)),
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+
+            let res1 = exec.next(&mut bufmgr);
+            let nodata = res1.unwrap();
+            assert!(nodata.is_none());
+        }
+    }
+    #[test]
+    fn index_only_scan_test() {
+        let mut bufmgr = Empty {};
+        {
+            let plan = IndexOnlyScan {
+                index_accessor: Arc::new(Generate { is_table: false }),
+                search_mode: TupleSearchMode::Start,
+                num_key_elems: 1,
+                stop_key: Bound::Unbounded,
+                while_cond: Expr::TRUE,
+                schema: vec![],
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+
+            let res1 = exec.next(&mut bufmgr);
+            let first = res1.unwrap().unwrap().into_tuple();
+            assert_eq!(first, vec![&[0], &[0]]);
+
+            let res2 = exec.next(&mut bufmgr);
+            let second = res2.unwrap().unwrap().into_tuple();
+            assert_eq!(second, vec![&[1], &[1]]);
+        }
+        {
+            let plan = IndexOnlyScan {
+                index_accessor: Arc::new(Generate { is_table: false }),
+                search_mode: TupleSearchMode::Key(vec![vec![42u8]]),
+                num_key_elems: 1,
+                stop_key: Bound::Unbounded,
+                while_cond: Expr::TRUE,
+                schema: vec![],
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+
+            let res1 = exec.next(&mut bufmgr);
+            let first = res1.unwrap().unwrap().into_tuple();
+            assert_eq!(first, vec![&[42], &[42]]);
+
+            let res2 = exec.next(&mut bufmgr);
+            let second = res2.unwrap().unwrap().into_tuple();
+            assert_eq!(second, vec![&[43], &[43]]);
+        }
+        {
+            let plan = IndexOnlyScan {
+                index_accessor: Arc::new(Generate { is_table: false }),
+                search_mode: TupleSearchMode::Key(vec![vec![42u8]]),
+                num_key_elems: 1,
+                stop_key: Bound::Unbounded,
+                while_cond: Expr::Literal(Value::Bool(false)),
+                schema: vec![],
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+
+            let res1 = exec.next(&mut bufmgr);
+            let nodata = res1.unwrap();
+            assert!(nodata.is_none());
+        }
+    }
+    #[test]
+    fn sort_test() {
+        let mut bufmgr = Empty {};
+        let scan: SharedPlan<_, _> = Arc::new(SeqScan {
+            table_accessor: Arc::new(Generate { is_table: true }),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 1,
+            stop_key: Bound::Unbounded,
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        });
+        let sort_keys = [SortKey {
+            column: 1,
+            descending: true,
+        }];
+        // メモリに収まるときと、一タプルずつランに書き出すとき
+        for &work_mem in [usize::MAX, 0, 100].iter() {
+            let plan = Sort {
+                inner_plan: scan.clone(),
+                sort_keys: sort_keys.to_vec(),
+                work_mem,
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+            for c in (0..u8::MAX).rev() {
+                let tuple = exec.next(&mut bufmgr).unwrap().unwrap().into_tuple();
+                assert_eq!(tuple, vec![&[c], &[c]]);
+            }
+            assert!(exec.next(&mut bufmgr).unwrap().is_none());
+        }
+
+        // 同じ順位のものは入力の順を保つ
+        let plan = Sort {
+            inner_plan: scan.clone(),
+            sort_keys: vec![],
+            work_mem: 100,
+        };
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        for c in 0..u8::MAX {
+            let tuple = exec.next(&mut bufmgr).unwrap().unwrap().into_tuple();
+            assert_eq!(tuple, vec![&[c], &[c]]);
+        }
+        assert!(exec.next(&mut bufmgr).unwrap().is_none());
+    }
+    #[test]
+    fn merge_join_test() {
+        let mut bufmgr = Empty {};
+        {
+            let plan = MergeJoin {
+                outer_plan: Arc::new(SeqScan {
+                    table_accessor: Arc::new(Generate { is_table: true }),
+                    search_mode: TupleSearchMode::Start,
+                    num_key_elems: 1,
+                    stop_key: Bound::Unbounded,
+                    while_cond: Expr::TRUE,
+                    visibility: Arc::new(AllVisible),
+                    schema: vec![],
+                }),
+                inner_plan: Arc::new(IndexOnlyScan {
+                    index_accessor: Arc::new(Generate { is_table: false }),
+                    search_mode: TupleSearchMode::Key(vec![vec![42u8]]),
+                    num_key_elems: 1,
+                    stop_key: Bound::Unbounded,
+                    while_cond: Expr::compare(
+                        CmpOp::Lt,
+                        Expr::Column(0),
+                        Expr::Literal(Value::Bytes(vec![45])),
+                    ),
+                    schema: vec![],
+                }),
+                outer_keys: vec![0],
+                inner_keys: vec![1],
+                kind: JoinKind::Inner,
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+            for c in 42..45u8 {
+                let tuple = exec.next(&mut bufmgr).unwrap().unwrap().into_tuple();
+                assert_eq!(tuple, vec![&[c], &[c], &[c], &[c]]);
+            }
+            assert!(exec.next(&mut bufmgr).unwrap().is_none());
+        }
+        {
+            // 左右に同じ値が並んでいれば全ての組を返す
+            let rows = |keys: &[u8]| {
+                Rows(
+                    keys.iter()
+                        .enumerate()
+                        .map(|(i, &key)| bytes_row(vec![vec![key], vec![i as u8]]))
+                        .collect(),
+                )
+            };
+            let plan = MergeJoin {
+                outer_plan: Arc::new(rows(&[1, 1, 2, 3, 3, 5])),
+                inner_plan: Arc::new(rows(&[0, 1, 1, 3, 4, 5, 5])),
+                outer_keys: vec![0],
+                inner_keys: vec![0],
+                kind: JoinKind::Inner,
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+            let mut joined = vec![];
+            while let Some(row) = exec.next(&mut bufmgr).unwrap() {
+                let tuple = row.into_tuple();
+                assert_eq!(tuple[0], tuple[2]);
+                joined.push((tuple[1][0], tuple[3][0]));
+            }
+            assert_eq!(
+                vec![
+                    (0, 1),
+                    (0, 2),
+                    (1, 1),
+                    (1, 2),
+                    (3, 3),
+                    (4, 3),
+                    (5, 5),
+                    (5, 6)
+                ],
+                joined
+            );
+        }
+    }
+    #[test]
+    fn index_nested_loop_join_test() {
+        let mut bufmgr = Empty {};
+        let outer: SharedPlan<_, _> = Arc::new(Rows(vec![
+            bytes_row(vec![b"a".to_vec(), vec![3]]),
+            bytes_row(vec![b"b".to_vec(), vec![42]]),
+            bytes_row(vec![b"c".to_vec(), vec![3]]),
+        ]));
+        // 主キーで引くときとインデックスで引くとき
+        for index_accessor in [None, Some(Arc::new(Generate { is_table: false }))] {
+            let plan = IndexNestedLoopJoin {
+                outer_plan: outer.clone(),
+                table_accessor: Arc::new(Generate { is_table: true }),
+                index_accessor: index_accessor.map(|index| index as SharedAccessMethod<_, _>),
+                outer_keys: vec![1],
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+            for (name, c) in [(b"a", 3u8), (b"b", 42), (b"c", 3)].iter() {
+                let tuple = exec.next(&mut bufmgr).unwrap().unwrap().into_tuple();
+                assert_eq!(tuple, vec![&name[..], &[*c], &[*c], &[*c]]);
+            }
+            assert!(exec.next(&mut bufmgr).unwrap().is_none());
+        }
+    }
+    #[test]
+    fn hash_aggregate_test() {
+        let mut bufmgr = Empty {};
+        // [グループ, 値] の行 (値はグループごとに 0 から数える)
+        let rows: SharedPlan<_, _> = Arc::new(Rows(
+            (0..200i64)
+                .map(|n| {
+                    Row::from_values(vec![Value::Bytes(vec![(n % 7) as u8]), Value::Int(n / 7)])
+                })
+                .collect(),
+        ));
+        let aggregates = [
+            Aggregate::Count,
+            Aggregate::Sum(1),
+            Aggregate::Min(1),
+            Aggregate::Max(1),
+        ];
+        // メモリに収まるときと、一グループずつしか持てないとき
+        for &work_mem in [usize::MAX, 0].iter() {
+            let plan = HashAggregate {
+                inner_plan: rows.clone(),
+                group_cols: vec![0],
+                aggregates: aggregates.to_vec(),
+                work_mem,
             };
             let mut exec = plan.start(&mut bufmgr).unwrap();
+            let mut groups = vec![];
+            while let Some(row) = exec.next(&mut bufmgr).unwrap() {
+                let values: Vec<_> = (0..row.len()).map(|i| row.get(i).unwrap()).collect();
+                groups.push(values);
+            }
+            groups.sort();
+            let expected: Vec<_> = (0..7i64)
+                .map(|group| {
+                    let count = (200 - group + 6) / 7;
+                    vec![
+                        Value::Bytes(vec![group as u8]),
+                        Value::Int(count),
+                        Value::Int(count * (count - 1) / 2),
+                        Value::Int(0),
+                        Value::Int(count - 1),
+                    ]
+                })
+                .collect();
+            assert_eq!(expected, groups);
+        }
 
-            let res1 = exec.next(&mut bufmgr);
-            let first = res1.unwrap().unwrap();
-            assert_eq!(first, vec![&[1], &[1]]);
+        // 整数でない値の合計は失敗する
+        let plan = HashAggregate {
+            inner_plan: rows.clone(),
+            group_cols: vec![],
+            aggregates: vec![Aggregate::Sum(0)],
+            work_mem: usize::MAX,
+        };
+        assert!(plan.start(&mut bufmgr).is_err());
 
-            let res2 = exec.next(&mut bufmgr);
-            let second = res2.unwrap().unwrap();
-            assert_eq!(second, vec![&[3], &[3]]);
+        // NULL は COUNT でだけ数え、NULL しかなければ NULL になる
+        let rows = Rows(
+            [
+                [Value::Int(0), Value::Null],
+                [Value::Int(0), Value::Int(3)],
+                [Value::Int(0), Value::Null],
+                [Value::Int(1), Value::Null],
+            ]
+            .iter()
+            .map(|row| Row::from_values(row.to_vec()))
+            .collect(),
+        );
+        let plan = HashAggregate {
+            inner_plan: Arc::new(rows),
+            group_cols: vec![0],
+            aggregates: aggregates.to_vec(),
+            // NULL の行も型ごと書き出して読み戻す
+            work_mem: 0,
+        };
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        let mut groups = vec![];
+        while let Some(row) = exec.next(&mut bufmgr).unwrap() {
+            groups.push(
+                (0..row.len())
+                    .map(|i| row.get(i).unwrap())
+                    .collect::<Vec<_>>(),
+            );
         }
+        groups.sort();
+        let (n, i) = (Value::Null, Value::Int);
+        assert_eq!(
+            vec![
+                vec![i(0), i(3), i(3), i(3), i(3)],
+                vec![i(1), i(1), n.clone(), n.clone(), n],
+            ],
+            groups
+        );
+    }
+    #[test]
+    fn union_test() {
+        let mut bufmgr = Empty {};
+        let rows = |keys: &[u8]| Rows(keys.iter().map(|&key| bytes_row(vec![vec![key]])).collect());
+        let (first, second, third) = (rows(&[1, 2, 2]), rows(&[]), rows(&[3, 1]));
+        let inner_plans: Vec<SharedPlan<_, _>> =
+            vec![Arc::new(first), Arc::new(second), Arc::new(third)];
+        for &(distinct, expected) in [(false, &[1, 2, 2, 3, 1][..]), (true, &[1, 2, 3][..])].iter()
         {
-            let plan = Filter {
-                cond: &|record| record[1].as_slice() < &[44u8],
-                inner_plan: &SeqScan {
-                    table_accessor: &Generate {},
-                    search_mode: TupleSearchMode::Key(&[&[42u8]]),
-                    while_cond: &|_| true,
-                },
+            let plan = Union {
+                inner_plans: inner_plans.clone(),
+                distinct,
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+            for &key in expected {
+                assert_eq!(
+                    exec.next(&mut bufmgr).unwrap().unwrap().into_tuple(),
+                    vec![vec![key]]
+                );
+            }
+            assert!(exec.next(&mut bufmgr).unwrap().is_none());
+        }
+    }
+    #[test]
+    fn top_n_test() {
+        let mut bufmgr = Empty {};
+        // 負の数も数として並べる
+        let rows: SharedPlan<_, _> = Arc::new(Rows(
+            [5i64, -1, 4, -1, 5, 9, -2, 6]
+                .iter()
+                .enumerate()
+                .map(|(i, &n)| Row::from_values(vec![Value::Int(n), Value::Int(i as i64)]))
+                .collect(),
+        ));
+        let sort_keys = [SortKey {
+            column: 0,
+            descending: true,
+        }];
+        for &(limit, expected) in [
+            (3, &[(9, 5), (6, 7), (5, 0)][..]),
+            (0, &[][..]),
+            (4, &[(9, 5), (6, 7), (5, 0), (5, 4)][..]),
+            (
+                8,
+                &[
+                    (9, 5),
+                    (6, 7),
+                    (5, 0),
+                    (5, 4),
+                    (4, 2),
+                    (-1, 1),
+                    (-1, 3),
+                    (-2, 6),
+                ][..],
+            ),
+        ]
+        .iter()
+        {
+            let plan = TopN {
+                inner_plan: rows.clone(),
+                sort_keys: sort_keys.to_vec(),
+                limit,
             };
             let mut exec = plan.start(&mut bufmgr).unwrap();
+            for &(n, i) in expected {
+                assert_eq!(
+                    exec.next(&mut bufmgr).unwrap().unwrap(),
+                    Row::from_values(vec![Value::Int(n), Value::Int(i)])
+                );
+            }
+            assert!(exec.next(&mut bufmgr).unwrap().is_none());
+        }
+    }
+    #[test]
+    fn delete_test() {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![UniqueIndex {
+                meta_page_id: PageId::INVALID_PAGE_ID,
+                skey: vec![2],
+            }],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        table.create(&mut bufmgr).unwrap();
+        table.insert(&mut bufmgr, &[b"a", b"Alice", b"x"]).unwrap();
+        table.insert(&mut bufmgr, &[b"b", b"Bob", b"y"]).unwrap();
+        table.insert(&mut bufmgr, &[b"c", b"Carol", b"z"]).unwrap();
+        let table_accessor = BTree::new(table.meta_page_id);
+        let index_accessor = BTree::new(table.unique_indices[0].meta_page_id);
 
-            let res1 = exec.next(&mut bufmgr);
-            let first = res1.unwrap().unwrap();
-            assert_eq!(first, vec![&[42], &[42]]);
+        let scan: SharedPlan<_, _> = Arc::new(SeqScan {
+            table_accessor: Arc::new(table_accessor),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 1,
+            stop_key: Bound::Unbounded,
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        });
+        let table = Arc::new(table);
+        let plan = Delete {
+            table: table.clone(),
+            inner_plan: Arc::new(Filter {
+                inner_plan: scan.clone(),
+                cond: Expr::compare(
+                    CmpOp::Ne,
+                    Expr::Column(1),
+                    Expr::Literal(Value::Bytes(b"Alice".to_vec())),
+                ),
+            }),
+        };
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        assert_eq!(
+            Row::from_values(vec![Value::Int(2)]),
+            exec.next(&mut bufmgr).unwrap().unwrap()
+        );
+        assert!(exec.next(&mut bufmgr).unwrap().is_none());
+        drop(exec);
 
-            let res2 = exec.next(&mut bufmgr);
-            let second = res2.unwrap().unwrap();
-            assert_eq!(second, vec![&[43], &[43]]);
+        let mut exec = scan.start(&mut bufmgr).unwrap();
+        assert_eq!(
+            vec![b"a".to_vec(), b"Alice".to_vec(), b"x".to_vec()],
+            exec.next(&mut bufmgr).unwrap().unwrap().into_tuple()
+        );
+        assert!(exec.next(&mut bufmgr).unwrap().is_none());
+        let plan = IndexOnlyScan {
+            index_accessor: Arc::new(index_accessor),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 1,
+            stop_key: Bound::Unbounded,
+            while_cond: Expr::TRUE,
+            schema: vec![],
+        };
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        assert_eq!(
+            vec![b"x".to_vec(), b"a".to_vec()],
+            exec.next(&mut bufmgr).unwrap().unwrap().into_tuple()
+        );
+        assert!(exec.next(&mut bufmgr).unwrap().is_none());
+        drop(exec);
 
-            let res3 = exec.next(&mut bufmgr);
-            let nodata = res3.unwrap();
-            assert!(nodata.is_none());
-        }
+        // 渡した行の主キー以外の列が古くても、書いてある行からインデックスの項目を消す
+        table.delete(&mut bufmgr, &[b"a", b"Bob", b"q"]).unwrap();
+        assert!(table.get(&mut bufmgr, &[b"a"]).unwrap().is_none());
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        assert!(exec.next(&mut bufmgr).unwrap().is_none());
+        assert!(table.delete(&mut bufmgr, &[b"a", b"Alice", b"x"]).is_err());
     }
     #[test]
-    fn index_scan_test() {
+    fn explain_test() {
         let mut bufmgr = Empty {};
-        {
-            let plan = IndexScan {
-                table_accessor: &Generate {},
-                index_accessor: &Generate {},
+        let scan: SharedPlan<_, _> = Arc::new(SeqScan {
+            table_accessor: Arc::new(Generate { is_table: true }),
+            search_mode: TupleSearchMode::Key(vec![vec![42u8]]),
+            num_key_elems: 1,
+            stop_key: Bound::Included(vec![vec![44u8]]),
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        });
+        let filter = Filter {
+            inner_plan: scan.clone(),
+            cond: Expr::compare(
+                CmpOp::Ne,
+                Expr::Column(0),
+                Expr::Literal(Value::Bytes(vec![43])),
+            ),
+        };
+        let rows = Rows(vec![bytes_row(vec![vec![1]])]);
+        let plan = Union {
+            inner_plans: vec![Arc::new(filter), Arc::new(rows)],
+            distinct: false,
+        };
+        let scan = r#"SeqScan (from Tuple("*" [2a]), to <= Tuple("," [2c]))"#;
+        let filter = "Filter (Compare(Ne, Column(0), Literal(Bytes([43]))))";
+        assert_eq!(
+            format!(
+                "Union All\n  -> {}\n       -> {}\n  -> Rows (1 rows)",
+                filter, scan
+            ),
+            plan.explain()
+        );
+        assert_eq!(
+            format!(
+                "Union All (loops=1 rows=3 fetches=0)\n  \
+                 -> {} (loops=1 rows=2 fetches=0)\n       \
+                 -> {} (loops=1 rows=3 fetches=0)\n  \
+                 -> Rows (1 rows) (loops=1 rows=1 fetches=0)",
+                filter, scan
+            ),
+            explain_analyze(&plan, &mut bufmgr).unwrap()
+        );
+
+        // RecordingManager なら取得したページを数える
+
+        let mut bufmgr =
+            RecordingManager::new(ClockSweepManager::new(MemoryManager::new(), 10).unwrap());
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        table.create(&mut bufmgr).unwrap();
+        table.insert(&mut bufmgr, &[b"a", b"Alice"]).unwrap();
+        table.insert(&mut bufmgr, &[b"b", b"Bob"]).unwrap();
+        let table_accessor = BTree::new(table.meta_page_id);
+        let plan = TopN {
+            inner_plan: Arc::new(SeqScan {
+                table_accessor: Arc::new(table_accessor),
                 search_mode: TupleSearchMode::Start,
-                while_cond: &|_| true,
-            };
+                num_key_elems: 1,
+                stop_key: Bound::Unbounded,
+                while_cond: Expr::TRUE,
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
+            }),
+            sort_keys: vec![],
+            limit: 1,
+        };
+        assert_eq!(
+            "TopN (keys: [], limit: 1) (loops=1 rows=1 fetches=2)\n  \
+             -> SeqScan (loops=1 rows=2 fetches=2)",
+            explain_analyze(&plan, &mut bufmgr).unwrap()
+        );
+    }
+    #[test]
+    fn next_batch_test() {
+        let mut bufmgr = Empty {};
+        let scan: SharedPlan<_, _> = Arc::new(SeqScan {
+            table_accessor: Arc::new(Generate { is_table: true }),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 1,
+            stop_key: Bound::Excluded(vec![vec![200u8]]),
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        });
+        // 先頭の列が 100 より小さい行の二列目
+        let filter: SharedPlan<_, _> = Arc::new(Filter {
+            inner_plan: scan.clone(),
+            cond: Expr::compare(
+                CmpOp::Lt,
+                Expr::Column(0),
+                Expr::Literal(Value::Bytes(vec![100])),
+            ),
+        });
+        let project: SharedPlan<_, _> = Arc::new(Project {
+            inner_plan: filter.clone(),
+            exprs: vec![Expr::Column(1)],
+        });
+        let union: SharedPlan<_, _> = Arc::new(Union {
+            inner_plans: vec![
+                project.clone(),
+                Arc::new(Rows(vec![bytes_row(vec![vec![1]])])),
+            ],
+            distinct: false,
+        });
+        let plans = [scan.clone(), filter, project, union];
+        for plan in plans.iter() {
+            let mut expected = vec![];
             let mut exec = plan.start(&mut bufmgr).unwrap();
+            while let Some(row) = exec.next(&mut bufmgr).unwrap() {
+                expected.push(row);
+            }
 
-            let res1 = exec.next(&mut bufmgr);
-            let first = res1.unwrap().unwrap();
-            assert_eq!(first, vec![&[0], &[0]]);
+            // バッチの大きさで割り切れないときも同じ行を返す (Union は既定の next_batch)
+            let mut batch = TupleBatch::new(7);
+            let mut rows = vec![];
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+            loop {
+                exec.next_batch(&mut bufmgr, &mut batch).unwrap();
+                if batch.is_empty() {
+                    break;
+                }
+                assert!(batch.len() <= batch.capacity());
+                rows.extend(batch.rows().iter().cloned());
+            }
+            assert!(!expected.is_empty());
+            assert_eq!(expected, rows);
+        }
 
-            let res2 = exec.next(&mut bufmgr);
-            let second = res2.unwrap().unwrap();
-            assert_eq!(second, vec![&[1], &[1]]);
+        // 途中の行で条件の評価に失敗したらエラーを返す
+        let plan = Filter {
+            inner_plan: scan.clone(),
+            cond: Expr::compare(CmpOp::Lt, Expr::Column(0), Expr::Literal(Value::Int(1))),
+        };
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        assert!(exec
+            .next_batch(&mut bufmgr, &mut TupleBatch::new(7))
+            .is_err());
+    }
+    #[test]
+    fn index_corruption_test() {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![UniqueIndex {
+                meta_page_id: PageId::INVALID_PAGE_ID,
+                skey: vec![1],
+            }],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        table.create(&mut bufmgr).unwrap();
+        table.insert(&mut bufmgr, &[b"a", b"x"]).unwrap();
+        table.insert(&mut bufmgr, &[b"b", b"y"]).unwrap();
+        table.insert(&mut bufmgr, &[b"c", b"z"]).unwrap();
+        let table_accessor = BTree::new(table.meta_page_id);
+        let index_accessor = BTree::new(table.unique_indices[0].meta_page_id);
+        // インデックスの項目を残したままテーブルの行を消す
+        let mut pkey = vec![];
+        tuple::encode([b"b"].iter(), &mut pkey);
+        table_accessor.delete(&mut bufmgr, &pkey).unwrap();
+
+        let plan = IndexScan {
+            table_accessor: Arc::new(table_accessor),
+            index_accessor: Arc::new(index_accessor),
+            search_mode: TupleSearchMode::Start,
+            skey: vec![1],
+            stop_key: Bound::Unbounded,
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        };
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        assert_eq!(
+            vec![b"a".to_vec(), b"x".to_vec()],
+            exec.next(&mut bufmgr).unwrap().unwrap().into_tuple()
+        );
+        let err = exec.next(&mut bufmgr).unwrap_err();
+        match err.downcast_ref::<super::Error>() {
+            Some(super::Error::IndexCorruption { skey, pkey }) => {
+                assert_eq!(&vec![b"y".to_vec()], skey);
+                assert_eq!(&vec![b"b".to_vec()], pkey);
+            }
+            None => panic!("unexpected error: {}", err),
         }
-        {
-            let plan = IndexScan {
-                table_accessor: &Generate {},
-                index_accessor: &Generate {},
-                search_mode: TupleSearchMode::Key(&[&[42u8]]),
-                while_cond: &|_| true,
+    }
+    #[test]
+    fn materialize_test() {
+        let mut bufmgr = Empty {};
+        let scan: SharedPlan<_, _> = Arc::new(SeqScan {
+            table_accessor: Arc::new(Generate { is_table: true }),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 1,
+            stop_key: Bound::Excluded(vec![vec![3u8]]),
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        });
+        // メモリに収まるとき、全て書き出すとき、途中から書き出すとき
+        for &work_mem in [usize::MAX, 0, 3].iter() {
+            let plan = Materialize {
+                inner_plan: scan.clone(),
+                work_mem,
             };
             let mut exec = plan.start(&mut bufmgr).unwrap();
+            for _ in 0..2 {
+                for c in 0..3u8 {
+                    assert_eq!(
+                        vec![vec![c], vec![c]],
+                        exec.next(&mut bufmgr).unwrap().unwrap().into_tuple()
+                    );
+                }
+                assert!(exec.next(&mut bufmgr).unwrap().is_none());
+                assert!(exec.rewind(&mut bufmgr).unwrap());
+            }
+        }
 
-            let res1 = exec.next(&mut bufmgr);
-            let first = res1.unwrap().unwrap();
-            assert_eq!(first, vec![&[42], &[42]]);
+        let outer: SharedPlan<_, _> = Arc::new(Rows(vec![
+            bytes_row(vec![vec![2]]),
+            bytes_row(vec![vec![9]]),
+            bytes_row(vec![vec![0]]),
+        ]));
+        let materialize: SharedPlan<_, _> = Arc::new(Materialize {
+            inner_plan: scan.clone(),
+            work_mem: 1024,
+        });
+        let cond = Expr::compare(CmpOp::Eq, Expr::Column(0), Expr::Column(1));
+        for inner_plan in [scan.clone(), materialize.clone()] {
+            let plan = NestedLoopJoin {
+                outer_plan: outer.clone(),
+                inner_plan,
+                cond: cond.clone(),
+                kind: JoinKind::Inner,
+            };
+            let mut exec = plan.start(&mut bufmgr).unwrap();
+            for c in [2u8, 0].iter() {
+                assert_eq!(
+                    vec![vec![*c], vec![*c], vec![*c]],
+                    exec.next(&mut bufmgr).unwrap().unwrap().into_tuple()
+                );
+            }
+            assert!(exec.next(&mut bufmgr).unwrap().is_none());
+        }
 
-            let res2 = exec.next(&mut bufmgr);
-            let second = res2.unwrap().unwrap();
-            assert_eq!(second, vec![&[43], &[43]]);
+        // Materialize を挟めば右のスキャンは一度しか実行しない
+        let plan = NestedLoopJoin {
+            outer_plan: outer.clone(),
+            inner_plan: materialize.clone(),
+            cond: cond.clone(),
+            kind: JoinKind::Inner,
+        };
+        let explained = explain_analyze(&plan, &mut bufmgr).unwrap();
+        let lines: Vec<_> = explained.lines().collect();
+        assert!(lines[2].ends_with("Materialize (work_mem: 1024) (loops=3 rows=9 fetches=0)"));
+        assert!(lines[3].ends_with("(loops=1 rows=3 fetches=0)"));
+        let plan = NestedLoopJoin {
+            outer_plan: outer.clone(),
+            inner_plan: scan.clone(),
+            cond: cond.clone(),
+            kind: JoinKind::Inner,
+        };
+        let explained = explain_analyze(&plan, &mut bufmgr).unwrap();
+        assert!(explained.ends_with("(loops=3 rows=9 fetches=0)"));
+    }
+    #[test]
+    fn composite_key_test() {
+        // バイト列をつなげると前方が一致するが、列ごとには一致しない
+        assert_eq!(
+            Ordering::Greater,
+            compare_prefix(&[&b"ab"[..], b"c"], &[&b"a"[..]])
+        );
+        assert_eq!(
+            Ordering::Equal,
+            compare_prefix(&[&b"a"[..], b"bc"], &[&b"a"[..]])
+        );
+        assert_eq!(
+            Ordering::Less,
+            compare_keys(
+                &[Value::Int(1), Value::Int(-5)],
+                &[Value::Int(1), Value::Int(3)]
+            )
+            .unwrap()
+        );
+        assert!(compare_keys(&[Value::Int(1)], &[Value::Bool(true)]).is_err());
+
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        // 主キーは先頭の二列
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 2,
+            unique_indices: vec![],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        table.create(&mut bufmgr).unwrap();
+        for record in [
+            [&b"a"[..], b"1", b"x"],
+            [b"a", b"2", b"y"],
+            [b"ab", b"1", b"z"],
+            [b"b", b"1", b"w"],
+        ]
+        .iter()
+        {
+            table.insert(&mut bufmgr, record).unwrap();
         }
+        let table_accessor = BTree::new(table.meta_page_id);
+        let mut outer = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        outer.create(&mut bufmgr).unwrap();
+        for record in [
+            [&b"0"[..], b"a", b"2"],
+            [b"1", b"ab", b"1"],
+            [b"2", b"c", b"1"],
+        ]
+        .iter()
         {
-            let plan = IndexScan {
-                table_accessor: &Generate {},
-                index_accessor: &Generate {},
-                search_mode: TupleSearchMode::Key(&[&[42u8]]),
-                while_cond: &|_| false,
+            outer.insert(&mut bufmgr, record).unwrap();
+        }
+        let outer_accessor = BTree::new(outer.meta_page_id);
+        let outer_scan: SharedPlan<_, _> = Arc::new(SeqScan {
+            table_accessor: Arc::new(outer_accessor),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 1,
+            stop_key: Bound::Unbounded,
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        });
+        // 主キー全体で引くときと、主キーの先頭の列だけで引くとき
+        for &(outer_keys, expected) in [
+            (&[1, 2][..], &[(&b"0"[..], &b"y"[..]), (b"1", b"z")][..]),
+            (
+                &[1][..],
+                &[(&b"0"[..], &b"x"[..]), (b"0", b"y"), (b"1", b"z")][..],
+            ),
+        ]
+        .iter()
+        {
+            let plan = IndexNestedLoopJoin {
+                outer_plan: outer_scan.clone(),
+                table_accessor: Arc::new(table_accessor.clone()),
+                index_accessor: None,
+                outer_keys: outer_keys.to_vec(),
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
             };
             let mut exec = plan.start(&mut bufmgr).unwrap();
+            let mut joined = vec![];
+            while let Some(row) = exec.next(&mut bufmgr).unwrap() {
+                let tuple = row.into_tuple();
+                joined.push((tuple[0].clone(), tuple[5].clone()));
+            }
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|(id, value)| (id.to_vec(), value.to_vec()))
+                .collect();
+            assert_eq!(expected, joined);
+        }
 
-            let res1 = exec.next(&mut bufmgr);
-            let nodata = res1.unwrap();
-            assert!(nodata.is_none());
+        // 二列のキーで結合する
+        let rows = |keys: &[(i64, i64)]| {
+            Rows(
+                keys.iter()
+                    .map(|&(a, b)| Row::from_values(vec![Value::Int(a), Value::Int(b)]))
+                    .collect(),
+            )
+        };
+        let plan = MergeJoin {
+            outer_plan: Arc::new(rows(&[(-1, 2), (1, 1), (1, 2), (2, 0)])),
+            inner_plan: Arc::new(rows(&[(-1, 1), (1, 2), (1, 2), (2, 0), (3, 0)])),
+            outer_keys: vec![0, 1],
+            inner_keys: vec![0, 1],
+            kind: JoinKind::Inner,
+        };
+        let mut bufmgr = Empty {};
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        let mut joined = vec![];
+        while let Some(row) = exec.next(&mut bufmgr).unwrap() {
+            joined.push((row.get(0).unwrap(), row.get(1).unwrap()));
         }
+        assert_eq!(
+            vec![
+                (Value::Int(1), Value::Int(2)),
+                (Value::Int(1), Value::Int(2)),
+                (Value::Int(2), Value::Int(0))
+            ],
+            joined
+        );
     }
+
     #[test]
-    fn index_only_scan_test() {
+    fn apply_test() {
+        let ints = |rows: &[&[i64]]| {
+            Rows(
+                rows.iter()
+                    .map(|row| Row::from_values(row.iter().map(|&v| Value::Int(v)).collect()))
+                    .collect(),
+            )
+        };
+        // (部署, 給料)
+        let employees: SharedPlan<_, _> = Arc::new(ints(&[&[1, 10], &[1, 20], &[2, 5]]));
+        let same_dept = Expr::compare(CmpOp::Eq, Expr::Column(0), Expr::Param(0));
+        let filter: SharedPlan<_, _> = Arc::new(Filter {
+            inner_plan: employees.clone(),
+            cond: same_dept,
+        });
+        let total: SharedPlan<_, _> = Arc::new(HashAggregate {
+            inner_plan: filter.clone(),
+            group_cols: vec![],
+            aggregates: vec![Aggregate::Sum(1)],
+            work_mem: 1024,
+        });
         let mut bufmgr = Empty {};
-        {
-            let plan = IndexOnlyScan {
-                index_accessor: &Generate {},
-                search_mode: TupleSearchMode::Start,
-                while_cond: &|_| true,
+        let run = |plan: &dyn PlanNode<Empty, Iter = Counter>, bufmgr: &mut Empty| {
+            let mut exec = plan.start(bufmgr)?;
+            let mut values = vec![];
+            while let Some(row) = exec.next(bufmgr)? {
+                values.push(row.get(1)?);
+            }
+            Ok::<_, anyhow::Error>(values)
+        };
+
+        // 部署ごとの給料の合計
+        let depts: SharedPlan<_, _> = Arc::new(ints(&[&[1], &[2], &[1]]));
+        let plan = Apply {
+            outer_plan: depts.clone(),
+            inner_plan: total.clone(),
+            params: vec![Expr::Column(0)],
+            kind: ApplyKind::Scalar,
+        };
+        assert_eq!(
+            vec![Value::Int(30), Value::Int(5), Value::Int(30)],
+            run(&plan, &mut bufmgr).unwrap()
+        );
+        // 同じ部署は二度実行しない
+        let analyzed = explain_analyze(&plan, &mut bufmgr).unwrap();
+        let lines: Vec<_> = analyzed.lines().collect();
+        assert!(lines[0].ends_with("(loops=1 rows=3 fetches=0)"));
+        assert!(lines[2].ends_with("(loops=2 rows=2 fetches=0)"));
+
+        // 社員のいる部署かどうか
+        let plan = Apply {
+            outer_plan: Arc::new(ints(&[&[3], &[2], &[3]])),
+            inner_plan: filter.clone(),
+            params: vec![Expr::Column(0)],
+            kind: ApplyKind::Exists,
+        };
+        assert_eq!(
+            vec![Value::Bool(false), Value::Bool(true), Value::Bool(false)],
+            run(&plan, &mut bufmgr).unwrap()
+        );
+
+        // 一行にならないものは値にできない
+        for &dept in [1, 3].iter() {
+            let plan = Apply {
+                outer_plan: Arc::new(ints(&[&[dept]])),
+                inner_plan: filter.clone(),
+                params: vec![Expr::Column(0)],
+                kind: ApplyKind::Scalar,
+            };
+            assert!(run(&plan, &mut bufmgr).is_err());
+        }
+    }
+
+    #[test]
+    fn outer_join_test() {
+        let n = Value::Null;
+        let i = Value::Int;
+        let rows = |rows: Vec<[Value; 2]>| {
+            Rows(
+                rows.into_iter()
+                    .map(|row| Row::from_values(row.to_vec()))
+                    .collect(),
+            )
+        };
+        // キーの順に並べておく (NULL が先)
+        let outer: SharedPlan<_, _> = Arc::new(rows(vec![
+            [n.clone(), i(10)],
+            [i(1), i(11)],
+            [i(2), i(12)],
+            [i(4), i(14)],
+        ]));
+        let inner: SharedPlan<_, _> = Arc::new(rows(vec![
+            [n.clone(), i(20)],
+            [i(2), i(22)],
+            [i(2), i(23)],
+            [i(3), i(24)],
+            [i(4), i(25)],
+        ]));
+        let matched = vec![
+            vec![i(2), i(12), i(2), i(22)],
+            vec![i(2), i(12), i(2), i(23)],
+            vec![i(4), i(14), i(4), i(25)],
+        ];
+        let outer_only = vec![
+            vec![n.clone(), i(10), n.clone(), n.clone()],
+            vec![i(1), i(11), n.clone(), n.clone()],
+        ];
+        let inner_only = vec![
+            vec![n.clone(), n.clone(), n.clone(), i(20)],
+            vec![n.clone(), n.clone(), i(3), i(24)],
+        ];
+        let cases = [
+            (JoinKind::Inner, vec![&matched]),
+            (
+                JoinKind::Left { inner_width: 2 },
+                vec![&matched, &outer_only],
+            ),
+            (
+                JoinKind::Right { outer_width: 2 },
+                vec![&matched, &inner_only],
+            ),
+            (
+                JoinKind::Full {
+                    outer_width: 2,
+                    inner_width: 2,
+                },
+                vec![&matched, &outer_only, &inner_only],
+            ),
+        ];
+        let cond = Expr::compare(CmpOp::Eq, Expr::Column(0), Expr::Column(2));
+        let mut bufmgr = Empty {};
+        for (kind, expected) in cases.iter() {
+            let mut expected: Vec<_> = expected
+                .iter()
+                .flat_map(|rows| rows.iter().cloned())
+                .collect();
+            expected.sort();
+            let nested_loop = NestedLoopJoin {
+                outer_plan: outer.clone(),
+                inner_plan: inner.clone(),
+                cond: cond.clone(),
+                kind: *kind,
+            };
+            let merge = MergeJoin {
+                outer_plan: outer.clone(),
+                inner_plan: inner.clone(),
+                outer_keys: vec![0],
+                inner_keys: vec![0],
+                kind: *kind,
             };
+            let plans: [&dyn PlanNode<_, Iter = _>; 2] = [&nested_loop, &merge];
+            for plan in plans.iter() {
+                let mut exec = plan.start(&mut bufmgr).unwrap();
+                let mut joined = vec![];
+                while let Some(row) = exec.next(&mut bufmgr).unwrap() {
+                    joined.push((0..4).map(|c| row.get(c).unwrap()).collect::<Vec<_>>());
+                }
+                joined.sort();
+                assert_eq!(expected, joined, "{}", plan.describe());
+            }
+        }
+    }
+
+    #[test]
+    fn cursor_test() {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        table.create(&mut bufmgr).unwrap();
+        for c in 0..100u8 {
+            table.insert(&mut bufmgr, &[&[c], &[c]]).unwrap();
+        }
+        let btree = BTree::new(table.meta_page_id);
+        let scan = |while_cond| SeqScan {
+            table_accessor: Arc::new(btree.clone()),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 1,
+            stop_key: Bound::Unbounded,
+            while_cond,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        };
+        let below_50 = Expr::compare(
+            CmpOp::Lt,
+            Expr::Column(0),
+            Expr::Literal(Value::Bytes(vec![50])),
+        );
+        let (all, first_half) = (scan(Expr::TRUE), scan(below_50));
+        let key = |row: &Row| row.tuple()[0][0];
+
+        // 二つのカーソルを交互に進める
+        let mut cursor = Cursor::open(&all, &mut bufmgr).unwrap();
+        let mut other = Cursor::open(&first_half, &mut bufmgr).unwrap();
+        let mut keys = vec![];
+        let mut other_keys = vec![];
+        while !cursor.is_done() || !other.is_done() {
+            keys.extend(cursor.fetch(&mut bufmgr, 7).unwrap().iter().map(key));
+            other_keys.extend(other.fetch(&mut bufmgr, 3).unwrap().iter().map(key));
+            // 合間に別のページを読んでもよい
+            assert!(btree.search(&mut bufmgr, SearchMode::Start).is_ok());
+        }
+        assert_eq!((0..100).collect::<Vec<u8>>(), keys);
+        assert_eq!((0..50).collect::<Vec<u8>>(), other_keys);
+        assert_eq!((100, 50), (cursor.fetched(), other.fetched()));
+        assert!(cursor.fetch(&mut bufmgr, 1).unwrap().is_empty());
+        assert!(cursor.fetch(&mut bufmgr, 0).unwrap().is_empty());
+    }
+    #[test]
+    fn owned_plan_test() {
+        // 関数から返したプランを別のスレッドで実行する
+        fn build(limit: u8) -> SharedPlan<Empty, Counter> {
+            let scan = Arc::new(SeqScan {
+                table_accessor: Arc::new(Generate { is_table: true }),
+                search_mode: TupleSearchMode::Start,
+                num_key_elems: 1,
+                stop_key: Bound::Excluded(vec![vec![limit]]),
+                while_cond: Expr::TRUE,
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
+            });
+            Arc::new(Project {
+                inner_plan: scan,
+                exprs: vec![Expr::Column(1)],
+            })
+        }
+        let plan = build(3);
+        let handle = std::thread::spawn(move || {
+            let mut bufmgr = Empty {};
             let mut exec = plan.start(&mut bufmgr).unwrap();
+            // 実行器はプランを手放しても使える
+            drop(plan);
+            let mut rows = vec![];
+            while let Some(row) = exec.next(&mut bufmgr).unwrap() {
+                rows.push(row.into_tuple());
+            }
+            rows
+        });
+        assert_eq!(
+            vec![vec![vec![0]], vec![vec![1]], vec![vec![2]]],
+            handle.join().unwrap()
+        );
+    }
+    #[test]
+    fn output_ordering_test() {
+        let scan: SharedPlan<_, _> = Arc::new(SeqScan {
+            table_accessor: Arc::new(Generate { is_table: true }),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 2,
+            stop_key: Bound::Unbounded,
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        });
+        let pkey = vec![SortKey::asc(0), SortKey::asc(1)];
+        assert_eq!(pkey, scan.output_ordering());
 
-            let res1 = exec.next(&mut bufmgr);
-            let first = res1.unwrap().unwrap();
-            assert_eq!(first, vec![&[0], &[0]]);
+        // 主キーの先頭の列だけの順にも並んでいる
+        assert!(satisfies_ordering(&pkey, &[SortKey::asc(0)]));
+        assert!(satisfies_ordering(&pkey, &[]));
+        assert!(!satisfies_ordering(&pkey, &[SortKey::asc(1)]));
+        let desc = SortKey {
+            column: 0,
+            descending: true,
+        };
+        assert!(!satisfies_ordering(&pkey, &[desc]));
 
-            let res2 = exec.next(&mut bufmgr);
-            let second = res2.unwrap().unwrap();
-            assert_eq!(second, vec![&[1], &[1]]);
+        // 並んでいる列を出す間だけ引き継ぐ
+        let project = Project {
+            inner_plan: scan.clone(),
+            exprs: vec![Expr::Column(1), Expr::Column(0)],
+        };
+        assert_eq!(
+            vec![SortKey::asc(1), SortKey::asc(0)],
+            project.output_ordering()
+        );
+        let project = Project {
+            inner_plan: scan.clone(),
+            exprs: vec![Expr::Column(0), Expr::Literal(Value::Int(1))],
+        };
+        assert_eq!(vec![SortKey::asc(0)], project.output_ordering());
+        let filter = Filter {
+            inner_plan: scan.clone(),
+            cond: Expr::TRUE,
+        };
+        assert_eq!(pkey, filter.output_ordering());
+
+        // 右の行を後から返す結合や、ハッシュで集める集約は順を保たない
+        let rows: SharedPlan<_, _> = Arc::new(Rows(vec![]));
+        let join = |kind| NestedLoopJoin {
+            outer_plan: scan.clone(),
+            inner_plan: rows.clone(),
+            cond: Expr::TRUE,
+            kind,
+        };
+        let left = join(JoinKind::Left { inner_width: 1 });
+        assert_eq!(pkey, left.output_ordering());
+        let right = join(JoinKind::Right { outer_width: 2 });
+        assert!(right.output_ordering().is_empty());
+        let aggregate = HashAggregate {
+            inner_plan: scan.clone(),
+            group_cols: vec![0],
+            aggregates: vec![Aggregate::Count],
+            work_mem: usize::MAX,
+        };
+        assert!(aggregate.output_ordering().is_empty());
+
+        // 並んでいなければ Sort を挟む
+        let plan = sorted(scan.clone(), vec![SortKey::asc(0)], usize::MAX);
+        assert!(Arc::ptr_eq(&scan, &plan));
+        let plan = sorted(scan.clone(), vec![desc], usize::MAX);
+        assert!(plan.describe().starts_with("Sort"));
+        assert_eq!(vec![desc], plan.output_ordering());
+        let mut bufmgr = Empty {};
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        let first = exec.next(&mut bufmgr).unwrap().unwrap().into_tuple();
+        assert_eq!(vec![vec![u8::MAX - 1], vec![u8::MAX - 1]], first);
+    }
+    #[test]
+    fn memory_context_test() {
+        let mut bufmgr = Empty {};
+        let scan: SharedPlan<_, _> = Arc::new(SeqScan {
+            table_accessor: Arc::new(Generate { is_table: true }),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 1,
+            stop_key: Bound::Unbounded,
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        });
+        let sort: SharedPlan<_, _> = Arc::new(Sort {
+            inner_plan: scan.clone(),
+            sort_keys: vec![SortKey {
+                column: 0,
+                descending: true,
+            }],
+            work_mem: usize::MAX,
+        });
+        let aggregate = HashAggregate {
+            inner_plan: scan.clone(),
+            group_cols: vec![0],
+            aggregates: vec![Aggregate::Count],
+            work_mem: usize::MAX,
+        };
+        let materialize = Materialize {
+            inner_plan: sort.clone(),
+            work_mem: usize::MAX,
+        };
+
+        // work_mem に収まっても、クエリ全体の上限を超える分は書き出す
+        let context = Arc::new(MemoryContext::new(20));
+        with_memory_context(context.clone(), || {
+            let mut exec = materialize.start(&mut bufmgr).unwrap();
+            for c in (0..u8::MAX).rev() {
+                let tuple = exec.next(&mut bufmgr).unwrap().unwrap().into_tuple();
+                assert_eq!(vec![vec![c], vec![c]], tuple);
+            }
+            assert!(exec.next(&mut bufmgr).unwrap().is_none());
+
+            let mut exec = aggregate.start(&mut bufmgr).unwrap();
+            let mut groups = 0;
+            while let Some(row) = exec.next(&mut bufmgr).unwrap() {
+                assert_eq!(Value::Int(1), row.get(1).unwrap());
+                groups += 1;
+            }
+            assert_eq!(u8::MAX, groups);
+        });
+        // 集約は表が空でも一つは入れるので、一グループ分だけ超えることがある
+        assert!(context.peak() <= context.limit() + 9);
+        // 実行器を手放せば全て返している
+        assert_eq!(0, context.used());
+
+        // 上限がなければメモリだけで済む
+        let context = Arc::new(MemoryContext::new(usize::MAX));
+        with_memory_context(context.clone(), || {
+            let exec = sort.start(&mut bufmgr).unwrap();
+            assert_eq!(2 * u8::MAX as usize, context.used());
+            drop(exec);
+        });
+        assert_eq!(0, context.used());
+    }
+    #[test]
+    fn sorted_aggregate_test() {
+        let mut bufmgr = Empty {};
+        // [グループ, 値] の行をグループの順に並べたもの (NULL のグループもまとめる)
+        let (n, i) = (Value::Null, Value::Int);
+        let rows = Rows(
+            [
+                [n.clone(), i(7)],
+                [n.clone(), i(8)],
+                [i(1), i(1)],
+                [i(1), n.clone()],
+                [i(1), i(2)],
+                [i(2), i(5)],
+                [i(3), n.clone()],
+            ]
+            .iter()
+            .map(|row| Row::from_values(row.to_vec()))
+            .collect(),
+        );
+        let plan = SortedAggregate {
+            inner_plan: Arc::new(rows),
+            group_cols: vec![0],
+            aggregates: vec![Aggregate::Count, Aggregate::Sum(1), Aggregate::Max(1)],
+        };
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        let mut groups = vec![];
+        while let Some(row) = exec.next(&mut bufmgr).unwrap() {
+            groups.push(
+                (0..row.len())
+                    .map(|c| row.get(c).unwrap())
+                    .collect::<Vec<_>>(),
+            );
+        }
+        assert_eq!(
+            vec![
+                vec![n.clone(), i(2), i(15), i(8)],
+                vec![i(1), i(3), i(3), i(2)],
+                vec![i(2), i(1), i(5), i(5)],
+                vec![i(3), i(1), n.clone(), n.clone()],
+            ],
+            groups
+        );
+        assert!(exec.next(&mut bufmgr).unwrap().is_none());
+
+        // 入力が空なら何も返さない
+        let plan = SortedAggregate {
+            inner_plan: Arc::new(Rows(vec![])),
+            group_cols: vec![],
+            aggregates: vec![Aggregate::Count],
+        };
+        assert!(plan
+            .start(&mut bufmgr)
+            .unwrap()
+            .next(&mut bufmgr)
+            .unwrap()
+            .is_none());
+
+        // 並んだ入力ならグループの列の順に返す
+        let scan: SharedPlan<_, _> = Arc::new(IndexOnlyScan {
+            index_accessor: Arc::new(Generate { is_table: false }),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 1,
+            stop_key: Bound::Excluded(vec![vec![3u8]]),
+            while_cond: Expr::TRUE,
+            schema: vec![],
+        });
+        let plan = SortedAggregate {
+            inner_plan: scan,
+            group_cols: vec![1, 0],
+            aggregates: vec![Aggregate::Count],
+        };
+        assert_eq!(vec![SortKey::asc(1)], plan.output_ordering());
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        for c in 0..3u8 {
+            let tuple = exec.next(&mut bufmgr).unwrap().unwrap().into_tuple();
+            assert_eq!(vec![vec![c], vec![c]], tuple[..2]);
         }
+        assert!(exec.next(&mut bufmgr).unwrap().is_none());
+    }
+    #[test]
+    fn analyze_test() {
+        let mut bufmgr = Empty {};
+        let scan: SharedPlan<_, _> = Arc::new(SeqScan {
+            table_accessor: Arc::new(Generate { is_table: true }),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 1,
+            stop_key: Bound::Excluded(vec![vec![10u8]]),
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        });
+        let plan = Filter {
+            inner_plan: scan.clone(),
+            cond: Expr::compare(
+                CmpOp::Lt,
+                Expr::Column(0),
+                Expr::Literal(Value::Bytes(vec![4])),
+            ),
+        };
+        let stats = analyze(&plan, &mut bufmgr).unwrap();
+        // 終わりを知るためにもう一回ずつ呼ぶ
+        let (filter, scan_stats) = (stats.get(&plan), stats.get(scan.as_ref()));
+        assert_eq!((1, 4, 5), (filter.loops, filter.rows, filter.calls));
+        assert_eq!(
+            (1, 10, 11),
+            (scan_stats.loops, scan_stats.rows, scan_stats.calls)
+        );
+        // 子の時間も含む
+        assert!(filter.elapsed >= scan_stats.elapsed);
+
+        let explained = stats.explain(&plan);
+        let lines: Vec<_> = explained.lines().collect();
+        assert!(lines[0].contains("(loops=1 rows=4 fetches=0 calls=5 time="));
+        assert!(lines[1].contains("(loops=1 rows=10 fetches=0 calls=11 time="));
+        assert!(lines.iter().all(|line| line.ends_with("ms)")));
+
+        // 計測していないノードは 0
+        assert_eq!(NodeStats::default(), stats.get(&Rows(vec![])));
+    }
+    #[test]
+    fn index_merge_scan_test() {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![
+                UniqueIndex {
+                    meta_page_id: PageId::INVALID_PAGE_ID,
+                    skey: vec![1],
+                },
+                UniqueIndex {
+                    meta_page_id: PageId::INVALID_PAGE_ID,
+                    skey: vec![2],
+                },
+            ],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        table.create(&mut bufmgr).unwrap();
+        for record in [
+            [&b"a"[..], b"p", b"x"],
+            [b"b", b"q", b"y"],
+            [b"c", b"r", b"z"],
+            [b"d", b"s", b"w"],
+        ]
+        .iter()
         {
-            let plan = IndexOnlyScan {
-                index_accessor: &Generate {},
-                search_mode: TupleSearchMode::Key(&[&[42u8]]),
-                while_cond: &|_| true,
+            table.insert(&mut bufmgr, record).unwrap();
+        }
+        let table_accessor: SharedAccessMethod<_, _> = Arc::new(BTree::new(table.meta_page_id));
+        // 一列目 <= r の行 (a, b, c) と二列目 >= y の行 (b, c)
+        let sources = || {
+            vec![
+                KeySource {
+                    plan: Arc::new(IndexOnlyScan {
+                        index_accessor: Arc::new(BTree::new(table.unique_indices[0].meta_page_id)),
+                        search_mode: TupleSearchMode::Start,
+                        num_key_elems: 1,
+                        stop_key: Bound::Included(vec![b"r".to_vec()]),
+                        while_cond: Expr::TRUE,
+                        schema: vec![],
+                    }) as SharedPlan<_, _>,
+                    pkey_cols: vec![1],
+                },
+                KeySource {
+                    plan: Arc::new(IndexOnlyScan {
+                        index_accessor: Arc::new(BTree::new(table.unique_indices[1].meta_page_id)),
+                        search_mode: TupleSearchMode::Key(vec![b"y".to_vec()]),
+                        num_key_elems: 1,
+                        stop_key: Bound::Unbounded,
+                        while_cond: Expr::TRUE,
+                        schema: vec![],
+                    }),
+                    pkey_cols: vec![1],
+                },
+            ]
+        };
+        let pkeys = |kind, bufmgr: &mut ClockSweepManager<MemoryManager>| {
+            let plan = IndexMergeScan {
+                table_accessor: table_accessor.clone(),
+                sources: sources(),
+                kind,
+                visibility: Arc::new(AllVisible),
+                schema: vec![],
             };
-            let mut exec = plan.start(&mut bufmgr).unwrap();
+            assert_eq!(vec![SortKey::asc(0)], plan.output_ordering());
+            let mut exec = plan.start(bufmgr).unwrap();
+            let mut pkeys = vec![];
+            while let Some(row) = exec.next(bufmgr).unwrap() {
+                assert_eq!(3, row.tuple().len());
+                pkeys.push(row.into_tuple().swap_remove(0));
+            }
+            pkeys
+        };
+        assert_eq!(
+            vec![b"b".to_vec(), b"c".to_vec()],
+            pkeys(MergeKind::Intersect, &mut bufmgr)
+        );
+        assert_eq!(
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()],
+            pkeys(MergeKind::Union, &mut bufmgr)
+        );
+    }
+    #[test]
+    fn skip_scan_test() {
+        use crate::rdbms::btree;
+        use std::sync::atomic::{AtomicUsize, Ordering};
 
-            let res1 = exec.next(&mut bufmgr);
-            let first = res1.unwrap().unwrap();
-            assert_eq!(first, vec![&[42], &[42]]);
+        // 探した回数を数える
+        struct CountSearch {
+            inner: BTree,
+            count: Arc<AtomicUsize>,
+        }
+        impl<T: BufferPoolManager> AccessMethod<T> for CountSearch {
+            type Iterable = btree::Iter;
+            fn search(
+                &self,
+                bufmgr: &mut T,
+                search_option: SearchMode,
+            ) -> Result<Self::Iterable, method::Error> {
+                self.count.fetch_add(1, Ordering::Relaxed);
+                self.inner.search(bufmgr, search_option)
+            }
+            fn insert(&self, _: &mut T, _: &[u8], _: &[u8]) -> Result<(), method::Error> {
+                panic!("Not implement!")
+            }
+            fn delete(&self, _: &mut T, _: &[u8]) -> Result<(), method::Error> {
+                panic!("Not implement!")
+            }
+        }
 
-            let res2 = exec.next(&mut bufmgr);
-            let second = res2.unwrap().unwrap();
-            assert_eq!(second, vec![&[43], &[43]]);
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![UniqueIndex {
+                meta_page_id: PageId::INVALID_PAGE_ID,
+                skey: vec![1, 2],
+            }],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        table.create(&mut bufmgr).unwrap();
+        for a in ["x", "y", "z"].iter() {
+            for b in 1..=6 {
+                let (a, b) = (a.as_bytes(), b.to_string().into_bytes());
+                let pkey = [a, &b[..]].concat();
+                table.insert(&mut bufmgr, &[&pkey[..], a, &b]).unwrap();
+            }
         }
-        {
-            let plan = IndexOnlyScan {
-                index_accessor: &Generate {},
-                search_mode: TupleSearchMode::Key(&[&[42u8]]),
-                while_cond: &|_| false,
+        let count = Arc::new(AtomicUsize::new(0));
+        // 二列目だけの条件 2 < b <= 4
+        let plan = SkipScan {
+            index_accessor: Arc::new(CountSearch {
+                inner: BTree::new(table.unique_indices[0].meta_page_id),
+                count: count.clone(),
+            }),
+            num_key_elems: 2,
+            prefix_len: 1,
+            start_key: Bound::Excluded(vec![b"2".to_vec()]),
+            stop_key: Bound::Included(vec![b"4".to_vec()]),
+            schema: vec![],
+        };
+        assert_eq!(
+            "SkipScan (prefix_len: 1, from > Tuple(\"2\" [32]), to <= Tuple(\"4\" [34]))",
+            PlanNode::<ClockSweepManager<MemoryManager>>::describe(&plan)
+        );
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        let mut rows = vec![];
+        while let Some(row) = exec.next(&mut bufmgr).unwrap() {
+            rows.push(row.into_tuple());
+        }
+        let expected: Vec<Tuple> = ["x3", "x4", "y3", "y4", "z3", "z4"]
+            .iter()
+            .map(|pkey| {
+                let (a, b) = pkey.split_at(1);
+                vec![a.into(), b.into(), pkey.as_bytes().to_vec()]
+            })
+            .collect();
+        assert_eq!(expected, rows);
+        // 最初と、先頭の列の値ごとに範囲の始めと次の値へ探し直す
+        assert_eq!(7, count.load(Ordering::Relaxed));
+    }
+    #[test]
+    fn param_plan_test() {
+        let mut bufmgr = Empty {};
+        // 一列目が Param(0) から Param(1) 未満の行
+        let scan: SharedPlan<_, _> = Arc::new(SeqScan {
+            table_accessor: Arc::new(Generate { is_table: true }),
+            search_mode: TupleSearchMode::Exprs(vec![Expr::Param(0)]),
+            num_key_elems: 1,
+            stop_key: Bound::Unbounded,
+            while_cond: Expr::compare(CmpOp::Lt, Expr::Column(0), Expr::Param(1)),
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        });
+        assert_eq!(
+            "SeqScan (from [Param(0)], while Compare(Lt, Column(0), Param(1)))",
+            scan.describe()
+        );
+        // 右は左の行ごとに作り直すが、同じ値で束縛し直す
+        let plan = NestedLoopJoin {
+            outer_plan: scan.clone(),
+            inner_plan: scan.clone(),
+            cond: Expr::compare(CmpOp::Eq, Expr::Column(1), Expr::Column(2)),
+            kind: JoinKind::Inner,
+        };
+        let run = |bufmgr: &mut Empty, from: u8, to: u8| {
+            let params = vec![Value::Bytes(vec![from]), Value::Bytes(vec![to])];
+            let mut exec = start_with_params(&plan, bufmgr, params).unwrap();
+            let mut rows = vec![];
+            while let Some(row) = exec.next(bufmgr).unwrap() {
+                rows.push(row.into_tuple()[0][0]);
+            }
+            rows
+        };
+        assert_eq!(vec![3, 4], run(&mut bufmgr, 3, 5));
+        assert_eq!(vec![10, 11, 12], run(&mut bufmgr, 10, 13));
+        // 束縛しないと実行器を作るときに失敗する
+        assert!(plan.start(&mut bufmgr).is_err());
+    }
+    #[test]
+    fn values_test() {
+        let mut bufmgr = Empty {};
+        let rows = |values: &[i64]| -> Vec<Row> {
+            values
+                .iter()
+                .map(|&n| Row::from_values(vec![Value::Int(n)]))
+                .collect()
+        };
+        let collect_rows = |exec: &mut BoxExecutor<Empty>, bufmgr: &mut Empty| {
+            let mut rows = vec![];
+            while let Some(row) = exec.next(bufmgr).unwrap() {
+                rows.push(row);
+            }
+            rows
+        };
+        let values: SharedPlan<Empty, Counter> = Arc::new(Values::new(rows(&[1, 2, 3])));
+        assert_eq!("Values (rows: 3)", values.describe());
+        let mut exec = values.start(&mut bufmgr).unwrap();
+        assert_eq!(rows(&[1, 2, 3]), collect_rows(&mut exec, &mut bufmgr));
+        assert!(exec.rewind(&mut bufmgr).unwrap());
+        assert_eq!(rows(&[1, 2, 3]), collect_rows(&mut exec, &mut bufmgr));
+
+        // 右にすれば左の行ごとに読み直す
+        let plan = NestedLoopJoin {
+            outer_plan: Arc::new(Values::new(rows(&[2, 3, 4]))),
+            inner_plan: values,
+            cond: Expr::compare(CmpOp::Lt, Expr::Column(0), Expr::Column(1)),
+            kind: JoinKind::Inner,
+        };
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        let pairs: Vec<_> = collect_rows(&mut exec, &mut bufmgr)
+            .iter()
+            .map(|row| (row.get(0).unwrap(), row.get(1).unwrap()))
+            .collect();
+        assert_eq!(vec![(Value::Int(2), Value::Int(3))], pairs);
+    }
+    #[test]
+    fn limit_test() {
+        let mut bufmgr = Empty {};
+        let rows: Vec<Row> = (0..5)
+            .map(|n| Row::from_values(vec![Value::Int(n)]))
+            .collect();
+        let values: SharedPlan<Empty, Counter> = Arc::new(Values::new(rows));
+        let run = |limit, offset, bufmgr: &mut Empty| {
+            let plan = Limit {
+                inner_plan: values.clone(),
+                limit,
+                offset,
             };
-            let mut exec = plan.start(&mut bufmgr).unwrap();
+            let mut exec = plan.start(bufmgr).unwrap();
+            let mut out = vec![];
+            while let Some(row) = exec.next(bufmgr).unwrap() {
+                out.push(row.get(0).unwrap());
+            }
+            out
+        };
+        let ints = |ns: &[i64]| ns.iter().map(|&n| Value::Int(n)).collect::<Vec<_>>();
+        assert_eq!(ints(&[1, 2]), run(Some(2), 1, &mut bufmgr));
+        assert_eq!(ints(&[3, 4]), run(None, 3, &mut bufmgr));
+        assert_eq!(ints(&[]), run(Some(3), 7, &mut bufmgr));
+        assert_eq!(ints(&[]), run(Some(0), 0, &mut bufmgr));
+    }
 
-            let res1 = exec.next(&mut bufmgr);
-            let nodata = res1.unwrap();
-            assert!(nodata.is_none());
+    #[test]
+    fn non_unique_index_test() {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![],
+            non_unique_indices: vec![NonUniqueIndex {
+                meta_page_id: PageId::INVALID_PAGE_ID,
+                skey: vec![1],
+            }],
+            schema: None,
+        };
+        table.create(&mut bufmgr).unwrap();
+        for record in [
+            [&b"a"[..], b"Bob", b"x"],
+            [b"b", b"Alice", b"y"],
+            [b"c", b"Bob", b"z"],
+            [b"d", b"Carol", b"w"],
+        ] {
+            table.insert(&mut bufmgr, &record).unwrap();
+        }
+        let table_accessor = Arc::new(BTree::new(table.meta_page_id));
+        let index_accessor = Arc::new(BTree::new(table.non_unique_indices[0].meta_page_id));
+        let bob = vec![b"Bob".to_vec()];
+        let index_scan = |table_accessor: &Arc<BTree>, index_accessor: &Arc<BTree>| IndexScan {
+            table_accessor: table_accessor.clone(),
+            index_accessor: index_accessor.clone(),
+            search_mode: TupleSearchMode::Key(bob.clone()),
+            skey: vec![1],
+            stop_key: Bound::Included(bob.clone()),
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(AllVisible),
+            schema: vec![],
+        };
+
+        // 同じ値の行を全て主キーの順に返す
+        let plan = index_scan(&table_accessor, &index_accessor);
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        for pkey in [b"a", b"c"] {
+            let row = exec.next(&mut bufmgr).unwrap().unwrap().into_tuple();
+            assert_eq!(vec![pkey.to_vec(), bob[0].clone()], row[..2].to_vec());
         }
+        assert!(exec.next(&mut bufmgr).unwrap().is_none());
+        drop(exec);
+
+        // キーに主キーが入っていても、副キーと主キーを一度ずつ並べる
+        let plan = IndexOnlyScan {
+            index_accessor: index_accessor.clone(),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 1,
+            stop_key: Bound::Unbounded,
+            while_cond: Expr::TRUE,
+            schema: vec![],
+        };
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        assert_eq!(
+            vec![b"Alice".to_vec(), b"b".to_vec()],
+            exec.next(&mut bufmgr).unwrap().unwrap().into_tuple()
+        );
+        drop(exec);
+
+        // 消した行の項目だけがなくなる
+        table.delete(&mut bufmgr, &[b"a", b"Bob", b"x"]).unwrap();
+        let plan = index_scan(&table_accessor, &index_accessor);
+        let mut exec = plan.start(&mut bufmgr).unwrap();
+        assert_eq!(
+            b"c".to_vec(),
+            exec.next(&mut bufmgr).unwrap().unwrap().into_tuple()[0]
+        );
+        assert!(exec.next(&mut bufmgr).unwrap().is_none());
     }
 }
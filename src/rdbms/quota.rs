@@ -0,0 +1,139 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::buffer::{
+    entity::{Buffer, Checkpoint},
+    manager::*,
+};
+use crate::storage::entity::PageId;
+
+// 一つの操作 (executor など) が使うフレーム数を limit までに制限する
+// 上限を超えると Error::QuotaExceeded を返すので、呼び出し側はスピルに切り替える
+pub struct QuotaManager<'a, T: BufferPoolManager> {
+    bufmgr: &'a mut T,
+    limit: usize,
+    pages: HashSet<PageId>,
+}
+
+impl<'a, T: BufferPoolManager> QuotaManager<'a, T> {
+    pub fn new(bufmgr: &'a mut T, limit: usize) -> Self {
+        Self {
+            bufmgr,
+            limit,
+            pages: HashSet::new(),
+        }
+    }
+
+    // この操作が使っているフレーム数
+    pub fn num_used_frames(&self) -> usize {
+        self.pages.len()
+    }
+
+    // 使い終わったページをクォータから外す
+    pub fn release(&mut self, page_id: PageId) {
+        self.pages.remove(&page_id);
+    }
+
+    // 新たにクォータに加えたなら true を返す
+    fn reserve(&mut self, page_id: PageId) -> Result<bool, Error> {
+        if !self.pages.contains(&page_id) && self.pages.len() >= self.limit {
+            return Err(Error::QuotaExceeded(self.limit));
+        }
+        Ok(self.pages.insert(page_id))
+    }
+}
+
+impl<'a, T: BufferPoolManager> BufferPoolManager for QuotaManager<'a, T> {
+    fn fetch_page(&mut self, page_id: PageId) -> Result<Rc<Buffer>, Error> {
+        let reserved = self.reserve(page_id)?;
+        let result = self.bufmgr.fetch_page(page_id);
+        // 読めなかったページの分は数えない
+        if result.is_err() && reserved {
+            self.pages.remove(&page_id);
+        }
+        result
+    }
+
+    fn create_page(&mut self) -> Result<Rc<Buffer>, Error> {
+        if self.pages.len() >= self.limit {
+            return Err(Error::QuotaExceeded(self.limit));
+        }
+        let buffer = self.bufmgr.create_page()?;
+        self.pages.insert(buffer.page_id);
+        Ok(buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.bufmgr.flush()
+    }
+
+    fn checkpoint(&mut self) -> Result<Checkpoint, Error> {
+        self.bufmgr.checkpoint()
+    }
+
+    fn discard_page(&mut self, page_id: PageId) -> Result<(), Error> {
+        self.bufmgr.discard_page(page_id)?;
+        self.pages.remove(&page_id);
+        Ok(())
+    }
+
+    fn free_page(&mut self, page_id: PageId) -> Result<(), Error> {
+        self.bufmgr.free_page(page_id)?;
+        self.pages.remove(&page_id);
+        Ok(())
+    }
+
+    fn fetch_count(&self) -> u64 {
+        self.bufmgr.fetch_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::entity::PAGE_SIZE;
+    use crate::rdbms::{clocksweep::ClockSweepManager, disk::DiskManager};
+    use tempfile::tempfile;
+
+    #[test]
+    fn quota_test() {
+        let disk = DiskManager::new(tempfile().unwrap(), PAGE_SIZE).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 10).unwrap();
+        let page_ids: Vec<_> = (0..3)
+            .map(|_| bufmgr.create_page().unwrap().page_id)
+            .collect();
+
+        let mut quota = QuotaManager::new(&mut bufmgr, 2);
+        assert!(quota.fetch_page(page_ids[0]).is_ok());
+        assert!(quota.fetch_page(page_ids[1]).is_ok());
+        // 同じページは何度取得してもよい
+        assert!(quota.fetch_page(page_ids[0]).is_ok());
+        assert_eq!(2, quota.num_used_frames());
+
+        let res_err = quota.fetch_page(page_ids[2]);
+        assert!(matches!(res_err, Err(Error::QuotaExceeded(2))));
+        assert!(matches!(quota.create_page(), Err(Error::QuotaExceeded(2))));
+
+        quota.release(page_ids[0]);
+        assert!(quota.fetch_page(page_ids[2]).is_ok());
+        assert_eq!(2, quota.num_used_frames());
+    }
+
+    #[test]
+    fn fetch_error_test() {
+        let disk = DiskManager::new(tempfile().unwrap(), PAGE_SIZE).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 1).unwrap();
+        let page_id = bufmgr.create_page().unwrap().page_id;
+        let other = bufmgr.create_page().unwrap();
+
+        // フレームが空かず読めなければクォータを使わない
+        let mut quota = QuotaManager::new(&mut bufmgr, 1);
+        assert!(matches!(
+            quota.fetch_page(page_id),
+            Err(Error::NoFreeBuffer)
+        ));
+        assert_eq!(0, quota.num_used_frames());
+        drop(other);
+        assert!(quota.fetch_page(page_id).is_ok());
+    }
+}
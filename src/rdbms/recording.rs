@@ -0,0 +1,122 @@
+use std::rc::Rc;
+
+use crate::buffer::{
+    entity::{Buffer, Checkpoint},
+    manager::*,
+};
+use crate::storage::entity::PageId;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call {
+    Fetch(PageId),
+    Create(PageId),
+    Flush,
+    Checkpoint,
+    Discard(PageId),
+    Free(PageId),
+}
+
+// 成功した buffermanager への呼び出しをページ ID 付きで記録する
+// テストでのアクセスパターンの確認やクエリプランの I/O トレースに使う
+pub struct RecordingManager<T: BufferPoolManager> {
+    bufmgr: T,
+    history: Vec<Call>,
+    // clear_history しても戻さない fetch_page の回数
+    fetches: u64,
+}
+
+impl<T: BufferPoolManager> RecordingManager<T> {
+    pub fn new(bufmgr: T) -> Self {
+        Self {
+            bufmgr,
+            history: vec![],
+            fetches: 0,
+        }
+    }
+
+    pub fn history(&self) -> &[Call] {
+        &self.history
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    pub fn into_inner(self) -> T {
+        self.bufmgr
+    }
+}
+
+impl<T: BufferPoolManager> BufferPoolManager for RecordingManager<T> {
+    fn fetch_page(&mut self, page_id: PageId) -> Result<Rc<Buffer>, Error> {
+        let buffer = self.bufmgr.fetch_page(page_id)?;
+        self.history.push(Call::Fetch(page_id));
+        self.fetches += 1;
+        Ok(buffer)
+    }
+
+    fn create_page(&mut self) -> Result<Rc<Buffer>, Error> {
+        let buffer = self.bufmgr.create_page()?;
+        self.history.push(Call::Create(buffer.page_id));
+        Ok(buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.bufmgr.flush()?;
+        self.history.push(Call::Flush);
+        Ok(())
+    }
+
+    fn checkpoint(&mut self) -> Result<Checkpoint, Error> {
+        let checkpoint = self.bufmgr.checkpoint()?;
+        self.history.push(Call::Checkpoint);
+        Ok(checkpoint)
+    }
+
+    fn discard_page(&mut self, page_id: PageId) -> Result<(), Error> {
+        self.bufmgr.discard_page(page_id)?;
+        self.history.push(Call::Discard(page_id));
+        Ok(())
+    }
+
+    fn free_page(&mut self, page_id: PageId) -> Result<(), Error> {
+        self.bufmgr.free_page(page_id)?;
+        self.history.push(Call::Free(page_id));
+        Ok(())
+    }
+
+    fn fetch_count(&self) -> u64 {
+        self.fetches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdbms::temp::TempBufferManager;
+
+    #[test]
+    fn recording_test() {
+        let mut bufmgr = RecordingManager::new(TempBufferManager::new(1).unwrap());
+        let page_id = bufmgr.create_page().unwrap().page_id;
+        assert!(bufmgr.fetch_page(page_id).is_ok());
+        // pin されているページがあるので失敗し、記録されない
+        {
+            let _pinned = bufmgr.fetch_page(page_id).unwrap();
+            assert!(bufmgr.create_page().is_err());
+        }
+        assert!(bufmgr.flush().is_ok());
+        assert_eq!(
+            &[
+                Call::Create(page_id),
+                Call::Fetch(page_id),
+                Call::Fetch(page_id),
+                Call::Flush,
+            ],
+            bufmgr.history()
+        );
+        bufmgr.clear_history();
+        assert!(bufmgr.history().is_empty());
+        assert_eq!(2, bufmgr.fetch_count());
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+use crate::buffer::entity::PAGE_SIZE;
+use crate::storage::{
+    checksum,
+    entity::PageId,
+    manager::{self, StorageManager},
+};
+
+// キーとバイト列を読み書きするオブジェクトストア
+// S3 互換のストアのクライアントはこれを実装すれば RemoteStorage の下で使える
+pub trait ObjectStore {
+    // オブジェクトを読み出す (存在しなければ None)
+    fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>>;
+    // オブジェクトを丸ごと書き込む
+    fn put(&mut self, key: &str, data: &[u8]) -> Result<()>;
+}
+
+// ディレクトリの一つのファイルを一つのオブジェクトとする ObjectStore
+pub struct LocalObjectStore {
+    root: PathBuf,
+}
+
+impl LocalObjectStore {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+}
+
+impl ObjectStore for LocalObjectStore {
+    fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.root.join(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn put(&mut self, key: &str, data: &[u8]) -> Result<()> {
+        // 書きかけのオブジェクトが見えないように、別名で書いてから置き換える
+        let tmp_path = self.root.join(format!("{}.tmp", key));
+        fs::write(&tmp_path, data)?;
+        fs::rename(tmp_path, self.root.join(key))
+    }
+}
+
+// 採番の状態を置くオブジェクトのキー
+const META_KEY: &str = "meta";
+// 一つのエクステントに入れるページ数の既定値
+pub const DEFAULT_PAGES_PER_EXTENT: usize = 16;
+// キャッシュしておくエクステント数の既定値
+pub const DEFAULT_CACHE_CAPACITY: usize = 64;
+
+struct Extent {
+    data: Vec<u8>,
+    dirty: bool,
+    last_used: u64,
+}
+
+// 連続するページをまとめたエクステントを一つのオブジェクトとして ObjectStore に置く storagemanager
+// 読み書きはローカルのキャッシュに対して行い、sync やキャッシュから追い出すときにまとめてアップロードする
+// ページIDの 0 は DiskManager と同じくヘッダ用に空けておく
+pub struct RemoteStorage<S: ObjectStore> {
+    store: S,
+    pages_per_extent: usize,
+    cache_capacity: usize,
+    cache: HashMap<u64, Extent>,
+    // LRU で追い出すための論理時刻
+    clock: u64,
+    // 採番するページを決めるカウンタ
+    next_page_id: u64,
+}
+
+impl<S: ObjectStore> RemoteStorage<S> {
+    pub fn new(store: S) -> Result<Self> {
+        Self::with_layout(store, DEFAULT_PAGES_PER_EXTENT, DEFAULT_CACHE_CAPACITY)
+    }
+
+    pub fn with_layout(
+        mut store: S,
+        pages_per_extent: usize,
+        cache_capacity: usize,
+    ) -> Result<Self> {
+        assert!(pages_per_extent > 0 && cache_capacity > 0);
+        let next_page_id = match store.get(META_KEY)? {
+            Some(meta) => {
+                let (next_page_id, stored_pages_per_extent) = decode_meta(&meta)?;
+                if stored_pages_per_extent != pages_per_extent as u64 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "pages per extent mismatch: stored {}, requested {}",
+                            stored_pages_per_extent, pages_per_extent
+                        ),
+                    ));
+                }
+                next_page_id
+            }
+            None => 1,
+        };
+        Ok(Self {
+            store,
+            pages_per_extent,
+            cache_capacity,
+            cache: HashMap::new(),
+            clock: 0,
+            next_page_id,
+        })
+    }
+
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+
+    fn extent_key(extent_no: u64) -> String {
+        format!("extent-{:016x}", extent_no)
+    }
+
+    // ページを含むエクステントと、エクステント内のバイト位置
+    fn locate(&self, page_id: PageId) -> (u64, std::ops::Range<usize>) {
+        let extent_no = page_id.to_u64() / self.pages_per_extent as u64;
+        let offset = (page_id.to_u64() % self.pages_per_extent as u64) as usize * PAGE_SIZE;
+        (extent_no, offset..offset + PAGE_SIZE)
+    }
+
+    // エクステントをキャッシュに載せる
+    fn extent(&mut self, extent_no: u64) -> Result<&mut Extent> {
+        self.clock += 1;
+        if !self.cache.contains_key(&extent_no) {
+            if self.cache.len() >= self.cache_capacity {
+                self.evict()?;
+            }
+            let extent_size = self.pages_per_extent * PAGE_SIZE;
+            let mut data = self
+                .store
+                .get(&Self::extent_key(extent_no))?
+                .unwrap_or_default();
+            if data.len() > extent_size {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("extent {} is too large", extent_no),
+                ));
+            }
+            data.resize(extent_size, 0);
+            self.cache.insert(
+                extent_no,
+                Extent {
+                    data,
+                    dirty: false,
+                    last_used: 0,
+                },
+            );
+        }
+        let extent = self.cache.get_mut(&extent_no).unwrap();
+        extent.last_used = self.clock;
+        Ok(extent)
+    }
+
+    // 最も長く使われていないエクステントを追い出す
+    fn evict(&mut self) -> Result<()> {
+        let victim = match self.cache.iter().min_by_key(|(_, extent)| extent.last_used) {
+            Some((&extent_no, _)) => extent_no,
+            None => return Ok(()),
+        };
+        let extent = self.cache.remove(&victim).unwrap();
+        if extent.dirty {
+            self.store.put(&Self::extent_key(victim), &extent.data)?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_meta(next_page_id: u64, pages_per_extent: u64) -> Vec<u8> {
+    let mut meta = next_page_id.to_le_bytes().to_vec();
+    meta.extend_from_slice(&pages_per_extent.to_le_bytes());
+    meta
+}
+
+fn decode_meta(meta: &[u8]) -> Result<(u64, u64)> {
+    if meta.len() != 16 {
+        return Err(Error::new(ErrorKind::InvalidData, "broken meta object"));
+    }
+    Ok((
+        u64::from_le_bytes(meta[..8].try_into().unwrap()),
+        u64::from_le_bytes(meta[8..].try_into().unwrap()),
+    ))
+}
+
+impl<S: ObjectStore> StorageManager for RemoteStorage<S> {
+    fn allocate_page(&mut self) -> manager::Result<PageId> {
+        let page_id = self.next_page_id;
+        self.next_page_id += 1;
+        Ok(PageId(page_id))
+    }
+
+    fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> manager::Result<()> {
+        if page_id.to_u64() >= self.next_page_id {
+            return Err(manager::Error::PastEof(page_id));
+        }
+        let (extent_no, range) = self.locate(page_id);
+        let page = &self.extent(extent_no)?.data[range];
+        if !checksum::verify(page) {
+            return Err(manager::Error::Corrupt(page_id));
+        }
+        data.copy_from_slice(page);
+        Ok(())
+    }
+
+    fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> manager::Result<()> {
+        let (extent_no, range) = self.locate(page_id);
+        let extent = self.extent(extent_no)?;
+        let page = &mut extent.data[range];
+        page.copy_from_slice(data);
+        checksum::stamp(page);
+        extent.dirty = true;
+        Ok(())
+    }
+
+    // 変更のあったエクステントを全てアップロードしてから採番の状態を書く
+    fn sync(&mut self) -> manager::Result<()> {
+        for (&extent_no, extent) in self.cache.iter_mut().filter(|(_, extent)| extent.dirty) {
+            self.store.put(&Self::extent_key(extent_no), &extent.data)?;
+            extent.dirty = false;
+        }
+        let meta = encode_meta(self.next_page_id, self.pages_per_extent as u64);
+        Ok(self.store.put(META_KEY, &meta)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::entity::PAGE_BODY_SIZE;
+    use tempfile::tempdir;
+
+    #[test]
+    fn remote_test() {
+        let dir = tempdir().unwrap();
+        let store = LocalObjectStore::new(dir.path()).unwrap();
+        let mut remote = RemoteStorage::with_layout(store, 4, 2).unwrap();
+
+        let pages: Vec<_> = (0..10u8).map(|n| vec![n; PAGE_SIZE]).collect();
+        let page_ids: Vec<_> = pages
+            .iter()
+            .map(|page| {
+                let page_id = remote.allocate_page().unwrap();
+                remote.write_page_data(page_id, page).unwrap();
+                page_id
+            })
+            .collect();
+        assert_eq!(PageId(1), page_ids[0]);
+        // キャッシュは 2 エクステントまでなので先頭のエクステントは追い出されている
+        assert!(dir
+            .path()
+            .join(RemoteStorage::<LocalObjectStore>::extent_key(0))
+            .exists());
+
+        let mut buf = vec![0u8; PAGE_SIZE];
+        remote.read_page_data(page_ids[0], &mut buf).unwrap();
+        assert_eq!(pages[0][..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        remote.sync().unwrap();
+        drop(remote);
+
+        let store = LocalObjectStore::new(dir.path()).unwrap();
+        let mut remote = RemoteStorage::with_layout(store, 4, 2).unwrap();
+        for (page_id, page) in page_ids.iter().zip(pages.iter()) {
+            remote.read_page_data(*page_id, &mut buf).unwrap();
+            assert_eq!(page[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        }
+        assert_eq!(PageId(11), remote.allocate_page().unwrap());
+        assert!(remote.read_page_data(PageId(42), &mut buf).is_err());
+
+        // エクステントの大きさが違うと開けない
+        let store = LocalObjectStore::new(dir.path()).unwrap();
+        assert!(RemoteStorage::with_layout(store, 8, 2).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use anyhow::Result;
+
+use super::{
+    binder::{LogicalPlan, OrderKey},
+    catalog::Catalog,
+    expr::{conjoin, conjuncts, Expr},
+};
+
+// 物理プランを組み立てる前に論理プランを書き換える
+// 式の定数を畳み、述語を射影や並べ替えや結合の下へできるだけ下ろし、常に真の Filter を除く
+// 述語がテーブルを読むすぐ上に来るので、プランナがインデックスを選ぶときに述語を見つけやすい
+pub fn rewrite(catalog: &Catalog, plan: &LogicalPlan) -> Result<LogicalPlan> {
+    let boxed =
+        |plan: &LogicalPlan| -> Result<Box<LogicalPlan>> { Ok(Box::new(rewrite(catalog, plan)?)) };
+    let fold_all = |exprs: &[Expr]| exprs.iter().map(Expr::fold).collect();
+    Ok(match plan {
+        LogicalPlan::Scan { .. } => plan.clone(),
+        LogicalPlan::Values { rows } => LogicalPlan::Values {
+            rows: rows.iter().map(|row| fold_all(row)).collect(),
+        },
+        LogicalPlan::Filter { input, cond } => {
+            let mut conds = vec![];
+            conjuncts(&cond.fold(), &mut conds);
+            push_down(catalog, rewrite(catalog, input)?, conds)?
+        }
+        LogicalPlan::Join { left, right, cond } => LogicalPlan::Join {
+            left: boxed(left)?,
+            right: boxed(right)?,
+            cond: cond.fold(),
+        },
+        LogicalPlan::Project { input, exprs } => LogicalPlan::Project {
+            input: boxed(input)?,
+            exprs: fold_all(exprs),
+        },
+        LogicalPlan::Sort { input, keys } => LogicalPlan::Sort {
+            input: boxed(input)?,
+            keys: keys
+                .iter()
+                .map(|key| OrderKey {
+                    expr: key.expr.fold(),
+                    descending: key.descending,
+                })
+                .collect(),
+        },
+        LogicalPlan::Limit {
+            input,
+            limit,
+            offset,
+        } => LogicalPlan::Limit {
+            input: boxed(input)?,
+            limit: *limit,
+            offset: *offset,
+        },
+    })
+}
+
+// input の行のうち conds を全て満たすものを返すプランを、述語をできるだけ下に置いて作る
+// LIMIT は述語で減らす前の行を数えるので、その下へは下ろさない
+fn push_down(catalog: &Catalog, input: LogicalPlan, mut conds: Vec<Expr>) -> Result<LogicalPlan> {
+    if conds.is_empty() {
+        return Ok(input);
+    }
+    Ok(match input {
+        LogicalPlan::Filter { input, cond } => {
+            conjuncts(&cond, &mut conds);
+            push_down(catalog, *input, conds)?
+        }
+        // 射影した列を射影する前の式に置き換えて下ろす
+        LogicalPlan::Project { input, exprs } => {
+            let conds = conds
+                .iter()
+                .map(|cond| cond.substitute(&exprs).fold())
+                .collect();
+            LogicalPlan::Project {
+                input: Box::new(push_down(catalog, *input, conds)?),
+                exprs,
+            }
+        }
+        LogicalPlan::Sort { input, keys } => LogicalPlan::Sort {
+            input: Box::new(push_down(catalog, *input, conds)?),
+            keys,
+        },
+        // 片方の列だけを見る述語はその入力へ下ろし、両方を見るものは結合の条件にする
+        LogicalPlan::Join { left, right, cond } => {
+            let width = left.width(catalog)?;
+            let (mut left_conds, mut right_conds, mut join_conds) = (vec![], vec![], vec![]);
+            conjuncts(&cond, &mut join_conds);
+            for cond in conds {
+                let mut columns = vec![];
+                cond.columns(&mut columns);
+                if columns.iter().all(|&column| column < width) {
+                    left_conds.push(cond);
+                } else if columns.iter().all(|&column| column >= width) {
+                    right_conds.push(cond.map_columns(&|column| column - width));
+                } else {
+                    join_conds.push(cond);
+                }
+            }
+            LogicalPlan::Join {
+                left: Box::new(push_down(catalog, *left, left_conds)?),
+                right: Box::new(push_down(catalog, *right, right_conds)?),
+                cond: conjoin(join_conds),
+            }
+        }
+        input => LogicalPlan::Filter {
+            input: Box::new(input),
+            cond: conjoin(conds),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdbms::{
+        binder::{bind, BoundStatement},
+        catalog::ColumnSchema,
+        expr::CmpOp,
+    };
+    use crate::sql::{
+        dml::entity::{Type, Value},
+        parser::parse_statement,
+    };
+    use crate::storage::entity::PageId;
+
+    fn catalog() -> Catalog {
+        let column = |name: &str, column_type| ColumnSchema {
+            name: name.to_string(),
+            column_type,
+            nullable: true,
+        };
+        let mut catalog = Catalog::new();
+        let people = vec![
+            column("id", Type::Int),
+            column("name", Type::Bytes),
+            column("dept", Type::Int),
+        ];
+        catalog.add_table("people", people, 1, PageId(1)).unwrap();
+        let dept = vec![column("id", Type::Int), column("title", Type::Bytes)];
+        catalog.add_table("dept", dept, 1, PageId(2)).unwrap();
+        catalog
+    }
+
+    fn rewritten(catalog: &Catalog, sql: &str) -> LogicalPlan {
+        match bind(catalog, &parse_statement(sql).unwrap()).unwrap() {
+            BoundStatement::Query { plan, .. } => rewrite(catalog, &plan).unwrap(),
+            statement => panic!("not a query: {:?}", statement),
+        }
+    }
+
+    fn filter(input: LogicalPlan, cond: Expr) -> LogicalPlan {
+        LogicalPlan::Filter {
+            input: Box::new(input),
+            cond,
+        }
+    }
+
+    fn project(input: LogicalPlan, exprs: Vec<Expr>) -> LogicalPlan {
+        LogicalPlan::Project {
+            input: Box::new(input),
+            exprs,
+        }
+    }
+
+    #[test]
+    fn fold_test() {
+        let catalog = catalog();
+        let int = |value| Expr::Literal(Value::Int(value));
+        let scan = LogicalPlan::Scan { table: 0 };
+        let columns = || (0..3).map(Expr::Column).collect::<Vec<_>>();
+        // 定数を畳めば主キーと比べる述語になる
+        assert_eq!(
+            project(
+                filter(
+                    scan.clone(),
+                    Expr::compare(CmpOp::Eq, Expr::Column(0), int(3))
+                ),
+                columns()
+            ),
+            rewritten(&catalog, "SELECT * FROM people WHERE id = 1 + 2 AND 1 < 2")
+        );
+        // 常に真の述語は Filter ごと除く
+        assert_eq!(
+            project(scan.clone(), columns()),
+            rewritten(&catalog, "SELECT * FROM people WHERE 1 = 1 OR id = $1")
+        );
+        // 偽と決まれば FALSE のまま残す
+        assert_eq!(
+            project(filter(scan, Expr::Literal(Value::Bool(false))), columns()),
+            rewritten(&catalog, "SELECT * FROM people WHERE id = $1 AND 2 < 1")
+        );
+    }
+
+    #[test]
+    fn push_down_test() {
+        let catalog = catalog();
+        let eq = |lhs, rhs| Expr::compare(CmpOp::Eq, lhs, rhs);
+        let bytes = |value: &str| Expr::Literal(Value::Bytes(value.into()));
+        let people = LogicalPlan::Scan { table: 0 };
+        let dept = LogicalPlan::Scan { table: 1 };
+        // 片方だけを見る述語はそれぞれのテーブルの上へ、両方を見る述語は結合の条件へ
+        let plan = rewritten(
+            &catalog,
+            "SELECT p.name FROM people p JOIN dept d ON TRUE \
+             WHERE d.title = 'dev' AND p.dept = d.id AND p.name = 'bob'",
+        );
+        let join = LogicalPlan::Join {
+            left: Box::new(filter(people.clone(), eq(Expr::Column(1), bytes("bob")))),
+            right: Box::new(filter(dept, eq(Expr::Column(1), bytes("dev")))),
+            cond: eq(Expr::Column(2), Expr::Column(3)),
+        };
+        assert_eq!(project(join, vec![Expr::Column(1)]), plan);
+
+        // 射影の上の述語は射影する前の式に置き換えて下ろす
+        let plan = filter(
+            project(people.clone(), vec![Expr::Column(2), Expr::Column(0)]),
+            eq(Expr::Column(1), Expr::Literal(Value::Int(4))),
+        );
+        assert_eq!(
+            project(
+                filter(people, eq(Expr::Column(0), Expr::Literal(Value::Int(4)))),
+                vec![Expr::Column(2), Expr::Column(0)]
+            ),
+            rewrite(&catalog, &plan).unwrap()
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use super::disk::DiskManager;
+use crate::storage::{
+    entity::PageId,
+    manager::{Error, Result, StorageManager},
+};
+
+// リレーションのファイルの拡張子
+const SEGMENT_EXTENSION: &str = "rel";
+
+// テーブルやインデックスの B+Tree ごとにディレクトリの下の別ファイルを使う storagemanager
+// ページIDの上位ビット (PageId::file_id) をリレーションIDとして使う
+// リレーションの削除はファイルを消すだけで済み、リレーションごとのサイズもファイルの大きさで分かる
+pub struct SegmentedStorage {
+    dir: PathBuf,
+    page_size: usize,
+    relations: BTreeMap<u16, DiskManager>,
+    // allocate_page で新しいページを割り当てるリレーション
+    current_relation: Option<u16>,
+}
+
+impl SegmentedStorage {
+    // ディレクトリにあるリレーションのファイルを全て開く
+    pub fn open(dir: impl AsRef<Path>, page_size: usize) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut relations = BTreeMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let relation_id = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u16>().ok());
+            if let Some(relation_id) = relation_id {
+                relations.insert(relation_id, DiskManager::open(&path, page_size)?);
+            }
+        }
+        // 最後に作ったリレーションに割り当てる
+        let current_relation = relations.keys().next_back().copied();
+        Ok(Self {
+            dir,
+            page_size,
+            relations,
+            current_relation,
+        })
+    }
+
+    fn segment_path(&self, relation_id: u16) -> PathBuf {
+        self.dir
+            .join(format!("{}.{}", relation_id, SEGMENT_EXTENSION))
+    }
+
+    // 新しいリレーションのファイルを作ってリレーションIDを返す
+    // 作ったリレーションが以後の allocate_page の割り当て先になる
+    pub fn create_relation(&mut self) -> io::Result<u16> {
+        let relation_id = match self.relations.keys().next_back() {
+            Some(&last) => last
+                .checked_add(1)
+                .ok_or_else(|| io::Error::other("too many relations"))?,
+            None => 0,
+        };
+        let disk = DiskManager::open(self.segment_path(relation_id), self.page_size)?;
+        self.relations.insert(relation_id, disk);
+        self.current_relation = Some(relation_id);
+        Ok(relation_id)
+    }
+
+    // リレーションのファイルを消す
+    // バッファプールに残っているそのリレーションのページは先に破棄しておくこと
+    pub fn drop_relation(&mut self, relation_id: u16) -> io::Result<()> {
+        if self.relations.remove(&relation_id).is_none() {
+            return Err(no_such_relation(relation_id));
+        }
+        if self.current_relation == Some(relation_id) {
+            self.current_relation = None;
+        }
+        fs::remove_file(self.segment_path(relation_id))
+    }
+
+    pub fn relation_ids(&self) -> Vec<u16> {
+        self.relations.keys().copied().collect()
+    }
+
+    // リレーションのファイルの大きさ (バイト)
+    pub fn relation_size(&self, relation_id: u16) -> io::Result<u64> {
+        Ok(fs::metadata(self.segment_path(relation_id))?.len())
+    }
+
+    // 以後の allocate_page で使うリレーションを切り替える
+    // B+Tree のページ分割でも allocate_page が呼ばれるので、
+    // リレーションを操作する前には必ずそのリレーションを選んでおくこと
+    pub fn set_current_relation(&mut self, relation_id: u16) -> io::Result<()> {
+        if !self.relations.contains_key(&relation_id) {
+            return Err(no_such_relation(relation_id));
+        }
+        self.current_relation = Some(relation_id);
+        Ok(())
+    }
+
+    // ページIDを担当するリレーションとファイル内のページIDに分解する
+    fn route(&mut self, page_id: PageId) -> Result<(&mut DiskManager, PageId)> {
+        let relation_id = page_id.file_id();
+        let disk = self
+            .relations
+            .get_mut(&relation_id)
+            .ok_or_else(|| Error::Io(no_such_relation(relation_id)))?;
+        Ok((disk, PageId(page_id.page_no())))
+    }
+}
+
+fn no_such_relation(relation_id: u16) -> io::Error {
+    io::Error::new(
+        ErrorKind::NotFound,
+        format!("no such relation: {}", relation_id),
+    )
+}
+
+impl StorageManager for SegmentedStorage {
+    fn page_size(&self) -> Option<usize> {
+        Some(self.page_size)
+    }
+
+    fn allocate_page(&mut self) -> Result<PageId> {
+        let relation_id = self.current_relation.ok_or_else(|| {
+            Error::Io(io::Error::new(
+                ErrorKind::InvalidInput,
+                "no relation is selected for allocation",
+            ))
+        })?;
+        let (disk, _) = self.route(PageId::new(relation_id, 0))?;
+        let page_id = disk.allocate_page()?;
+        Ok(PageId::new(relation_id, page_id.to_u64()))
+    }
+
+    fn deallocate_page(&mut self, page_id: PageId) -> Result<()> {
+        let (disk, local_page_id) = self.route(page_id)?;
+        disk.deallocate_page(local_page_id)
+    }
+
+    fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> Result<()> {
+        let (disk, local_page_id) = self.route(page_id)?;
+        disk.read_page_data(local_page_id, data)
+    }
+
+    fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+        let (disk, local_page_id) = self.route(page_id)?;
+        disk.write_page_data(local_page_id, data)
+    }
+
+    fn read_pages_data(&mut self, first_page_id: PageId, bufs: &mut [&mut [u8]]) -> Result<()> {
+        let (disk, local_page_id) = self.route(first_page_id)?;
+        disk.read_pages_data(local_page_id, bufs)
+    }
+
+    fn write_pages_data(&mut self, first_page_id: PageId, pages: &[&[u8]]) -> Result<()> {
+        let (disk, local_page_id) = self.route(first_page_id)?;
+        disk.write_pages_data(local_page_id, pages)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        for disk in self.relations.values_mut() {
+            disk.sync()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accessor::{
+        entity::SearchMode,
+        method::{AccessMethod, Iterable},
+    };
+    use crate::buffer::{entity::PAGE_SIZE, manager::BufferPoolManager};
+    use crate::rdbms::{btree::BTree, clocksweep::ClockSweepManager};
+    use tempfile::tempdir;
+
+    #[test]
+    fn segment_test() {
+        let dir = tempdir().unwrap();
+        let storage = SegmentedStorage::open(dir.path(), PAGE_SIZE).unwrap();
+        let mut bufmgr = ClockSweepManager::new(storage, 10).unwrap();
+
+        let users = bufmgr.storage_mut().create_relation().unwrap();
+        let users_btree = BTree::create(&mut bufmgr).unwrap();
+        users_btree.insert(&mut bufmgr, b"alice", b"1").unwrap();
+        let items = bufmgr.storage_mut().create_relation().unwrap();
+        let items_btree = BTree::create(&mut bufmgr).unwrap();
+        items_btree.insert(&mut bufmgr, b"apple", b"100").unwrap();
+        bufmgr.storage_mut().set_current_relation(users).unwrap();
+        users_btree.insert(&mut bufmgr, b"bob", b"2").unwrap();
+        bufmgr.flush().unwrap();
+
+        assert_eq!(users, users_btree.meta_page_id.file_id());
+        assert_eq!(items, items_btree.meta_page_id.file_id());
+        let storage = bufmgr.storage_mut();
+        assert_eq!(vec![users, items], storage.relation_ids());
+        assert!(storage.relation_size(items).unwrap() > 0);
+        drop(bufmgr);
+
+        // 開き直してもリレーションごとに読める
+        let storage = SegmentedStorage::open(dir.path(), PAGE_SIZE).unwrap();
+        let mut bufmgr = ClockSweepManager::new(storage, 10).unwrap();
+        let mut iter = users_btree.search(&mut bufmgr, SearchMode::Start).unwrap();
+        let (key, _) = iter.next(&mut bufmgr).unwrap().unwrap();
+        assert_eq!(b"alice", key.as_slice());
+        // 開き直した後は最後のリレーションに割り当てる
+        let page_id = bufmgr.create_page().unwrap().page_id;
+        assert_eq!(items, page_id.file_id());
+        assert!(bufmgr.storage_mut().set_current_relation(99).is_err());
+
+        // リレーションの削除はファイルを消すだけ
+        bufmgr.storage_mut().drop_relation(items).unwrap();
+        assert_eq!(vec![users], bufmgr.storage_mut().relation_ids());
+        assert!(!dir.path().join(format!("{}.rel", items)).exists());
+        assert!(bufmgr.fetch_page(items_btree.meta_page_id).is_err());
+        // 割り当て先を消せば、選び直すまで割り当てられない
+        assert!(bufmgr.create_page().is_err());
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+use zerocopy::{AsBytes, ByteSlice, FromBytes, LayoutVerified};
+
+use crate::buffer::manager::BufferPoolManager;
+use crate::storage::entity::PageId;
+
+// 一度にページから取ってきて手元に持っておく値の数
+pub const DEFAULT_CACHE: i64 = 32;
+
+#[derive(Debug, FromBytes, AsBytes)]
+#[repr(C)]
+pub struct Header {
+    // まだどこにも渡していない最初の値
+    pub next: i64,
+}
+
+struct SequencePage<B> {
+    header: LayoutVerified<B, Header>,
+    _unused: B,
+}
+
+impl<B: ByteSlice> SequencePage<B> {
+    fn new(bytes: B) -> Self {
+        let (header, _unused) =
+            LayoutVerified::new_from_prefix(bytes).expect("sequence page must be aligned");
+        Self { header, _unused }
+    }
+}
+
+// 一つのページに次の値を書いておき、増えていく値を順に振るシーケンス
+// 値は cache 個ずつページから取ってきて手元で配るので、ページを書くのはその度だけでよい
+// 手元に残った値は開き直すと捨てるので、振った値は増えていくが飛ぶことがある
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sequence {
+    pub page_id: PageId,
+    pub cache: i64,
+    // 手元にある値の範囲 [next, end)
+    next: i64,
+    end: i64,
+}
+
+impl Sequence {
+    // start から値を振るシーケンスのページを作る
+    pub fn create(bufmgr: &mut dyn BufferPoolManager, start: i64) -> Result<Self> {
+        let buffer = bufmgr.create_page()?;
+        let mut page = SequencePage::new(buffer.body_mut());
+        page.header.next = start;
+        buffer.is_dirty.set(true);
+        Ok(Self::new(buffer.page_id))
+    }
+
+    pub fn new(page_id: PageId) -> Self {
+        Self {
+            page_id,
+            cache: DEFAULT_CACHE,
+            next: 0,
+            end: 0,
+        }
+    }
+
+    // 次の値を振る (手元の値が尽きたらページから cache 個取ってくる)
+    pub fn next_value(&mut self, bufmgr: &mut dyn BufferPoolManager) -> Result<i64> {
+        if self.next == self.end {
+            let buffer = bufmgr.fetch_page(self.page_id)?;
+            let mut page = SequencePage::new(buffer.body_mut());
+            let next = page.header.next;
+            let end = match next.checked_add(self.cache.max(1)) {
+                Some(end) => end,
+                None => bail!("sequence {:?} is exhausted", self.page_id),
+            };
+            page.header.next = end;
+            buffer.is_dirty.set(true);
+            self.next = next;
+            self.end = end;
+        }
+        let value = self.next;
+        self.next += 1;
+        Ok(value)
+    }
+
+    // シーケンスのページを解放する
+    pub fn destroy(self, bufmgr: &mut dyn BufferPoolManager) -> Result<()> {
+        bufmgr.free_page(self.page_id)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdbms::{clocksweep::ClockSweepManager, memory::MemoryManager};
+
+    #[test]
+    fn sequence_test() {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut seq = Sequence::create(&mut bufmgr, 1).unwrap();
+        seq.cache = 3;
+        let values: Vec<_> = (0..4)
+            .map(|_| seq.next_value(&mut bufmgr).unwrap())
+            .collect();
+        assert_eq!(vec![1, 2, 3, 4], values);
+
+        // 開き直すと手元に残った 5, 6 は飛ばす
+        let mut reopened = Sequence::new(seq.page_id);
+        assert_eq!(7, reopened.next_value(&mut bufmgr).unwrap());
+        assert_eq!(5, seq.next_value(&mut bufmgr).unwrap());
+        assert_eq!(8, reopened.next_value(&mut bufmgr).unwrap());
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::wal::entity::TxnId;
+
+use super::{
+    lock::LockTarget,
+    mvcc::{Error, TxnSnapshot},
+};
+
+// Serializable なトランザクションの状態
+struct State {
+    snapshot: TxnSnapshot,
+    // 読んだ行か木 (SIREAD ロック)
+    reads: HashSet<LockTarget>,
+    // 書いた行
+    writes: HashSet<LockTarget>,
+    // 並行するトランザクションから rw 依存を受けている (in) / 与えている (out)
+    in_conflict: bool,
+    out_conflict: bool,
+    // 他のトランザクションが見つけた危険な構造の中心で、コミットできない
+    doomed: bool,
+    // コミットした時点で次に採番する ID (これ以降のトランザクションとは並行しない)
+    committed: Option<TxnId>,
+}
+
+// Serializable Snapshot Isolation のための rw 依存の追跡
+// 並行するトランザクションの間で、読んだものを相手が書いた (rw 依存) ときに辺を張り、
+// in と out の両方の辺を持つトランザクション (危険な構造の中心) ができればアボートさせる
+// コミットしたトランザクションの状態は、並行するトランザクションが全て終わるまで残す
+#[derive(Default)]
+pub struct SsiTracker {
+    txns: HashMap<TxnId, State>,
+}
+
+// read が write と重なるか (木を読んだなら、その木のどの行を書いても重なる)
+fn overlaps(read: &LockTarget, write: &LockTarget) -> bool {
+    match (read, write) {
+        (LockTarget::Row(read_tree, read_key), LockTarget::Row(write_tree, write_key)) => {
+            read_tree == write_tree && read_key == write_key
+        }
+        (LockTarget::Page(read_tree), LockTarget::Row(write_tree, _))
+        | (LockTarget::Page(read_tree), LockTarget::Page(write_tree))
+        | (LockTarget::Row(read_tree, _), LockTarget::Page(write_tree)) => read_tree == write_tree,
+    }
+}
+
+impl SsiTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, snapshot: TxnSnapshot) {
+        self.txns.insert(
+            snapshot.txn_id,
+            State {
+                snapshot,
+                reads: HashSet::new(),
+                writes: HashSet::new(),
+                in_conflict: false,
+                out_conflict: false,
+                doomed: false,
+                committed: None,
+            },
+        );
+    }
+
+    pub fn is_registered(&self, txn_id: TxnId) -> bool {
+        self.txns.contains_key(&txn_id)
+    }
+
+    // txn_id が target を読んだ
+    // スナップショットから見えない書き込みをしたトランザクションへ rw 依存の辺を張る
+    pub fn read(&mut self, txn_id: TxnId, target: LockTarget) -> Result<(), Error> {
+        self.check_doomed(txn_id)?;
+        let reader = &self.txns[&txn_id];
+        let writers: Vec<_> = self
+            .txns
+            .iter()
+            .filter(|(&writer, state)| {
+                writer != txn_id
+                    && !reader.snapshot.is_committed(writer)
+                    && state.writes.iter().any(|write| overlaps(&target, write))
+            })
+            .map(|(&writer, _)| writer)
+            .collect();
+        for writer in writers {
+            self.add_edge(txn_id, writer, txn_id)?;
+        }
+        self.txns.get_mut(&txn_id).unwrap().reads.insert(target);
+        Ok(())
+    }
+
+    // txn_id が target を書く
+    // 並行して target を読んだトランザクションから rw 依存の辺を張る
+    pub fn write(&mut self, txn_id: TxnId, target: LockTarget) -> Result<(), Error> {
+        self.check_doomed(txn_id)?;
+        let writer = &self.txns[&txn_id];
+        let readers: Vec<_> = self
+            .txns
+            .iter()
+            .filter(|(&reader, state)| {
+                reader != txn_id
+                    && !writer.snapshot.is_committed(reader)
+                    && state.reads.iter().any(|read| overlaps(read, &target))
+            })
+            .map(|(&reader, _)| reader)
+            .collect();
+        for reader in readers {
+            self.add_edge(reader, txn_id, txn_id)?;
+        }
+        self.txns.get_mut(&txn_id).unwrap().writes.insert(target);
+        Ok(())
+    }
+
+    // reader から writer への辺を張る
+    // current が中心になるなら辺を張らずに current をアボートさせる
+    // 他方が中心になるなら、実行中ならコミットできなくし、コミット済みなら current をアボートさせる
+    fn add_edge(&mut self, reader: TxnId, writer: TxnId, current: TxnId) -> Result<(), Error> {
+        let would_be_pivot = |txn_id: TxnId, state: &State| {
+            (state.in_conflict || txn_id == writer) && (state.out_conflict || txn_id == reader)
+        };
+        let other = if current == reader { writer } else { reader };
+        if would_be_pivot(current, &self.txns[&current]) {
+            return Err(Error::SerializationFailure(current));
+        }
+        let other_state = &self.txns[&other];
+        if would_be_pivot(other, other_state) && other_state.committed.is_some() {
+            return Err(Error::SerializationFailure(current));
+        }
+        self.txns.get_mut(&reader).unwrap().out_conflict = true;
+        let writer_state = self.txns.get_mut(&writer).unwrap();
+        writer_state.in_conflict = true;
+        let other_state = self.txns.get_mut(&other).unwrap();
+        if other_state.in_conflict && other_state.out_conflict {
+            other_state.doomed = true;
+        }
+        Ok(())
+    }
+
+    fn check_doomed(&self, txn_id: TxnId) -> Result<(), Error> {
+        let state = &self.txns[&txn_id];
+        if state.doomed || (state.in_conflict && state.out_conflict) {
+            return Err(Error::SerializationFailure(txn_id));
+        }
+        Ok(())
+    }
+
+    // コミットしてよいか確かめて、コミット済みにする
+    // next_txn_id はコミットした時点で次に採番する ID
+    pub fn commit(&mut self, txn_id: TxnId, next_txn_id: TxnId) -> Result<(), Error> {
+        self.check_doomed(txn_id)?;
+        self.txns.get_mut(&txn_id).unwrap().committed = Some(next_txn_id);
+        self.prune();
+        Ok(())
+    }
+
+    pub fn abort(&mut self, txn_id: TxnId) {
+        self.txns.remove(&txn_id);
+        self.prune();
+    }
+
+    // 実行中のどのトランザクションとも並行しなくなったコミット済みのものを消す
+    fn prune(&mut self) {
+        let oldest_active = self
+            .txns
+            .iter()
+            .filter(|(_, state)| state.committed.is_none())
+            .map(|(&txn_id, _)| txn_id)
+            .min();
+        self.txns
+            .retain(|_, state| match (state.committed, oldest_active) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(committed), Some(oldest_active)) => oldest_active < committed,
+            });
+    }
+}
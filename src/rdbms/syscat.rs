@@ -0,0 +1,777 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+use super::{
+    analyze::StatsTable,
+    btree::BTree,
+    catalog::{Catalog, ColumnSchema, IndexSchema, TableId, TableSchema},
+    expr::Expr,
+    introspect::FIRST_VIRTUAL_TABLE_ID,
+    mvcc::AllVisible,
+    query::{SeqScan, TupleSearchMode},
+    sequence::Sequence,
+    util::tuple,
+};
+use crate::accessor::method::AccessMethod;
+use crate::buffer::manager::BufferPoolManager;
+use crate::sql::ddl::table::Table as ITable;
+use crate::sql::dml::{
+    entity::{Type, Value},
+    query::PlanNode,
+};
+use crate::storage::entity::PageId;
+
+// システムカタログのテーブルの番号 (ユーザのテーブルとぶつからないよう上から振る)
+pub const CLASS_TABLE_ID: TableId = TableId::MAX;
+pub const ATTRIBUTE_TABLE_ID: TableId = TableId::MAX - 1;
+pub const INDEX_TABLE_ID: TableId = TableId::MAX - 2;
+pub const STATISTIC_TABLE_ID: TableId = TableId::MAX - 3;
+// 仮想テーブルの番号の下に振る (これを持たない古いファイルもある)
+pub const MIGRATION_TABLE_ID: TableId = FIRST_VIRTUAL_TABLE_ID - 1;
+// これより後ろの番号はシステムカタログと仮想テーブルのもの
+pub const FIRST_SYSTEM_TABLE_ID: TableId = MIGRATION_TABLE_ID;
+
+// システムカタログのテーブルの定義
+fn system_table(id: TableId, meta_page_id: PageId) -> TableSchema {
+    let (name, columns, num_key_elems): (_, &[(&str, Type)], _) = match id {
+        CLASS_TABLE_ID => (
+            "minidb_class",
+            &[
+                ("id", Type::Int),
+                ("name", Type::Bytes),
+                ("num_key_elems", Type::Int),
+                ("meta_page_id", Type::Int),
+                // 主キーを振るシーケンスのページ (なければ INVALID_PAGE_ID)
+                ("sequence_page_id", Type::Int),
+            ],
+            1,
+        ),
+        ATTRIBUTE_TABLE_ID => (
+            "minidb_attribute",
+            &[
+                ("table_id", Type::Int),
+                ("number", Type::Int),
+                ("name", Type::Bytes),
+                ("type", Type::Int),
+                ("nullable", Type::Bool),
+            ],
+            2,
+        ),
+        INDEX_TABLE_ID => (
+            "minidb_index",
+            &[
+                ("table_id", Type::Int),
+                ("name", Type::Bytes),
+                // 副キーにする列の番号を tuple::encode したもの
+                ("columns", Type::Bytes),
+                ("unique", Type::Bool),
+                ("meta_page_id", Type::Int),
+            ],
+            2,
+        ),
+        STATISTIC_TABLE_ID => (
+            "minidb_statistic",
+            &[
+                ("table_id", Type::Int),
+                ("entry", Type::Int),
+                ("stats", Type::Bytes),
+            ],
+            2,
+        ),
+        // 適用したマイグレーション (一番大きい version がスキーマのバージョン)
+        MIGRATION_TABLE_ID => (
+            "minidb_migration",
+            &[("version", Type::Int), ("name", Type::Bytes)],
+            1,
+        ),
+        _ => unreachable!(),
+    };
+    TableSchema {
+        id,
+        name: name.to_string(),
+        columns: columns
+            .iter()
+            .map(|&(name, column_type)| ColumnSchema {
+                name: name.to_string(),
+                column_type,
+                nullable: false,
+            })
+            .collect(),
+        num_key_elems,
+        meta_page_id,
+        indexes: vec![],
+        sequence_page_id: None,
+    }
+}
+
+fn class_row(table: &TableSchema) -> [Value; 5] {
+    let sequence_page_id = table.sequence_page_id.unwrap_or_default();
+    [
+        Value::Int(table.id as i64),
+        Value::Bytes(table.name.as_bytes().to_vec()),
+        Value::Int(table.num_key_elems as i64),
+        Value::Int(table.meta_page_id.0 as i64),
+        Value::Int(sequence_page_id.0 as i64),
+    ]
+}
+
+// テーブルとインデックスの定義をデータベースの中に書いておくシステムカタログ
+// minidb_class がシステムカタログ自身を含む全てのテーブルとその meta ページを持つので、
+// 開き直すときは minidb_class の meta ページ (ファイルヘッダの catalog_root_page_id) だけ分かればよい
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemCatalog {
+    pub class_page_id: PageId,
+    pub attribute_page_id: PageId,
+    pub index_page_id: PageId,
+    pub stats: StatsTable,
+    // minidb_migration の meta ページ (まだ作っていなければ None)
+    pub migration_page_id: Option<PageId>,
+}
+
+impl SystemCatalog {
+    // 空のシステムカタログを作り、自身の定義を書いておく
+    pub fn create<T: BufferPoolManager>(bufmgr: &mut T) -> Result<Self> {
+        let syscat = Self {
+            class_page_id: BTree::create(bufmgr)?.meta_page_id,
+            attribute_page_id: BTree::create(bufmgr)?.meta_page_id,
+            index_page_id: BTree::create(bufmgr)?.meta_page_id,
+            stats: StatsTable::create(bufmgr)?,
+            migration_page_id: Some(BTree::create(bufmgr)?.meta_page_id),
+        };
+        for table in syscat.system_tables() {
+            syscat.insert_table(bufmgr, &table)?;
+        }
+        Ok(syscat)
+    }
+
+    // minidb_class からほかのシステムカタログの meta ページを引く
+    pub fn open<T: BufferPoolManager + 'static>(
+        bufmgr: &mut T,
+        class_page_id: PageId,
+    ) -> Result<Self> {
+        let mut meta_page_ids = BTreeMap::new();
+        for row in scan(bufmgr, &system_table(CLASS_TABLE_ID, class_page_id))? {
+            if let [Value::Int(id), _, _, Value::Int(meta_page_id), _] = row.as_slice() {
+                meta_page_ids.insert(*id as TableId, PageId(*meta_page_id as u64));
+            }
+        }
+        let meta_page_id = |id: TableId| match meta_page_ids.get(&id) {
+            Some(&meta_page_id) => Ok(meta_page_id),
+            None => bail!("system catalog {} is missing", id),
+        };
+        Ok(Self {
+            class_page_id: meta_page_id(CLASS_TABLE_ID)?,
+            attribute_page_id: meta_page_id(ATTRIBUTE_TABLE_ID)?,
+            index_page_id: meta_page_id(INDEX_TABLE_ID)?,
+            stats: StatsTable {
+                meta_page_id: meta_page_id(STATISTIC_TABLE_ID)?,
+            },
+            migration_page_id: meta_page_ids.get(&MIGRATION_TABLE_ID).copied(),
+        })
+    }
+
+    pub fn system_tables(&self) -> Vec<TableSchema> {
+        let mut tables = vec![
+            system_table(CLASS_TABLE_ID, self.class_page_id),
+            system_table(ATTRIBUTE_TABLE_ID, self.attribute_page_id),
+            system_table(INDEX_TABLE_ID, self.index_page_id),
+            system_table(STATISTIC_TABLE_ID, self.stats.meta_page_id),
+        ];
+        if let Some(page_id) = self.migration_page_id {
+            tables.push(system_table(MIGRATION_TABLE_ID, page_id));
+        }
+        tables
+    }
+
+    // 適用したマイグレーションの version と名前を version の順に返す
+    pub fn migrations<T: BufferPoolManager + 'static>(
+        &self,
+        bufmgr: &mut T,
+    ) -> Result<Vec<(u32, String)>> {
+        let page_id = match self.migration_page_id {
+            Some(page_id) => page_id,
+            None => return Ok(vec![]),
+        };
+        let mut migrations = vec![];
+        for row in scan(bufmgr, &system_table(MIGRATION_TABLE_ID, page_id))? {
+            match row.as_slice() {
+                [Value::Int(version), Value::Bytes(name)] => {
+                    migrations.push((*version as u32, String::from_utf8(name.clone())?));
+                }
+                _ => bail!("broken row in minidb_migration: {:?}", row),
+            }
+        }
+        Ok(migrations)
+    }
+
+    // マイグレーションを適用したことを書いておく
+    // minidb_migration のない古いファイルなら、ここで作って minidb_class に加える
+    pub fn add_migration<T: BufferPoolManager>(
+        &mut self,
+        bufmgr: &mut T,
+        version: u32,
+        name: &str,
+    ) -> Result<()> {
+        let page_id = match self.migration_page_id {
+            Some(page_id) => page_id,
+            None => {
+                let page_id = BTree::create(bufmgr)?.meta_page_id;
+                self.insert_table(bufmgr, &system_table(MIGRATION_TABLE_ID, page_id))?;
+                self.migration_page_id = Some(page_id);
+                page_id
+            }
+        };
+        let row = [
+            Value::Int(version as i64),
+            Value::Bytes(name.as_bytes().to_vec()),
+        ];
+        insert(bufmgr, &system_table(MIGRATION_TABLE_ID, page_id), &row)
+    }
+
+    // ユーザのテーブルとインデックスの定義と統計を読んでカタログを組み立てる
+    pub fn load<T: BufferPoolManager + 'static>(&self, bufmgr: &mut T) -> Result<Catalog> {
+        let mut tables = BTreeMap::new();
+        for row in scan(bufmgr, &system_table(CLASS_TABLE_ID, self.class_page_id))? {
+            match row.as_slice() {
+                [Value::Int(id), Value::Bytes(name), Value::Int(num_key_elems), Value::Int(meta_page_id), Value::Int(sequence_page_id)] =>
+                {
+                    let id = *id as TableId;
+                    if id >= FIRST_SYSTEM_TABLE_ID {
+                        continue;
+                    }
+                    let table = TableSchema {
+                        id,
+                        name: String::from_utf8(name.clone())?,
+                        columns: vec![],
+                        num_key_elems: *num_key_elems as usize,
+                        meta_page_id: PageId(*meta_page_id as u64),
+                        indexes: vec![],
+                        sequence_page_id: PageId(*sequence_page_id as u64).valid(),
+                    };
+                    tables.insert(id, table);
+                }
+                _ => bail!("broken row in minidb_class: {:?}", row),
+            }
+        }
+        // 列は (テーブル, 列の番号) の順に並んでいる
+        let attributes = system_table(ATTRIBUTE_TABLE_ID, self.attribute_page_id);
+        for row in scan(bufmgr, &attributes)? {
+            match row.as_slice() {
+                [Value::Int(id), Value::Int(_), Value::Bytes(name), Value::Int(tag), Value::Bool(nullable)] => {
+                    if let Some(table) = tables.get_mut(&(*id as TableId)) {
+                        table.columns.push(ColumnSchema {
+                            name: String::from_utf8(name.clone())?,
+                            column_type: Type::from_tag(*tag as u8)?,
+                            nullable: *nullable,
+                        });
+                    }
+                }
+                _ => bail!("broken row in minidb_attribute: {:?}", row),
+            }
+        }
+        for row in scan(bufmgr, &system_table(INDEX_TABLE_ID, self.index_page_id))? {
+            match row.as_slice() {
+                [Value::Int(id), Value::Bytes(name), Value::Bytes(columns), Value::Bool(unique), Value::Int(meta_page_id)] =>
+                {
+                    let mut elems = vec![];
+                    tuple::decode(columns, &mut elems);
+                    let columns = elems
+                        .iter()
+                        .map(|elem| match Value::decode(Type::Int, elem)? {
+                            Value::Int(column) => Ok(column as usize),
+                            _ => unreachable!(),
+                        })
+                        .collect::<Result<_>>()?;
+                    if let Some(table) = tables.get_mut(&(*id as TableId)) {
+                        table.indexes.push(IndexSchema {
+                            name: String::from_utf8(name.clone())?,
+                            columns,
+                            unique: *unique,
+                            meta_page_id: PageId(*meta_page_id as u64),
+                        });
+                    }
+                }
+                _ => bail!("broken row in minidb_index: {:?}", row),
+            }
+        }
+        let mut catalog = Catalog::new();
+        for (_, table) in tables {
+            catalog.restore_table(table)?;
+        }
+        self.stats.load(bufmgr, &mut catalog)?;
+        Ok(catalog)
+    }
+
+    // カタログにテーブルを加え、その定義を書いておく
+    pub fn add_table<T: BufferPoolManager>(
+        &self,
+        bufmgr: &mut T,
+        catalog: &mut Catalog,
+        name: &str,
+        columns: Vec<ColumnSchema>,
+        num_key_elems: usize,
+        meta_page_id: PageId,
+    ) -> Result<TableId> {
+        let id = catalog.add_table(name, columns, num_key_elems, meta_page_id)?;
+        if id >= FIRST_SYSTEM_TABLE_ID {
+            bail!("too many tables");
+        }
+        let table = catalog.table_by_id(id).unwrap();
+        self.insert_table(bufmgr, table)?;
+        Ok(id)
+    }
+
+    // カタログにインデックスを加え、その定義を書いておく
+    pub fn add_index<T: BufferPoolManager>(
+        &self,
+        bufmgr: &mut T,
+        catalog: &mut Catalog,
+        table: TableId,
+        index: IndexSchema,
+    ) -> Result<()> {
+        catalog.add_index(table, index.clone())?;
+        self.insert_index(bufmgr, table, &index)
+    }
+
+    // テーブルの主キーをシーケンスで振るようにする
+    // シーケンスのページを作ってカタログと minidb_class の行に書いておく
+    pub fn add_sequence<T: BufferPoolManager>(
+        &self,
+        bufmgr: &mut T,
+        catalog: &mut Catalog,
+        table: TableId,
+        start: i64,
+    ) -> Result<Sequence> {
+        let sequence = Sequence::create(bufmgr, start)?;
+        catalog.set_sequence(table, sequence.page_id)?;
+        let class = system_table(CLASS_TABLE_ID, self.class_page_id);
+        delete(bufmgr, &class, &[Value::Int(table as i64)])?;
+        insert(
+            bufmgr,
+            &class,
+            &class_row(catalog.table_by_id(table).unwrap()),
+        )?;
+        Ok(sequence)
+    }
+
+    // テーブルとそのインデックスの定義と統計を消し、それぞれの B+Tree のページを全て解放する
+    pub fn drop_table<T: BufferPoolManager>(
+        &self,
+        bufmgr: &mut T,
+        catalog: &mut Catalog,
+        table: TableId,
+    ) -> Result<()> {
+        let schema = catalog.drop_table(table)?;
+        let id = Value::Int(table as i64);
+        let attributes = system_table(ATTRIBUTE_TABLE_ID, self.attribute_page_id);
+        for number in 0..schema.columns.len() {
+            delete(
+                bufmgr,
+                &attributes,
+                &[id.clone(), Value::Int(number as i64)],
+            )?;
+        }
+        let class = system_table(CLASS_TABLE_ID, self.class_page_id);
+        delete(bufmgr, &class, &[id])?;
+        for index in schema.indexes {
+            self.delete_index(bufmgr, table, index)?;
+        }
+        self.stats.remove(bufmgr, table)?;
+        BTree::new(schema.meta_page_id).destroy(bufmgr)?;
+        if let Some(page_id) = schema.sequence_page_id {
+            Sequence::new(page_id).destroy(bufmgr)?;
+        }
+        Ok(())
+    }
+
+    // インデックスの定義を消し、その B+Tree のページを全て解放する
+    pub fn drop_index<T: BufferPoolManager>(
+        &self,
+        bufmgr: &mut T,
+        catalog: &mut Catalog,
+        table: TableId,
+        name: &str,
+    ) -> Result<()> {
+        let index = catalog.drop_index(table, name)?;
+        self.delete_index(bufmgr, table, index)
+    }
+
+    fn delete_index<T: BufferPoolManager>(
+        &self,
+        bufmgr: &mut T,
+        table: TableId,
+        index: IndexSchema,
+    ) -> Result<()> {
+        let key = [
+            Value::Int(table as i64),
+            Value::Bytes(index.name.into_bytes()),
+        ];
+        delete(
+            bufmgr,
+            &system_table(INDEX_TABLE_ID, self.index_page_id),
+            &key,
+        )?;
+        BTree::new(index.meta_page_id).destroy(bufmgr)?;
+        Ok(())
+    }
+
+    fn insert_table<T: BufferPoolManager>(
+        &self,
+        bufmgr: &mut T,
+        table: &TableSchema,
+    ) -> Result<()> {
+        insert(
+            bufmgr,
+            &system_table(CLASS_TABLE_ID, self.class_page_id),
+            &class_row(table),
+        )?;
+        let attributes = system_table(ATTRIBUTE_TABLE_ID, self.attribute_page_id);
+        for (number, column) in table.columns.iter().enumerate() {
+            let attribute = [
+                Value::Int(table.id as i64),
+                Value::Int(number as i64),
+                Value::Bytes(column.name.as_bytes().to_vec()),
+                Value::Int(column.column_type.tag() as i64),
+                Value::Bool(column.nullable),
+            ];
+            insert(bufmgr, &attributes, &attribute)?;
+        }
+        for index in &table.indexes {
+            self.insert_index(bufmgr, table.id, index)?;
+        }
+        Ok(())
+    }
+
+    fn insert_index<T: BufferPoolManager>(
+        &self,
+        bufmgr: &mut T,
+        table: TableId,
+        index: &IndexSchema,
+    ) -> Result<()> {
+        let mut columns = vec![];
+        tuple::encode(
+            index
+                .columns
+                .iter()
+                .map(|&column| Value::Int(column as i64).encode()),
+            &mut columns,
+        );
+        let row = [
+            Value::Int(table as i64),
+            Value::Bytes(index.name.as_bytes().to_vec()),
+            Value::Bytes(columns),
+            Value::Bool(index.unique),
+            Value::Int(index.meta_page_id.0 as i64),
+        ];
+        insert(
+            bufmgr,
+            &system_table(INDEX_TABLE_ID, self.index_page_id),
+            &row,
+        )
+    }
+}
+
+fn insert<T: BufferPoolManager>(
+    bufmgr: &mut T,
+    table: &TableSchema,
+    values: &[Value],
+) -> Result<()> {
+    let table = table.table();
+    let record: Vec<_> = values.iter().map(Value::encode).collect();
+    let record: Vec<_> = record.iter().map(Vec::as_slice).collect();
+    table.insert(bufmgr, &record)
+}
+
+// 主キーで行を消す
+fn delete<T: BufferPoolManager>(bufmgr: &mut T, table: &TableSchema, key: &[Value]) -> Result<()> {
+    let mut encoded = vec![];
+    tuple::encode(key.iter().map(Value::encode), &mut encoded);
+    BTree::new(table.meta_page_id).delete(bufmgr, &encoded)?;
+    Ok(())
+}
+
+// システムカタログのテーブルの行を全て読む
+fn scan<T: BufferPoolManager + 'static>(
+    bufmgr: &mut T,
+    table: &TableSchema,
+) -> Result<Vec<Vec<Value>>> {
+    let plan = SeqScan {
+        table_accessor: Arc::new(BTree::new(table.meta_page_id)),
+        search_mode: TupleSearchMode::Start,
+        num_key_elems: table.num_key_elems,
+        stop_key: Bound::Unbounded,
+        while_cond: Expr::TRUE,
+        visibility: Arc::new(AllVisible),
+        schema: table.types(),
+    };
+    let mut exec = PlanNode::<T>::start(&plan, bufmgr)?;
+    let mut rows = vec![];
+    while let Some(row) = exec.next(bufmgr)? {
+        let row = (0..table.columns.len())
+            .map(|column| row.get(column))
+            .collect::<Result<_>>()?;
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rdbms::{
+        catalog::TableStats,
+        clocksweep::ClockSweepManager,
+        memory::MemoryManager,
+        table::{Table, UniqueIndex},
+    };
+
+    #[test]
+    fn syscat_test() {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let syscat = SystemCatalog::create(&mut bufmgr).unwrap();
+        let mut catalog = Catalog::new();
+        let column = |name: &str, column_type| ColumnSchema {
+            name: name.to_string(),
+            column_type,
+            nullable: true,
+        };
+        let id = ColumnSchema {
+            nullable: false,
+            ..column("id", Type::Int)
+        };
+        let people = syscat
+            .add_table(
+                &mut bufmgr,
+                &mut catalog,
+                "people",
+                vec![id, column("name", Type::Bytes)],
+                1,
+                PageId(100),
+            )
+            .unwrap();
+        let flags = vec![column("on", Type::Bool)];
+        syscat
+            .add_table(&mut bufmgr, &mut catalog, "flags", flags, 1, PageId(200))
+            .unwrap();
+        let index = IndexSchema {
+            name: "by_name".to_string(),
+            columns: vec![1, 0],
+            unique: false,
+            meta_page_id: PageId(101),
+        };
+        syscat
+            .add_index(&mut bufmgr, &mut catalog, people, index)
+            .unwrap();
+        let stats = TableStats {
+            rows: 3,
+            pages: 1,
+            columns: vec![Default::default(), Default::default()],
+        };
+        syscat.stats.save(&mut bufmgr, people, &stats).unwrap();
+
+        // minidb_class の meta ページだけから開き直す
+        let reopened = SystemCatalog::open(&mut bufmgr, syscat.class_page_id).unwrap();
+        assert_eq!(syscat, reopened);
+        let mut loaded = reopened.load(&mut bufmgr).unwrap();
+        let tables: Vec<_> = catalog.tables().collect();
+        assert_eq!(tables, loaded.tables().collect::<Vec<_>>());
+        assert_eq!(Some(&stats), loaded.stats(people));
+        // 読み戻したカタログも続きの番号を振る
+        let next = loaded.add_table("next", vec![], 1, PageId(300)).unwrap();
+        assert!(tables.iter().all(|table| table.id < next));
+        // システムカタログ自身の定義も minidb_class と minidb_attribute にある
+        let class = system_table(CLASS_TABLE_ID, syscat.class_page_id);
+        let names: Vec<_> = scan(&mut bufmgr, &class)
+            .unwrap()
+            .into_iter()
+            .map(|row| row[1].clone())
+            .collect();
+        assert!(names.contains(&Value::Bytes(b"minidb_attribute".to_vec())));
+        let attributes = system_table(ATTRIBUTE_TABLE_ID, syscat.attribute_page_id);
+        assert_eq!(23, scan(&mut bufmgr, &attributes).unwrap().len());
+    }
+
+    #[test]
+    fn drop_test() {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let syscat = SystemCatalog::create(&mut bufmgr).unwrap();
+        let mut catalog = Catalog::new();
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![UniqueIndex {
+                meta_page_id: PageId::INVALID_PAGE_ID,
+                skey: vec![1],
+            }],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        table.create(&mut bufmgr).unwrap();
+        for id in 0..100u64 {
+            let name = format!("name{}", id);
+            table
+                .insert(&mut bufmgr, &[&id.to_be_bytes(), name.as_bytes()])
+                .unwrap();
+        }
+        let column = |name: &str| ColumnSchema {
+            name: name.to_string(),
+            column_type: Type::Bytes,
+            nullable: true,
+        };
+        let columns = vec![column("id"), column("name")];
+        let people = syscat
+            .add_table(
+                &mut bufmgr,
+                &mut catalog,
+                "people",
+                columns,
+                1,
+                table.meta_page_id,
+            )
+            .unwrap();
+        let index = IndexSchema {
+            name: "by_name".to_string(),
+            columns: vec![1],
+            unique: true,
+            meta_page_id: table.unique_indices[0].meta_page_id,
+        };
+        syscat
+            .add_index(&mut bufmgr, &mut catalog, people, index)
+            .unwrap();
+        let stats = TableStats {
+            rows: 100,
+            pages: 2,
+            columns: vec![],
+        };
+        syscat.stats.save(&mut bufmgr, people, &stats).unwrap();
+        let index_pages = BTree::new(table.unique_indices[0].meta_page_id)
+            .page_ids(&mut bufmgr)
+            .unwrap();
+        let table_pages = BTree::new(table.meta_page_id)
+            .page_ids(&mut bufmgr)
+            .unwrap();
+
+        syscat
+            .drop_index(&mut bufmgr, &mut catalog, people, "by_name")
+            .unwrap();
+        let loaded = syscat.load(&mut bufmgr).unwrap();
+        assert!(loaded.table("people").unwrap().indexes.is_empty());
+        // 解放したページを次の B+Tree が使う
+        let reused = BTree::create(&mut bufmgr).unwrap();
+        assert!(index_pages.contains(&reused.meta_page_id));
+
+        syscat
+            .drop_table(&mut bufmgr, &mut catalog, people)
+            .unwrap();
+        assert_eq!(None, catalog.table("people"));
+        let loaded = syscat.load(&mut bufmgr).unwrap();
+        assert_eq!(0, loaded.tables().count());
+        assert_eq!(None, loaded.stats(people));
+        let reused = BTree::create(&mut bufmgr).unwrap();
+        assert!(table_pages.contains(&reused.meta_page_id));
+        assert!(syscat
+            .drop_table(&mut bufmgr, &mut catalog, people)
+            .is_err());
+    }
+
+    #[test]
+    fn sequence_test() {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let syscat = SystemCatalog::create(&mut bufmgr).unwrap();
+        let mut catalog = Catalog::new();
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![],
+            non_unique_indices: vec![],
+            schema: None,
+        };
+        table.create(&mut bufmgr).unwrap();
+        let column = |name: &str, column_type| ColumnSchema {
+            name: name.to_string(),
+            column_type,
+            nullable: false,
+        };
+        let columns = vec![column("id", Type::Int), column("name", Type::Bytes)];
+        let people = syscat
+            .add_table(
+                &mut bufmgr,
+                &mut catalog,
+                "people",
+                columns,
+                1,
+                table.meta_page_id,
+            )
+            .unwrap();
+        let mut sequence = syscat
+            .add_sequence(&mut bufmgr, &mut catalog, people, 1)
+            .unwrap();
+        for name in ["alice", "bob"] {
+            table
+                .insert_auto(&mut bufmgr, &mut sequence, &[name.as_bytes()])
+                .unwrap();
+        }
+
+        // 開き直したシーケンスは、前に取ってきた値の続きから振る
+        let loaded = syscat.load(&mut bufmgr).unwrap();
+        let schema = loaded.table_by_id(people).unwrap();
+        assert_eq!(Some(sequence.page_id), schema.sequence_page_id);
+        let mut reopened = Sequence::new(schema.sequence_page_id.unwrap());
+        let id = table
+            .insert_auto(&mut bufmgr, &mut reopened, &[b"carol"])
+            .unwrap();
+        assert!(id > 2);
+        let ids: Vec<_> = scan(&mut bufmgr, schema)
+            .unwrap()
+            .into_iter()
+            .map(|row| row[0].clone())
+            .collect();
+        assert_eq!(vec![Value::Int(1), Value::Int(2), Value::Int(id)], ids);
+
+        // 整数の一列の主キーでなければシーケンスで振れない
+        let flags = vec![column("on", Type::Bool)];
+        let flags = syscat
+            .add_table(&mut bufmgr, &mut catalog, "flags", flags, 1, PageId(200))
+            .unwrap();
+        assert!(syscat
+            .add_sequence(&mut bufmgr, &mut catalog, flags, 1)
+            .is_err());
+    }
+
+    #[test]
+    fn migration_test() {
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let syscat = SystemCatalog::create(&mut bufmgr).unwrap();
+        // minidb_migration を持たない古いファイルにする
+        let class = system_table(CLASS_TABLE_ID, syscat.class_page_id);
+        let id = Value::Int(MIGRATION_TABLE_ID as i64);
+        delete(&mut bufmgr, &class, std::slice::from_ref(&id)).unwrap();
+        let attributes = system_table(ATTRIBUTE_TABLE_ID, syscat.attribute_page_id);
+        for number in 0..2 {
+            let key = [id.clone(), Value::Int(number)];
+            delete(&mut bufmgr, &attributes, &key).unwrap();
+        }
+        let mut old = SystemCatalog::open(&mut bufmgr, syscat.class_page_id).unwrap();
+        assert_eq!(None, old.migration_page_id);
+        assert!(old.migrations(&mut bufmgr).unwrap().is_empty());
+
+        // 初めて書くときに作り、開き直しても見つかる
+        old.add_migration(&mut bufmgr, 1, "create people").unwrap();
+        old.add_migration(&mut bufmgr, 2, "index people").unwrap();
+        let reopened = SystemCatalog::open(&mut bufmgr, syscat.class_page_id).unwrap();
+        assert_eq!(old, reopened);
+        assert_eq!(
+            vec![
+                (1, "create people".to_string()),
+                (2, "index people".to_string())
+            ],
+            reopened.migrations(&mut bufmgr).unwrap()
+        );
+        assert!(old.add_migration(&mut bufmgr, 2, "again").is_err());
+        // ユーザのテーブルとしては読まない
+        assert_eq!(0, reopened.load(&mut bufmgr).unwrap().tables().count());
+    }
+}
@@ -1,12 +1,17 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 use super::util::tuple;
-use crate::accessor::method::AccessMethod;
+use crate::accessor::method::{AccessMethod, Error as AccessError};
 use crate::buffer::manager::BufferPoolManager;
-use crate::sql::ddl::table::{Table as ITable, UniqueIndex as IUniqueIndex};
+use crate::sql::ddl::entity::Schema;
+use crate::sql::ddl::table::{
+    NonUniqueIndex as INonUniqueIndex, Table as ITable, UniqueIndex as IUniqueIndex,
+};
+use crate::sql::dml::entity::{Tuple, Value};
 use crate::storage::entity::PageId;
+use crate::wal::entity::TxnId;
 
-use super::btree::BTree;
+use super::{btree::BTree, mvcc::TupleHeader, sequence::Sequence};
 
 #[derive(Debug)]
 pub struct SimpleTable {
@@ -14,6 +19,43 @@ pub struct SimpleTable {
     pub num_key_elems: usize,
 }
 
+// レコードを B-tree のキーと値にする
+fn encode(num_key_elems: usize, txn_id: TxnId, record: &[&[u8]]) -> (Vec<u8>, Vec<u8>) {
+    let mut key = vec![];
+    tuple::encode(record[..num_key_elems].iter(), &mut key);
+    let mut value = vec![];
+    TupleHeader::new(txn_id).encode(&mut value);
+    tuple::encode(record[num_key_elems..].iter(), &mut value);
+    (key, value)
+}
+
+// B-tree のキーと値をレコードに戻す
+fn decode(key: &[u8], value: &[u8]) -> Result<Tuple> {
+    let (_, value) = TupleHeader::decode(value)?;
+    let mut record = vec![];
+    tuple::decode(key, &mut record);
+    tuple::decode(value, &mut record);
+    Ok(record)
+}
+
+// 主キーが pkey のレコード
+fn get(
+    bufmgr: &mut dyn BufferPoolManager,
+    meta_page_id: PageId,
+    pkey: &[&[u8]],
+) -> Result<Option<Tuple>> {
+    let mut key = vec![];
+    tuple::encode(pkey.iter(), &mut key);
+    let value = BTree::new(meta_page_id).get(bufmgr, &key)?;
+    value.map(|value| decode(&key, &value)).transpose()
+}
+
+impl SimpleTable {
+    pub fn encode(&self, txn_id: TxnId, record: &[&[u8]]) -> (Vec<u8>, Vec<u8>) {
+        encode(self.num_key_elems, txn_id, record)
+    }
+}
+
 impl<T: BufferPoolManager> ITable<T> for SimpleTable {
     fn create(&mut self, bufmgr: &mut T) -> Result<()> {
         let btree = BTree::create(bufmgr)?;
@@ -21,15 +63,30 @@ impl<T: BufferPoolManager> ITable<T> for SimpleTable {
         Ok(())
     }
 
-    fn insert(&self, bufmgr: &mut T, record: &[&[u8]]) -> Result<()> {
+    fn insert_as(&self, bufmgr: &mut T, txn_id: TxnId, record: &[&[u8]]) -> Result<()> {
+        let btree = BTree::new(self.meta_page_id);
+        let (key, value) = self.encode(txn_id, record);
+        btree.insert(bufmgr, &key, &value)?;
+        Ok(())
+    }
+
+    fn delete(&self, bufmgr: &mut T, record: &[&[u8]]) -> Result<()> {
         let btree = BTree::new(self.meta_page_id);
         let mut key = vec![];
         tuple::encode(record[..self.num_key_elems].iter(), &mut key);
-        let mut value = vec![];
-        tuple::encode(record[self.num_key_elems..].iter(), &mut value);
-        btree.insert(bufmgr, &key, &value)?;
+        btree.delete(bufmgr, &key)?;
         Ok(())
     }
+
+    fn get(&self, bufmgr: &mut T, pkey: &[&[u8]]) -> Result<Option<Tuple>> {
+        get(bufmgr, self.meta_page_id, pkey)
+    }
+
+    fn upsert(&self, bufmgr: &mut T, record: &[&[u8]]) -> Result<Option<Tuple>> {
+        let (key, value) = self.encode(TxnId::INVALID_TXN_ID, record);
+        let old = BTree::new(self.meta_page_id).upsert(bufmgr, &key, &value)?;
+        old.map(|old| decode(&key, &old)).transpose()
+    }
 }
 
 #[derive(Debug)]
@@ -37,6 +94,9 @@ pub struct Table {
     pub meta_page_id: PageId,
     pub num_key_elems: usize,
     pub unique_indices: Vec<self::UniqueIndex>,
+    pub non_unique_indices: Vec<self::NonUniqueIndex>,
+    // INSERT するレコードを確かめる列の定義 (None なら確かめずにそのまま書く)
+    pub schema: Option<Schema>,
 }
 
 impl<T: BufferPoolManager> ITable<T> for self::Table {
@@ -46,21 +106,227 @@ impl<T: BufferPoolManager> ITable<T> for self::Table {
         for unique_index in &mut self.unique_indices {
             unique_index.create(bufmgr)?;
         }
+        for index in &mut self.non_unique_indices {
+            index.create(bufmgr)?;
+        }
         Ok(())
     }
 
-    fn insert(&self, bufmgr: &mut T, record: &[&[u8]]) -> Result<()> {
+    // 一意のインデックスで他の行と重ならないか先に確かめ、それでもインデックスに書けなければ
+    // 書いた項目を消してから返す (失敗したら何も書かない)
+    fn insert_as(&self, bufmgr: &mut T, txn_id: TxnId, record: &[&[u8]]) -> Result<()> {
+        if let Some(schema) = &self.schema {
+            schema.validate(record)?;
+        }
+        for index in &self.unique_indices {
+            if BTree::new(index.meta_page_id)
+                .get(bufmgr, &index.key(record))?
+                .is_some()
+            {
+                return Err(AccessError::DuplicateKey.into());
+            }
+        }
         let btree = BTree::new(self.meta_page_id);
-        let mut key = vec![];
-        tuple::encode(record[..self.num_key_elems].iter(), &mut key);
-        let mut value = vec![];
-        tuple::encode(record[self.num_key_elems..].iter(), &mut value);
+        let (key, value) = encode(self.num_key_elems, txn_id, record);
         btree.insert(bufmgr, &key, &value)?;
+        if let Err(err) = self.insert_index_entries(bufmgr, &key, record) {
+            btree.delete(bufmgr, &key)?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // record は主キーの列だけを見て、インデックスのキーは書いてある行から作る
+    // 行を木から消してしまうので MVCC の外の操作 (トランザクションの中では
+    // TransactionManager::mark_deleted で印を付け、vacuum に消させる)
+    fn delete(&self, bufmgr: &mut T, record: &[&[u8]]) -> Result<()> {
+        let pkey = &record[..self.num_key_elems];
+        let current = match get(bufmgr, self.meta_page_id, pkey)? {
+            Some(current) => current,
+            None => return Err(AccessError::KeyNotFound.into()),
+        };
+        let mut key = vec![];
+        tuple::encode(pkey.iter(), &mut key);
+        BTree::new(self.meta_page_id).delete(bufmgr, &key)?;
         for unique_index in &self.unique_indices {
-            unique_index.insert(bufmgr, &key, record)?;
+            unique_index.delete(bufmgr, &current)?;
+        }
+        for index in &self.non_unique_indices {
+            index.delete(bufmgr, &key, &current)?;
         }
         Ok(())
     }
+
+    fn get(&self, bufmgr: &mut T, pkey: &[&[u8]]) -> Result<Option<Tuple>> {
+        get(bufmgr, self.meta_page_id, pkey)
+    }
+
+    // 副キーの変わったインデックスだけ、前の項目を消して新しい項目を書く
+    fn upsert(&self, bufmgr: &mut T, record: &[&[u8]]) -> Result<Option<Tuple>> {
+        if let Some(schema) = &self.schema {
+            schema.validate(record)?;
+        }
+        // 途中で失敗して木がずれないように、一意のインデックスで他の行と重ならないか先に確かめる
+        let current = get(bufmgr, self.meta_page_id, &record[..self.num_key_elems])?;
+        for index in &self.unique_indices {
+            let skey = index.key(record);
+            if current
+                .as_ref()
+                .is_some_and(|current| index.key(current) == skey)
+            {
+                continue;
+            }
+            if BTree::new(index.meta_page_id).get(bufmgr, &skey)?.is_some() {
+                return Err(AccessError::DuplicateKey.into());
+            }
+        }
+        let (key, value) = encode(self.num_key_elems, TxnId::INVALID_TXN_ID, record);
+        let old = BTree::new(self.meta_page_id).upsert(bufmgr, &key, &value)?;
+        let old = old.map(|old| decode(&key, &old)).transpose()?;
+        for index in &self.unique_indices {
+            if let Some(old) = &old {
+                if index.key(old) == index.key(record) {
+                    continue;
+                }
+                index.delete(bufmgr, old)?;
+            }
+            index.insert(bufmgr, &key, record)?;
+        }
+        for index in &self.non_unique_indices {
+            if let Some(old) = &old {
+                if index.key(&key, old) == index.key(&key, record) {
+                    continue;
+                }
+                index.delete(bufmgr, &key, old)?;
+            }
+            index.insert(bufmgr, &key, record)?;
+        }
+        Ok(old)
+    }
+}
+
+impl Table {
+    // 空のテーブルに records をまとめて書く (全ての列を並べたもの、順は問わない)
+    // 主キーとインデックスの木をそれぞれキーの順に並べ替えて BTree::bulk_load で詰める
+    // 主キーや一意のインデックスで重なる行があれば、どの木にも書く前にエラーを返す
+    pub fn bulk_load<T: BufferPoolManager>(&self, bufmgr: &mut T, records: &[Tuple]) -> Result<()> {
+        let records: Vec<Vec<&[u8]>> = records
+            .iter()
+            .map(|record| record.iter().map(Vec::as_slice).collect())
+            .collect();
+        let mut pairs = vec![];
+        for record in &records {
+            if let Some(schema) = &self.schema {
+                schema.validate(record)?;
+            }
+            pairs.push(encode(self.num_key_elems, TxnId::INVALID_TXN_ID, record));
+        }
+        let pkeys: Vec<_> = pairs.iter().map(|(key, _)| key.clone()).collect();
+        let mut trees = vec![(self.meta_page_id, pairs)];
+        for index in &self.unique_indices {
+            let pairs = pkeys
+                .iter()
+                .zip(&records)
+                .map(|(pkey, record)| (index.key(record), pkey.clone()))
+                .collect();
+            trees.push((index.meta_page_id, pairs));
+        }
+        for index in &self.non_unique_indices {
+            let pairs = pkeys
+                .iter()
+                .zip(&records)
+                .map(|(pkey, record)| (index.key(pkey, record), pkey.clone()))
+                .collect();
+            trees.push((index.meta_page_id, pairs));
+        }
+        for (meta_page_id, pairs) in &mut trees {
+            if !BTree::new(*meta_page_id).is_empty(bufmgr)? {
+                return Err(AccessError::NotEmpty.into());
+            }
+            pairs.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            if pairs.windows(2).any(|window| window[0].0 == window[1].0) {
+                return Err(AccessError::DuplicateKey.into());
+            }
+        }
+        for (meta_page_id, pairs) in trees {
+            BTree::new(meta_page_id).bulk_load(bufmgr, pairs)?;
+        }
+        Ok(())
+    }
+
+    // 全てのインデックスに項目を書く (失敗したらそれまでに書いた項目を消す)
+    fn insert_index_entries<T: BufferPoolManager>(
+        &self,
+        bufmgr: &mut T,
+        key: &[u8],
+        record: &[&[u8]],
+    ) -> Result<()> {
+        for (i, index) in self.unique_indices.iter().enumerate() {
+            if let Err(err) = index.insert(bufmgr, key, record) {
+                for index in &self.unique_indices[..i] {
+                    index.delete(bufmgr, record)?;
+                }
+                return Err(err);
+            }
+        }
+        for (i, index) in self.non_unique_indices.iter().enumerate() {
+            if let Err(err) = index.insert(bufmgr, key, record) {
+                for index in &self.unique_indices {
+                    index.delete(bufmgr, record)?;
+                }
+                for index in &self.non_unique_indices[..i] {
+                    index.delete(bufmgr, key, record)?;
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    // 主キーが key で値が value の行を指すインデックスの項目を消す (vacuum で消した古い版に使う)
+    // 一意のインデックスの項目は、後から同じ値で挿入した別の行を指していれば残す
+    pub fn delete_index_entries<T: BufferPoolManager>(
+        &self,
+        bufmgr: &mut T,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<()> {
+        let record = decode(key, value)?;
+        for index in &self.unique_indices {
+            let btree = BTree::new(index.meta_page_id);
+            let skey = index.key(&record);
+            if btree.get(bufmgr, &skey)?.as_deref() == Some(key) {
+                btree.delete(bufmgr, &skey)?;
+            }
+        }
+        for index in &self.non_unique_indices {
+            match BTree::new(index.meta_page_id).delete(bufmgr, &index.key(key, &record)) {
+                Ok(()) | Err(AccessError::KeyNotFound) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    // 主キーをシーケンスで振って INSERT し、振った主キーを返す
+    // record は主キーを除いた列 (主キーは一列の整数に限る)
+    pub fn insert_auto<T: BufferPoolManager>(
+        &self,
+        bufmgr: &mut T,
+        sequence: &mut Sequence,
+        record: &[&[u8]],
+    ) -> Result<i64> {
+        if self.num_key_elems != 1 {
+            bail!("auto-increment needs a single-column primary key");
+        }
+        let id = sequence.next_value(bufmgr)?;
+        let pkey = Value::Int(id).encode();
+        let record: Vec<_> = std::iter::once(pkey.as_slice())
+            .chain(record.iter().copied())
+            .collect();
+        self.insert(bufmgr, &record)?;
+        Ok(id)
+    }
 }
 
 #[derive(Debug)]
@@ -69,6 +335,17 @@ pub struct UniqueIndex {
     pub skey: Vec<usize>,
 }
 
+impl UniqueIndex {
+    fn key(&self, record: &[impl AsRef<[u8]>]) -> Vec<u8> {
+        let mut skey = vec![];
+        tuple::encode(
+            self.skey.iter().map(|&index| record[index].as_ref()),
+            &mut skey,
+        );
+        skey
+    }
+}
+
 impl<T: BufferPoolManager> IUniqueIndex<T> for UniqueIndex {
     fn create(&mut self, bufmgr: &mut T) -> Result<()> {
         let btree = BTree::create(bufmgr)?;
@@ -78,12 +355,53 @@ impl<T: BufferPoolManager> IUniqueIndex<T> for UniqueIndex {
 
     fn insert(&self, bufmgr: &mut T, pkey: &[u8], record: &[impl AsRef<[u8]>]) -> Result<()> {
         let btree = BTree::new(self.meta_page_id);
-        let mut skey = vec![];
+        btree.insert(bufmgr, &self.key(record), pkey)?;
+        Ok(())
+    }
+
+    fn delete(&self, bufmgr: &mut T, record: &[impl AsRef<[u8]>]) -> Result<()> {
+        let btree = BTree::new(self.meta_page_id);
+        btree.delete(bufmgr, &self.key(record))?;
+        Ok(())
+    }
+}
+
+// 値は UniqueIndex と同じく主キーにして、インデックスを引く側がどちらか気にせず主キーを読めるようにする
+#[derive(Debug)]
+pub struct NonUniqueIndex {
+    pub meta_page_id: PageId,
+    pub skey: Vec<usize>,
+}
+
+impl NonUniqueIndex {
+    fn key(&self, pkey: &[u8], record: &[impl AsRef<[u8]>]) -> Vec<u8> {
+        let mut key = vec![];
         tuple::encode(
             self.skey.iter().map(|&index| record[index].as_ref()),
-            &mut skey,
+            &mut key,
         );
-        btree.insert(bufmgr, &skey, pkey)?;
+        // 主キーも memcmpable に符号化したものなので、そのまま続ければ列を並べたのと同じになる
+        key.extend_from_slice(pkey);
+        key
+    }
+}
+
+impl<T: BufferPoolManager> INonUniqueIndex<T> for NonUniqueIndex {
+    fn create(&mut self, bufmgr: &mut T) -> Result<()> {
+        let btree = BTree::create(bufmgr)?;
+        self.meta_page_id = btree.meta_page_id;
+        Ok(())
+    }
+
+    fn insert(&self, bufmgr: &mut T, pkey: &[u8], record: &[impl AsRef<[u8]>]) -> Result<()> {
+        let btree = BTree::new(self.meta_page_id);
+        btree.insert(bufmgr, &self.key(pkey, record), pkey)?;
+        Ok(())
+    }
+
+    fn delete(&self, bufmgr: &mut T, pkey: &[u8], record: &[impl AsRef<[u8]>]) -> Result<()> {
+        let btree = BTree::new(self.meta_page_id);
+        btree.delete(bufmgr, &self.key(pkey, record))?;
         Ok(())
     }
 }
@@ -0,0 +1,153 @@
+use std::io::{self, ErrorKind};
+
+use crate::storage::{
+    entity::PageId,
+    manager::{Error, Result, StorageManager},
+};
+
+// 複数のヒープファイルを一つの storagemanager として見せる
+// ページIDの上位ビットをファイルID (テーブルスペース) として使い、該当するファイルへ振り分ける
+// 大きなテーブルやインデックスを別ファイルや別ディスクに置くために使う
+pub struct TablespaceManager<T: StorageManager> {
+    files: Vec<T>,
+    // allocate_page で新しいページを割り当てるファイル
+    default_file_id: u16,
+}
+
+impl<T: StorageManager> TablespaceManager<T> {
+    // files[0] がファイルID 0 (ヘッダやカタログを置く既定のファイル) になる
+    pub fn new(files: Vec<T>) -> Self {
+        assert!(!files.is_empty(), "tablespace needs at least one file");
+        assert!(files.len() <= u16::MAX as usize, "too many files");
+        Self {
+            files,
+            default_file_id: 0,
+        }
+    }
+
+    // ファイルを追加してそのファイルIDを返す
+    pub fn add_file(&mut self, file: T) -> u16 {
+        assert!(self.files.len() < u16::MAX as usize, "too many files");
+        self.files.push(file);
+        (self.files.len() - 1) as u16
+    }
+
+    pub fn num_files(&self) -> usize {
+        self.files.len()
+    }
+
+    // 以後の allocate_page で使うファイルを切り替える
+    pub fn set_default_file(&mut self, file_id: u16) {
+        assert!((file_id as usize) < self.files.len(), "unknown file id");
+        self.default_file_id = file_id;
+    }
+
+    // 指定したファイルに新しいページを割り当てる
+    pub fn allocate_page_in(&mut self, file_id: u16) -> Result<PageId> {
+        let page_id = self.files[file_id as usize].allocate_page()?;
+        Ok(PageId::new(file_id, page_id.to_u64()))
+    }
+
+    // ページIDを担当するファイルとファイル内のページIDに分解する
+    fn route(&mut self, page_id: PageId) -> Result<(&mut T, PageId)> {
+        let file = self
+            .files
+            .get_mut(page_id.file_id() as usize)
+            .ok_or_else(|| {
+                Error::Io(io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("no such file in tablespace: {}", page_id.file_id()),
+                ))
+            })?;
+        Ok((file, PageId(page_id.page_no())))
+    }
+}
+
+impl<T: StorageManager> StorageManager for TablespaceManager<T> {
+    fn page_size(&self) -> Option<usize> {
+        self.files[self.default_file_id as usize].page_size()
+    }
+
+    fn allocate_page(&mut self) -> Result<PageId> {
+        self.allocate_page_in(self.default_file_id)
+    }
+
+    fn deallocate_page(&mut self, page_id: PageId) -> Result<()> {
+        let (file, local_page_id) = self.route(page_id)?;
+        file.deallocate_page(local_page_id)
+    }
+
+    fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> Result<()> {
+        let (file, local_page_id) = self.route(page_id)?;
+        file.read_page_data(local_page_id, data)
+    }
+
+    fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+        let (file, local_page_id) = self.route(page_id)?;
+        file.write_page_data(local_page_id, data)
+    }
+
+    // 連続するページIDは必ず同じファイルに属するのでそのまま渡せる
+    fn read_pages_data(&mut self, first_page_id: PageId, bufs: &mut [&mut [u8]]) -> Result<()> {
+        let (file, local_page_id) = self.route(first_page_id)?;
+        file.read_pages_data(local_page_id, bufs)
+    }
+
+    fn write_pages_data(&mut self, first_page_id: PageId, pages: &[&[u8]]) -> Result<()> {
+        let (file, local_page_id) = self.route(first_page_id)?;
+        file.write_pages_data(local_page_id, pages)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        for file in self.files.iter_mut() {
+            file.sync()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::entity::{PAGE_BODY_SIZE, PAGE_SIZE};
+    use crate::rdbms::disk::DiskManager;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn tablespace_test() {
+        let (file0, path0) = NamedTempFile::new().unwrap().into_parts();
+        let (file1, path1) = NamedTempFile::new().unwrap().into_parts();
+        let mut ts = TablespaceManager::new(vec![DiskManager::new(file0, PAGE_SIZE).unwrap()]);
+        assert_eq!(1, ts.add_file(DiskManager::new(file1, PAGE_SIZE).unwrap()));
+
+        let mut hello = vec![0u8; PAGE_SIZE];
+        hello[..5].copy_from_slice(b"hello");
+        let hello_page_id = ts.allocate_page().unwrap();
+        assert_eq!(PageId(1), hello_page_id);
+        ts.write_page_data(hello_page_id, &hello).unwrap();
+
+        ts.set_default_file(1);
+        let mut world = vec![0u8; PAGE_SIZE];
+        world[..5].copy_from_slice(b"world");
+        let world_page_id = ts.allocate_page().unwrap();
+        assert_eq!(1, world_page_id.file_id());
+        assert_eq!(1, world_page_id.page_no());
+        ts.write_page_data(world_page_id, &world).unwrap();
+        ts.sync().unwrap();
+
+        let mut buf = vec![0u8; PAGE_SIZE];
+        ts.read_page_data(world_page_id, &mut buf).unwrap();
+        assert_eq!(world[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        let err = ts.read_page_data(PageId::new(2, 1), &mut buf).unwrap_err();
+        assert!(matches!(err, Error::Io(err) if err.kind() == ErrorKind::NotFound));
+        drop(ts);
+
+        // それぞれのファイルは単独の DiskManager としても読める
+        let mut disk0 = DiskManager::open(&path0, PAGE_SIZE).unwrap();
+        disk0.read_page_data(PageId(1), &mut buf).unwrap();
+        assert_eq!(hello[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+        let mut disk1 = DiskManager::open(&path1, PAGE_SIZE).unwrap();
+        disk1.read_page_data(PageId(1), &mut buf).unwrap();
+        assert_eq!(world[..PAGE_BODY_SIZE], buf[..PAGE_BODY_SIZE]);
+    }
+}
@@ -0,0 +1,203 @@
+use std::convert::TryInto;
+use std::io;
+use std::rc::Rc;
+
+use crate::buffer::{
+    entity::{Buffer, Checkpoint, PAGE_BODY_SIZE, PAGE_SIZE},
+    manager::*,
+};
+use crate::sql::dml::entity::Tuple;
+use crate::storage::entity::PageId;
+
+use super::{clocksweep::ClockSweepManager, disk::DiskManager, util::tuple};
+
+// ソートのランやハッシュ結合のビルド側など一時データ用の buffermanager
+// pool_size ページまではメモリに置き、溢れた分は名前の無い一時ファイルへ追い出す
+pub struct TempBufferManager {
+    bufmgr: ClockSweepManager<DiskManager>,
+}
+
+impl TempBufferManager {
+    pub fn new(pool_size: usize) -> io::Result<Self> {
+        let disk = DiskManager::new(tempfile::tempfile()?, PAGE_SIZE)?;
+        let bufmgr = ClockSweepManager::new(disk, pool_size).map_err(io::Error::other)?;
+        Ok(Self { bufmgr })
+    }
+}
+
+impl BufferPoolManager for TempBufferManager {
+    fn fetch_page(&mut self, page_id: PageId) -> Result<Rc<Buffer>, Error> {
+        self.bufmgr.fetch_page(page_id)
+    }
+
+    fn create_page(&mut self) -> Result<Rc<Buffer>, Error> {
+        self.bufmgr.create_page()
+    }
+
+    // 一時データは永続化しないので何もしない
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn checkpoint(&mut self) -> Result<Checkpoint, Error> {
+        Ok(Checkpoint::default())
+    }
+
+    fn discard_page(&mut self, page_id: PageId) -> Result<(), Error> {
+        self.bufmgr.discard_page(page_id)
+    }
+
+    fn free_page(&mut self, page_id: PageId) -> Result<(), Error> {
+        self.bufmgr.free_page(page_id)
+    }
+}
+
+// 一時データに書き出したタプルの列 (ソートのランやスピルしたパーティションなど)
+// [長さ: u32][tuple::encode したタプル] を続けて並べ、ページの境目を跨いで書く
+// 一ページに満たない末尾はメモリに持っておく
+#[derive(Default)]
+pub struct TupleRun {
+    page_ids: Vec<PageId>,
+    tail: Vec<u8>,
+}
+
+impl TupleRun {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write<'a, T: BufferPoolManager>(
+        bufmgr: &mut T,
+        tuples: impl IntoIterator<Item = &'a Tuple>,
+    ) -> Result<Self, Error> {
+        let mut run = Self::new();
+        for elems in tuples {
+            run.push(bufmgr, elems)?;
+        }
+        Ok(run)
+    }
+
+    // 末尾に足す
+    pub fn push<T: BufferPoolManager>(
+        &mut self,
+        bufmgr: &mut T,
+        elems: &[Vec<u8>],
+    ) -> Result<(), Error> {
+        let mut record = vec![];
+        tuple::encode(elems.iter(), &mut record);
+        self.tail
+            .extend_from_slice(&(record.len() as u32).to_le_bytes());
+        self.tail.extend_from_slice(&record);
+        while self.tail.len() >= PAGE_BODY_SIZE {
+            let buffer = bufmgr.create_page()?;
+            buffer
+                .body_mut()
+                .copy_from_slice(&self.tail[..PAGE_BODY_SIZE]);
+            buffer.is_dirty.set(true);
+            self.page_ids.push(buffer.page_id);
+            self.tail.drain(..PAGE_BODY_SIZE);
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.page_ids.is_empty() && self.tail.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.page_ids.len() * PAGE_BODY_SIZE + self.tail.len()
+    }
+
+    // 先頭から順に読む
+    pub fn into_reader(self) -> TupleRunReader {
+        TupleRunReader {
+            run: self,
+            pos: 0,
+            page: vec![],
+        }
+    }
+}
+
+pub struct TupleRunReader {
+    run: TupleRun,
+    // 次に読む位置と、その位置のページの残り
+    pos: usize,
+    page: Vec<u8>,
+}
+
+impl TupleRunReader {
+    pub fn next<T: BufferPoolManager>(&mut self, bufmgr: &mut T) -> Result<Option<Tuple>, Error> {
+        if self.pos == self.run.len() {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes(self.read(bufmgr, 4)?.try_into().unwrap()) as usize;
+        let record = self.read(bufmgr, len)?;
+        let mut elems = vec![];
+        tuple::decode(&record, &mut elems);
+        Ok(Some(elems))
+    }
+
+    // 先頭から読み直す
+    pub fn rewind(&mut self) {
+        self.pos = 0;
+        self.page.clear();
+    }
+
+    fn read<T: BufferPoolManager>(&mut self, bufmgr: &mut T, len: usize) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            if self.page.is_empty() {
+                let offset = self.pos % PAGE_BODY_SIZE;
+                self.page = match self.run.page_ids.get(self.pos / PAGE_BODY_SIZE) {
+                    Some(&page_id) => bufmgr.fetch_page(page_id)?.body()[offset..].to_vec(),
+                    None => self.run.tail[offset..].to_vec(),
+                };
+            }
+            let n = self.page.len().min(len - bytes.len());
+            bytes.extend(self.page.drain(..n));
+            self.pos += n;
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::entity::PAGE_BODY_SIZE;
+
+    #[test]
+    fn spill_test() {
+        let mut bufmgr = TempBufferManager::new(2).unwrap();
+        let page_ids: Vec<_> = (0..5u8)
+            .map(|n| {
+                let buffer = bufmgr.create_page().unwrap();
+                buffer.body_mut().copy_from_slice(&[n; PAGE_BODY_SIZE]);
+                buffer.page_id
+            })
+            .collect();
+        for (n, &page_id) in page_ids.iter().enumerate() {
+            let buffer = bufmgr.fetch_page(page_id).unwrap();
+            assert_eq!(&[n as u8; PAGE_BODY_SIZE], buffer.body().as_ref());
+        }
+    }
+
+    #[test]
+    fn tuple_run_test() {
+        let mut bufmgr = TempBufferManager::new(2).unwrap();
+        // ページを跨ぐ大きなタプルと空のタプル
+        let tuples: Vec<Tuple> = (0..100u8)
+            .map(|n| vec![vec![n; n as usize * 10], vec![]])
+            .collect();
+        let run = TupleRun::write(&mut bufmgr, &tuples).unwrap();
+        let mut reader = run.into_reader();
+        for expected in &tuples {
+            assert_eq!(Some(expected), reader.next(&mut bufmgr).unwrap().as_ref());
+        }
+        assert_eq!(None, reader.next(&mut bufmgr).unwrap());
+        reader.rewind();
+        assert_eq!(Some(&tuples[0]), reader.next(&mut bufmgr).unwrap().as_ref());
+        let mut reader = TupleRun::write(&mut bufmgr, &[]).unwrap().into_reader();
+        assert_eq!(None, reader.next(&mut bufmgr).unwrap());
+    }
+}
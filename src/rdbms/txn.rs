@@ -0,0 +1,2089 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+
+use crate::accessor::{
+    entity::SearchMode,
+    method::{self, AccessMethod, Iterable},
+};
+use crate::buffer::{
+    entity::{Buffer, Checkpoint, Snapshot},
+    manager::{self, BufferPoolManager},
+};
+use crate::storage::{entity::PageId, manager::StorageManager};
+use crate::wal::{
+    entity::{LogRecord, Lsn, TxnId},
+    manager::LogManager,
+};
+
+use super::{
+    btree::BTree,
+    cdc::ChangeStream,
+    checkpoint::Checkpointer,
+    clocksweep::ClockSweepManager,
+    lock::{LockManager, LockMode, LockStatus, LockTarget},
+    mvcc::{self, TupleHeader, TxnSnapshot},
+    ssi::SsiTracker,
+    table::{SimpleTable, Table},
+};
+
+// コミットの統計
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CommitStats {
+    pub num_commits: u64,
+    // コミットのためにログを書き出した回数
+    pub num_flushes: u64,
+    // commit を呼んでから永続化されるまでの時間の合計と最大
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+    // 統計を取り始めてからの時間
+    pub elapsed: Duration,
+}
+
+impl CommitStats {
+    pub fn avg_latency(&self) -> Duration {
+        if self.num_commits == 0 {
+            Duration::ZERO
+        } else {
+            self.total_latency / self.num_commits as u32
+        }
+    }
+
+    // 1 秒あたりのコミット数
+    pub fn throughput(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            0.0
+        } else {
+            self.num_commits as f64 / self.elapsed.as_secs_f64()
+        }
+    }
+}
+
+// コミットがいつ返るか (PostgreSQL の synchronous_commit)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    // ログを書き出して永続化してから返る
+    Sync,
+    // ログに追記しただけで返る (クラッシュすると最大 max_data_loss の間のコミットを失う)
+    Async,
+}
+
+// トランザクションの分離レベル
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsolationLevel {
+    // 開始した時点のスナップショットを読む (write skew が起こりうる)
+    Snapshot,
+    // スナップショットを読んだうえで、並行するトランザクションとの rw 依存を追跡し、
+    // 直列に実行したのと同じ結果にならなくなるものをアボートさせる (SSI)
+    Serializable,
+}
+
+// トランザクションの書き換えの取り消し方 (一つのトランザクションでは最初に書いた方に決まる)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoMode {
+    // 触ったページを触る前の内容に戻す (bind や mark_deleted での書き換え)
+    Physical,
+    // TransactionManager::insert で挿入したキーを消す
+    Logical,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // 取り消し方の違う書き換えを混ぜると、ロールバックで片方が残る
+    #[error("transaction {txn_id:?} already writes with {mode:?} undo")]
+    MixedUndo { txn_id: TxnId, mode: UndoMode },
+    // 書き込む行のロックを他のトランザクションが持っている
+    // 要求は待ち行列に残るので、持っているトランザクションが終わってからもう一度書く
+    #[error("transaction {txn_id:?} is waiting for a lock on {target:?}")]
+    LockWait { txn_id: TxnId, target: LockTarget },
+    // 待つとデッドロックになる (トランザクションをロールバックすること)
+    #[error("transaction {0:?} would deadlock")]
+    Deadlock(TxnId),
+    // コミットの書き出しに失敗した後は、ログに残ったコミットのレコードが後から永続化しうるので使えない
+    // (開き直してリカバリすること)
+    #[error("the commit log could not be flushed; reopen and recover the database")]
+    Poisoned,
+}
+
+// 実行中のトランザクションの統計
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TxnStats {
+    // トランザクションのスナップショットで見えた行の数
+    pub rows_read: u64,
+    // insert や mark_deleted で書き込んだ行の数
+    pub rows_written: u64,
+    // 書き換えたページの数
+    pub pages_dirtied: usize,
+    // 書いたログの大きさ (書き換えたページはコミットのときに書くので、それまでは含まない)
+    pub wal_bytes: u64,
+    // 取ったか待っているロックの数
+    pub locks_held: usize,
+}
+
+// 非同期コミットを書き出すまでに待つ最大の時間の既定値
+pub const DEFAULT_MAX_DATA_LOSS: Duration = Duration::from_millis(200);
+
+// vacuum の統計
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VacuumStats {
+    // 消した古い版の数
+    pub num_removed: usize,
+    // 隣の葉に寄せて空になり、ストレージに返したページ
+    pub freed_pages: Vec<PageId>,
+}
+
+// vacuum で一つのトランザクションにまとめる削除の数
+// (トランザクションが触ったページはコミットまで pin されるので、バッファプールに収まる数にする)
+const VACUUM_BATCH_SIZE: usize = 64;
+// vacuum で一つのトランザクションにまとめる葉の併合の数 (一つの併合で親と隣の葉も触る)
+const VACUUM_MERGE_BATCH_SIZE: usize = 8;
+
+// トランザクションの開始・コミット・ロールバックを受け持つ
+// コミットのときにトランザクションが書き換えたページの内容をログに書いて永続化するので、
+// コミットしたトランザクションの書き換えは全て残り、そうでないものは何も残らない
+pub struct TransactionManager<L: LogManager> {
+    log: L,
+    next_txn_id: u64,
+    // 活動中のトランザクションと、その最初のレコードの LSN
+    active: BTreeMap<TxnId, Lsn>,
+    // 活動中のトランザクションのスナップショットで、実行中だったものの一番古い ID
+    xmins: BTreeMap<TxnId, TxnId>,
+    lockmgr: LockManager,
+    // コミットのレコードを書いてから書き出すまで待つ時間 (グループコミット)
+    commit_window: Duration,
+    // 書き出しを待っているトランザクションと commit を呼んだ時刻
+    pending: Vec<(Txn, Instant)>,
+    // トランザクションで指定しなかったときのコミットの永続性
+    durability: Durability,
+    // 非同期コミットを書き出すまでに待つ最大の時間 (クラッシュで失うかもしれない範囲)
+    max_data_loss: Duration,
+    // コミット済みにしたがまだ書き出していない非同期コミットと commit を呼んだ時刻
+    unflushed: Vec<(Txn, Instant)>,
+    stats: CommitStats,
+    stats_started: Instant,
+    // チェックポイントの後でログに書いたページと、最後に書いた内容
+    // 二度目からは最後に書いた内容との差分だけを書く
+    logged_images: HashMap<PageId, Vec<u8>>,
+    // これから開始するトランザクションの分離レベル
+    isolation: IsolationLevel,
+    // Serializable なトランザクションの読み書き
+    ssi: SsiTracker,
+    // コミットもロールバックもせずに捨てられたトランザクション (Txn の drop で積まれる)
+    abandoned: Rc<RefCell<Vec<Abandoned>>>,
+    // 活動中から外したが、まだアボートのレコードを書いていないもの
+    unlogged_aborts: Vec<TxnId>,
+    // 捨てられたが、挿入をまだ取り消していないもの
+    // 取り消すまでは活動中のままでロックも持ち続ける
+    undo_pending: Vec<Abandoned>,
+    // コミットの書き出しに失敗した
+    poisoned: bool,
+}
+
+// 捨てられたトランザクション
+struct Abandoned {
+    txn_id: TxnId,
+    // 挿入を取り消すまで pin しておくページと、取り消す挿入 (ページごと元に戻したものは空)
+    pages: HashMap<PageId, (Rc<Buffer>, Snapshot)>,
+    undo: Vec<(Lsn, PageId, Vec<u8>)>,
+}
+
+impl<L: LogManager> TransactionManager<L> {
+    // ログに残っているトランザクション ID とは重ならないように採番する
+    // 切り詰められたログのトランザクションの ID はチェックポイントに残っている
+    pub fn new(mut log: L) -> Result<Self> {
+        let start_lsn = log.start_lsn();
+        let next_txn_id = log
+            .read_from(start_lsn)?
+            .into_iter()
+            .filter_map(|(_, record)| match record {
+                LogRecord::Checkpoint { next_txn_id, .. } => Some(next_txn_id.0),
+                record => record.txn_id().map(|txn_id| txn_id.0 + 1),
+            })
+            .fold(1, std::cmp::max);
+        Ok(Self {
+            log,
+            next_txn_id,
+            active: BTreeMap::new(),
+            xmins: BTreeMap::new(),
+            lockmgr: LockManager::new(),
+            commit_window: Duration::ZERO,
+            pending: vec![],
+            durability: Durability::Sync,
+            max_data_loss: DEFAULT_MAX_DATA_LOSS,
+            unflushed: vec![],
+            stats: CommitStats::default(),
+            stats_started: Instant::now(),
+            logged_images: HashMap::new(),
+            isolation: IsolationLevel::Snapshot,
+            ssi: SsiTracker::new(),
+            abandoned: Rc::new(RefCell::new(vec![])),
+            unlogged_aborts: vec![],
+            undo_pending: vec![],
+            poisoned: false,
+        })
+    }
+
+    pub fn log(&self) -> &L {
+        &self.log
+    }
+
+    pub fn log_mut(&mut self) -> &mut L {
+        &mut self.log
+    }
+
+    // 活動中のトランザクション (チェックポイントに記録する)
+    pub fn active_txns(&mut self) -> Vec<(TxnId, Lsn)> {
+        self.release_abandoned();
+        self.active
+            .iter()
+            .map(|(&txn_id, &lsn)| (txn_id, lsn))
+            .collect()
+    }
+
+    // 次に採番するトランザクション ID (チェックポイントに記録する)
+    pub fn next_txn_id(&self) -> TxnId {
+        TxnId(self.next_txn_id)
+    }
+
+    // トランザクションの外から読むときのスナップショット
+    // 今の時点でコミット済みの書き込みだけが見える
+    pub fn snapshot(&mut self) -> TxnSnapshot {
+        self.release_abandoned();
+        self.snapshot_for(TxnId::INVALID_TXN_ID)
+    }
+
+    fn snapshot_for(&self, txn_id: TxnId) -> TxnSnapshot {
+        TxnSnapshot::new(
+            txn_id,
+            self.next_txn_id(),
+            self.active.keys().copied().collect(),
+        )
+    }
+
+    // B-tree を書き換える前に、木 (メタページ) と行のロックを取る
+    // ロックはコミットかロールバックまで持ち続ける (二相ロック)
+    // Waiting なら他のトランザクションが終わってから is_granted で確かめ、
+    // Deadlock ならロールバックすること
+    pub fn lock(&mut self, txn: &Txn, target: LockTarget, mode: LockMode) -> LockStatus {
+        self.release_abandoned();
+        let status = self.lockmgr.lock(txn.txn_id, target.clone(), mode);
+        if status != LockStatus::Deadlock {
+            txn.locks.borrow_mut().insert(target);
+        }
+        status
+    }
+
+    pub fn is_granted(&mut self, txn: &Txn, target: &LockTarget) -> bool {
+        self.release_abandoned();
+        self.lockmgr.is_granted(txn.txn_id, target)
+    }
+
+    pub fn isolation(&self) -> IsolationLevel {
+        self.isolation
+    }
+
+    // これから begin するトランザクションの分離レベルを決める
+    pub fn set_isolation(&mut self, isolation: IsolationLevel) {
+        self.isolation = isolation;
+    }
+
+    // Serializable なトランザクションが読んだ行 (Row) か木全体 (Page) を記録する
+    // スキャンやキーでの検索の結果を使う前に呼ぶ (Snapshot なら何もしない)
+    // 並行するトランザクションとの間で直列化できなくなれば mvcc::Error::SerializationFailure を返すので、
+    // そのときはロールバックすること
+    pub fn record_read(&mut self, txn: &Txn, target: LockTarget) -> Result<()> {
+        self.release_abandoned();
+        if self.ssi.is_registered(txn.txn_id) {
+            self.ssi.read(txn.txn_id, target)?;
+        }
+        Ok(())
+    }
+
+    // 書き込む行の排他ロックと木の共有ロックを取り、SSI に書き込みを記録する
+    // (木全体を書き換えるトランザクションは木の排他ロックを取るので、その間は待つ)
+    fn record_write(&mut self, txn: &Txn, tree: PageId, key: &[u8]) -> Result<()> {
+        let targets = [
+            (LockTarget::Page(tree), LockMode::Shared),
+            (LockTarget::Row(tree, key.to_vec()), LockMode::Exclusive),
+        ];
+        for (target, mode) in targets {
+            match self.lock(txn, target.clone(), mode) {
+                LockStatus::Granted => {}
+                LockStatus::Waiting => {
+                    let txn_id = txn.txn_id;
+                    return Err(Error::LockWait { txn_id, target }.into());
+                }
+                LockStatus::Deadlock => return Err(Error::Deadlock(txn.txn_id).into()),
+            }
+        }
+        if self.ssi.is_registered(txn.txn_id) {
+            self.ssi
+                .write(txn.txn_id, LockTarget::Row(tree, key.to_vec()))?;
+        }
+        Ok(())
+    }
+
+    // チェックポイントを始める
+    // 書き出しの途中で切れたページも redo で作り直せるように、
+    // この後で最初に書き換えるページは全体をログに書く
+    pub fn begin_checkpoint<S: StorageManager>(
+        &mut self,
+        checkpointer: &mut Checkpointer,
+        bufmgr: &ClockSweepManager<S>,
+    ) -> Result<Lsn> {
+        self.reap()?;
+        let active_txns = self.active_txns();
+        let next_txn_id = self.next_txn_id();
+        let lsn = checkpointer.begin(bufmgr, &mut self.log, &active_txns, next_txn_id)?;
+        self.logged_images.clear();
+        Ok(lsn)
+    }
+
+    // 開始した時点のスナップショットを取る
+    // トランザクションの中の読み出しは全てこのスナップショットで行うので、
+    // 後からコミットした書き込みは途中から見えたりしない
+    pub fn begin(&mut self) -> Result<Txn> {
+        self.reap()?;
+        let txn_id = TxnId(self.next_txn_id);
+        self.next_txn_id += 1;
+        let lsn = self.log.append(&LogRecord::Begin { txn_id })?;
+        let wal_bytes = self.log.end_lsn().to_u64() - lsn.to_u64();
+        self.active.insert(txn_id, lsn);
+        let snapshot = self.snapshot_for(txn_id);
+        let xmin = snapshot.active.iter().next().copied().unwrap_or(txn_id);
+        self.xmins.insert(txn_id, xmin);
+        if self.isolation == IsolationLevel::Serializable {
+            self.ssi.register(snapshot.clone());
+        }
+        let txn = self.new_txn(txn_id, snapshot);
+        txn.wal_bytes.set(wal_bytes);
+        Ok(txn)
+    }
+
+    fn new_txn(&self, txn_id: TxnId, snapshot: TxnSnapshot) -> Txn {
+        Txn {
+            txn_id,
+            snapshot,
+            pages: HashMap::new(),
+            undo: vec![],
+            undo_mode: None,
+            on_commit: vec![],
+            on_abort: vec![],
+            durability: None,
+            rows_written: Cell::new(0),
+            wal_bytes: Cell::new(0),
+            locks: RefCell::new(HashSet::new()),
+            finished: false,
+            abandoned: Rc::clone(&self.abandoned),
+        }
+    }
+
+    pub fn commit_window(&self) -> Duration {
+        self.commit_window
+    }
+
+    // 0 でなければ、最初のコミットからこの時間内に来たコミットをまとめて一度に書き出す
+    // その間の Sync のコミットは永続化を待たずに commit から返る
+    pub fn set_commit_window(&mut self, window: Duration) {
+        self.commit_window = window;
+    }
+
+    // 書き出しを待っているトランザクション
+    pub fn pending_commits(&self) -> Vec<TxnId> {
+        self.pending.iter().map(|(txn, _)| txn.txn_id).collect()
+    }
+
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    pub fn max_data_loss(&self) -> Duration {
+        self.max_data_loss
+    }
+
+    pub fn set_max_data_loss(&mut self, max_data_loss: Duration) {
+        self.max_data_loss = max_data_loss;
+    }
+
+    // コミット済みだがまだ書き出していない非同期コミット
+    pub fn unflushed_commits(&self) -> Vec<TxnId> {
+        self.unflushed.iter().map(|(txn, _)| txn.txn_id).collect()
+    }
+
+    pub fn commit_stats(&self) -> CommitStats {
+        CommitStats {
+            elapsed: self.stats_started.elapsed(),
+            ..self.stats
+        }
+    }
+
+    pub fn reset_commit_stats(&mut self) {
+        self.stats = CommitStats::default();
+        self.stats_started = Instant::now();
+    }
+
+    // 書き換えたページの内容とコミットのレコードを書く
+    // Sync なら、待つ時間が過ぎていれば書き出しを待っているものとまとめて永続化する
+    // commit_window が 0 でなければ、まだ永続化していなくても Ok を返すので、
+    // 永続化したことは on_commit のフックか poll_commits, flush_commits の返り値で確かめる
+    // 永続化するまではロックもページも持ったままで、他のトランザクションからは実行中に見える
+    // 書き出しに失敗したときはロールバックされる (flush_commits を参照)
+    // Async なら、ロックを外してすぐにコミット済みにし、書き出しは max_data_loss の間に行う
+    // Serializable で直列化できなくなっていれば、ページごと元に戻して
+    // mvcc::Error::SerializationFailure を返す
+    // 途中で失敗したときは txn を捨てるので、捨てたトランザクションと同じくアボートする
+    pub fn commit(&mut self, mut txn: Txn) -> Result<()> {
+        self.reap()?;
+        let started = Instant::now();
+        let txn_id = txn.txn_id;
+        if self.ssi.is_registered(txn_id) {
+            if let Err(err) = self.ssi.commit(txn_id, self.next_txn_id()) {
+                drop(txn);
+                self.reap()?;
+                return Err(err.into());
+            }
+        }
+        self.log_pages(&txn)?;
+        let commit_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        self.append(
+            &txn,
+            &LogRecord::Commit {
+                txn_id,
+                commit_time,
+            },
+        )?;
+        match txn.durability.unwrap_or(self.durability) {
+            Durability::Sync => self.pending.push((txn, started)),
+            Durability::Async => {
+                // 書き出すまではページが書き戻されないように pin したままにする
+                txn.finished = true;
+                self.finish(txn_id);
+                self.record_commit(started);
+                self.unflushed.push((txn, started));
+            }
+        }
+        self.poll_commits()?;
+        Ok(())
+    }
+
+    // 最初に待ち始めたコミットから commit_window が過ぎているか、
+    // 最初の非同期コミットから max_data_loss が過ぎていれば書き出す
+    // 非同期コミットを使うときは、定期的に呼んで失う範囲を抑える
+    // 永続化したトランザクションを返す
+    pub fn poll_commits(&mut self) -> Result<Vec<TxnId>> {
+        self.reap()?;
+        let sync_due = self
+            .pending
+            .first()
+            .is_some_and(|(_, started)| started.elapsed() >= self.commit_window);
+        let async_due = self
+            .unflushed
+            .first()
+            .is_some_and(|(_, started)| started.elapsed() >= self.max_data_loss);
+        if sync_due || async_due {
+            self.flush_commits()
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    // 書き出しを待っているコミットを一度の書き出しで永続化する
+    // 永続化したトランザクションの on_commit のフックを呼ぶ
+    // 失敗したときは待っていたトランザクションを全てロールバックし、
+    // 非同期コミットは失われたものとして on_abort のフックを呼ぶ
+    // 書けなかったコミットのレコードはログに残っていて、後の書き出しで永続化しうるので、
+    // それからは何をしても Error::Poisoned を返す (開き直してリカバリすること)
+    pub fn flush_commits(&mut self) -> Result<Vec<TxnId>> {
+        self.reap()?;
+        if self.pending.is_empty() && self.unflushed.is_empty() {
+            return Ok(vec![]);
+        }
+        let pending = std::mem::take(&mut self.pending);
+        let unflushed = std::mem::take(&mut self.unflushed);
+        let result = self.log.flush();
+        self.poisoned = result.is_err();
+        let mut txn_ids = vec![];
+        let mut committed = vec![];
+        for (mut txn, started) in pending {
+            let txn_id = txn.txn_id;
+            if result.is_ok() {
+                txn.finished = true;
+                self.record_commit(started);
+                txn_ids.push(txn_id);
+                committed.push(txn);
+            }
+            self.finish(txn_id);
+        }
+        let lost = result.is_err();
+        for (txn, _) in unflushed {
+            txn_ids.push(txn.txn_id);
+            committed.push(txn);
+        }
+        txn_ids.sort();
+        committed.sort_by_key(|txn| txn.txn_id);
+        // ロックを外してから呼ぶので、フックの中から次のトランザクションを始めてもよい
+        for mut txn in committed {
+            txn.run_hooks(!lost);
+        }
+        result?;
+        self.stats.num_flushes += 1;
+        Ok(txn_ids)
+    }
+
+    fn record_commit(&mut self, started: Instant) {
+        let latency = started.elapsed();
+        self.stats.num_commits += 1;
+        self.stats.total_latency += latency;
+        self.stats.max_latency = self.stats.max_latency.max(latency);
+    }
+
+    // 活動中のトランザクションから外してロックを外す
+    fn finish(&mut self, txn_id: TxnId) {
+        self.active.remove(&txn_id);
+        self.xmins.remove(&txn_id);
+        self.lockmgr.release_all(txn_id);
+    }
+
+    // 捨てられたトランザクションのうち、ページを drop で元に戻したものを活動中から外し、
+    // ロックと SSI の記録を外す (アボートのレコードは次の reap で書く)
+    // 挿入を取り消さなければならないものは undo_abandoned まで残す
+    fn release_abandoned(&mut self) {
+        let abandoned = std::mem::take(&mut *self.abandoned.borrow_mut());
+        for abandoned in abandoned {
+            if !abandoned.undo.is_empty() {
+                self.undo_pending.push(abandoned);
+                continue;
+            }
+            let txn_id = abandoned.txn_id;
+            self.finish(txn_id);
+            self.ssi.abort(txn_id);
+            self.unlogged_aborts.push(txn_id);
+        }
+    }
+
+    // 捨てられたトランザクションをアボートする
+    // コミットの書き出しに失敗していれば Error::Poisoned を返す
+    fn reap(&mut self) -> Result<()> {
+        if self.poisoned {
+            return Err(Error::Poisoned.into());
+        }
+        self.release_abandoned();
+        while let Some(&txn_id) = self.unlogged_aborts.first() {
+            self.log.append(&LogRecord::Abort { txn_id })?;
+            self.unlogged_aborts.remove(0);
+        }
+        Ok(())
+    }
+
+    // txn のレコードを書き、書いた大きさを txn の統計に足す
+    fn append(&mut self, txn: &Txn, record: &LogRecord) -> Result<Lsn> {
+        let lsn = self.log.append(record)?;
+        let len = self.log.end_lsn().to_u64() - lsn.to_u64();
+        txn.wal_bytes.set(txn.wal_bytes.get() + len);
+        Ok(lsn)
+    }
+
+    // トランザクションが書き換えたページのうち、最後にログに書いてから変わったものを書く
+    fn log_pages(&mut self, txn: &Txn) -> Result<()> {
+        let mut page_ids: Vec<_> = txn.pages.keys().copied().collect();
+        page_ids.sort();
+        for page_id in page_ids {
+            let (buffer, before) = &txn.pages[&page_id];
+            let page = buffer.page.borrow();
+            let record = match self.logged_images.get(&page_id) {
+                Some(last) => match page_delta(last, &page[..]) {
+                    Some((offset, data)) => LogRecord::PageDelta {
+                        txn_id: txn.txn_id,
+                        page_id,
+                        offset: offset as u32,
+                        data: data.to_vec(),
+                    },
+                    None => continue,
+                },
+                None if page[..] == before[..] => continue,
+                None => LogRecord::PageImage {
+                    txn_id: txn.txn_id,
+                    page_id,
+                    image: page.to_vec(),
+                },
+            };
+            self.append(txn, &record)?;
+            self.logged_images.insert(page_id, page.to_vec());
+        }
+        Ok(())
+    }
+
+    // 捨てられたトランザクションの挿入を取り消してアボートする
+    // bufmgr を渡すメソッド (insert, mark_deleted, vacuum, rollback) でも取り消す
+    pub fn undo_abandoned<T: BufferPoolManager>(&mut self, bufmgr: &mut T) -> Result<()> {
+        self.reap()?;
+        while let Some(abandoned) = self.undo_pending.pop() {
+            let mut txn = self.new_txn(abandoned.txn_id, self.snapshot_for(abandoned.txn_id));
+            txn.pages = abandoned.pages;
+            txn.undo = abandoned.undo;
+            txn.undo_mode = Some(UndoMode::Logical);
+            self.rollback(txn, bufmgr)?;
+        }
+        Ok(())
+    }
+
+    // btree に挿入して、取り消すための Insert のレコードを書く
+    // こうして挿入したトランザクションのロールバックはページを元に戻すのではなくキーを消すので、
+    // 他のトランザクションが同じ B-tree の別の行を書き換えていても巻き込まない
+    // bind や mark_deleted で書き換えたトランザクションでは使えない (Error::MixedUndo を返す)
+    // 他のトランザクションが同じキーを書いていれば Error::LockWait を返す
+    pub fn insert<T: BufferPoolManager>(
+        &mut self,
+        txn: &mut Txn,
+        bufmgr: &mut T,
+        btree: &BTree,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<()> {
+        self.undo_abandoned(bufmgr)?;
+        txn.set_undo_mode(UndoMode::Logical)?;
+        let tree = btree.meta_page_id;
+        self.record_write(txn, tree, key)?;
+        btree.insert(&mut txn.wrap(bufmgr), key, value)?;
+        let lsn = self.append(
+            txn,
+            &LogRecord::Insert {
+                txn_id: txn.txn_id,
+                tree,
+                key: key.to_vec(),
+                value: value.to_vec(),
+            },
+        )?;
+        txn.undo.push((lsn, tree, key.to_vec()));
+        txn.rows_written.set(txn.rows_written.get() + 1);
+        Ok(())
+    }
+
+    // テーブルに行を挿入する (insert と同じくキーを消して取り消す)
+    // ログに残るので ChangeStream で取り出せる
+    pub fn insert_row<T: BufferPoolManager>(
+        &mut self,
+        txn: &mut Txn,
+        bufmgr: &mut T,
+        table: &SimpleTable,
+        record: &[&[u8]],
+    ) -> Result<()> {
+        let (key, value) = table.encode(txn.txn_id, record);
+        self.insert(txn, bufmgr, &BTree::new(table.meta_page_id), &key, &value)
+    }
+
+    // 変更の取り出しを始める
+    // bind で書いた変更は取り出せない (ChangeStream を参照)
+    // 活動中のトランザクションの変更も取り出せるように最初のレコードから読み、
+    // 今までにコミットしたトランザクションの変更は飛ばす
+    pub fn subscribe(&mut self) -> ChangeStream {
+        self.release_abandoned();
+        let end_lsn = self.log.end_lsn();
+        let start_lsn = self.active.values().copied().fold(end_lsn, std::cmp::min);
+        ChangeStream::new(start_lsn, end_lsn)
+    }
+
+    // txn の挿入を新しいものから一つずつ取り消す
+    // 取り消すたびに書き換えたページと補償レコードを書くので、途中でクラッシュしても続きから取り消せる
+    // 途中で失敗すれば、取り消していない挿入は txn に残る
+    // 既に消えているキーは取り消し済みとみなす
+    fn undo<T: BufferPoolManager>(&mut self, txn: &mut Txn, bufmgr: &mut T) -> Result<()> {
+        while let Some((lsn, tree, key)) = txn.undo.last().cloned() {
+            match BTree::new(tree).delete(&mut txn.wrap(bufmgr), &key) {
+                Ok(()) | Err(method::Error::KeyNotFound) => {}
+                Err(err) => return Err(err.into()),
+            }
+            self.log_pages(txn)?;
+            self.append(
+                txn,
+                &LogRecord::Compensation {
+                    txn_id: txn.txn_id,
+                    undone: lsn,
+                },
+            )?;
+            txn.undo.pop();
+        }
+        Ok(())
+    }
+
+    // 行に削除の印 (xmax) を付ける
+    // 印を付けた行は vacuum で消すまで残り、削除がコミットする前のスナップショットからは見え続ける
+    // 他のトランザクションが先に印を付けていれば mvcc::Error::AlreadyDeleted を、
+    // 印を付けてまだ終わっていなければ Error::LockWait を返す
+    // 印はページごと元に戻して取り消すので、insert したトランザクションでは使えない
+    // (Error::MixedUndo を返す)
+    pub fn mark_deleted<T: BufferPoolManager>(
+        &mut self,
+        txn: &mut Txn,
+        bufmgr: &mut T,
+        btree: &BTree,
+        key: &[u8],
+    ) -> Result<()> {
+        self.undo_abandoned(bufmgr)?;
+        txn.set_undo_mode(UndoMode::Physical)?;
+        let txn_id = txn.txn_id;
+        self.record_write(txn, btree.meta_page_id, key)?;
+        let mut bufmgr = txn.wrap(bufmgr);
+        let value = {
+            let mut iter = btree.search(&mut bufmgr, SearchMode::Key(key.to_vec()))?;
+            match iter.next(&mut bufmgr)? {
+                Some((found, value)) if found == key => value,
+                _ => return Err(method::Error::KeyNotFound.into()),
+            }
+        };
+        let (mut header, body) = TupleHeader::decode(&value)?;
+        if header.xmax != TxnId::INVALID_TXN_ID {
+            return Err(mvcc::Error::AlreadyDeleted(header.xmax).into());
+        }
+        header.xmax = txn_id;
+        let mut new_value = vec![];
+        header.encode(&mut new_value);
+        new_value.extend_from_slice(body);
+        btree.delete(&mut bufmgr, key)?;
+        btree.insert(&mut bufmgr, key, &new_value)?;
+        self.append(
+            txn,
+            &LogRecord::Delete {
+                txn_id,
+                tree: btree.meta_page_id,
+                key: key.to_vec(),
+            },
+        )?;
+        txn.rows_written.set(txn.rows_written.get() + 1);
+        Ok(())
+    }
+
+    // これより前の ID のトランザクションは、活動中のどのトランザクションから見てもコミットかアボートが済んでいる
+    // (トランザクションの外で取ったスナップショットは数えない)
+    pub fn vacuum_horizon(&mut self) -> TxnId {
+        self.release_abandoned();
+        self.xmins
+            .values()
+            .copied()
+            .min()
+            .unwrap_or_else(|| self.next_txn_id())
+    }
+
+    // どのスナップショットからも見えなくなった古い版を消し、table のインデックスからもその項目を消す
+    // 値の先頭に TupleHeader のある B-tree (テーブル) に使う
+    // それから、テーブルとインデックスの木の葉を隣の葉に収まるなら寄せて (空になった葉も含む)
+    // 木から外し、ストレージに返す
+    // 削除や併合はいくつかずつのトランザクションで行い、ログに書いてから次に進む
+    // 同じ木を書き換えるトランザクションと同時に走らせてはいけない
+    pub fn vacuum<T: BufferPoolManager>(
+        &mut self,
+        bufmgr: &mut T,
+        table: &Table,
+    ) -> Result<VacuumStats> {
+        self.undo_abandoned(bufmgr)?;
+        let horizon = self.vacuum_horizon();
+        let btree = BTree::new(table.meta_page_id);
+        let mut dead_rows = vec![];
+        let mut iter = btree.search(bufmgr, SearchMode::Start)?;
+        while let Some((key, value)) = iter.next(bufmgr)? {
+            let (header, _) = TupleHeader::decode(&value)?;
+            if header.xmax != TxnId::INVALID_TXN_ID && header.xmax < horizon {
+                dead_rows.push((key, value));
+            }
+        }
+        drop(iter);
+        for rows in dead_rows.chunks(VACUUM_BATCH_SIZE) {
+            let mut txn = self.begin()?;
+            for (key, value) in rows {
+                let mut bufmgr = txn.bind(bufmgr)?;
+                btree.delete(&mut bufmgr, key)?;
+                table.delete_index_entries(&mut bufmgr, key, value)?;
+            }
+            self.commit(txn)?;
+        }
+
+        let trees = std::iter::once(table.meta_page_id)
+            .chain(table.unique_indices.iter().map(|index| index.meta_page_id))
+            .chain(
+                table
+                    .non_unique_indices
+                    .iter()
+                    .map(|index| index.meta_page_id),
+            );
+        let mut freed_pages = vec![];
+        for meta_page_id in trees {
+            let btree = BTree::new(meta_page_id);
+            let leaves = btree.leaves(bufmgr)?;
+            for leaves in leaves.chunks(VACUUM_MERGE_BATCH_SIZE) {
+                let mut txn = self.begin()?;
+                for &(parent_page_id, leaf_page_id) in leaves {
+                    if btree.merge_leaf(&mut txn.bind(bufmgr)?, parent_page_id, leaf_page_id)? {
+                        freed_pages.push(leaf_page_id);
+                    }
+                }
+                self.commit(txn)?;
+            }
+        }
+        // 木から外したことが永続化してからページを返す
+        self.flush_commits()?;
+        for &page_id in &freed_pages {
+            bufmgr.free_page(page_id)?;
+        }
+        Ok(VacuumStats {
+            num_removed: dead_rows.len(),
+            freed_pages,
+        })
+    }
+
+    // 書き換えを取り消す
+    // insert で挿入したものはキーを消して取り消し、そうでなければページごと元に戻す
+    // 取り消しに失敗したときは捨てたトランザクションとして残り、undo_abandoned で続きを取り消す
+    pub fn rollback<T: BufferPoolManager>(&mut self, mut txn: Txn, bufmgr: &mut T) -> Result<()> {
+        self.undo_abandoned(bufmgr)?;
+        let logical = txn.undo_mode == Some(UndoMode::Logical);
+        if logical {
+            self.undo(&mut txn, bufmgr)?;
+        } else {
+            txn.restore();
+        }
+        txn.finished = true;
+        self.finish(txn.txn_id);
+        self.ssi.abort(txn.txn_id);
+        self.log.append(&LogRecord::Abort { txn_id: txn.txn_id })?;
+        // キーを消したページは元の内容と違うので、ページより先にログを書き出しておく
+        if logical {
+            self.log.flush()?;
+        }
+        txn.run_hooks(false);
+        Ok(())
+    }
+
+    // リカバリの後で、コミットもアボートもしていないトランザクションの挿入を取り消す
+    // 補償レコードのある挿入は取り消し済みなので飛ばす
+    // 取り消した挿入の数を返す
+    pub fn undo_incomplete<T: BufferPoolManager>(&mut self, bufmgr: &mut T) -> Result<usize> {
+        self.undo_abandoned(bufmgr)?;
+        let start_lsn = self.log.start_lsn();
+        let records = self.log.read_from(start_lsn)?;
+        let mut finished = HashSet::new();
+        let mut compensated = HashSet::new();
+        let mut losers: BTreeMap<TxnId, Vec<(Lsn, PageId, Vec<u8>)>> = BTreeMap::new();
+        for (lsn, record) in records {
+            match record {
+                LogRecord::Commit { txn_id, .. } | LogRecord::Abort { txn_id } => {
+                    finished.insert(txn_id);
+                }
+                LogRecord::Compensation { undone, .. } => {
+                    compensated.insert(undone);
+                }
+                LogRecord::Insert {
+                    txn_id, tree, key, ..
+                } => {
+                    losers.entry(txn_id).or_default().push((lsn, tree, key));
+                }
+                _ => {}
+            }
+        }
+        let mut num_undone = 0;
+        for (txn_id, inserts) in losers {
+            if finished.contains(&txn_id) {
+                continue;
+            }
+            let inserts: Vec<_> = inserts
+                .into_iter()
+                .filter(|(lsn, _, _)| !compensated.contains(lsn))
+                .collect();
+            num_undone += inserts.len();
+            let mut txn = self.new_txn(txn_id, self.snapshot_for(txn_id));
+            txn.undo = inserts;
+            txn.undo_mode = Some(UndoMode::Logical);
+            self.undo(&mut txn, bufmgr)?;
+            txn.finished = true;
+            self.log.append(&LogRecord::Abort { txn_id })?;
+        }
+        self.log.flush()?;
+        Ok(num_undone)
+    }
+}
+
+// last から page への書き換えを含む範囲の位置と、page でのその内容
+fn page_delta<'a>(last: &[u8], page: &'a [u8]) -> Option<(usize, &'a [u8])> {
+    let start = last.iter().zip(page).position(|(a, b)| a != b)?;
+    let end = last.iter().zip(page).rposition(|(a, b)| a != b).unwrap() + 1;
+    Some((start, &page[start..end]))
+}
+
+// 実行中のトランザクション
+// 触ったページはコミットかロールバックまで pin しておくので、
+// コミット前の内容が追い出されたりチェックポイントで書き出されたりすることはない
+// (その分、一つのトランザクションで触れるページはバッファプールの大きさまでになる)
+// bind で書き換えたトランザクションのロールバックはページごと元に戻すので、
+// 同じ B-tree を書き換えるトランザクションは TransactionManager::lock で木のロックを取って一つずつ走らせる
+// コミットもロールバックもせずに捨てると書き換えは元に戻り、
+// TransactionManager が次に呼ばれたときにアボートしてロックを外す
+// (TransactionManager::insert で挿入したものは、次に bufmgr を渡されたときにキーを消して取り消す)
+pub struct Txn {
+    txn_id: TxnId,
+    // 開始した時点のスナップショット
+    snapshot: TxnSnapshot,
+    // 触ったページと、触る前の内容
+    pages: HashMap<PageId, (Rc<Buffer>, Snapshot)>,
+    // insert で挿入したものの Insert のレコードの LSN と B-tree とキー
+    undo: Vec<(Lsn, PageId, Vec<u8>)>,
+    // 最初に書き換えたときに決まる
+    undo_mode: Option<UndoMode>,
+    on_commit: Vec<Hook>,
+    on_abort: Vec<Hook>,
+    // 指定しなければ TransactionManager の設定に従う
+    durability: Option<Durability>,
+    rows_written: Cell<u64>,
+    wal_bytes: Cell<u64>,
+    // lock で要求した対象
+    locks: RefCell<HashSet<LockTarget>>,
+    finished: bool,
+    // 捨てたときに積む TransactionManager の列
+    abandoned: Rc<RefCell<Vec<Abandoned>>>,
+}
+
+// コミットやアボートの後に呼ぶ関数
+pub type Hook = Box<dyn FnOnce()>;
+
+impl Txn {
+    pub fn id(&self) -> TxnId {
+        self.txn_id
+    }
+
+    // SeqScan や IndexScan の visibility に渡す
+    pub fn snapshot(&self) -> &TxnSnapshot {
+        &self.snapshot
+    }
+
+    // 返したラッパーを bufmgr の代わりに Table::insert や executor に渡す
+    // 使い終われば他のトランザクションや読み出しで bufmgr を使える
+    // ロールバックはページごと元に戻すことになるので、TransactionManager::insert で挿入した
+    // トランザクションでは使えない (Error::MixedUndo を返す)
+    // 書き換えはページの内容しかログに残らないので、ChangeStream では取り出せない
+    pub fn bind<'a, T: BufferPoolManager>(
+        &'a mut self,
+        bufmgr: &'a mut T,
+    ) -> Result<TxnBufferManager<'a, T>, Error> {
+        self.set_undo_mode(UndoMode::Physical)?;
+        Ok(self.wrap(bufmgr))
+    }
+
+    // 取り消し方を確かめずに bufmgr を包む
+    fn wrap<'a, T: BufferPoolManager>(&'a mut self, bufmgr: &'a mut T) -> TxnBufferManager<'a, T> {
+        TxnBufferManager { txn: self, bufmgr }
+    }
+
+    fn set_undo_mode(&mut self, mode: UndoMode) -> Result<(), Error> {
+        match self.undo_mode {
+            Some(current) if current != mode => Err(Error::MixedUndo {
+                txn_id: self.txn_id,
+                mode: current,
+            }),
+            _ => {
+                self.undo_mode = Some(mode);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn stats(&self) -> TxnStats {
+        let pages_dirtied = self
+            .pages
+            .values()
+            .filter(|(buffer, before)| buffer.page.borrow()[..] != before[..])
+            .count();
+        TxnStats {
+            rows_read: self.snapshot.rows_read(),
+            rows_written: self.rows_written.get(),
+            pages_dirtied,
+            wal_bytes: self.wal_bytes.get(),
+            locks_held: self.locks.borrow().len(),
+        }
+    }
+
+    // このトランザクションのコミットの永続性を決める
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = Some(durability);
+    }
+
+    // コミットが永続化した後に呼ぶ関数を登録する (アプリケーションのキャッシュの無効化など)
+    // 永続化に失敗したときは呼ばない
+    pub fn on_commit(&mut self, hook: impl FnOnce() + 'static) {
+        self.on_commit.push(Box::new(hook));
+    }
+
+    // ロールバックした後か、コミットもロールバックもせずに捨てたときに呼ぶ関数を登録する
+    // コミットの永続化に失敗したときもこちらを呼ぶ
+    pub fn on_abort(&mut self, hook: impl FnOnce() + 'static) {
+        self.on_abort.push(Box::new(hook));
+    }
+
+    // 登録した順に呼び、もう一方は捨てる
+    fn run_hooks(&mut self, committed: bool) {
+        let on_commit = std::mem::take(&mut self.on_commit);
+        let on_abort = std::mem::take(&mut self.on_abort);
+        let hooks = if committed { on_commit } else { on_abort };
+        for hook in hooks {
+            hook();
+        }
+    }
+
+    fn track(&mut self, buffer: &Rc<Buffer>) {
+        self.pages
+            .entry(buffer.page_id)
+            .or_insert_with(|| (Rc::clone(buffer), buffer.freeze()));
+    }
+
+    fn restore(&mut self) {
+        for (buffer, before) in self.pages.values() {
+            let mut page = buffer.page.borrow_mut();
+            if page[..] != before[..] {
+                page.copy_from_slice(&before[..]);
+                buffer.is_dirty.set(true);
+            }
+        }
+        self.pages.clear();
+    }
+}
+
+impl Drop for Txn {
+    fn drop(&mut self) {
+        if !self.finished {
+            // 挿入したキーは bufmgr がないと消せないので、ページを pin したまま TransactionManager に任せる
+            if self.undo_mode != Some(UndoMode::Logical) {
+                self.restore();
+            }
+            self.run_hooks(false);
+            self.abandoned.borrow_mut().push(Abandoned {
+                txn_id: self.txn_id,
+                pages: std::mem::take(&mut self.pages),
+                undo: std::mem::take(&mut self.undo),
+            });
+        }
+    }
+}
+
+// トランザクションの中でページを読み書きする buffermanager のラッパー
+pub struct TxnBufferManager<'a, T: BufferPoolManager> {
+    txn: &'a mut Txn,
+    bufmgr: &'a mut T,
+}
+
+impl<'a, T: BufferPoolManager> BufferPoolManager for TxnBufferManager<'a, T> {
+    fn fetch_page(&mut self, page_id: PageId) -> Result<Rc<Buffer>, manager::Error> {
+        let buffer = self.bufmgr.fetch_page(page_id)?;
+        self.txn.track(&buffer);
+        Ok(buffer)
+    }
+
+    fn create_page(&mut self) -> Result<Rc<Buffer>, manager::Error> {
+        let buffer = self.bufmgr.create_page()?;
+        self.txn.track(&buffer);
+        Ok(buffer)
+    }
+
+    // コミット前の内容を書き出さないように何もしない (コミットで永続化する)
+    fn flush(&mut self) -> Result<(), manager::Error> {
+        Ok(())
+    }
+
+    fn checkpoint(&mut self) -> Result<Checkpoint, manager::Error> {
+        Ok(Checkpoint::default())
+    }
+
+    fn discard_page(&mut self, page_id: PageId) -> Result<(), manager::Error> {
+        self.bufmgr.discard_page(page_id)
+    }
+
+    fn fetch_count(&self) -> u64 {
+        self.bufmgr.fetch_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accessor::{
+        entity::SearchMode,
+        method::{AccessMethod, Iterable},
+    };
+    use crate::buffer::entity::PAGE_SIZE;
+    use crate::rdbms::{
+        btree::BTree,
+        checkpoint::recover,
+        clocksweep::ClockSweepManager,
+        disk::DiskManager,
+        expr::Expr,
+        memory::MemoryManager,
+        query::{SeqScan, TupleSearchMode},
+        table::{NonUniqueIndex, SimpleTable, UniqueIndex},
+        util::tuple,
+        wal::FileLogManager,
+    };
+    use crate::sql::{ddl::table::Table as ITable, dml::query::PlanNode};
+    use std::cell::RefCell;
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+    use std::ops::Bound;
+    use std::sync::Arc;
+    use tempfile::{tempdir, NamedTempFile};
+
+    fn count_rows<T: BufferPoolManager>(bufmgr: &mut T, btree: &BTree) -> usize {
+        let mut iter = btree.search(bufmgr, SearchMode::Start).unwrap();
+        let mut count = 0;
+        while iter.next(bufmgr).unwrap().is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    // 一列の主キーを B-tree のキーにする
+    fn tuple_key(elem: &[u8]) -> Vec<u8> {
+        let mut key = vec![];
+        tuple::encode([elem].iter(), &mut key);
+        key
+    }
+
+    // visibility から見える行の数
+    fn count_visible<T: BufferPoolManager>(
+        bufmgr: &mut T,
+        btree: &BTree,
+        snapshot: &TxnSnapshot,
+    ) -> usize {
+        let plan = SeqScan {
+            table_accessor: Arc::new(btree.clone()),
+            search_mode: TupleSearchMode::Start,
+            num_key_elems: 1,
+            stop_key: Bound::Unbounded,
+            while_cond: Expr::TRUE,
+            visibility: Arc::new(snapshot.clone()),
+            schema: vec![],
+        };
+        let mut exec = plan.start(bufmgr).unwrap();
+        let mut count = 0;
+        while exec.next(bufmgr).unwrap().is_some() {
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn txn_test() {
+        let dir = tempdir().unwrap();
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file, PAGE_SIZE).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 10).unwrap();
+        let mut table = SimpleTable {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+        };
+        table.create(&mut bufmgr).unwrap();
+        bufmgr.flush().unwrap();
+        let btree = BTree::new(table.meta_page_id);
+
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        let mut txn = txn_mgr.begin().unwrap();
+        let txn_id = txn.id();
+        table
+            .insert_as(
+                &mut txn.bind(&mut bufmgr).unwrap(),
+                txn_id,
+                &[b"z", b"Alice"],
+            )
+            .unwrap();
+        table
+            .insert_as(&mut txn.bind(&mut bufmgr).unwrap(), txn_id, &[b"x", b"Bob"])
+            .unwrap();
+        assert_eq!(1, txn_mgr.active_txns().len());
+        // コミットするまで他からは見えない
+        assert_eq!(0, count_visible(&mut bufmgr, &btree, &txn_mgr.snapshot()));
+        let own = txn.snapshot().clone();
+        assert_eq!(
+            2,
+            count_visible(&mut txn.bind(&mut bufmgr).unwrap(), &btree, &own)
+        );
+        txn_mgr.commit(txn).unwrap();
+        assert!(txn_mgr.active_txns().is_empty());
+        assert_eq!(2, count_visible(&mut bufmgr, &btree, &txn_mgr.snapshot()));
+
+        // ロールバックした挿入は残らない
+        let mut txn = txn_mgr.begin().unwrap();
+        let txn_id = txn.id();
+        table
+            .insert_as(
+                &mut txn.bind(&mut bufmgr).unwrap(),
+                txn_id,
+                &[b"y", b"Charlie"],
+            )
+            .unwrap();
+        assert_eq!(3, count_rows(&mut txn.bind(&mut bufmgr).unwrap(), &btree));
+        txn_mgr.rollback(txn, &mut bufmgr).unwrap();
+        assert_eq!(2, count_rows(&mut bufmgr, &btree));
+
+        // コミットもロールバックもしなければ元に戻る
+        {
+            let mut txn = txn_mgr.begin().unwrap();
+            let txn_id = txn.id();
+            table
+                .insert_as(
+                    &mut txn.bind(&mut bufmgr).unwrap(),
+                    txn_id,
+                    &[b"w", b"Dave"],
+                )
+                .unwrap();
+        }
+        assert_eq!(2, count_rows(&mut bufmgr, &btree));
+
+        // ダーティページを書き出さずにクラッシュしても、コミットした挿入はログから戻せる
+        drop(bufmgr);
+        drop(txn_mgr);
+        let mut disk = DiskManager::open(&data_file_path, PAGE_SIZE).unwrap();
+        let mut log = FileLogManager::open(dir.path()).unwrap();
+        recover(&mut disk, &mut log).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 10).unwrap();
+        assert_eq!(2, count_rows(&mut bufmgr, &btree));
+
+        // 開き直してもログに残っているトランザクションと ID は重ならない
+        // (書き出されなかったロールバックの ID は使い回してよい)
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        let txn = txn_mgr.begin().unwrap();
+        assert_eq!(TxnId(2), txn.id());
+    }
+
+    #[test]
+    fn snapshot_test() {
+        let dir = tempdir().unwrap();
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut table = SimpleTable {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+        };
+        table.create(&mut bufmgr).unwrap();
+        table.insert(&mut bufmgr, &[b"a", b"Alice"]).unwrap();
+        let btree = BTree::new(table.meta_page_id);
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+
+        // reader の開始時点で実行中だった writer と、後から始まった writer
+        // 同じ木を書き換えるので、後の writer は先の writer が終わるまで待つ
+        let tree = LockTarget::Page(table.meta_page_id);
+        let mut before = txn_mgr.begin().unwrap();
+        let status = txn_mgr.lock(&before, tree.clone(), LockMode::Exclusive);
+        assert_eq!(LockStatus::Granted, status);
+        let txn_id = before.id();
+        table
+            .insert_as(
+                &mut before.bind(&mut bufmgr).unwrap(),
+                txn_id,
+                &[b"b", b"Bob"],
+            )
+            .unwrap();
+        let reader = txn_mgr.begin().unwrap();
+        let mut after = txn_mgr.begin().unwrap();
+        let status = txn_mgr.lock(&after, tree.clone(), LockMode::Exclusive);
+        assert_eq!(LockStatus::Waiting, status);
+        txn_mgr.commit(before).unwrap();
+        assert!(txn_mgr.is_granted(&after, &tree));
+        let txn_id = after.id();
+        table
+            .insert_as(
+                &mut after.bind(&mut bufmgr).unwrap(),
+                txn_id,
+                &[b"c", b"Charlie"],
+            )
+            .unwrap();
+        txn_mgr.commit(after).unwrap();
+
+        // どちらも reader の開始時点ではコミットしていなかったので見えない
+        assert_eq!(1, count_visible(&mut bufmgr, &btree, reader.snapshot()));
+        txn_mgr.commit(reader).unwrap();
+        let txn = txn_mgr.begin().unwrap();
+        assert_eq!(3, count_visible(&mut bufmgr, &btree, txn.snapshot()));
+    }
+
+    #[test]
+    fn txn_stats_test() {
+        let dir = tempdir().unwrap();
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut table = SimpleTable {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+        };
+        table.create(&mut bufmgr).unwrap();
+        table.insert(&mut bufmgr, &[b"a", b"Alice"]).unwrap();
+        let btree = BTree::new(table.meta_page_id);
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+
+        let mut txn = txn_mgr.begin().unwrap();
+        let begin_bytes = txn.stats().wal_bytes;
+        assert!(begin_bytes > 0);
+        let tree = LockTarget::Page(table.meta_page_id);
+        txn_mgr.lock(&txn, tree, LockMode::Exclusive);
+        for record in [[&b"b"[..], b"Bob"], [b"c", b"Charlie"]] {
+            txn_mgr
+                .insert_row(&mut txn, &mut bufmgr, &table, &record)
+                .unwrap();
+        }
+        // insert で挿入したトランザクションは bind せずに読む
+        assert_eq!(3, count_visible(&mut bufmgr, &btree, txn.snapshot()));
+        let stats = txn.stats();
+        assert_eq!(3, stats.rows_read);
+        assert_eq!(2, stats.rows_written);
+        assert_eq!(1, stats.pages_dirtied);
+        assert!(stats.wal_bytes > begin_bytes);
+        // 木のロックと、書いた二つの行のロック
+        assert_eq!(3, stats.locks_held);
+        txn_mgr.commit(txn).unwrap();
+    }
+
+    #[test]
+    fn group_commit_test() {
+        let dir = tempdir().unwrap();
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut table = SimpleTable {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+        };
+        table.create(&mut bufmgr).unwrap();
+        let btree = BTree::new(table.meta_page_id);
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        txn_mgr.set_commit_window(Duration::from_secs(3600));
+
+        let fired = Rc::new(RefCell::new(vec![]));
+        let mut txn_ids = vec![];
+        for key in [b"a", b"b"] {
+            let mut txn = txn_mgr.begin().unwrap();
+            let txn_id = txn.id();
+            table
+                .insert_as(
+                    &mut txn.bind(&mut bufmgr).unwrap(),
+                    txn_id,
+                    &[key, b"Alice"],
+                )
+                .unwrap();
+            let (on_commit, on_abort) = (Rc::clone(&fired), Rc::clone(&fired));
+            txn.on_commit(move || on_commit.borrow_mut().push((txn_id, true)));
+            txn.on_abort(move || on_abort.borrow_mut().push((txn_id, false)));
+            txn_mgr.commit(txn).unwrap();
+            txn_ids.push(txn_id);
+        }
+        // 書き出すまではどちらも見えない
+        assert_eq!(txn_ids, txn_mgr.pending_commits());
+        assert!(txn_mgr.log().flushed_lsn() < txn_mgr.log().end_lsn());
+        assert_eq!(0, count_visible(&mut bufmgr, &btree, &txn_mgr.snapshot()));
+        assert!(txn_mgr.poll_commits().unwrap().is_empty());
+        assert!(fired.borrow().is_empty());
+
+        // まとめて一度に書き出し、永続化してからフックを呼ぶ
+        assert_eq!(txn_ids, txn_mgr.flush_commits().unwrap());
+        let expected: Vec<_> = txn_ids.iter().map(|&txn_id| (txn_id, true)).collect();
+        assert_eq!(expected, *fired.borrow());
+        assert_eq!(2, count_visible(&mut bufmgr, &btree, &txn_mgr.snapshot()));
+        let stats = txn_mgr.commit_stats();
+        assert_eq!(2, stats.num_commits);
+        assert_eq!(1, stats.num_flushes);
+        assert!(stats.max_latency <= stats.total_latency);
+
+        // 待たなければコミットごとに書き出す
+        txn_mgr.set_commit_window(Duration::ZERO);
+        let txn = txn_mgr.begin().unwrap();
+        txn_mgr.commit(txn).unwrap();
+        assert!(txn_mgr.pending_commits().is_empty());
+        assert_eq!(2, txn_mgr.commit_stats().num_flushes);
+
+        // ロールバックすればアボートのフックだけを呼ぶ
+        fired.borrow_mut().clear();
+        let mut txn = txn_mgr.begin().unwrap();
+        let txn_id = txn.id();
+        let (on_commit, on_abort) = (Rc::clone(&fired), Rc::clone(&fired));
+        txn.on_commit(move || on_commit.borrow_mut().push((txn_id, true)));
+        txn.on_abort(move || on_abort.borrow_mut().push((txn_id, false)));
+        txn_mgr.rollback(txn, &mut bufmgr).unwrap();
+        assert_eq!(vec![(txn_id, false)], *fired.borrow());
+    }
+
+    #[test]
+    fn async_commit_test() {
+        let dir = tempdir().unwrap();
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file, PAGE_SIZE).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 10).unwrap();
+        let mut table = SimpleTable {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+        };
+        table.create(&mut bufmgr).unwrap();
+        bufmgr.flush().unwrap();
+        let btree = BTree::new(table.meta_page_id);
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        txn_mgr.set_durability(Durability::Async);
+        txn_mgr.set_max_data_loss(Duration::from_secs(3600));
+        let tree = LockTarget::Page(table.meta_page_id);
+        let fired = Rc::new(RefCell::new(vec![]));
+
+        // 書き出す前にコミット済みになり、ロックも外れる
+        let mut txn = txn_mgr.begin().unwrap();
+        let txn_id = txn.id();
+        txn_mgr.lock(&txn, tree.clone(), LockMode::Exclusive);
+        table
+            .insert_as(
+                &mut txn.bind(&mut bufmgr).unwrap(),
+                txn_id,
+                &[b"a", b"Alice"],
+            )
+            .unwrap();
+        let on_commit = Rc::clone(&fired);
+        txn.on_commit(move || on_commit.borrow_mut().push(txn_id));
+        txn_mgr.commit(txn).unwrap();
+        assert_eq!(vec![txn_id], txn_mgr.unflushed_commits());
+        assert!(txn_mgr.log().flushed_lsn() < txn_mgr.log().end_lsn());
+        assert_eq!(1, count_visible(&mut bufmgr, &btree, &txn_mgr.snapshot()));
+        let txn = txn_mgr.begin().unwrap();
+        assert_eq!(
+            LockStatus::Granted,
+            txn_mgr.lock(&txn, tree, LockMode::Exclusive)
+        );
+        assert!(txn_mgr.poll_commits().unwrap().is_empty());
+        assert!(fired.borrow().is_empty());
+
+        // 同期コミットのトランザクションがあればまとめて書き出す
+        let mut txn = txn;
+        txn.set_durability(Durability::Sync);
+        let sync_txn_id = txn.id();
+        table
+            .insert_as(
+                &mut txn.bind(&mut bufmgr).unwrap(),
+                sync_txn_id,
+                &[b"b", b"Bob"],
+            )
+            .unwrap();
+        txn_mgr.commit(txn).unwrap();
+        assert!(txn_mgr.unflushed_commits().is_empty());
+        assert_eq!(txn_mgr.log().flushed_lsn(), txn_mgr.log().end_lsn());
+        assert_eq!(vec![txn_id], *fired.borrow());
+
+        // 書き出す前にクラッシュすれば非同期コミットは失われる
+        let mut txn = txn_mgr.begin().unwrap();
+        let txn_id = txn.id();
+        table
+            .insert_as(
+                &mut txn.bind(&mut bufmgr).unwrap(),
+                txn_id,
+                &[b"c", b"Charlie"],
+            )
+            .unwrap();
+        txn_mgr.commit(txn).unwrap();
+        assert_eq!(3, count_visible(&mut bufmgr, &btree, &txn_mgr.snapshot()));
+        drop(bufmgr);
+        drop(txn_mgr);
+        let mut disk = DiskManager::open(&data_file_path, PAGE_SIZE).unwrap();
+        let mut log = FileLogManager::open(dir.path()).unwrap();
+        recover(&mut disk, &mut log).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 10).unwrap();
+        assert_eq!(2, count_rows(&mut bufmgr, &btree));
+    }
+
+    #[test]
+    fn full_page_image_test() {
+        let dir = tempdir().unwrap();
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file, PAGE_SIZE).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 10).unwrap();
+        let mut table = SimpleTable {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+        };
+        table.create(&mut bufmgr).unwrap();
+        bufmgr.flush().unwrap();
+        let btree = BTree::new(table.meta_page_id);
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        let insert = |txn_mgr: &mut TransactionManager<_>, bufmgr: &mut _, key| {
+            let mut txn = txn_mgr.begin().unwrap();
+            let txn_id = txn.id();
+            table
+                .insert_as(&mut txn.bind(bufmgr).unwrap(), txn_id, &[key, b"Alice"])
+                .unwrap();
+            txn_mgr.commit(txn).unwrap();
+        };
+        // 最後に書いたページのレコード
+        let last_page_record = |txn_mgr: &mut TransactionManager<FileLogManager>| {
+            let start_lsn = txn_mgr.log().start_lsn();
+            txn_mgr
+                .log_mut()
+                .read_from(start_lsn)
+                .unwrap()
+                .into_iter()
+                .rev()
+                .find_map(|(_, record)| match record {
+                    LogRecord::PageImage { page_id, .. } => Some((page_id, true)),
+                    LogRecord::PageDelta { page_id, .. } => Some((page_id, false)),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        // 一度目はページ全体、二度目からは差分を書く
+        insert(&mut txn_mgr, &mut bufmgr, b"a");
+        let (leaf_page_id, is_image) = last_page_record(&mut txn_mgr);
+        assert!(is_image);
+        insert(&mut txn_mgr, &mut bufmgr, b"b");
+        assert_eq!((leaf_page_id, false), last_page_record(&mut txn_mgr));
+
+        // チェックポイントの後はまたページ全体を書く
+        let mut checkpointer = Checkpointer::new(0);
+        txn_mgr
+            .begin_checkpoint(&mut checkpointer, &bufmgr)
+            .unwrap();
+        while !checkpointer
+            .step(&mut bufmgr, txn_mgr.log_mut(), 10)
+            .unwrap()
+        {}
+        insert(&mut txn_mgr, &mut bufmgr, b"c");
+        assert_eq!((leaf_page_id, true), last_page_record(&mut txn_mgr));
+
+        // 書き出しが途中で切れてもログから作り直せる
+        bufmgr.flush().unwrap();
+        drop(bufmgr);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&data_file_path)
+            .unwrap();
+        file.seek(SeekFrom::Start(
+            PAGE_SIZE as u64 * leaf_page_id.to_u64() + PAGE_SIZE as u64 / 2,
+        ))
+        .unwrap();
+        file.write_all(&[0u8; PAGE_SIZE / 2]).unwrap();
+        drop(file);
+        let mut disk = DiskManager::open(&data_file_path, PAGE_SIZE).unwrap();
+        recover(&mut disk, txn_mgr.log_mut()).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 10).unwrap();
+        assert_eq!(3, count_rows(&mut bufmgr, &btree));
+    }
+
+    #[test]
+    fn undo_test() {
+        let dir = tempdir().unwrap();
+        let (data_file, data_file_path) = NamedTempFile::new().unwrap().into_parts();
+        let disk = DiskManager::new(data_file, PAGE_SIZE).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 10).unwrap();
+        let btree = BTree::create(&mut bufmgr).unwrap();
+        bufmgr.flush().unwrap();
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        let num_compensations = |txn_mgr: &mut TransactionManager<FileLogManager>| {
+            let start_lsn = txn_mgr.log().start_lsn();
+            txn_mgr
+                .log_mut()
+                .read_from(start_lsn)
+                .unwrap()
+                .iter()
+                .filter(|(_, record)| matches!(record, LogRecord::Compensation { .. }))
+                .count()
+        };
+
+        let mut txn = txn_mgr.begin().unwrap();
+        txn_mgr
+            .insert(&mut txn, &mut bufmgr, &btree, b"a", b"Alice")
+            .unwrap();
+        txn_mgr.commit(txn).unwrap();
+
+        // 挿入したキーを新しいものから消し、消すたびに補償レコードを書く
+        let mut txn = txn_mgr.begin().unwrap();
+        for key in [b"b", b"c"] {
+            txn_mgr
+                .insert(&mut txn, &mut bufmgr, &btree, key, b"Bob")
+                .unwrap();
+        }
+        assert_eq!(3, count_rows(&mut bufmgr, &btree));
+        txn_mgr.rollback(txn, &mut bufmgr).unwrap();
+        assert_eq!(1, count_rows(&mut bufmgr, &btree));
+        assert_eq!(2, num_compensations(&mut txn_mgr));
+
+        // 実行中のトランザクションの挿入が、同じページを書き換えた別のトランザクションのコミットでログに載る
+        let mut loser = txn_mgr.begin().unwrap();
+        txn_mgr
+            .insert(&mut loser, &mut bufmgr, &btree, b"d", b"Dave")
+            .unwrap();
+        let mut txn = txn_mgr.begin().unwrap();
+        txn_mgr
+            .insert(&mut txn, &mut bufmgr, &btree, b"f", b"Frank")
+            .unwrap();
+        txn_mgr.commit(txn).unwrap();
+
+        // loser を終えずにクラッシュした
+        std::mem::forget(loser);
+        drop(bufmgr);
+        drop(txn_mgr);
+        let mut disk = DiskManager::open(&data_file_path, PAGE_SIZE).unwrap();
+        let mut log = FileLogManager::open(dir.path()).unwrap();
+        recover(&mut disk, &mut log).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 10).unwrap();
+        assert_eq!(3, count_rows(&mut bufmgr, &btree));
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        assert_eq!(1, txn_mgr.undo_incomplete(&mut bufmgr).unwrap());
+        assert_eq!(2, count_rows(&mut bufmgr, &btree));
+        assert_eq!(3, num_compensations(&mut txn_mgr));
+        // 取り消し終えたトランザクションはもう取り消さない
+        assert_eq!(0, txn_mgr.undo_incomplete(&mut bufmgr).unwrap());
+
+        // 取り消しもログから作り直せる
+        drop(bufmgr);
+        drop(txn_mgr);
+        let mut disk = DiskManager::open(&data_file_path, PAGE_SIZE).unwrap();
+        let mut log = FileLogManager::open(dir.path()).unwrap();
+        recover(&mut disk, &mut log).unwrap();
+        let mut bufmgr = ClockSweepManager::new(disk, 10).unwrap();
+        assert_eq!(2, count_rows(&mut bufmgr, &btree));
+    }
+
+    #[test]
+    fn serializable_test() {
+        let dir = tempdir().unwrap();
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut tables = vec![];
+        for _ in 0..2 {
+            let mut table = SimpleTable {
+                meta_page_id: PageId::INVALID_PAGE_ID,
+                num_key_elems: 1,
+            };
+            table.create(&mut bufmgr).unwrap();
+            tables.push(table);
+        }
+        // 当番の医者は一人は残す (write skew の例)
+        let doctors = BTree::new(tables[0].meta_page_id);
+        for name in [&b"alice"[..], b"bob", b"carol", b"dave"].iter() {
+            tables[0].insert(&mut bufmgr, &[name]).unwrap();
+        }
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        let on_call = LockTarget::Page(doctors.meta_page_id);
+
+        // Snapshot ではどちらも二人いるのを見て一人ずつ外し、二人とも外れる
+        let mut t1 = txn_mgr.begin().unwrap();
+        let mut t2 = txn_mgr.begin().unwrap();
+        for txn in [&t1, &t2].iter() {
+            txn_mgr.record_read(txn, on_call.clone()).unwrap();
+            assert_eq!(4, count_visible(&mut bufmgr, &doctors, txn.snapshot()));
+        }
+        txn_mgr
+            .mark_deleted(&mut t1, &mut bufmgr, &doctors, &tuple_key(b"alice"))
+            .unwrap();
+        txn_mgr
+            .mark_deleted(&mut t2, &mut bufmgr, &doctors, &tuple_key(b"bob"))
+            .unwrap();
+        txn_mgr.commit(t1).unwrap();
+        txn_mgr.commit(t2).unwrap();
+        assert_eq!(2, count_visible(&mut bufmgr, &doctors, &txn_mgr.snapshot()));
+
+        // Serializable では後から書いた方が直列化できなくなる
+        txn_mgr.set_isolation(IsolationLevel::Serializable);
+        let mut t1 = txn_mgr.begin().unwrap();
+        let mut t2 = txn_mgr.begin().unwrap();
+        for txn in [&t1, &t2].iter() {
+            txn_mgr.record_read(txn, on_call.clone()).unwrap();
+            assert_eq!(2, count_visible(&mut bufmgr, &doctors, txn.snapshot()));
+        }
+        txn_mgr
+            .mark_deleted(&mut t1, &mut bufmgr, &doctors, &tuple_key(b"carol"))
+            .unwrap();
+        let err = txn_mgr
+            .mark_deleted(&mut t2, &mut bufmgr, &doctors, &tuple_key(b"dave"))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(mvcc::Error::SerializationFailure(_))
+        ));
+        txn_mgr.rollback(t2, &mut bufmgr).unwrap();
+        txn_mgr.commit(t1).unwrap();
+        assert_eq!(1, count_visible(&mut bufmgr, &doctors, &txn_mgr.snapshot()));
+
+        // t2 が t1 の読んだ x を書き、t3 が t2 の読んだ y を書くと、
+        // 中心になった t2 はコミットできない
+        let x = BTree::new(tables[0].meta_page_id);
+        let y = BTree::new(tables[1].meta_page_id);
+        tables[1].insert(&mut bufmgr, &[b"y"]).unwrap();
+        let x_key = tuple_key(b"dave");
+        let y_key = tuple_key(b"y");
+        let t1 = txn_mgr.begin().unwrap();
+        let mut t2 = txn_mgr.begin().unwrap();
+        let mut t3 = txn_mgr.begin().unwrap();
+        txn_mgr
+            .record_read(&t1, LockTarget::Row(x.meta_page_id, x_key.clone()))
+            .unwrap();
+        txn_mgr
+            .record_read(&t2, LockTarget::Row(y.meta_page_id, y_key.clone()))
+            .unwrap();
+        txn_mgr
+            .mark_deleted(&mut t2, &mut bufmgr, &x, &x_key)
+            .unwrap();
+        txn_mgr
+            .mark_deleted(&mut t3, &mut bufmgr, &y, &y_key)
+            .unwrap();
+        let err = txn_mgr.commit(t2).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(mvcc::Error::SerializationFailure(_))
+        ));
+        txn_mgr.commit(t3).unwrap();
+        txn_mgr.commit(t1).unwrap();
+        assert_eq!(1, count_visible(&mut bufmgr, &x, &txn_mgr.snapshot()));
+        assert_eq!(0, count_visible(&mut bufmgr, &y, &txn_mgr.snapshot()));
+    }
+
+    #[test]
+    fn vacuum_test() {
+        let dir = tempdir().unwrap();
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 32).unwrap();
+        let mut table = Table {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+            unique_indices: vec![UniqueIndex {
+                meta_page_id: PageId::INVALID_PAGE_ID,
+                skey: vec![1],
+            }],
+            non_unique_indices: vec![NonUniqueIndex {
+                meta_page_id: PageId::INVALID_PAGE_ID,
+                skey: vec![2],
+            }],
+            schema: None,
+        };
+        table.create(&mut bufmgr).unwrap();
+        let btree = BTree::new(table.meta_page_id);
+        let keys: Vec<_> = (0..200).map(|i| format!("{:04}", i).into_bytes()).collect();
+        for (i, key) in keys.iter().enumerate() {
+            let name = format!("n{}", i).into_bytes();
+            table
+                .insert(&mut bufmgr, &[key, &name, &[b'x'; 50]])
+                .unwrap();
+        }
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        let num_leaves = btree.leaves(&mut bufmgr).unwrap().len();
+
+        // 削除より前に始まった reader からは見え続ける
+        // 前の 150 行と、残りの一つおきを消す
+        let reader = txn_mgr.begin().unwrap();
+        let mut txn = txn_mgr.begin().unwrap();
+        let deleted = (0..200).filter(|i| i < &150 || i % 2 == 0);
+        for i in deleted.clone() {
+            let key = tuple_key(&keys[i]);
+            txn_mgr
+                .mark_deleted(&mut txn, &mut bufmgr, &btree, &key)
+                .unwrap();
+        }
+        txn_mgr.commit(txn).unwrap();
+        let mut txn = txn_mgr.begin().unwrap();
+        let err = txn_mgr
+            .mark_deleted(&mut txn, &mut bufmgr, &btree, &tuple_key(&keys[0]))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(mvcc::Error::AlreadyDeleted(_))
+        ));
+        txn_mgr.rollback(txn, &mut bufmgr).unwrap();
+        assert_eq!(0, txn_mgr.vacuum(&mut bufmgr, &table).unwrap().num_removed);
+        assert_eq!(200, count_visible(&mut bufmgr, &btree, reader.snapshot()));
+        assert_eq!(25, count_visible(&mut bufmgr, &btree, &txn_mgr.snapshot()));
+
+        // reader が終われば消せる
+        txn_mgr.commit(reader).unwrap();
+        let stats = txn_mgr.vacuum(&mut bufmgr, &table).unwrap();
+        assert_eq!(deleted.count(), stats.num_removed);
+        assert_eq!(25, count_rows(&mut bufmgr, &btree));
+        assert_eq!(25, count_visible(&mut bufmgr, &btree, &txn_mgr.snapshot()));
+        // インデックスの項目も消える
+        let unique = BTree::new(table.unique_indices[0].meta_page_id);
+        let non_unique = BTree::new(table.non_unique_indices[0].meta_page_id);
+        assert_eq!(25, count_rows(&mut bufmgr, &unique));
+        assert_eq!(25, count_rows(&mut bufmgr, &non_unique));
+        // 一つおきに残った行は一つの葉に寄せられる
+        assert!(btree.leaves(&mut bufmgr).unwrap().len() <= 1);
+        assert!(stats.freed_pages.len() >= num_leaves - 1);
+        // 返したページは再利用される
+        let buffer = bufmgr.create_page().unwrap();
+        assert!(stats.freed_pages.contains(&buffer.page_id));
+    }
+
+    #[test]
+    fn abandoned_txn_test() {
+        let dir = tempdir().unwrap();
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let mut table = SimpleTable {
+            meta_page_id: PageId::INVALID_PAGE_ID,
+            num_key_elems: 1,
+        };
+        table.create(&mut bufmgr).unwrap();
+        let btree = BTree::new(table.meta_page_id);
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        let tree = LockTarget::Page(table.meta_page_id);
+
+        // 木のロックを持ったまま捨てる
+        let mut txn = txn_mgr.begin().unwrap();
+        let abandoned_id = txn.id();
+        txn_mgr.lock(&txn, tree.clone(), LockMode::Exclusive);
+        table
+            .insert_as(
+                &mut txn.bind(&mut bufmgr).unwrap(),
+                abandoned_id,
+                &[b"a", b"Alice"],
+            )
+            .unwrap();
+        let waiter = txn_mgr.begin().unwrap();
+        assert_eq!(
+            LockStatus::Waiting,
+            txn_mgr.lock(&waiter, tree.clone(), LockMode::Exclusive)
+        );
+        assert_eq!(abandoned_id, txn_mgr.vacuum_horizon());
+        drop(txn);
+
+        // 活動中から外れてロックも外れ、waiter が終われば vacuum を止めなくなる
+        assert!(txn_mgr.is_granted(&waiter, &tree));
+        let active: Vec<_> = txn_mgr
+            .active_txns()
+            .into_iter()
+            .map(|(txn_id, _)| txn_id)
+            .collect();
+        assert_eq!(vec![waiter.id()], active);
+        assert_eq!(0, count_rows(&mut bufmgr, &btree));
+        txn_mgr.commit(waiter).unwrap();
+        assert!(txn_mgr.active_txns().is_empty());
+        assert_eq!(txn_mgr.next_txn_id(), txn_mgr.vacuum_horizon());
+
+        // アボートのレコードを書く
+        let start_lsn = txn_mgr.log().start_lsn();
+        let records = txn_mgr.log_mut().read_from(start_lsn).unwrap();
+        assert!(records.iter().any(|(_, record)| *record
+            == LogRecord::Abort {
+                txn_id: abandoned_id
+            }));
+    }
+
+    #[test]
+    fn undo_mode_test() {
+        let dir = tempdir().unwrap();
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let btree = BTree::create(&mut bufmgr).unwrap();
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        let is_mixed =
+            |err: anyhow::Error| matches!(err.downcast_ref(), Some(Error::MixedUndo { .. }));
+
+        // 挿入したトランザクションではページを書き換えられない
+        let mut txn = txn_mgr.begin().unwrap();
+        txn_mgr
+            .insert(&mut txn, &mut bufmgr, &btree, b"a", b"Alice")
+            .unwrap();
+        assert!(matches!(
+            txn.bind(&mut bufmgr),
+            Err(Error::MixedUndo {
+                mode: UndoMode::Logical,
+                ..
+            })
+        ));
+        let err = txn_mgr
+            .mark_deleted(&mut txn, &mut bufmgr, &btree, b"a")
+            .unwrap_err();
+        assert!(is_mixed(err));
+        txn_mgr.commit(txn).unwrap();
+
+        // ページを書き換えたトランザクションでは挿入できない
+        let mut txn = txn_mgr.begin().unwrap();
+        btree
+            .insert(&mut txn.bind(&mut bufmgr).unwrap(), b"b", b"Bob")
+            .unwrap();
+        let err = txn_mgr
+            .insert(&mut txn, &mut bufmgr, &btree, b"c", b"Charlie")
+            .unwrap_err();
+        assert!(is_mixed(err));
+        txn_mgr.rollback(txn, &mut bufmgr).unwrap();
+        assert_eq!(1, count_rows(&mut bufmgr, &btree));
+
+        // 挿入したトランザクションを捨てても、同じページへの他のコミットは残る
+        let mut loser = txn_mgr.begin().unwrap();
+        let loser_id = loser.id();
+        txn_mgr
+            .insert(&mut loser, &mut bufmgr, &btree, b"d", b"Dave")
+            .unwrap();
+        let mut txn = txn_mgr.begin().unwrap();
+        txn_mgr
+            .insert(&mut txn, &mut bufmgr, &btree, b"e", b"Eve")
+            .unwrap();
+        txn_mgr.commit(txn).unwrap();
+        drop(loser);
+        // キーを消すまでは活動中のまま
+        assert!(txn_mgr
+            .active_txns()
+            .iter()
+            .any(|&(txn_id, _)| txn_id == loser_id));
+        txn_mgr.undo_abandoned(&mut bufmgr).unwrap();
+        assert!(txn_mgr.active_txns().is_empty());
+        let mut iter = btree.search(&mut bufmgr, SearchMode::Start).unwrap();
+        let mut keys = vec![];
+        while let Some((key, _)) = iter.next(&mut bufmgr).unwrap() {
+            keys.push(key);
+        }
+        assert_eq!(vec![b"a".to_vec(), b"e".to_vec()], keys);
+    }
+
+    #[test]
+    fn row_lock_test() {
+        let dir = tempdir().unwrap();
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let btree = BTree::create(&mut bufmgr).unwrap();
+        let log = FileLogManager::open(dir.path()).unwrap();
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        let is_lock_wait =
+            |err: anyhow::Error| matches!(err.downcast_ref(), Some(Error::LockWait { .. }));
+
+        // 同じキーへの挿入は先に書いたトランザクションが終わるまで待つ
+        let mut t1 = txn_mgr.begin().unwrap();
+        let mut t2 = txn_mgr.begin().unwrap();
+        txn_mgr
+            .insert(&mut t1, &mut bufmgr, &btree, b"a", b"Alice")
+            .unwrap();
+        let err = txn_mgr
+            .insert(&mut t2, &mut bufmgr, &btree, b"a", b"Alan")
+            .unwrap_err();
+        assert!(is_lock_wait(err));
+        // 別のキーなら書ける
+        txn_mgr
+            .insert(&mut t2, &mut bufmgr, &btree, b"b", b"Bob")
+            .unwrap();
+        txn_mgr.rollback(t1, &mut bufmgr).unwrap();
+        txn_mgr
+            .insert(&mut t2, &mut bufmgr, &btree, b"a", b"Alan")
+            .unwrap();
+        txn_mgr.commit(t2).unwrap();
+        assert_eq!(2, count_rows(&mut bufmgr, &btree));
+
+        // 互いに相手の書いたキーを書くとデッドロックになる
+        let mut t1 = txn_mgr.begin().unwrap();
+        let mut t2 = txn_mgr.begin().unwrap();
+        txn_mgr
+            .insert(&mut t1, &mut bufmgr, &btree, b"c", b"Charlie")
+            .unwrap();
+        txn_mgr
+            .insert(&mut t2, &mut bufmgr, &btree, b"d", b"Dave")
+            .unwrap();
+        let err = txn_mgr
+            .insert(&mut t1, &mut bufmgr, &btree, b"d", b"Dan")
+            .unwrap_err();
+        assert!(is_lock_wait(err));
+        let err = txn_mgr
+            .insert(&mut t2, &mut bufmgr, &btree, b"c", b"Carol")
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::Deadlock(_))));
+        txn_mgr.rollback(t2, &mut bufmgr).unwrap();
+        txn_mgr
+            .insert(&mut t1, &mut bufmgr, &btree, b"d", b"Dan")
+            .unwrap();
+        txn_mgr.commit(t1).unwrap();
+        assert_eq!(4, count_rows(&mut bufmgr, &btree));
+
+        // 木全体を書き換えるトランザクションとは木のロックで待ち合わせる
+        let t1 = txn_mgr.begin().unwrap();
+        let tree = LockTarget::Page(btree.meta_page_id);
+        assert_eq!(
+            LockStatus::Granted,
+            txn_mgr.lock(&t1, tree, LockMode::Exclusive)
+        );
+        let mut t2 = txn_mgr.begin().unwrap();
+        let err = txn_mgr
+            .insert(&mut t2, &mut bufmgr, &btree, b"e", b"Eve")
+            .unwrap_err();
+        assert!(is_lock_wait(err));
+        txn_mgr.commit(t1).unwrap();
+        txn_mgr
+            .insert(&mut t2, &mut bufmgr, &btree, b"e", b"Eve")
+            .unwrap();
+        txn_mgr.commit(t2).unwrap();
+    }
+
+    // 書き出しに失敗させられる LogManager
+    struct FailingLog {
+        log: FileLogManager,
+        fail: Rc<Cell<bool>>,
+    }
+
+    impl LogManager for FailingLog {
+        fn append(&mut self, record: &LogRecord) -> crate::wal::manager::Result<Lsn> {
+            self.log.append(record)
+        }
+
+        fn flush(&mut self) -> crate::wal::manager::Result<()> {
+            if self.fail.get() {
+                return Err(std::io::Error::other("flush failed").into());
+            }
+            self.log.flush()
+        }
+
+        fn start_lsn(&self) -> Lsn {
+            self.log.start_lsn()
+        }
+
+        fn end_lsn(&self) -> Lsn {
+            self.log.end_lsn()
+        }
+
+        fn flushed_lsn(&self) -> Lsn {
+            self.log.flushed_lsn()
+        }
+
+        fn read_from(&mut self, lsn: Lsn) -> crate::wal::manager::Result<Vec<(Lsn, LogRecord)>> {
+            self.log.read_from(lsn)
+        }
+
+        fn truncate_before(&mut self, lsn: Lsn) -> crate::wal::manager::Result<()> {
+            self.log.truncate_before(lsn)
+        }
+    }
+
+    #[test]
+    fn failed_flush_test() {
+        let dir = tempdir().unwrap();
+        let mut bufmgr = ClockSweepManager::new(MemoryManager::new(), 10).unwrap();
+        let btree = BTree::create(&mut bufmgr).unwrap();
+        let fail = Rc::new(Cell::new(false));
+        let log = FailingLog {
+            log: FileLogManager::open(dir.path()).unwrap(),
+            fail: Rc::clone(&fail),
+        };
+        let mut txn_mgr = TransactionManager::new(log).unwrap();
+        txn_mgr.set_commit_window(Duration::from_secs(3600));
+
+        // 待っている間は永続化していなくても commit から返る
+        let mut txn = txn_mgr.begin().unwrap();
+        let txn_id = txn.id();
+        txn_mgr
+            .insert(&mut txn, &mut bufmgr, &btree, b"a", b"Alice")
+            .unwrap();
+        txn_mgr.commit(txn).unwrap();
+        assert_eq!(vec![txn_id], txn_mgr.pending_commits());
+
+        // 書き出しに失敗すればロールバックされ、それからは使えない
+        fail.set(true);
+        assert!(txn_mgr.flush_commits().is_err());
+        assert!(txn_mgr.pending_commits().is_empty());
+        fail.set(false);
+        for err in [
+            txn_mgr.flush_commits().unwrap_err(),
+            txn_mgr.begin().err().unwrap(),
+        ] {
+            assert!(matches!(err.downcast_ref(), Some(Error::Poisoned)));
+        }
+    }
+}
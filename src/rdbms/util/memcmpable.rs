@@ -5,7 +5,7 @@ const ESCAPE_LENGTH: usize = 9;
 pub fn encoded_size(len: usize) -> usize {
     // https://github.com/rust-lang/rfcs/issues/2844
     let d = ESCAPE_LENGTH - 1;
-    let num_of_chunks = len / d + u32::from(len % d != 0) as usize;
+    let num_of_chunks = len / d + u32::from(!len.is_multiple_of(d)) as usize;
     cmp::max(1, num_of_chunks) * ESCAPE_LENGTH
 }
 
@@ -38,6 +38,27 @@ pub fn decode(src: &mut &[u8], dst: &mut Vec<u8>) {
     }
 }
 
+// decode と同じだが、符号化したものとして読めなければ src を進めずに None を返す
+pub fn try_decode(src: &mut &[u8], dst: &mut Vec<u8>) -> Option<()> {
+    let mut rest = *src;
+    let mut elem = vec![];
+    loop {
+        let chunk = rest.get(..ESCAPE_LENGTH)?;
+        let extra = chunk[ESCAPE_LENGTH - 1] as usize;
+        if extra > ESCAPE_LENGTH {
+            return None;
+        }
+        elem.extend_from_slice(&chunk[..cmp::min(ESCAPE_LENGTH - 1, extra)]);
+        rest = &rest[ESCAPE_LENGTH..];
+        if extra < ESCAPE_LENGTH {
+            break;
+        }
+    }
+    dst.extend(elem);
+    *src = rest;
+    Some(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
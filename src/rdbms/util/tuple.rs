@@ -20,6 +20,18 @@ pub fn decode(bytes: &[u8], elems: &mut Vec<Vec<u8>>) {
     }
 }
 
+// decode と同じだが、符号化したものとして読めなければ None を返す
+pub fn try_decode(bytes: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut rest = bytes;
+    let mut elems = vec![];
+    while !rest.is_empty() {
+        let mut elem = vec![];
+        memcmpable::try_decode(&mut rest, &mut elem)?;
+        elems.push(elem);
+    }
+    Some(elems)
+}
+
 pub struct Pretty<'a, T>(pub &'a [T]);
 
 impl<'a, T: AsRef<[u8]>> Debug for Pretty<'a, T> {
@@ -70,6 +82,21 @@ mod tests {
         assert_eq!(dec1.as_slice(), expected);
     }
 
+    #[test]
+    fn try_decode_test() {
+        let mut enc = vec![];
+        let org: Vec<&[u8]> = vec![b"hello", b"", b"world!!!!"];
+        encode(org.iter(), &mut enc);
+        assert_eq!(
+            Some(org.iter().map(|elem| elem.to_vec()).collect()),
+            try_decode(&enc)
+        );
+        // 途中で切れたものや、符号化していないもの
+        assert_eq!(None, try_decode(&enc[..enc.len() - 1]));
+        assert_eq!(None, try_decode(b"Alice"));
+        assert_eq!(None, try_decode(&[0xff; 9]));
+    }
+
     #[test]
     fn fmt_for_pretty_test() {
         let mut enc1 = vec![];
@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::storage::{
+    entity::PageId,
+    manager::{Error, Result, StorageManager},
+};
+
+// 上書きされたページの古い版をチェックポイントのエポックごとに残す storagemanager のラッパー
+// sync (チェックポイント) のたびにエポックが一つ進み、前のエポックで書かれたページを
+// 上書きするときには古い内容を下位層の別のページへコピーしてから書き換える
+// read_page_as_of で「チェックポイント N の時点のデータベース」を読める
+// 版の対応表はメモリ上にしか持たないので、開き直すと古い版は辿れなくなる
+pub struct VersionedStorage<T: StorageManager> {
+    inner: T,
+    // 現在のエポック (まだ sync されていない書き込みが属する)
+    epoch: u64,
+    // ページの現在の内容が書かれたエポック
+    written_at: HashMap<PageId, u64>,
+    // ページの古い版: 書かれたエポック -> コピー先のページ
+    versions: HashMap<PageId, BTreeMap<u64, PageId>>,
+}
+
+impl<T: StorageManager> VersionedStorage<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            epoch: 1,
+            written_at: HashMap::new(),
+            versions: HashMap::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    // 現在のエポック (次の sync で完了する)
+    pub fn current_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    // ページの書かれたエポック (このラッパーを通さずに書かれたものは 0 とする)
+    fn written_at(&self, page_id: PageId) -> u64 {
+        self.written_at.get(&page_id).copied().unwrap_or(0)
+    }
+
+    // epoch 番目のチェックポイントが完了した時点のページの内容を読む
+    pub fn read_page_as_of(&mut self, page_id: PageId, epoch: u64, data: &mut [u8]) -> Result<()> {
+        if self.written_at(page_id) <= epoch {
+            return self.inner.read_page_data(page_id, data);
+        }
+        let copy_page_id = self
+            .versions
+            .get(&page_id)
+            .and_then(|versions| versions.range(..=epoch).next_back())
+            .map(|(_, &copy_page_id)| copy_page_id)
+            // その時点ではまだ書かれていなかった
+            .ok_or(Error::PastEof(page_id))?;
+        self.inner.read_page_data(copy_page_id, data)
+    }
+
+    // epoch より前の時点を読む必要がなくなった古い版を解放する
+    pub fn release_versions_before(&mut self, epoch: u64) -> Result<()> {
+        for (&page_id, versions) in self.versions.iter_mut() {
+            // epoch の時点で見える版は残す
+            let visible = match self.written_at.get(&page_id) {
+                Some(&written_at) if written_at <= epoch => None,
+                _ => versions.range(..=epoch).next_back().map(|(&e, _)| e),
+            };
+            let obsolete: Vec<_> = versions
+                .range(..epoch)
+                .map(|(&e, _)| e)
+                .filter(|&e| Some(e) != visible)
+                .collect();
+            for e in obsolete {
+                let copy_page_id = versions.remove(&e).unwrap();
+                self.inner.deallocate_page(copy_page_id)?;
+            }
+        }
+        self.versions.retain(|_, versions| !versions.is_empty());
+        Ok(())
+    }
+
+    // 古い版のページの数
+    pub fn num_retained_versions(&self) -> usize {
+        self.versions.values().map(|versions| versions.len()).sum()
+    }
+}
+
+impl<T: StorageManager> StorageManager for VersionedStorage<T> {
+    fn page_size(&self) -> Option<usize> {
+        self.inner.page_size()
+    }
+
+    fn allocate_page(&mut self) -> Result<PageId> {
+        self.inner.allocate_page()
+    }
+
+    fn deallocate_page(&mut self, page_id: PageId) -> Result<()> {
+        self.inner.deallocate_page(page_id)
+    }
+
+    fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> Result<()> {
+        self.inner.read_page_data(page_id, data)
+    }
+
+    fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> Result<()> {
+        let written_at = self.written_at(page_id);
+        if written_at < self.epoch {
+            // 前のエポックの内容をコピーして残す
+            let mut old = vec![0u8; data.len()];
+            match self.inner.read_page_data(page_id, &mut old) {
+                Ok(()) => {
+                    let copy_page_id = self.inner.allocate_page()?;
+                    self.inner.write_page_data(copy_page_id, &old)?;
+                    self.versions
+                        .entry(page_id)
+                        .or_default()
+                        .insert(written_at, copy_page_id);
+                }
+                // まだ一度も書かれていないページには残す版が無い
+                Err(Error::PastEof(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        self.inner.write_page_data(page_id, data)?;
+        self.written_at.insert(page_id, self.epoch);
+        Ok(())
+    }
+
+    // 同期したらエポックを一つ進める
+    fn sync(&mut self) -> Result<()> {
+        self.inner.sync()?;
+        self.epoch += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::entity::PAGE_SIZE;
+    use crate::rdbms::memory::MemoryManager;
+
+    #[test]
+    fn versioned_test() {
+        let mut storage = VersionedStorage::new(MemoryManager::new());
+        let page_id = storage.allocate_page().unwrap();
+        storage.write_page_data(page_id, &[1u8; PAGE_SIZE]).unwrap();
+        storage.sync().unwrap(); // epoch 1
+        storage.write_page_data(page_id, &[2u8; PAGE_SIZE]).unwrap();
+        // 同じエポックの中での上書きでは版を残さない
+        storage.write_page_data(page_id, &[3u8; PAGE_SIZE]).unwrap();
+        storage.sync().unwrap(); // epoch 2
+        storage.write_page_data(page_id, &[4u8; PAGE_SIZE]).unwrap();
+        assert_eq!(2, storage.num_retained_versions());
+
+        let mut buf = vec![0u8; PAGE_SIZE];
+        storage.read_page_as_of(page_id, 1, &mut buf).unwrap();
+        assert_eq!(1, buf[0]);
+        storage.read_page_as_of(page_id, 2, &mut buf).unwrap();
+        assert_eq!(3, buf[0]);
+        storage.read_page_as_of(page_id, 3, &mut buf).unwrap();
+        assert_eq!(4, buf[0]);
+        storage.read_page_data(page_id, &mut buf).unwrap();
+        assert_eq!(4, buf[0]);
+        assert!(matches!(
+            storage.read_page_as_of(page_id, 0, &mut buf),
+            Err(Error::PastEof(_))
+        ));
+
+        // エポック 2 より前を読まないなら、エポック 1 の版は要らない
+        storage.release_versions_before(2).unwrap();
+        assert_eq!(1, storage.num_retained_versions());
+        storage.read_page_as_of(page_id, 2, &mut buf).unwrap();
+        assert_eq!(3, buf[0]);
+    }
+}
@@ -0,0 +1,518 @@
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use bincode::Options;
+use crc::{Crc, CRC_32_ISCSI};
+
+use crate::wal::{
+    entity::{LogRecord, Lsn},
+    manager::{Error, LogManager, Result},
+};
+
+// セグメントを切り替える大きさの既定値
+pub const DEFAULT_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+// レコードの前に置くヘッダ
+// [len: u32][crc: u32] の順でリトルエンディアンで書き、crc は本体の CRC32C
+const RECORD_HEADER_SIZE: usize = 8;
+
+const CRC32C: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+
+// 書き終えたセグメントの先頭の LSN とファイルを受け取ってアーカイブする
+pub type Archiver = Box<dyn FnMut(Lsn, &Path) -> io::Result<()>>;
+
+// 書き終えたセグメントを dir にコピーする Archiver
+// コピー先のディレクトリは ArchivedLog で開いて restore に使える
+// コピーしたファイルとディレクトリのエントリを sync してから返すので、返ればアーカイブは永続化している
+pub fn archive_to(dir: impl AsRef<Path>) -> Archiver {
+    let dir = dir.as_ref().to_path_buf();
+    Box::new(move |_, path| {
+        fs::create_dir_all(&dir)?;
+        copy_synced(path, &dir)?;
+        File::open(&dir)?.sync_all()
+    })
+}
+
+// path を dir に同じ名前でコピーし、コピーしたファイルを sync する
+fn copy_synced(path: &Path, dir: &Path) -> io::Result<()> {
+    let dest = dir.join(path.file_name().unwrap());
+    fs::copy(path, &dest)?;
+    File::open(dest)?.sync_all()
+}
+
+// dir の中のセグメントの先頭の LSN (古い順)
+fn list_segments(dir: &Path) -> io::Result<Vec<Lsn>> {
+    let mut segments = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("wal") {
+            continue;
+        }
+        let lsn = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| u64::from_str_radix(stem, 16).ok());
+        if let Some(lsn) = lsn {
+            segments.push(Lsn(lsn));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+// セグメントの先頭から読める (書きかけでない) レコードの長さ
+fn valid_len(buf: &[u8]) -> usize {
+    let mut valid_len = 0;
+    while let Some((_, next)) = decode_record(buf, valid_len) {
+        valid_len = next;
+    }
+    valid_len
+}
+
+// dir の segments から、lsn 以降で end より前のレコードを読む
+fn read_segments(
+    dir: &Path,
+    segments: &[Lsn],
+    end: Lsn,
+    lsn: Lsn,
+) -> Result<Vec<(Lsn, LogRecord)>> {
+    let mut records = vec![];
+    for (i, &segment_lsn) in segments.iter().enumerate() {
+        let segment_end = segments.get(i + 1).copied().unwrap_or(end);
+        if segment_end <= lsn {
+            continue;
+        }
+        let mut buf = vec![];
+        File::open(segment_path(dir, segment_lsn))?.read_to_end(&mut buf)?;
+        // 書き出していないレコードは読まない
+        buf.truncate((segment_end.to_u64() - segment_lsn.to_u64()) as usize);
+        let mut offset = 0;
+        while offset < buf.len() {
+            let record_lsn = Lsn(segment_lsn.to_u64() + offset as u64);
+            let (record, next) = decode_record(&buf, offset).ok_or(Error::Corrupt(record_lsn))?;
+            if record_lsn >= lsn {
+                records.push((record_lsn, record));
+            }
+            offset = next;
+        }
+    }
+    Ok(records)
+}
+
+// ディレクトリの中のセグメントファイルにログを書いていく logmanager
+// セグメントのファイル名は先頭のレコードの LSN で、レコードはセグメントをまたがない
+// 一定の大きさを超えたら次のセグメントに切り替え、切り詰めはセグメント単位で消して行う
+// アーカイブするときは、書き終えたセグメントを切り替えのたびに Archiver に渡し、
+// アーカイブが済むまで切り詰めない
+pub struct FileLogManager {
+    dir: PathBuf,
+    // 残っているセグメントの先頭の LSN (古い順)
+    segments: Vec<Lsn>,
+    // 最後のセグメント (追記先)
+    current: File,
+    segment_size: u64,
+    // 追記されたがまだ書き出していないレコード
+    pending: Vec<u8>,
+    flushed_lsn: Lsn,
+    archiver: Option<Archiver>,
+    // これより前から始まるセグメントはアーカイブが済んでいる
+    archived_lsn: Lsn,
+}
+
+impl FileLogManager {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        Self::with_segment_size(dir, DEFAULT_SEGMENT_SIZE)
+    }
+
+    // 開き直したときは最後のセグメントの書きかけのレコードを捨てる
+    pub fn with_segment_size(dir: impl AsRef<Path>, segment_size: u64) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut segments = list_segments(&dir)?;
+        if segments.is_empty() {
+            segments.push(Lsn(0));
+        }
+        let last = *segments.last().unwrap();
+        let mut current = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(segment_path(&dir, last))?;
+        let mut buf = vec![];
+        current.read_to_end(&mut buf)?;
+        let valid_len = valid_len(&buf);
+        current.set_len(valid_len as u64)?;
+        current.seek(SeekFrom::End(0))?;
+        let first = segments[0];
+        Ok(Self {
+            dir,
+            segments,
+            current,
+            segment_size,
+            pending: vec![],
+            flushed_lsn: Lsn(last.to_u64() + valid_len as u64),
+            archiver: None,
+            archived_lsn: first,
+        })
+    }
+
+    // 開き直したときは残っているセグメントを全てアーカイブし直す
+    pub fn set_archiver(&mut self, archiver: Archiver) {
+        self.archiver = Some(archiver);
+        self.archived_lsn = self.start_lsn();
+    }
+
+    // 書き出してから次のセグメントに切り替え、書き終えたセグメントをアーカイブする
+    // ベースバックアップの後などに最後のセグメントまでアーカイブしたいときに使う
+    pub fn switch_segment(&mut self) -> Result<()> {
+        self.flush()?;
+        if self.flushed_lsn > *self.segments.last().unwrap() {
+            self.start_segment()?;
+        }
+        Ok(())
+    }
+
+    // 追記先を書き出し済みの終わりで切り、そこへシークする
+    fn rewind(&mut self) -> Result<()> {
+        let last = *self.segments.last().unwrap();
+        let offset = self.flushed_lsn.to_u64() - last.to_u64();
+        self.current.set_len(offset)?;
+        self.current.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    fn start_segment(&mut self) -> Result<()> {
+        self.current = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(segment_path(&self.dir, self.flushed_lsn))?;
+        self.segments.push(self.flushed_lsn);
+        // 新しいセグメントのエントリを永続化する
+        File::open(&self.dir)?.sync_all()?;
+        self.archive_completed()
+    }
+
+    // まだアーカイブしていない書き終えたセグメントをアーカイブする
+    fn archive_completed(&mut self) -> Result<()> {
+        let archiver = match self.archiver.as_mut() {
+            Some(archiver) => archiver,
+            None => return Ok(()),
+        };
+        for window in self.segments.windows(2) {
+            if window[0] >= self.archived_lsn {
+                archiver(window[0], &segment_path(&self.dir, window[0]))?;
+                self.archived_lsn = window[1];
+            }
+        }
+        Ok(())
+    }
+
+    // lsn 以降のレコードを含むセグメントを dir にコピーして増分バックアップを作る
+    // lsn には前のバックアップの終わりの LSN を渡し、返した LSN を次の増分バックアップに渡す
+    // 作ったバックアップは checkpoint::apply_incremental で前のバックアップに適用する
+    // lsn 以降のセグメントが切り詰められていれば Error::Truncated を返す
+    pub fn backup_since(&mut self, lsn: Lsn, dir: impl AsRef<Path>) -> Result<Lsn> {
+        if lsn < self.start_lsn() {
+            return Err(Error::Truncated(lsn));
+        }
+        self.flush()?;
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        for (i, &segment_lsn) in self.segments.iter().enumerate() {
+            let segment_end = self
+                .segments
+                .get(i + 1)
+                .copied()
+                .unwrap_or(self.flushed_lsn);
+            if segment_end <= lsn && segment_end < self.flushed_lsn {
+                continue;
+            }
+            copy_synced(&segment_path(&self.dir, segment_lsn), dir)?;
+        }
+        File::open(dir)?.sync_all()?;
+        Ok(self.flushed_lsn)
+    }
+
+    // 残っているセグメントのファイル (古い順)
+    pub fn segment_paths(&self) -> Vec<PathBuf> {
+        self.segments
+            .iter()
+            .map(|&lsn| segment_path(&self.dir, lsn))
+            .collect()
+    }
+}
+
+fn segment_path(dir: &Path, lsn: Lsn) -> PathBuf {
+    dir.join(format!("{:016x}.wal", lsn.to_u64()))
+}
+
+fn encode_record(record: &LogRecord, buf: &mut Vec<u8>) {
+    let body = bincode::options().serialize(record).unwrap();
+    buf.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&CRC32C.checksum(&body).to_le_bytes());
+    buf.extend_from_slice(&body);
+}
+
+// offset から一つレコードを読み、次のレコードの位置と組にして返す
+// 途中で切れていたりチェックサムが合わなかったりすれば None を返す
+fn decode_record(buf: &[u8], offset: usize) -> Option<(LogRecord, usize)> {
+    let header = buf.get(offset..offset + RECORD_HEADER_SIZE)?;
+    let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let start = offset + RECORD_HEADER_SIZE;
+    let body = buf.get(start..start + len)?;
+    if CRC32C.checksum(body) != crc {
+        return None;
+    }
+    let record = bincode::options().deserialize(body).ok()?;
+    Some((record, start + len))
+}
+
+impl LogManager for FileLogManager {
+    fn append(&mut self, record: &LogRecord) -> Result<Lsn> {
+        let lsn = self.end_lsn();
+        encode_record(record, &mut self.pending);
+        Ok(lsn)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let written = self
+            .current
+            .write_all(&self.pending)
+            .and_then(|()| self.current.sync_data());
+        if let Err(err) = written {
+            // 途中まで書いたレコードの後ろに書き直さないよう、書き出し済みの終わりまで戻す
+            // pending は残すので、もう一度 flush すれば同じところから書き直す
+            self.rewind()?;
+            return Err(err.into());
+        }
+        self.flushed_lsn = Lsn(self.flushed_lsn.to_u64() + self.pending.len() as u64);
+        self.pending.clear();
+        let last = *self.segments.last().unwrap();
+        if self.flushed_lsn.to_u64() - last.to_u64() >= self.segment_size {
+            self.start_segment()?;
+        }
+        Ok(())
+    }
+
+    fn start_lsn(&self) -> Lsn {
+        self.segments[0]
+    }
+
+    fn end_lsn(&self) -> Lsn {
+        Lsn(self.flushed_lsn.to_u64() + self.pending.len() as u64)
+    }
+
+    fn flushed_lsn(&self) -> Lsn {
+        self.flushed_lsn
+    }
+
+    fn read_from(&mut self, lsn: Lsn) -> Result<Vec<(Lsn, LogRecord)>> {
+        if lsn < self.start_lsn() {
+            return Err(Error::Truncated(lsn));
+        }
+        read_segments(&self.dir, &self.segments, self.flushed_lsn, lsn)
+    }
+
+    // lsn より前で終わるセグメントを消す (追記先とアーカイブしていないセグメントは残す)
+    fn truncate_before(&mut self, lsn: Lsn) -> Result<()> {
+        let lsn = if self.archiver.is_some() {
+            std::cmp::min(lsn, self.archived_lsn)
+        } else {
+            lsn
+        };
+        while self.segments.len() > 1 && self.segments[1] <= lsn {
+            fs::remove_file(segment_path(&self.dir, self.segments[0]))?;
+            self.segments.remove(0);
+        }
+        Ok(())
+    }
+}
+
+// アーカイブや増分バックアップのセグメントのディレクトリを読むだけの logmanager
+// FileLogManager と違ってファイルを作ったり切り詰めたりしないので、バックアップを開いても壊さない
+// 最後のセグメントの書きかけのレコードは読まずに残し、追記や切り詰めはエラーにする
+pub struct ArchivedLog {
+    dir: PathBuf,
+    segments: Vec<Lsn>,
+    end_lsn: Lsn,
+}
+
+impl ArchivedLog {
+    // セグメントが一つもなければ NotFound を返す
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let segments = list_segments(&dir)?;
+        let last = match segments.last() {
+            Some(&last) => last,
+            None => {
+                let message = format!("no log segments in {}", dir.display());
+                return Err(io::Error::new(io::ErrorKind::NotFound, message).into());
+            }
+        };
+        let mut buf = vec![];
+        File::open(segment_path(&dir, last))?.read_to_end(&mut buf)?;
+        let end_lsn = Lsn(last.to_u64() + valid_len(&buf) as u64);
+        Ok(Self {
+            dir,
+            segments,
+            end_lsn,
+        })
+    }
+
+    fn read_only() -> Error {
+        io::Error::new(io::ErrorKind::PermissionDenied, "archived log is read-only").into()
+    }
+}
+
+impl LogManager for ArchivedLog {
+    fn append(&mut self, _: &LogRecord) -> Result<Lsn> {
+        Err(Self::read_only())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn start_lsn(&self) -> Lsn {
+        self.segments[0]
+    }
+
+    fn end_lsn(&self) -> Lsn {
+        self.end_lsn
+    }
+
+    fn flushed_lsn(&self) -> Lsn {
+        self.end_lsn
+    }
+
+    fn read_from(&mut self, lsn: Lsn) -> Result<Vec<(Lsn, LogRecord)>> {
+        if lsn < self.start_lsn() {
+            return Err(Error::Truncated(lsn));
+        }
+        read_segments(&self.dir, &self.segments, self.end_lsn, lsn)
+    }
+
+    fn truncate_before(&mut self, _: Lsn) -> Result<()> {
+        Err(Self::read_only())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::entity::PageId;
+    use crate::wal::entity::TxnId;
+    use tempfile::tempdir;
+
+    fn page_image(page_id: u64, n: u8) -> LogRecord {
+        LogRecord::PageImage {
+            txn_id: TxnId(page_id),
+            page_id: PageId(page_id),
+            image: vec![n; 100],
+        }
+    }
+
+    #[test]
+    fn file_log_test() {
+        let dir = tempdir().unwrap();
+        let mut log = FileLogManager::with_segment_size(dir.path(), 256).unwrap();
+        let lsns: Vec<_> = (0..6u8)
+            .map(|n| {
+                let lsn = log.append(&page_image(n as u64, n)).unwrap();
+                // 3 レコードごとに書き出すとセグメントが切り替わる
+                if n % 3 == 2 {
+                    log.flush().unwrap();
+                }
+                lsn
+            })
+            .collect();
+        assert_eq!(Lsn(0), lsns[0]);
+        assert_eq!(3, log.segment_paths().len());
+        // 書き出していないレコードは読めない
+        log.append(&page_image(6, 6)).unwrap();
+        let records = log.read_from(lsns[1]).unwrap();
+        assert_eq!(5, records.len());
+        assert_eq!((lsns[4], page_image(4, 4)), records[3]);
+        let last_segment = log.segment_paths().pop().unwrap();
+        drop(log);
+
+        // 書きかけのレコードを残してクラッシュした
+        let mut file = OpenOptions::new().append(true).open(last_segment).unwrap();
+        file.write_all(&[42; 5]).unwrap();
+        drop(file);
+
+        let mut log = FileLogManager::with_segment_size(dir.path(), 256).unwrap();
+        assert_eq!(log.flushed_lsn(), log.end_lsn());
+        let lsn = log.append(&page_image(7, 7)).unwrap();
+        log.flush().unwrap();
+        let records = log.read_from(lsns[0]).unwrap();
+        assert_eq!(7, records.len());
+        assert_eq!((lsn, page_image(7, 7)), records[6]);
+
+        // 切り詰めたところは読めない
+        log.truncate_before(lsns[4]).unwrap();
+        assert_eq!(lsns[3], log.start_lsn());
+        assert_eq!(2, log.segment_paths().len());
+        assert!(matches!(log.read_from(lsns[0]), Err(Error::Truncated(_))));
+        assert_eq!(4, log.read_from(lsns[3]).unwrap().len());
+    }
+
+    #[test]
+    fn rewind_test() {
+        let dir = tempdir().unwrap();
+        let mut log = FileLogManager::open(dir.path()).unwrap();
+        log.append(&page_image(0, 0)).unwrap();
+        log.flush().unwrap();
+        let lsn = log.append(&page_image(1, 1)).unwrap();
+        // 書き出しが途中で失敗して書きかけのレコードが残った
+        log.current.write_all(&[42; 5]).unwrap();
+        log.rewind().unwrap();
+        log.flush().unwrap();
+        drop(log);
+
+        let mut log = FileLogManager::open(dir.path()).unwrap();
+        let records = log.read_from(Lsn(0)).unwrap();
+        assert_eq!(2, records.len());
+        assert_eq!((lsn, page_image(1, 1)), records[1]);
+    }
+
+    #[test]
+    fn archived_log_test() {
+        let dir = tempdir().unwrap();
+        let mut log = FileLogManager::open(dir.path()).unwrap();
+        log.append(&page_image(0, 0)).unwrap();
+        let lsn = log.append(&page_image(1, 1)).unwrap();
+        log.flush().unwrap();
+        let segment = log.segment_paths().pop().unwrap();
+        drop(log);
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(&[42; 5]).unwrap();
+        drop(file);
+        let len = fs::metadata(&segment).unwrap().len();
+
+        // 書きかけのレコードは読まず、ファイルもそのまま残す
+        let mut archived = ArchivedLog::open(dir.path()).unwrap();
+        let records = archived.read_from(Lsn(0)).unwrap();
+        assert_eq!(2, records.len());
+        assert_eq!((lsn, page_image(1, 1)), records[1]);
+        assert!(archived.append(&page_image(2, 2)).is_err());
+        assert!(archived.truncate_before(lsn).is_err());
+        assert_eq!(len, fs::metadata(&segment).unwrap().len());
+
+        // 空のディレクトリや無いディレクトリにはセグメントを作らない
+        let empty = tempdir().unwrap();
+        assert!(ArchivedLog::open(empty.path()).is_err());
+        assert_eq!(0, fs::read_dir(empty.path()).unwrap().count());
+        assert!(ArchivedLog::open(empty.path().join("missing")).is_err());
+        assert!(!empty.path().join("missing").exists());
+    }
+}
@@ -6,3 +6,6 @@ pub mod dml;
 
 // Data Control Language
 // pub mod dcl;
+
+// SQL の文字列を構文木にする
+pub mod parser;
@@ -0,0 +1,107 @@
+use crate::sql::dml::entity::{Type, Value};
+
+// INSERT するレコードがテーブルの定義に合わない
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("expected {expected} columns but got {actual}")]
+    Arity { expected: usize, actual: usize },
+    #[error("column {0} must not be NULL")]
+    NotNull(String),
+    #[error("column {column} expects {expected:?} but got {actual:02x?}")]
+    Type {
+        column: String,
+        expected: Type,
+        actual: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub column_type: Type,
+    pub nullable: bool,
+}
+
+// テーブルの列の定義 (レコードの列の順)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schema {
+    pub columns: Vec<Column>,
+}
+
+impl Schema {
+    // レコードの列の数と、各列をその型の値として読めるかを確かめる
+    // 空のバイト列は NULL (NULL でない値は Value::encode で空にならない)
+    pub fn validate(&self, record: &[&[u8]]) -> Result<(), Error> {
+        if record.len() != self.columns.len() {
+            return Err(Error::Arity {
+                expected: self.columns.len(),
+                actual: record.len(),
+            });
+        }
+        for (column, bytes) in self.columns.iter().zip(record) {
+            if bytes.is_empty() {
+                if !column.nullable {
+                    return Err(Error::NotNull(column.name.clone()));
+                }
+            } else if Value::decode(column.column_type, bytes).is_err() {
+                return Err(Error::Type {
+                    column: column.name.clone(),
+                    expected: column.column_type,
+                    actual: bytes.to_vec(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_test() {
+        let schema = Schema {
+            columns: vec![
+                Column {
+                    name: "id".to_string(),
+                    column_type: Type::Int,
+                    nullable: false,
+                },
+                Column {
+                    name: "ok".to_string(),
+                    column_type: Type::Bool,
+                    nullable: true,
+                },
+            ],
+        };
+        let id = Value::Int(1).encode();
+        assert!(schema.validate(&[&id, &[1]]).is_ok());
+        assert!(schema.validate(&[&id, &[]]).is_ok());
+        assert!(matches!(
+            schema.validate(&[&id]),
+            Err(Error::Arity {
+                expected: 2,
+                actual: 1
+            })
+        ));
+        assert!(matches!(
+            schema.validate(&[&[], &[1]]),
+            Err(Error::NotNull(column)) if column == "id"
+        ));
+        assert!(matches!(
+            schema.validate(&[b"1", &[1]]),
+            Err(Error::Type {
+                expected: Type::Int,
+                ..
+            })
+        ));
+        assert!(matches!(
+            schema.validate(&[&id, &[2]]),
+            Err(Error::Type {
+                expected: Type::Bool,
+                ..
+            })
+        ));
+    }
+}
@@ -1,10 +1,24 @@
 use anyhow::Result;
 
 use crate::buffer::manager::BufferPoolManager;
+use crate::wal::entity::TxnId;
 
 pub trait Table<T: BufferPoolManager> {
     fn create(&mut self, bufmgr: &mut T) -> Result<()>;
-    fn insert(&self, bufmgr: &mut T, record: &[&[u8]]) -> Result<()>;
+    // トランザクションの外での INSERT (全ての読み出しから見える)
+    fn insert(&self, bufmgr: &mut T, record: &[&[u8]]) -> Result<()> {
+        self.insert_as(bufmgr, TxnId::INVALID_TXN_ID, record)
+    }
+    // トランザクション txn_id の中での INSERT
+    fn insert_as(&self, bufmgr: &mut T, txn_id: TxnId, record: &[&[u8]]) -> Result<()>;
+    // レコードの DELETE (主キーとインデックスの項目を消す)
+    // 木から物理的に消すので、トランザクションのスナップショットからも見えなくなる
+    fn delete(&self, bufmgr: &mut T, record: &[&[u8]]) -> Result<()>;
+    // 主キーが pkey のレコード (全ての列を並べたもの)
+    fn get(&self, bufmgr: &mut T, pkey: &[&[u8]]) -> Result<Option<Vec<Vec<u8>>>>;
+    // 主キーが同じレコードがあれば置き換え (インデックスの項目も書き換える)、なければ INSERT する
+    // 置き換えたら前のレコードを返す
+    fn upsert(&self, bufmgr: &mut T, record: &[&[u8]]) -> Result<Option<Vec<Vec<u8>>>>;
 }
 
 pub trait UniqueIndex<T: BufferPoolManager> {
@@ -12,4 +26,14 @@ pub trait UniqueIndex<T: BufferPoolManager> {
     fn create(&mut self, bufmgr: &mut T) -> Result<()>;
     // TABLE へのレコードの INSERT
     fn insert(&self, bufmgr: &mut T, pkey: &[u8], record: &[impl AsRef<[u8]>]) -> Result<()>;
+    // TABLE からのレコードの DELETE
+    fn delete(&self, bufmgr: &mut T, record: &[impl AsRef<[u8]>]) -> Result<()>;
+}
+
+// 同じ値を何度も持てるインデックス (キーは副キーと主キーを並べたもの)
+pub trait NonUniqueIndex<T: BufferPoolManager> {
+    fn create(&mut self, bufmgr: &mut T) -> Result<()>;
+    fn insert(&self, bufmgr: &mut T, pkey: &[u8], record: &[impl AsRef<[u8]>]) -> Result<()>;
+    // 副キーが同じ項目は主キーで見分ける
+    fn delete(&self, bufmgr: &mut T, pkey: &[u8], record: &[impl AsRef<[u8]>]) -> Result<()>;
 }
@@ -1 +1,301 @@
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
 pub type Tuple = Vec<Vec<u8>>;
+
+// 列の型
+// NULL はどの型でも空のバイト列で表し、NULL でない値は空にならないように符号化する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Type {
+    // 8 バイトのビッグエンディアンで、符号ビットを反転してバイト列の順と数の順を揃える
+    Int,
+    // 一バイトの 1 と 0
+    Bool,
+    // 空か 0 で始まるバイト列だけ前に 0 を一つ足す (NULL と区別でき、バイト列の順も変わらない)
+    Bytes,
+    // 外部結合で埋めた列など、NULL しか入らない列
+    Null,
+}
+
+impl Type {
+    pub fn tag(self) -> u8 {
+        match self {
+            Type::Int => 0,
+            Type::Bool => 1,
+            Type::Bytes => 2,
+            Type::Null => 3,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(Type::Int),
+            1 => Ok(Type::Bool),
+            2 => Ok(Type::Bytes),
+            3 => Ok(Type::Null),
+            _ => bail!("unknown column type {}", tag),
+        }
+    }
+}
+
+// 列の値
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Value {
+    // どの値よりも前に並ぶ
+    Null,
+    Int(i64),
+    Bool(bool),
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    pub fn column_type(&self) -> Type {
+        match self {
+            Value::Int(_) => Type::Int,
+            Value::Bool(_) => Type::Bool,
+            Value::Bytes(_) => Type::Bytes,
+            Value::Null => Type::Null,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Value::Int(n) => ((*n as u64) ^ (1 << 63)).to_be_bytes().to_vec(),
+            Value::Bool(b) => vec![*b as u8],
+            Value::Bytes(bytes) => match bytes.first() {
+                None | Some(0) => [&[0], bytes.as_slice()].concat(),
+                Some(_) => bytes.clone(),
+            },
+            Value::Null => vec![],
+        }
+    }
+
+    pub fn decode(ty: Type, bytes: &[u8]) -> Result<Self> {
+        if bytes.is_empty() {
+            return Ok(Value::Null);
+        }
+        match ty {
+            Type::Int => match bytes.try_into() {
+                Ok(bytes) => Ok(Value::Int((u64::from_be_bytes(bytes) ^ (1 << 63)) as i64)),
+                Err(_) => bail!("expected an 8-byte integer but got {} bytes", bytes.len()),
+            },
+            Type::Bool => match bytes {
+                [0] => Ok(Value::Bool(false)),
+                [1] => Ok(Value::Bool(true)),
+                _ => bail!("expected a boolean but got {:02x?}", bytes),
+            },
+            Type::Bytes => match bytes {
+                [0, rest @ ..] => Ok(Value::Bytes(rest.to_vec())),
+                _ => Ok(Value::Bytes(bytes.to_vec())),
+            },
+            Type::Null => bail!("expected NULL but got {:02x?}", bytes),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    // 同じ型の値どうしを比べる (NULL はどの値よりも小さく、NULL どうしは等しいとする)
+    pub fn try_cmp(&self, other: &Value) -> Result<Ordering> {
+        if !self.is_null() && !other.is_null() && self.column_type() != other.column_type() {
+            bail!("cannot compare {:?} with {:?}", self, other);
+        }
+        Ok(self.cmp(other))
+    }
+}
+
+// 実行器が返す行
+// 列はエンコードしたまま持ち、get で読むときに型に従って値にする
+// 型の分からない列 (types より後ろの列) はバイト列とみなす
+#[derive(Clone, PartialEq, Eq)]
+pub struct Row {
+    tuple: Tuple,
+    types: Arc<[Type]>,
+}
+
+impl Row {
+    pub fn new(tuple: Tuple, types: Arc<[Type]>) -> Self {
+        Self { tuple, types }
+    }
+
+    pub fn from_values(values: Vec<Value>) -> Self {
+        let types = values.iter().map(|value| value.column_type()).collect();
+        let tuple = values.iter().map(|value| value.encode()).collect();
+        Self { tuple, types }
+    }
+
+    // 全ての列が NULL の行
+    pub fn nulls(len: usize) -> Self {
+        Self::from_values(vec![Value::Null; len])
+    }
+
+    pub fn len(&self) -> usize {
+        self.tuple.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tuple.is_empty()
+    }
+
+    pub fn column_type(&self, column: usize) -> Type {
+        self.types.get(column).copied().unwrap_or(Type::Bytes)
+    }
+
+    pub fn types(&self) -> &Arc<[Type]> {
+        &self.types
+    }
+
+    pub fn get(&self, column: usize) -> Result<Value> {
+        match self.tuple.get(column) {
+            Some(bytes) => Value::decode(self.column_type(column), bytes),
+            None => bail!("column {} is out of range", column),
+        }
+    }
+
+    // エンコードしたままの列
+    pub fn tuple(&self) -> &Tuple {
+        &self.tuple
+    }
+
+    pub fn into_tuple(self) -> Tuple {
+        self.tuple
+    }
+
+    // 列の型を最後の要素に付けたタプル
+    // NULL の列があると行ごとに型が変わるので、一時ファイルに書き出すときは型も一緒に書く
+    pub fn to_tagged(&self) -> Tuple {
+        let mut tuple = self.tuple.clone();
+        tuple.push(
+            (0..self.len())
+                .map(|column| self.column_type(column).tag())
+                .collect(),
+        );
+        tuple
+    }
+
+    pub fn from_tagged(mut tuple: Tuple) -> Result<Self> {
+        let tags = match tuple.pop() {
+            Some(tags) if tags.len() == tuple.len() => tags,
+            _ => bail!("column types are missing"),
+        };
+        let types = tags
+            .into_iter()
+            .map(Type::from_tag)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            tuple,
+            types: Arc::from(types),
+        })
+    }
+
+    // 後ろに other の列を並べる
+    pub fn concat(self, other: &Row) -> Row {
+        let types = (0..self.len())
+            .map(|column| self.column_type(column))
+            .chain((0..other.len()).map(|column| other.column_type(column)))
+            .collect();
+        let mut tuple = self.tuple;
+        tuple.extend(other.tuple.iter().cloned());
+        Row { tuple, types }
+    }
+}
+
+impl Debug for Row {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_tuple("Row");
+        for column in 0..self.len() {
+            match self.get(column) {
+                Ok(Value::Bytes(bytes)) => match std::str::from_utf8(&bytes) {
+                    Ok(s) => d.field(&s),
+                    Err(_) => d.field(&format_args!("{:02x?}", bytes)),
+                },
+                Ok(value) => d.field(&value),
+                Err(_) => d.field(&format_args!("{:02x?}", self.tuple[column])),
+            };
+        }
+        d.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_test() {
+        // 数はバイト列の順でも数の順に並ぶ
+        let mut encoded: Vec<_> = [3i64, -1, i64::MIN, 0, i64::MAX]
+            .iter()
+            .map(|&n| Value::Int(n).encode())
+            .collect();
+        encoded.sort();
+        let decoded: Vec<_> = encoded
+            .iter()
+            .map(|bytes| Value::decode(Type::Int, bytes).unwrap())
+            .collect();
+        assert_eq!(
+            vec![
+                Value::Int(i64::MIN),
+                Value::Int(-1),
+                Value::Int(0),
+                Value::Int(3),
+                Value::Int(i64::MAX)
+            ],
+            decoded
+        );
+
+        let row = Row::from_values(vec![Value::Int(10), Value::Bool(true)]);
+        let other = Row::new(vec![b"x".to_vec()], Arc::from(vec![]));
+        let row = row.concat(&other);
+        assert_eq!(Value::Int(10), row.get(0).unwrap());
+        assert_eq!(Value::Bool(true), row.get(1).unwrap());
+        assert_eq!(Value::Bytes(b"x".to_vec()), row.get(2).unwrap());
+        assert!(row.get(3).is_err());
+        assert!(Value::Int(9).try_cmp(&Value::Int(10)).unwrap() == Ordering::Less);
+        assert!(Value::Int(9).try_cmp(&Value::Bytes(vec![])).is_err());
+        // 型と合わない列は読むときに失敗する
+        let row = Row::new(vec![b"x".to_vec()], Arc::from(vec![Type::Int]));
+        assert!(row.get(0).is_err());
+
+        // NULL の列は型を Null にして空のバイト列で持つ
+        let row = Row::from_values(vec![Value::Int(1)]).concat(&Row::nulls(2));
+        assert_eq!(
+            vec![vec![0x80, 0, 0, 0, 0, 0, 0, 1], vec![], vec![]],
+            *row.tuple()
+        );
+        assert_eq!(Value::Null, row.get(2).unwrap());
+        assert!(Value::Null.try_cmp(&Value::Int(i64::MIN)).unwrap() == Ordering::Less);
+        assert!(Value::Bytes(vec![]).try_cmp(&Value::Null).unwrap() == Ordering::Greater);
+        assert!(Value::Null.try_cmp(&Value::Null).unwrap() == Ordering::Equal);
+        // 型ごと書き出して読み戻せる
+        let tagged = row.to_tagged();
+        assert_eq!(row, Row::from_tagged(tagged.clone()).unwrap());
+        assert!(Row::from_tagged(tagged[1..].to_vec()).is_err());
+
+        // 列の型で読んでも NULL は NULL のまま、空のバイト列とは区別する
+        let values = [
+            Value::Null,
+            Value::Bytes(vec![]),
+            Value::Bytes(vec![0]),
+            Value::Bytes(vec![0, 1]),
+            Value::Bytes(vec![1]),
+            Value::Bytes(b"x".to_vec()),
+        ];
+        let encoded: Vec<_> = values.iter().map(Value::encode).collect();
+        let mut sorted = encoded.clone();
+        sorted.sort();
+        assert_eq!(encoded, sorted);
+        for (value, bytes) in values.iter().zip(&encoded) {
+            assert_eq!(*value, Value::decode(Type::Bytes, bytes).unwrap());
+        }
+        let row = Row::new(vec![vec![], vec![]], Arc::from(vec![Type::Int, Type::Bool]));
+        assert_eq!(Value::Null, row.get(0).unwrap());
+        assert_eq!(Value::Null, row.get(1).unwrap());
+    }
+}
@@ -1,15 +1,139 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 
-use super::entity::Tuple;
+use super::entity::Row;
 use crate::{accessor::method::HaveAccessMethod, buffer::manager::BufferPoolManager};
 
 pub trait Executor<T: BufferPoolManager> {
-    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Tuple>>;
+    fn next(&mut self, bufmgr: &mut T) -> Result<Option<Row>>;
+
+    // out を空にして、最大 out.capacity() 行までまとめて読む (空のままなら終わり)
+    // 既定では next を繰り返す
+    fn next_batch(&mut self, bufmgr: &mut T, out: &mut TupleBatch) -> Result<()> {
+        out.clear();
+        while !out.is_full() {
+            match self.next(bufmgr)? {
+                Some(row) => out.push(row),
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    // 最初の行から読み直せるようにする (できない実行器は false を返して何もしない)
+    fn rewind(&mut self, _bufmgr: &mut T) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+// next_batch で受け渡す行のまとまり
+// 同じものを使い回して、バッチごとの確保を減らす
+pub struct TupleBatch {
+    rows: Vec<Row>,
+    capacity: usize,
+}
+
+impl TupleBatch {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "batch capacity must be positive");
+        Self {
+            rows: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.rows.len() >= self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.clear();
+    }
+
+    pub fn push(&mut self, row: Row) {
+        self.rows.push(row);
+    }
+
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    pub fn rows_mut(&mut self) -> &mut Vec<Row> {
+        &mut self.rows
+    }
 }
 
 pub type BoxExecutor<'a, T> = Box<dyn Executor<T> + 'a>;
 
+// 並べる列と向き (列の値は型に従って比べる)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub column: usize,
+    pub descending: bool,
+}
+
+impl SortKey {
+    pub fn asc(column: usize) -> Self {
+        Self {
+            column,
+            descending: false,
+        }
+    }
+}
+
+// 子のプラン (実行器からも共有して、プランを借用せずに実行し直せるようにする)
+pub type SharedPlan<T, U> = Arc<dyn PlanNode<T, Iter = U> + Send + Sync>;
+
 pub trait PlanNode<T: BufferPoolManager>: HaveAccessMethod<T> {
     // PLANNER から EXECUTER を生成
-    fn start(&self, bufmgr: &mut T) -> Result<BoxExecutor<T>>;
+    // 実行器はプランを借用しないので、プランより長く使ってよい
+    fn start<'a>(&self, bufmgr: &mut T) -> Result<BoxExecutor<'a, T>>
+    where
+        T: 'a;
+
+    // ノード自身の一行の説明 (子は含まない)
+    fn describe(&self) -> String;
+
+    // 出力の行がどの列の順に並んでいるか (分からなければ空)
+    // 先頭の何列かだけを見れば、その列の順にも並んでいる
+    fn output_ordering(&self) -> Vec<SortKey> {
+        vec![]
+    }
+
+    // 子のプラン
+    fn children(&self) -> Vec<&dyn PlanNode<T, Iter = Self::Iter>> {
+        vec![]
+    }
+
+    // プランの木を一行に一ノードずつ、子を字下げして説明する (EXPLAIN)
+    fn explain(&self) -> String {
+        self.explain_with(&|_| String::new())
+    }
+
+    // annotate が返すものを各ノードの行の後ろに書き足して説明する
+    // ノードはアドレスで見分ける
+    fn explain_with(&self, annotate: &dyn Fn(usize) -> String) -> String {
+        let mut out = self.describe();
+        out += &annotate(self as *const Self as *const () as usize);
+        for child in self.children() {
+            for (i, line) in child.explain_with(annotate).lines().enumerate() {
+                out += if i == 0 { "\n  -> " } else { "\n     " };
+                out += line;
+            }
+        }
+        out
+    }
 }
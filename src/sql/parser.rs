@@ -0,0 +1,1213 @@
+use anyhow::Result;
+
+use super::dml::entity::{Type, Value};
+
+// 構文の誤り (pos は SQL の文字列の中のバイト位置)
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{message} at position {pos}")]
+    Syntax { pos: usize, message: String },
+}
+
+fn syntax_error<T>(pos: usize, message: impl Into<String>) -> Result<T> {
+    Err(Error::Syntax {
+        pos,
+        message: message.into(),
+    }
+    .into())
+}
+
+// 名前とその位置 (引用符で囲まなければ小文字にそろえる)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ident {
+    pub name: String,
+    pub pos: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    CreateTable(CreateTable),
+    CreateIndex(CreateIndex),
+    // DROP TABLE name
+    DropTable(Ident),
+    DropIndex(DropIndex),
+    Insert(Insert),
+    Select(Select),
+    Update(Update),
+    Delete(Delete),
+}
+
+// CREATE TABLE name (column type [NOT NULL | NULL] [PRIMARY KEY], ..., [PRIMARY KEY (column, ...)])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateTable {
+    pub name: Ident,
+    pub columns: Vec<ColumnDef>,
+    // 列の定義に書いたものも含めた主キーの列
+    pub primary_key: Vec<Ident>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnDef {
+    pub name: Ident,
+    pub column_type: Type,
+    // NOT NULL と書いたら false (主キーの列は書かなくても NULL にできない)
+    pub nullable: bool,
+}
+
+// CREATE [UNIQUE] INDEX name ON table (column, ...)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateIndex {
+    pub unique: bool,
+    pub name: Ident,
+    pub table: Ident,
+    pub columns: Vec<Ident>,
+}
+
+// DROP INDEX name ON table (インデックスの名前はテーブルごとに付ける)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DropIndex {
+    pub name: Ident,
+    pub table: Ident,
+}
+
+// INSERT INTO table [(column, ...)] {VALUES (expr, ...), ... | SELECT ...}
+// [ON CONFLICT [(column, ...)] DO {NOTHING | UPDATE SET column = expr, ...}]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Insert {
+    pub table: Ident,
+    // 省略したらテーブルの全ての列を順に
+    pub columns: Option<Vec<Ident>>,
+    pub source: InsertSource,
+    pub on_conflict: Option<OnConflict>,
+}
+
+// 主キーが同じ行がすでにあるときにすること
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnConflict {
+    // 衝突を調べる列 (書くなら主キーの列)
+    pub columns: Option<Vec<Ident>>,
+    pub action: ConflictAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictAction {
+    Nothing,
+    // 式ではテーブルの名前で今の行の列を、excluded で INSERT しようとした行の列を読む
+    Update(Vec<(Ident, Expr)>),
+}
+
+// INSERT する行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InsertSource {
+    Values(Vec<Vec<Expr>>),
+    Select(Box<Select>),
+}
+
+// SELECT items [FROM table [alias] {, table [alias] | [INNER] JOIN table [alias] ON expr}]
+// [WHERE expr] [ORDER BY expr [ASC | DESC], ...] [LIMIT n [OFFSET n]]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Select {
+    pub items: Vec<SelectItem>,
+    pub from: Vec<FromItem>,
+    pub where_cond: Option<Expr>,
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelectItem {
+    // * (pos は * の位置)
+    Wildcard(usize),
+    Expr { expr: Expr, alias: Option<Ident> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FromItem {
+    pub table: Ident,
+    pub alias: Option<Ident>,
+    // JOIN ... ON で結合したときの条件
+    pub on: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderBy {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+// UPDATE table SET column = expr, ... [WHERE expr]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Update {
+    pub table: Ident,
+    pub assignments: Vec<(Ident, Expr)>,
+    pub where_cond: Option<Expr>,
+}
+
+// DELETE FROM table [WHERE expr]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delete {
+    pub table: Ident,
+    pub where_cond: Option<Expr>,
+}
+
+// 式とその位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub pos: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprKind {
+    // [table.]column
+    Column { table: Option<String>, name: String },
+    Literal(Value),
+    // $1 や ? (0 から数える)
+    Param(usize),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    // expr IS [NOT] NULL
+    IsNull { expr: Box<Expr>, negated: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+}
+
+// ; で区切った文を順に読む
+pub fn parse(sql: &str) -> Result<Vec<Statement>> {
+    let mut parser = Parser::new(sql)?;
+    let mut statements = vec![];
+    loop {
+        while parser.eat_symbol(";") {}
+        if parser.peek().kind == TokenKind::End {
+            return Ok(statements);
+        }
+        statements.push(parser.statement()?);
+        if parser.peek().kind != TokenKind::End {
+            parser.expect_symbol(";")?;
+        }
+    }
+}
+
+// 一つの文だけを読む
+pub fn parse_statement(sql: &str) -> Result<Statement> {
+    let mut statements = parse(sql)?;
+    if statements.len() != 1 {
+        return syntax_error(
+            0,
+            format!("expected one statement but got {}", statements.len()),
+        );
+    }
+    Ok(statements.remove(0))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    // 予約語も名前として読み、使うところで大文字小文字を区別せずに比べる
+    Word(String),
+    // "..." で囲んだ名前
+    QuotedIdent(String),
+    Number(i64),
+    String(String),
+    Param(usize),
+    Symbol(&'static str),
+    End,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    pos: usize,
+}
+
+// 名前や別名に使えない語
+const RESERVED: &[&str] = &[
+    "select", "from", "where", "order", "by", "limit", "offset", "join", "inner", "on", "and",
+    "or", "not", "is", "null", "true", "false", "as", "asc", "desc", "set", "values", "into",
+];
+
+// 長いものから先に比べる
+const SYMBOLS: &[&str] = &[
+    "<>", "!=", "<=", ">=", "(", ")", ",", ";", "*", ".", "=", "<", ">", "+", "-", "/",
+];
+
+fn tokenize(sql: &str) -> Result<Vec<Token>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = vec![];
+    let mut pos = 0;
+    // ? の場合の次の番号
+    let mut next_param = 0;
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+        if sql[pos..].starts_with("--") {
+            pos = sql[pos..].find('\n').map_or(bytes.len(), |end| pos + end);
+            continue;
+        }
+        let start = pos;
+        let kind = if c.is_ascii_alphabetic() || c == b'_' {
+            while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_') {
+                pos += 1;
+            }
+            TokenKind::Word(sql[start..pos].to_ascii_lowercase())
+        } else if c.is_ascii_digit() {
+            while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                pos += 1;
+            }
+            match sql[start..pos].parse() {
+                Ok(n) => TokenKind::Number(n),
+                Err(_) => return syntax_error(start, "integer out of range"),
+            }
+        } else if c == b'\'' || c == b'"' {
+            // 引用符は二つ重ねて書く
+            let mut text = String::new();
+            pos += 1;
+            loop {
+                match sql[pos..].find(c as char) {
+                    Some(end) => {
+                        text.push_str(&sql[pos..pos + end]);
+                        pos += end + 1;
+                        if bytes.get(pos) == Some(&c) {
+                            text.push(c as char);
+                            pos += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    None => return syntax_error(start, "unterminated quoted string"),
+                }
+            }
+            if c == b'\'' {
+                TokenKind::String(text)
+            } else {
+                TokenKind::QuotedIdent(text)
+            }
+        } else if c == b'$' {
+            pos += 1;
+            while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                pos += 1;
+            }
+            match sql[start + 1..pos].parse::<usize>() {
+                Ok(n) if n > 0 => TokenKind::Param(n - 1),
+                _ => return syntax_error(start, "expected a parameter number after $"),
+            }
+        } else if c == b'?' {
+            pos += 1;
+            next_param += 1;
+            TokenKind::Param(next_param - 1)
+        } else {
+            match SYMBOLS
+                .iter()
+                .find(|symbol| sql[pos..].starts_with(*symbol))
+            {
+                Some(symbol) => {
+                    pos += symbol.len();
+                    TokenKind::Symbol(symbol)
+                }
+                None => {
+                    let found = sql[pos..].chars().next().unwrap_or_default();
+                    return syntax_error(pos, format!("unexpected character {:?}", found));
+                }
+            }
+        };
+        tokens.push(Token { kind, pos: start });
+    }
+    tokens.push(Token {
+        kind: TokenKind::End,
+        pos: bytes.len(),
+    });
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(sql: &str) -> Result<Self> {
+        Ok(Self {
+            tokens: tokenize(sql)?,
+            pos: 0,
+        })
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T> {
+        let token = self.peek();
+        let found = match &token.kind {
+            TokenKind::Word(word) => word.clone(),
+            TokenKind::QuotedIdent(name) => format!("\"{}\"", name),
+            TokenKind::Number(n) => n.to_string(),
+            TokenKind::String(text) => format!("'{}'", text),
+            TokenKind::Param(index) => format!("${}", index + 1),
+            TokenKind::Symbol(symbol) => symbol.to_string(),
+            TokenKind::End => "end of input".to_string(),
+        };
+        syntax_error(
+            token.pos,
+            format!("expected {} but found {}", expected, found),
+        )
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Word(word) if word == keyword)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if !self.eat_keyword(keyword) {
+            return self.unexpected(&keyword.to_ascii_uppercase());
+        }
+        Ok(())
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(&self.peek().kind, TokenKind::Symbol(found) if *found == symbol);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<()> {
+        if !self.eat_symbol(symbol) {
+            return self.unexpected(&format!("'{}'", symbol));
+        }
+        Ok(())
+    }
+
+    fn ident(&mut self) -> Result<Ident> {
+        let token = self.peek().clone();
+        let name = match token.kind {
+            TokenKind::Word(word) if !RESERVED.contains(&word.as_str()) => word,
+            TokenKind::QuotedIdent(name) => name,
+            _ => return self.unexpected("a name"),
+        };
+        self.advance();
+        Ok(Ident {
+            name,
+            pos: token.pos,
+        })
+    }
+
+    // 名前の並び (a, b, ...)
+    fn ident_list(&mut self) -> Result<Vec<Ident>> {
+        self.expect_symbol("(")?;
+        let mut idents = vec![self.ident()?];
+        while self.eat_symbol(",") {
+            idents.push(self.ident()?);
+        }
+        self.expect_symbol(")")?;
+        Ok(idents)
+    }
+
+    fn unsigned(&mut self) -> Result<u64> {
+        match self.peek().kind {
+            TokenKind::Number(n) => {
+                self.advance();
+                Ok(n as u64)
+            }
+            _ => self.unexpected("a non-negative integer"),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement> {
+        if self.eat_keyword("create") {
+            if self.eat_keyword("table") {
+                return Ok(Statement::CreateTable(self.create_table()?));
+            }
+            let unique = self.eat_keyword("unique");
+            if self.eat_keyword("index") {
+                return Ok(Statement::CreateIndex(self.create_index(unique)?));
+            }
+            return self.unexpected(if unique { "INDEX" } else { "TABLE or INDEX" });
+        }
+        if self.eat_keyword("drop") {
+            if self.eat_keyword("table") {
+                return Ok(Statement::DropTable(self.ident()?));
+            }
+            self.expect_keyword("index")?;
+            let name = self.ident()?;
+            self.expect_keyword("on")?;
+            let table = self.ident()?;
+            return Ok(Statement::DropIndex(DropIndex { name, table }));
+        }
+        if self.eat_keyword("insert") {
+            return Ok(Statement::Insert(self.insert()?));
+        }
+        if self.eat_keyword("select") {
+            return Ok(Statement::Select(self.select()?));
+        }
+        if self.eat_keyword("update") {
+            return Ok(Statement::Update(self.update()?));
+        }
+        if self.eat_keyword("delete") {
+            return Ok(Statement::Delete(self.delete()?));
+        }
+        self.unexpected("a statement")
+    }
+
+    fn create_table(&mut self) -> Result<CreateTable> {
+        let name = self.ident()?;
+        self.expect_symbol("(")?;
+        let mut columns = vec![];
+        let mut primary_key = vec![];
+        loop {
+            if self.eat_keyword("primary") {
+                self.expect_keyword("key")?;
+                if !primary_key.is_empty() {
+                    let pos = self.tokens[self.pos - 1].pos;
+                    return syntax_error(pos, "multiple primary keys");
+                }
+                primary_key = self.ident_list()?;
+            } else {
+                let column = self.ident()?;
+                let column_type = self.column_type()?;
+                let nullable = if self.eat_keyword("not") {
+                    self.expect_keyword("null")?;
+                    false
+                } else {
+                    self.eat_keyword("null");
+                    true
+                };
+                if self.eat_keyword("primary") {
+                    self.expect_keyword("key")?;
+                    if !primary_key.is_empty() {
+                        return syntax_error(column.pos, "multiple primary keys");
+                    }
+                    primary_key.push(column.clone());
+                }
+                columns.push(ColumnDef {
+                    name: column,
+                    column_type,
+                    nullable,
+                });
+            }
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        self.expect_symbol(")")?;
+        Ok(CreateTable {
+            name,
+            columns,
+            primary_key,
+        })
+    }
+
+    fn column_type(&mut self) -> Result<Type> {
+        let column_type = match &self.peek().kind {
+            TokenKind::Word(word) => match word.as_str() {
+                "int" | "integer" | "bigint" => Type::Int,
+                "bool" | "boolean" => Type::Bool,
+                "text" | "varchar" | "bytes" | "blob" => Type::Bytes,
+                _ => return self.unexpected("a column type"),
+            },
+            _ => return self.unexpected("a column type"),
+        };
+        self.advance();
+        // VARCHAR(n) の長さは見ない
+        if self.eat_symbol("(") {
+            self.unsigned()?;
+            self.expect_symbol(")")?;
+        }
+        Ok(column_type)
+    }
+
+    fn create_index(&mut self, unique: bool) -> Result<CreateIndex> {
+        let name = self.ident()?;
+        self.expect_keyword("on")?;
+        let table = self.ident()?;
+        let columns = self.ident_list()?;
+        Ok(CreateIndex {
+            unique,
+            name,
+            table,
+            columns,
+        })
+    }
+
+    fn insert(&mut self) -> Result<Insert> {
+        self.expect_keyword("into")?;
+        let table = self.ident()?;
+        let columns = if self.peek().kind == TokenKind::Symbol("(") {
+            Some(self.ident_list()?)
+        } else {
+            None
+        };
+        let source = if self.eat_keyword("select") {
+            InsertSource::Select(Box::new(self.select()?))
+        } else {
+            self.expect_keyword("values")?;
+            let mut rows = vec![];
+            loop {
+                self.expect_symbol("(")?;
+                let mut row = vec![self.expr()?];
+                while self.eat_symbol(",") {
+                    row.push(self.expr()?);
+                }
+                self.expect_symbol(")")?;
+                rows.push(row);
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+            InsertSource::Values(rows)
+        };
+        let on_conflict = match self.eat_keyword("on") {
+            true => Some(self.on_conflict()?),
+            false => None,
+        };
+        Ok(Insert {
+            table,
+            columns,
+            source,
+            on_conflict,
+        })
+    }
+
+    fn on_conflict(&mut self) -> Result<OnConflict> {
+        self.expect_keyword("conflict")?;
+        let columns = if self.peek().kind == TokenKind::Symbol("(") {
+            Some(self.ident_list()?)
+        } else {
+            None
+        };
+        self.expect_keyword("do")?;
+        let action = if self.eat_keyword("nothing") {
+            ConflictAction::Nothing
+        } else {
+            self.expect_keyword("update")?;
+            self.expect_keyword("set")?;
+            ConflictAction::Update(self.assignments()?)
+        };
+        Ok(OnConflict { columns, action })
+    }
+
+    fn select(&mut self) -> Result<Select> {
+        let mut items = vec![];
+        loop {
+            let pos = self.peek().pos;
+            if self.eat_symbol("*") {
+                items.push(SelectItem::Wildcard(pos));
+            } else {
+                let expr = self.expr()?;
+                let alias = self.alias()?;
+                items.push(SelectItem::Expr { expr, alias });
+            }
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        let mut from = vec![];
+        if self.eat_keyword("from") {
+            from.push(self.table_ref()?);
+            loop {
+                if self.eat_symbol(",") {
+                    from.push(self.table_ref()?);
+                } else if self.is_keyword("join") || self.is_keyword("inner") {
+                    self.eat_keyword("inner");
+                    self.expect_keyword("join")?;
+                    let mut item = self.table_ref()?;
+                    self.expect_keyword("on")?;
+                    item.on = Some(self.expr()?);
+                    from.push(item);
+                } else {
+                    break;
+                }
+            }
+        }
+        let where_cond = self.where_cond()?;
+        let mut order_by = vec![];
+        if self.eat_keyword("order") {
+            self.expect_keyword("by")?;
+            loop {
+                let expr = self.expr()?;
+                let descending = if self.eat_keyword("desc") {
+                    true
+                } else {
+                    self.eat_keyword("asc");
+                    false
+                };
+                order_by.push(OrderBy { expr, descending });
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+        let limit = match self.eat_keyword("limit") {
+            true => Some(self.unsigned()?),
+            false => None,
+        };
+        let offset = match self.eat_keyword("offset") {
+            true => Some(self.unsigned()?),
+            false => None,
+        };
+        Ok(Select {
+            items,
+            from,
+            where_cond,
+            order_by,
+            limit,
+            offset,
+        })
+    }
+
+    // [AS] alias
+    fn alias(&mut self) -> Result<Option<Ident>> {
+        if self.eat_keyword("as") {
+            return Ok(Some(self.ident()?));
+        }
+        match &self.peek().kind {
+            TokenKind::Word(word) if !RESERVED.contains(&word.as_str()) => Ok(Some(self.ident()?)),
+            TokenKind::QuotedIdent(_) => Ok(Some(self.ident()?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn table_ref(&mut self) -> Result<FromItem> {
+        let table = self.ident()?;
+        let alias = self.alias()?;
+        Ok(FromItem {
+            table,
+            alias,
+            on: None,
+        })
+    }
+
+    fn where_cond(&mut self) -> Result<Option<Expr>> {
+        match self.eat_keyword("where") {
+            true => Ok(Some(self.expr()?)),
+            false => Ok(None),
+        }
+    }
+
+    fn update(&mut self) -> Result<Update> {
+        let table = self.ident()?;
+        self.expect_keyword("set")?;
+        let assignments = self.assignments()?;
+        let where_cond = self.where_cond()?;
+        Ok(Update {
+            table,
+            assignments,
+            where_cond,
+        })
+    }
+
+    // column = expr, ...
+    fn assignments(&mut self) -> Result<Vec<(Ident, Expr)>> {
+        let mut assignments = vec![];
+        loop {
+            let column = self.ident()?;
+            self.expect_symbol("=")?;
+            assignments.push((column, self.expr()?));
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        Ok(assignments)
+    }
+
+    fn delete(&mut self) -> Result<Delete> {
+        self.expect_keyword("from")?;
+        let table = self.ident()?;
+        let where_cond = self.where_cond()?;
+        Ok(Delete { table, where_cond })
+    }
+
+    // 結合の弱いものから OR, AND, NOT, 比較と IS NULL, + -, * /, 単項の -
+    fn expr(&mut self) -> Result<Expr> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr> {
+        const LEVELS: &[&[(&str, BinaryOp)]] = &[
+            &[("or", BinaryOp::Or)],
+            &[("and", BinaryOp::And)],
+            &[],
+            &[
+                ("=", BinaryOp::Eq),
+                ("<>", BinaryOp::Ne),
+                ("!=", BinaryOp::Ne),
+                ("<=", BinaryOp::Le),
+                (">=", BinaryOp::Ge),
+                ("<", BinaryOp::Lt),
+                (">", BinaryOp::Gt),
+            ],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            &[("*", BinaryOp::Mul), ("/", BinaryOp::Div)],
+        ];
+        const NOT_LEVEL: usize = 2;
+        const COMPARE_LEVEL: usize = 3;
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        if level == NOT_LEVEL {
+            let pos = self.peek().pos;
+            if self.eat_keyword("not") {
+                let expr = self.binary(level)?;
+                return Ok(Expr {
+                    kind: ExprKind::Not(Box::new(expr)),
+                    pos,
+                });
+            }
+            return self.binary(level + 1);
+        }
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let op = LEVELS[level]
+                .iter()
+                .find(|(token, _)| match &self.peek().kind {
+                    TokenKind::Word(word) => word == token,
+                    TokenKind::Symbol(symbol) => symbol == token,
+                    _ => false,
+                });
+            if let Some(&(_, op)) = op {
+                self.advance();
+                let rhs = self.binary(level + 1)?;
+                let pos = lhs.pos;
+                lhs = Expr {
+                    kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+                    pos,
+                };
+                // 比較はつなげて書けない
+                if level == COMPARE_LEVEL {
+                    break;
+                }
+            } else if level == COMPARE_LEVEL && self.eat_keyword("is") {
+                let negated = self.eat_keyword("not");
+                self.expect_keyword("null")?;
+                let pos = lhs.pos;
+                lhs = Expr {
+                    kind: ExprKind::IsNull {
+                        expr: Box::new(lhs),
+                        negated,
+                    },
+                    pos,
+                };
+                break;
+            } else {
+                break;
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let pos = self.peek().pos;
+        if self.eat_symbol("-") {
+            // 整数のリテラルならそのまま負の数にする
+            if let TokenKind::Number(n) = self.peek().kind {
+                self.advance();
+                return Ok(Expr {
+                    kind: ExprKind::Literal(Value::Int(-n)),
+                    pos,
+                });
+            }
+            let expr = self.unary()?;
+            return Ok(Expr {
+                kind: ExprKind::Neg(Box::new(expr)),
+                pos,
+            });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let token = self.peek().clone();
+        let kind = match token.kind {
+            TokenKind::Number(n) => ExprKind::Literal(Value::Int(n)),
+            TokenKind::String(text) => ExprKind::Literal(Value::Bytes(text.into_bytes())),
+            TokenKind::Param(index) => ExprKind::Param(index),
+            TokenKind::Symbol("(") => {
+                self.advance();
+                let expr = self.expr()?;
+                self.expect_symbol(")")?;
+                return Ok(expr);
+            }
+            TokenKind::Word(word) if word == "null" => ExprKind::Literal(Value::Null),
+            TokenKind::Word(word) if word == "true" => ExprKind::Literal(Value::Bool(true)),
+            TokenKind::Word(word) if word == "false" => ExprKind::Literal(Value::Bool(false)),
+            TokenKind::Word(_) | TokenKind::QuotedIdent(_) => {
+                let first = self.ident()?;
+                let kind = if self.eat_symbol(".") {
+                    ExprKind::Column {
+                        table: Some(first.name),
+                        name: self.ident()?.name,
+                    }
+                } else {
+                    ExprKind::Column {
+                        table: None,
+                        name: first.name,
+                    }
+                };
+                return Ok(Expr {
+                    kind,
+                    pos: token.pos,
+                });
+            }
+            _ => return self.unexpected("an expression"),
+        };
+        self.advance();
+        Ok(Expr {
+            kind,
+            pos: token.pos,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, pos: usize) -> Expr {
+        Expr {
+            kind: ExprKind::Column {
+                table: None,
+                name: name.to_string(),
+            },
+            pos,
+        }
+    }
+
+    fn literal(value: Value, pos: usize) -> Expr {
+        Expr {
+            kind: ExprKind::Literal(value),
+            pos,
+        }
+    }
+
+    fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+        let pos = lhs.pos;
+        Expr {
+            kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+            pos,
+        }
+    }
+
+    fn ident(name: &str, pos: usize) -> Ident {
+        Ident {
+            name: name.to_string(),
+            pos,
+        }
+    }
+
+    #[test]
+    fn create_test() {
+        let statements = parse(
+            "CREATE TABLE people (id INT PRIMARY KEY, Name VARCHAR(20), ok BOOLEAN);
+             create unique index by_name on people (name, id);",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                Statement::CreateTable(CreateTable {
+                    name: ident("people", 13),
+                    columns: vec![
+                        ColumnDef {
+                            name: ident("id", 21),
+                            column_type: Type::Int,
+                            nullable: true,
+                        },
+                        ColumnDef {
+                            name: ident("name", 41),
+                            column_type: Type::Bytes,
+                            nullable: true,
+                        },
+                        ColumnDef {
+                            name: ident("ok", 59),
+                            column_type: Type::Bool,
+                            nullable: true,
+                        },
+                    ],
+                    primary_key: vec![ident("id", 21)],
+                }),
+                Statement::CreateIndex(CreateIndex {
+                    unique: true,
+                    name: ident("by_name", 105),
+                    table: ident("people", 116),
+                    columns: vec![ident("name", 124), ident("id", 130)],
+                }),
+            ],
+            statements
+        );
+        let composite = parse_statement("CREATE TABLE t (a INT, b TEXT, PRIMARY KEY (a, b))");
+        match composite.unwrap() {
+            Statement::CreateTable(table) => {
+                let names: Vec<_> = table
+                    .primary_key
+                    .iter()
+                    .map(|key| key.name.as_str())
+                    .collect();
+                assert_eq!(vec!["a", "b"], names);
+            }
+            statement => panic!("unexpected statement: {:?}", statement),
+        }
+        let nullable = parse_statement("CREATE TABLE t (a INT NOT NULL PRIMARY KEY, b TEXT NULL)");
+        match nullable.unwrap() {
+            Statement::CreateTable(table) => {
+                let nullable: Vec<_> = table.columns.iter().map(|c| c.nullable).collect();
+                assert_eq!(vec![false, true], nullable);
+                assert_eq!(1, table.primary_key.len());
+            }
+            statement => panic!("unexpected statement: {:?}", statement),
+        }
+        assert!(parse_statement("CREATE TABLE t (a INT NOT PRIMARY KEY)").is_err());
+        assert_eq!(
+            Statement::DropTable(ident("people", 11)),
+            parse_statement("DROP TABLE people").unwrap()
+        );
+        assert_eq!(
+            Statement::DropIndex(DropIndex {
+                name: ident("by_name", 11),
+                table: ident("people", 22),
+            }),
+            parse_statement("drop index by_name on people").unwrap()
+        );
+        assert!(parse_statement("DROP people").is_err());
+    }
+
+    #[test]
+    fn select_test() {
+        let sql = "SELECT p.id, name AS n, * FROM people p JOIN dept d ON p.dept = d.id, t \
+                   WHERE id >= 10 AND NOT ok OR name IS NOT NULL \
+                   ORDER BY name DESC, id LIMIT 5 OFFSET 2";
+        let select = match parse_statement(sql).unwrap() {
+            Statement::Select(select) => select,
+            statement => panic!("unexpected statement: {:?}", statement),
+        };
+        assert_eq!(3, select.items.len());
+        assert_eq!(SelectItem::Wildcard(24), select.items[2]);
+        match &select.items[1] {
+            SelectItem::Expr { expr, alias } => {
+                assert_eq!(&column("name", 13), expr);
+                assert_eq!(&Some(ident("n", 21)), alias);
+            }
+            item => panic!("unexpected item: {:?}", item),
+        }
+        let tables: Vec<_> = select
+            .from
+            .iter()
+            .map(|item| {
+                (
+                    item.table.name.as_str(),
+                    item.alias.as_ref().map(|a| a.name.as_str()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![("people", Some("p")), ("dept", Some("d")), ("t", None)],
+            tables
+        );
+        assert!(select.from[1].on.is_some());
+        // OR より AND、AND より NOT が強い
+        let where_cond = select.where_cond.unwrap();
+        match where_cond.kind {
+            ExprKind::Binary(BinaryOp::Or, lhs, rhs) => {
+                match lhs.kind {
+                    ExprKind::Binary(BinaryOp::And, cmp, not) => {
+                        assert_eq!(
+                            binary(BinaryOp::Ge, column("id", 78), literal(Value::Int(10), 84)),
+                            *cmp
+                        );
+                        assert!(matches!(not.kind, ExprKind::Not(_)));
+                    }
+                    kind => panic!("unexpected expression: {:?}", kind),
+                }
+                assert!(matches!(rhs.kind, ExprKind::IsNull { negated: true, .. }));
+            }
+            kind => panic!("unexpected expression: {:?}", kind),
+        }
+        let order: Vec<_> = select.order_by.iter().map(|o| o.descending).collect();
+        assert_eq!(vec![true, false], order);
+        assert_eq!((Some(5), Some(2)), (select.limit, select.offset));
+    }
+
+    #[test]
+    fn expr_test() {
+        let expr = |sql: &str| match parse_statement(&format!("SELECT {}", sql)).unwrap() {
+            Statement::Select(mut select) => match select.items.remove(0) {
+                SelectItem::Expr { expr, .. } => expr,
+                item => panic!("unexpected item: {:?}", item),
+            },
+            statement => panic!("unexpected statement: {:?}", statement),
+        };
+        // * は + より強く、同じ強さなら左から
+        assert_eq!(
+            binary(
+                BinaryOp::Sub,
+                binary(
+                    BinaryOp::Add,
+                    literal(Value::Int(1), 7),
+                    binary(
+                        BinaryOp::Mul,
+                        literal(Value::Int(2), 11),
+                        literal(Value::Int(-3), 15)
+                    )
+                ),
+                literal(Value::Int(4), 20)
+            ),
+            expr("1 + 2 * -3 - 4")
+        );
+        assert_eq!(literal(Value::Bytes(b"it's".to_vec()), 7), expr("'it''s'"));
+        assert_eq!(
+            vec![ExprKind::Param(1), ExprKind::Param(0)],
+            vec![expr("$2").kind, expr("?").kind]
+        );
+        assert_eq!(
+            ExprKind::Column {
+                table: Some("t".to_string()),
+                name: "Mixed Case".to_string(),
+            },
+            expr("T.\"Mixed Case\"").kind
+        );
+    }
+
+    #[test]
+    fn dml_test() {
+        let statements = parse(
+            "INSERT INTO t (a, b) VALUES (1, 'x'), ($1, NULL);
+             UPDATE t SET b = 'y', a = a + 1 WHERE a = 1;
+             DELETE FROM t;",
+        )
+        .unwrap();
+        match &statements[0] {
+            Statement::Insert(Insert {
+                columns,
+                source: InsertSource::Values(rows),
+                ..
+            }) => {
+                assert_eq!(2, columns.as_ref().unwrap().len());
+                assert_eq!(
+                    vec![ExprKind::Param(0), ExprKind::Literal(Value::Null)],
+                    rows[1].iter().map(|e| e.kind.clone()).collect::<Vec<_>>()
+                );
+            }
+            statement => panic!("unexpected statement: {:?}", statement),
+        }
+        match &statements[1] {
+            Statement::Update(update) => {
+                assert_eq!(2, update.assignments.len());
+                assert!(update.where_cond.is_some());
+            }
+            statement => panic!("unexpected statement: {:?}", statement),
+        }
+        assert_eq!(
+            Statement::Delete(Delete {
+                table: ident("t", 133),
+                where_cond: None,
+            }),
+            statements[2]
+        );
+        // VALUES の代わりに SELECT の結果を入れる
+        match parse_statement("INSERT INTO t (a) SELECT b FROM u WHERE b > 1").unwrap() {
+            Statement::Insert(Insert {
+                source: InsertSource::Select(select),
+                ..
+            }) => {
+                assert_eq!(1, select.items.len());
+                assert!(select.where_cond.is_some());
+            }
+            statement => panic!("unexpected statement: {:?}", statement),
+        }
+        match parse_statement(
+            "INSERT INTO t VALUES (1, 2) ON CONFLICT (a) DO UPDATE SET b = excluded.b + t.b",
+        )
+        .unwrap()
+        {
+            Statement::Insert(Insert {
+                on_conflict: Some(on_conflict),
+                ..
+            }) => {
+                assert_eq!(1, on_conflict.columns.unwrap().len());
+                assert!(matches!(
+                    &on_conflict.action,
+                    ConflictAction::Update(assignments) if assignments.len() == 1
+                ));
+            }
+            statement => panic!("unexpected statement: {:?}", statement),
+        }
+        assert!(matches!(
+            parse_statement("INSERT INTO t SELECT * FROM u ON CONFLICT DO NOTHING").unwrap(),
+            Statement::Insert(Insert {
+                on_conflict: Some(OnConflict {
+                    columns: None,
+                    action: ConflictAction::Nothing,
+                }),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn error_test() {
+        let error = |sql: &str| match parse(sql).unwrap_err().downcast::<Error>() {
+            Ok(Error::Syntax { pos, message }) => (pos, message),
+            Err(err) => panic!("unexpected error: {}", err),
+        };
+        assert_eq!(
+            (14, "expected a name but found where".to_string()),
+            error("SELECT * FROM where")
+        );
+        assert_eq!(
+            (
+                10,
+                "expected an expression but found end of input".to_string()
+            ),
+            error("SELECT a +")
+        );
+        assert_eq!(
+            (25, "expected a column type but found float".to_string()),
+            error("CREATE TABLE t (a INT, b FLOAT)")
+        );
+        assert_eq!(
+            (9, "unexpected character '#'".to_string()),
+            error("SELECT a # b")
+        );
+        assert_eq!(
+            (11, "expected ';' but found c".to_string()),
+            error("SELECT a b c")
+        );
+        assert_eq!(
+            (7, "unterminated quoted string".to_string()),
+            error("SELECT 'abc")
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use std::convert::TryInto;
+
+use crc::{Crc, CRC_32_ISCSI};
+
+use super::entity::CHECKSUM_SIZE;
+
+const CRC32C: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+
+fn split(page: &[u8]) -> (&[u8], &[u8]) {
+    page.split_at(page.len() - CHECKSUM_SIZE)
+}
+
+// ページ末尾にチェックサムを書き込む
+pub fn stamp(page: &mut [u8]) {
+    let body_len = page.len() - CHECKSUM_SIZE;
+    let checksum = CRC32C.checksum(&page[..body_len]);
+    page[body_len..].copy_from_slice(&checksum.to_le_bytes());
+}
+
+// ページ末尾のチェックサムを検証する
+// 一度も書かれていない (全てゼロの) ページは正しいものとして扱う
+pub fn verify(page: &[u8]) -> bool {
+    if page.iter().all(|&b| b == 0) {
+        return true;
+    }
+    let (body, trailer) = split(page);
+    CRC32C.checksum(body) == u32::from_le_bytes(trailer.try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_test() {
+        let mut page = vec![0u8; 64];
+        assert!(verify(&page));
+        page[..5].copy_from_slice(b"hello");
+        assert!(!verify(&page));
+        stamp(&mut page);
+        assert!(verify(&page));
+        page[3] ^= 1;
+        assert!(!verify(&page));
+    }
+}
@@ -1,8 +1,26 @@
 use std::convert::TryInto;
+use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
 use zerocopy::{AsBytes, FromBytes};
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, FromBytes, AsBytes)]
+// ページ末尾に置くチェックサム (CRC32C) のサイズ
+pub const CHECKSUM_SIZE: usize = 4;
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    FromBytes,
+    AsBytes,
+    Serialize,
+    Deserialize,
+)]
 #[repr(C)]
 pub struct PageId(pub u64);
 impl PageId {
@@ -19,6 +37,24 @@ impl PageId {
     pub fn to_u64(self) -> u64 {
         self.0
     }
+
+    // 上位 FILE_ID_BITS ビットにファイルID、残りにファイル内のページ番号を詰める
+    // ファイルID 0 のページIDは従来の単一ファイルのページIDと一致する
+    pub const FILE_ID_BITS: u32 = 16;
+    const PAGE_NO_MASK: u64 = (1 << (64 - Self::FILE_ID_BITS)) - 1;
+
+    pub fn new(file_id: u16, page_no: u64) -> PageId {
+        assert!(page_no <= Self::PAGE_NO_MASK, "page_no out of range");
+        PageId((file_id as u64) << (64 - Self::FILE_ID_BITS) | page_no)
+    }
+
+    pub fn file_id(self) -> u16 {
+        (self.0 >> (64 - Self::FILE_ID_BITS)) as u16
+    }
+
+    pub fn page_no(self) -> u64 {
+        self.0 & Self::PAGE_NO_MASK
+    }
 }
 
 impl Default for PageId {
@@ -39,3 +75,15 @@ impl From<&[u8]> for PageId {
         PageId(u64::from_ne_bytes(arr))
     }
 }
+
+// storagemanager が行った I/O の累計
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IoStats {
+    pub num_reads: u64,
+    pub num_writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub num_syncs: u64,
+    // sync にかかった時間の合計
+    pub sync_duration: Duration,
+}
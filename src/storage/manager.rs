@@ -1,14 +1,81 @@
 use super::entity::PageId;
 
-use std::io::Result;
+use std::future::Future;
+use std::io;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("no space left on the storage")]
+    OutOfSpace,
+    // 一度も書き出されていないページを読もうとした
+    #[error("page {0:?} is past the end of the storage")]
+    PastEof(PageId),
+    // 設定した上限を超えてページを割り当てようとした
+    #[error("database size limit of {0} bytes exceeded")]
+    SizeLimitExceeded(u64),
+    #[error("permission denied: {0}")]
+    PermissionDenied(io::Error),
+    // チェックサムが合わないなど、ページが壊れている
+    #[error("page {0:?} is corrupted")]
+    Corrupt(PageId),
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::StorageFull => Error::OutOfSpace,
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied(err),
+            _ => Error::Io(err),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
 
 pub trait StorageManager {
+    // 決まった大きさのページしか読み書きできなければ、その大きさ
+    fn page_size(&self) -> Option<usize> {
+        None
+    }
     // 新しいページIDを採番する
-    fn allocate_page(&mut self) -> PageId;
-    // ページのデータを読み出す
+    fn allocate_page(&mut self) -> Result<PageId>;
+    // 不要になったページを解放して再利用できるようにする
+    fn deallocate_page(&mut self, _page_id: PageId) -> Result<()> {
+        Ok(())
+    }
+    // ページのデータを読み出す (ページが壊れていれば Error::Corrupt を返す)
     fn read_page_data(&mut self, page_id: PageId, data: &mut [u8]) -> Result<()>;
     // データをページに書き出す
     fn write_page_data(&mut self, page_id: PageId, data: &[u8]) -> Result<()>;
+    // first_page_id から連続するページをまとめて読み出す
+    fn read_pages_data(&mut self, first_page_id: PageId, bufs: &mut [&mut [u8]]) -> Result<()> {
+        for (i, data) in bufs.iter_mut().enumerate() {
+            self.read_page_data(PageId(first_page_id.to_u64() + i as u64), data)?;
+        }
+        Ok(())
+    }
+    // first_page_id から連続するページにまとめて書き出す
+    fn write_pages_data(&mut self, first_page_id: PageId, pages: &[&[u8]]) -> Result<()> {
+        for (i, data) in pages.iter().enumerate() {
+            self.write_page_data(PageId(first_page_id.to_u64() + i as u64), data)?;
+        }
+        Ok(())
+    }
     // 同期処理
     fn sync(&mut self) -> Result<()>;
 }
+
+// 非同期にページを読み書きする storagemanager
+// &self で呼べるので複数のページの読み書きを重ねて発行できる
+pub trait AsyncStorageManager {
+    // 新しいページIDを採番する
+    fn allocate_page(&self) -> PageId;
+    // ページのデータを読み出す (ページが壊れていれば Error::Corrupt を返す)
+    fn read_page_data(&self, page_id: PageId, data: &mut [u8]) -> impl Future<Output = Result<()>>;
+    // データをページに書き出す
+    fn write_page_data(&self, page_id: PageId, data: &[u8]) -> impl Future<Output = Result<()>>;
+    // 同期処理
+    fn sync(&self) -> impl Future<Output = Result<()>>;
+}
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+use crate::storage::entity::PageId;
+
+// ログ中のレコードの位置 (ログの先頭からのバイトオフセット)
+#[derive(
+    Debug, Default, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+pub struct Lsn(pub u64);
+
+impl Lsn {
+    pub fn to_u64(self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+pub struct TxnId(pub u64);
+
+impl TxnId {
+    // トランザクションの外での書き込みを表す (ID は 1 から採番する)
+    pub const INVALID_TXN_ID: TxnId = TxnId(0);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogRecord {
+    Begin {
+        txn_id: TxnId,
+    },
+    // commit_time はコミットした UNIX 時刻 (マイクロ秒) で、時刻を指定したリカバリに使う
+    Commit {
+        txn_id: TxnId,
+        commit_time: u64,
+    },
+    Abort {
+        txn_id: TxnId,
+    },
+    // トランザクションが書き換えたページの内容 (redo に使う)
+    PageImage {
+        txn_id: TxnId,
+        page_id: PageId,
+        image: Vec<u8>,
+    },
+    // ページの offset から data に書き換えた差分
+    // チェックポイントの後で最初に書き換えたときは PageImage を書くので、
+    // redo では必ずその後に適用する
+    PageDelta {
+        txn_id: TxnId,
+        page_id: PageId,
+        offset: u32,
+        data: Vec<u8>,
+    },
+    // B-tree (メタページが tree のもの) への key と value の挿入
+    // ロールバックやリカバリでは key を消して取り消す (value は変更の取り出しに使う)
+    Insert {
+        txn_id: TxnId,
+        tree: PageId,
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    // B-tree の key の行に削除の印を付けた (変更の取り出しに使う)
+    // 取り消しはページごと元に戻すので、ロールバックやリカバリでは使わない
+    Delete {
+        txn_id: TxnId,
+        tree: PageId,
+        key: Vec<u8>,
+    },
+    // undone の Insert を取り消した補償レコード
+    // 取り消しで書き換えたページは直前に書いてあり、トランザクションがコミットしていなくても redo する
+    // リカバリで続きを取り消すときは、補償済みの挿入を二度取り消さない
+    Compensation {
+        txn_id: TxnId,
+        undone: Lsn,
+    },
+    // チェックポイントを始めた時点のダーティページと活動中のトランザクション
+    // (トランザクションは最初のレコードの LSN と組にする)
+    // タプルに残ったトランザクション ID を使い回さないように次に採番する ID も残す
+    Checkpoint {
+        dirty_pages: Vec<PageId>,
+        active_txns: Vec<(TxnId, Lsn)>,
+        next_txn_id: TxnId,
+    },
+}
+
+impl LogRecord {
+    // レコードを書いたトランザクション
+    pub fn txn_id(&self) -> Option<TxnId> {
+        match *self {
+            LogRecord::Begin { txn_id }
+            | LogRecord::Commit { txn_id, .. }
+            | LogRecord::Abort { txn_id }
+            | LogRecord::PageImage { txn_id, .. }
+            | LogRecord::PageDelta { txn_id, .. }
+            | LogRecord::Insert { txn_id, .. }
+            | LogRecord::Delete { txn_id, .. }
+            | LogRecord::Compensation { txn_id, .. } => Some(txn_id),
+            LogRecord::Checkpoint { .. } => None,
+        }
+    }
+}
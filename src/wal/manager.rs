@@ -0,0 +1,34 @@
+use super::entity::{LogRecord, Lsn};
+
+use std::io;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // チェックサムが合わないなど、レコードが壊れている
+    #[error("log record at {0:?} is corrupted")]
+    Corrupt(Lsn),
+    // 既に切り詰めたところを読もうとした
+    #[error("log before {0:?} has been truncated")]
+    Truncated(Lsn),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub trait LogManager {
+    // レコードを追記して LSN を返す (flush するまでは永続化されない)
+    fn append(&mut self, record: &LogRecord) -> Result<Lsn>;
+    // 追記したレコードを永続化する
+    fn flush(&mut self) -> Result<()>;
+    // 残っている最も古いレコードの LSN
+    fn start_lsn(&self) -> Lsn;
+    // 次に追記されるレコードの LSN
+    fn end_lsn(&self) -> Lsn;
+    // ここまでは永続化されている
+    fn flushed_lsn(&self) -> Lsn;
+    // lsn 以降の永続化されたレコードを順に読み出す
+    fn read_from(&mut self, lsn: Lsn) -> Result<Vec<(Lsn, LogRecord)>>;
+    // lsn より前のレコードを捨ててもよいことを伝える
+    fn truncate_before(&mut self, lsn: Lsn) -> Result<()>;
+}